@@ -0,0 +1,31 @@
+// Run with `cargo bench`. The workloads are the same as for `raytracer bench`,
+// which prints plain samples per second instead.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use raytracer::bench;
+
+fn workloads(c: &mut Criterion) {
+    let (camera, world) = bench::scene();
+
+    let mut group = c.benchmark_group("raytracer");
+    group.throughput(Throughput::Elements(
+        (camera.width() * camera.height()) as u64,
+    ));
+    group.bench_function("primary_rays", |b| {
+        b.iter(|| bench::primary_rays(&camera, &world))
+    });
+
+    group.throughput(Throughput::Elements(3000));
+    group.bench_function("scatter", |b| b.iter(|| bench::scatter(1000)));
+
+    // Whole renders are slow, so fewer of them.
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(
+        (camera.width() * camera.height() * camera.samples_per_pixel()) as u64,
+    ));
+    group.bench_function("render", |b| b.iter(|| bench::render(&camera, &world)));
+    group.finish();
+}
+
+criterion_group!(benches, workloads);
+criterion_main!(benches);
@@ -0,0 +1,19 @@
+// With the "capi" feature, generates the C header for src/capi.rs into
+// OUT_DIR. `cargo xtask capi` copies it to include/raytracer.h.
+fn main() {
+    #[cfg(feature = "capi")]
+    {
+        println!("cargo:rerun-if-changed=src/capi.rs");
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        let dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", dir))
+            .expect("Unable to read cbindgen.toml");
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(format!("{}/src/capi.rs", dir))
+            .generate()
+            .expect("Unable to generate C bindings")
+            .write_to_file(format!("{}/raytracer.h", out_dir));
+    }
+}
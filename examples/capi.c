@@ -0,0 +1,65 @@
+/* Renders a scene through the C API and writes it as a PPM image.
+ *
+ *   cargo xtask capi
+ *   cc examples/capi.c -Iinclude -Ltarget/release -lraytracer -o render
+ *   LD_LIBRARY_PATH=target/release ./render data/example_scene.json out.ppm
+ */
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "raytracer.h"
+
+static char *read_file(const char *path) {
+    FILE *f = fopen(path, "rb");
+    if (!f) return NULL;
+    fseek(f, 0, SEEK_END);
+    long size = ftell(f);
+    rewind(f);
+    char *text = malloc(size + 1);
+    text[fread(text, 1, size, f)] = '\0';
+    fclose(f);
+    return text;
+}
+
+static void progress(double done, void *user_data) {
+    (void)user_data;
+    fprintf(stderr, "\r%3.0f%%", done * 100.0);
+}
+
+int main(int argc, char **argv) {
+    if (argc != 3) {
+        fprintf(stderr, "usage: %s <scene.json> <out.ppm>\n", argv[0]);
+        return 1;
+    }
+
+    char *json = read_file(argv[1]);
+    if (!json) {
+        perror(argv[1]);
+        return 1;
+    }
+    RtScene *scene = rt_scene_from_json(json);
+    free(json);
+    if (!scene) {
+        fprintf(stderr, "error: %s\n", rt_last_error());
+        return 1;
+    }
+
+    uint32_t width, height;
+    rt_scene_image_size(scene, &width, &height);
+    size_t len = (size_t)width * height * 4;
+    uint8_t *rgba = malloc(len);
+    if (rt_render(scene, rgba, len, progress, NULL) != 0) {
+        fprintf(stderr, "error: %s\n", rt_last_error());
+        return 1;
+    }
+    fprintf(stderr, "\n");
+
+    FILE *out = fopen(argv[2], "wb");
+    fprintf(out, "P6\n%u %u\n255\n", width, height);
+    for (size_t i = 0; i < len; i += 4) fwrite(rgba + i, 1, 3, out);
+    fclose(out);
+
+    free(rgba);
+    rt_scene_free(scene);
+    return 0;
+}
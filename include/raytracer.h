@@ -0,0 +1,27 @@
+#ifndef RAYTRACER_H
+#define RAYTRACER_H
+
+/* Generated by cbindgen from src/capi.rs, do not edit. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+typedef struct RtScene RtScene;
+
+typedef void (*RtProgressCallback)(double progress, void *user_data);
+
+const char *rt_last_error(void);
+
+struct RtScene *rt_scene_from_json(const char *json);
+
+int32_t rt_scene_image_size(const struct RtScene *scene, uint32_t *width, uint32_t *height);
+
+int32_t rt_render(const struct RtScene *scene,
+                  uint8_t *rgba,
+                  size_t len,
+                  RtProgressCallback progress,
+                  void *user_data);
+
+void rt_scene_free(struct RtScene *scene);
+
+#endif  /* RAYTRACER_H */
@@ -0,0 +1,150 @@
+// Axis-aligned bounding boxes, the building block of acceleration structures:
+// a ray that misses a box misses everything inside it.
+
+use crate::interval::Interval;
+use crate::ray::Ray;
+use crate::vec3::Point3D;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Aabb {
+    pub x: Interval,
+    pub y: Interval,
+    pub z: Interval,
+}
+
+impl Aabb {
+    pub const EMPTY: Aabb = Aabb {
+        x: Interval::EMPTY,
+        y: Interval::EMPTY,
+        z: Interval::EMPTY,
+    };
+
+    pub fn new(x: Interval, y: Interval, z: Interval) -> Aabb {
+        Aabb { x, y, z }
+    }
+
+    // The box spanned by two opposite corners, in any order.
+    pub fn from_points(a: Point3D, b: Point3D) -> Aabb {
+        let span = |a: f64, b: f64| Interval::new(a.min(b), a.max(b));
+        Aabb::new(span(a.x(), b.x()), span(a.y(), b.y()), span(a.z(), b.z()))
+    }
+
+    pub fn surrounding_box(a: &Aabb, b: &Aabb) -> Aabb {
+        Aabb::new(a.x.union(&b.x), a.y.union(&b.y), a.z.union(&b.z))
+    }
+
+    pub fn axis(&self, n: usize) -> &Interval {
+        match n {
+            0 => &self.x,
+            1 => &self.y,
+            _ => &self.z,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty() || self.y.is_empty() || self.z.is_empty()
+    }
+
+    pub fn min(&self) -> Point3D {
+        Point3D::new(self.x.min, self.y.min, self.z.min)
+    }
+
+    pub fn max(&self) -> Point3D {
+        Point3D::new(self.x.max, self.y.max, self.z.max)
+    }
+
+    pub fn centroid(&self) -> Point3D {
+        0.5 * (self.min() + self.max())
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let (dx, dy, dz) = (self.x.size(), self.y.size(), self.z.size());
+        2.0 * (dx * dy + dy * dz + dz * dx)
+    }
+
+    // 0, 1 or 2 for x, y or z.
+    pub fn longest_axis(&self) -> usize {
+        let (dx, dy, dz) = (self.x.size(), self.y.size(), self.z.size());
+        if dx > dy && dx > dz {
+            0
+        } else if dy > dz {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn hit(&self, r: &Ray, ray_t: &Interval) -> bool {
+        self.clip(r, ray_t).is_some()
+    }
+
+    // Slab test: narrows `ray_t` to where the ray is inside each pair of
+    // planes in turn, and returns what is left, if anything.
+    pub fn clip(&self, r: &Ray, ray_t: &Interval) -> Option<Interval> {
+        let origin = r.origin();
+        let direction = r.direction();
+        let mut ray_t = *ray_t;
+        for (axis, o, d) in [
+            (&self.x, origin.x(), direction.x()),
+            (&self.y, origin.y(), direction.y()),
+            (&self.z, origin.z(), direction.z()),
+        ] {
+            let inverse = 1.0 / d;
+            let t0 = (axis.min - o) * inverse;
+            let t1 = (axis.max - o) * inverse;
+            ray_t = ray_t.intersection(&Interval::new(t0.min(t1), t0.max(t1)));
+            if ray_t.max <= ray_t.min {
+                return None;
+            }
+        }
+        Some(ray_t)
+    }
+
+    // Grows zero-thickness sides a little, so flat shapes can still be hit.
+    pub fn pad(&self, delta: f64) -> Aabb {
+        let pad = |i: &Interval| {
+            if i.size() < delta {
+                i.expand(delta)
+            } else {
+                *i
+            }
+        };
+        Aabb::new(pad(&self.x), pad(&self.y), pad(&self.z))
+    }
+}
+
+#[test]
+fn test_hit() {
+    use crate::vec3::Vec3;
+
+    let aabb = Aabb::from_points(Point3D::new(1.0, 1.0, -3.0), Point3D::new(-1.0, -1.0, -1.0));
+    let t = Interval::new(0.0, f64::INFINITY);
+    let origin = Point3D::new(0.0, 0.0, 0.0);
+    assert!(aabb.hit(&Ray::new(origin, Vec3::new(0.0, 0.0, -1.0)), &t));
+    assert!(aabb.hit(&Ray::new(origin, Vec3::new(0.2, -0.2, -1.0)), &t));
+    assert!(!aabb.hit(&Ray::new(origin, Vec3::new(0.0, 0.0, 1.0)), &t));
+    assert!(!aabb.hit(&Ray::new(origin, Vec3::new(1.0, 0.0, -0.1)), &t));
+    assert!(!aabb.hit(
+        &Ray::new(origin, Vec3::new(0.0, 0.0, -1.0)),
+        &Interval::new(0.0, 0.5)
+    ));
+}
+
+#[test]
+fn test_measures() {
+    let a = Aabb::from_points(Point3D::new(0.0, 0.0, 0.0), Point3D::new(4.0, 1.0, 2.0));
+    assert_eq!(a.centroid(), Point3D::new(2.0, 0.5, 1.0));
+    assert_eq!(a.surface_area(), 2.0 * (4.0 + 2.0 + 8.0));
+    assert_eq!(a.longest_axis(), 0);
+
+    let b = Aabb::from_points(Point3D::new(-1.0, 0.0, 0.0), Point3D::new(0.0, 6.0, 0.0));
+    let both = Aabb::surrounding_box(&a, &b);
+    assert_eq!(both.min(), Point3D::new(-1.0, 0.0, 0.0));
+    assert_eq!(both.max(), Point3D::new(4.0, 6.0, 2.0));
+    assert_eq!(both.longest_axis(), 1);
+    assert_eq!(Aabb::surrounding_box(&Aabb::EMPTY, &b), b);
+    assert_eq!(Aabb::EMPTY.surface_area(), 0.0);
+}
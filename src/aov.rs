@@ -0,0 +1,417 @@
+// Arbitrary output variables: extra images rendered next to the beauty pass
+// for use in compositing. Each AOV is written next to the output file, so
+// `render.png` gets `render.object_id.png`.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::camera::{Camera, PixelPasses};
+use crate::color::{heat_color, Color};
+use crate::film::Film;
+use crate::hittable::{HitRecord, Object, ObjectList};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+pub const AOVS: &[&str] = &[
+    "object_id",
+    "alpha",
+    "samples",
+    "variance",
+    "direct",
+    "indirect",
+    "emission",
+    "diffuse",
+    "specular",
+    "denoise",
+];
+
+pub fn aov_path(output: &Path, aov: &str, extension: &str) -> PathBuf {
+    output.with_extension(format!("{}.{}", aov, extension))
+}
+
+// 32-bit FNV-1a, so the same name gets the same ID in every render.
+fn hash(name: &str) -> u32 {
+    name.bytes().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+// Named objects are identified by their name. Unnamed objects fall back to
+// their position in the scene, which changes when objects are added before
+// them.
+pub fn object_id(object: &Object, index: usize) -> u32 {
+    match object.name() {
+        Some(name) => hash(name),
+        None => hash(&format!("#{}", index)),
+    }
+}
+
+// A bright, well spread color derived from the ID bits. ID 0 is reserved for
+// the background and stays black.
+pub fn id_color(id: u32) -> [u8; 3] {
+    if id == 0 {
+        return [0, 0, 0];
+    }
+    let mixed = id.wrapping_mul(0x9e3779b1);
+    let [r, g, b, _] = mixed.to_le_bytes();
+    [r | 0x40, g | 0x40, b | 0x40]
+}
+
+#[derive(Debug, Serialize)]
+pub struct ManifestEntry {
+    pub id: String,
+    pub color: String,
+}
+
+pub fn manifest(world: &ObjectList) -> BTreeMap<String, ManifestEntry> {
+    world
+        .objects
+        .iter()
+        .enumerate()
+        .map(|(index, object)| {
+            let id = object_id(object, index);
+            let [r, g, b] = id_color(id);
+            let name = object
+                .name()
+                .map_or_else(|| format!("#{}", index), str::to_string);
+            let entry = ManifestEntry {
+                id: format!("{:08x}", id),
+                color: format!("#{:02x}{:02x}{:02x}", r, g, b),
+            };
+            (name, entry)
+        })
+        .collect()
+}
+
+// Renders the object covering most of each pixel as a flat color and writes
+// a JSON manifest mapping object names to IDs and colors.
+#[cfg(feature = "json")]
+pub fn render_object_ids(
+    camera: &Camera,
+    world: &ObjectList,
+    output: &Path,
+) -> std::io::Result<()> {
+    use crate::parallel::*;
+
+    let ids: Vec<u32> = (0..camera.width() * camera.height())
+        .into_par_iter()
+        .map(|pixel| {
+            let (i, j) = (pixel % camera.width(), pixel / camera.width());
+            camera
+                .object_at(i, j, world)
+                .map_or(0, |index| object_id(&world.objects[index], index))
+        })
+        .collect();
+
+    let buffer: Vec<u8> = ids.iter().flat_map(|id| id_color(*id)).collect();
+    crate::film::write_image(
+        &aov_path(output, "object_id", "png").to_string_lossy(),
+        &buffer,
+        (camera.width(), camera.height()),
+    )?;
+
+    let manifest = serde_json::to_string_pretty(&manifest(world))?;
+    std::fs::write(aov_path(output, "object_id", "json"), manifest)
+}
+
+fn write_heat(camera: &Camera, output: &Path, aov: &str, values: &[f64]) -> std::io::Result<()> {
+    let buffer: Vec<u8> = values.iter().flat_map(|t| heat_color(*t)).collect();
+    crate::film::write_image(
+        &aov_path(output, aov, "png").to_string_lossy(),
+        &buffer,
+        (camera.width(), camera.height()),
+    )
+}
+
+// White where a pixel got all of the camera's samples. Without adaptive
+// sampling only pixels cut short by `check_nan` get fewer.
+pub fn write_samples(
+    camera: &Camera,
+    passes: &[PixelPasses],
+    output: &Path,
+) -> std::io::Result<()> {
+    let max = camera.samples_per_pixel().max(1) as f64;
+    let values: Vec<f64> = passes
+        .iter()
+        .map(|p| p.stats.samples as f64 / max)
+        .collect();
+    write_heat(camera, output, "samples", &values)
+}
+
+// The noise of every pixel as its standard deviation, scaled so the 99th
+// percentile is white and a few fireflies don't make the rest black.
+pub fn write_variance(
+    camera: &Camera,
+    passes: &[PixelPasses],
+    output: &Path,
+) -> std::io::Result<()> {
+    let deviations: Vec<f64> = passes.iter().map(|p| p.stats.variance.sqrt()).collect();
+    let scale = percentile(&deviations, 0.99);
+    tracing::info!(white = scale, "standard deviation shown as white");
+    let values: Vec<f64> = deviations
+        .iter()
+        .map(|d| if scale > 0.0 { d / scale } else { 0.0 })
+        .collect();
+    write_heat(camera, output, "variance", &values)
+}
+
+// Direct and indirect light, see `PathLight`. Both are white balanced like
+// the beauty pass but get no post effects, so in linear light they add up to
+// the beauty pass before its effects.
+pub fn light_split(camera: &Camera, passes: &[PixelPasses]) -> (Film, Film) {
+    let [direct, indirect] = pass_films(camera, passes, |p| [p.light.direct(), p.light.indirect()]);
+    (direct, indirect)
+}
+
+// Emission, diffuse and specular light, see `PathLight`, which add up to the
+// beauty pass like `light_split`.
+pub fn path_types(camera: &Camera, passes: &[PixelPasses]) -> [Film; 3] {
+    pass_films(camera, passes, |p| {
+        [p.light.emission(), p.light.diffuse(), p.light.specular()]
+    })
+}
+
+// The coverage of every pixel as a linear grey mask, see
+// `Camera::coverage`: white where the camera sees only objects that aren't
+// holdouts or mattes, black where it sees the sky, holdouts or mattes, and
+// grey where shadows fall onto holdouts.
+pub fn write_alpha(camera: &Camera, world: &ObjectList, output: &Path) -> std::io::Result<()> {
+    use crate::parallel::*;
+
+    let alpha: Vec<u8> = (0..camera.width() * camera.height())
+        .into_par_iter()
+        .map(|pixel| {
+            let (i, j) = (pixel % camera.width(), pixel / camera.width());
+            (camera.coverage(i, j, world) * 255.0).round() as u8
+        })
+        .collect();
+    let buffer: Vec<u8> = alpha.iter().flat_map(|a| [*a; 3]).collect();
+    crate::film::write_image(
+        &aov_path(output, "alpha", "png").to_string_lossy(),
+        &buffer,
+        (camera.width(), camera.height()),
+    )
+}
+
+// One white balanced film for each of the colors `pass` takes from a pixel.
+fn pass_films<const N: usize>(
+    camera: &Camera,
+    passes: &[PixelPasses],
+    pass: impl Fn(&PixelPasses) -> [Color; N],
+) -> [Film; N] {
+    let mut films = films(camera, passes, pass);
+    if let Some(white_balance) = &camera.white_balance {
+        films.iter_mut().for_each(|film| white_balance.apply(film));
+    }
+    films
+}
+
+fn films<const N: usize>(
+    camera: &Camera,
+    passes: &[PixelPasses],
+    pass: impl Fn(&PixelPasses) -> [Color; N],
+) -> [Film; N] {
+    let pixels: Vec<[Color; N]> = passes.iter().map(pass).collect();
+    std::array::from_fn(|index| Film {
+        width: camera.width(),
+        height: camera.height(),
+        pixels: pixels.iter().map(|colors| colors[index]).collect(),
+    })
+}
+
+// The noisy color and the auxiliary images external denoisers such as Open
+// Image Denoise and the OptiX denoiser take with it, from the samples of the
+// beauty pass. All are averaged over each pixel's samples like the color, so
+// edges line up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Features {
+    // White balanced like the beauty pass, without post effects.
+    pub color: Film,
+    // The reflectance of the first surface hit, in [0, 1].
+    pub albedo: Film,
+    // The world space normal of the first surface hit, facing the camera,
+    // zero where a ray sees the sky.
+    pub normal: Film,
+    // Per channel variance of `color`, i.e. of the mean of the samples.
+    pub variance: Film,
+}
+
+impl Features {
+    // The normals in camera space, x to the right, y up and z towards the
+    // camera, as the OptiX denoiser wants them.
+    pub fn camera_space_normals(&self, camera: &Camera) -> Film {
+        let (x, y, z) = camera.orientation().axes();
+        Film {
+            pixels: self
+                .normal
+                .pixels
+                .iter()
+                .map(|n| Vec3::new(n.dot(&x), n.dot(&y), n.dot(&z)))
+                .collect(),
+            ..self.normal.clone()
+        }
+    }
+
+    // Every buffer by name, as interleaved RGB floats from the top row down.
+    pub fn buffers(&self) -> [(&'static str, Vec<f32>); 4] {
+        [
+            ("color", self.color.to_f32()),
+            ("albedo", self.albedo.to_f32()),
+            ("normal", self.normal.to_f32()),
+            ("variance", self.variance.to_f32()),
+        ]
+    }
+
+    // Writes `<output>.color.pfm`, `<output>.albedo.pfm`, `<output>.normal.pfm`
+    // and `<output>.variance.pfm`, ready for `oidnDenoise`.
+    pub fn save(&self, output: &Path) -> std::io::Result<()> {
+        for (name, film) in [
+            ("color", &self.color),
+            ("albedo", &self.albedo),
+            ("normal", &self.normal),
+            ("variance", &self.variance),
+        ] {
+            film.save_pfm(&aov_path(output, name, "pfm").to_string_lossy())?;
+        }
+        Ok(())
+    }
+}
+
+// The albedo denoisers expect: the color of diffuse and metal surfaces, of
+// hair and of volumes, white for glass, whose look comes from what is behind
+// it, and the clamped color of lights and the sky.
+pub(crate) fn albedo(rec: Option<&HitRecord>, r: &Ray, world: &ObjectList) -> Color {
+    let clamp = |c: Color| Color::new(c.x().min(1.0), c.y().min(1.0), c.z().min(1.0));
+    match rec.map(|rec| (rec, &rec.mat)) {
+        None => clamp(crate::camera::sky(r, world)),
+        Some((rec, Material::Lambertian(l))) => l.albedo(r, rec),
+        Some((_, Material::Metal(m))) => m.albedo,
+        Some((_, Material::Glass(_))) => Color::new(1.0, 1.0, 1.0),
+        Some((_, Material::Hair(h))) => h.color,
+        Some((_, Material::Medium(i))) => i.albedo,
+        Some((rec, mat @ Material::DiffuseLight(_))) => clamp(mat.emitted_at(r, rec)),
+        Some((_, Material::Named(_))) => Color::default(),
+    }
+}
+
+pub fn features(camera: &Camera, passes: &[PixelPasses]) -> Features {
+    let [mut color, albedo, normal, mut variance] = films(camera, passes, |p| {
+        [p.light.total(), p.albedo, p.normal, p.variance]
+    });
+    if let Some(white_balance) = &camera.white_balance {
+        white_balance.apply(&mut color);
+        let gains = white_balance.gains();
+        variance
+            .pixels
+            .iter_mut()
+            .for_each(|v| *v = *v * gains * gains);
+    }
+    Features {
+        color,
+        albedo,
+        normal,
+        variance,
+    }
+}
+
+// Whether the AOV comes from the passes of the beauty pass, see
+// `Camera::render_film_with_passes`, instead of looking at the scene itself.
+pub fn is_pass(aov: &str) -> bool {
+    !matches!(aov, "object_id" | "alpha")
+}
+
+// Of the finite values.
+fn percentile(values: &[f64], p: f64) -> f64 {
+    let mut sorted: Vec<f64> = values.iter().copied().filter(|v| v.is_finite()).collect();
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    sorted.sort_by(f64::total_cmp);
+    sorted[((sorted.len() - 1) as f64 * p).round() as usize]
+}
+
+#[test]
+fn test_object_id() {
+    let sphere = crate::sphere::Sphere::new(
+        crate::vec3::Point3D::new(0.0, 0.0, 0.0),
+        1.0,
+        crate::material::Material::Named("x".to_string()),
+    );
+    let a = Object::from(sphere.clone()).named("teapot");
+    let b = Object::from(sphere).named("teapot");
+    assert_eq!(object_id(&a, 0), object_id(&b, 7));
+    assert_ne!(object_id(&a, 0), hash("#0"));
+    assert_ne!(id_color(object_id(&a, 0)), [0, 0, 0]);
+    assert_eq!(id_color(0), [0, 0, 0]);
+}
+
+#[test]
+fn test_aov_path() {
+    assert_eq!(
+        aov_path(Path::new("out/render.png"), "object_id", "json"),
+        Path::new("out/render.object_id.json")
+    );
+}
+
+#[test]
+fn test_percentile() {
+    let values: Vec<f64> = (0..=100).map(|v| v as f64).chain([f64::INFINITY]).collect();
+    assert_eq!(percentile(&values, 0.99), 99.0);
+    assert_eq!(percentile(&values, 0.0), 0.0);
+    assert_eq!(percentile(&[f64::INFINITY], 0.5), 0.0);
+}
+
+#[test]
+fn test_features() {
+    use crate::material::Lambertian;
+    use crate::vec3::Point3D;
+
+    let mut world = ObjectList::new();
+    world.add_sphere(
+        Point3D::new(0.0, 0.0, -2.0),
+        1.0,
+        Material::Lambertian(Lambertian::new(Color::new(0.8, 0.3, 0.3))),
+    );
+    let camera = Camera::new(
+        8,
+        8,
+        4,
+        8,
+        90.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let (beauty, passes) = camera
+        .render_film_with_passes(&world, &[], true, None)
+        .unwrap();
+    assert_eq!(beauty, camera.render_film(&world, &[]));
+    let features = features(&camera, &passes);
+    for (a, b) in features.color.pixels.iter().zip(&beauty.pixels) {
+        assert!((*a - *b).length() < 1e-9);
+    }
+
+    let center = 4 * 8 + 4;
+    assert!((features.albedo.pixels[center] - Color::new(0.8, 0.3, 0.3)).length() < 1e-9);
+    assert!(features.normal.pixels[center].z() > 0.9);
+    assert!(features.camera_space_normals(&camera).pixels[center].z() > 0.9);
+    assert_eq!(features.normal.pixels[0], Color::default());
+    assert!(features
+        .variance
+        .pixels
+        .iter()
+        .all(|v| v.x() >= 0.0 && v.y() >= 0.0 && v.z() >= 0.0));
+    assert!(features.variance.pixels[center].x() > 0.0);
+
+    let buffers = features.buffers();
+    assert_eq!(buffers[1].1.len(), 8 * 8 * 3);
+    assert_eq!(
+        buffers.map(|(name, _)| name),
+        ["color", "albedo", "normal", "variance"]
+    );
+}
@@ -0,0 +1,219 @@
+// A planet's atmosphere in place of the sky's gradient, lit by the sun.
+// Molecules scatter blue light most (Rayleigh scattering), which makes the
+// sky blue and the setting sun red; haze scatters all colors alike and
+// mostly onwards (Mie scattering), which whitens the sky around the sun. Both
+// thin out exponentially with height. The light scattered towards a ray is
+// summed along it, once per point (single scattering), with what the air
+// before and after the point lets through. From far enough away the planet
+// shows as a ball with a thin blue rim.
+//
+// Lengths are in meters; the scene's origin is `altitude` above the ground,
+// with y up, and `meters_per_unit` scales the scene into the planet.
+
+use serde::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::sun::Sun;
+use crate::vec3::{Point3D, Vec3};
+
+use std::f64::consts::PI;
+
+// Points along a view ray, and along the way from each to the sun.
+const VIEW_SAMPLES: usize = 16;
+const LIGHT_SAMPLES: usize = 8;
+
+// Haze absorbs a little of the light it stops.
+const MIE_EXTINCTION: f64 = 1.1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Atmosphere {
+    pub planet_radius: f64,
+    // Where the air ends, above the ground.
+    pub atmosphere_height: f64,
+    // The heights over which the densities of molecules and haze fall to
+    // 1/e of theirs at the ground.
+    pub rayleigh_scale_height: f64,
+    pub mie_scale_height: f64,
+    // Scattering per meter at the ground.
+    pub rayleigh: Color,
+    pub mie: f64,
+    // Henyey-Greenstein g of haze.
+    pub mie_anisotropy: f64,
+    pub ground_albedo: Color,
+    pub altitude: f64,
+    pub meters_per_unit: f64,
+}
+
+// The earth's.
+impl Default for Atmosphere {
+    fn default() -> Self {
+        Atmosphere {
+            planet_radius: 6_360e3,
+            atmosphere_height: 100e3,
+            rayleigh_scale_height: 8e3,
+            mie_scale_height: 1.2e3,
+            rayleigh: Color::new(5.8e-6, 13.5e-6, 33.1e-6),
+            mie: 21e-6,
+            mie_anisotropy: 0.76,
+            ground_albedo: Color::new(0.3, 0.3, 0.3),
+            altitude: 1.0,
+            meters_per_unit: 1.0,
+        }
+    }
+}
+
+fn exp(c: Color) -> Color {
+    Color::new((-c.x()).exp(), (-c.y()).exp(), (-c.z()).exp())
+}
+
+// Where a ray from `p` along the unit vector `d` enters and leaves a sphere
+// around the planet's center.
+fn sphere_hits(p: Vec3, d: Vec3, radius: f64) -> Option<(f64, f64)> {
+    let half_b = p.dot(&d);
+    let discriminant = half_b * half_b - (p.length_squared() - radius * radius);
+    if discriminant < 0.0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    Some((-half_b - root, -half_b + root))
+}
+
+impl Atmosphere {
+    // A scene point, from the planet's center.
+    fn position(&self, p: Point3D) -> Vec3 {
+        Vec3::new(0.0, self.planet_radius + self.altitude, 0.0) + p * self.meters_per_unit
+    }
+
+    // The densities of molecules and of haze at `p`, relative to the ground.
+    fn densities(&self, p: Vec3) -> (f64, f64) {
+        let height = (p.length() - self.planet_radius).max(0.0);
+        (
+            (-height / self.rayleigh_scale_height).exp(),
+            (-height / self.mie_scale_height).exp(),
+        )
+    }
+
+    fn optical_depth(&self, rayleigh: f64, mie: f64) -> Color {
+        self.rayleigh * rayleigh + Color::new(1.0, 1.0, 1.0) * (self.mie * MIE_EXTINCTION * mie)
+    }
+
+    // The densities summed from `p` along the unit vector `d` out of the
+    // air, or None when the ground is in the way.
+    fn column(&self, p: Vec3, d: Vec3, samples: usize) -> Option<(f64, f64)> {
+        if matches!(sphere_hits(p, d, self.planet_radius), Some((near, _)) if near > 0.0) {
+            return None;
+        }
+        let top = self.planet_radius + self.atmosphere_height;
+        let (_, far) = sphere_hits(p, d, top)?;
+        let step = far.max(0.0) / samples as f64;
+        let (mut rayleigh, mut mie) = (0.0, 0.0);
+        for i in 0..samples {
+            let (r, m) = self.densities(p + (i as f64 + 0.5) * step * d);
+            rayleigh += r * step;
+            mie += m * step;
+        }
+        Some((rayleigh, mie))
+    }
+
+    // What the air lets through of light reaching `origin` from `direction`,
+    // black where the ground is in the way.
+    pub fn transmittance(&self, origin: Point3D, direction: Vec3) -> Color {
+        match self.column(self.position(origin), direction.unit_vector(), VIEW_SAMPLES) {
+            Some((rayleigh, mie)) => exp(self.optical_depth(rayleigh, mie)),
+            None => Color::default(),
+        }
+    }
+
+    // The light reaching `origin` from `direction`: sunlight scattered by
+    // the air on the way, and the ground lit by the sun where the ray hits
+    // it. Space beyond is black, and so is the sun's disk, which the sun
+    // draws itself.
+    pub fn sky(&self, origin: Point3D, direction: Vec3, sun: &Sun) -> Color {
+        let (p, d) = (self.position(origin), direction.unit_vector());
+        let top = self.planet_radius + self.atmosphere_height;
+        let Some((enter, leave)) = sphere_hits(p, d, top) else {
+            return Color::default();
+        };
+        let ground = sphere_hits(p, d, self.planet_radius)
+            .map(|(near, _)| near)
+            .filter(|&near| near > 0.0);
+        let (start, end) = (enter.max(0.0), ground.unwrap_or(leave));
+        if end <= start {
+            return Color::default();
+        }
+
+        let step = (end - start) / VIEW_SAMPLES as f64;
+        let (mut view_rayleigh, mut view_mie) = (0.0, 0.0);
+        let (mut rayleigh, mut mie) = (Color::default(), Color::default());
+        for i in 0..VIEW_SAMPLES {
+            let x = p + (start + (i as f64 + 0.5) * step) * d;
+            let (r, m) = self.densities(x);
+            view_rayleigh += r * step;
+            view_mie += m * step;
+            let Some((light_rayleigh, light_mie)) = self.column(x, sun.towards, LIGHT_SAMPLES)
+            else {
+                continue;
+            };
+            let through =
+                exp(self.optical_depth(view_rayleigh + light_rayleigh, view_mie + light_mie));
+            rayleigh += through * (r * step);
+            mie += through * (m * step);
+        }
+
+        let cos_theta = d.dot(&sun.towards);
+        let rayleigh_phase = 3.0 / (16.0 * PI) * (1.0 + cos_theta * cos_theta);
+        let g = self.mie_anisotropy;
+        let mie_phase = (1.0 - g * g) / (4.0 * PI * (1.0 + g * g - 2.0 * g * cos_theta).powf(1.5));
+        let scattered = rayleigh * self.rayleigh * rayleigh_phase + mie * (self.mie * mie_phase);
+
+        let ground_light = match ground {
+            Some(t) => {
+                let x = p + t * d;
+                let cosine = x.unit_vector().dot(&sun.towards).max(0.0);
+                let lit = match self.column(x, sun.towards, LIGHT_SAMPLES) {
+                    Some((r, m)) => exp(self.optical_depth(r, m)),
+                    None => Color::default(),
+                };
+                self.ground_albedo / PI
+                    * cosine
+                    * lit
+                    * exp(self.optical_depth(view_rayleigh, view_mie))
+            }
+            None => Color::default(),
+        };
+        sun.irradiance * (scattered + ground_light)
+    }
+}
+
+#[test]
+fn test_sky() {
+    let atmosphere = Atmosphere::default();
+    let noon = Sun::new(Vec3::new(0.2, 1.0, 0.0));
+    let up = atmosphere.sky(Point3D::default(), Vec3::new(0.0, 1.0, 0.0), &noon);
+    // Blue, and about as bright as the gradient sky under the default sun.
+    assert!(up.z() > up.y() && up.y() > up.x(), "{:?}", up);
+    assert!(up.z() > 0.2 && up.z() < 2.0, "{:?}", up);
+
+    // At sunset the sky towards the sun is red, and the sun itself too.
+    let sunset = Sun::new(Vec3::new(1.0, 0.02, 0.0));
+    let towards = atmosphere.sky(Point3D::default(), Vec3::new(1.0, 0.05, 0.0), &sunset);
+    assert!(towards.x() > towards.z(), "{:?}", towards);
+    let light = atmosphere.transmittance(Point3D::default(), sunset.towards);
+    assert!(light.x() > 2.0 * light.z(), "{:?}", light);
+    let overhead = atmosphere.transmittance(Point3D::default(), Vec3::new(0.0, 1.0, 0.0));
+    assert!(overhead.x() > 0.8 && overhead.z() < overhead.x());
+    assert_eq!(
+        atmosphere.transmittance(Point3D::default(), Vec3::new(0.0, -1.0, 0.0)),
+        Color::default()
+    );
+
+    // From space the planet is lit, and space around it black.
+    let space = Point3D::new(0.0, 2e6, 0.0);
+    let planet = atmosphere.sky(space, Vec3::new(0.0, -1.0, 0.0), &noon);
+    assert!(planet.y() > 0.1, "{:?}", planet);
+    assert_eq!(
+        atmosphere.sky(space, Vec3::new(0.0, 1.0, 0.0), &noon),
+        Color::default()
+    );
+}
@@ -0,0 +1,54 @@
+// Backplates: a photograph behind the scene, seen by camera rays that miss
+// every object, for integrating CG into it. The image is stretched over the
+// frame in screen space, so it stays put when the camera turns, and it lights
+// nothing: reflections and lighting still come from the sky.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::film::{read_linear_image, Film};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Backplate {
+    pub file: String,
+    // In linear colors.
+    #[serde(skip)]
+    pub image: Option<Arc<Film>>,
+}
+
+impl Backplate {
+    // Reads the image and returns the path it was read from.
+    pub fn load(&mut self, base_dir: &Path) -> io::Result<PathBuf> {
+        let path = base_dir.join(&self.file);
+        let image = read_linear_image(&path.to_string_lossy())
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        self.image = Some(Arc::new(image));
+        Ok(path)
+    }
+
+    // The color at (x, y) as fractions of the frame's width and height from
+    // its top left corner; None until loaded.
+    pub fn color(&self, x: f64, y: f64) -> Option<Color> {
+        let image = self.image.as_ref()?;
+        Some(image.sample(x * image.width as f64, y * image.height as f64))
+    }
+}
+
+#[test]
+fn test_color() {
+    let mut backplate = Backplate {
+        file: String::new(),
+        image: None,
+    };
+    assert_eq!(backplate.color(0.5, 0.5), None);
+
+    let mut image = Film::new(2, 2);
+    image.pixels[1] = Color::new(1.0, 0.0, 0.0);
+    backplate.image = Some(Arc::new(image));
+    assert_eq!(backplate.color(0.75, 0.25), Some(Color::new(1.0, 0.0, 0.0)));
+    assert_eq!(backplate.color(0.25, 0.75), Some(Color::default()));
+}
@@ -0,0 +1,245 @@
+// Bakes ambient occlusion or irradiance over the surface of a mesh into a
+// texture laid out by the mesh's UVs. The mesh only occludes itself and is not
+// part of the scene; the scene's objects occlude and light it.
+
+use std::io;
+
+use crate::camera::Camera;
+use crate::color::{write_color, Color};
+use crate::film::write_image;
+use crate::hittable::Hittable;
+use crate::interval::Interval;
+use crate::mesh::{Mesh, TriangleMesh};
+use crate::parallel::*;
+use crate::ray::{Ray, RayKind};
+use crate::scene::Scene;
+use crate::vec3::Vec3;
+
+// Offset of the rays from the surface so it doesn't occlude itself.
+const EPSILON: f64 = 1e-4;
+
+#[derive(Debug, Clone, Copy)]
+pub struct BakeSettings {
+    // Width and height of the texture.
+    pub size: usize,
+    pub samples: usize,
+    // Occluders further away than this don't darken the ambient occlusion.
+    pub distance: f64,
+    // Bake the light arriving at the surface instead of ambient occlusion.
+    pub irradiance: bool,
+    // Texels outside of all triangles copy their neighbours this many times,
+    // so filtering doesn't bleed the background into seams.
+    pub padding: usize,
+}
+
+impl Default for BakeSettings {
+    fn default() -> Self {
+        BakeSettings {
+            size: 512,
+            samples: 64,
+            distance: f64::INFINITY,
+            irradiance: false,
+            padding: 2,
+        }
+    }
+}
+
+// For every texel, the triangle and barycentric coordinates it covers.
+fn rasterize(mesh: &Mesh, size: usize) -> Vec<Option<(usize, [f64; 3])>> {
+    let mut texels = vec![None; size * size];
+
+    for triangle in 0..mesh.triangles.len() {
+        let Some(uv) = mesh.uv(triangle) else {
+            continue;
+        };
+        // Row 0 of the image is at the top, where v = 1.
+        let p = uv.map(|(u, v)| (u * size as f64, (1.0 - v) * size as f64));
+        let area = (p[1].0 - p[0].0) * (p[2].1 - p[0].1) - (p[2].0 - p[0].0) * (p[1].1 - p[0].1);
+        if area.abs() < 1e-12 {
+            continue;
+        }
+
+        let min_x = p.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
+        let max_x = p.iter().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max);
+        let min_y = p.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+        let max_y = p.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+        let x_range = (min_x.floor().max(0.0) as usize)..(max_x.ceil().min(size as f64) as usize);
+        let y_range = (min_y.floor().max(0.0) as usize)..(max_y.ceil().min(size as f64) as usize);
+
+        for y in y_range {
+            for x in x_range.clone() {
+                let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+                let edge = |a: (f64, f64), b: (f64, f64)| {
+                    ((b.0 - a.0) * (py - a.1) - (px - a.0) * (b.1 - a.1)) / area
+                };
+                let barycentric = [edge(p[1], p[2]), edge(p[2], p[0]), edge(p[0], p[1])];
+                if barycentric.iter().all(|b| *b >= 0.0) {
+                    texels[y * size + x] = Some((triangle, barycentric));
+                }
+            }
+        }
+    }
+
+    texels
+}
+
+fn bake_texel(
+    camera: &Camera,
+    scene: &Scene,
+    mesh: &TriangleMesh,
+    settings: &BakeSettings,
+    triangle: usize,
+    barycentric: [f64; 3],
+) -> Color {
+    let normal = mesh.mesh.normal(triangle, barycentric);
+    let origin = mesh.mesh.position(triangle, barycentric) + EPSILON * normal;
+
+    let mut total = Color::default();
+    for _ in 0..settings.samples {
+        // Cosine weighted, like the scattering of a Lambertian surface.
+        let direction = (normal + Vec3::random_unit_vector()).or_if_near_zero(normal);
+        let r = Ray::new(origin, direction.unit_vector()).with_kind(RayKind::Diffuse);
+
+        if settings.irradiance {
+            if !mesh.occluded(&r, &Interval::new(0.0, f64::INFINITY)) {
+                total += camera.ray_color(&r, camera.max_depth(), &scene.objects);
+            }
+        } else {
+            let ray_t = Interval::new(0.0, settings.distance);
+            let blocked = mesh.occluded(&r, &ray_t)
+                || scene
+                    .intersect(&r)
+                    .is_some_and(|rec| rec.t <= settings.distance);
+            if !blocked {
+                total += Color::new(1.0, 1.0, 1.0);
+            }
+        }
+    }
+    total / settings.samples.max(1) as f64
+}
+
+fn dilate(texels: &mut [Option<Color>], size: usize) {
+    let source = texels.to_vec();
+    for y in 0..size {
+        for x in 0..size {
+            if source[y * size + x].is_some() {
+                continue;
+            }
+            let mut sum = Color::default();
+            let mut count = 0;
+            for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                if nx < 0 || ny < 0 || nx >= size as i64 || ny >= size as i64 {
+                    continue;
+                }
+                if let Some(color) = source[ny as usize * size + nx as usize] {
+                    sum += color;
+                    count += 1;
+                }
+            }
+            if count > 0 {
+                texels[y * size + x] = Some(sum / count as f64);
+            }
+        }
+    }
+}
+
+pub fn bake(
+    camera: &Camera,
+    scene: &Scene,
+    mesh: &TriangleMesh,
+    settings: &BakeSettings,
+) -> Vec<Color> {
+    let mut texels: Vec<Option<Color>> = rasterize(&mesh.mesh, settings.size)
+        .into_par_iter()
+        .map(|texel| {
+            texel.map(|(triangle, barycentric)| {
+                bake_texel(camera, scene, mesh, settings, triangle, barycentric)
+            })
+        })
+        .collect();
+
+    for _ in 0..settings.padding {
+        dilate(&mut texels, settings.size);
+    }
+    texels.into_iter().map(Option::unwrap_or_default).collect()
+}
+
+// Ambient occlusion is stored linearly, irradiance gamma corrected like a
+// regular render.
+pub fn bake_to_file(
+    camera: &Camera,
+    scene: &Scene,
+    mesh: &TriangleMesh,
+    settings: &BakeSettings,
+    filename: &str,
+) -> io::Result<()> {
+    if mesh
+        .mesh
+        .triangles
+        .iter()
+        .all(|t| t.iter().any(|v| v.uv.is_none()))
+    {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "the mesh has no texture coordinates",
+        ));
+    }
+
+    let texels = bake(camera, scene, mesh, settings);
+    let mut buffer = Vec::with_capacity(texels.len() * 3);
+    for texel in texels {
+        if settings.irradiance {
+            write_color(&mut buffer, texel)?;
+        } else {
+            let value = (texel.x().clamp(0.0, 1.0) * 255.0).round() as u8;
+            buffer.extend([value; 3]);
+        }
+    }
+    write_image(filename, &buffer, (settings.size, settings.size))
+}
+
+#[test]
+fn test_bake_ambient_occlusion() {
+    // A unit quad covering the whole texture with a wall standing on its
+    // right edge.
+    let mesh = TriangleMesh::new(
+        Mesh::parse_obj(
+            "v 0 0 0\nv 1 0 0\nv 1 0 -1\nv 0 0 -1\nvt 0 1\nvt 1 1\nvt 1 0\nvt 0 0\nvn 0 1 0\n\
+         f 1/1/1 2/2/1 3/3/1 4/4/1\n",
+        )
+        .unwrap(),
+    );
+    let mut objects = crate::hittable::ObjectList::new();
+    objects.add(crate::hittable::Object::from(crate::sphere::Sphere::new(
+        crate::vec3::Point3D::new(1001.0, 0.0, 0.0),
+        1000.0,
+        crate::material::Material::Named("x".to_string()),
+    )));
+    let scene = Scene::new(objects);
+    let camera = Camera::new(
+        1,
+        1,
+        1,
+        1,
+        90.0,
+        crate::vec3::Point3D::new(0.0, 1.0, 0.0),
+        crate::vec3::Point3D::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, -1.0),
+        0.0,
+        1.0,
+    );
+    let settings = BakeSettings {
+        size: 8,
+        samples: 256,
+        distance: 0.5,
+        ..BakeSettings::default()
+    };
+
+    let texels = bake(&camera, &scene, &mesh, &settings);
+    assert_eq!(texels.len(), 64);
+    let (left, right) = (texels[4 * 8].x(), texels[4 * 8 + 7].x());
+    assert_eq!(left, 1.0);
+    assert!(right < 0.8);
+    assert!(texels.iter().all(|t| (0.0..=1.0).contains(&t.x())));
+}
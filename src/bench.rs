@@ -0,0 +1,122 @@
+// Fixed workloads for measuring performance, shared by `raytracer bench` and
+// the criterion benches in benches/. Each one returns how many samples it
+// traced, so results can be compared as samples per second.
+
+use std::time::{Duration, Instant};
+
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable, ObjectList};
+use crate::interval::Interval;
+use crate::material::{Glass, Lambertian, Material, Metal, Scatterable};
+use crate::ray::Ray;
+use crate::vec3::{Point3D, Vec3};
+
+pub const WORKLOADS: &[&str] = &["primary_rays", "scatter", "render"];
+
+// The cover scene of "Ray Tracing in One Weekend", 481 spheres, at a
+// small size.
+pub fn scene() -> (Camera, ObjectList) {
+    let config = crate::scenes::random_spheres(42);
+    let mut camera = config.camera;
+    camera.set_size(160, 90);
+    camera.set_samples_per_pixel(4);
+    camera.set_max_depth(8);
+    (camera, config.object_list)
+}
+
+// One ray through the center of every pixel, only finding the closest hit.
+pub fn primary_rays(camera: &Camera, world: &ObjectList) -> usize {
+    for j in 0..camera.height() {
+        for i in 0..camera.width() {
+            let mut rec = HitRecord::default();
+            world.hit(
+                &camera.pixel_ray(i, j),
+                &Interval::new(0.001, f64::INFINITY),
+                &mut rec,
+            );
+        }
+    }
+    camera.width() * camera.height()
+}
+
+// `count` scattering events on each kind of material.
+pub fn scatter(count: usize) -> usize {
+    let materials = [
+        Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        Material::Metal(Metal::new(Color::new(0.8, 0.8, 0.8), 0.3)),
+        Material::Glass(Glass::new(1.5)),
+    ];
+    let mut rec = HitRecord {
+        normal: Vec3::new(0.0, 1.0, 0.0),
+        front_face: true,
+        ..HitRecord::default()
+    };
+    let r_in = Ray::new(Point3D::new(0.0, 1.0, 1.0), Vec3::new(0.0, -1.0, -1.0));
+    for material in materials.iter() {
+        rec.mat = material.clone();
+        for _ in 0..count {
+            let mut attenuation = Color::default();
+            let mut scattered = Ray::default();
+            material.scatter(&r_in, &rec, &mut attenuation, &mut scattered);
+        }
+    }
+    count * materials.len()
+}
+
+// Full paths for every sample of the image.
+pub fn render(camera: &Camera, world: &ObjectList) -> usize {
+    camera.render_film(world, &[]);
+    camera.width() * camera.height() * camera.samples_per_pixel()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct BenchResult {
+    pub name: &'static str,
+    pub samples: usize,
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    pub fn msamples_per_second(&self) -> f64 {
+        self.samples as f64 / self.elapsed.as_secs_f64() / 1e6
+    }
+}
+
+// Repeats each workload for at least `duration`.
+pub fn run(duration: Duration) -> Vec<BenchResult> {
+    let (camera, world) = scene();
+    WORKLOADS
+        .iter()
+        .map(|&name| {
+            let start = Instant::now();
+            let mut samples = 0;
+            while samples == 0 || start.elapsed() < duration {
+                samples += match name {
+                    "primary_rays" => primary_rays(&camera, &world),
+                    "scatter" => scatter(100_000),
+                    _ => render(&camera, &world),
+                };
+            }
+            BenchResult {
+                name,
+                samples,
+                elapsed: start.elapsed(),
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_workloads() {
+    let (camera, world) = scene();
+    assert_eq!(primary_rays(&camera, &world), 160 * 90);
+    assert_eq!(scatter(10), 30);
+
+    let result = BenchResult {
+        name: "render",
+        samples: 3_000_000,
+        elapsed: Duration::from_millis(1500),
+    };
+    assert_eq!(result.msamples_per_second(), 2.0);
+}
@@ -0,0 +1,165 @@
+// A tileable blue noise texture: thresholds in (0, 1) whose neighbors are as
+// different as possible, so noise made from them has no clumps and no low
+// frequencies. Made once with Ulichney's void-and-cluster method: points go
+// into the largest gap, or come out of the tightest cluster, of a pattern
+// blurred with a Gaussian that wraps around the edges, and the order they do
+// that in is the threshold.
+
+use std::sync::OnceLock;
+
+use rand::Rng;
+
+use crate::rng::SplitMix64;
+
+pub const SIZE: usize = 64;
+const SIGMA: f64 = 1.5;
+// Share of the pixels in the starting pattern.
+const INITIAL: f64 = 0.1;
+
+// The threshold of pixel (x, y), repeating every SIZE pixels.
+pub fn threshold(x: usize, y: usize) -> f64 {
+    tile()[(y % SIZE) * SIZE + x % SIZE]
+}
+
+pub fn tile() -> &'static [f64] {
+    static TILE: OnceLock<Vec<f64>> = OnceLock::new();
+    TILE.get_or_init(|| {
+        let n = SIZE * SIZE;
+        void_and_cluster()
+            .into_iter()
+            .map(|rank| (rank as f64 + 0.5) / n as f64)
+            .collect()
+    })
+}
+
+// The Gaussian blur of a pattern, updated point by point.
+struct Energy {
+    kernel: Vec<f64>,
+    values: Vec<f64>,
+}
+
+impl Energy {
+    fn new() -> Energy {
+        let mut kernel = vec![0.0; SIZE * SIZE];
+        for dy in 0..SIZE {
+            for dx in 0..SIZE {
+                // The shorter way around the torus.
+                let x = dx.min(SIZE - dx) as f64;
+                let y = dy.min(SIZE - dy) as f64;
+                kernel[dy * SIZE + dx] = (-(x * x + y * y) / (2.0 * SIGMA * SIGMA)).exp();
+            }
+        }
+        Energy {
+            kernel,
+            values: vec![0.0; SIZE * SIZE],
+        }
+    }
+
+    fn add(&mut self, index: usize, sign: f64) {
+        let (px, py) = (index % SIZE, index / SIZE);
+        for y in 0..SIZE {
+            let dy = (y + SIZE - py) % SIZE;
+            for x in 0..SIZE {
+                let dx = (x + SIZE - px) % SIZE;
+                self.values[y * SIZE + x] += sign * self.kernel[dy * SIZE + dx];
+            }
+        }
+    }
+
+    // The point of the pattern with the most energy around it.
+    fn tightest_cluster(&self, pattern: &[bool]) -> usize {
+        (0..pattern.len())
+            .filter(|&i| pattern[i])
+            .max_by(|&a, &b| self.values[a].total_cmp(&self.values[b]))
+            .unwrap()
+    }
+
+    // The empty pixel with the least energy around it.
+    fn largest_void(&self, pattern: &[bool]) -> usize {
+        (0..pattern.len())
+            .filter(|&i| !pattern[i])
+            .min_by(|&a, &b| self.values[a].total_cmp(&self.values[b]))
+            .unwrap()
+    }
+}
+
+// The rank of every pixel, 0 to SIZE² - 1.
+fn void_and_cluster() -> Vec<usize> {
+    let n = SIZE * SIZE;
+    let mut rng = SplitMix64::new(0x5eed);
+    let mut pattern = vec![false; n];
+    let mut energy = Energy::new();
+    let ones = (n as f64 * INITIAL) as usize;
+    let mut placed = 0;
+    while placed < ones {
+        let index = rng.gen_range(0..n);
+        if !pattern[index] {
+            pattern[index] = true;
+            energy.add(index, 1.0);
+            placed += 1;
+        }
+    }
+
+    // Spread the starting points out: move the tightest cluster into the
+    // largest void until that is where it came from.
+    loop {
+        let cluster = energy.tightest_cluster(&pattern);
+        pattern[cluster] = false;
+        energy.add(cluster, -1.0);
+        let void = energy.largest_void(&pattern);
+        pattern[void] = true;
+        energy.add(void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0; n];
+    let initial = (pattern.clone(), energy.values.clone());
+
+    // Ranks below the starting pattern's, removing its points one by one.
+    for rank in (0..ones).rev() {
+        let cluster = energy.tightest_cluster(&pattern);
+        pattern[cluster] = false;
+        energy.add(cluster, -1.0);
+        ranks[cluster] = rank;
+    }
+
+    // And above, filling the voids until every pixel is taken.
+    (pattern, energy.values) = initial;
+    for rank in ones..n {
+        let void = energy.largest_void(&pattern);
+        pattern[void] = true;
+        energy.add(void, 1.0);
+        ranks[void] = rank;
+    }
+    ranks
+}
+
+#[test]
+fn test_tile() {
+    let tile = tile();
+    let n = SIZE * SIZE;
+
+    // Every threshold appears exactly once.
+    let mut sorted = tile.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    for (rank, value) in sorted.iter().enumerate() {
+        assert_eq!(*value, (rank as f64 + 0.5) / n as f64);
+    }
+    assert_eq!(threshold(3, 5), threshold(3 + SIZE, 5 + 2 * SIZE));
+
+    // No low frequencies: every 8x8 block averages close to one half, much
+    // closer than for white noise, whose blocks spread by about 0.036.
+    for by in 0..SIZE / 8 {
+        for bx in 0..SIZE / 8 {
+            let mut sum = 0.0;
+            for y in by * 8..by * 8 + 8 {
+                for x in bx * 8..bx * 8 + 8 {
+                    sum += threshold(x, y);
+                }
+            }
+            assert!((sum / 64.0 - 0.5).abs() < 0.03, "{}", sum / 64.0);
+        }
+    }
+}
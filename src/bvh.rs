@@ -0,0 +1,189 @@
+// A bounding volume hierarchy over many small primitives of one shape, like
+// the triangles of a mesh. It only knows their boxes: nodes are split at the
+// middle primitive along their longest axis, and a ray visits the leaves whose
+// boxes it passes through, closer hits narrowing the search as it goes.
+
+use crate::aabb::Aabb;
+use crate::interval::Interval;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+// Primitives per leaf, at most.
+const LEAF_SIZE: usize = 4;
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    bounds: Aabb,
+    // A leaf's first primitive in `order`, or an inner node's second child;
+    // the first child follows its parent.
+    start: usize,
+    // Primitives in a leaf, 0 for inner nodes.
+    count: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    order: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn new(boxes: &[Aabb]) -> Bvh {
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(2 * boxes.len().div_ceil(LEAF_SIZE)),
+            order: (0..boxes.len()).collect(),
+        };
+        if !boxes.is_empty() {
+            bvh.build(boxes, 0, boxes.len());
+        }
+        bvh
+    }
+
+    fn build(&mut self, boxes: &[Aabb], start: usize, end: usize) {
+        let bounds = self.order[start..end]
+            .iter()
+            .fold(Aabb::EMPTY, |bounds, &i| {
+                Aabb::surrounding_box(&bounds, &boxes[i])
+            });
+        let index = self.nodes.len();
+        self.nodes.push(Node {
+            bounds,
+            start,
+            count: end - start,
+        });
+        if end - start <= LEAF_SIZE {
+            return;
+        }
+
+        let centroids = self.order[start..end].iter().fold(Aabb::EMPTY, |c, &i| {
+            let p = boxes[i].centroid();
+            Aabb::surrounding_box(&c, &Aabb::from_points(p, p))
+        });
+        let axis = centroids.longest_axis();
+        let middle = (start + end) / 2;
+        self.order[start..end].select_nth_unstable_by(middle - start, |&a, &b| {
+            let key = |i: usize| [Vec3::x, Vec3::y, Vec3::z][axis](&boxes[i].centroid());
+            key(a).total_cmp(&key(b))
+        });
+        self.build(boxes, start, middle);
+        let second = self.nodes.len();
+        self.build(boxes, middle, end);
+        self.nodes[index].start = second;
+        self.nodes[index].count = 0;
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::EMPTY, |node| node.bounds)
+    }
+
+    // Calls `hit` with every primitive whose box `r` passes through within
+    // the closest hit so far, which `hit` returns the distance of. Whether
+    // anything was hit.
+    pub fn hit(
+        &self,
+        r: &Ray,
+        ray_t: &Interval,
+        mut hit: impl FnMut(usize, &Interval) -> Option<f64>,
+    ) -> bool {
+        let mut closest = ray_t.max;
+        let mut found = false;
+        let mut stack = Vec::with_capacity(64);
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            let interval = Interval::new(ray_t.min, closest);
+            if !node.bounds.hit(r, &interval) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.start);
+                stack.push(index + 1);
+                continue;
+            }
+            for &i in &self.order[node.start..node.start + node.count] {
+                if let Some(t) = hit(i, &Interval::new(ray_t.min, closest)) {
+                    closest = t;
+                    found = true;
+                }
+            }
+        }
+        found
+    }
+
+    // Whether `hit` is true for any primitive along the ray, stopping at the
+    // first, in no particular order.
+    pub fn any(&self, r: &Ray, ray_t: &Interval, mut hit: impl FnMut(usize) -> bool) -> bool {
+        let mut stack = Vec::with_capacity(64);
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if !node.bounds.hit(r, ray_t) {
+                continue;
+            }
+            if node.count == 0 {
+                stack.push(node.start);
+                stack.push(index + 1);
+                continue;
+            }
+            if self.order[node.start..node.start + node.count]
+                .iter()
+                .any(|&i| hit(i))
+            {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+#[test]
+fn test_bvh() {
+    use crate::vec3::Point3D;
+
+    // A row of unit boxes along x.
+    let boxes: Vec<Aabb> = (0..100)
+        .map(|i| {
+            let x = i as f64 * 2.0;
+            Aabb::from_points(Point3D::new(x, 0.0, 0.0), Point3D::new(x + 1.0, 1.0, 1.0))
+        })
+        .collect();
+    let bvh = Bvh::new(&boxes);
+    assert_eq!(bvh.bounds().x, Interval::new(0.0, 199.0));
+
+    // Looking down the row from the far end, the last box is the closest.
+    let r = Ray::new(Point3D::new(300.0, 0.5, 0.5), Vec3::new(-1.0, 0.0, 0.0));
+    let mut visited = 0;
+    let mut closest = None;
+    let hit = bvh.hit(&r, &Interval::new(0.0, f64::INFINITY), |i, interval| {
+        visited += 1;
+        let t = 300.0 - (i as f64 * 2.0 + 1.0);
+        interval.surrounds(t).then(|| {
+            closest = Some(i);
+            t
+        })
+    });
+    assert!(hit);
+    assert_eq!(closest, Some(99));
+    // Any box blocks it, and the search stops there.
+    let before = visited;
+    assert!(bvh.any(&r, &Interval::new(0.0, f64::INFINITY), |_| {
+        visited += 1;
+        true
+    }));
+    assert_eq!(visited, before + 1);
+    assert!(!bvh.any(&r, &Interval::new(0.0, 50.0), |i| i < 100 - 25));
+
+    // A ray past the row visits nothing.
+    let r = Ray::new(Point3D::new(0.5, 5.0, 0.5), Vec3::new(1.0, 0.0, 0.0));
+    let before = visited;
+    assert!(!bvh.hit(&r, &Interval::new(0.0, f64::INFINITY), |_, _| {
+        visited += 1;
+        None
+    }));
+    assert_eq!(visited, before);
+    assert!(!Bvh::new(&[]).hit(&r, &Interval::new(0.0, 1.0), |_, _| Some(0.5)));
+}
@@ -1,67 +1,451 @@
-use crate::color::{write_color, Color};
-use crate::hittable::{HitRecord, Hittable, ObjectList};
+use crate::aov;
+use crate::color::{luminance, Clip, Color};
+use crate::exr;
+use crate::film::{Film, ImageStream};
+use crate::guide::{Guiding, GUIDED_FRACTION};
+use crate::hittable::{HitRecord, Hittable, Object, ObjectList};
 use crate::interval::Interval;
-use crate::material::Scatterable;
-use crate::ray::Ray;
+use crate::light_link::ObjectSet;
+use crate::material::{Lambertian, Material, Scatterable};
+use crate::parallel::*;
+use crate::portal::{self, power_heuristic};
+use crate::post::{self, PostEffect, WhiteBalance};
+use crate::profile::Cost;
+use crate::quat::{Quat, Rotation};
+use crate::ray::{Ray, RayKind};
+use crate::rng;
+use crate::shake::CameraShake;
+use crate::sppm::{self, Sppm};
+use crate::sun::Sun;
+use crate::texture::uv_checker;
 use crate::vec3::{Point3D, Vec3};
-use chrono::{Local, Timelike};
-use image::codecs::png::PngEncoder;
-use image::{ExtendedColorType, ImageEncoder};
-use std::fs::File;
+use std::f64::consts::PI;
 use std::io;
+use std::mem;
+use std::time::{Duration, Instant};
 
-use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
-fn write_image(filename: &str, pixels: &[u8], bounds: (usize, usize)) -> Result<(), io::Error> {
-    let output = File::create(filename)?;
-    let encoder = PngEncoder::new(output);
+// Called with the partially rendered film and the finished fraction of the
+// render.
+pub type Progress<'a> = dyn FnMut(&Film, f64) + 'a;
 
-    encoder
-        .write_image(
-            pixels,
-            bounds.0 as u32,
-            bounds.1 as u32,
-            ExtendedColorType::Rgb8,
-        )
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
-    Ok(())
+// Rows per band when streaming; a band of a 16k wide image is about 6 MB.
+const STREAM_BAND: usize = 16;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RenderEstimate {
+    pub sampled_pixels: usize,
+    pub total_pixels: usize,
+    pub elapsed: Duration,
+    pub estimated: Duration,
+    // Film, output buffer and objects; textures and acceleration structures
+    // would come on top.
+    pub memory_bytes: usize,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-#[serde(from = "CameraParams")]
+// How a pixel's samples went, for the sampling AOVs.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PixelStats {
+    pub samples: usize,
+    // Variance of the pixel's luminance, i.e. of the mean of its samples.
+    // Infinite where `check_nan` found a bad sample.
+    pub variance: f64,
+}
+
+// The light of a path or a pixel split by how it got there, for the light
+// AOVs: emitters and the sky seen directly, then the light found after one
+// bounce and after more, each by whether the path first scattered off a
+// diffuse or a specular surface.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PathLight([Color; 5]);
+
+impl PathLight {
+    fn add(&mut self, path: &PathState, color: Color) {
+        let index = match path.bounce {
+            0 => 0,
+            1 => 1,
+            _ => 3,
+        };
+        let specular = path.bounce > 0 && path.specular;
+        self.0[index + specular as usize] += color;
+    }
+
+    fn add_scaled(&mut self, other: PathLight, scale: Color) {
+        for (light, other) in self.0.iter_mut().zip(other.0) {
+            *light += other * scale;
+        }
+    }
+
+    fn scaled(self, scale: f64) -> PathLight {
+        PathLight(self.0.map(|light| light * scale))
+    }
+
+    pub fn total(&self) -> Color {
+        self.0.iter().fold(Color::default(), |a, &b| a + b)
+    }
+
+    // Emitters and the sky seen directly or after a single bounce.
+    pub fn direct(&self) -> Color {
+        self.0[0] + self.0[1] + self.0[2]
+    }
+
+    // Everything that bounced more often.
+    pub fn indirect(&self) -> Color {
+        self.0[3] + self.0[4]
+    }
+
+    // Emitters and the sky seen directly, like the light path expression
+    // C L.
+    pub fn emission(&self) -> Color {
+        self.0[0]
+    }
+
+    // Light that first scattered off a diffuse surface, C D .* L.
+    pub fn diffuse(&self) -> Color {
+        self.0[1] + self.0[3]
+    }
+
+    // Light that first scattered off a specular surface, C S .* L.
+    pub fn specular(&self) -> Color {
+        self.0[2] + self.0[4]
+    }
+}
+
+// Everything the AOVs take from a pixel, averaged over its samples.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PixelPasses {
+    // Adds up to the pixel's color.
+    pub light: PathLight,
+    pub stats: PixelStats,
+    // Per channel variance of the color, i.e. of the mean of the samples.
+    pub variance: Color,
+    // Of the first surface hit, see `aov::Features`.
+    pub albedo: Color,
+    pub normal: Color,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StereoLayout {
+    // Left eye on the left half of the image.
+    SideBySide,
+    // Left eye on the top half of the image.
+    TopBottom,
+    // Red channel from the left eye, green and blue from the right.
+    Anaglyph,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Stereo {
+    // Distance between the eyes.
+    pub interocular: f64,
+    // Distance from the camera at which both eyes see the same image, i.e.
+    // where objects appear at screen depth.
+    pub convergence: f64,
+    pub layout: StereoLayout,
+}
+
+// Radial distortion of a real lens, as in the Brown-Conrady model: a point at
+// distance r from the image center, in units of half the image diagonal,
+// shows what an ideal lens shows at r (1 + k1 r² + k2 r⁴). Positive
+// coefficients bow straight lines outwards (barrel distortion, like a wide
+// angle lens), negative ones pull them in (pincushion).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct LensDistortion {
+    #[serde(default)]
+    pub k1: f64,
+    #[serde(default)]
+    pub k2: f64,
+}
+
+impl LensDistortion {
+    // Maps an offset from the image center, in any unit, to the offset an
+    // ideal lens would show there.
+    pub fn apply(&self, x: f64, y: f64, half_diagonal: f64) -> (f64, f64) {
+        let r2 = (x * x + y * y) / (half_diagonal * half_diagonal);
+        let scale = 1.0 + self.k1 * r2 + self.k2 * r2 * r2;
+        (x * scale, y * scale)
+    }
+}
+
+// Limits on the bounces of each kind a path takes, on top of `max_depth`,
+// which still bounds all of them together. Diffuse bounces scatter over the
+// hemisphere, glossy ones reflect around the mirror direction and
+// transmission ones pass through the surface, so a glass-heavy scene can
+// follow light through many panes without as many diffuse bounces. Volume
+// bounces scatter inside volumes; with a limit of their own they no longer
+// count towards `max_depth`, since light finds its way out of a cloud only
+// after many of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct BounceLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diffuse: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub glossy: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transmission: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<usize>,
+}
+
+impl BounceLimits {
+    pub fn is_unlimited(&self) -> bool {
+        *self == BounceLimits::default()
+    }
+}
+
+// Path regularization: past the first `after` bounces, metal, glass and hair
+// get rougher by `strength` at every bounce, up to fully rough. Light that
+// reaches a small light only by a mirror, a diffuse bounce and another
+// mirror is then found by chance often enough not to show up as fireflies,
+// at the price of blurring what is seen through the later bounces.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Regularization {
+    #[serde(default = "default_regularization_after")]
+    pub after: usize,
+    #[serde(default = "default_regularization_strength")]
+    pub strength: f64,
+}
+
+fn default_regularization_after() -> usize {
+    1
+}
+
+fn default_regularization_strength() -> f64 {
+    0.25
+}
+
+impl Default for Regularization {
+    fn default() -> Self {
+        Regularization {
+            after: default_regularization_after(),
+            strength: default_regularization_strength(),
+        }
+    }
+}
+
+impl Regularization {
+    // The least roughness of surfaces at `bounce`, which is 0 where the
+    // camera ray hits.
+    pub fn min_roughness(&self, bounce: usize) -> f64 {
+        (bounce.saturating_sub(self.after) as f64 * self.strength).clamp(0.0, 1.0)
+    }
+}
+
+// Where a path is. `scatter_pdf` is how likely a diffuse surface was to
+// scatter its ray, see `diffuse_scatter`, and `from` the index of the object
+// it left, for light linking. `bounces` are counted against the camera's
+// limits, `bounce` counts all of them and `specular` is whether the first
+// was off a specular surface.
+#[derive(Debug, Clone, Copy, Default)]
+struct PathState {
+    scatter_pdf: Option<f64>,
+    from: Option<usize>,
+    bounces: Bounces,
+    bounce: usize,
+    specular: bool,
+}
+
+// The bounces of each kind a path took so far.
+#[derive(Debug, Clone, Copy, Default)]
+struct Bounces {
+    diffuse: usize,
+    glossy: usize,
+    transmission: usize,
+    volume: usize,
+}
+
+impl Bounces {
+    // With the bounce from `rec` into `scattered`, if `limits` allow it.
+    fn after(mut self, rec: &HitRecord, scattered: &Ray, limits: &BounceLimits) -> Option<Bounces> {
+        // The normal faces the incoming ray, so going on is going against it.
+        let (count, limit) = if matches!(rec.mat, Material::Medium(_)) {
+            (&mut self.volume, limits.volume)
+        } else if scattered.kind() == RayKind::Diffuse {
+            (&mut self.diffuse, limits.diffuse)
+        } else if scattered.direction().dot(&rec.normal) < 0.0 {
+            (&mut self.transmission, limits.transmission)
+        } else {
+            (&mut self.glossy, limits.glossy)
+        };
+        *count += 1;
+        (*count <= limit.unwrap_or(usize::MAX)).then_some(self)
+    }
+}
+
+// Photographic exposure, in place of a plain camera that shows radiance as it
+// is. Scenes lit in physical units come out correctly exposed, and the
+// aperture sets the depth of field instead of `defocus_angle`. Scene units
+// are meters.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Exposure {
+    pub iso: f64,
+    pub f_number: f64,
+    // In seconds.
+    pub shutter: f64,
+    // In millimeters; with the vertical field of view it gives the focal
+    // length. 24 is a full frame camera.
+    #[serde(default = "full_frame")]
+    pub sensor_height: f64,
+}
+
+fn full_frame() -> f64 {
+    24.0
+}
+
+impl Exposure {
+    // Exposure value at ISO 100.
+    pub fn ev100(&self) -> f64 {
+        (self.f_number * self.f_number / self.shutter * 100.0 / self.iso).log2()
+    }
+
+    // What radiance is multiplied with: a luminance of 1.2 * 2^EV100 cd/m²
+    // just saturates the sensor, the usual calibration of light meters.
+    pub fn scale(&self) -> f64 {
+        1.0 / (1.2 * self.ev100().exp2())
+    }
+
+    // The focal length divided by the f-number is the aperture's diameter.
+    pub fn aperture_radius(&self, vfov: f64) -> f64 {
+        let focal_length = self.sensor_height / 1000.0 / 2.0 / (vfov.to_radians() / 2.0).tan();
+        focal_length / self.f_number / 2.0
+    }
+}
+
+// Renders every surface but the lights with one stand-in material, to judge
+// the lighting or the geometry apart from the shading: grey clay that is lit
+// like any other surface, clay colored with a checkerboard in texture space
+// to check UV layouts (see `uv_checker`), or the surface normal shown as a
+// color, red for +x, green for +y and blue for +z, without any lighting.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MaterialOverride {
+    Clay,
+    UvChecker,
+    Normals,
+}
+
+impl MaterialOverride {
+    pub fn from_name(name: &str) -> Option<MaterialOverride> {
+        match name {
+            "clay" => Some(MaterialOverride::Clay),
+            "uv_checker" => Some(MaterialOverride::UvChecker),
+            "normals" => Some(MaterialOverride::Normals),
+            _ => None,
+        }
+    }
+}
+
+const CLAY_ALBEDO: f64 = 0.5;
+
+// Draws the edges of mesh triangles over the render, to check how finely
+// meshes are tessellated or subdivided. Lines are about `width` pixels wide
+// wherever they are, and only drawn on what the camera sees directly.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Wireframe {
+    #[serde(default = "one_pixel")]
+    pub width: f64,
+    #[serde(default)]
+    pub color: Color,
+}
+
+fn one_pixel() -> f64 {
+    1.0
+}
+
+impl Default for Wireframe {
+    fn default() -> Self {
+        Wireframe {
+            width: one_pixel(),
+            color: Color::default(),
+        }
+    }
+}
+
+// One switch between quick previews and final renders. A preview caps the
+// samples per pixel, the bounces and the brightness of single samples, and
+// regularizes paths so glass and mirrors converge sooner, whatever the
+// camera's own settings say; final leaves them as they are.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quality {
+    Preview,
+    #[default]
+    Final,
+}
+
+fn is_final(quality: &Quality) -> bool {
+    *quality == Quality::Final
+}
+
+const PREVIEW_SAMPLES: usize = 16;
+const PREVIEW_MAX_DEPTH: usize = 4;
+const PREVIEW_MAX_RADIANCE: f64 = 10.0;
+
+// How the light reaching the camera is computed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Integrator {
+    #[default]
+    PathTracing,
+    // Photon mapping, for caustics.
+    Sppm(Sppm),
+}
+
+fn is_path_tracing(integrator: &Integrator) -> bool {
+    *integrator == Integrator::PathTracing
+}
+
+// Only the parameters are serialized, the other fields are derived from them
+// again when a camera is read.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "CameraParams", into = "CameraParams")]
 pub struct Camera {
-    pub height: usize,
-    pub width: usize,
-    pub samples_per_pixel: usize,
-    pub max_depth: usize,
-    pub vfov: f64,
-    pub lookfrom: Point3D,
-    pub lookat: Point3D,
-    pub vup: Vec3,
-    pub defocus_angle: f64,
-    pub focus_dist: f64,
-    #[serde(skip_serializing)]
-    pub aspect_ratio: f64,
-    #[serde(skip_serializing)]
+    // Private, so the derived fields can't go stale: use the setters.
+    height: usize,
+    width: usize,
+    samples_per_pixel: usize,
+    max_depth: usize,
+    vfov: f64,
+    lookfrom: Point3D,
+    lookat: Point3D,
+    vup: Vec3,
+    defocus_angle: f64,
+    focus_dist: f64,
+    exposure: Option<Exposure>,
+    shake: Option<CameraShake>,
+    aspect_ratio: f64,
+    pub stereo: Option<Stereo>,
+    pub distortion: Option<LensDistortion>,
+    pub white_balance: Option<WhiteBalance>,
+    // How colors brighter than white are written to 8-bit images.
+    pub clip: Clip,
+    // Learns where light comes from before rendering, see `Guide`.
+    pub guiding: Option<Guiding>,
+    pub bounces: BounceLimits,
+    pub regularization: Option<Regularization>,
+    // Samples brighter than this are scaled down to it, trading fireflies
+    // for a little lost energy.
+    pub max_radiance: Option<f64>,
+    pub material_override: Option<MaterialOverride>,
+    pub wireframe: Option<Wireframe>,
+    quality: Quality,
+    pub integrator: Integrator,
+    // Paints pixels with NaN or infinite samples magenta and logs where they
+    // came from.
+    pub check_nan: bool,
+    // Picks the noise pattern. The same seed gives the same image.
+    pub seed: u64,
     pixel_samples_scale: f64,
-    #[serde(skip_serializing)]
     center: Point3D,
-    #[serde(skip_serializing)]
     pixel00_loc: Point3D,
-    #[serde(skip_serializing)]
     pixel_delta_u: Vec3,
-    #[serde(skip_serializing)]
     pixel_delta_v: Vec3,
-    #[serde(skip_serializing)]
     u: Vec3,
-    #[serde(skip_serializing)]
     v: Vec3,
-    #[serde(skip_serializing)]
     w: Vec3,
-    #[serde(skip_serializing)]
     defocus_disk_u: Vec3,
-    #[serde(skip_serializing)]
     defocus_disk_v: Vec3,
 }
 
@@ -73,26 +457,126 @@ pub struct CameraParams {
     pub max_depth: usize,
     pub vfov: f64,
     pub lookfrom: Point3D,
+    #[serde(default)]
     pub lookat: Point3D,
+    #[serde(default)]
     pub vup: Vec3,
+    // Replaces `lookat` and `vup`: the camera looks down its rotated -z axis
+    // with y up.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orientation: Option<Rotation>,
     pub defocus_angle: f64,
     pub focus_dist: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exposure: Option<Exposure>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shake: Option<CameraShake>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stereo: Option<Stereo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub distortion: Option<LensDistortion>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub white_balance: Option<WhiteBalance>,
+    #[serde(default, skip_serializing_if = "Clip::is_channel")]
+    pub clip: Clip,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub guiding: Option<Guiding>,
+    #[serde(default, skip_serializing_if = "BounceLimits::is_unlimited")]
+    pub bounces: BounceLimits,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regularization: Option<Regularization>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_radiance: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub material_override: Option<MaterialOverride>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wireframe: Option<Wireframe>,
+    #[serde(default, skip_serializing_if = "is_final")]
+    pub quality: Quality,
+    #[serde(default, skip_serializing_if = "is_path_tracing")]
+    pub integrator: Integrator,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub check_nan: bool,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub seed: u64,
+}
+
+fn is_zero(seed: &u64) -> bool {
+    *seed == 0
+}
+
+impl From<Camera> for CameraParams {
+    fn from(c: Camera) -> Self {
+        CameraParams {
+            height: c.height,
+            width: c.width,
+            samples_per_pixel: c.samples_per_pixel,
+            max_depth: c.max_depth,
+            vfov: c.vfov,
+            lookfrom: c.lookfrom,
+            lookat: c.lookat,
+            vup: c.vup,
+            orientation: None,
+            defocus_angle: c.defocus_angle,
+            focus_dist: c.focus_dist,
+            exposure: c.exposure,
+            shake: c.shake,
+            stereo: c.stereo,
+            distortion: c.distortion,
+            white_balance: c.white_balance,
+            clip: c.clip,
+            guiding: c.guiding,
+            bounces: c.bounces,
+            regularization: c.regularization,
+            max_radiance: c.max_radiance,
+            material_override: c.material_override,
+            wireframe: c.wireframe,
+            quality: c.quality,
+            integrator: c.integrator,
+            check_nan: c.check_nan,
+            seed: c.seed,
+        }
+    }
 }
 
 impl From<CameraParams> for Camera {
     fn from(p: CameraParams) -> Self {
-        Camera::new(
+        let (lookat, vup) = match p.orientation {
+            Some(rotation) => {
+                let (_, y, z) = Quat::from(rotation).axes();
+                (p.lookfrom - p.focus_dist * z, y)
+            }
+            None => (p.lookat, p.vup),
+        };
+        let mut camera = Camera::new(
             p.height,
             p.width,
             p.samples_per_pixel,
             p.max_depth,
             p.vfov,
             p.lookfrom,
-            p.lookat,
-            p.vup,
+            lookat,
+            vup,
             p.defocus_angle,
             p.focus_dist,
-        )
+        );
+        camera.set_exposure(p.exposure);
+        camera.set_shake(p.shake);
+        camera.stereo = p.stereo;
+        camera.distortion = p.distortion;
+        camera.white_balance = p.white_balance;
+        camera.clip = p.clip;
+        camera.guiding = p.guiding;
+        camera.bounces = p.bounces;
+        camera.regularization = p.regularization;
+        camera.max_radiance = p.max_radiance;
+        camera.material_override = p.material_override;
+        camera.wireframe = p.wireframe;
+        camera.integrator = p.integrator;
+        camera.check_nan = p.check_nan;
+        camera.seed = p.seed;
+        camera.set_quality(p.quality);
+        camera
     }
 }
 
@@ -121,7 +605,23 @@ impl Camera {
             vup,
             defocus_angle,
             focus_dist,
+            exposure: None,
+            shake: None,
             aspect_ratio: 0.0,
+            stereo: None,
+            distortion: None,
+            white_balance: None,
+            clip: Clip::Channel,
+            guiding: None,
+            bounces: BounceLimits::default(),
+            regularization: None,
+            max_radiance: None,
+            material_override: None,
+            wireframe: None,
+            quality: Quality::Final,
+            integrator: Integrator::PathTracing,
+            check_nan: false,
+            seed: 0,
             pixel_samples_scale: 0.0,
             center: Point3D::default(),
             pixel00_loc: Point3D::default(),
@@ -137,21 +637,227 @@ impl Camera {
         camera
     }
 
+    // A cheaper copy of the camera for quick previews: at most `max_width`
+    // pixels wide, with fewer samples and bounces.
+    pub fn preview(&self, max_width: usize, samples_per_pixel: usize) -> Camera {
+        let scale = (max_width as f64 / self.width as f64).min(1.0);
+        let mut preview = Camera::new(
+            ((self.height as f64 * scale).round() as usize).max(1),
+            ((self.width as f64 * scale).round() as usize).max(1),
+            samples_per_pixel.min(self.samples_per_pixel()),
+            self.max_depth().min(8),
+            self.vfov,
+            self.lookfrom,
+            self.lookat,
+            self.vup,
+            self.defocus_angle,
+            self.focus_dist,
+        );
+        preview.set_exposure(self.exposure);
+        preview.set_shake(self.shake);
+        preview.distortion = self.distortion;
+        preview.white_balance = self.white_balance;
+        preview.clip = self.clip;
+        preview.bounces = self.bounce_limits();
+        preview.regularization = self.roughening();
+        preview.max_radiance = self.radiance_limit();
+        preview.material_override = self.material_override;
+        preview.wireframe = self.wireframe;
+        preview.check_nan = self.check_nan;
+        preview.seed = self.seed;
+        preview
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    // The samples and depth rendered with, capped in previews.
+    pub fn samples_per_pixel(&self) -> usize {
+        match self.quality {
+            Quality::Preview => self.samples_per_pixel.min(PREVIEW_SAMPLES),
+            Quality::Final => self.samples_per_pixel,
+        }
+    }
+
+    pub fn max_depth(&self) -> usize {
+        match self.quality {
+            Quality::Preview => self.max_depth.min(PREVIEW_MAX_DEPTH),
+            Quality::Final => self.max_depth,
+        }
+    }
+
+    // Like `max_radiance`, capped in previews.
+    pub fn radiance_limit(&self) -> Option<f64> {
+        match self.quality {
+            Quality::Preview => Some(
+                self.max_radiance
+                    .map_or(PREVIEW_MAX_RADIANCE, |max| max.min(PREVIEW_MAX_RADIANCE)),
+            ),
+            Quality::Final => self.max_radiance,
+        }
+    }
+
+    // Like `regularization`, always on in previews.
+    pub fn roughening(&self) -> Option<Regularization> {
+        match self.quality {
+            Quality::Preview => Some(self.regularization.unwrap_or_default()),
+            Quality::Final => self.regularization,
+        }
+    }
+
+    // Like `bounces`, with the volume depth capped in previews.
+    fn bounce_limits(&self) -> BounceLimits {
+        let mut bounces = self.bounces;
+        if self.quality == Quality::Preview {
+            bounces.volume = bounces.volume.map(|v| v.min(PREVIEW_MAX_DEPTH));
+        }
+        bounces
+    }
+
+    pub fn vfov(&self) -> f64 {
+        self.vfov
+    }
+
+    pub fn lookfrom(&self) -> Point3D {
+        self.lookfrom
+    }
+
+    pub fn lookat(&self) -> Point3D {
+        self.lookat
+    }
+
+    pub fn vup(&self) -> Vec3 {
+        self.vup
+    }
+
+    pub fn defocus_angle(&self) -> f64 {
+        self.defocus_angle
+    }
+
+    pub fn focus_dist(&self) -> f64 {
+        self.focus_dist
+    }
+
+    pub fn aspect_ratio(&self) -> f64 {
+        self.aspect_ratio
+    }
+
+    pub fn params(&self) -> CameraParams {
+        CameraParams::from(*self)
+    }
+
+    // Replaces all parameters at once, e.g. with edited `params()`.
+    pub fn update(&mut self, params: CameraParams) {
+        *self = Camera::from(params);
+    }
+
+    pub fn set_size(&mut self, width: usize, height: usize) {
+        self.width = width;
+        self.height = height;
+        self.initialize();
+    }
+
+    pub fn set_samples_per_pixel(&mut self, samples_per_pixel: usize) {
+        self.samples_per_pixel = samples_per_pixel;
+        self.initialize();
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = max_depth;
+    }
+
+    pub fn set_vfov(&mut self, vfov: f64) {
+        self.vfov = vfov;
+        self.initialize();
+    }
+
+    pub fn set_look_at(&mut self, lookfrom: Point3D, lookat: Point3D, vup: Vec3) {
+        self.lookfrom = lookfrom;
+        self.lookat = lookat;
+        self.vup = vup;
+        self.initialize();
+    }
+
+    // Keeps the camera where it is and the focus distance the same.
+    pub fn set_orientation(&mut self, orientation: Quat) {
+        let (_, y, z) = orientation.axes();
+        self.lookat = self.lookfrom - self.focus_dist * z;
+        self.vup = y;
+        self.initialize();
+    }
+
+    pub fn set_focus(&mut self, defocus_angle: f64, focus_dist: f64) {
+        self.defocus_angle = defocus_angle;
+        self.focus_dist = focus_dist;
+        self.initialize();
+    }
+
+    pub fn quality(&self) -> Quality {
+        self.quality
+    }
+
+    // Previews cap some settings while rendering, see `samples_per_pixel`
+    // and the like; the settings themselves stay as they were written.
+    pub fn set_quality(&mut self, quality: Quality) {
+        self.quality = quality;
+        self.initialize();
+    }
+
+    pub fn exposure(&self) -> Option<Exposure> {
+        self.exposure
+    }
+
+    pub fn set_exposure(&mut self, exposure: Option<Exposure>) {
+        self.exposure = exposure;
+        self.initialize();
+    }
+
+    pub fn shake(&self) -> Option<CameraShake> {
+        self.shake
+    }
+
+    pub fn set_shake(&mut self, shake: Option<CameraShake>) {
+        self.shake = shake;
+        self.initialize();
+    }
+
+    pub fn orientation(&self) -> Quat {
+        Quat::look_rotation(self.lookat - self.lookfrom, self.vup)
+    }
+
     fn initialize(&mut self) {
-        self.aspect_ratio = self.width as f64 / self.height as f64;
         self.height = if self.height < 1 { 1 } else { self.height };
+        self.aspect_ratio = self.width as f64 / self.height as f64;
 
-        self.pixel_samples_scale = 1.0 / self.samples_per_pixel as f64;
+        let exposure = self.exposure.map_or(1.0, |e| e.scale());
+        self.pixel_samples_scale = exposure / self.samples_per_pixel() as f64;
 
-        self.center = self.lookfrom;
+        // Shaking moves the camera, but not where it was placed.
+        let (lookfrom, lookat, vup) = match self.shake {
+            Some(shake) => {
+                let (position, rotation) = shake.offset();
+                let turned =
+                    self.orientation() * Quat::from_euler(rotation.x(), rotation.y(), rotation.z());
+                let (_, y, z) = turned.axes();
+                let lookfrom = self.lookfrom + position;
+                (lookfrom, lookfrom - self.focus_dist * z, y)
+            }
+            None => (self.lookfrom, self.lookat, self.vup),
+        };
+        self.center = lookfrom;
 
         let theta = self.vfov.to_radians();
         let h = (theta / 2.0).tan();
         let viewport_height = 2.0 * h * self.focus_dist;
         let viewport_width = viewport_height * (self.width as f64 / self.height as f64);
 
-        self.w = (self.lookfrom - self.lookat).unit_vector();
-        self.u = self.vup.cross(&self.w).unit_vector();
+        self.w = (lookfrom - lookat).unit_vector();
+        self.u = vup.cross(&self.w).unit_vector();
         self.v = self.w.cross(&self.u);
 
         let viewport_u = viewport_width * self.u;
@@ -164,46 +870,482 @@ impl Camera {
             self.center - (self.focus_dist * self.w) - viewport_u / 2.0 - viewport_v / 2.0;
         self.pixel00_loc = viewport_upper_left + 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
 
-        let defocus_radius = self.focus_dist * (self.defocus_angle / 2.0).to_radians().tan();
+        let defocus_radius = match self.exposure {
+            Some(exposure) => exposure.aperture_radius(self.vfov),
+            None => self.focus_dist * (self.defocus_angle / 2.0).to_radians().tan(),
+        };
         self.defocus_disk_u = self.u * defocus_radius;
         self.defocus_disk_v = self.v * defocus_radius;
     }
 
+    // The camera of one eye, moved `offset` to the right. Its image plane is
+    // shifted instead of turned, so both eyes agree at the convergence
+    // distance without vertical parallax.
+    pub fn eye(&self, offset: f64, convergence: f64) -> Camera {
+        let mut eye = *self;
+        eye.stereo = None;
+        eye.center = self.center + offset * self.u;
+        eye.pixel00_loc =
+            self.pixel00_loc + offset * (1.0 - self.focus_dist / convergence) * self.u;
+        eye
+    }
+
+    // Width and height of the rendered image, which stereo layouts enlarge.
+    pub fn output_size(&self) -> (usize, usize) {
+        match self.stereo.map(|s| s.layout) {
+            Some(StereoLayout::SideBySide) => (self.width * 2, self.height),
+            Some(StereoLayout::TopBottom) => (self.width, self.height * 2),
+            _ => (self.width, self.height),
+        }
+    }
+
     pub fn render(&self, filename: &str, world: &ObjectList) -> io::Result<()> {
-        let mut pixels = vec![Color::default(); self.width * self.height];
-        let mut buffer = Vec::with_capacity(self.width * self.height * 3);
+        self.render_film(world, &[])
+            .save_with(filename, &[], self.clip)
+    }
+
+    // White balance first, then the effects in order.
+    fn develop(&self, film: &mut Film, effects: &[PostEffect]) {
+        if let Some(white_balance) = &self.white_balance {
+            white_balance.apply(film);
+        }
+        post::apply(effects, film);
+    }
+
+    // Renders the image, or both eyes of a stereo camera, and applies the post
+    // effects. Stereo eyes are processed separately before they are combined.
+    pub fn render_film(&self, world: &ObjectList, effects: &[PostEffect]) -> Film {
+        self.render_film_with(world, effects, None)
+    }
+
+    // Like `render_film`, calling `progress` with the partially rendered film
+    // and the finished fraction of the whole render every few rows.
+    pub fn render_film_with_progress(
+        &self,
+        world: &ObjectList,
+        effects: &[PostEffect],
+        progress: &mut dyn FnMut(&Film, f64),
+    ) -> Film {
+        self.render_film_with(world, effects, Some(progress))
+    }
+
+    fn render_film_with(
+        &self,
+        world: &ObjectList,
+        effects: &[PostEffect],
+        mut progress: Option<&mut Progress>,
+    ) -> Film {
+        let (width, height) = self.output_size();
+        let span = tracing::info_span!("render", width, height, samples = self.samples_per_pixel());
+        let _enter = span.enter();
+
+        let Some(stereo) = self.stereo else {
+            let mut film = self.render_pixels(world, None, false, progress);
+            self.develop(&mut film, effects);
+            return film;
+        };
+
+        let half = stereo.interocular / 2.0;
+        let mut left =
+            self.eye(-half, stereo.convergence)
+                .render_eye(world, 0.0, progress.as_deref_mut());
+        let mut right = self
+            .eye(half, stereo.convergence)
+            .render_eye(world, 0.5, progress);
+        self.develop(&mut left, effects);
+        self.develop(&mut right, effects);
 
-        let rows: Vec<(usize, &mut [Color])> = pixels.chunks_mut(self.width).enumerate().collect();
+        match stereo.layout {
+            StereoLayout::SideBySide => Film {
+                width: self.width * 2,
+                height: self.height,
+                pixels: left
+                    .pixels
+                    .chunks(self.width)
+                    .zip(right.pixels.chunks(self.width))
+                    .flat_map(|(l, r)| l.iter().chain(r))
+                    .copied()
+                    .collect(),
+            },
+            StereoLayout::TopBottom => Film {
+                width: self.width,
+                height: self.height * 2,
+                pixels: left.pixels.into_iter().chain(right.pixels).collect(),
+            },
+            StereoLayout::Anaglyph => Film {
+                width: self.width,
+                height: self.height,
+                pixels: left
+                    .pixels
+                    .iter()
+                    .zip(&right.pixels)
+                    .map(|(l, r)| Color::new(l.x(), r.y(), r.z()))
+                    .collect(),
+            },
+        }
+    }
+
+    // One eye of a stereo image, whose progress counts as half of the
+    // whole, from `start` on.
+    fn render_eye(&self, world: &ObjectList, start: f64, progress: Option<&mut Progress>) -> Film {
+        match progress {
+            Some(progress) => self.render_pixels(
+                world,
+                None,
+                false,
+                Some(&mut |film, done| progress(film, start + done / 2.0)),
+            ),
+            None => self.render_pixels(world, None, false, None),
+        }
+    }
 
-        rows.into_par_iter().for_each(|(j, row)| {
-            let second_mod_4 = Local::now().second() % 4;
-            let dots = ".".repeat(second_mod_4 as usize % 4);
-            eprint!("\rRunning{}", dots);
+    // Like `render_film_with_progress`, also collecting every pixel's
+    // passes for the AOVs from the same samples, row by row. The albedo and
+    // normal for denoising are only looked up with `features`. Stereo and
+    // SPPM images have none to give.
+    pub fn render_film_with_passes(
+        &self,
+        world: &ObjectList,
+        effects: &[PostEffect],
+        features: bool,
+        progress: Option<&mut Progress>,
+    ) -> io::Result<(Film, Vec<PixelPasses>)> {
+        if self.stereo.is_some() || self.integrator != Integrator::PathTracing {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only mono path traced images have AOV passes",
+            ));
+        }
+        let span = tracing::info_span!(
+            "render",
+            width = self.width,
+            height = self.height,
+            samples = self.samples_per_pixel()
+        );
+        let _enter = span.enter();
+        let mut passes = Vec::with_capacity(self.width * self.height);
+        let mut film = self.render_pixels(world, Some(&mut passes), features, progress);
+        self.develop(&mut film, effects);
+        Ok((film, passes))
+    }
 
-            for (i, pixel_color) in row.iter_mut().enumerate() {
-                for _ in 0..self.samples_per_pixel {
-                    let r = self.get_ray(i, j);
-                    *pixel_color += self.ray_color(&r, self.max_depth, world);
+    // Renders the rows in 16 bands and reports progress after each of them,
+    // appending the pixels' passes to `passes` if given, with the denoising
+    // features if `features` is set. Without `progress` all rows are one
+    // band, so no thread waits for the others to finish a band.
+    fn render_pixels(
+        &self,
+        world: &ObjectList,
+        mut passes: Option<&mut Vec<PixelPasses>>,
+        features: bool,
+        mut progress: Option<&mut Progress>,
+    ) -> Film {
+        if let Integrator::Sppm(sppm) = self.integrator {
+            return sppm::render(self, world, sppm, progress.unwrap_or(&mut |_, _| {}));
+        }
+        let mut film = Film::new(self.width, self.height);
+        let band = match progress {
+            Some(_) => self.height.div_ceil(16),
+            None => self.height,
+        }
+        .max(1);
+
+        self.render_bands(
+            world,
+            band,
+            passes.is_some(),
+            features,
+            &mut |first_row, rows, rows_passes| {
+                film.pixels[first_row * self.width..][..rows.pixels.len()]
+                    .copy_from_slice(&rows.pixels);
+                if let Some(passes) = passes.as_deref_mut() {
+                    passes.extend(rows_passes);
                 }
-                *pixel_color *= self.pixel_samples_scale;
-            }
-        });
+                if let Some(progress) = progress.as_deref_mut() {
+                    progress(&film, (first_row + rows.height) as f64 / self.height as f64);
+                }
+                Ok(())
+            },
+        )
+        .expect("copying into the film can't fail");
+        film
+    }
 
-        for pixel_color in pixels.iter() {
-            write_color(&mut buffer, *pixel_color)?;
+    // Renders `band` rows at a time and hands each band, as a film of its own,
+    // to `f` together with the index of its first row, and with the passes
+    // of its pixels if `with_passes` is set, see `sample_pixel` for
+    // `features`.
+    fn render_bands(
+        &self,
+        world: &ObjectList,
+        band: usize,
+        with_passes: bool,
+        features: bool,
+        f: &mut dyn FnMut(usize, Film, Vec<PixelPasses>) -> io::Result<()>,
+    ) -> io::Result<()> {
+        for first_row in (0..self.height).step_by(band) {
+            let last_row = (first_row + band).min(self.height);
+            let mut rows = Film::new(self.width, last_row - first_row);
+            let mut passes = if with_passes {
+                vec![PixelPasses::default(); rows.pixels.len()]
+            } else {
+                Vec::new()
+            };
+            let mut pass_rows = passes.chunks_mut(self.width);
+            let row_slices: Vec<_> = rows
+                .pixels
+                .chunks_mut(self.width)
+                .enumerate()
+                .map(|(j, row)| (j, row, pass_rows.next()))
+                .collect();
+
+            row_slices.into_par_iter().for_each(|(j, row, mut passes)| {
+                for (i, pixel_color) in row.iter_mut().enumerate() {
+                    *pixel_color = match passes.as_deref_mut() {
+                        Some(passes) => {
+                            passes[i] = self.sample_pixel(i, first_row + j, world, features);
+                            passes[i].light.total()
+                        }
+                        None => self.render_pixel(i, first_row + j, world),
+                    };
+                }
+            });
+            tracing::debug!(first_row, last_row, "band finished");
+            f(first_row, rows, passes)?;
         }
+        Ok(())
+    }
 
-        write_image(filename, &buffer, (self.width, self.height))?;
+    // Like `render`, but encodes every few rows as soon as they are done
+    // instead of keeping the whole film, for images too large for memory.
+    // Post effects may need neighbouring pixels and stereo images are put
+    // together from both eyes, so neither can be streamed; white balance can.
+    // EXR files are written a row of tiles at a time.
+    pub fn render_streaming(
+        &self,
+        filename: &str,
+        world: &ObjectList,
+        effects: &[PostEffect],
+        text: &[(&str, String)],
+    ) -> io::Result<()> {
+        if self.stereo.is_some() || !effects.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "stereo images and post effects can't be streamed",
+            ));
+        }
+        if self.integrator != Integrator::PathTracing {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "only path traced images can be streamed",
+            ));
+        }
 
-        eprintln!("\rDone.                 ");
+        let span = tracing::info_span!(
+            "render",
+            width = self.width,
+            height = self.height,
+            samples = self.samples_per_pixel(),
+            filename
+        );
+        let _enter = span.enter();
+        let mut image = ImageStream::create(filename, (self.width, self.height), text)?;
+        let band = if matches!(image, ImageStream::Exr(_)) {
+            exr::TILE
+        } else {
+            STREAM_BAND
+        };
+        self.render_bands(world, band, false, false, &mut |_, mut rows, _| {
+            self.develop(&mut rows, &[]);
+            image.write_rows(&rows, self.clip)
+        })?;
+        image.finish()?;
+        tracing::info!("image written");
         Ok(())
     }
 
-    fn get_ray(&self, i: usize, j: usize) -> Ray {
+    pub fn render_pixel(&self, i: usize, j: usize, world: &ObjectList) -> Color {
+        self.sample_pixel(i, j, world, false).light.total()
+    }
+
+    // Like `render_pixel`, also estimating the variance from the spread of
+    // the samples.
+    pub fn render_pixel_stats(
+        &self,
+        i: usize,
+        j: usize,
+        world: &ObjectList,
+    ) -> (Color, PixelStats) {
+        let passes = self.sample_pixel(i, j, world, false);
+        (passes.light.total(), passes.stats)
+    }
+
+    // Everything the AOVs take from pixel (i, j), from the same samples as
+    // `render_pixel`.
+    pub fn render_pixel_passes(&self, i: usize, j: usize, world: &ObjectList) -> PixelPasses {
+        self.sample_pixel(i, j, world, true)
+    }
+
+    // The first hit of every sample is only looked up again for the
+    // denoising features when `features` is set.
+    fn sample_pixel(&self, i: usize, j: usize, world: &ObjectList, features: bool) -> PixelPasses {
+        // Warnings from `ray_color` name the pixel through the span.
+        let _span = self
+            .check_nan
+            .then(|| tracing::warn_span!("pixel", x = i, y = j).entered());
+        let mut passes = PixelPasses::default();
+        let (mut sum, mut sum_squares) = (0.0, 0.0);
+        let mut color_squares = Color::default();
+        for n in 0..self.samples_per_pixel() {
+            let r = self.get_ray(i, j, n);
+            if features {
+                // Volumes draw random numbers to find their first hit, so
+                // the generator is put back to keep the samples those of
+                // `render_pixel`.
+                let (albedo, normal) = rng::preserving(|| {
+                    let mut rec = HitRecord::default();
+                    let hit = world.hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec);
+                    let rec = hit.then_some(&rec);
+                    (
+                        aov::albedo(rec, &r, world),
+                        rec.map_or(Color::default(), |rec| rec.normal),
+                    )
+                });
+                passes.albedo += albedo;
+                passes.normal += normal;
+            }
+            let light = self.ray_light(&r, self.max_depth(), world);
+            let sample = light.total();
+            let scale = self.radiance_scale(sample);
+            let sample = sample * scale;
+            if self.check_nan && !sample.is_finite() {
+                let mut light = PathLight::default();
+                light.0[0] = Color::new(1.0, 0.0, 1.0);
+                return PixelPasses {
+                    light,
+                    stats: PixelStats {
+                        samples: n + 1,
+                        variance: f64::INFINITY,
+                    },
+                    variance: Color::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+                    ..passes
+                };
+            }
+            passes
+                .light
+                .add_scaled(light, Color::new(scale, scale, scale));
+            color_squares += sample * sample;
+            let l = luminance(sample);
+            sum += l;
+            sum_squares += l * l;
+        }
+
+        let samples = self.samples_per_pixel();
+        let n = samples as f64;
+        if samples > 1 {
+            passes.stats.variance = ((sum_squares - sum * sum / n) / (n - 1.0) / n).max(0.0);
+            let mean = passes.light.total() / n;
+            // Exposed like the color.
+            let exposure = self.pixel_samples_scale * n;
+            passes.variance = (color_squares / n - mean * mean) / (n - 1.0) * exposure * exposure;
+        }
+        passes.stats.samples = samples;
+        passes.light = passes.light.scaled(self.pixel_samples_scale);
+        passes.albedo /= n.max(1.0);
+        passes.normal /= n.max(1.0);
+        passes
+    }
+
+    // What a sample is multiplied with to keep it within `max_radiance`.
+    fn radiance_scale(&self, sample: Color) -> f64 {
+        match self.radiance_limit() {
+            Some(max) if luminance(sample) > max => max / luminance(sample),
+            _ => 1.0,
+        }
+    }
+
+    // The object seen through most of the camera samples of pixel (i, j), or
+    // None where the background dominates.
+    pub fn object_at(&self, i: usize, j: usize, world: &ObjectList) -> Option<usize> {
+        let mut counts = std::collections::HashMap::new();
+        for sample in 0..self.samples_per_pixel().clamp(1, 16) {
+            let r = self.get_ray(i, j, sample);
+            let mut rec = HitRecord::default();
+            let object = world
+                .hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec)
+                .then_some(rec.object);
+            *counts.entry(object).or_insert(0) += 1;
+        }
+        counts
+            .into_iter()
+            .max_by_key(|(object, count)| (*count, *object))
+            .and_then(|(object, _)| object)
+    }
+
+    // The fraction of the camera samples of pixel (i, j) that see an object
+    // other than a holdout or matte, which is what the image and render
+    // layers are cut out with. Samples that see a holdout count with the
+    // shadow caught on it, so compositing darkens the backplate there.
+    pub fn coverage(&self, i: usize, j: usize, world: &ObjectList) -> f64 {
+        const SHADOW_SAMPLES: usize = 8;
+        let samples = self.samples_per_pixel().clamp(1, 16);
+        let covered: f64 = (0..samples)
+            .map(|sample| {
+                let r = self.get_ray(i, j, sample);
+                let mut rec = HitRecord::default();
+                if !world.hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec) {
+                    0.0
+                } else if world.is_holdout(&r, &rec) {
+                    let lit: f64 = (0..SHADOW_SAMPLES)
+                        .map(|_| holdout_light(&rec, world))
+                        .sum();
+                    1.0 - lit / SHADOW_SAMPLES as f64
+                } else if world.is_matte(&r, &rec) {
+                    0.0
+                } else {
+                    1.0
+                }
+            })
+            .sum();
+        covered / samples as f64
+    }
+
+    // Renders a regular grid of at most `max_pixels` pixels and extrapolates
+    // how long the full image would take.
+    pub fn estimate(&self, world: &ObjectList, max_pixels: usize) -> RenderEstimate {
+        let total_pixels = self.width * self.height;
+        let stride = ((total_pixels as f64 / max_pixels.max(1) as f64)
+            .sqrt()
+            .ceil() as usize)
+            .max(1);
+        let pixels: Vec<(usize, usize)> = (0..self.height)
+            .step_by(stride)
+            .flat_map(|j| (0..self.width).step_by(stride).map(move |i| (i, j)))
+            .collect();
+
+        let start = Instant::now();
+        pixels.par_iter().for_each(|&(i, j)| {
+            self.render_pixel(i, j, world);
+        });
+        let elapsed = start.elapsed();
+
+        let scale = total_pixels as f64 / pixels.len() as f64;
+        RenderEstimate {
+            sampled_pixels: pixels.len(),
+            total_pixels,
+            elapsed,
+            estimated: elapsed.mul_f64(scale),
+            memory_bytes: total_pixels * (mem::size_of::<Color>() + 3)
+                + world.objects.len() * mem::size_of::<Object>(),
+        }
+    }
+
+    // Also seeds the random numbers for the rest of the sample, see `rng`.
+    pub(crate) fn get_ray(&self, i: usize, j: usize, sample: usize) -> Ray {
+        rng::seed_sample(self.seed, i, j, sample);
         let offset = self.sample_square();
-        let pixel_sample = self.pixel00_loc
-            + ((i as f64 + offset.x()) * self.pixel_delta_u)
-            + ((j as f64 + offset.y()) * self.pixel_delta_v);
+        let pixel_sample =
+            self.image_point(i as f64 + 0.5 + offset.x(), j as f64 + 0.5 + offset.y());
 
         let ray_origin = if self.defocus_angle <= 0.0 {
             self.center
@@ -212,15 +1354,36 @@ impl Camera {
         };
         let ray_direction = pixel_sample - ray_origin;
 
-        Ray::new(ray_origin, ray_direction)
+        Ray::new(ray_origin, ray_direction).with_cone(0.0, self.pixel_spread())
+    }
+
+    // The ray from the lens center through the center of pixel (i, j).
+    pub fn pixel_ray(&self, i: usize, j: usize) -> Ray {
+        let pixel_center = self.image_point(i as f64 + 0.5, j as f64 + 0.5);
+        Ray::new(self.center, pixel_center - self.center).with_cone(0.0, self.pixel_spread())
+    }
+
+    // The angle a pixel covers, for the cones of camera rays.
+    fn pixel_spread(&self) -> f64 {
+        self.pixel_delta_u.length() / self.focus_dist
+    }
+
+    // The point on the focus plane seen at continuous pixel coordinates,
+    // where (0, 0) is the top left corner of the image.
+    fn image_point(&self, x: f64, y: f64) -> Point3D {
+        let (x, y) = match self.distortion {
+            Some(distortion) => {
+                let (cx, cy) = (self.width as f64 / 2.0, self.height as f64 / 2.0);
+                let (dx, dy) = distortion.apply(x - cx, y - cy, cx.hypot(cy));
+                (cx + dx, cy + dy)
+            }
+            None => (x, y),
+        };
+        self.pixel00_loc + (x - 0.5) * self.pixel_delta_u + (y - 0.5) * self.pixel_delta_v
     }
 
     fn sample_square(&self) -> Vec3 {
-        Vec3::new(
-            rand::random::<f64>() - 0.5,
-            rand::random::<f64>() - 0.5,
-            0.0,
-        )
+        Vec3::new(rng::random() - 0.5, rng::random() - 0.5, 0.0)
     }
 
     fn defocus_disk_sample(&self) -> Point3D {
@@ -228,24 +1391,1629 @@ impl Camera {
         self.center + (p.x() * self.defocus_disk_u) + (p.y() * self.defocus_disk_v)
     }
 
-    #[allow(clippy::only_used_in_recursion)]
-    fn ray_color(&self, r: &Ray, depth: usize, world: &ObjectList) -> Color {
+    // The backplate behind a camera ray, where it misses everything or hits
+    // a holdout.
+    fn plate(&self, r: &Ray, world: &ObjectList) -> Option<Color> {
+        if r.kind() != RayKind::Camera {
+            return None;
+        }
+        let (x, y) = self.frame_position(r);
+        world.backplate.as_ref()?.color(x, y)
+    }
+
+    // Where `r` crosses the focus plane, as fractions of the frame's width
+    // and height from its top left corner, ignoring lens distortion.
+    fn frame_position(&self, r: &Ray) -> (f64, f64) {
+        let (origin, direction) = (*r.origin(), *r.direction());
+        let depth =
+            (self.focus_dist + (origin - self.center).dot(&self.w)) / -direction.dot(&self.w);
+        let corner = self.pixel00_loc - 0.5 * (self.pixel_delta_u + self.pixel_delta_v);
+        let offset = origin + depth * direction - corner;
+        let along = |delta: Vec3, pixels: usize| {
+            offset.dot(&delta) / delta.length_squared() / pixels as f64
+        };
+        (
+            along(self.pixel_delta_u, self.width),
+            along(self.pixel_delta_v, self.height),
+        )
+    }
+
+    // Puts clay in place of the material at `rec`, or returns the color of
+    // its normal. Lights and volumes are left as they are.
+    fn override_material(&self, rec: &mut HitRecord) -> Option<Color> {
+        if matches!(rec.mat, Material::DiffuseLight(_) | Material::Medium(_)) {
+            return None;
+        }
+        match self.material_override? {
+            MaterialOverride::Clay => {
+                let grey = Color::new(CLAY_ALBEDO, CLAY_ALBEDO, CLAY_ALBEDO);
+                rec.mat = Material::Lambertian(Lambertian::new(grey));
+                None
+            }
+            MaterialOverride::UvChecker => {
+                let checker = uv_checker(rec.u, rec.v);
+                rec.mat = Material::Lambertian(Lambertian::new(checker));
+                None
+            }
+            MaterialOverride::Normals => Some(0.5 * (rec.normal + Color::new(1.0, 1.0, 1.0))),
+        }
+    }
+
+    // The wireframe's color, if `r` came from the camera and hit close
+    // enough to an edge.
+    fn wire(&self, r: &Ray, rec: &HitRecord) -> Option<Color> {
+        let wireframe = self.wireframe?;
+        let edge = rec.edge?;
+        let half_width = 0.5 * wireframe.width * r.cone_width(rec.t);
+        (r.kind() == RayKind::Camera && edge.length() < half_width).then_some(wireframe.color)
+    }
+
+    fn regularize(&self, rec: &mut HitRecord, bounce: usize) {
+        if let Some(regularization) = self.roughening() {
+            rec.mat.roughen(regularization.min_roughness(bounce));
+        }
+    }
+
+    // The depth left for the path after it scattered at `rec`.
+    fn depth_after(&self, depth: usize, rec: &HitRecord) -> usize {
+        if self.bounce_limits().volume.is_some() && matches!(rec.mat, Material::Medium(_)) {
+            depth
+        } else {
+            depth - 1
+        }
+    }
+
+    pub(crate) fn ray_color(&self, r: &Ray, depth: usize, world: &ObjectList) -> Color {
+        self.ray_light(r, depth, world).total()
+    }
+
+    // Like `ray_color`, split by where the light came from.
+    pub(crate) fn ray_light(&self, r: &Ray, depth: usize, world: &ObjectList) -> PathLight {
+        self.ray_color_from(r, depth, world, PathState::default(), None)
+    }
+
+    // Like `ray_color`, adding the cost of every bounce to the object hit
+    // there in `costs`, whose last entry is the background.
+    pub(crate) fn ray_color_costed(
+        &self,
+        r: &Ray,
+        world: &ObjectList,
+        costs: &mut [Cost],
+    ) -> Color {
+        self.ray_color_from(
+            r,
+            self.max_depth(),
+            world,
+            PathState::default(),
+            Some(costs),
+        )
+        .total()
+    }
+
+    fn ray_color_from(
+        &self,
+        r: &Ray,
+        depth: usize,
+        world: &ObjectList,
+        path: PathState,
+        mut costs: Option<&mut [Cost]>,
+    ) -> PathLight {
         if depth == 0 {
-            return Color::new(0.0, 0.0, 0.0);
+            return PathLight::default();
         }
 
+        let start = costs.is_some().then(Instant::now);
         let mut rec = HitRecord::default();
-        if world.hit(r, &Interval::new(0.001, f64::INFINITY), &mut rec) {
-            let mut scattered = Ray::default();
-            let mut attenuation = Color::default();
-            if rec.mat.scatter(r, &rec, &mut attenuation, &mut scattered) {
-                return attenuation * self.ray_color(&scattered, depth - 1, world);
+        let (light, object, later_bounces) =
+            if world.hit(r, &Interval::new(0.001, f64::INFINITY), &mut rec) {
+                let object = rec.object;
+                let (light, later_bounces) =
+                    self.shade(r, rec, depth, world, path, costs.as_deref_mut());
+                (light, Some(object), later_bounces)
+            } else {
+                (self.background(r, world, path), None, Duration::ZERO)
+            };
+        if let (Some(costs), Some(start)) = (costs, start) {
+            let index = object.unwrap_or(costs.len() - 1);
+            costs[index] += Cost {
+                rays: 1,
+                time: start.elapsed().saturating_sub(later_bounces),
+            };
+        }
+        light
+    }
+
+    // The light leaving `rec` towards `r`, and how long the bounces after it
+    // took if they were timed.
+    fn shade(
+        &self,
+        r: &Ray,
+        mut rec: HitRecord,
+        depth: usize,
+        world: &ObjectList,
+        path: PathState,
+        costs: Option<&mut [Cost]>,
+    ) -> (PathLight, Duration) {
+        let mut light = PathLight::default();
+        if world.is_matte(r, &rec) {
+            if let Some(plate) = self.plate(r, world) {
+                let shadow = if world.is_holdout(r, &rec) {
+                    holdout_light(&rec, world)
+                } else {
+                    1.0
+                };
+                light.add(&path, plate * shadow);
+            }
+            return (light, Duration::ZERO);
+        }
+        if let Some(wire) = self.wire(r, &rec) {
+            light.add(&path, wire);
+            return (light, Duration::ZERO);
+        }
+        if let Some(normal) = self.override_material(&mut rec) {
+            light.add(&path, normal);
+            return (light, Duration::ZERO);
+        }
+        let mut scattered = Ray::default();
+        let mut attenuation = Color::default();
+        let emitted = emission(r, &rec, world, path.from);
+        light.add(&path, emitted);
+        self.regularize(&mut rec, path.bounce);
+        let mut incoming = Color::default();
+        let mut later_bounces = Duration::ZERO;
+        if rec.mat.scatter(r, &rec, &mut attenuation, &mut scattered) {
+            let (scatter_pdf, from) = if passes_shadow_rays(&rec, &scattered, world) {
+                (path.scatter_pdf, path.from)
+            } else {
+                let pdf = diffuse_scatter(&rec, world, &mut attenuation, &mut scattered);
+                (pdf, Some(rec.object))
+            };
+            let next = PathState {
+                scatter_pdf,
+                from,
+                bounces: path.bounces,
+                bounce: path.bounce + 1,
+                specular: if path.bounce == 0 {
+                    rec.mat.is_specular()
+                } else {
+                    path.specular
+                },
+            };
+            if let Some(bounces) = path.bounces.after(&rec, &scattered, &self.bounce_limits()) {
+                let start = costs.is_some().then(Instant::now);
+                let next_light = self.ray_color_from(
+                    &scattered,
+                    self.depth_after(depth, &rec),
+                    world,
+                    PathState { bounces, ..next },
+                    costs,
+                );
+                later_bounces = start.map_or(Duration::ZERO, |start| start.elapsed());
+                incoming = next_light.total();
+                if let (Some(guide), Some(pdf)) = (&world.guide, scatter_pdf) {
+                    guide.record(rec.p, *scattered.direction(), luminance(incoming) / pdf);
+                }
+                light.add_scaled(next_light, attenuation);
             }
-            return Color::new(0.0, 0.0, 0.0);
+            light.add(
+                &next,
+                portal_light(r, &rec, world) + sun_light(r, &rec, world),
+            );
+        }
+        // Only the bounce where it first went wrong is logged.
+        if self.check_nan && !light.total().is_finite() && incoming.is_finite() {
+            tracing::warn!(
+                bounce = path.bounce,
+                object = rec.object,
+                material = rec.mat.kind(),
+                ?emitted,
+                ?attenuation,
+                "non-finite radiance"
+            );
+        }
+        (light, later_bounces)
+    }
+
+    // The backplate or what lies beyond the scene in the direction of `r`.
+    fn background(&self, r: &Ray, world: &ObjectList, path: PathState) -> PathLight {
+        let color = self
+            .plate(r, world)
+            .unwrap_or_else(|| escaped(r, world, path.scatter_pdf, path.from));
+        if self.check_nan && !color.is_finite() {
+            tracing::warn!(
+                bounce = path.bounce,
+                direction = ?r.direction(),
+                "non-finite radiance from the background"
+            );
+        }
+        let mut light = PathLight::default();
+        light.add(&path, color);
+        light
+    }
+}
+
+// The sky in the direction of `r`, as the sun leaves it or lights the
+// atmosphere, without the sun's disk, turned and scaled by the environment.
+pub(crate) fn sky(r: &Ray, world: &ObjectList) -> Color {
+    let environment = &world.environment;
+    let r = &Ray::new(*r.origin(), environment.lookup(*r.direction()));
+    let color = match (&world.sun, &world.atmosphere) {
+        (Some(sun), Some(atmosphere)) => atmosphere.sky(*r.origin(), *r.direction(), sun),
+        (Some(sun), None) => sun.sky(world.sky.color(*r.direction()), *r.direction()),
+        (None, _) => world.sky.color(*r.direction()),
+    };
+    color * environment.intensity
+}
+
+// What the air lets through of the sun's light on its way along `r`.
+pub(crate) fn sunlight(r: &Ray, sun: &Sun, world: &ObjectList) -> Color {
+    match &world.atmosphere {
+        Some(atmosphere) => atmosphere.transmittance(*r.origin(), *r.direction()),
+        None => sun.color(),
+    }
+}
+
+// The sky seen by a ray that left the scene. When a portal ray could have
+// found it too, it only counts as much as the power heuristic says, and not
+// at all through portals with their own shadows, which portal rays alone
+// see right. Nothing when a portal it passed doesn't light the object
+// `from` that it left. The sun's disk is left out after diffuse bounces,
+// whose rays towards it `sun_light` finds instead.
+fn escaped(r: &Ray, world: &ObjectList, scatter_pdf: Option<f64>, from: Option<usize>) -> Color {
+    let (origin, direction) = (*r.origin(), r.direction().unit_vector());
+    let crossed: Vec<_> = portal::crossed(&world.portals, origin, direction).collect();
+    if let Some(from) = from {
+        let object = &world.objects[from].attributes;
+        if crossed
+            .iter()
+            .any(|portal| !portal.links.illuminates.contains(object))
+        {
+            return Color::default();
+        }
+    }
+    let weight = scatter_pdf.map_or(1.0, |pdf| {
+        if crossed
+            .iter()
+            .any(|portal| !portal.links.shadows.is_everything())
+        {
+            0.0
+        } else {
+            power_heuristic(pdf, portal::pdf(&world.portals, origin, direction))
+        }
+    });
+    let disk = match (&world.sun, scatter_pdf) {
+        (Some(sun), None) if sun.disk(direction) > 0.0 => {
+            sun.disk(direction) * sunlight(r, sun, world)
+        }
+        _ => Color::default(),
+    };
+    weight * sky(r, world) + disk
+}
+
+// What the emitter at `rec` sends back along `r`, unless it isn't linked to
+// the object `from` that the ray left.
+fn emission(r: &Ray, rec: &HitRecord, world: &ObjectList, from: Option<usize>) -> Color {
+    if let (Material::DiffuseLight(light), Some(from)) = (&rec.mat, from) {
+        if !light
+            .links
+            .illuminates
+            .contains(&world.objects[from].attributes)
+        {
+            return Color::default();
+        }
+    }
+    rec.mat.emitted_at(r, rec)
+}
+
+// The density with which a diffuse surface at `rec` scatters in `direction`:
+// cosine distributed, mixed with the guide where it learned something.
+fn diffuse_pdf(rec: &HitRecord, world: &ObjectList, direction: Vec3) -> f64 {
+    let cosine = rec.normal.dot(&direction.unit_vector()).max(0.0) / PI;
+    match world.guide.as_ref().and_then(|guide| guide.cell(rec.p)) {
+        Some(cell) => (1.0 - GUIDED_FRACTION) * cosine + GUIDED_FRACTION * cell.pdf(direction),
+        None => cosine,
+    }
+}
+
+// With a guide, draws some of the rays a diffuse surface scattered from it
+// instead and reweights the attenuation by the mixed density. Returns the
+// density of `scattered` when portals, the guide or the sun need it; None
+// for other materials, whose rays never meet a portal or sun ray and count
+// fully.
+fn diffuse_scatter(
+    rec: &HitRecord,
+    world: &ObjectList,
+    attenuation: &mut Color,
+    scattered: &mut Ray,
+) -> Option<f64> {
+    if !matches!(rec.mat, Material::Lambertian(_))
+        || (world.portals.is_empty() && world.guide.is_none() && world.sun.is_none())
+    {
+        return None;
+    }
+    let cell = world.guide.as_ref().and_then(|guide| guide.cell(rec.p));
+    if let Some(cell) = cell {
+        if rng::random() < GUIDED_FRACTION {
+            *scattered = Ray::new(rec.p, cell.sample())
+                .with_kind(RayKind::Diffuse)
+                .with_cone(scattered.cone_width(0.0), scattered.spread());
+        }
+    }
+    let direction = *scattered.direction();
+    let pdf = diffuse_pdf(rec, world, direction);
+    if cell.is_some() {
+        let cosine = rec.normal.dot(&direction.unit_vector()).max(0.0) / PI;
+        *attenuation = if pdf > 0.0 {
+            *attenuation * (cosine / pdf)
+        } else {
+            Color::default()
+        };
+    }
+    Some(pdf)
+}
+
+// Whether `scattered` went straight on through glass that shadow rays see
+// through. It keeps the density of the diffuse bounce before the glass, since
+// the portal rays from there found the same light.
+fn passes_shadow_rays(rec: &HitRecord, scattered: &Ray, world: &ObjectList) -> bool {
+    // The normal faces the incoming ray, so going on is going against it.
+    world.transparent_shadows
+        && matches!(rec.mat, Material::Glass(_))
+        && scattered.direction().dot(&rec.normal) < 0.0
+}
+
+// The sky seen from a diffuse hit through a random point of a portal, as the
+// second strategy next to the scattered ray.
+fn portal_light(r: &Ray, rec: &HitRecord, world: &ObjectList) -> Color {
+    if !matches!(rec.mat, Material::Lambertian(_)) || world.portals.is_empty() {
+        return Color::default();
+    }
+    let direction = portal::sample_direction(&world.portals, rec.p);
+    let cosine = rec.normal.dot(&direction);
+    let portal_pdf = portal::pdf(&world.portals, rec.p, direction);
+    if cosine <= 0.0 || portal_pdf == 0.0 {
+        return Color::default();
+    }
+    // Linked portals the ray passes through decide what it lights and what
+    // blocks it.
+    let object = &world.objects[rec.object].attributes;
+    let crossed: Vec<_> = portal::crossed(&world.portals, rec.p, direction).collect();
+    if crossed
+        .iter()
+        .any(|portal| !portal.links.illuminates.contains(object))
+    {
+        return Color::default();
+    }
+    let blockers = crossed
+        .iter()
+        .map(|portal| &portal.links.shadows)
+        .find(|shadows| !shadows.is_everything());
+    let shadow = Ray::new(rec.p, direction).with_kind(RayKind::Diffuse);
+    let transmittance = world.transmittance(
+        &shadow,
+        &Interval::new(0.001, f64::INFINITY),
+        blockers.unwrap_or(&ObjectSet::default()),
+    );
+    if transmittance == Color::default() {
+        return Color::default();
+    }
+    let weight = match blockers {
+        Some(_) => 1.0,
+        None => power_heuristic(portal_pdf, diffuse_pdf(rec, world, direction)),
+    };
+    rec.mat.eval_along(r, direction, rec) * transmittance * sky(&shadow, world) * weight
+        / portal_pdf
+}
+
+// The sun seen from a diffuse hit, aimed at directly since its disk is too
+// small for scattered rays to find, see `escaped`.
+fn sun_light(r: &Ray, rec: &HitRecord, world: &ObjectList) -> Color {
+    let (Some(sun), Material::Lambertian(_)) = (&world.sun, &rec.mat) else {
+        return Color::default();
+    };
+    let direction = sun.sample_direction();
+    let cosine = rec.normal.dot(&direction);
+    if cosine <= 0.0 {
+        return Color::default();
+    }
+    let shadow = Ray::new(rec.p, direction).with_kind(RayKind::Diffuse);
+    let transmittance = world.transmittance(
+        &shadow,
+        &Interval::new(0.001, f64::INFINITY),
+        &ObjectSet::default(),
+    );
+    rec.mat.eval_along(r, direction, rec)
+        * transmittance
+        * sun.irradiance
+        * sunlight(&shadow, sun, world)
+}
+
+// How much of the light reaching a holdout at `rec` from the sky and the
+// sun the other objects let through, from one sample of each: 1 where
+// nothing but holdouts is in the way, as the photographed set already shows
+// its own shadows. Light from glowing objects isn't caught.
+fn holdout_light(rec: &HitRecord, world: &ObjectList) -> f64 {
+    let ray_t = Interval::new(0.001, f64::INFINITY);
+    let (mut lit, mut unshadowed) = (0.0, 0.0);
+    let mut add = |r: &Ray, irradiance: f64| {
+        if irradiance > 0.0 && !world.occluded_by_holdouts(r, &ray_t) {
+            unshadowed += irradiance;
+            lit += irradiance * luminance(world.transmittance(r, &ray_t, &ObjectSet::default()));
         }
+    };
 
-        let unit_direction = r.direction().unit_vector();
-        let t = 0.5 * (unit_direction.y() + 1.0);
-        (1.0 - t) * Color::new(1.0, 1.0, 1.0) + t * Color::new(0.5, 0.7, 1.0)
+    // Cosine weighted, so the sky lights the point with π times its
+    // radiance.
+    let direction = (rec.normal + Vec3::random_unit_vector()).or_if_near_zero(rec.normal);
+    let r = Ray::new(rec.p, direction.unit_vector()).with_kind(RayKind::Diffuse);
+    add(&r, PI * luminance(sky(&r, world)));
+    if let Some(sun) = &world.sun {
+        let direction = sun.sample_direction();
+        let r = Ray::new(rec.p, direction).with_kind(RayKind::Diffuse);
+        let cosine = rec.normal.dot(&direction).max(0.0);
+        add(
+            &r,
+            cosine * sun.irradiance * luminance(sunlight(&r, sun, world)),
+        );
     }
+
+    if unshadowed > 0.0 {
+        lit / unshadowed
+    } else {
+        1.0
+    }
+}
+
+#[test]
+fn test_estimate() {
+    let camera = Camera::new(
+        40,
+        60,
+        1,
+        2,
+        90.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let estimate = camera.estimate(&ObjectList::new(), 100);
+    assert_eq!(estimate.total_pixels, 2400);
+    assert!(estimate.sampled_pixels > 0 && estimate.sampled_pixels <= 100);
+    assert!(estimate.estimated >= estimate.elapsed);
+}
+
+#[test]
+fn test_stereo_eyes_converge() {
+    let camera = Camera::new(
+        20,
+        20,
+        1,
+        2,
+        60.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        2.0,
+    );
+    let (left, right) = (camera.eye(-0.1, 5.0), camera.eye(0.1, 5.0));
+    let (l, r) = (left.pixel_ray(3, 7), right.pixel_ray(3, 7));
+    assert_eq!(l.origin().x(), -0.1);
+
+    // Both rays meet on the convergence plane at z = -5.
+    let at = |ray: &Ray| ray.at(-5.0 / ray.direction().z());
+    assert!((at(&l) - at(&r)).length() < 1e-9);
+    assert_eq!(l.direction().y(), r.direction().y());
+}
+
+// A 10x10 camera at `lookfrom` looking down -z, with one sample and one
+// bounce, for the tests to build their cameras and scenes around.
+#[cfg(test)]
+pub(crate) fn test_camera_json(lookfrom: [f64; 3]) -> serde_json::Value {
+    let [x, y, z] = lookfrom;
+    serde_json::json!({
+        "height": 10, "width": 10, "samples_per_pixel": 1, "max_depth": 1, "vfov": 20.0,
+        "lookfrom": [x, y, z], "lookat": [x, y, z - 1.0], "vup": [0.0, 1.0, 0.0],
+        "defocus_angle": 0.0, "focus_dist": 1.0
+    })
+}
+
+// `test_camera_json` turned by `orientation` instead of looking at a point,
+// which puts its lookat `focus_dist` ahead.
+#[cfg(test)]
+fn test_oriented_camera(
+    lookfrom: [f64; 3],
+    focus_dist: f64,
+    orientation: serde_json::Value,
+) -> Camera {
+    let mut json = test_camera_json(lookfrom);
+    let params = json.as_object_mut().unwrap();
+    params.remove("lookat");
+    params.remove("vup");
+    params.insert("focus_dist".to_string(), focus_dist.into());
+    params.insert("orientation".to_string(), orientation);
+    serde_json::from_value(json).unwrap()
+}
+
+#[test]
+fn test_orientation() {
+    let camera = test_oriented_camera(
+        [1.0, 2.0, 3.0],
+        2.0,
+        serde_json::json!({"euler": {"x": 0.0, "y": 90.0, "z": 0.0}}),
+    );
+    // Turning 90 degrees around y makes the camera look down -x.
+    assert!((camera.lookat - Point3D::new(-1.0, 2.0, 3.0)).length() < 1e-9);
+    assert!((camera.vup - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9);
+
+    let back = camera.orientation();
+    let expected = Quat::from_euler(0.0, 90.0, 0.0);
+    assert!(back.dot(&expected).abs() > 1.0 - 1e-9);
+}
+
+#[test]
+fn test_round_trip() {
+    let mut camera = Camera::new(
+        30,
+        40,
+        4,
+        5,
+        35.0,
+        Point3D::new(1.0, 2.0, 3.0),
+        Point3D::new(0.1, 0.2, -0.3),
+        Vec3::new(0.0, 1.0, 0.1),
+        0.7,
+        3.3,
+    );
+    camera.stereo = Some(Stereo {
+        interocular: 0.065,
+        convergence: 4.0,
+        layout: StereoLayout::Anaglyph,
+    });
+    camera.white_balance = Some(WhiteBalance {
+        temperature: 3200.0,
+        tint: 0.1,
+    });
+
+    let json = serde_json::to_string(&camera).unwrap();
+    let back: Camera = serde_json::from_str(&json).unwrap();
+    assert_eq!(back, camera);
+    assert_eq!(serde_json::to_string(&back).unwrap(), json);
+    // Derived fields are never written.
+    assert!(!json.contains("pixel00_loc") && !json.contains("aspect_ratio"));
+
+    // A camera given by orientation comes back with the equivalent lookat.
+    let camera = test_oriented_camera(
+        [0.0, 0.0, 0.0],
+        1.0,
+        serde_json::json!({"axis_angle": {"axis": [1.0, 0.0, 0.0], "angle": -30.0}}),
+    );
+    let json = serde_json::to_string(&camera).unwrap();
+    assert!(!json.contains("orientation"));
+    assert_eq!(serde_json::from_str::<Camera>(&json).unwrap(), camera);
+}
+
+#[test]
+fn test_setters() {
+    let mut camera = Camera::new(
+        10,
+        10,
+        1,
+        2,
+        90.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let before = camera.pixel_ray(0, 0);
+    camera.set_vfov(45.0);
+    let after = camera.pixel_ray(0, 0);
+    assert!(after.direction().x() > before.direction().x());
+
+    let mut fresh = camera.params();
+    fresh.vfov = 45.0;
+    assert_eq!(Camera::from(fresh), camera);
+
+    camera.set_orientation(Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 180.0));
+    assert!((camera.lookat() - Point3D::new(0.0, 0.0, 1.0)).length() < 1e-9);
+    assert!(camera.pixel_ray(5, 5).direction().z() > 0.0);
+
+    camera.set_size(20, 5);
+    assert_eq!(camera.aspect_ratio(), 4.0);
+    camera.update(fresh);
+    assert_eq!(camera.width(), 10);
+}
+
+#[test]
+fn test_check_nan() {
+    use crate::material::{Lambertian, Material};
+
+    let mut world = ObjectList::new();
+    world.add_sphere(
+        Point3D::new(0.0, 0.0, -2.0),
+        1.0,
+        Material::Lambertian(Lambertian::new(Color::new(f64::NAN, 0.5, 0.5))),
+    );
+    let mut camera = Camera::new(
+        5,
+        5,
+        4,
+        4,
+        90.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    assert!(!camera.render_pixel(2, 2, &world).is_finite());
+
+    camera.check_nan = true;
+    assert_eq!(camera.render_pixel(2, 2, &world), Color::new(1.0, 0.0, 1.0));
+    // The background is still fine.
+    assert!(camera.render_pixel(0, 0, &world).is_finite());
+}
+
+#[test]
+fn test_pixel_stats() {
+    use crate::material::{Lambertian, Material};
+
+    let mut world = ObjectList::new();
+    world.add_sphere(
+        Point3D::new(0.0, 0.0, -2.0),
+        1.0,
+        Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    );
+    let camera = Camera::new(
+        5,
+        5,
+        64,
+        4,
+        90.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    // The sphere is noisy, the sky at the top corner is nearly flat.
+    let (_, sphere) = camera.render_pixel_stats(2, 2, &world);
+    let (_, sky) = camera.render_pixel_stats(0, 0, &world);
+    assert_eq!(sphere.samples, 64);
+    assert!(sphere.variance > 0.0);
+    assert!(sky.variance < sphere.variance);
+}
+
+#[test]
+fn test_light_split() {
+    use crate::material::{Lambertian, Material, Metal};
+
+    let mut world = ObjectList::new();
+    world.add_sphere(
+        Point3D::new(0.0, 0.0, -2.0),
+        1.0,
+        Material::Metal(Metal::new(Color::new(0.5, 0.5, 0.5), 0.0)),
+    );
+    world.add_sphere(
+        Point3D::new(0.0, -101.0, -2.0),
+        100.0,
+        Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    );
+    let camera = Camera::new(
+        5,
+        5,
+        1,
+        10,
+        90.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+
+    // The sky seen directly is direct light.
+    let sky = camera.ray_light(&camera.pixel_ray(0, 0), camera.max_depth, &world);
+    assert_eq!(
+        sky.direct(),
+        world.sky.color(*camera.pixel_ray(0, 0).direction())
+    );
+    assert_eq!(sky.indirect(), Color::default());
+
+    // A mirror shows the sky after one bounce, or the ground and then the
+    // sky after more. Both passes add up to the pixel.
+    let mut camera = camera;
+    camera.set_samples_per_pixel(100);
+    let mut indirect_light = 0.0;
+    for (i, j) in [(2, 2), (2, 0), (2, 4)] {
+        let light = camera.render_pixel_passes(i, j, &world).light;
+        assert_eq!(light.total(), camera.render_pixel(i, j, &world));
+        assert!((light.direct() + light.indirect() - light.total()).length() < 1e-9);
+        indirect_light += light.indirect().length();
+    }
+    assert!(indirect_light > 0.0);
+}
+
+#[test]
+fn test_path_types() {
+    use crate::material::{Lambertian, Metal};
+
+    let mut world = ObjectList::new();
+    world.add_sphere(
+        Point3D::new(0.0, 0.0, -2.0),
+        1.0,
+        Material::Metal(Metal::new(Color::new(0.5, 0.5, 0.5), 0.0)),
+    );
+    world.add_sphere(
+        Point3D::new(0.0, -101.0, -2.0),
+        100.0,
+        Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    );
+    let camera = Camera::new(
+        5,
+        5,
+        20,
+        10,
+        90.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+
+    // Sky, mirror and ground: each pixel only has the light of its own kind
+    // of path, and the passes add up to the pixel.
+    let black = Color::default();
+    for ((i, j), pass) in [((0, 0), 0), ((2, 2), 2), ((2, 4), 1)] {
+        let light = camera.render_pixel_passes(i, j, &world).light;
+        let paths = [light.emission(), light.diffuse(), light.specular()];
+        let sum = paths[0] + paths[1] + paths[2];
+        assert!((sum - camera.render_pixel(i, j, &world)).length() < 1e-9);
+        for (index, light) in paths.iter().enumerate() {
+            assert_eq!(index == pass, *light != black, "{:?} {:?}", (i, j), paths);
+        }
+    }
+}
+
+#[test]
+fn test_bounce_limits() {
+    use crate::material::Glass;
+
+    let mut world = ObjectList::new();
+    world.add_sphere(
+        Point3D::new(0.0, 0.0, -2.0),
+        1.0,
+        Material::Glass(Glass::new(1.5)),
+    );
+    let mut camera = Camera::new(
+        5,
+        5,
+        20,
+        10,
+        90.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let clear = camera.render_pixel(2, 2, &world);
+
+    // Without going into the glass only its reflections are left, and
+    // without those nothing.
+    camera.bounces.transmission = Some(0);
+    let reflected = camera.render_pixel(2, 2, &world);
+    assert!(luminance(reflected) > 0.0 && luminance(reflected) < 0.5 * luminance(clear));
+    let light = camera.render_pixel_passes(2, 2, &world).light;
+    assert_eq!(light.emission() + light.diffuse(), Color::default());
+    camera.bounces.glossy = Some(0);
+    assert_eq!(camera.render_pixel(2, 2, &world), Color::default());
+    let light = camera.render_pixel_passes(2, 2, &world).light;
+    assert_eq!(light.specular(), Color::default());
+
+    let json = serde_json::to_value(camera).unwrap();
+    assert_eq!(
+        json["bounces"],
+        serde_json::json!({"glossy": 0, "transmission": 0})
+    );
+    let camera: Camera = serde_json::from_value(json).unwrap();
+    assert_eq!(camera.bounces.glossy, Some(0));
+}
+
+#[test]
+fn test_volume_bounces() {
+    use crate::material::Medium;
+    use crate::volume::Volume;
+
+    // A white cloud in the sky, which only gets out the light it lets in
+    // after many bounces inside.
+    let mut world = ObjectList::new();
+    world.add(
+        Volume::new(
+            Point3D::new(-1.0, -1.0, -3.0),
+            Point3D::new(1.0, 1.0, -1.0),
+            20.0,
+            Material::Medium(Medium::new(Color::new(1.0, 1.0, 1.0))),
+        )
+        .into(),
+    );
+    let mut camera = Camera::new(
+        3,
+        3,
+        400,
+        2,
+        20.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let shallow = luminance(camera.render_pixel(1, 1, &world));
+    camera.bounces.volume = Some(200);
+    let deep = luminance(camera.render_pixel(1, 1, &world));
+    assert!(deep > 1.5 * shallow, "{} {}", deep, shallow);
+    camera.bounces.volume = Some(0);
+    assert_eq!(camera.render_pixel(1, 1, &world), Color::default());
+}
+
+#[test]
+fn test_passes_keep_volume_samples() {
+    use crate::material::Medium;
+    use crate::volume::Volume;
+
+    // Finding the first hit in a volume draws random numbers, which must
+    // not change the samples of the passes.
+    let mut world = ObjectList::new();
+    world.add(
+        Volume::new(
+            Point3D::new(-1.0, -1.0, -3.0),
+            Point3D::new(1.0, 1.0, -1.0),
+            2.0,
+            Material::Medium(Medium::new(Color::new(0.8, 0.8, 0.8))),
+        )
+        .into(),
+    );
+    let camera = Camera::new(
+        3,
+        3,
+        16,
+        8,
+        20.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let passes = camera.render_pixel_passes(1, 1, &world);
+    assert_eq!(passes.light.total(), camera.render_pixel(1, 1, &world));
+    assert_ne!(passes.albedo, Color::default());
+}
+
+#[test]
+fn test_regularization() {
+    let regularization: Regularization = serde_json::from_str("{}").unwrap();
+    assert_eq!(regularization.after, 1);
+    let roughness: Vec<f64> = (0..7).map(|b| regularization.min_roughness(b)).collect();
+    assert_eq!(roughness, [0.0, 0.0, 0.25, 0.5, 0.75, 1.0, 1.0]);
+
+    // The mirror seen by the camera stays sharp.
+    let mut world = ObjectList::new();
+    world.add_sphere(
+        Point3D::new(0.0, 0.0, -2.0),
+        1.0,
+        Material::Metal(crate::material::Metal::new(Color::new(0.8, 0.8, 0.8), 0.0)),
+    );
+    let mut camera = Camera::new(
+        5,
+        5,
+        4,
+        10,
+        90.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let sharp = camera.render_pixel(2, 2, &world);
+    camera.regularization = Some(Regularization {
+        after: 0,
+        strength: 1.0,
+    });
+    assert_eq!(camera.render_pixel(2, 2, &world), sharp);
+}
+
+#[test]
+fn test_quality() {
+    use crate::material::DiffuseLight;
+
+    let json = serde_json::json!({
+        "height": 5, "width": 5, "samples_per_pixel": 256, "max_depth": 50, "vfov": 90.0,
+        "lookfrom": [0, 0, 0], "lookat": [0, 0, -1], "vup": [0, 1, 0],
+        "defocus_angle": 0.0, "focus_dist": 1.0, "quality": "preview"
+    });
+    let camera: Camera = serde_json::from_value(json).unwrap();
+    assert_eq!(camera.quality(), Quality::Preview);
+    assert_eq!((camera.samples_per_pixel(), camera.max_depth()), (16, 4));
+    assert_eq!(camera.radiance_limit(), Some(10.0));
+    assert_eq!(camera.roughening(), Some(Regularization::default()));
+    // The written settings are kept: reading the preview back and switching
+    // it to final gives the full render again.
+    let json = serde_json::to_value(camera).unwrap();
+    assert_eq!(json["samples_per_pixel"], 256);
+    assert!(json.get("max_radiance").is_none());
+    let mut again: Camera = serde_json::from_value(json).unwrap();
+    assert_eq!(again, camera);
+    again.set_quality(Quality::Final);
+    assert_eq!((again.samples_per_pixel(), again.max_depth()), (256, 50));
+    assert_eq!((again.radiance_limit(), again.roughening()), (None, None));
+
+    // A lamp far brighter than the cap is seen at the cap.
+    let mut world = ObjectList::new();
+    world.add_sphere(
+        Point3D::new(0.0, 0.0, -2.0),
+        1.0,
+        Material::DiffuseLight(DiffuseLight::new(Color::new(100.0, 100.0, 100.0))),
+    );
+    let pixel = camera.render_pixel(2, 2, &world);
+    assert!((luminance(pixel) - 10.0).abs() < 1e-9, "{:?}", pixel);
+    let light = camera.render_pixel_passes(2, 2, &world).light;
+    assert!((luminance(light.direct() + light.indirect()) - 10.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_material_override() {
+    use crate::material::{DiffuseLight, Metal};
+
+    let world = |ball: Material| {
+        let mut world = ObjectList::new();
+        world
+            .add_sphere(Point3D::new(0.0, 0.0, -2.0), 1.0, ball)
+            .add_sphere(
+                Point3D::new(1.6, 0.0, -2.0),
+                0.5,
+                Material::DiffuseLight(DiffuseLight::new(Color::new(4.0, 4.0, 4.0))),
+            );
+        world
+    };
+    let metal = world(Material::Metal(Metal::new(Color::new(0.9, 0.6, 0.2), 0.0)));
+    let clay = world(Material::Lambertian(Lambertian::new(Color::new(
+        CLAY_ALBEDO,
+        CLAY_ALBEDO,
+        CLAY_ALBEDO,
+    ))));
+    let mut camera = Camera::new(
+        5,
+        5,
+        8,
+        10,
+        90.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let lamp = camera.render_pixel(4, 2, &metal);
+    let grey = camera.render_pixel(2, 2, &clay);
+
+    // Clay renders like a grey ball, and the lamp stays as it was.
+    camera.material_override = Some(MaterialOverride::Clay);
+    assert_eq!(camera.render_pixel(2, 2, &metal), grey);
+    assert_eq!(camera.render_pixel(4, 2, &metal), lamp);
+
+    // The checker's color is the albedo where the ball was hit.
+    camera.material_override = Some(MaterialOverride::UvChecker);
+    let mut rec = HitRecord {
+        mat: Material::Metal(Metal::new(Color::new(0.9, 0.6, 0.2), 0.0)),
+        u: 0.3,
+        v: 0.7,
+        ..HitRecord::default()
+    };
+    assert_eq!(camera.override_material(&mut rec), None);
+    match rec.mat {
+        Material::Lambertian(lambertian) => assert_eq!(lambertian.albedo, uv_checker(0.3, 0.7)),
+        _ => panic!("{:?}", rec.mat),
+    }
+    assert_eq!(camera.render_pixel(4, 2, &metal), lamp);
+
+    // The ball faces the camera, +z, in the middle.
+    camera.material_override = Some(MaterialOverride::Normals);
+    let normal = camera.render_pixel(2, 2, &metal);
+    assert!(
+        (normal - Color::new(0.5, 0.5, 1.0)).length() < 0.1,
+        "{:?}",
+        normal
+    );
+    assert_eq!(camera.render_pixel(4, 2, &metal), lamp);
+    assert_eq!(
+        MaterialOverride::from_name("normals"),
+        camera.material_override
+    );
+    assert_eq!(MaterialOverride::from_name("chalk"), None);
+}
+
+#[test]
+fn test_wireframe() {
+    use crate::hittable::Shape;
+    use crate::mesh::{Mesh, MeshShape};
+
+    // A square split along the diagonal through the middle of the image,
+    // past its sides.
+    let square =
+        Mesh::parse_obj("v -3 -3 -2\nv 3 -3 -2\nv 3 3 -2\nv -3 3 -2\nf 1 2 3\nf 1 3 4\n").unwrap();
+    let mut world = ObjectList::new();
+    world.add(Object::new(Shape::Mesh(MeshShape::from_mesh(
+        square,
+        Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    ))));
+    let mut camera = Camera::new(
+        5,
+        5,
+        8,
+        10,
+        90.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let shaded = camera.render_pixel(2, 2, &world);
+    let red = Color::new(1.0, 0.0, 0.0);
+    assert!((shaded - red).length() > 0.1);
+
+    // Two pixels wide, the line covers the middle pixel wherever it is
+    // sampled, and misses the corner more than two pixels away.
+    let wireframe: Wireframe = serde_json::from_str(r#"{"width": 2.0}"#).unwrap();
+    assert_eq!(wireframe.color, Color::default());
+    camera.wireframe = Some(Wireframe {
+        color: red,
+        ..wireframe
+    });
+    assert!((camera.render_pixel(2, 2, &world) - red).length() < 1e-9);
+    assert!((camera.render_pixel(0, 0, &world) - red).length() > 0.1);
+
+    // Spheres have no edges.
+    let mut ball = ObjectList::new();
+    ball.add_sphere(
+        Point3D::new(0.0, 0.0, -2.0),
+        1.0,
+        Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    );
+    assert!((camera.render_pixel(2, 2, &ball) - red).length() > 0.1);
+}
+
+#[test]
+fn test_deterministic() {
+    let mut world = ObjectList::new();
+    world.add_sphere(
+        Point3D::new(0.0, 0.0, -2.0),
+        1.0,
+        crate::material::Material::Glass(crate::material::Glass::new(1.5)),
+    );
+    let mut camera = Camera::new(
+        8,
+        8,
+        4,
+        8,
+        90.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.5,
+        2.0,
+    );
+    // Rendered in parallel bands, and pixel by pixel in a different order.
+    let film = camera.render_film(&world, &[]);
+    assert_eq!(camera.render_film(&world, &[]), film);
+    for (n, pixel) in film.pixels.iter().enumerate().rev() {
+        assert_eq!(camera.render_pixel(n % 8, n / 8, &world), *pixel);
+    }
+
+    camera.seed = 1;
+    assert_ne!(camera.render_film(&world, &[]), film);
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn test_render_streaming() {
+    use crate::material::{Lambertian, Material};
+
+    let mut camera = Camera::new(
+        37,
+        24,
+        4,
+        5,
+        40.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    camera.white_balance = Some(WhiteBalance {
+        temperature: 4000.0,
+        tint: 0.0,
+    });
+    let mut world = ObjectList::new();
+    world.add_sphere(
+        Point3D::new(0.0, 0.0, -3.0),
+        1.0,
+        Material::Lambertian(Lambertian::new(Color::new(0.2, 0.6, 0.4))),
+    );
+
+    // 37 rows are two full bands and a partial one.
+    let path = std::env::temp_dir().join(format!("raytracer-stream-{}.png", std::process::id()));
+    let filename = path.to_str().unwrap();
+    camera.render_streaming(filename, &world, &[], &[]).unwrap();
+    let (pixels, bounds) = crate::film::read_image(filename).unwrap();
+    std::fs::remove_file(filename).unwrap();
+    assert_eq!(bounds, (24, 37));
+    assert_eq!(pixels, camera.render_film(&world, &[]).to_rgb8());
+
+    let bloom = [PostEffect::Bloom(Default::default())];
+    assert!(camera
+        .render_streaming(filename, &world, &bloom, &[])
+        .is_err());
+}
+
+#[test]
+fn test_render_streaming_exr() {
+    use crate::material::{Lambertian, Material};
+
+    let camera = Camera::new(
+        70,
+        10,
+        2,
+        5,
+        40.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let mut world = ObjectList::new();
+    world.add_sphere(
+        Point3D::new(0.0, 0.0, -3.0),
+        1.0,
+        Material::Lambertian(Lambertian::new(Color::new(0.2, 0.6, 0.4))),
+    );
+
+    // 70 rows are a full row of tiles and a partial one.
+    let dir = std::env::temp_dir();
+    let streamed = dir.join(format!("raytracer-stream-{}.exr", std::process::id()));
+    let saved = dir.join(format!("raytracer-saved-{}.exr", std::process::id()));
+    let (streamed, saved) = (streamed.to_str().unwrap(), saved.to_str().unwrap());
+    camera.render_streaming(streamed, &world, &[], &[]).unwrap();
+    camera.render(saved, &world).unwrap();
+    assert_eq!(
+        std::fs::read(streamed).unwrap(),
+        std::fs::read(saved).unwrap()
+    );
+    std::fs::remove_file(streamed).unwrap();
+    std::fs::remove_file(saved).unwrap();
+}
+
+#[test]
+fn test_exposure() {
+    // Sunny 16: f/16, 1/100 s at ISO 100 is EV 14.6 and needs the bright
+    // daylight a light meter expects for it.
+    let sunny = Exposure {
+        iso: 100.0,
+        f_number: 16.0,
+        shutter: 0.01,
+        sensor_height: 24.0,
+    };
+    assert!((sunny.ev100() - 14.64).abs() < 0.01);
+    assert!((1.0 / sunny.scale() - 1.2 * 25600.0).abs() < 1e-6);
+    let iso_400 = Exposure {
+        iso: 400.0,
+        ..sunny
+    };
+    assert!((iso_400.scale() / sunny.scale() - 4.0).abs() < 1e-9);
+
+    // A 50 mm lens at f/2 is 25 mm wide.
+    let vfov = 2.0 * (12.0f64 / 50.0).atan().to_degrees();
+    let f2 = Exposure {
+        f_number: 2.0,
+        ..sunny
+    };
+    assert!((f2.aperture_radius(vfov) - 0.0125).abs() < 1e-12);
+
+    let mut camera = Camera::new(
+        4,
+        4,
+        1,
+        2,
+        vfov,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let world = ObjectList::new();
+    let sky = camera.render_pixel(1, 1, &world);
+    camera.set_exposure(Some(sunny));
+    assert!((camera.render_pixel(1, 1, &world) - sunny.scale() * sky).length() < 1e-12);
+    assert_eq!(camera.defocus_disk_u.length(), sunny.aperture_radius(vfov));
+
+    let json = serde_json::to_string(&camera).unwrap();
+    let read: Camera = serde_json::from_str(&json).unwrap();
+    assert_eq!(read.exposure(), Some(sunny));
+    assert_eq!(read, camera);
+}
+
+#[test]
+fn test_portals() {
+    use crate::material::Lambertian;
+    use crate::portal::Portal;
+
+    let mut world = ObjectList::new();
+    world.add_sphere(
+        Point3D::new(0.0, -100.0, 0.0),
+        100.0,
+        Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    );
+    let mut camera = Camera::new(
+        1,
+        1,
+        1,
+        4,
+        90.0,
+        Point3D::new(0.0, 1.0, 1.0),
+        Point3D::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    camera.initialize();
+    let r = Ray::new(Point3D::new(0.0, 1.0, 1.0), Vec3::new(0.0, -1.0, -1.0));
+    let mean = |world: &ObjectList| {
+        let samples = 20_000;
+        (0..samples)
+            .map(|_| camera.ray_color(&r, camera.max_depth, world))
+            .fold(Color::default(), |sum, c| sum + c)
+            / samples as f64
+    };
+    let without = mean(&world);
+
+    // A window over the hit point doesn't change what it sees, only how the
+    // sky is found.
+    world.portals.push(Portal::new(
+        Point3D::new(-0.5, 1.0, -0.5),
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 1.0),
+    ));
+    let with = mean(&world);
+    assert!((with - without).length() < 0.01, "{:?} {:?}", with, without);
+    assert_eq!(
+        camera
+            .ray_light(
+                &Ray::new(Point3D::new(0.0, 1.0, 1.0), Vec3::new(0.0, 1.0, 0.0)),
+                camera.max_depth,
+                &world
+            )
+            .direct(),
+        world.sky.color(Vec3::new(0.0, 1.0, 0.0))
+    );
+}
+
+#[test]
+fn test_light_links() {
+    use crate::material::{DiffuseLight, Lambertian};
+    use crate::portal::Portal;
+    use crate::sphere::Sphere;
+
+    let floor = || {
+        let grey = Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+        Object::from(Sphere::new(Point3D::new(0.0, -100.0, 0.0), 100.0, grey)).named("floor")
+    };
+    let mut camera = Camera::new(
+        1,
+        1,
+        1,
+        4,
+        90.0,
+        Point3D::new(0.0, 1.0, 1.0),
+        Point3D::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    camera.initialize();
+    let r = Ray::new(Point3D::new(0.0, 1.0, 1.0), Vec3::new(0.0, -1.0, -1.0));
+    let mean = |world: &ObjectList| {
+        let samples = 20_000;
+        (0..samples)
+            .map(|_| camera.ray_color(&r, camera.max_depth, world))
+            .fold(Color::default(), |sum, c| sum + c)
+            / samples as f64
+    };
+    let open = mean(&ObjectList::with_objects(vec![floor()]));
+
+    // A lamp that lights everything but the floor only hides some sky.
+    let lamp = |brightness: f64, links: &str| {
+        let mut light = DiffuseLight::new(Color::new(brightness, brightness, brightness));
+        light.links = serde_json::from_str(links).unwrap();
+        Object::from(Sphere::new(
+            Point3D::new(1.0, 1.5, 0.0),
+            0.5,
+            Material::DiffuseLight(light),
+        ))
+    };
+    let mut world = ObjectList::with_objects(vec![floor(), lamp(0.0, "{}")]);
+    let unlit = mean(&world);
+    world.objects = vec![floor(), lamp(4.0, "{}")];
+    assert!((mean(&world) - unlit).length() > 0.1);
+    world.objects = vec![
+        floor(),
+        lamp(4.0, r#"{"illuminates": {"exclude": ["floor"]}}"#),
+    ];
+    let excluded = mean(&world);
+    assert!(
+        (excluded - unlit).length() < 0.01,
+        "{:?} {:?}",
+        excluded,
+        unlit
+    );
+
+    // A black ball under a window shades the floor, unless the window's
+    // light passes it by.
+    let ball = || {
+        let black = Material::Lambertian(Lambertian::new(Color::default()));
+        Object::from(Sphere::new(Point3D::new(0.0, 0.5, 0.0), 0.2, black)).named("ball")
+    };
+    let mut window = Portal::new(
+        Point3D::new(-0.5, 1.0, -0.5),
+        Vec3::new(1.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 1.0),
+    );
+    world.objects = vec![floor(), ball()];
+    world.portals = vec![window.clone()];
+    let shaded = mean(&world);
+    assert!((open - shaded).length() > 0.05, "{:?} {:?}", open, shaded);
+    window.links.shadows.exclude = vec!["ball".to_string()];
+    world.portals = vec![window];
+    let passed = mean(&world);
+    assert!((passed - open).length() < 0.01, "{:?} {:?}", passed, open);
+}
+
+#[test]
+fn test_lens_distortion() {
+    let mut camera = Camera::new(
+        20,
+        30,
+        1,
+        1,
+        40.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let angle = |camera: &Camera, i, j| {
+        let d = camera.pixel_ray(i, j).direction().unit_vector();
+        d.dot(&Vec3::new(0.0, 0.0, -1.0)).acos()
+    };
+    let straight = angle(&camera, 0, 0);
+
+    // Barrel distortion shows more at the edges, pincushion less; the middle
+    // stays where it was.
+    camera.distortion = Some(LensDistortion { k1: 0.2, k2: 0.0 });
+    assert!(angle(&camera, 0, 0) > straight);
+    let middle = camera.pixel_ray(15, 10).direction().unit_vector();
+    let expected =
+        Vec3::new(0.5, -0.5, 0.0) * camera.pixel_delta_u.length() + Vec3::new(0.0, 0.0, -1.0);
+    assert!((middle - expected.unit_vector()).length() < 1e-3);
+    camera.distortion = Some(LensDistortion {
+        k1: -0.1,
+        k2: -0.05,
+    });
+    assert!(angle(&camera, 0, 0) < straight);
+
+    let distortion = LensDistortion { k1: 0.1, k2: 0.01 };
+    assert_eq!(distortion.apply(3.0, 4.0, 5.0), (3.0 * 1.11, 4.0 * 1.11));
+    let json = serde_json::to_string(&Camera {
+        distortion: Some(distortion),
+        ..camera
+    })
+    .unwrap();
+    let camera: Camera = serde_json::from_str(&json).unwrap();
+    assert_eq!(camera.distortion, Some(distortion));
+}
+
+#[test]
+fn test_render_layers() {
+    use crate::light_link::ObjectSet;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+
+    let grey = Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let ball = |x: f64, name: &str| {
+        Object::from(Sphere::new(Point3D::new(x, 0.0, -2.0), 0.45, grey.clone())).named(name)
+    };
+    let mut world = ObjectList::with_objects(vec![ball(-1.41, "left"), ball(1.41, "right")]);
+    let camera = Camera::new(
+        1,
+        5,
+        4,
+        4,
+        20.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        2.0,
+    );
+    let (left, right) = (
+        camera.render_pixel(0, 0, &world),
+        camera.render_pixel(4, 0, &world),
+    );
+    assert!(luminance(left) > 0.0 && luminance(right) > 0.0);
+
+    // The right ball is a matte in the left ball's layer: black, and not
+    // part of the layer's alpha. The sky in between stays.
+    world.isolate(&serde_json::from_str::<ObjectSet>(r#"{"include": ["left"]}"#).unwrap());
+    assert_eq!(camera.render_pixel(0, 0, &world), left);
+    assert_eq!(camera.render_pixel(4, 0, &world), Color::default());
+    assert_eq!(camera.coverage(0, 0, &world), 1.0);
+    assert_eq!(camera.coverage(4, 0, &world), 0.0);
+    assert_eq!(camera.coverage(2, 0, &world), 0.0);
+
+    world.isolate(&ObjectSet::default());
+    assert_eq!(camera.render_pixel(4, 0, &world), right);
+}
+
+#[test]
+fn test_backplate() {
+    use crate::backplate::Backplate;
+    use crate::material::Metal;
+    use crate::sphere::Sphere;
+    use std::sync::Arc;
+
+    let mut camera = Camera::new(
+        4,
+        6,
+        4,
+        4,
+        60.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(1.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        2.0,
+        3.0,
+    );
+    let r = camera.pixel_ray(4, 1);
+    let (x, y) = camera.frame_position(&r);
+    assert!((x - 4.5 / 6.0).abs() < 1e-9 && (y - 1.5 / 4.0).abs() < 1e-9);
+    // Rays from anywhere on the lens land on the same place in focus.
+    camera.set_focus(10.0, 3.0);
+    let (x, y) = camera.frame_position(&camera.get_ray(4, 1, 0));
+    assert!((x - 4.5 / 6.0).abs() < 0.1 && (y - 1.5 / 4.0).abs() < 0.1);
+
+    // A plate that is red on the left and blue on the right, behind a
+    // mirror ball that still reflects the sky.
+    let mut image = Film::new(2, 1);
+    image.pixels = vec![Color::new(1.0, 0.0, 0.0), Color::new(0.0, 0.0, 1.0)];
+    let mut world = ObjectList::new();
+    world.backplate = Some(Backplate {
+        file: String::new(),
+        image: Some(Arc::new(image)),
+    });
+    let camera = Camera::new(
+        1,
+        5,
+        1,
+        4,
+        20.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    assert_eq!(camera.render_pixel(0, 0, &world), Color::new(1.0, 0.0, 0.0));
+    assert_eq!(camera.render_pixel(4, 0, &world), Color::new(0.0, 0.0, 1.0));
+    let mirror = Material::Metal(Metal::new(Color::new(1.0, 1.0, 1.0), 0.0));
+    world.add(Object::from(Sphere::new(
+        Point3D::new(0.0, 0.0, -5.0),
+        1.0,
+        mirror,
+    )));
+    let reflected = camera.render_pixel(2, 0, &world);
+    assert!(
+        reflected.x() > 0.7 && reflected.y() > 0.7,
+        "{:?}",
+        reflected
+    );
+}
+
+#[test]
+fn test_holdout_shadows() {
+    use crate::backplate::Backplate;
+    use crate::sphere::Sphere;
+    use std::sync::Arc;
+
+    // A holdout ground under a white sky with a CG ball floating over the
+    // origin, and a white plate behind it.
+    let mut world = ObjectList::new();
+    let mut ground = Object::from(Sphere::new(
+        Point3D::new(0.0, -1000.0, 0.0),
+        1000.0,
+        Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    ));
+    ground.attributes.holdout = true;
+    world.add(ground);
+    world.add_sphere(
+        Point3D::new(0.0, 0.6, 0.0),
+        0.5,
+        Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    );
+    let mut image = Film::new(1, 1);
+    image.pixels = vec![Color::new(1.0, 1.0, 1.0)];
+    world.backplate = Some(Backplate {
+        file: String::new(),
+        image: Some(Arc::new(image)),
+    });
+
+    // Looking past the ball at the ground under it, and at ground far away.
+    let looking_at = |x: f64| {
+        Camera::new(
+            1,
+            1,
+            16,
+            4,
+            1.0,
+            Point3D::new(0.0, 0.3, 3.0),
+            Point3D::new(x, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            0.0,
+            1.0,
+        )
+    };
+    let (under, away) = (looking_at(0.0), looking_at(3.0));
+    assert!(under.coverage(0, 0, &world) > 0.5);
+    assert!(away.coverage(0, 0, &world) < 0.15);
+    let (dark, bright) = (
+        under.render_pixel(0, 0, &world).x(),
+        away.render_pixel(0, 0, &world).x(),
+    );
+    assert!(dark < 0.5 && bright > 0.85, "{} {}", dark, bright);
+
+    // Shadows the set casts onto itself are in the photograph already.
+    world.objects[1].attributes.holdout = true;
+    assert_eq!(under.coverage(0, 0, &world), 0.0);
+    assert_eq!(under.render_pixel(0, 0, &world), Color::new(1.0, 1.0, 1.0));
+}
+
+#[test]
+fn test_environment() {
+    use crate::environment::Environment;
+
+    let mut world = ObjectList::new();
+    let up = Ray::new(Point3D::default(), Vec3::new(0.0, 1.0, 0.0));
+    let down = Ray::new(Point3D::default(), Vec3::new(0.0, -1.0, 0.0));
+    // Turned upside down and twice as bright, the white below is overhead.
+    world.environment = Environment {
+        pitch: 180.0,
+        intensity: 2.0,
+        ..Environment::default()
+    };
+    assert!((sky(&up, &world) - 2.0 * world.sky.color(*down.direction())).length() < 1e-9);
+
+    let json = r#"{"objects": [], "environment": {"yaw": 90, "intensity": 0.5}}"#;
+    let world: ObjectList = serde_json::from_str(json).unwrap();
+    assert_eq!(world.environment.yaw, 90.0);
+    assert_eq!(sky(&up, &world), 0.5 * world.sky.color(*up.direction()));
 }
@@ -0,0 +1,174 @@
+// C API for embedding the renderer, enabled by the "capi" feature. `cargo
+// xtask capi` builds the shared library and copies the header cbindgen
+// generates for it to include/raytracer.h.
+//
+// Functions returning a pointer return NULL on failure and functions returning
+// an int return non-zero; rt_last_error() then describes the problem. Strings
+// must be NUL terminated UTF-8 and pointers must be valid for the documented
+// sizes; a scene may be used from one thread at a time.
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::config::Config;
+use crate::renderer::Renderer;
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_error(msg: impl Into<Vec<u8>>) {
+    let msg = CString::new(msg).unwrap_or_else(|_| c"invalid error message".into());
+    LAST_ERROR.with(|e| *e.borrow_mut() = Some(msg));
+}
+
+// Runs `f`, turning panics into errors so they don't unwind into C.
+fn guard<T>(fallback: T, f: impl FnOnce() -> Result<T, String>) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(msg)) => {
+            set_error(msg);
+            fallback
+        }
+        Err(_) => {
+            set_error("the renderer panicked");
+            fallback
+        }
+    }
+}
+
+// A loaded scene, opaque to C.
+pub struct RtScene {
+    renderer: Renderer,
+}
+
+// Called from the rendering thread with the finished fraction, 0 to 1.
+pub type RtProgressCallback = Option<extern "C" fn(progress: f64, user_data: *mut c_void)>;
+
+// The message of the last error on this thread, or NULL. It stays valid until
+// the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn rt_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| e.borrow().as_ref().map_or(ptr::null(), |msg| msg.as_ptr()))
+}
+
+// Loads a scene from JSON. The scene can't include other files. Free it with
+// rt_scene_free().
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_from_json(json: *const c_char) -> *mut RtScene {
+    guard(ptr::null_mut(), || {
+        if json.is_null() {
+            return Err("json is NULL".to_string());
+        }
+        let json = CStr::from_ptr(json).to_str().map_err(|e| e.to_string())?;
+        let config = Config::from_json(json).map_err(|e| e.to_string())?;
+        let scene = RtScene {
+            renderer: Renderer::from(config),
+        };
+        Ok(Box::into_raw(Box::new(scene)))
+    })
+}
+
+// Size of the rendered image in pixels.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_image_size(
+    scene: *const RtScene,
+    width: *mut u32,
+    height: *mut u32,
+) -> i32 {
+    guard(-1, || {
+        let scene = scene.as_ref().ok_or("scene is NULL")?;
+        let (w, h) = scene.renderer.camera.output_size();
+        if !width.is_null() {
+            *width = w as u32;
+        }
+        if !height.is_null() {
+            *height = h as u32;
+        }
+        Ok(0)
+    })
+}
+
+// Renders into `rgba`, which must hold width * height * 4 bytes, row by row
+// from the top left. `progress` may be NULL.
+#[no_mangle]
+pub unsafe extern "C" fn rt_render(
+    scene: *const RtScene,
+    rgba: *mut u8,
+    len: usize,
+    progress: RtProgressCallback,
+    user_data: *mut c_void,
+) -> i32 {
+    guard(-1, || {
+        let scene = scene.as_ref().ok_or("scene is NULL")?;
+        let (width, height) = scene.renderer.camera.output_size();
+        let needed = width * height * 4;
+        if rgba.is_null() || len < needed {
+            return Err(format!("the buffer must hold {} bytes", needed));
+        }
+
+        let film = scene.renderer.render_film_with_progress(&mut |_, done| {
+            if let Some(callback) = progress {
+                callback(done, user_data);
+            }
+        });
+        let pixels = film.to_rgba8_with(scene.renderer.camera.clip);
+        ptr::copy_nonoverlapping(pixels.as_ptr(), rgba, pixels.len());
+        Ok(0)
+    })
+}
+
+// Frees a scene from rt_scene_from_json(). NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn rt_scene_free(scene: *mut RtScene) {
+    if !scene.is_null() {
+        drop(Box::from_raw(scene));
+    }
+}
+
+#[test]
+fn test_render() {
+    extern "C" fn count(progress: f64, user_data: *mut c_void) {
+        let calls = unsafe { &mut *(user_data as *mut Vec<f64>) };
+        calls.push(progress);
+    }
+
+    let mut config = crate::scenes::random_spheres(0);
+    config.camera = config.camera.preview(8, 1);
+    let json = CString::new(config.to_json().unwrap()).unwrap();
+
+    unsafe {
+        let scene = rt_scene_from_json(json.as_ptr());
+        assert!(!scene.is_null());
+        let (mut width, mut height) = (0, 0);
+        assert_eq!(rt_scene_image_size(scene, &mut width, &mut height), 0);
+        assert_eq!((width, height), (8, 5));
+
+        let mut buffer = vec![0u8; (width * height * 4) as usize];
+        let mut calls: Vec<f64> = Vec::new();
+        let user_data = &mut calls as *mut Vec<f64> as *mut c_void;
+        assert_eq!(
+            rt_render(scene, buffer.as_mut_ptr(), 3, None, ptr::null_mut()),
+            -1
+        );
+        assert!(!rt_last_error().is_null());
+        let status = rt_render(
+            scene,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            Some(count),
+            user_data,
+        );
+        assert_eq!(status, 0);
+        assert_eq!(calls.last(), Some(&1.0));
+        assert!(buffer.chunks(4).all(|p| p[3] == 255));
+        rt_scene_free(scene);
+
+        let bad = CString::new("{").unwrap();
+        assert!(rt_scene_from_json(bad.as_ptr()).is_null());
+    }
+}
@@ -0,0 +1,163 @@
+// Capsules: every point within `radius` of the segment from `a` to `b`, a
+// cylinder with a half sphere on each end.
+
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::{Point3D, Vec3};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Capsule {
+    pub a: Point3D,
+    pub b: Point3D,
+    pub radius: f64,
+    pub material: Material,
+}
+
+// Where a ray r(t) = o + t·d meets a sphere around the origin, if it does.
+fn sphere_roots(o: Vec3, d: Vec3, radius: f64) -> Option<[f64; 2]> {
+    let a = d.length_squared();
+    let half_b = o.dot(&d);
+    let c = o.length_squared() - radius * radius;
+    let discriminant = half_b * half_b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let sqrtd = discriminant.sqrt();
+    Some([(-half_b - sqrtd) / a, (-half_b + sqrtd) / a])
+}
+
+impl Capsule {
+    pub fn new(a: Point3D, b: Point3D, radius: f64, material: Material) -> Capsule {
+        Capsule {
+            a,
+            b,
+            radius: radius.max(0.0),
+            material,
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        let length = (self.b - self.a).length();
+        2.0 * PI * self.radius * length + 4.0 * PI * self.radius * self.radius
+    }
+}
+
+impl Hittable for Capsule {
+    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+        let axis = self.b - self.a;
+        let length = axis.length();
+        let w = if length > 0.0 { axis / length } else { axis };
+        let (o, d) = (*r.origin() - self.a, *r.direction());
+        // How far along the axis a point of the ray is.
+        let along = |t: f64| (o + t * d).dot(&w);
+
+        // The side is an infinite cylinder around the axis, seen across it,
+        // cut off at the ends; the ends are spheres, cut off at the side.
+        let mut candidates = Vec::with_capacity(6);
+        if length > 0.0 {
+            let (o_across, d_across) = (o - o.dot(&w) * w, d - d.dot(&w) * w);
+            if d_across.length_squared() > 0.0 {
+                if let Some(roots) = sphere_roots(o_across, d_across, self.radius) {
+                    candidates.extend(roots.into_iter().filter(|&t| {
+                        let s = along(t);
+                        s > 0.0 && s < length
+                    }));
+                }
+            }
+        }
+        if let Some(roots) = sphere_roots(o, d, self.radius) {
+            candidates.extend(roots.into_iter().filter(|&t| along(t) <= 0.0));
+        }
+        if let Some(roots) = sphere_roots(o - axis, d, self.radius) {
+            candidates.extend(roots.into_iter().filter(|&t| along(t) >= length));
+        }
+
+        let Some(t) = candidates
+            .into_iter()
+            .filter(|&t| ray_t.contains(t))
+            .min_by(f64::total_cmp)
+        else {
+            return false;
+        };
+        rec.t = t;
+        rec.p = r.at(t);
+        let closest = self.a + along(t).clamp(0.0, length) * w;
+        rec.set_face_normal(r, (rec.p - closest) / self.radius);
+        // No texture coordinates.
+        rec.u = 0.0;
+        rec.v = 0.0;
+        rec.dpdu = Vec3::default();
+        rec.dpdv = Vec3::default();
+        rec.mat = self.material.clone();
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::surrounding_box(
+            &Aabb::from_points(self.a - r, self.a + r),
+            &Aabb::from_points(self.b - r, self.b + r),
+        )
+    }
+}
+
+#[test]
+fn test_capsule() {
+    let capsule = Capsule::new(
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 2.0, 0.0),
+        0.5,
+        Material::Named("x".to_string()),
+    );
+    let hit = |origin: Point3D, direction: Vec3| {
+        let mut rec = HitRecord::default();
+        let r = Ray::new(origin, direction);
+        capsule
+            .hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec)
+            .then_some(rec)
+    };
+
+    // The side, with a ray that isn't unit length.
+    let rec = hit(Point3D::new(5.0, 1.0, 0.0), Vec3::new(-2.0, 0.0, 0.0)).unwrap();
+    assert!((rec.t - 2.25).abs() < 1e-12);
+    assert!((rec.normal - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-12);
+    // The caps, from above and below.
+    let rec = hit(Point3D::new(0.0, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).unwrap();
+    assert!((rec.t - 2.5).abs() < 1e-12);
+    assert!((rec.normal - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+    let rec = hit(Point3D::new(0.3, -5.0, 0.0), Vec3::new(0.0, 1.0, 0.0)).unwrap();
+    assert!((rec.t - 4.6).abs() < 1e-12);
+    // Slanting in through the top cap, just past the end of the side.
+    let rec = hit(Point3D::new(5.0, 7.1, 0.0), Vec3::new(-1.0, -1.0, 0.0)).unwrap();
+    assert!(rec.p.y() > 2.0 && (rec.p - Point3D::new(0.0, 2.0, 0.0)).length() > 0.499);
+    // Past the ends and beside the side.
+    assert!(hit(Point3D::new(5.0, 2.6, 0.0), Vec3::new(-1.0, 0.0, 0.0)).is_none());
+    assert!(hit(Point3D::new(0.6, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0)).is_none());
+    // From inside the far wall faces away.
+    let rec = hit(Point3D::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)).unwrap();
+    assert!((rec.t - 0.5).abs() < 1e-12 && !rec.front_face);
+
+    assert!((capsule.area() - 3.0 * PI).abs() < 1e-12);
+    let bounds = capsule.bounding_box();
+    assert_eq!(bounds.min(), Point3D::new(-0.5, -0.5, -0.5));
+    assert_eq!(bounds.max(), Point3D::new(0.5, 2.5, 0.5));
+
+    // Without a length it is a sphere.
+    let ball = Capsule::new(
+        Point3D::default(),
+        Point3D::default(),
+        1.0,
+        capsule.material,
+    );
+    let mut rec = HitRecord::default();
+    let r = Ray::new(Point3D::new(0.0, 0.0, 3.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(ball.hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec));
+    assert!((rec.t - 2.0).abs() < 1e-12);
+}
@@ -0,0 +1,69 @@
+// Clipping planes, for cutaway views. Each plane removes everything on the
+// side its normal points to, from one object or from the whole scene, as if
+// the surfaces weren't there: the cut isn't capped, so the inside of a solid
+// shows through it. Rays only look for hits where they are on the kept side
+// of every plane, which is a single stretch of the ray.
+
+use serde::{Deserialize, Serialize};
+
+use crate::interval::Interval;
+use crate::ray::Ray;
+use crate::vec3::{Point3D, Vec3};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ClipPlane {
+    pub point: Point3D,
+    pub normal: Vec3,
+}
+
+impl ClipPlane {
+    pub fn new(point: Point3D, normal: Vec3) -> ClipPlane {
+        ClipPlane { point, normal }
+    }
+
+    // The part of `ray_t` where the ray is on the kept side.
+    fn clip(&self, r: &Ray, ray_t: Interval) -> Interval {
+        let start = (*r.origin() - self.point).dot(&self.normal);
+        let speed = r.direction().dot(&self.normal);
+        if speed == 0.0 {
+            return if start > 0.0 { Interval::EMPTY } else { ray_t };
+        }
+        let t = -start / speed;
+        if speed > 0.0 {
+            Interval::new(ray_t.min, ray_t.max.min(t))
+        } else {
+            Interval::new(ray_t.min.max(t), ray_t.max)
+        }
+    }
+}
+
+// What is left of `ray_t` after all the planes, if anything.
+pub fn clip(planes: &[ClipPlane], r: &Ray, ray_t: &Interval) -> Option<Interval> {
+    let ray_t = planes
+        .iter()
+        .fold(*ray_t, |ray_t, plane| plane.clip(r, ray_t));
+    (ray_t.min <= ray_t.max).then_some(ray_t)
+}
+
+#[test]
+fn test_clip() {
+    // Keeps x below 1 and z above 0.
+    let planes = [
+        ClipPlane::new(Point3D::new(1.0, 0.0, 0.0), Vec3::new(2.0, 0.0, 0.0)),
+        ClipPlane::new(Point3D::new(0.0, 5.0, 0.0), Vec3::new(0.0, 0.0, -1.0)),
+    ];
+    let all = Interval::new(0.001, f64::INFINITY);
+
+    let r = Ray::new(Point3D::new(-3.0, 0.0, 1.0), Vec3::new(2.0, 0.0, 0.0));
+    assert_eq!(clip(&planes, &r, &all), Some(Interval::new(0.001, 2.0)));
+    let r = Ray::new(Point3D::new(5.0, 0.0, 1.0), Vec3::new(-1.0, 0.0, -1.0));
+    assert_eq!(clip(&planes, &r, &all), None);
+    let r = Ray::new(Point3D::new(5.0, 0.0, 5.0), Vec3::new(-1.0, 0.0, -1.0));
+    assert_eq!(clip(&planes, &r, &all), Some(Interval::new(4.0, 5.0)));
+    // Parallel to a plane, on either side of it.
+    let r = Ray::new(Point3D::new(0.0, 0.0, 1.0), Vec3::new(0.0, 1.0, 0.0));
+    assert_eq!(clip(&planes, &r, &all), Some(all));
+    let r = Ray::new(Point3D::new(0.0, 0.0, -1.0), Vec3::new(0.0, 1.0, 0.0));
+    assert_eq!(clip(&planes, &r, &all), None);
+    assert_eq!(clip(&[], &r, &all), Some(all));
+}
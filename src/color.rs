@@ -1,9 +1,11 @@
+use crate::post::SoftClip;
 use crate::vec3::Vec3;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::io;
 
 pub type Color = Vec3;
 
-fn linear_to_gamma(linear_component: f64) -> f64 {
+pub fn linear_to_gamma(linear_component: f64) -> f64 {
     if linear_component > 0.0 {
         linear_component.sqrt()
     } else {
@@ -11,7 +13,157 @@ fn linear_to_gamma(linear_component: f64) -> f64 {
     }
 }
 
+pub fn gamma_to_linear(gamma_component: f64) -> f64 {
+    gamma_component * gamma_component
+}
+
+pub fn luminance(c: Color) -> f64 {
+    0.2126 * c.x() + 0.7152 * c.y() + 0.0722 * c.z()
+}
+
+// Black at 0 through red and yellow to white at 1, for images of values
+// rather than colors.
+pub fn heat_color(t: f64) -> [u8; 3] {
+    let t = t.clamp(0.0, 1.0) * 3.0;
+    [t, t - 1.0, t - 2.0].map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+// Piecewise Gaussian fit of the CIE 1931 color matching functions (Wyman,
+// Sloan and Shirley 2013), `nm` in nanometers.
+fn cie_xyz(nm: f64) -> (f64, f64, f64) {
+    let g = |mu: f64, s1: f64, s2: f64| {
+        let s = if nm < mu { s1 } else { s2 };
+        (-(nm - mu) * (nm - mu) / (2.0 * s * s)).exp()
+    };
+    (
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2),
+        0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1),
+        1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8),
+    )
+}
+
+// The CIE XYZ color of the light of a black body at `kelvin`, in arbitrary
+// but fixed units.
+fn planck_xyz(kelvin: f64) -> (f64, f64, f64) {
+    const C2: f64 = 1.4388e-2;
+    let (mut x, mut y, mut z) = (0.0, 0.0, 0.0);
+    for nm in (380..=780).step_by(5) {
+        let nm = nm as f64;
+        let m = nm * 1e-9;
+        let radiance = 1.0 / (m.powi(5) * ((C2 / (m * kelvin.max(1.0))).exp() - 1.0));
+        let (cx, cy, cz) = cie_xyz(nm);
+        x += radiance * cx;
+        y += radiance * cy;
+        z += radiance * cz;
+    }
+    (x, y, z)
+}
+
+// The linear sRGB color of a black body at `kelvin`, scaled to a luminance
+// of 1 so only the hue depends on the temperature.
+pub fn blackbody(kelvin: f64) -> Color {
+    blackbody_with_luminance(kelvin).0
+}
+
+// `blackbody` together with the luminance `blackbody_luminance` compares,
+// from a single integration.
+pub fn blackbody_with_luminance(kelvin: f64) -> (Color, f64) {
+    let (x, y, z) = planck_xyz(kelvin);
+    let (x, z) = (x / y, z / y);
+
+    let color = Color::new(
+        (3.2406 * x - 1.5372 - 0.4986 * z).max(0.0),
+        (-0.9689 * x + 1.8758 + 0.0415 * z).max(0.0),
+        (0.0557 * x - 0.2040 + 1.0570 * z).max(0.0),
+    );
+    (color, y)
+}
+
+// How bright a black body at `kelvin` looks next to one at `reference`
+// kelvin. Glowing matter brightens steeply as it heats up: at 1000 K it is
+// hardly visible next to 1500 K.
+pub fn blackbody_luminance(kelvin: f64, reference: f64) -> f64 {
+    planck_xyz(kelvin).1 / planck_xyz(reference).1
+}
+
+// Color temperatures of common light sources in Kelvin, which scene files
+// may give by name.
+pub const NAMED_TEMPERATURES: &[(&str, f64)] = &[
+    ("candle", 1900.0),
+    ("incandescent", 2700.0),
+    ("tungsten", 3200.0),
+    ("daylight", 5600.0),
+    ("overcast", 6500.0),
+    ("shade", 7500.0),
+];
+
+pub fn named_temperature(name: &str) -> Option<f64> {
+    NAMED_TEMPERATURES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, kelvin)| *kelvin)
+}
+
+// A temperature in Kelvin, or the name of a light source from
+// `NAMED_TEMPERATURES`.
+pub fn deserialize_kelvin<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Kelvin {
+        Number(f64),
+        Name(String),
+    }
+    match Kelvin::deserialize(deserializer)? {
+        Kelvin::Number(kelvin) => Ok(kelvin),
+        Kelvin::Name(name) => named_temperature(&name).ok_or_else(|| {
+            let names: Vec<&str> = NAMED_TEMPERATURES.iter().map(|(n, _)| *n).collect();
+            serde::de::Error::custom(format!(
+                "unknown light source \"{}\", expected a temperature in Kelvin or one of: {}",
+                name,
+                names.join(", ")
+            ))
+        }),
+    }
+}
+
+pub fn deserialize_optional_kelvin<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<f64>, D::Error> {
+    deserialize_kelvin(deserializer).map(Some)
+}
+
+// How `write_color_with` brings colors brighter than white into range.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Clip {
+    // Every channel is cut at white on its own, so an overexposed orange
+    // lamp turns yellow and then white.
+    #[default]
+    Channel,
+    // Highlights are rolled off together, keeping their hue, see `SoftClip`.
+    Soft(SoftClip),
+}
+
+impl Clip {
+    pub fn is_channel(&self) -> bool {
+        *self == Clip::Channel
+    }
+
+    // `color` as it is written, before the per channel clamp.
+    pub fn apply(&self, color: Color) -> Color {
+        match self {
+            Clip::Channel => color,
+            Clip::Soft(soft_clip) => soft_clip.pixel(color),
+        }
+    }
+}
+
 pub fn write_color(buffer: &mut Vec<u8>, pixel_color: Color) -> io::Result<()> {
+    write_color_with(buffer, pixel_color, Clip::Channel)
+}
+
+pub fn write_color_with(buffer: &mut Vec<u8>, pixel_color: Color, clip: Clip) -> io::Result<()> {
+    let pixel_color = clip.apply(pixel_color);
     let mut r = pixel_color.x();
     let mut g = pixel_color.y();
     let mut b = pixel_color.z();
@@ -49,4 +201,52 @@ fn test_write_color() {
     let pixel_color = Color::new(0.5, 0.25, 0.75);
     write_color(&mut buffer, pixel_color).unwrap();
     assert_eq!(buffer, vec![181, 128, 221]);
+
+    // Overexposed orange: cut per channel it turns yellow, softly clipped it
+    // stays orange.
+    let orange = Color::new(4.0, 2.0, 0.0);
+    let mut hard = Vec::new();
+    write_color(&mut hard, orange).unwrap();
+    assert_eq!(hard, vec![255, 255, 0]);
+    let mut soft = Vec::new();
+    write_color_with(&mut soft, orange, Clip::Soft(SoftClip::default())).unwrap();
+    assert!(soft[0] > soft[1] + 40 && soft[2] == 0, "{:?}", soft);
+    let mut dark = Vec::new();
+    write_color_with(&mut dark, pixel_color, Clip::Soft(SoftClip::default())).unwrap();
+    assert_eq!(dark, buffer);
+
+    let clip: Clip = serde_json::from_str(r#"{"soft": {"knee": 0.5}}"#).unwrap();
+    assert_eq!(clip, Clip::Soft(SoftClip { knee: 0.5 }));
+    assert_eq!(
+        serde_json::from_str::<Clip>(r#""channel""#).unwrap(),
+        Clip::Channel
+    );
+}
+
+#[test]
+fn test_blackbody() {
+    let white = blackbody(6500.0);
+    assert!((luminance(white) - 1.0).abs() < 0.05);
+    assert!((white.x() - white.z()).abs() < 0.1);
+
+    let candle = blackbody(1900.0);
+    assert!(candle.x() > candle.y() && candle.y() > candle.z());
+    let sky = blackbody(12000.0);
+    assert!(sky.z() > sky.x());
+}
+
+#[test]
+fn test_named_temperatures() {
+    #[derive(Deserialize)]
+    struct Light {
+        #[serde(deserialize_with = "deserialize_kelvin")]
+        temperature: f64,
+    }
+    let kelvin = |json: &str| serde_json::from_str::<Light>(json).map(|l| l.temperature);
+    assert_eq!(kelvin(r#"{"temperature": 4000}"#).unwrap(), 4000.0);
+    assert_eq!(kelvin(r#"{"temperature": "tungsten"}"#).unwrap(), 3200.0);
+    let error = kelvin(r#"{"temperature": "neon"}"#)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("daylight"), "{}", error);
 }
@@ -0,0 +1,405 @@
+// Measures how far apart two renders of the same scene are, e.g. before and
+// after an optimization. Images are 8-bit RGB, row by row from the top left.
+
+use std::io;
+
+use crate::color::heat_color;
+use crate::film::{read_image, write_image};
+
+// SSIM is computed over windows of this size, moved by half a window.
+const WINDOW: usize = 8;
+
+// FLIP assumes the images are seen at this many pixels per degree, a 4K
+// monitor 0.7 m away.
+const PIXELS_PER_DEGREE: f64 = 67.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Comparison {
+    // Peak signal-to-noise ratio in dB, infinite for identical images.
+    pub psnr: f64,
+    // Structural similarity of the luma, 1 for identical images.
+    pub ssim: f64,
+    // The mean LDR-FLIP error, 0 for identical images and at most 1.
+    pub flip: f64,
+    // The largest difference of any channel.
+    pub max_difference: u8,
+}
+
+pub fn compare(a: &[u8], b: &[u8], width: usize, height: usize) -> io::Result<Comparison> {
+    assert_eq!(a.len(), width * height * 3);
+    assert_eq!(b.len(), a.len());
+    if a.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "can't compare images without pixels",
+        ));
+    }
+    let errors = flip(a, b, width, height);
+    Ok(Comparison {
+        psnr: psnr(a, b),
+        ssim: ssim(&luma(a), &luma(b), width, height),
+        flip: errors.iter().sum::<f64>() / errors.len() as f64,
+        max_difference: a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap_or(0),
+    })
+}
+
+// Images must not be empty.
+pub fn psnr(a: &[u8], b: &[u8]) -> f64 {
+    let mse = a
+        .iter()
+        .zip(b)
+        .map(|(a, b)| (*a as f64 - *b as f64).powi(2))
+        .sum::<f64>()
+        / a.len() as f64;
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
+
+// Rec. 601 weights, as most SSIM implementations use.
+fn luma(rgb: &[u8]) -> Vec<f64> {
+    rgb.chunks(3)
+        .map(|p| 0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64)
+        .collect()
+}
+
+// The mean SSIM of all windows. Images smaller than a window are one window.
+// Images must not be empty.
+pub fn ssim(a: &[f64], b: &[f64], width: usize, height: usize) -> f64 {
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let (w, h) = (WINDOW.min(width), WINDOW.min(height));
+    let starts = |size: usize, window: usize| {
+        let mut starts: Vec<usize> = (0..=size - window).step_by(WINDOW / 2).collect();
+        if starts.last() != Some(&(size - window)) {
+            starts.push(size - window);
+        }
+        starts
+    };
+
+    let mut total = 0.0;
+    let mut windows = 0;
+    for y0 in starts(height, h) {
+        for x0 in starts(width, w) {
+            let pixels =
+                || (y0..y0 + h).flat_map(move |y| (x0..x0 + w).map(move |x| y * width + x));
+            let n = (w * h) as f64;
+            let mean_a = pixels().map(|i| a[i]).sum::<f64>() / n;
+            let mean_b = pixels().map(|i| b[i]).sum::<f64>() / n;
+            let (mut var_a, mut var_b, mut covariance) = (0.0, 0.0, 0.0);
+            for i in pixels() {
+                let (da, db) = (a[i] - mean_a, b[i] - mean_b);
+                var_a += da * da;
+                var_b += db * db;
+                covariance += da * db;
+            }
+            let (var_a, var_b, covariance) = (var_a / n, var_b / n, covariance / n);
+            total += (2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2)
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+    total / windows as f64
+}
+
+// Linear RGB to CIE XYZ and back, for the sRGB primaries.
+const RGB_TO_XYZ: [[f64; 3]; 3] = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.0721750],
+    [0.0193339, 0.1191920, 0.9503041],
+];
+const XYZ_TO_RGB: [[f64; 3]; 3] = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.9692660, 1.8760108, 0.0415560],
+    [0.0556434, -0.2040259, 1.0572252],
+];
+
+fn transform(m: &[[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+// XYZ relative to the white point, the XYZ of linear (1, 1, 1).
+fn relative(xyz: [f64; 3]) -> [f64; 3] {
+    let white = transform(&RGB_TO_XYZ, [1.0; 3]);
+    [xyz[0] / white[0], xyz[1] / white[1], xyz[2] / white[2]]
+}
+
+fn srgb_to_linear(c: u8) -> f64 {
+    let c = c as f64 / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// The linearized opponent space YCxCz, in which FLIP filters the images the
+// way the eye blurs them.
+fn ycxcz(linear: [f64; 3]) -> [f64; 3] {
+    let [x, y, z] = relative(transform(&RGB_TO_XYZ, linear));
+    [116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z)]
+}
+
+fn ycxcz_to_linear(ycxcz: [f64; 3]) -> [f64; 3] {
+    let white = transform(&RGB_TO_XYZ, [1.0; 3]);
+    let y = (ycxcz[0] + 16.0) / 116.0;
+    let xyz = [
+        (ycxcz[1] / 500.0 + y) * white[0],
+        y * white[1],
+        (y - ycxcz[2] / 200.0) * white[2],
+    ];
+    transform(&XYZ_TO_RGB, xyz)
+}
+
+// CIELAB with a and b scaled by the lightness, so that dark colors differ
+// less (the Hunt effect).
+fn hunt_lab(linear: [f64; 3]) -> [f64; 3] {
+    const DELTA: f64 = 6.0 / 29.0;
+    let f = |t: f64| {
+        if t > DELTA * DELTA * DELTA {
+            t.cbrt()
+        } else {
+            t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+        }
+    };
+    let [x, y, z] = relative(transform(&RGB_TO_XYZ, linear)).map(f);
+    let l = 116.0 * y - 16.0;
+    [l, 0.01 * l * 500.0 * (x - y), 0.01 * l * 200.0 * (y - z)]
+}
+
+fn hyab(a: [f64; 3], b: [f64; 3]) -> f64 {
+    (a[0] - b[0]).abs() + ((a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+// Convolves one channel with a square kernel, repeating the edge pixels.
+fn convolve(image: &[f64], width: usize, height: usize, kernel: &[f64], radius: usize) -> Vec<f64> {
+    let size = 2 * radius + 1;
+    let mut out = vec![0.0; image.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = 0.0;
+            for ky in 0..size {
+                let sy = (y + ky).saturating_sub(radius).min(height - 1);
+                for kx in 0..size {
+                    let sx = (x + kx).saturating_sub(radius).min(width - 1);
+                    sum += kernel[ky * size + kx] * image[sy * width + sx];
+                }
+            }
+            out[y * width + x] = sum;
+        }
+    }
+    out
+}
+
+// The contrast sensitivity of the eye to each YCxCz channel as a spatial
+// filter, a sum of two Gaussians given as (a1, b1, a2, b2) in degrees.
+fn csf_kernels() -> ([Vec<f64>; 3], usize) {
+    const PARAMETERS: [[f64; 4]; 3] = [
+        [1.0, 0.0047, 0.0, 1e-5],
+        [1.0, 0.0053, 0.0, 1e-5],
+        [34.1, 0.04, 13.5, 0.025],
+    ];
+    let pi2 = std::f64::consts::PI * std::f64::consts::PI;
+    let radius = (3.0 * (0.04 / (2.0 * pi2)).sqrt() * PIXELS_PER_DEGREE).ceil() as usize;
+    let size = 2 * radius + 1;
+    let kernels = PARAMETERS.map(|[a1, b1, a2, b2]| {
+        let gaussian =
+            |a: f64, b: f64, d2: f64| a * (std::f64::consts::PI / b).sqrt() * (-pi2 * d2 / b).exp();
+        let mut kernel: Vec<f64> = (0..size * size)
+            .map(|i| {
+                let dx = (i % size) as f64 - radius as f64;
+                let dy = (i / size) as f64 - radius as f64;
+                let d2 = (dx * dx + dy * dy) / (PIXELS_PER_DEGREE * PIXELS_PER_DEGREE);
+                gaussian(a1, b1, d2) + gaussian(a2, b2, d2)
+            })
+            .collect();
+        let total: f64 = kernel.iter().sum();
+        kernel.iter_mut().for_each(|k| *k /= total);
+        kernel
+    });
+    (kernels, radius)
+}
+
+// The first and second derivatives of a Gaussian along x, for finding edges
+// and points. Positive weights sum to 1 and negative ones to -1.
+fn feature_kernels() -> ([Vec<f64>; 2], usize) {
+    let sd = 0.5 * 0.082 * PIXELS_PER_DEGREE;
+    let radius = (3.0 * sd).ceil() as usize;
+    let size = 2 * radius + 1;
+    let kernel = |f: &dyn Fn(f64) -> f64| {
+        let mut kernel: Vec<f64> = (0..size * size)
+            .map(|i| {
+                let x = (i % size) as f64 - radius as f64;
+                let y = (i / size) as f64 - radius as f64;
+                f(x) * (-(x * x + y * y) / (2.0 * sd * sd)).exp()
+            })
+            .collect();
+        let positive: f64 = kernel.iter().filter(|k| **k > 0.0).sum();
+        let negative: f64 = -kernel.iter().filter(|k| **k < 0.0).sum::<f64>();
+        for k in kernel.iter_mut() {
+            *k /= if *k > 0.0 { positive } else { negative };
+        }
+        kernel
+    };
+    let edge = kernel(&|x| -x);
+    let point = kernel(&|x| x * x / (sd * sd) - 1.0);
+    ([edge, point], radius)
+}
+
+fn transpose(kernel: &[f64], radius: usize) -> Vec<f64> {
+    let size = 2 * radius + 1;
+    (0..size * size)
+        .map(|i| kernel[(i % size) * size + i / size])
+        .collect()
+}
+
+// The LDR-FLIP error of every pixel (Andersson et al. 2020), between 0 and
+// 1: the color difference of the images as the eye blurs them, raised where
+// edges or points differ.
+pub fn flip(a: &[u8], b: &[u8], width: usize, height: usize) -> Vec<f64> {
+    const QC: f64 = 0.7;
+    const PC: f64 = 0.4;
+    const PT: f64 = 0.95;
+    const QF: f64 = 0.5;
+
+    let (csf, csf_radius) = csf_kernels();
+    let ([edge, point], feature_radius) = feature_kernels();
+    let kernels = [
+        transpose(&edge, feature_radius),
+        edge,
+        transpose(&point, feature_radius),
+        point,
+    ];
+
+    // For each image, Hunt-adjusted Lab after filtering and the magnitude of
+    // its edges and points.
+    let prepare = |rgb: &[u8]| {
+        let opponent: Vec<[f64; 3]> = rgb
+            .chunks(3)
+            .map(|p| ycxcz([p[0], p[1], p[2]].map(srgb_to_linear)))
+            .collect();
+        let filtered: Vec<Vec<f64>> = (0..3)
+            .map(|c| {
+                let channel: Vec<f64> = opponent.iter().map(|p| p[c]).collect();
+                convolve(&channel, width, height, &csf[c], csf_radius)
+            })
+            .collect();
+        let lab: Vec<[f64; 3]> = (0..opponent.len())
+            .map(|i| {
+                let linear = ycxcz_to_linear([filtered[0][i], filtered[1][i], filtered[2][i]]);
+                hunt_lab(linear.map(|c| c.clamp(0.0, 1.0)))
+            })
+            .collect();
+
+        let brightness: Vec<f64> = opponent.iter().map(|p| (p[0] + 16.0) / 116.0).collect();
+        let [ex, ey, px, py] = kernels
+            .each_ref()
+            .map(|k| convolve(&brightness, width, height, k, feature_radius));
+        let edges: Vec<f64> = ex.iter().zip(&ey).map(|(x, y)| x.hypot(*y)).collect();
+        let points: Vec<f64> = px.iter().zip(&py).map(|(x, y)| x.hypot(*y)).collect();
+        (lab, edges, points)
+    };
+    let (lab_a, edges_a, points_a) = prepare(a);
+    let (lab_b, edges_b, points_b) = prepare(b);
+
+    // Green and blue are the furthest apart of the colors an image can hold.
+    let cmax = hyab(hunt_lab([0.0, 1.0, 0.0]), hunt_lab([0.0, 0.0, 1.0])).powf(QC);
+    (0..lab_a.len())
+        .map(|i| {
+            let color = hyab(lab_a[i], lab_b[i]).powf(QC);
+            let color = if color < PC * cmax {
+                color * PT / (PC * cmax)
+            } else {
+                (PT + (color - PC * cmax) / (cmax - PC * cmax) * (1.0 - PT)).min(1.0)
+            };
+            let feature = ((edges_a[i] - edges_b[i]).abs()).max((points_a[i] - points_b[i]).abs())
+                / std::f64::consts::SQRT_2;
+            color.powf(1.0 - feature.powf(QF))
+        })
+        .collect()
+}
+
+// Black where the images agree, `heat_color` of the largest difference of
+// any channel elsewhere. Differences are scaled so that a quarter
+// of the full range is already white.
+pub fn heatmap(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.chunks(3)
+        .zip(b.chunks(3))
+        .flat_map(|(a, b)| {
+            let difference = (0..3).map(|c| a[c].abs_diff(b[c])).max().unwrap_or(0);
+            heat_color(difference as f64 / 64.0)
+        })
+        .collect()
+}
+
+// Compares two image files of the same size and optionally writes the
+// heatmap of their differences as a PNG.
+pub fn compare_files(a: &str, b: &str, heatmap_file: Option<&str>) -> io::Result<Comparison> {
+    let (pixels_a, size_a) = read_image(a)?;
+    let (pixels_b, size_b) = read_image(b)?;
+    if size_a != size_b {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} is {}x{} but {} is {}x{}",
+                a, size_a.0, size_a.1, b, size_b.0, size_b.1
+            ),
+        ));
+    }
+    if let Some(filename) = heatmap_file {
+        write_image(filename, &heatmap(&pixels_a, &pixels_b), size_a)?;
+    }
+    compare(&pixels_a, &pixels_b, size_a.0, size_a.1)
+}
+
+#[test]
+fn test_identical() {
+    let image: Vec<u8> = (0..16 * 12 * 3).map(|i| (i * 7 % 256) as u8).collect();
+    let comparison = compare(&image, &image, 16, 12).unwrap();
+    assert_eq!(comparison.psnr, f64::INFINITY);
+    assert!((comparison.ssim - 1.0).abs() < 1e-12);
+    assert_eq!(comparison.flip, 0.0);
+    assert_eq!(comparison.max_difference, 0);
+    assert!(heatmap(&image, &image).iter().all(|&v| v == 0));
+}
+
+#[test]
+fn test_differences() {
+    let a: Vec<u8> = (0..16 * 12 * 3).map(|i| (i * 7 % 256) as u8).collect();
+    let mut b = a.clone();
+    b[0] = b[0].wrapping_add(100);
+    let noisy: Vec<u8> = a.iter().map(|v| v.saturating_add(10)).collect();
+
+    // One channel of one pixel off by 100: MSE = 100² / 576.
+    let one = compare(&a, &b, 16, 12).unwrap();
+    assert!((one.psnr - 10.0 * (255.0f64 * 255.0 * 576.0 / 10000.0).log10()).abs() < 1e-9);
+    assert_eq!(one.max_difference, 100);
+    assert!(one.ssim < 1.0);
+    assert!(compare(&a, &noisy, 16, 12).unwrap().psnr < one.psnr);
+
+    let map = heatmap(&a, &b);
+    assert_eq!(&map[..3], &[255, 255, 255]);
+    assert!(map[3..].iter().all(|&v| v == 0));
+
+    // Structure matters more than brightness: a flat image is further from
+    // `a` than a brightened copy.
+    let flat = vec![128; a.len()];
+    let (flat, noisy) = (
+        compare(&a, &flat, 16, 12).unwrap(),
+        compare(&a, &noisy, 16, 12).unwrap(),
+    );
+    assert!(flat.ssim < noisy.ssim);
+    assert!(one.flip > 0.0 && one.flip < noisy.flip && noisy.flip < flat.flip);
+    let (black, white) = (vec![0; a.len()], vec![255; a.len()]);
+    assert!(compare(&black, &white, 16, 12).unwrap().flip > 0.9);
+}
+
+#[test]
+fn test_empty() {
+    assert!(compare(&[], &[], 0, 0).is_err());
+    assert!(compare(&[], &[], 4, 0).is_err());
+}
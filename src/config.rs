@@ -1,9 +1,1421 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
 use serde::{Deserialize, Serialize};
 
 use crate::camera::Camera;
-use crate::hittable::ObjectList;
+use crate::curves::Curves;
+use crate::hittable::{ObjectList, Shape};
+use crate::light_link::ObjectSet;
+use crate::material::Material;
+use crate::mesh::MeshShape;
+use crate::migration::{self, CURRENT_VERSION};
+use crate::pbrt;
+use crate::point_cloud::PointCloud;
+use crate::post::{PostEffect, WhiteBalance};
+use crate::scenes::Generator;
+use crate::texture::MipChain;
+use crate::texture_cache::{TextureCache, TextureCacheSettings};
+use crate::volume::Volume;
+
+fn current_version() -> u32 {
+    CURRENT_VERSION
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Config {
+    #[serde(default = "current_version")]
+    pub version: u32,
     pub camera: Camera,
+    // More views of the same scene, by name, which `use_camera` switches to.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub cameras: BTreeMap<String, Camera>,
     pub object_list: ObjectList, // right now the only object it sphere
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub materials: BTreeMap<String, Material>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub generators: Vec<Generator>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post: Vec<PostEffect>,
+    // Loads image textures lazily, a tile at a time, within a memory budget.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub texture_cache: Option<TextureCacheSettings>,
+    // Render layers by name, each rendered on its own with the objects outside
+    // it as mattes, see `ObjectList::isolate`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub layers: BTreeMap<String, ObjectSet>,
+}
+
+// A scene file as written on disk. Unlike `Config` it may leave out the
+// camera and pull in other files, which are merged before it becomes a
+// `Config`.
+#[derive(Deserialize)]
+struct SceneFile {
+    camera: Option<Camera>,
+    #[serde(default)]
+    cameras: BTreeMap<String, Camera>,
+    #[serde(default)]
+    object_list: ObjectList,
+    #[serde(default)]
+    materials: BTreeMap<String, Material>,
+    #[serde(default)]
+    generators: Vec<Generator>,
+    #[serde(default)]
+    post: Vec<PostEffect>,
+    texture_cache: Option<TextureCacheSettings>,
+    #[serde(default)]
+    layers: BTreeMap<String, ObjectSet>,
+    #[serde(default)]
+    include: Vec<String>,
+    // Rhai source, either inline or in a file next to the scene file.
+    script: Option<String>,
+    script_file: Option<String>,
+}
+
+impl From<Config> for SceneFile {
+    fn from(config: Config) -> Self {
+        SceneFile {
+            camera: Some(config.camera),
+            cameras: config.cameras,
+            object_list: config.object_list,
+            materials: config.materials,
+            generators: config.generators,
+            post: config.post,
+            texture_cache: config.texture_cache,
+            layers: config.layers,
+            include: Vec::new(),
+            script: None,
+            script_file: None,
+        }
+    }
+}
+
+impl SceneFile {
+    // The palette and the materials of the objects.
+    fn materials_mut(&mut self) -> impl Iterator<Item = &mut Material> {
+        self.materials.values_mut().chain(
+            self.object_list
+                .objects
+                .iter_mut()
+                .map(|object| object.material_mut()),
+        )
+    }
+
+    // Also those inside instances.
+    fn meshes_mut(&mut self) -> impl Iterator<Item = &mut MeshShape> {
+        fn mesh(shape: &mut Shape) -> Option<&mut MeshShape> {
+            match shape {
+                Shape::Mesh(mesh) => Some(mesh),
+                Shape::Instance(instance) => mesh(&mut instance.shape),
+                _ => None,
+            }
+        }
+        self.object_list
+            .objects
+            .iter_mut()
+            .filter_map(|object| mesh(&mut object.shape))
+    }
+
+    fn curves_mut(&mut self) -> impl Iterator<Item = &mut Curves> {
+        fn curves(shape: &mut Shape) -> Option<&mut Curves> {
+            match shape {
+                Shape::Curves(curves) => Some(curves),
+                Shape::Instance(instance) => curves(&mut instance.shape),
+                _ => None,
+            }
+        }
+        self.object_list
+            .objects
+            .iter_mut()
+            .filter_map(|object| curves(&mut object.shape))
+    }
+
+    fn point_clouds_mut(&mut self) -> impl Iterator<Item = &mut PointCloud> {
+        fn cloud(shape: &mut Shape) -> Option<&mut PointCloud> {
+            match shape {
+                Shape::PointCloud(cloud) => Some(cloud),
+                Shape::Instance(instance) => cloud(&mut instance.shape),
+                _ => None,
+            }
+        }
+        self.object_list
+            .objects
+            .iter_mut()
+            .filter_map(|object| cloud(&mut object.shape))
+    }
+
+    fn volumes_mut(&mut self) -> impl Iterator<Item = &mut Volume> {
+        fn volume(shape: &mut Shape) -> Option<&mut Volume> {
+            match shape {
+                Shape::Volume(volume) => Some(volume),
+                Shape::Instance(instance) => volume(&mut instance.shape),
+                _ => None,
+            }
+        }
+        self.object_list
+            .objects
+            .iter_mut()
+            .filter_map(|object| volume(&mut object.shape))
+    }
+
+    fn read(path: &Path) -> io::Result<SceneFile> {
+        match Format::from_path(path) {
+            Format::Pbrt => return Ok(pbrt::load(path)?.into()),
+            Format::Usd => return Ok(load_usd(path)?.into()),
+            _ => {}
+        }
+
+        let mut value = read_value(path)?;
+        migration::migrate(&mut value)
+            .map_err(|e| invalid_data(format!("{}: {}", path.display(), e)))?;
+        serde_json::from_value(value).map_err(invalid_data)
+    }
+
+    fn merge(&mut self, other: SceneFile) {
+        if other.camera.is_some() {
+            self.camera = other.camera;
+        }
+        self.cameras.extend(other.cameras);
+        self.object_list
+            .portals
+            .extend(other.object_list.portals.iter().cloned());
+        self.object_list
+            .clip_planes
+            .extend(other.object_list.clip_planes.iter());
+        self.object_list.transparent_shadows |= other.object_list.transparent_shadows;
+        if let Some(backplate) = &other.object_list.backplate {
+            self.object_list.backplate = Some(backplate.clone());
+        }
+        if let Some(sun) = &other.object_list.sun {
+            self.object_list.sun = Some(sun.clone());
+        }
+        if !other.object_list.sky.is_default() {
+            self.object_list.sky = other.object_list.sky;
+        }
+        if let Some(atmosphere) = &other.object_list.atmosphere {
+            self.object_list.atmosphere = Some(atmosphere.clone());
+        }
+        if !other.object_list.environment.is_default() {
+            self.object_list.environment = other.object_list.environment;
+        }
+        self.object_list.extend(other.object_list);
+        self.materials.extend(other.materials);
+        self.generators.extend(other.generators);
+        if !other.post.is_empty() {
+            self.post = other.post;
+        }
+        if other.texture_cache.is_some() {
+            self.texture_cache = other.texture_cache;
+        }
+        self.layers.extend(other.layers);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Json,
+    Yaml,
+    Pbrt,
+    Usd,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => Format::Yaml,
+            Some("pbrt") => Format::Pbrt,
+            Some("usda" | "usd") => Format::Usd,
+            _ => Format::Json,
+        }
+    }
+}
+
+#[cfg(feature = "usd")]
+fn load_usd(path: &Path) -> io::Result<Config> {
+    crate::usd::load(path)
+}
+
+#[cfg(not(feature = "usd"))]
+fn load_usd(_path: &Path) -> io::Result<Config> {
+    Err(invalid_data(
+        "USD files need raytracer to be built with the \"usd\" feature",
+    ))
+}
+
+#[cfg(feature = "scripting")]
+fn run_script(source: &str) -> io::Result<Vec<crate::hittable::Object>> {
+    crate::script::run(source)
+}
+
+#[cfg(not(feature = "scripting"))]
+fn run_script(_source: &str) -> io::Result<Vec<crate::hittable::Object>> {
+    Err(invalid_data(
+        "the scene uses a script, but raytracer was built without the \"scripting\" feature",
+    ))
+}
+
+fn invalid_data<E>(e: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(feature = "yaml")]
+fn from_yaml(contents: &[u8]) -> io::Result<serde_json::Value> {
+    migration::yaml_to_json(serde_yaml::from_slice(contents).map_err(invalid_data)?)
+}
+
+#[cfg(feature = "yaml")]
+fn to_yaml<T: Serialize>(value: &T) -> io::Result<String> {
+    serde_yaml::to_string(value).map_err(invalid_data)
+}
+
+#[cfg(not(feature = "yaml"))]
+fn from_yaml(_contents: &[u8]) -> io::Result<serde_json::Value> {
+    Err(invalid_data(
+        "YAML needs raytracer to be built with the \"yaml\" feature",
+    ))
+}
+
+#[cfg(not(feature = "yaml"))]
+fn to_yaml<T: Serialize>(_value: &T) -> io::Result<String> {
+    Err(invalid_data(
+        "YAML needs raytracer to be built with the \"yaml\" feature",
+    ))
+}
+
+// Reads a JSON or YAML scene file without interpreting it.
+pub fn read_value(path: &Path) -> io::Result<serde_json::Value> {
+    let contents = fs::read(path)?;
+    match Format::from_path(path) {
+        Format::Yaml => from_yaml(&contents),
+        Format::Json => serde_json::from_slice(&contents).map_err(invalid_data),
+        Format::Pbrt => Err(invalid_data("PBRT files have no schema version")),
+        Format::Usd => Err(invalid_data("USD files have no schema version")),
+    }
+}
+
+// Rewrites a scene file in the current schema version and returns the version
+// it was written in.
+pub fn migrate_file(input: &Path, output: &Path) -> io::Result<u32> {
+    let mut value = read_value(input)?;
+    let version = migration::migrate(&mut value)?;
+    let contents = match Format::from_path(output) {
+        Format::Yaml => to_yaml(&value)?,
+        Format::Json => serde_json::to_string_pretty(&value).map_err(invalid_data)?,
+        Format::Pbrt => return Err(invalid_data("exporting to PBRT is not supported")),
+        Format::Usd => return Err(invalid_data("exporting to USD is not supported")),
+    };
+    fs::write(output, contents)?;
+    Ok(version)
+}
+
+// Loads a scene file together with everything it includes and keeps track of
+// all files that were read.
+#[derive(Default)]
+struct Loader {
+    stack: Vec<PathBuf>,
+    files: Vec<PathBuf>,
+}
+
+impl Loader {
+    // Included files are merged in order, then the including file is applied
+    // on top: its objects come last, its materials and camera win.
+    fn load(&mut self, path: &Path) -> io::Result<SceneFile> {
+        let canonical = path.canonicalize()?;
+        if self.stack.contains(&canonical) {
+            return Err(invalid_data(format!("{} includes itself", path.display())));
+        }
+        self.stack.push(canonical.clone());
+        self.files.push(canonical);
+
+        let mut file = SceneFile::read(path)?;
+        let base_dir = path.parent().unwrap_or(Path::new("."));
+        self.run_scripts(&mut file, base_dir)?;
+        self.load_luts(&mut file, base_dir)?;
+        self.load_light_files(&mut file, base_dir)?;
+        if let Some(backplate) = &mut file.object_list.backplate {
+            self.files.push(backplate.load(base_dir)?.canonicalize()?);
+        }
+        self.resolve_textures(&mut file, base_dir)?;
+        self.load_meshes(&mut file, base_dir)?;
+        self.load_curves(&mut file, base_dir)?;
+        self.load_point_clouds(&mut file, base_dir)?;
+        self.load_volumes(&mut file, base_dir)?;
+        let mut merged = SceneFile {
+            camera: None,
+            cameras: BTreeMap::new(),
+            object_list: ObjectList::new(),
+            materials: BTreeMap::new(),
+            generators: Vec::new(),
+            post: Vec::new(),
+            texture_cache: None,
+            layers: BTreeMap::new(),
+            include: Vec::new(),
+            script: None,
+            script_file: None,
+        };
+        for include in &file.include {
+            let included = self.load(&base_dir.join(include))?;
+            merged.merge(included);
+        }
+        merged.merge(file);
+
+        self.stack.pop();
+        Ok(merged)
+    }
+
+    fn load_luts(&mut self, file: &mut SceneFile, base_dir: &Path) -> io::Result<()> {
+        for effect in &mut file.post {
+            if let PostEffect::Lut(lut) = effect {
+                self.files.push(lut.load(base_dir)?.canonicalize()?);
+            }
+        }
+        Ok(())
+    }
+
+    // IES profiles and gobo images of lights.
+    fn load_light_files(&mut self, file: &mut SceneFile, base_dir: &Path) -> io::Result<()> {
+        for material in file.materials_mut() {
+            let Material::DiffuseLight(light) = material else {
+                continue;
+            };
+            if let Some(ies) = &mut light.ies {
+                self.files.push(ies.load(base_dir)?.canonicalize()?);
+            }
+            if let Some(gobo) = &mut light.gobo {
+                self.files.push(gobo.load(base_dir)?.canonicalize()?);
+            }
+        }
+        Ok(())
+    }
+
+    fn load_meshes(&mut self, file: &mut SceneFile, base_dir: &Path) -> io::Result<()> {
+        for mesh in file.meshes_mut() {
+            for path in mesh.load(base_dir)? {
+                self.files.push(path.canonicalize()?);
+            }
+        }
+        Ok(())
+    }
+
+    // Hair files; strands in the scene need nothing loaded.
+    fn load_curves(&mut self, file: &mut SceneFile, base_dir: &Path) -> io::Result<()> {
+        for curves in file.curves_mut() {
+            if let Some(path) = curves.load(base_dir)? {
+                self.files.push(path.canonicalize()?);
+            }
+        }
+        Ok(())
+    }
+
+    fn load_point_clouds(&mut self, file: &mut SceneFile, base_dir: &Path) -> io::Result<()> {
+        for cloud in file.point_clouds_mut() {
+            self.files.push(cloud.load(base_dir)?.canonicalize()?);
+        }
+        Ok(())
+    }
+
+    // Grid files; constant and inline grids need nothing loaded.
+    fn load_volumes(&mut self, file: &mut SceneFile, base_dir: &Path) -> io::Result<()> {
+        for volume in file.volumes_mut() {
+            for grid in volume.grids_mut() {
+                if let Some(path) = grid.load(base_dir)? {
+                    self.files.push(path.canonicalize()?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Only finds the images; they are loaded once it is known whether the
+    // whole scene uses a texture cache.
+    fn resolve_textures(&mut self, file: &mut SceneFile, base_dir: &Path) -> io::Result<()> {
+        for texture in file.materials_mut().flat_map(Material::textures_mut) {
+            let path = texture.resolve(base_dir);
+            let canonical = path
+                .canonicalize()
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+            self.files.push(canonical);
+        }
+        Ok(())
+    }
+
+    fn run_scripts(&mut self, file: &mut SceneFile, base_dir: &Path) -> io::Result<()> {
+        let mut sources = Vec::new();
+        if let Some(script_file) = file.script_file.take() {
+            let path = base_dir.join(script_file);
+            sources.push(fs::read_to_string(&path)?);
+            self.files.push(path.canonicalize()?);
+        }
+        sources.extend(file.script.take());
+
+        for source in sources {
+            file.object_list.extend(run_script(&source)?);
+        }
+        Ok(())
+    }
+}
+
+// Materials sharing an image share its mip chain, and with a texture cache
+// its tiles.
+fn load_textures(file: &mut SceneFile) -> io::Result<()> {
+    let cache = file
+        .texture_cache
+        .map(|settings| Arc::new(TextureCache::new(settings)));
+    let mut loaded: HashMap<PathBuf, Arc<MipChain>> = HashMap::new();
+    for texture in file.materials_mut().flat_map(Material::textures_mut) {
+        let path = texture
+            .path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(&texture.file));
+        if let Some(mips) = loaded.get(&path) {
+            texture.mips = Some(mips.clone());
+            continue;
+        }
+        texture.load(cache.as_ref())?;
+        loaded.insert(path, texture.mips.clone().unwrap());
+    }
+    Ok(())
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> io::Result<Config> {
+        Ok(Config::from_file_with_dependencies(path)?.0)
+    }
+
+    // Also returns every file the scene was assembled from, starting with
+    // `path` itself.
+    pub fn from_file_with_dependencies(path: &Path) -> io::Result<(Config, Vec<PathBuf>)> {
+        let _span = tracing::info_span!("load scene", path = %path.display()).entered();
+        let mut loader = Loader::default();
+        let file = loader.load(path)?;
+        let config = Config::from_scene_file(file, &path.display().to_string())?;
+        tracing::info!(
+            files = loader.files.len(),
+            objects = config.object_list.objects.len(),
+            "scene loaded"
+        );
+        Ok((config, loader.files))
+    }
+
+    // Reads a scene from a JSON string, for when there is no file system. The
+    // scene can't refer to other files, but inline scripts work.
+    pub fn from_json(src: &str) -> io::Result<Config> {
+        let mut value = serde_json::from_str(src).map_err(invalid_data)?;
+        migration::migrate(&mut value)?;
+        let mut file: SceneFile = serde_json::from_value(value).map_err(invalid_data)?;
+
+        if !file.include.is_empty() || file.script_file.is_some() {
+            return Err(invalid_data(
+                "includes and script files need a scene file on disk",
+            ));
+        }
+        if file.post.iter().any(|e| matches!(e, PostEffect::Lut(_))) {
+            return Err(invalid_data("LUT files need a scene file on disk"));
+        }
+        let has_files = |m: &mut Material| matches!(m, Material::DiffuseLight(d) if d.ies.is_some() || d.gobo.is_some());
+        if file.materials_mut().any(has_files) {
+            return Err(invalid_data(
+                "IES files and gobo images need a scene file on disk",
+            ));
+        }
+        if file
+            .materials_mut()
+            .any(|m| m.textures_mut().next().is_some())
+        {
+            return Err(invalid_data("textures need a scene file on disk"));
+        }
+        if file.object_list.backplate.is_some() {
+            return Err(invalid_data("backplate images need a scene file on disk"));
+        }
+        if file.meshes_mut().next().is_some() {
+            return Err(invalid_data("meshes need a scene file on disk"));
+        }
+        if file.curves_mut().any(|curves| curves.file.is_some()) {
+            return Err(invalid_data("hair files need a scene file on disk"));
+        }
+        if file.point_clouds_mut().next().is_some() {
+            return Err(invalid_data("point clouds need a scene file on disk"));
+        }
+        if file
+            .volumes_mut()
+            .any(|volume| volume.grids_mut().any(|grid| grid.file.is_some()))
+        {
+            return Err(invalid_data("volume grid files need a scene file on disk"));
+        }
+        if let Some(source) = file.script.take() {
+            file.object_list.extend(run_script(&source)?);
+        }
+        Config::from_scene_file(file, "the scene")
+    }
+
+    fn from_scene_file(mut file: SceneFile, name: &str) -> io::Result<Config> {
+        // Without a camera of its own a scene starts out looking through the
+        // first of its named ones.
+        let camera = file
+            .camera
+            .or_else(|| file.cameras.values().next().copied())
+            .ok_or_else(|| invalid_data(format!("{} has no camera", name)))?;
+        load_textures(&mut file)?;
+        for volume in file.volumes_mut() {
+            volume.check()?;
+        }
+        let mut config = Config {
+            version: CURRENT_VERSION,
+            camera,
+            cameras: file.cameras,
+            object_list: file.object_list,
+            materials: file.materials,
+            generators: file.generators,
+            post: file.post,
+            texture_cache: file.texture_cache,
+            layers: file.layers,
+        };
+        config.expand_generators();
+        if let Some(sun) = &mut config.object_list.sun {
+            sun.resolve()?;
+        } else if config.object_list.atmosphere.is_some() {
+            return Err(invalid_data(format!(
+                "{} has an atmosphere but no sun",
+                name
+            )));
+        }
+        config.resolve_materials()?;
+        config.resolve_light_units()?;
+        Ok(config)
+    }
+
+    // Makes the named camera the one the scene is rendered with.
+    pub fn use_camera(&mut self, name: &str) -> io::Result<()> {
+        self.camera = *self.cameras.get(name).ok_or_else(|| {
+            let names: Vec<&str> = self.cameras.keys().map(String::as_str).collect();
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "unknown camera \"{}\", the scene has: {}",
+                    name,
+                    names.join(", ")
+                ),
+            )
+        })?;
+        Ok(())
+    }
+
+    // Returns the problems that would make the scene fail or render garbage.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let camera = &self.camera;
+        if camera.width() == 0 || camera.height() == 0 {
+            problems.push(format!(
+                "image size {}x{} is empty",
+                camera.width(),
+                camera.height()
+            ));
+        }
+        if camera.samples_per_pixel() == 0 {
+            problems.push("samples_per_pixel must be at least 1".to_string());
+        }
+        if !(camera.vfov() > 0.0 && camera.vfov() < 180.0) {
+            problems.push(format!("vfov {} is not between 0 and 180", camera.vfov()));
+        }
+        let view = camera.lookat() - camera.lookfrom();
+        if view.length() == 0.0 {
+            problems.push("lookfrom and lookat are the same point".to_string());
+        } else if view.cross(&camera.vup()).length() == 0.0 {
+            problems.push("vup is parallel to the viewing direction".to_string());
+        }
+        if camera.focus_dist() <= 0.0 {
+            problems.push(format!(
+                "focus_dist {} must be positive",
+                camera.focus_dist()
+            ));
+        }
+        if let Some(white_balance) = &camera.white_balance {
+            let range = WhiteBalance::TEMPERATURES;
+            if !range.contains(&white_balance.temperature) {
+                problems.push(format!(
+                    "white balance temperature {} K is not between {} and {} K",
+                    white_balance.temperature,
+                    range.start(),
+                    range.end()
+                ));
+            }
+        }
+        if let Some(stereo) = &camera.stereo {
+            if stereo.interocular < 0.0 {
+                problems.push(format!(
+                    "stereo interocular {} must not be negative",
+                    stereo.interocular
+                ));
+            }
+            if stereo.convergence <= 0.0 {
+                problems.push(format!(
+                    "stereo convergence {} must be positive",
+                    stereo.convergence
+                ));
+            }
+        }
+
+        for (i, plane) in self.object_list.clip_planes.iter().enumerate() {
+            if plane.normal.near_zero() {
+                problems.push(format!("clip plane {} has no normal", i));
+            }
+        }
+
+        for (name, layer) in &self.layers {
+            if !self
+                .object_list
+                .iter()
+                .any(|object| layer.contains(&object.attributes))
+            {
+                problems.push(format!("render layer \"{}\" has no objects", name));
+            }
+        }
+
+        for (i, object) in self.object_list.objects.iter().enumerate() {
+            if object
+                .attributes
+                .clip_planes
+                .iter()
+                .any(|plane| plane.normal.near_zero())
+            {
+                problems.push(format!("object {} has a clip plane without a normal", i));
+            }
+            match &object.shape {
+                Shape::Sphere(sphere) if sphere.radius <= 0.0 => {
+                    problems.push(format!("object {} has a non-positive radius", i));
+                }
+                Shape::Capsule(capsule) if capsule.radius <= 0.0 => {
+                    problems.push(format!("object {} has a non-positive radius", i));
+                }
+                Shape::Instance(instance)
+                    if instance.scale.x() * instance.scale.y() * instance.scale.z() == 0.0 =>
+                {
+                    problems.push(format!("object {} is scaled to zero", i));
+                }
+                Shape::Mesh(mesh)
+                    if mesh
+                        .triangles
+                        .as_ref()
+                        .is_some_and(|t| t.mesh.triangles.is_empty()) =>
+                {
+                    problems.push(format!("object {} is a mesh without faces", i));
+                }
+                Shape::Sdf(sdf) if !sdf.shape.is_valid() => {
+                    problems.push(format!(
+                        "object {} has a size, rounding or exponent out of range",
+                        i
+                    ));
+                }
+                Shape::Quadric(quadric) if quadric.is_clipped_away() => {
+                    problems.push(format!("object {} is a quadric clipped to an empty box", i));
+                }
+                Shape::Curves(curves) if curves.strand_count() == 0 => {
+                    problems.push(format!("object {} has no strands", i));
+                }
+                Shape::PointCloud(cloud)
+                    if cloud
+                        .points
+                        .as_ref()
+                        .is_some_and(|points| points.positions.is_empty()) =>
+                {
+                    problems.push(format!("object {} is a point cloud without points", i));
+                }
+                _ => {}
+            }
+            if let Material::Named(name) = object.material() {
+                problems.push(format!("object {} uses unknown material \"{}\"", i, name));
+            }
+            if let Material::DiffuseLight(light) = object.material() {
+                if light.watts.is_some() && light.lumens.is_some() {
+                    problems.push(format!("object {} has a light in both watts and lumens", i));
+                }
+            }
+        }
+        problems
+    }
+
+    // Replaces references to the material palette with the materials themselves.
+    pub fn resolve_materials(&mut self) -> io::Result<()> {
+        for object in &mut self.object_list.objects {
+            let material = object.material_mut();
+            if let Material::Named(name) = material {
+                *material = self
+                    .materials
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| invalid_data(format!("unknown material \"{}\"", name)))?;
+            }
+        }
+        Ok(())
+    }
+
+    // Lights given in watts or lumens need the area of their object.
+    pub fn resolve_light_units(&mut self) -> io::Result<()> {
+        for (i, object) in self.object_list.objects.iter_mut().enumerate() {
+            let area = object.shape.area();
+            if let Material::DiffuseLight(light) = object.material_mut() {
+                if light.power_in_lumens().is_some() {
+                    light.area = Some(area.ok_or_else(|| {
+                        invalid_data(format!(
+                            "object {} has a light in watts or lumens but no known area",
+                            i
+                        ))
+                    })?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn expand_generators(&mut self) {
+        for generator in self.generators.drain(..) {
+            self.object_list.extend(generator.generate());
+        }
+    }
+
+    pub fn to_json(&self) -> io::Result<String> {
+        serde_json::to_string_pretty(self).map_err(invalid_data)
+    }
+
+    // 64-bit FNV-1a of the scene as JSON, which is the same for every file
+    // describing the same scene, however it is formatted or split up.
+    pub fn hash(&self) -> io::Result<u64> {
+        Ok(self
+            .to_json()?
+            .bytes()
+            .fold(0xcbf29ce484222325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+            }))
+    }
+
+    pub fn to_yaml(&self) -> io::Result<String> {
+        to_yaml(self)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let contents = match Format::from_path(path) {
+            Format::Json => self.to_json()?,
+            Format::Yaml => self.to_yaml()?,
+            Format::Pbrt => return Err(invalid_data("exporting to PBRT is not supported")),
+            Format::Usd => return Err(invalid_data("exporting to USD is not supported")),
+        };
+        fs::write(path, contents)
+    }
+}
+
+// `scene` with the camera of `test_camera_json` at z = `z` added in front,
+// leaving the rest as written so tests can still edit it by replacing text.
+#[cfg(test)]
+fn test_scene_json(z: f64, scene: &str) -> String {
+    let rest = scene.trim_start().strip_prefix('{').expect("a JSON object");
+    let camera = crate::camera::test_camera_json([0.0, 0.0, z]);
+    format!("{{\"camera\": {}, {}", camera, rest)
+}
+
+// A directory for a scene and the files it reads, removed again when the
+// test ends, also when it fails.
+#[cfg(test)]
+struct TestDir(PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    fn new(name: &str) -> TestDir {
+        let dir = std::env::temp_dir().join(format!("raytracer-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        TestDir(dir)
+    }
+
+    fn path(&self, file: &str) -> PathBuf {
+        self.0.join(file)
+    }
+
+    fn write(&self, file: &str, contents: impl AsRef<[u8]>) {
+        fs::write(self.path(file), contents).unwrap();
+    }
+
+    // Loads `scene` from scene.json in the directory, after checking that
+    // it can't be loaded without the files next to it.
+    fn load(&self, scene: &str) -> (Config, Vec<PathBuf>) {
+        assert!(Config::from_json(scene).is_err());
+        self.write("scene.json", scene);
+        Config::from_file_with_dependencies(&self.path("scene.json")).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[test]
+fn test_json_round_trip() {
+    let config = crate::scenes::random_spheres(7);
+    let json = config.to_json().unwrap();
+    let parsed: Config = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.to_json().unwrap(), json);
+    assert_eq!(parsed.camera, config.camera);
+    assert_eq!(
+        parsed.object_list.objects.len(),
+        config.object_list.objects.len()
+    );
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn test_yaml_round_trip() {
+    let config = crate::scenes::random_spheres(7);
+    let yaml = config.to_yaml().unwrap();
+    let value = migration::yaml_to_json(serde_yaml::from_str(&yaml).unwrap()).unwrap();
+    let parsed: Config = serde_json::from_value(value).unwrap();
+    assert_eq!(parsed.to_yaml().unwrap(), yaml);
+    assert_eq!(parsed.camera, config.camera);
+}
+
+#[test]
+fn test_expand_generators() {
+    let json = test_scene_json(
+        0.0,
+        r#"{
+            "object_list": {"objects": []},
+            "generators": [{"random_spheres": {"count": 4, "feature_spheres": false}}]
+        }"#,
+    );
+    let mut config: Config = serde_json::from_str(&json).unwrap();
+    config.expand_generators();
+    assert!(config.generators.is_empty());
+    assert_eq!(config.object_list.objects.len(), 4);
+}
+
+#[test]
+fn test_named_cameras() {
+    let camera = |z: f64| crate::camera::test_camera_json([0.0, 0.0, z]);
+    let json = format!(
+        r#"{{"cameras": {{"wide": {}, "closeup": {}}}, "object_list": {{"objects": []}}}}"#,
+        camera(10.0),
+        camera(2.0)
+    );
+    let mut config = Config::from_json(&json).unwrap();
+    // Sorted by name, so "closeup" comes first.
+    assert_eq!(config.camera.lookfrom().z(), 2.0);
+    config.use_camera("wide").unwrap();
+    assert_eq!(config.camera.lookfrom().z(), 10.0);
+    let error = config.use_camera("hero").unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert!(error.to_string().contains("closeup, wide"));
+
+    let saved = Config::from_json(&config.to_json().unwrap()).unwrap();
+    assert_eq!(saved.cameras.len(), 2);
+    assert_eq!(saved.camera.lookfrom().z(), 10.0);
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn test_include() {
+    let dir = TestDir::new("include");
+    dir.write(
+        "props.yaml",
+        r#"
+materials:
+  red: !Lambertian
+    albedo: [1.0, 0.0, 0.0]
+object_list:
+  objects:
+    - !Sphere
+      center: { x: 0.0, y: 0.0, z: -1.0 }
+      radius: 0.5
+      material: !Named red
+"#,
+    );
+    dir.write("loop.json", r#"{"include": ["loop.json"]}"#);
+
+    let (config, files) = dir.load(&test_scene_json(
+        0.0,
+        r#"{
+            "include": ["props.yaml"],
+            "object_list": {"objects": [
+                {"Sphere": {"center": {"x": 0.0, "y": -100.5, "z": -1.0}, "radius": 100.0,
+                            "material": {"Named": "red"}}}
+            ]}
+        }"#,
+    ));
+    assert_eq!(config.object_list.objects.len(), 2);
+    assert_eq!(files.len(), 2);
+    assert!(files[1].ends_with("props.yaml"));
+    assert_eq!(config.camera.width(), 10);
+    assert!(config.validate().is_empty());
+    assert!(config
+        .object_list
+        .objects
+        .iter()
+        .all(|object| matches!(object.material(), Material::Lambertian(_))));
+
+    assert!(Config::from_file(&dir.path("props.yaml")).is_err());
+    assert!(Config::from_file(&dir.path("loop.json")).is_err());
+}
+
+#[test]
+fn test_from_json() {
+    let scene = crate::scenes::random_spheres(3).to_json().unwrap();
+    let config = Config::from_json(&scene).unwrap();
+    assert_eq!(config.camera.width(), 1200);
+    assert!(!config.object_list.objects.is_empty());
+
+    let include = r#"{"include": ["other.json"], "camera": {}}"#;
+    assert!(Config::from_json(include).is_err());
+
+    // An atmosphere needs a sun to light it.
+    let mut value: serde_json::Value = serde_json::from_str(&scene).unwrap();
+    value["object_list"]["atmosphere"] = serde_json::json!({"altitude": 10});
+    assert!(Config::from_json(&value.to_string()).is_err());
+    value["object_list"]["sun"] = serde_json::json!({"direction": [0, 1, 0]});
+    let config = Config::from_json(&value.to_string()).unwrap();
+    assert_eq!(config.object_list.atmosphere.unwrap().altitude, 10.0);
+
+    // Opacity masks are textures too and aren't looked for on disk.
+    let mut value: serde_json::Value = serde_json::from_str(&scene).unwrap();
+    value["object_list"]["objects"]
+        .as_array_mut()
+        .unwrap()
+        .push(serde_json::json!({"Sphere": {
+            "center": [0.0, 0.0, 0.0], "radius": 1.0,
+            "material": {"Lambertian": {"albedo": [0.5, 0.5, 0.5],
+                                        "opacity": {"file": "leaf.png"}}}
+        }}));
+    let Err(err) = Config::from_json(&value.to_string()) else {
+        panic!("an opacity mask loaded without a scene file");
+    };
+    assert!(
+        err.to_string().contains("textures need a scene file"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_light_units() {
+    use crate::color::luminance;
+    use crate::material::Scatterable;
+    use crate::ray::Ray;
+
+    let scene = &test_scene_json(
+        0.0,
+        r#"{
+        "materials": {
+            "bulb": {"DiffuseLight": {"emit": [1.0, 0.5, 0.2], "lumens": 800.0}}
+        },
+        "object_list": {"objects": [
+            {"Sphere": {"center": [0.0, 0.0, -2.0], "radius": 0.5, "material": {"Named": "bulb"}}},
+            {"Sphere": {"center": [2.0, 0.0, -2.0], "radius": 1.0, "material": {"Named": "bulb"}}},
+            {"Instance": {"shape": {"Sphere": {"center": [0.0, 0.0, 0.0], "radius": 0.5,
+                                               "material": {"DiffuseLight": {"emit": [1.0, 1.0, 1.0], "watts": 2.0}}}},
+                          "scale": [2.0, 2.0, 2.0]}}
+        ]}
+    }"#,
+    );
+    let config = Config::from_json(scene).unwrap();
+    assert!(config.validate().is_empty());
+    let objects = &config.object_list.objects;
+
+    // Both spheres send out 800 lm, spread over different areas.
+    for object in &objects[..2] {
+        let emitted = object.material().emitted(&Ray::default());
+        let area = object.shape.area().unwrap();
+        let lumens = luminance(emitted) * std::f64::consts::PI * area;
+        assert!((lumens - 800.0).abs() < 1e-9);
+        assert!((emitted.y() / emitted.x() - 0.5).abs() < 1e-12);
+    }
+    let scaled = objects[2].material().emitted(&Ray::default());
+    let area = 4.0 * std::f64::consts::PI;
+    assert!((luminance(scaled) * std::f64::consts::PI * area - 2.0 * 683.0).abs() < 1e-9);
+
+    // Stretched spheres have no simple area.
+    let stretched = scene.replace("\"scale\": [2.0, 2.0, 2.0]", "\"scale\": [2.0, 1.0, 1.0]");
+    assert!(Config::from_json(&stretched).is_err());
+}
+
+#[test]
+fn test_ies_profile() {
+    use crate::color::Color;
+    use crate::material::Scatterable;
+    use crate::ray::Ray;
+    use crate::vec3::{Point3D, Vec3};
+
+    let dir = TestDir::new("ies");
+    dir.write(
+        "spot.ies",
+        "IESNA:LM-63-2002\nTILT=NONE\n1 1000 1 3 1 1 2 0 0 0\n1 1 10\n0 30 60\n0\n500 250 0\n",
+    );
+    let (config, files) = dir.load(&test_scene_json(
+        0.0,
+        r#"{
+        "object_list": {"objects": [
+            {"Sphere": {"center": [0.0, 3.0, 0.0], "radius": 0.1,
+                        "material": {"DiffuseLight": {"emit": [4.0, 4.0, 4.0], "ies": {"file": "spot.ies"}}}}}
+        ]}
+    }"#,
+    ));
+    assert!(files[1].ends_with("spot.ies"));
+    let light = config.object_list.objects[0].material();
+    let origin = Point3D::new(0.0, 0.0, 0.0);
+    // Hit from straight below, from 30 degrees off the axis and from above.
+    let below = light.emitted(&Ray::new(origin, Vec3::new(0.0, 1.0, 0.0)));
+    let off_axis = light.emitted(&Ray::new(origin, Vec3::new(-0.5, 0.75f64.sqrt(), 0.0)));
+    let above = light.emitted(&Ray::new(origin, Vec3::new(0.0, -1.0, 0.0)));
+    assert_eq!(below, Color::new(4.0, 4.0, 4.0));
+    assert!((off_axis - Color::new(2.0, 2.0, 2.0)).length() < 1e-9);
+    assert_eq!(above, Color::default());
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn test_texture() {
+    use crate::color::Color;
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::interval::Interval;
+    use crate::material::Scatterable;
+    use crate::ray::Ray;
+    use crate::vec3::{Point3D, Vec3};
+
+    let dir = TestDir::new("texture");
+    // Red on the left half, blue on the right.
+    crate::film::write_image(
+        &dir.path("map.png").to_string_lossy(),
+        &[255, 0, 0, 0, 0, 255],
+        (2, 1),
+    )
+    .unwrap();
+    let (config, files) = dir.load(&test_scene_json(
+        0.0,
+        r#"{
+        "object_list": {"objects": [
+            {"Sphere": {"center": [0.0, 0.0, 0.0], "radius": 1.0,
+                        "material": {"Lambertian": {"albedo": [0.5, 0.5, 0.5], "texture": {"file": "map.png"}}}}}
+        ]}
+    }"#,
+    ));
+    assert!(files[1].ends_with("map.png"));
+    // The +z side of the sphere is at u = 1/4, the -z side at u = 3/4.
+    let albedo = |config: &Config, z: f64| {
+        let r = Ray::new(Point3D::new(0.0, 0.0, 5.0 * z), Vec3::new(0.0, 0.0, -z));
+        let mut rec = HitRecord::default();
+        assert!(config
+            .object_list
+            .hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec));
+        let mut attenuation = Color::default();
+        let mut scattered = Ray::default();
+        rec.mat.scatter(&r, &rec, &mut attenuation, &mut scattered);
+        attenuation
+    };
+    assert!((albedo(&config, 1.0) - Color::new(0.5, 0.0, 0.0)).length() < 1e-9);
+    assert!((albedo(&config, -1.0) - Color::new(0.0, 0.0, 0.5)).length() < 1e-9);
+
+    // The same colors through a texture cache, set in an including file.
+    dir.write(
+        "cached.json",
+        r#"{"include": ["scene.json"], "texture_cache": {"memory_mb": 1}}"#,
+    );
+    let config = Config::from_file(&dir.path("cached.json")).unwrap();
+    assert_eq!(config.texture_cache.unwrap().memory_mb, 1);
+    assert!((albedo(&config, 1.0) - Color::new(0.5, 0.0, 0.0)).length() < 1e-6);
+    assert!((albedo(&config, -1.0) - Color::new(0.0, 0.0, 0.5)).length() < 1e-6);
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn test_mesh() {
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::interval::Interval;
+    use crate::ray::Ray;
+    use crate::vec3::{Point3D, Vec3};
+
+    let dir = TestDir::new("mesh");
+    dir.write(
+        "quad.obj",
+        "v -1 -1 0\nv 1 -1 0\nv 1 1 0\nv -1 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 2/2 3/3 4/4\n",
+    );
+    dir.write(
+        "small.obj",
+        "v -0.1 -0.1 0\nv 0.1 -0.1 0\nv 0.1 0.1 0\nv -0.1 0.1 0\nf 1 2 3 4\n",
+    );
+    // All white: the whole quad moves up by the scale.
+    crate::film::write_image(
+        &dir.path("height.png").to_string_lossy(),
+        &[255; 12],
+        (2, 2),
+    )
+    .unwrap();
+    let (mut config, files) = dir.load(&test_scene_json(
+        5.0,
+        r#"{
+        "object_list": {"objects": [
+            {"Mesh": {"file": "quad.obj", "material": {"Lambertian": {"albedo": [0.5, 0.5, 0.5]}},
+                      "displacement": {"file": "height.png", "scale": 0.5}}},
+            {"Instance": {"shape": {"Mesh": {"file": "quad.obj", "material": {"Lambertian": {"albedo": [0.5, 0.5, 0.5]}},
+                                             "lods": [{"file": "small.obj", "distance": 8.0}]}},
+                          "translate": [10.0, 0.0, 0.0]}}
+        ]}
+    }"#,
+    ));
+    assert!(files[1].ends_with("quad.obj") && files[2].ends_with("height.png"));
+    assert!(files[4].ends_with("small.obj"));
+    assert!(config.validate().is_empty());
+    let t = |config: &Config, x: f64| {
+        let r = Ray::new(Point3D::new(x, 0.05, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::default();
+        config
+            .object_list
+            .hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec)
+            .then_some(rec.t)
+    };
+    assert!((t(&config, 0.2).unwrap() - 4.5).abs() < 1e-9);
+    assert!((t(&config, 10.2).unwrap() - 5.0).abs() < 1e-9);
+    assert_eq!(t(&config, 5.0), None);
+
+    // From the camera the instance is far enough for its small version.
+    config.object_list.select_lods(config.camera.lookfrom());
+    assert!((t(&config, 0.2).unwrap() - 4.5).abs() < 1e-9);
+    assert_eq!(t(&config, 10.2), None);
+    assert!((t(&config, 10.05).unwrap() - 5.0).abs() < 1e-9);
+}
+
+#[test]
+fn test_curves() {
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::interval::Interval;
+    use crate::ray::Ray;
+    use crate::vec3::{Point3D, Vec3};
+
+    let scene = |curves: &str| {
+        let objects = format!(
+            r#"{{"object_list": {{"objects": [
+                {{"Curves": {{{}, "width": 0.1, "material": {{"Hair": {{"color": [0.6, 0.4, 0.2]}}}}}}}}
+            ]}}}}"#,
+            curves
+        );
+        test_scene_json(5.0, &objects)
+    };
+    let hit = |config: &Config| {
+        let r = Ray::new(Point3D::new(0.5, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::default();
+        config
+            .object_list
+            .hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec)
+            .then_some(rec.t)
+    };
+
+    let inline = scene(r#""strands": [{"points": [[0, 0, 0], [1, 0, 0], [2, 0, 0], [3, 0, 0]]}]"#);
+    let config = Config::from_json(&inline).unwrap();
+    assert!(config.validate().is_empty());
+    assert!((hit(&config).unwrap() - 5.0).abs() < 1e-9);
+    let Material::Hair(hair) = config.object_list.objects[0].material() else {
+        panic!("not hair");
+    };
+    assert_eq!(hair.roughness, 0.3);
+    let empty = Config::from_json(&scene(r#""strands": []"#)).unwrap();
+    assert_eq!(
+        empty.validate(),
+        vec!["object 0 has no strands".to_string()]
+    );
+
+    // The same strand from a .hair file, as a B-spline through its points.
+    let dir = TestDir::new("curves");
+    let mut hair = b"HAIR".to_vec();
+    for value in [1u32, 4, 2, 3] {
+        hair.extend(value.to_le_bytes());
+    }
+    hair.resize(128, 0);
+    for x in 0..4 {
+        for c in [x as f32, 0.0, 0.0] {
+            hair.extend(c.to_le_bytes());
+        }
+    }
+    dir.write("strand.hair", hair);
+    let (config, files) = dir.load(&scene(
+        r#""file": "strand.hair", "basis": "bspline", "mode": "round""#,
+    ));
+    assert!(files[1].ends_with("strand.hair"));
+    assert!((hit(&config).unwrap() - 4.95).abs() < 1e-4);
+}
+
+#[test]
+fn test_point_cloud() {
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::interval::Interval;
+    use crate::ray::Ray;
+    use crate::vec3::{Point3D, Vec3};
+
+    let dir = TestDir::new("points");
+    dir.write(
+        "scan.ply",
+        "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\nproperty float z\nend_header\n0 0 0\n10 0 0\n",
+    );
+    let (config, files) = dir.load(&test_scene_json(
+        5.0,
+        r#"{
+        "object_list": {"objects": [
+            {"PointCloud": {"file": "scan.ply", "radius": 0.5, "splat": "sphere",
+                            "material": {"Lambertian": {"albedo": [0.5, 0.5, 0.5]}}}}
+        ]}
+    }"#,
+    ));
+    assert!(files[1].ends_with("scan.ply"));
+    assert!(config.validate().is_empty());
+    let r = Ray::new(Point3D::new(10.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    let mut rec = HitRecord::default();
+    assert!(config
+        .object_list
+        .hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec));
+    assert!((rec.t - 4.5).abs() < 1e-12);
+}
+
+#[test]
+fn test_volume() {
+    use crate::color::Color;
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::interval::Interval;
+    use crate::material::Scatterable;
+    use crate::ray::Ray;
+    use crate::vec3::{Point3D, Vec3};
+
+    let dir = TestDir::new("volume");
+    let temperatures: Vec<u8> = [1500.0f32, 300.0]
+        .iter()
+        .flat_map(|t| t.to_le_bytes())
+        .collect();
+    dir.write("fire.raw", temperatures);
+    let scene = test_scene_json(
+        5.0,
+        r#"{
+        "object_list": {"objects": [
+            {"Volume": {"min": [-1.0, -1.0, -1.0], "max": [1.0, 1.0, 1.0],
+                        "density": {"resolution": [1, 1, 2], "values": [1000.0, 1000.0]},
+                        "emission": {"temperature": {"resolution": [2, 1, 1], "file": "fire.raw"}},
+                        "material": {"Medium": {"albedo": [0.0, 0.0, 0.0]}}}}
+        ]}
+    }"#,
+    );
+    let (config, files) = dir.load(&scene);
+    assert!(files[1].ends_with("fire.raw"));
+    assert!(config.validate().is_empty());
+    // The hot half glows, the cold one doesn't.
+    let glow = |x: f64| {
+        let r = Ray::new(Point3D::new(x, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::default();
+        assert!(config
+            .object_list
+            .hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec));
+        rec.mat.emitted(&r)
+    };
+    assert!(glow(-0.9).x() > 0.5);
+    assert_eq!(glow(0.9), Color::default());
+
+    dir.write("scene.json", scene.replace("[1000.0, 1000.0]", "[1000.0]"));
+    assert!(Config::from_file(&dir.path("scene.json")).is_err());
+}
+
+#[test]
+fn test_quadric() {
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::interval::Interval;
+    use crate::ray::Ray;
+    use crate::vec3::{Point3D, Vec3};
+
+    // A cylinder, x² + z² - 1 = 0, from y = 0 to 2.
+    let scene = &test_scene_json(
+        5.0,
+        r#"{
+        "object_list": {"objects": [
+            {"Quadric": {"coefficients": [[1, 0, 0, 0], [0, 0, 0, 0], [0, 0, 1, 0], [0, 0, 0, -1]],
+                         "min": [-1, 0, -1], "max": [1, 2, 1],
+                         "material": {"Lambertian": {"albedo": [0.5, 0.5, 0.5]}}}}
+        ]}
+    }"#,
+    );
+    let config = Config::from_json(scene).unwrap();
+    assert!(config.validate().is_empty());
+    let mut rec = HitRecord::default();
+    let r = Ray::new(Point3D::new(0.0, 1.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(config
+        .object_list
+        .hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec));
+    assert!((rec.t - 4.0).abs() < 1e-12);
+    let r = Ray::new(Point3D::new(0.0, 3.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(!config
+        .object_list
+        .hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec));
+
+    let flipped = scene.replace("\"max\": [1, 2, 1]", "\"max\": [1, -2, 1]");
+    let problems = Config::from_json(&flipped).unwrap().validate();
+    assert_eq!(problems, ["object 0 is a quadric clipped to an empty box"]);
+}
+
+#[test]
+fn test_white_balance_validation() {
+    let problems = |temperature: f64| {
+        let mut scene: serde_json::Value =
+            serde_json::from_str(&test_scene_json(0.0, r#"{"object_list": {"objects": []}}"#))
+                .unwrap();
+        scene["camera"]["white_balance"] = serde_json::json!({"temperature": temperature});
+        Config::from_json(&scene.to_string()).unwrap().validate()
+    };
+    assert!(problems(6500.0).is_empty());
+    assert_eq!(
+        problems(500.0),
+        ["white balance temperature 500 K is not between 1000 and 40000 K"]
+    );
+}
+
+#[test]
+fn test_clip_planes() {
+    use crate::hittable::{HitRecord, Hittable};
+    use crate::interval::Interval;
+    use crate::ray::Ray;
+    use crate::vec3::{Point3D, Vec3};
+
+    // Two spheres, the front halves of both cut away by the scene's plane
+    // and the right one's top also by its own.
+    let scene = &test_scene_json(
+        5.0,
+        r#"{
+        "object_list": {
+            "objects": [
+                {"Sphere": {"center": [0, 0, 0], "radius": 1, "material": {"Named": "grey"}}},
+                {"Sphere": {"center": [3, 0, 0], "radius": 1, "material": {"Named": "grey"}},
+                 "clip_planes": [{"point": [0, 0.5, 0], "normal": [0, 1, 0]}]}
+            ],
+            "clip_planes": [{"point": [0, 0, 0], "normal": [0, 0, 1]}]
+        },
+        "materials": {"grey": {"Lambertian": {"albedo": [0.5, 0.5, 0.5]}}}
+    }"#,
+    );
+    let config = Config::from_json(scene).unwrap();
+    assert!(config.validate().is_empty());
+    let world = &config.object_list;
+    let hit = |x: f64, y: f64| {
+        let r = Ray::new(Point3D::new(x, y, 5.0), Vec3::new(0.0, 0.0, -1.0));
+        let mut rec = HitRecord::default();
+        world
+            .hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec)
+            .then_some(rec)
+    };
+    // Through the cut, onto the inside of the back half.
+    let rec = hit(0.0, 0.0).unwrap();
+    assert!((rec.t - 6.0).abs() < 1e-12 && !rec.front_face);
+    assert!((hit(3.0, 0.0).unwrap().t - 6.0).abs() < 1e-12);
+    assert!(hit(0.0, 0.8).is_some());
+    assert!(hit(3.0, 0.8).is_none());
+
+    let json = config.to_json().unwrap();
+    assert!(json.contains("clip_planes"));
+    let flat = scene.replace("\"normal\": [0, 1, 0]", "\"normal\": [0, 0, 0]");
+    let problems = Config::from_json(&flat).unwrap().validate();
+    assert_eq!(problems, ["object 1 has a clip plane without a normal"]);
 }
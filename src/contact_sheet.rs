@@ -0,0 +1,86 @@
+// Small previews of a batch of renders: a thumbnail of every frame and a
+// contact sheet with all of them in a grid, to see at a glance what a batch
+// or an animation came out like.
+
+use crate::film::Film;
+
+// Default width of thumbnails, in pixels.
+pub const THUMBNAIL_WIDTH: usize = 160;
+// Black border around and between the thumbnails of a contact sheet.
+const GAP: usize = 4;
+
+// Scales `film` down to `width` pixels wide, keeping its aspect ratio. Films
+// that are narrower already keep their size.
+pub fn thumbnail(film: &Film, width: usize) -> Film {
+    let width = width.min(film.width).max(1);
+    let height = ((film.height * width) as f64 / film.width as f64).round() as usize;
+    film.downsample(width, height.max(1))
+}
+
+// A roughly square grid for `count` thumbnails.
+pub fn columns(count: usize) -> usize {
+    (count as f64).sqrt().ceil().max(1.0) as usize
+}
+
+// Lays the thumbnails out left to right and top to bottom, `columns` per row.
+// Every cell is as large as the largest thumbnail, with smaller ones in its
+// top left corner.
+pub fn contact_sheet(thumbnails: &[Film], columns: usize) -> Film {
+    let cell_width = thumbnails.iter().map(|t| t.width).max().unwrap_or(0);
+    let cell_height = thumbnails.iter().map(|t| t.height).max().unwrap_or(0);
+    let rows = thumbnails.len().div_ceil(columns);
+    let mut sheet = Film::new(
+        GAP + columns * (cell_width + GAP),
+        GAP + rows * (cell_height + GAP),
+    );
+
+    for (index, thumbnail) in thumbnails.iter().enumerate() {
+        let x0 = GAP + (index % columns) * (cell_width + GAP);
+        let y0 = GAP + (index / columns) * (cell_height + GAP);
+        for y in 0..thumbnail.height {
+            let start = (y0 + y) * sheet.width + x0;
+            sheet.pixels[start..start + thumbnail.width]
+                .copy_from_slice(&thumbnail.pixels[y * thumbnail.width..][..thumbnail.width]);
+        }
+    }
+    sheet
+}
+
+#[test]
+fn test_thumbnail() {
+    let film = Film::new(640, 360);
+    let small = thumbnail(&film, 160);
+    assert_eq!((small.width, small.height), (160, 90));
+    let tiny = Film::new(20, 10);
+    let same = thumbnail(&tiny, 160);
+    assert_eq!((same.width, same.height), (20, 10));
+    assert_eq!(thumbnail(&Film::new(1000, 1), 10).height, 1);
+}
+
+#[test]
+fn test_contact_sheet() {
+    use crate::color::Color;
+
+    let white = Color::new(1.0, 1.0, 1.0);
+    let mut frames = Vec::new();
+    for index in 0..5 {
+        let mut frame = Film::new(3, 2);
+        frame.pixels[0] = index as f64 * white;
+        frames.push(frame);
+    }
+    frames[4] = Film::new(2, 1);
+    frames[4].pixels[1] = white;
+
+    assert_eq!(columns(5), 3);
+    let sheet = contact_sheet(&frames, 3);
+    assert_eq!((sheet.width, sheet.height), (4 + 3 * 7, 4 + 2 * 6));
+    // Top left pixel of each cell.
+    assert_eq!(sheet.get(4, 4), Color::default());
+    assert_eq!(sheet.get(11, 4), white);
+    assert_eq!(sheet.get(18, 4), 2.0 * white);
+    assert_eq!(sheet.get(4, 10), 3.0 * white);
+    assert_eq!(sheet.get(12, 10), white);
+    assert_eq!(sheet.get(13, 10), Color::default());
+    // Nothing in the gaps.
+    assert_eq!(sheet.get(9, 4), Color::default());
+}
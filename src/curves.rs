@@ -0,0 +1,533 @@
+// Curves for hair and fur: strands of cubic Bézier or B-spline segments whose
+// width changes along them, given in the scene or read from a Cem Yuksel
+// .hair file next to it. Strands are far thinner than a pixel and there are
+// many of them, so their segments are kept in a BVH and hit like pbrt does:
+// each segment is looked at from along the ray and split in halves until the
+// pieces are nearly straight, then the ray is tested against the closest
+// point on a piece. Ribbons are flat strips that always face the ray; round
+// curves are tubes, with the normals of one.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::{Point3D, Vec3};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Basis {
+    // Every segment is four points, sharing its first with the one before.
+    #[default]
+    Bezier,
+    // Every point pulls the strand towards it, which passes near, not
+    // through, them. The ends are doubled so that it runs from near the first
+    // point to near the last.
+    Bspline,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CurveMode {
+    #[default]
+    Ribbon,
+    Round,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Strand {
+    pub points: Vec<Point3D>,
+    // One per point, or none to use the curves' width.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub widths: Vec<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    points: [Point3D; 4],
+    widths: [f64; 2],
+    // Where the segment starts and ends along its strand, 0 to 1.
+    span: [f64; 2],
+}
+
+// A ray passing a segment.
+#[derive(Debug, Clone, Copy)]
+struct CurveHit {
+    t: f64,
+    // Along the segment, 0 to 1.
+    u: f64,
+    // Across the strand, -1 to 1.
+    h: f64,
+}
+
+#[derive(Debug, Default)]
+struct Segments {
+    segments: Vec<Segment>,
+    bvh: Bvh,
+}
+
+fn default_width() -> f64 {
+    0.01
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Curves {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub strands: Vec<Strand>,
+    // A .hair file relative to the scene file, whose strands are added to
+    // these.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(default)]
+    pub basis: Basis,
+    #[serde(default)]
+    pub mode: CurveMode,
+    // The width of strands without widths of their own.
+    #[serde(default = "default_width")]
+    pub width: f64,
+    pub material: Material,
+    #[serde(skip)]
+    loaded: Vec<Strand>,
+    // Built on first use, once the file is loaded.
+    #[serde(skip)]
+    segments: OnceLock<Arc<Segments>>,
+}
+
+impl Curves {
+    pub fn new(strands: Vec<Strand>, basis: Basis, material: Material) -> Curves {
+        Curves {
+            strands,
+            file: None,
+            basis,
+            mode: CurveMode::default(),
+            width: default_width(),
+            material,
+            loaded: Vec::new(),
+            segments: OnceLock::new(),
+        }
+    }
+
+    // Reads the file, if there is one, and returns its path.
+    pub fn load(&mut self, base_dir: &Path) -> io::Result<Option<PathBuf>> {
+        let Some(file) = &self.file else {
+            return Ok(None);
+        };
+        let path = base_dir.join(file);
+        self.loaded = fs::read(&path)
+            .and_then(|data| parse_hair(&data))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        self.segments = OnceLock::new();
+        Ok(Some(path))
+    }
+
+    pub fn strand_count(&self) -> usize {
+        self.strands.len() + self.loaded.len()
+    }
+
+    fn segments(&self) -> &Segments {
+        self.segments.get_or_init(|| {
+            let segments: Vec<Segment> = self
+                .strands
+                .iter()
+                .chain(&self.loaded)
+                .flat_map(|strand| strand.segments(self.basis, self.width))
+                .collect();
+            let boxes: Vec<Aabb> = segments.iter().map(Segment::bounding_box).collect();
+            Arc::new(Segments {
+                bvh: Bvh::new(&boxes),
+                segments,
+            })
+        })
+    }
+}
+
+impl Strand {
+    fn segments(&self, basis: Basis, width: f64) -> Vec<Segment> {
+        let points = &self.points;
+        let width_at = |i: usize| {
+            self.widths
+                .get(i)
+                .or(self.widths.last())
+                .copied()
+                .unwrap_or(width)
+        };
+        match basis {
+            Basis::Bezier => {
+                let n = points.len().saturating_sub(1) / 3;
+                (0..n)
+                    .map(|i| Segment {
+                        points: [
+                            points[3 * i],
+                            points[3 * i + 1],
+                            points[3 * i + 2],
+                            points[3 * i + 3],
+                        ],
+                        widths: [width_at(3 * i), width_at(3 * i + 3)],
+                        span: [i as f64 / n as f64, (i + 1) as f64 / n as f64],
+                    })
+                    .collect()
+            }
+            Basis::Bspline => {
+                if points.len() < 2 {
+                    return Vec::new();
+                }
+                let last = points.len() - 1;
+                let indices: Vec<usize> = [0].into_iter().chain(0..=last).chain([last]).collect();
+                let n = indices.len() - 3;
+                (0..n)
+                    .map(|i| {
+                        let [a, b, c, d] = [0, 1, 2, 3].map(|k| indices[i + k]);
+                        let [p0, p1, p2, p3] = [a, b, c, d].map(|j| points[j]);
+                        Segment {
+                            points: [
+                                (p0 + 4.0 * p1 + p2) / 6.0,
+                                (4.0 * p1 + 2.0 * p2) / 6.0,
+                                (2.0 * p1 + 4.0 * p2) / 6.0,
+                                (p1 + 4.0 * p2 + p3) / 6.0,
+                            ],
+                            widths: [width_at(b), width_at(c)],
+                            span: [i as f64 / n as f64, (i + 1) as f64 / n as f64],
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+// The point at `u` and the derivative there.
+fn bezier(points: &[Vec3; 4], u: f64) -> (Vec3, Vec3) {
+    let lerp = |a: Vec3, b: Vec3| (1.0 - u) * a + u * b;
+    let [a, b, c] = [0, 1, 2].map(|i| lerp(points[i], points[i + 1]));
+    let (d, e) = (lerp(a, b), lerp(b, c));
+    (lerp(d, e), 3.0 * (e - d))
+}
+
+fn split(points: &[Vec3; 4]) -> ([Vec3; 4], [Vec3; 4]) {
+    let middle = |a: Vec3, b: Vec3| 0.5 * (a + b);
+    let [a, b, c] = [0, 1, 2].map(|i| middle(points[i], points[i + 1]));
+    let (d, e) = (middle(a, b), middle(b, c));
+    let center = middle(d, e);
+    ([points[0], a, d, center], [center, e, c, points[3]])
+}
+
+impl Segment {
+    fn bounding_box(&self) -> Aabb {
+        let radius = 0.5 * self.widths[0].max(self.widths[1]);
+        let hull = self.points.iter().fold(Aabb::EMPTY, |bounds, &p| {
+            Aabb::surrounding_box(&bounds, &Aabb::from_points(p, p))
+        });
+        let r = Vec3::new(radius, radius, radius);
+        Aabb::from_points(hull.min() - r, hull.max() + r)
+    }
+
+    fn hit(&self, r: &Ray, ray_t: &Interval, mode: CurveMode) -> Option<CurveHit> {
+        // In a frame with the ray along z from the origin, the curve is hit
+        // where it passes within half its width of the z axis.
+        let length = r.direction().length();
+        let w = *r.direction() / length;
+        let (x, y) = w.perpendiculars();
+        let local = self.points.map(|p| {
+            let d = p - *r.origin();
+            Vec3::new(d.dot(&x), d.dot(&y), d.dot(&w))
+        });
+        // Split until the pieces are within a twentieth of the width of
+        // their chords.
+        let bend = (0..2)
+            .map(|i| (local[i] - 2.0 * local[i + 1] + local[i + 2]).length())
+            .fold(0.0, f64::max);
+        let epsilon = 0.05 * self.widths[0].max(self.widths[1]);
+        let depth = ((6.0 * std::f64::consts::SQRT_2 * bend / (8.0 * epsilon)).log2() / 2.0)
+            .ceil()
+            .clamp(0.0, 10.0) as usize;
+        let z = Interval::new(ray_t.min * length, ray_t.max * length);
+        let hit = self.hit_piece(&local, [0.0, 1.0], depth, z, mode)?;
+        Some(CurveHit {
+            t: hit.t / length,
+            ..hit
+        })
+    }
+
+    // With `t` the distance along the ray.
+    fn hit_piece(
+        &self,
+        local: &[Vec3; 4],
+        span: [f64; 2],
+        depth: usize,
+        z: Interval,
+        mode: CurveMode,
+    ) -> Option<CurveHit> {
+        let radius = 0.5 * self.widths[0].max(self.widths[1]);
+        let (min, max) = local.iter().fold(
+            (
+                Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+                -Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            ),
+            |(min, max), p| {
+                (
+                    Vec3::new(min.x().min(p.x()), min.y().min(p.y()), min.z().min(p.z())),
+                    Vec3::new(max.x().max(p.x()), max.y().max(p.y()), max.z().max(p.z())),
+                )
+            },
+        );
+        if min.x() > radius
+            || max.x() < -radius
+            || min.y() > radius
+            || max.y() < -radius
+            || min.z() > z.max + radius
+            || max.z() < z.min - radius
+        {
+            return None;
+        }
+
+        if depth > 0 {
+            let (first, second) = split(local);
+            let middle = 0.5 * (span[0] + span[1]);
+            let near = self.hit_piece(&first, [span[0], middle], depth - 1, z, mode);
+            let z = near.map_or(z, |hit| Interval::new(z.min, hit.t));
+            return self
+                .hit_piece(&second, [middle, span[1]], depth - 1, z, mode)
+                .or(near);
+        }
+
+        // Only where the axis is beyond the piece's start and before its end,
+        // so that neighbors don't both count.
+        let start = (-local[0].x()) * (local[1].x() - local[0].x())
+            + (-local[0].y()) * (local[1].y() - local[0].y());
+        let end = (-local[3].x()) * (local[2].x() - local[3].x())
+            + (-local[3].y()) * (local[2].y() - local[3].y());
+        if start < 0.0 || end < 0.0 {
+            return None;
+        }
+        let (dx, dy) = (local[3].x() - local[0].x(), local[3].y() - local[0].y());
+        let chord = dx * dx + dy * dy;
+        let along = if chord > 0.0 {
+            ((-local[0].x() * dx - local[0].y() * dy) / chord).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let (center, tangent) = bezier(local, along);
+        let u = span[0] + along * (span[1] - span[0]);
+        let radius = 0.5 * ((1.0 - u) * self.widths[0] + u * self.widths[1]);
+        let distance2 = center.x() * center.x() + center.y() * center.y();
+        if distance2 > radius * radius {
+            return None;
+        }
+        let t = match mode {
+            CurveMode::Ribbon => center.z(),
+            CurveMode::Round => center.z() - (radius * radius - distance2).sqrt(),
+        };
+        if !z.surrounds(t) {
+            return None;
+        }
+        let side = tangent.x() * center.y() - tangent.y() * center.x();
+        let h = (distance2.sqrt() / radius).copysign(side);
+        Some(CurveHit { t, u, h })
+    }
+}
+
+impl Hittable for Curves {
+    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+        let segments = self.segments();
+        let mut closest = None;
+        segments.bvh.hit(r, ray_t, |i, interval| {
+            let hit = segments.segments[i].hit(r, interval, self.mode)?;
+            closest = Some((i, hit));
+            Some(hit.t)
+        });
+        let Some((i, hit)) = closest else {
+            return false;
+        };
+        let segment = &segments.segments[i];
+        rec.t = hit.t;
+        rec.p = r.at(hit.t);
+        let (center, tangent) = bezier(&segment.points, hit.u);
+        let along = tangent.unit_vector();
+        let facing = -(*r.direction() - r.direction().dot(&along) * along);
+        let normal = match self.mode {
+            CurveMode::Ribbon => facing,
+            CurveMode::Round => {
+                let out = rec.p - center;
+                (out - out.dot(&along) * along).or_if_near_zero(facing)
+            }
+        };
+        rec.set_face_normal(r, normal.or_if_near_zero(-*r.direction()).unit_vector());
+        rec.u = segment.span[0] + hit.u * (segment.span[1] - segment.span[0]);
+        rec.v = 0.5 * (hit.h + 1.0);
+        rec.dpdu = tangent / (segment.span[1] - segment.span[0]);
+        let width = (1.0 - hit.u) * segment.widths[0] + hit.u * segment.widths[1];
+        rec.dpdv = width * rec.normal.cross(&along);
+        rec.mat = self.material.clone();
+        true
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: &Interval) -> bool {
+        let segments = self.segments();
+        segments.bvh.any(r, ray_t, |i| {
+            segments.segments[i].hit(r, ray_t, self.mode).is_some()
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.segments().bvh.bounds()
+    }
+}
+
+// Cem Yuksel's .hair files: a 128 byte header, then the number of segments of
+// every strand, the points, and the thickness at every point, little endian.
+// The numbers of segments and thicknesses can be left out for one value in
+// the header; transparencies and colors, which may follow, are ignored.
+fn parse_hair(data: &[u8]) -> io::Result<Vec<Strand>> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    if data.len() < 128 || &data[..4] != b"HAIR" {
+        return Err(invalid("not a .hair file"));
+    }
+    let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap()) as usize;
+    let f32_at = |i: usize| f32::from_le_bytes(data[i..i + 4].try_into().unwrap()) as f64;
+    let (strands, points, flags) = (u32_at(4), u32_at(8), u32_at(12));
+    let default_thickness = f32_at(20);
+
+    let mut offset = 128;
+    let mut take = |size: usize| {
+        let start = offset;
+        offset += size;
+        if offset > data.len() {
+            Err(invalid("the .hair file is cut short"))
+        } else {
+            Ok(start)
+        }
+    };
+    let segments: Vec<usize> = if flags & 1 != 0 {
+        let start = take(2 * strands)?;
+        (0..strands)
+            .map(|i| u16::from_le_bytes([data[start + 2 * i], data[start + 2 * i + 1]]) as usize)
+            .collect()
+    } else {
+        vec![u32_at(16); strands]
+    };
+    if flags & 2 == 0 {
+        return Err(invalid("the .hair file has no points"));
+    }
+    let positions = take(12 * points)?;
+    let thicknesses = if flags & 4 != 0 {
+        Some(take(4 * points)?)
+    } else {
+        None
+    };
+    if segments.iter().map(|s| s + 1).sum::<usize>() != points {
+        return Err(invalid(
+            "the .hair file's strands don't add up to its points",
+        ));
+    }
+
+    let mut next = 0;
+    Ok(segments
+        .iter()
+        .map(|&count| {
+            let range = next..next + count + 1;
+            next = range.end;
+            let point = |i: usize| {
+                let at = positions + 12 * i;
+                Point3D::new(f32_at(at), f32_at(at + 4), f32_at(at + 8))
+            };
+            let widths = match thicknesses {
+                Some(start) => range.clone().map(|i| f32_at(start + 4 * i)).collect(),
+                None if default_thickness > 0.0 => vec![default_thickness],
+                None => Vec::new(),
+            };
+            Strand {
+                points: range.map(point).collect(),
+                widths,
+            }
+        })
+        .collect())
+}
+
+#[test]
+fn test_curves() {
+    use crate::color::Color;
+    use crate::material::Lambertian;
+
+    // A straight strand along x, 0.2 wide, thinning to nothing at the end.
+    let strand = Strand {
+        points: (0..4).map(|i| Point3D::new(i as f64, 0.0, 0.0)).collect(),
+        widths: vec![0.2, 0.2, 0.2, 0.0],
+    };
+    let material = Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let mut curves = Curves::new(vec![strand.clone()], Basis::Bezier, material.clone());
+    let down = |x: f64, y: f64| Ray::new(Point3D::new(x, y, 2.0), Vec3::new(0.0, 0.0, -2.0));
+    let everywhere = Interval::new(0.001, f64::INFINITY);
+
+    let mut rec = HitRecord::default();
+    assert!(curves.hit(&down(0.3, 0.05), &everywhere, &mut rec));
+    assert!((rec.t - 1.0).abs() < 1e-9);
+    assert!((rec.u - 0.1).abs() < 1e-9);
+    assert!((rec.dpdu - Vec3::new(3.0, 0.0, 0.0)).length() < 1e-9);
+    assert!(rec.front_face && (rec.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+    assert!((rec.v - 0.5).abs() > 0.2);
+    // Beside the strand, and where it has thinned.
+    assert!(!curves.hit(&down(0.3, 0.15), &everywhere, &mut rec));
+    assert!(!curves.hit(&down(2.9, 0.05), &everywhere, &mut rec));
+
+    // As a tube, 0.18 wide there, it is hit on its top, where the normal
+    // leans out.
+    curves.mode = CurveMode::Round;
+    curves.segments = OnceLock::new();
+    assert!(curves.hit(&down(0.3, 0.054), &everywhere, &mut rec));
+    assert!((rec.p.z() - 0.072).abs() < 1e-9);
+    assert!((rec.normal - Vec3::new(0.0, 0.6, 0.8)).length() < 1e-9);
+
+    // A B-spline through points on a circle is curved but stays near them.
+    let points = (0..12)
+        .map(|i| {
+            let a = i as f64 * std::f64::consts::PI / 8.0;
+            Point3D::new(a.cos(), a.sin(), 0.0)
+        })
+        .collect();
+    let arc = Curves::new(
+        vec![Strand {
+            points,
+            widths: Vec::new(),
+        }],
+        Basis::Bspline,
+        material,
+    );
+    assert_eq!(arc.segments().segments.len(), 11);
+    // At the top it passes (P3 + 4 P4 + P5) / 6, going left.
+    let top = (4.0 + 2.0 * (std::f64::consts::PI / 8.0).cos()) / 6.0;
+    assert!(arc.hit(&down(0.001, top), &everywhere, &mut rec));
+    assert!(rec.dpdu.x() < 0.0 && (rec.dpdu.y() / rec.dpdu.x()).abs() < 0.01);
+    assert!(!arc.hit(&down(0.0, 0.95), &everywhere, &mut rec));
+}
+
+#[test]
+fn test_parse_hair() {
+    // Two strands of one segment each, with a thickness in the header.
+    let mut data = b"HAIR".to_vec();
+    for value in [2u32, 4, 2, 1] {
+        data.extend(value.to_le_bytes());
+    }
+    data.extend(0.5f32.to_le_bytes());
+    data.resize(128, 0);
+    for i in 0..4 {
+        for c in [i as f32, 0.0, 1.0] {
+            data.extend(c.to_le_bytes());
+        }
+    }
+    let strands = parse_hair(&data).unwrap();
+    assert_eq!(strands.len(), 2);
+    assert_eq!(strands[1].points[1], Point3D::new(3.0, 0.0, 1.0));
+    assert_eq!(strands[0].widths, vec![0.5]);
+
+    data.truncate(150);
+    assert!(parse_hair(&data).is_err());
+    assert!(parse_hair(b"HAIRS").is_err());
+}
@@ -0,0 +1,215 @@
+// Shapes defined outside this crate. Anything that implements `Hittable` can
+// be put into an `ObjectList` as a `Custom` shape; the material is kept here,
+// so palette references and validation work like for built-in shapes.
+//
+// Scene files refer to custom shapes by a type name registered with
+// `register_shape`:
+//
+//     {"Custom": {"type": "torus", "params": {...}, "material": {...}}}
+
+use std::fmt;
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+
+pub type SharedHittable = Arc<dyn Hittable + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Custom {
+    pub type_name: String,
+    pub hittable: SharedHittable,
+    pub material: Material,
+    // Written back out when the scene is saved.
+    #[cfg(feature = "json")]
+    pub params: serde_json::Value,
+}
+
+impl fmt::Debug for Custom {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Custom")
+            .field("type_name", &self.type_name)
+            .field("material", &self.material)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Custom {
+    pub fn new<T>(type_name: &str, shape: T, material: Material) -> Custom
+    where
+        T: Hittable + Serialize + Send + Sync + 'static,
+    {
+        Custom {
+            type_name: type_name.to_string(),
+            #[cfg(feature = "json")]
+            params: serde_json::to_value(&shape).unwrap_or_default(),
+            hittable: Arc::new(shape),
+            material,
+        }
+    }
+}
+
+impl Hittable for Custom {
+    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+        if !self.hittable.hit(r, ray_t, rec) {
+            return false;
+        }
+        rec.mat = self.material.clone();
+        true
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: &Interval) -> bool {
+        self.hittable.occluded(r, ray_t)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.hittable.bounding_box()
+    }
+}
+
+#[cfg(feature = "json")]
+pub use registry::register_shape;
+
+#[cfg(feature = "json")]
+mod registry {
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, RwLock};
+
+    use serde::de::{DeserializeOwned, Error as _};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::{Custom, SharedHittable};
+    use crate::hittable::Hittable;
+    use crate::material::Material;
+
+    type Constructor = fn(serde_json::Value) -> Result<SharedHittable, serde_json::Error>;
+
+    static SHAPES: RwLock<BTreeMap<String, Constructor>> = RwLock::new(BTreeMap::new());
+
+    // Lets scene files use `T` as a custom shape called `type_name`. Its
+    // `params` are deserialized into a `T`.
+    pub fn register_shape<T>(type_name: &str)
+    where
+        T: Hittable + DeserializeOwned + Send + Sync + 'static,
+    {
+        let constructor: Constructor =
+            |params| Ok(Arc::new(serde_json::from_value::<T>(params)?) as SharedHittable);
+        SHAPES
+            .write()
+            .unwrap()
+            .insert(type_name.to_string(), constructor);
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct CustomParams {
+        #[serde(rename = "type")]
+        type_name: String,
+        #[serde(default)]
+        params: serde_json::Value,
+        material: Material,
+    }
+
+    impl Serialize for Custom {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            CustomParams {
+                type_name: self.type_name.clone(),
+                params: self.params.clone(),
+                material: self.material.clone(),
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Custom {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Custom, D::Error> {
+            let p = CustomParams::deserialize(deserializer)?;
+            let constructor = SHAPES
+                .read()
+                .unwrap()
+                .get(&p.type_name)
+                .copied()
+                .ok_or_else(|| {
+                    D::Error::custom(format!("unknown custom shape \"{}\"", p.type_name))
+                })?;
+            let hittable = constructor(p.params.clone()).map_err(D::Error::custom)?;
+            Ok(Custom {
+                type_name: p.type_name,
+                hittable,
+                material: p.material,
+                params: p.params,
+            })
+        }
+    }
+}
+
+// A plane through the origin facing +z, cut off at a radius.
+#[cfg(all(test, feature = "json"))]
+#[derive(Serialize, serde::Deserialize)]
+struct Disk {
+    radius: f64,
+}
+
+#[cfg(all(test, feature = "json"))]
+impl Hittable for Disk {
+    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+        let t = -r.origin().z() / r.direction().z();
+        let p = r.at(t);
+        if !ray_t.surrounds(t) || p.x() * p.x() + p.y() * p.y() > self.radius * self.radius {
+            return false;
+        }
+        rec.t = t;
+        rec.p = p;
+        rec.set_face_normal(r, crate::vec3::Vec3::new(0.0, 0.0, 1.0));
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = self.radius;
+        Aabb::from_points(
+            crate::vec3::Point3D::new(-r, -r, 0.0),
+            crate::vec3::Point3D::new(r, r, 0.0),
+        )
+    }
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_custom_shape() {
+    use crate::hittable::{Object, ObjectList};
+    use crate::vec3::{Point3D, Vec3};
+
+    register_shape::<Disk>("disk");
+    let object: Object = serde_json::from_str(
+        r#"{"Custom": {"type": "disk", "params": {"radius": 2.0}, "material": {"Glass": {"refraction_index": 1.5}}}, "name": "disk"}"#,
+    )
+    .unwrap();
+    assert_eq!(object.kind(), "custom");
+    assert!(serde_json::to_string(&object)
+        .unwrap()
+        .contains("\"radius\":2.0"));
+
+    let mut list = ObjectList::new();
+    list.add(object);
+    list.add(Object::from(Custom::new(
+        "disk",
+        Disk { radius: 1.0 },
+        Material::Named("x".to_string()),
+    )));
+
+    let ray = Ray::new(Point3D::new(1.5, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    let mut rec = HitRecord::default();
+    assert!(list.hit(&ray, &Interval::new(0.001, f64::INFINITY), &mut rec));
+    assert_eq!(rec.t, 5.0);
+    assert!(matches!(rec.mat, Material::Glass(_)));
+    assert_eq!(list.bounding_box().x, Interval::new(-2.0, 2.0));
+
+    assert!(serde_json::from_str::<Object>(
+        r#"{"Custom": {"type": "teapot", "material": {"Glass": {"refraction_index": 1.5}}}}"#
+    )
+    .is_err());
+}
@@ -0,0 +1,223 @@
+// True displacement of meshes: every triangle is split into a grid of
+// smaller ones when the scene is loaded, and each new vertex is moved along
+// the surface normal by the height map at its texture coordinates, so the
+// bumps show in silhouettes and shadows. All triangles are split the same
+// number of times and the displacement follows normals averaged over the
+// triangles around a position, so triangles that share an edge still meet
+// after it; they may not where the texture coordinates jump, at UV seams.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::film::read_image;
+use crate::mesh::{Mesh, Vertex};
+use crate::vec3::{Point3D, Vec3};
+
+fn default_subdivisions() -> usize {
+    16
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Displacement {
+    // A grey image, black the lowest and white the highest.
+    pub file: String,
+    // How far white moves the surface, in scene units; negative pushes in.
+    pub scale: f64,
+    // The grey level that leaves the surface where it is, 0 to 1.
+    #[serde(default)]
+    pub midlevel: f64,
+    // The most pieces each edge is split into. Edges are split no finer
+    // than the height map's texels.
+    #[serde(default = "default_subdivisions")]
+    pub subdivisions: usize,
+}
+
+// The grey levels of an image, read as they are stored: heights aren't
+// colors, so there is no gamma to undo.
+pub struct HeightMap {
+    width: usize,
+    height: usize,
+    values: Vec<f64>,
+}
+
+impl HeightMap {
+    pub fn read(path: &Path) -> io::Result<HeightMap> {
+        let (pixels, (width, height)) = read_image(&path.to_string_lossy())?;
+        let values = pixels
+            .chunks(3)
+            .map(|p| p.iter().map(|&c| c as f64).sum::<f64>() / (3.0 * 255.0))
+            .collect();
+        Ok(HeightMap {
+            width,
+            height,
+            values,
+        })
+    }
+
+    // Bilinear, repeating, with v going up.
+    pub fn at(&self, u: f64, v: f64) -> f64 {
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let value = |dx: i64, dy: i64| {
+            let x = (x0 as i64 + dx).rem_euclid(self.width as i64) as usize;
+            let y = (y0 as i64 + dy).rem_euclid(self.height as i64) as usize;
+            self.values[y * self.width + x]
+        };
+        let top = (1.0 - fx) * value(0, 0) + fx * value(1, 0);
+        let bottom = (1.0 - fx) * value(0, 1) + fx * value(1, 1);
+        (1.0 - fy) * top + fy * bottom
+    }
+}
+
+impl Displacement {
+    pub fn apply(&self, mesh: &Mesh, map: &Path) -> io::Result<Mesh> {
+        Ok(self.displace(mesh, &HeightMap::read(map)?))
+    }
+
+    // Pieces every edge is split into: enough for the longest edge in
+    // texture space to step about a texel at a time.
+    fn segments(&self, mesh: &Mesh, heights: &HeightMap) -> usize {
+        let longest = (0..mesh.triangles.len())
+            .filter_map(|i| mesh.uv(i))
+            .flat_map(|uvs| {
+                (0..3).map(move |i| {
+                    let (a, b) = (uvs[i], uvs[(i + 1) % 3]);
+                    let du = (a.0 - b.0) * heights.width as f64;
+                    let dv = (a.1 - b.1) * heights.height as f64;
+                    du.hypot(dv)
+                })
+            })
+            .fold(0.0, f64::max);
+        (longest.ceil() as usize).clamp(1, self.subdivisions.max(1))
+    }
+
+    pub fn displace(&self, mesh: &Mesh, heights: &HeightMap) -> Mesh {
+        let n = self.segments(mesh, heights);
+        let mut smoothed = mesh.clone();
+        smoothed.smooth_normals();
+        let normals = smoothed.normals;
+        let mut displaced = Mesh::default();
+        // Grid points on shared edges come out bit for bit the same from both
+        // triangles, so they can be merged by position.
+        let mut merged: HashMap<[u64; 3], usize> = HashMap::new();
+
+        for (triangle, corners) in mesh.triangles.iter().enumerate() {
+            let uvs = mesh.uv(triangle);
+            let mut grid = Vec::with_capacity((n + 1) * (n + 2) / 2);
+            for i in 0..=n {
+                for j in 0..=n - i {
+                    // Exact fractions, one of them zero on an edge.
+                    let weights = [(n - i - j) as f64, i as f64, j as f64].map(|w| w / n as f64);
+                    let blend = |values: [Vec3; 3]| {
+                        weights[0] * values[0] + weights[1] * values[1] + weights[2] * values[2]
+                    };
+                    let p = blend(corners.map(|c| mesh.positions[c.position]));
+                    let normal = blend(corners.map(|c| normals[c.position])).unit_vector();
+                    let (uv, offset) = match uvs {
+                        Some(uvs) => {
+                            let u = weights[0] * uvs[0].0
+                                + weights[1] * uvs[1].0
+                                + weights[2] * uvs[2].0;
+                            let v = weights[0] * uvs[0].1
+                                + weights[1] * uvs[1].1
+                                + weights[2] * uvs[2].1;
+                            (
+                                Some((u, v)),
+                                self.scale * (heights.at(u, v) - self.midlevel),
+                            )
+                        }
+                        None => (None, 0.0),
+                    };
+                    let p: Point3D = p + offset * normal;
+                    let key = [p.x().to_bits(), p.y().to_bits(), p.z().to_bits()];
+                    let position = *merged.entry(key).or_insert_with(|| {
+                        displaced.positions.push(p);
+                        displaced.positions.len() - 1
+                    });
+                    let uv = uv.map(|uv| {
+                        displaced.uvs.push(uv);
+                        displaced.uvs.len() - 1
+                    });
+                    grid.push(Vertex {
+                        position,
+                        uv,
+                        // Set below, once all triangles are in place.
+                        normal: None,
+                    });
+                }
+            }
+
+            // Row i starts after the longer rows before it.
+            let at = |i: usize, j: usize| grid[i * (2 * n + 3 - i) / 2 + j];
+            for i in 0..n {
+                for j in 0..n - i {
+                    displaced
+                        .triangles
+                        .push([at(i, j), at(i + 1, j), at(i, j + 1)]);
+                    if j + 1 < n - i {
+                        displaced
+                            .triangles
+                            .push([at(i + 1, j), at(i + 1, j + 1), at(i, j + 1)]);
+                    }
+                }
+            }
+        }
+
+        displaced.smooth_normals();
+        displaced
+    }
+}
+
+#[test]
+fn test_displace() {
+    // Two triangles making a unit square facing +z.
+    let mesh = Mesh::parse_obj(
+        "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 2/2 3/3 4/4\n",
+    )
+    .unwrap();
+    // Heights rising from left to right.
+    let heights = HeightMap {
+        width: 4,
+        height: 1,
+        values: vec![0.0, 0.25, 0.5, 0.75],
+    };
+    let displacement = Displacement {
+        file: "height.png".to_string(),
+        scale: 2.0,
+        midlevel: 0.0,
+        subdivisions: 16,
+    };
+    // The longest edge, the diagonal, crosses about 4.1 texels.
+    assert_eq!(displacement.segments(&mesh, &heights), 5);
+    let displaced = displacement.displace(&mesh, &heights);
+    assert_eq!(displaced.triangles.len(), 2 * 25);
+    // The diagonal's points are shared, not doubled: a 6x6 grid.
+    assert_eq!(displaced.positions.len(), 36);
+
+    for p in &displaced.positions {
+        let expected = 2.0 * heights.at(p.x(), p.y());
+        assert!((p.z() - expected).abs() < 1e-12, "{:?}", p);
+    }
+    // Rising to the right, the normals lean to the left, except at the
+    // right edge where the heights wrap around to the left's.
+    let middle = (0..displaced.triangles.len())
+        .find(|&i| {
+            let [a, b, c] = displaced.corners(i);
+            ((a.x() + b.x() + c.x()) / 3.0 - 0.5).abs() < 0.1
+        })
+        .unwrap();
+    let normal = displaced.normal(middle, [1.0 / 3.0; 3]);
+    assert!(normal.x() < 0.0 && normal.z() > 0.0, "{:?}", normal);
+
+    // Without subdivisions it stays two triangles.
+    let coarse = Displacement {
+        subdivisions: 1,
+        ..displacement
+    };
+    assert_eq!(coarse.displace(&mesh, &heights).triangles.len(), 2);
+}
@@ -0,0 +1,95 @@
+// How the sky is placed and how bright it is, for art-directing the light it
+// casts without changing what makes it: the gradient, the sun's sky or the
+// atmosphere. The sun's disk and its light keep their own direction and
+// irradiance.
+
+use serde::{Deserialize, Serialize};
+
+use crate::quat::Quat;
+use crate::vec3::Vec3;
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Environment {
+    // In degrees: around y, then around x, then around z.
+    #[serde(default)]
+    pub yaw: f64,
+    #[serde(default)]
+    pub pitch: f64,
+    #[serde(default)]
+    pub roll: f64,
+    // Multiplies the sky's light.
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Environment {
+            yaw: 0.0,
+            pitch: 0.0,
+            roll: 0.0,
+            intensity: default_intensity(),
+        }
+    }
+}
+
+impl Environment {
+    pub fn is_default(&self) -> bool {
+        *self == Environment::default()
+    }
+
+    pub fn rotation(&self) -> Quat {
+        Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), self.yaw)
+            * Quat::from_axis_angle(Vec3::new(1.0, 0.0, 0.0), self.pitch)
+            * Quat::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), self.roll)
+    }
+
+    // Where to look up the unrotated sky for what is seen in `direction`.
+    pub fn lookup(&self, direction: Vec3) -> Vec3 {
+        if self.yaw == 0.0 && self.pitch == 0.0 && self.roll == 0.0 {
+            return direction;
+        }
+        self.rotation().conjugate().rotate(direction)
+    }
+
+    // Reads "yaw,pitch,roll" in degrees, as given on the command line.
+    pub fn parse_rotation(text: &str) -> Option<(f64, f64, f64)> {
+        let angles: Vec<f64> = text
+            .split(',')
+            .map(|angle| angle.trim().parse().ok())
+            .collect::<Option<_>>()?;
+        match angles[..] {
+            [yaw, pitch, roll] => Some((yaw, pitch, roll)),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_environment() {
+    let environment = Environment {
+        yaw: 90.0,
+        ..Environment::default()
+    };
+    // Turned a quarter to the left, what was in front is on the left.
+    let left = Vec3::new(-1.0, 0.0, 0.0);
+    assert!((environment.lookup(left) - Vec3::new(0.0, 0.0, -1.0)).length() < 1e-9);
+    let tilted = Environment {
+        pitch: 90.0,
+        ..Environment::default()
+    };
+    let front = Vec3::new(0.0, 0.0, -1.0);
+    assert!((tilted.lookup(front) - Vec3::new(0.0, -1.0, 0.0)).length() < 1e-9);
+    assert_eq!(Environment::default().lookup(left), left);
+
+    assert_eq!(
+        Environment::parse_rotation("90, -10,5"),
+        Some((90.0, -10.0, 5.0))
+    );
+    assert_eq!(Environment::parse_rotation("90,10"), None);
+    assert_eq!(Environment::parse_rotation("left"), None);
+}
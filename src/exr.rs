@@ -0,0 +1,262 @@
+// Tiled OpenEXR output: linear 32-bit float RGB, uncompressed, in square
+// tiles. Compositing packages read tiled files a tile at a time instead of
+// whole scanlines, which is what makes 16k frames workable there, and since
+// uncompressed tiles all have a known size the offset table can be written up
+// front and the tiles streamed behind it as the bands are rendered.
+
+use std::io::{self, Write};
+
+use crate::color::Color;
+use crate::film::Film;
+
+// Width and height of the tiles, and so the band `Camera::render_streaming`
+// renders at a time for EXR files.
+pub const TILE: usize = 64;
+
+const MAGIC: u32 = 20000630;
+// Version 2, single part, tiled.
+const VERSION: u32 = 2 | 0x200;
+const FLOAT: i32 = 2;
+// One channel of a tile row.
+const BYTES_PER_VALUE: usize = 4;
+
+pub fn is_exr(filename: &str) -> bool {
+    std::path::Path::new(filename)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("exr"))
+}
+
+// Writes `film` to `filename` with `text` as string attributes.
+pub fn write(filename: &str, film: &Film, text: &[(&str, String)]) -> io::Result<()> {
+    let mut image = TiledExr::create(filename, (film.width, film.height), text)?;
+    for band in film.pixels.chunks(TILE * film.width) {
+        image.write_rows(band)?;
+    }
+    image.finish()
+}
+
+// An EXR file written a row of tiles at a time.
+pub struct TiledExr {
+    output: io::BufWriter<std::fs::File>,
+    bounds: (usize, usize),
+    rows: usize,
+}
+
+impl TiledExr {
+    pub fn create(
+        filename: &str,
+        bounds: (usize, usize),
+        text: &[(&str, String)],
+    ) -> io::Result<TiledExr> {
+        if bounds.0 == 0 || bounds.1 == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "EXR images can't be empty",
+            ));
+        }
+        let header = header(bounds, text);
+        let mut output = io::BufWriter::new(std::fs::File::create(filename)?);
+        output.write_all(&header)?;
+
+        // The offset of every tile, row by row, behind the table itself.
+        let tiles = tiles(bounds);
+        let mut offset = (header.len() + 8 * tiles.len()) as u64;
+        for (_, _, width, height) in tiles {
+            output.write_all(&offset.to_le_bytes())?;
+            offset += (20 + width * height * 3 * BYTES_PER_VALUE) as u64;
+        }
+        Ok(TiledExr {
+            output,
+            bounds,
+            rows: 0,
+        })
+    }
+
+    // Linear colors of TILE whole rows, continuing below the previous ones;
+    // only the last rows of the image may be fewer.
+    pub fn write_rows(&mut self, pixels: &[Color]) -> io::Result<()> {
+        let (width, height) = self.bounds;
+        let rows = pixels.len() / width;
+        if !pixels.len().is_multiple_of(width)
+            || self.rows + rows > height
+            || (rows != TILE && self.rows + rows != height)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "EXR tiles are written a whole row of tiles at a time",
+            ));
+        }
+
+        let tile_y = self.rows / TILE;
+        for tile_x in 0..width.div_ceil(TILE) {
+            let x0 = tile_x * TILE;
+            let tile_width = TILE.min(width - x0);
+            let size = tile_width * rows * 3 * BYTES_PER_VALUE;
+            let mut chunk = Vec::with_capacity(20 + size);
+            for value in [tile_x as i32, tile_y as i32, 0, 0, size as i32] {
+                chunk.extend(value.to_le_bytes());
+            }
+            // Each row holds all its blue values, then green, then red, as
+            // channels are sorted by name.
+            for row in pixels.chunks(width) {
+                let row = &row[x0..x0 + tile_width];
+                for channel in [Color::z, Color::y, Color::x] {
+                    for pixel in row {
+                        chunk.extend((channel(pixel) as f32).to_le_bytes());
+                    }
+                }
+            }
+            self.output.write_all(&chunk)?;
+        }
+        self.rows += rows;
+        Ok(())
+    }
+
+    // Fails if fewer rows than the image height were written.
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.rows != self.bounds.1 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "not all rows of the EXR image were written",
+            ));
+        }
+        self.output.flush()
+    }
+}
+
+// Column, row, width and height of every tile, in the order they are stored.
+fn tiles(bounds: (usize, usize)) -> Vec<(usize, usize, usize, usize)> {
+    let (width, height) = bounds;
+    let mut tiles = Vec::new();
+    for tile_y in 0..height.div_ceil(TILE) {
+        for tile_x in 0..width.div_ceil(TILE) {
+            tiles.push((
+                tile_x,
+                tile_y,
+                TILE.min(width - tile_x * TILE),
+                TILE.min(height - tile_y * TILE),
+            ));
+        }
+    }
+    tiles
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    for s in [name, kind] {
+        header.extend(s.as_bytes());
+        header.push(0);
+    }
+    header.extend((value.len() as i32).to_le_bytes());
+    header.extend(value);
+}
+
+fn header(bounds: (usize, usize), text: &[(&str, String)]) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend(MAGIC.to_le_bytes());
+    header.extend(VERSION.to_le_bytes());
+
+    let mut channels = Vec::new();
+    for name in ["B", "G", "R"] {
+        channels.extend(name.as_bytes());
+        channels.push(0);
+        // Pixel type, linear flag and padding, x and y sampling.
+        channels.extend(FLOAT.to_le_bytes());
+        channels.extend([0; 4]);
+        channels.extend(1i32.to_le_bytes());
+        channels.extend(1i32.to_le_bytes());
+    }
+    channels.push(0);
+    attribute(&mut header, "channels", "chlist", &channels);
+    attribute(&mut header, "compression", "compression", &[0]);
+
+    let window: Vec<u8> = [0, 0, bounds.0 as i32 - 1, bounds.1 as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    // Increasing y.
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+
+    // A single level, rounding down.
+    let mut tiles = Vec::new();
+    tiles.extend((TILE as u32).to_le_bytes());
+    tiles.extend((TILE as u32).to_le_bytes());
+    tiles.push(0);
+    attribute(&mut header, "tiles", "tiledesc", &tiles);
+
+    for (name, value) in text {
+        attribute(&mut header, name, "string", value.as_bytes());
+    }
+    header.push(0);
+    header
+}
+
+#[test]
+fn test_write() {
+    // Two by two tiles, the right and bottom ones partial.
+    let mut film = Film::new(TILE + 3, TILE + 5);
+    for (i, pixel) in film.pixels.iter_mut().enumerate() {
+        *pixel = Color::new(i as f64, 0.5, -(i as f64) / 4.0);
+    }
+    let path = std::env::temp_dir().join(format!("raytracer-test-{}.exr", std::process::id()));
+    let filename = path.to_str().unwrap();
+    assert!(is_exr(filename));
+    write(filename, &film, &[("Software", "raytracer".to_string())]).unwrap();
+    let data = std::fs::read(filename).unwrap();
+
+    let header = header(
+        (film.width, film.height),
+        &[("Software", "raytracer".to_string())],
+    );
+    assert!(data.starts_with(&header));
+    let offset = |i: usize| {
+        let start = header.len() + 8 * i;
+        u64::from_le_bytes(data[start..start + 8].try_into().unwrap()) as usize
+    };
+    let value = |at: usize| f32::from_le_bytes(data[at..at + 4].try_into().unwrap());
+    // The last tile holds 3x5 pixels; its first value is the blue of the
+    // pixel at (TILE, TILE).
+    let last = offset(3);
+    assert_eq!(data.len(), last + 20 + 3 * 5 * 3 * 4);
+    assert_eq!(data[last..last + 8], [1, 0, 0, 0, 1, 0, 0, 0]);
+    let index = TILE * film.width + TILE;
+    assert_eq!(value(last + 20), -(index as f32) / 4.0);
+    assert_eq!(value(last + 20 + 2 * 3 * 4), index as f32);
+
+    // Tiles must come a whole row at a time.
+    let mut image = TiledExr::create(filename, (film.width, film.height), &[]).unwrap();
+    assert!(image.write_rows(&film.pixels[..film.width]).is_err());
+    assert!(image.finish().is_err());
+    std::fs::remove_file(filename).unwrap();
+}
+
+// The image crate's EXR decoder, itself written against the OpenEXR
+// specification, reads the same colors back.
+#[cfg(feature = "image")]
+#[test]
+fn test_decode() {
+    let mut film = Film::new(70, 3);
+    film.pixels[69] = Color::new(0.25, 2.0, 100.0);
+    let path = std::env::temp_dir().join(format!("raytracer-decode-{}.exr", std::process::id()));
+    let filename = path.to_str().unwrap();
+    write(filename, &film, &[]).unwrap();
+    let image = image::open(filename).unwrap().to_rgb32f();
+    std::fs::remove_file(filename).unwrap();
+    assert_eq!(image.dimensions(), (70, 3));
+    assert_eq!(image.get_pixel(69, 0).0, [0.25, 2.0, 100.0]);
+    assert_eq!(image.get_pixel(0, 2).0, [0.0, 0.0, 0.0]);
+}
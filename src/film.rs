@@ -0,0 +1,421 @@
+use std::io;
+
+use crate::color::{gamma_to_linear, write_color_with, Clip, Color};
+use crate::exr::{self, TiledExr};
+
+pub(crate) fn write_image(
+    filename: &str,
+    pixels: &[u8],
+    bounds: (usize, usize),
+) -> Result<(), io::Error> {
+    write_image_with_text(filename, pixels, bounds, &[])
+}
+
+// An RGB PNG encoder with `text` as tEXt chunks in front of the pixels.
+#[cfg(feature = "image")]
+fn png_encoder<W: io::Write>(
+    output: W,
+    bounds: (usize, usize),
+    text: &[(&str, String)],
+) -> io::Result<png::Encoder<'static, W>> {
+    let mut encoder = png::Encoder::new(output, bounds.0 as u32, bounds.1 as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+    for (keyword, value) in text {
+        encoder
+            .add_text_chunk(keyword.to_string(), value.clone())
+            .map_err(io::Error::other)?;
+    }
+    Ok(encoder)
+}
+
+#[cfg(feature = "image")]
+pub(crate) fn write_image_with_text(
+    filename: &str,
+    pixels: &[u8],
+    bounds: (usize, usize),
+    text: &[(&str, String)],
+) -> Result<(), io::Error> {
+    let output = io::BufWriter::new(std::fs::File::create(filename)?);
+    png_encoder(output, bounds, text)?
+        .write_header()
+        .and_then(|mut writer| {
+            writer.write_image_data(pixels)?;
+            writer.finish()
+        })
+        .map_err(io::Error::other)
+}
+
+#[cfg(not(feature = "image"))]
+pub(crate) fn write_image_with_text(
+    _filename: &str,
+    _pixels: &[u8],
+    _bounds: (usize, usize),
+    _text: &[(&str, String)],
+) -> Result<(), io::Error> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "writing images needs raytracer to be built with the \"image\" feature",
+    ))
+}
+
+// The tEXt chunks of a PNG file, e.g. the metadata of a render.
+#[cfg(feature = "image")]
+pub fn read_text(filename: &str) -> io::Result<Vec<(String, String)>> {
+    let input = io::BufReader::new(std::fs::File::open(filename)?);
+    let reader = png::Decoder::new(input)
+        .read_info()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(reader
+        .info()
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+        .collect())
+}
+
+#[cfg(not(feature = "image"))]
+pub fn read_text(_filename: &str) -> io::Result<Vec<(String, String)>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reading images needs raytracer to be built with the \"image\" feature",
+    ))
+}
+
+// A PNG or tiled EXR file written a few rows at a time, so that huge images
+// never have to be in memory as a whole.
+pub(crate) enum ImageStream {
+    #[cfg(feature = "image")]
+    Png(Box<png::StreamWriter<'static, io::BufWriter<std::fs::File>>>),
+    Exr(TiledExr),
+}
+
+impl ImageStream {
+    // EXR for files ending in .exr, PNG otherwise.
+    pub(crate) fn create(
+        filename: &str,
+        bounds: (usize, usize),
+        text: &[(&str, String)],
+    ) -> io::Result<ImageStream> {
+        if exr::is_exr(filename) {
+            return Ok(ImageStream::Exr(TiledExr::create(filename, bounds, text)?));
+        }
+        create_png_stream(filename, bounds, text)
+    }
+
+    // Whole rows, continuing below the previous ones, with bright colors
+    // brought into range by `clip` for PNG files. EXR files take
+    // `exr::TILE` rows at a time.
+    pub(crate) fn write_rows(&mut self, rows: &Film, clip: Clip) -> io::Result<()> {
+        // Only PNG files are clipped.
+        #[cfg(not(feature = "image"))]
+        let _ = clip;
+        match self {
+            #[cfg(feature = "image")]
+            ImageStream::Png(writer) => io::Write::write_all(writer, &rows.to_rgb8_with(clip)),
+            ImageStream::Exr(image) => image.write_rows(&rows.pixels),
+        }
+    }
+
+    // Fails if fewer rows than the image height were written.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        match self {
+            #[cfg(feature = "image")]
+            ImageStream::Png(writer) => writer.finish().map_err(io::Error::other),
+            ImageStream::Exr(image) => image.finish(),
+        }
+    }
+}
+
+#[cfg(feature = "image")]
+fn create_png_stream(
+    filename: &str,
+    bounds: (usize, usize),
+    text: &[(&str, String)],
+) -> io::Result<ImageStream> {
+    let output = io::BufWriter::new(std::fs::File::create(filename)?);
+    let writer = png_encoder(output, bounds, text)?
+        .write_header()
+        .and_then(png::Writer::into_stream_writer)
+        .map_err(io::Error::other)?;
+    Ok(ImageStream::Png(Box::new(writer)))
+}
+
+#[cfg(not(feature = "image"))]
+fn create_png_stream(
+    _filename: &str,
+    _bounds: (usize, usize),
+    _text: &[(&str, String)],
+) -> io::Result<ImageStream> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "writing images needs raytracer to be built with the \"image\" feature",
+    ))
+}
+
+// 8-bit RGB pixels and the size of any image file the image crate can decode.
+#[cfg(feature = "image")]
+pub(crate) fn read_image(filename: &str) -> io::Result<(Vec<u8>, (usize, usize))> {
+    let image = image::open(filename)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        .to_rgb8();
+    let bounds = (image.width() as usize, image.height() as usize);
+    Ok((image.into_raw(), bounds))
+}
+
+#[cfg(not(feature = "image"))]
+pub(crate) fn read_image(_filename: &str) -> io::Result<(Vec<u8>, (usize, usize))> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reading images needs raytracer to be built with the \"image\" feature",
+    ))
+}
+
+// The width and height of an image file, reading no more than its header.
+#[cfg(feature = "image")]
+pub(crate) fn read_image_size(filename: &str) -> io::Result<(usize, usize)> {
+    let (width, height) = image::image_dimensions(filename)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok((width as usize, height as usize))
+}
+
+#[cfg(not(feature = "image"))]
+pub(crate) fn read_image_size(_filename: &str) -> io::Result<(usize, usize)> {
+    read_image(_filename).map(|(_, bounds)| bounds)
+}
+
+// An 8-bit image file as linear colors, e.g. for a texture.
+pub(crate) fn read_linear_image(filename: &str) -> io::Result<Film> {
+    let (pixels, (width, height)) = read_image(filename)?;
+    let pixels = pixels
+        .chunks(3)
+        .map(|p| {
+            let linear = |c: u8| gamma_to_linear(c as f64 / 255.0);
+            Color::new(linear(p[0]), linear(p[1]), linear(p[2]))
+        })
+        .collect();
+    Ok(Film {
+        width,
+        height,
+        pixels,
+    })
+}
+
+// Linear colors of a rendered image, row by row from the top left.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Film {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Color>,
+}
+
+impl Film {
+    pub fn new(width: usize, height: usize) -> Film {
+        Film {
+            width,
+            height,
+            pixels: vec![Color::default(); width * height],
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Color {
+        self.pixels[y * self.width + x]
+    }
+
+    // Bilinear lookup at continuous pixel coordinates, where (0.5, 0.5) is the
+    // center of the top left pixel. Coordinates outside the film are clamped.
+    pub fn sample(&self, x: f64, y: f64) -> Color {
+        let x = (x - 0.5).clamp(0.0, (self.width - 1) as f64);
+        let y = (y - 0.5).clamp(0.0, (self.height - 1) as f64);
+        let (x0, y0) = (x.floor() as usize, y.floor() as usize);
+        let (x1, y1) = ((x0 + 1).min(self.width - 1), (y0 + 1).min(self.height - 1));
+        let (fx, fy) = (x - x0 as f64, y - y0 as f64);
+
+        let top = (1.0 - fx) * self.get(x0, y0) + fx * self.get(x1, y0);
+        let bottom = (1.0 - fx) * self.get(x0, y1) + fx * self.get(x1, y1);
+        (1.0 - fy) * top + fy * bottom
+    }
+
+    // Averages the pixels falling into each pixel of a smaller film.
+    pub fn downsample(&self, width: usize, height: usize) -> Film {
+        let mut small = Film::new(width, height);
+        for y in 0..height {
+            let (y0, y1) = (y * self.height / height, (y + 1) * self.height / height);
+            for x in 0..width {
+                let (x0, x1) = (x * self.width / width, (x + 1) * self.width / width);
+                let (x1, y1) = (x1.max(x0 + 1), y1.max(y0 + 1));
+                let mut sum = Color::default();
+                for sy in y0..y1 {
+                    for sx in x0..x1 {
+                        sum += self.get(sx, sy);
+                    }
+                }
+                small.pixels[y * width + x] = sum / ((x1 - x0) * (y1 - y0)) as f64;
+            }
+        }
+        small
+    }
+
+    // Gamma corrected 8-bit RGB, as written to PNG files.
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        self.to_rgb8_with(Clip::Channel)
+    }
+
+    // Like `to_rgb8`, bringing bright colors into range with `clip`.
+    pub fn to_rgb8_with(&self, clip: Clip) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(self.pixels.len() * 3);
+        for pixel_color in &self.pixels {
+            write_color_with(&mut buffer, *pixel_color, clip)
+                .expect("writing to a Vec cannot fail");
+        }
+        buffer
+    }
+
+    // Like `to_rgb8_with` with an opaque alpha channel, e.g. for an HTML
+    // canvas.
+    pub fn to_rgba8_with(&self, clip: Clip) -> Vec<u8> {
+        self.to_rgb8_with(clip)
+            .chunks(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect()
+    }
+
+    // The linear colors `clip` leaves, e.g. to downsample the image as it
+    // is written.
+    pub fn clipped(&self, clip: Clip) -> Film {
+        Film {
+            width: self.width,
+            height: self.height,
+            pixels: self.pixels.iter().map(|c| clip.apply(*c)).collect(),
+        }
+    }
+
+    // Interleaved RGB floats, top row first: the float3 image layout Open
+    // Image Denoise and the OptiX denoiser take.
+    pub fn to_f32(&self) -> Vec<f32> {
+        self.pixels
+            .iter()
+            .flat_map(|c| [c.x() as f32, c.y() as f32, c.z() as f32])
+            .collect()
+    }
+
+    pub fn save(&self, filename: &str) -> io::Result<()> {
+        self.save_with_text(filename, &[])
+    }
+
+    // Linear floats as a PFM file, the format the denoisers' command line
+    // tools read. PFM rows go from the bottom up.
+    pub fn save_pfm(&self, filename: &str) -> io::Result<()> {
+        let mut data = format!("PF\n{} {}\n-1.0\n", self.width, self.height).into_bytes();
+        for row in self.to_f32().chunks(self.width * 3).rev() {
+            data.extend(row.iter().flat_map(|v| v.to_le_bytes()));
+        }
+        std::fs::write(filename, data)
+    }
+
+    pub fn read_pfm(filename: &str) -> io::Result<Film> {
+        let data = std::fs::read(filename)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not an RGB PFM file");
+        // Three whitespace separated header lines, then the floats.
+        let mut fields = Vec::new();
+        let mut start = 0;
+        for (i, byte) in data.iter().enumerate() {
+            if byte.is_ascii_whitespace() {
+                if i > start {
+                    fields.push(std::str::from_utf8(&data[start..i]).map_err(|_| invalid())?);
+                }
+                start = i + 1;
+                if fields.len() == 4 {
+                    break;
+                }
+            }
+        }
+        let [kind, width, height, scale] = fields[..] else {
+            return Err(invalid());
+        };
+        let (width, height): (usize, usize) = match (width.parse(), height.parse()) {
+            (Ok(width), Ok(height)) if kind == "PF" => (width, height),
+            _ => return Err(invalid()),
+        };
+        let little_endian = scale.parse::<f32>().map_err(|_| invalid())? < 0.0;
+        let floats = &data[start..];
+        if floats.len() != width * height * 12 {
+            return Err(invalid());
+        }
+        let value = |bytes: &[u8]| {
+            let bytes = bytes.try_into().unwrap();
+            f64::from(if little_endian {
+                f32::from_le_bytes(bytes)
+            } else {
+                f32::from_be_bytes(bytes)
+            })
+        };
+        let mut film = Film::new(width, height);
+        for (y, row) in floats.chunks(width * 12).rev().enumerate() {
+            for (x, rgb) in row.chunks(12).enumerate() {
+                film.pixels[y * width + x] =
+                    Color::new(value(&rgb[0..4]), value(&rgb[4..8]), value(&rgb[8..12]));
+            }
+        }
+        Ok(film)
+    }
+
+    // Like `save`, adding `text` to the file, see `RenderMetadata`. Files
+    // ending in .exr get the linear colors as a tiled EXR, anything else an
+    // 8-bit PNG.
+    pub fn save_with_text(&self, filename: &str, text: &[(&str, String)]) -> io::Result<()> {
+        self.save_with(filename, text, Clip::Channel)
+    }
+
+    // Like `save_with_text`, bringing bright colors into range with `clip`.
+    // EXR files keep them as they are.
+    pub fn save_with(&self, filename: &str, text: &[(&str, String)], clip: Clip) -> io::Result<()> {
+        let _span = tracing::info_span!("encode", filename).entered();
+        if exr::is_exr(filename) {
+            exr::write(filename, self, text)?;
+        } else {
+            let rgb = self.to_rgb8_with(clip);
+            write_image_with_text(filename, &rgb, (self.width, self.height), text)?;
+        }
+        tracing::info!("image written");
+        Ok(())
+    }
+}
+
+#[test]
+fn test_sample() {
+    let mut film = Film::new(2, 1);
+    film.pixels[1] = Color::new(1.0, 1.0, 1.0);
+    assert_eq!(film.sample(0.5, 0.5), Color::new(0.0, 0.0, 0.0));
+    assert_eq!(film.sample(1.0, 0.5).x(), 0.5);
+    assert_eq!(film.sample(5.0, -3.0), Color::new(1.0, 1.0, 1.0));
+}
+
+#[test]
+fn test_pfm() {
+    let mut film = Film::new(3, 2);
+    film.pixels[0] = Color::new(2.5, 0.0, -1.0);
+    film.pixels[5] = Color::new(0.25, 100.0, 0.5);
+    assert_eq!(film.to_f32()[..3], [2.5, 0.0, -1.0]);
+
+    let filename = std::env::temp_dir().join(format!("raytracer-test-{}.pfm", std::process::id()));
+    let filename = filename.to_str().unwrap();
+    film.save_pfm(filename).unwrap();
+    let data = std::fs::read(filename).unwrap();
+    assert!(data.starts_with(b"PF\n3 2\n-1.0\n"));
+    // The bottom row comes first.
+    assert_eq!(data[data.len() - 3 * 12..][..4], 2.5f32.to_le_bytes());
+    assert_eq!(Film::read_pfm(filename).unwrap(), film);
+    std::fs::remove_file(filename).unwrap();
+}
+
+#[test]
+fn test_downsample() {
+    let mut film = Film::new(4, 2);
+    film.pixels[0] = Color::new(4.0, 0.0, 0.0);
+    let small = film.downsample(2, 1);
+    assert_eq!(small.get(0, 0), Color::new(1.0, 0.0, 0.0));
+    assert_eq!(small.get(1, 0), Color::new(0.0, 0.0, 0.0));
+    assert_eq!(film.downsample(8, 4).get(1, 1), Color::new(4.0, 0.0, 0.0));
+}
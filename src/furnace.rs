@@ -0,0 +1,141 @@
+// The white furnace test: a sphere lit by a uniformly white environment
+// reflects exactly its albedo, because every path that leaves it sees the
+// same light. A material that comes out brighter creates energy, one that
+// comes out darker loses some, both usually a sign of a wrongly normalized
+// scatter function.
+
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable, ObjectList};
+use crate::interval::Interval;
+use crate::material::{Material, Metal};
+use crate::ray::Ray;
+use crate::testing;
+use crate::vec3::{Point3D, Vec3};
+
+// Most materials are exact up to the sampling noise of the fuzzy ones.
+pub const TOLERANCE: f64 = 0.01;
+
+#[derive(Debug, Clone)]
+pub struct FurnaceResult {
+    pub material: Material,
+    pub expected: Color,
+    // False for materials that are allowed to lose energy, see `expected`.
+    pub exact: bool,
+    pub measured: Color,
+}
+
+impl FurnaceResult {
+    // Never brighter than the expected albedo or than white, and for exact
+    // materials not darker either.
+    pub fn passed(&self) -> bool {
+        let channels = |c: Color| [c.x(), c.y(), c.z()];
+        channels(self.expected)
+            .into_iter()
+            .zip(channels(self.measured))
+            .all(|(expected, measured)| {
+                measured <= expected.min(1.0) + TOLERANCE
+                    && (!self.exact || measured >= expected - TOLERANCE)
+            })
+    }
+}
+
+// The albedo a material should reflect in the furnace. Fuzzy metal scatters
+// some rays below the surface and absorbs them, so it may come out darker.
+// Lights, textured surfaces and unresolved references can't be tested.
+fn expected(material: &Material) -> Option<(Color, bool)> {
+    match material {
+        Material::Lambertian(l) if l.texture.is_some() => None,
+        Material::Lambertian(l) => Some((l.albedo, true)),
+        Material::Metal(m) => Some((m.albedo, m.fuzz == 0.0)),
+        Material::Glass(_) => Some((Color::new(1.0, 1.0, 1.0), true)),
+        // Hair loses the light that leaves a fiber after more than one
+        // reflection inside it.
+        // Volume materials scatter as much into the surface as out of it.
+        Material::DiffuseLight(_)
+        | Material::Hair(_)
+        | Material::Medium(_)
+        | Material::Named(_) => None,
+    }
+}
+
+// Averages `samples` rays shot at a unit sphere made of `material`. None for
+// materials without an expected albedo.
+pub fn furnace(material: &Material, samples: usize) -> Option<FurnaceResult> {
+    let (expected, exact) = expected(material)?;
+    let mut world = ObjectList::new();
+    world.add_sphere(Point3D::new(0.0, 0.0, 0.0), 1.0, material.clone());
+
+    // Every ray aims at a point inside the sphere's silhouette, so all of
+    // them hit it.
+    // The sphere is convex, so what leaves it after the first bounce sees
+    // the white sky, and glass, the only material going inside, absorbs
+    // nothing there.
+    let origin = Point3D::new(0.0, 0.0, 3.0);
+    let mut sum = Color::default();
+    for _ in 0..samples {
+        let target = 0.999 * Vec3::random_in_unit_disk();
+        let r = Ray::new(origin, target - origin);
+        let mut rec = HitRecord::default();
+        if world.hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec) {
+            sum += testing::white_furnace(material, &r, &rec, 1);
+        }
+    }
+    Some(FurnaceResult {
+        material: material.clone(),
+        expected,
+        exact,
+        measured: sum / samples as f64,
+    })
+}
+
+// One of each material, for checking the materials themselves rather than a
+// scene.
+pub fn builtin_materials() -> Vec<(&'static str, Material)> {
+    use crate::material::{Glass, Lambertian};
+
+    vec![
+        (
+            "grey",
+            Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        ),
+        (
+            "red",
+            Material::Lambertian(Lambertian::new(Color::new(0.9, 0.2, 0.1))),
+        ),
+        (
+            "mirror",
+            Material::Metal(Metal::new(Color::new(0.8, 0.8, 0.8), 0.0)),
+        ),
+        (
+            "brushed_gold",
+            Material::Metal(Metal::new(Color::new(0.8, 0.6, 0.2), 0.5)),
+        ),
+        ("glass", Material::Glass(Glass::new(1.5))),
+        ("diamond", Material::Glass(Glass::new(2.4))),
+    ]
+}
+
+#[test]
+fn test_builtin_materials() {
+    for (_, material) in builtin_materials() {
+        let result = furnace(&material, 2000).unwrap();
+        assert!(result.passed(), "{:?}", result);
+    }
+}
+
+#[test]
+fn test_energy_creation_fails() {
+    use crate::material::Lambertian;
+
+    let bright = Material::Lambertian(Lambertian::new(Color::new(1.2, 0.5, 0.5)));
+    let result = furnace(&bright, 100).unwrap();
+    assert!((result.measured.x() - 1.2).abs() < 1e-9);
+    assert!(!result.passed());
+
+    let mut lossy = furnace(&builtin_materials()[0].1, 100).unwrap();
+    lossy.measured *= 0.9;
+    assert!(!lossy.passed());
+
+    let light = Material::DiffuseLight(crate::material::DiffuseLight::new(Color::default()));
+    assert!(furnace(&light, 100).is_none());
+}
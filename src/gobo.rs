@@ -0,0 +1,98 @@
+// Gobos, or cookies: an image in front of a light, like a stage light
+// projecting a pattern or sun coming through a window frame. The image is
+// spread over a square cone around the light's direction and tints the light
+// leaving in each direction; outside the cone the light is dark.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::film::{read_linear_image, Film};
+use crate::vec3::Vec3;
+
+fn default_angle() -> f64 {
+    30.0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Gobo {
+    pub file: String,
+    // The center of the projected image. The image's right side is towards
+    // +x, or +y for lights pointing along the x axis.
+    #[serde(default = "crate::ies::down")]
+    pub direction: Vec3,
+    // Between opposite edges of the image, in degrees.
+    #[serde(default = "default_angle")]
+    pub angle: f64,
+    // In linear colors.
+    #[serde(skip)]
+    pub image: Option<Arc<Film>>,
+}
+
+impl Gobo {
+    // Reads the image and returns the path it was read from.
+    pub fn load(&mut self, base_dir: &Path) -> io::Result<PathBuf> {
+        let path = base_dir.join(&self.file);
+        let image = read_linear_image(&path.to_string_lossy())
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        self.image = Some(Arc::new(image));
+        Ok(path)
+    }
+
+    // The tint of light leaving towards `towards`; white until loaded.
+    pub fn color(&self, towards: Vec3) -> Color {
+        let Some(image) = &self.image else {
+            return Color::new(1.0, 1.0, 1.0);
+        };
+        let w = self.direction.unit_vector();
+        let (u, v) = w.perpendiculars();
+        let d = towards.unit_vector();
+        let forward = d.dot(&w);
+        if forward <= 0.0 {
+            return Color::default();
+        }
+
+        // -1 to 1 across the image, y up.
+        let half = (self.angle / 2.0).to_radians().tan();
+        let x = d.dot(&u) / forward / half;
+        let y = d.dot(&v) / forward / half;
+        if x.abs() > 1.0 || y.abs() > 1.0 {
+            return Color::default();
+        }
+        image.sample(
+            (x + 1.0) / 2.0 * image.width as f64,
+            (1.0 - y) / 2.0 * image.height as f64,
+        )
+    }
+}
+
+#[test]
+fn test_color() {
+    let mut gobo = Gobo {
+        file: String::new(),
+        direction: Vec3::new(0.0, 0.0, -1.0),
+        angle: 90.0,
+        image: None,
+    };
+    assert_eq!(
+        gobo.color(Vec3::new(0.0, 0.0, 1.0)),
+        Color::new(1.0, 1.0, 1.0)
+    );
+
+    // Red on the left half, blue on the right.
+    let mut image = Film::new(2, 1);
+    image.pixels[0] = Color::new(1.0, 0.0, 0.0);
+    image.pixels[1] = Color::new(0.0, 0.0, 1.0);
+    gobo.image = Some(Arc::new(image));
+    let (u, _) = gobo.direction.perpendiculars();
+
+    let toward = |x: f64| Vec3::new(0.0, 0.0, -1.0) + x * u;
+    assert_eq!(gobo.color(toward(-0.9)), Color::new(1.0, 0.0, 0.0));
+    assert_eq!(gobo.color(toward(0.9)), Color::new(0.0, 0.0, 1.0));
+    // Outside the 90 degree cone and behind the light.
+    assert_eq!(gobo.color(toward(1.1)), Color::default());
+    assert_eq!(gobo.color(Vec3::new(0.0, 0.0, 1.0)), Color::default());
+}
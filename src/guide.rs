@@ -0,0 +1,342 @@
+// Path guiding: learns from a few quick training renders where the light
+// that reaches each part of the scene comes from, then draws some of the
+// diffuse bounces of the real render from that instead of from the cosine
+// distribution alone. Scenes lit mostly indirectly, e.g. through a gap or by
+// a bright wall, converge much faster.
+//
+// The scene is split into a grid of cells, each with a histogram of the
+// incident radiance over equal-area bins of the sphere of directions. Every
+// training render samples from the histograms of the previous one, so they
+// get sharper as they go, like the iterations of "Practical Path Guiding"
+// (Müller et al. 2017) without its adaptive trees. Half the diffuse rays stay
+// cosine distributed, so directions the guide missed are still found and the
+// image stays unbiased.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::camera::Camera;
+use crate::hittable::{HitRecord, Hittable, ObjectList};
+use crate::interval::Interval;
+use crate::parallel::*;
+use crate::rng;
+use crate::vec3::{Point3D, Vec3};
+
+// Cells along each axis of the grid.
+const RESOLUTION: usize = 8;
+// Bins in cos(theta) around the y axis and in phi.
+const THETA_BINS: usize = 8;
+const PHI_BINS: usize = 16;
+const BINS: usize = THETA_BINS * PHI_BINS;
+// Share of the diffuse rays drawn from the guide.
+pub const GUIDED_FRACTION: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Guiding {
+    // The training renders use 1, 2, 4, ... samples per pixel.
+    #[serde(default = "default_training_passes")]
+    pub training_passes: usize,
+}
+
+fn default_training_passes() -> usize {
+    4
+}
+
+impl Default for Guiding {
+    fn default() -> Self {
+        Guiding {
+            training_passes: default_training_passes(),
+        }
+    }
+}
+
+thread_local! {
+    // The radiance `Guide::record` adds up on this thread, by slot, while a
+    // training render runs `recording`.
+    static RECORDED: RefCell<Option<HashMap<usize, f64>>> = const { RefCell::new(None) };
+}
+
+// What the training renders record while `f` runs on this thread.
+fn recording(f: impl FnOnce()) -> HashMap<usize, f64> {
+    RECORDED.with(|recorded| *recorded.borrow_mut() = Some(HashMap::new()));
+    f();
+    RECORDED.with(|recorded| recorded.borrow_mut().take().unwrap_or_default())
+}
+
+// The learned distribution of one cell, as the cumulative share of its bins.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CellDistribution {
+    cdf: [f64; BINS],
+}
+
+impl CellDistribution {
+    fn new(weights: &[f64]) -> Option<CellDistribution> {
+        let total: f64 = weights.iter().sum();
+        if !(total > 0.0 && total.is_finite()) {
+            return None;
+        }
+        let mut cdf = [0.0; BINS];
+        let mut sum = 0.0;
+        for (c, w) in cdf.iter_mut().zip(weights) {
+            sum += w / total;
+            *c = sum;
+        }
+        cdf[BINS - 1] = 1.0;
+        Some(CellDistribution { cdf })
+    }
+
+    fn probability(&self, bin: usize) -> f64 {
+        self.cdf[bin] - if bin == 0 { 0.0 } else { self.cdf[bin - 1] }
+    }
+
+    pub fn sample(&self) -> Vec3 {
+        let u = rng::random();
+        let bin = self.cdf.partition_point(|&c| c <= u).min(BINS - 1);
+        let (theta, phi) = (bin / PHI_BINS, bin % PHI_BINS);
+        let cos_theta = -1.0 + 2.0 * (theta as f64 + rng::random()) / THETA_BINS as f64;
+        let phi = 2.0 * PI * (phi as f64 + rng::random()) / PHI_BINS as f64;
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        Vec3::new(sin_theta * phi.cos(), cos_theta, sin_theta * phi.sin())
+    }
+
+    // Per solid angle; every bin covers 4π / BINS.
+    pub fn pdf(&self, direction: Vec3) -> f64 {
+        self.probability(bin(direction)) * BINS as f64 / (4.0 * PI)
+    }
+}
+
+fn bin(direction: Vec3) -> usize {
+    let d = direction.unit_vector();
+    let theta = ((d.y() + 1.0) / 2.0 * THETA_BINS as f64) as usize;
+    let phi = d.z().atan2(d.x()).rem_euclid(2.0 * PI) / (2.0 * PI) * PHI_BINS as f64;
+    theta.min(THETA_BINS - 1) * PHI_BINS + (phi as usize).min(PHI_BINS - 1)
+}
+
+#[derive(Debug)]
+pub struct Guide {
+    bounds: Aabb,
+    cells: Vec<Option<CellDistribution>>,
+    // Whether this is a training render's guide, which records radiance.
+    training: bool,
+}
+
+impl Guide {
+    fn new(bounds: Aabb, cells: Vec<Option<CellDistribution>>, training: bool) -> Guide {
+        Guide {
+            bounds,
+            cells,
+            training,
+        }
+    }
+
+    // Runs the training renders of `guiding` and leaves the guide learned
+    // from them in `world`.
+    pub fn train(camera: &Camera, world: &mut ObjectList, guiding: Guiding) {
+        let bounds = visible_bounds(camera, world);
+        let mut cells = vec![None; RESOLUTION * RESOLUTION * RESOLUTION];
+        let mut training = *camera;
+        training.guiding = None;
+        for pass in 0..guiding.training_passes {
+            let guide = Arc::new(Guide::new(bounds, cells, true));
+            world.guide = Some(guide.clone());
+            training.set_samples_per_pixel(1 << pass.min(10));
+            training.seed = camera.seed.wrapping_add(pass as u64 + 1);
+            // Every row adds up its own radiance and the rows are added in
+            // order, so the guide and the image come out the same on any
+            // number of threads.
+            let world = &*world;
+            let rows: Vec<HashMap<usize, f64>> = (0..training.height())
+                .into_par_iter()
+                .map(|j| {
+                    recording(|| {
+                        for i in 0..training.width() {
+                            training.render_pixel(i, j, world);
+                        }
+                    })
+                })
+                .collect();
+            let mut weights = vec![0.0; guide.cells.len() * BINS];
+            for row in rows {
+                for (slot, radiance) in row {
+                    weights[slot] += radiance;
+                }
+            }
+            cells = guide.learned(&weights);
+        }
+        world.guide = Some(Arc::new(Guide::new(bounds, cells, false)));
+    }
+
+    // What the radiance recorded by a training render, by slot, says, or
+    // the previous distribution of cells it didn't reach.
+    fn learned(&self, weights: &[f64]) -> Vec<Option<CellDistribution>> {
+        weights
+            .chunks(BINS)
+            .zip(&self.cells)
+            .map(|(bins, previous)| CellDistribution::new(bins).or_else(|| previous.clone()))
+            .collect()
+    }
+
+    fn cell_index(&self, p: Point3D) -> usize {
+        [p.x(), p.y(), p.z()]
+            .iter()
+            .enumerate()
+            .map(|(axis, v)| {
+                let interval = self.bounds.axis(axis);
+                let t = (v - interval.min) / interval.size();
+                ((t * RESOLUTION as f64).max(0.0) as usize).min(RESOLUTION - 1)
+            })
+            .fold(0, |index, i| index * RESOLUTION + i)
+    }
+
+    // The distribution to draw diffuse rays leaving `p` from, if anything
+    // was learned there.
+    pub fn cell(&self, p: Point3D) -> Option<&CellDistribution> {
+        self.cells[self.cell_index(p)].as_ref()
+    }
+
+    // Adds `radiance` arriving at `p` from `direction`, already divided by
+    // the density the direction was sampled with. Ignored once trained.
+    pub fn record(&self, p: Point3D, direction: Vec3, radiance: f64) {
+        if !(self.training && radiance > 0.0 && radiance.is_finite()) {
+            return;
+        }
+        let slot = self.cell_index(p) * BINS + bin(direction);
+        RECORDED.with(|recorded| {
+            if let Some(recorded) = recorded.borrow_mut().as_mut() {
+                *recorded.entry(slot).or_default() += radiance;
+            }
+        });
+    }
+}
+
+// Where the camera's rays first hit something, so the grid isn't spread over
+// a huge ground sphere.
+fn visible_bounds(camera: &Camera, world: &ObjectList) -> Aabb {
+    let mut bounds = Aabb::EMPTY;
+    for j in (0..camera.height()).step_by(4) {
+        for i in (0..camera.width()).step_by(4) {
+            let mut rec = HitRecord::default();
+            if world.hit(
+                &camera.pixel_ray(i, j),
+                &Interval::new(0.001, f64::INFINITY),
+                &mut rec,
+            ) {
+                bounds = Aabb::surrounding_box(&bounds, &Aabb::from_points(rec.p, rec.p));
+            }
+        }
+    }
+    if bounds.is_empty() {
+        return world.bounding_box();
+    }
+    let margin = 0.1 * (bounds.max() - bounds.min()).length().max(1.0);
+    Aabb::new(
+        bounds.x.expand(margin),
+        bounds.y.expand(margin),
+        bounds.z.expand(margin),
+    )
+}
+
+#[test]
+fn test_distribution() {
+    let mut weights = [0.0; BINS];
+    let up = bin(Vec3::new(0.1, 1.0, 0.1));
+    weights[up] = 1.0;
+    let cell = CellDistribution::new(&weights).unwrap();
+    for _ in 0..100 {
+        let direction = cell.sample();
+        assert_eq!(bin(direction), up);
+        assert!((cell.pdf(direction) - BINS as f64 / (4.0 * PI)).abs() < 1e-9);
+    }
+    assert_eq!(cell.pdf(Vec3::new(0.0, -1.0, 0.0)), 0.0);
+    assert!(CellDistribution::new(&[0.0; BINS]).is_none());
+
+    // Uniform weights give the uniform density over the sphere.
+    let uniform = CellDistribution::new(&[1.0; BINS]).unwrap();
+    assert!((uniform.pdf(Vec3::random_unit_vector()) - 1.0 / (4.0 * PI)).abs() < 1e-9);
+}
+
+#[test]
+fn test_record() {
+    let bounds = Aabb::from_points(Point3D::new(0.0, 0.0, 0.0), Point3D::new(8.0, 8.0, 8.0));
+    let guide = Guide::new(
+        bounds,
+        vec![None; RESOLUTION * RESOLUTION * RESOLUTION],
+        true,
+    );
+    let p = Point3D::new(1.5, 2.5, 3.5);
+    assert!(guide.cell(p).is_none());
+    let recorded = recording(|| {
+        guide.record(p, Vec3::new(0.0, 1.0, 0.0), 2.0);
+        guide.record(p, Vec3::new(0.0, 1.0, 0.0), f64::NAN);
+    });
+    assert_eq!(recorded.len(), 1);
+    let mut weights = vec![0.0; guide.cells.len() * BINS];
+    for (slot, radiance) in recorded {
+        weights[slot] += radiance;
+    }
+    let cells = guide.learned(&weights);
+    let cell = cells[guide.cell_index(p)].as_ref().unwrap();
+    assert_eq!(cell.probability(bin(Vec3::new(0.0, 1.0, 0.0))), 1.0);
+    assert_eq!(cells.iter().filter(|c| c.is_some()).count(), 1);
+    // Points outside the grid use the nearest cell.
+    assert_eq!(guide.cell_index(Point3D::new(-5.0, 100.0, 0.0)), 7 * 8);
+}
+
+#[test]
+fn test_train() {
+    use crate::color::Color;
+    use crate::material::{Lambertian, Material};
+    use crate::ray::Ray;
+
+    // Under a big ball that hides most of the sky.
+    let grey = Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let mut world = ObjectList::new();
+    world
+        .add_sphere(Point3D::new(0.0, -1000.0, 0.0), 1000.0, grey.clone())
+        .add_sphere(Point3D::new(0.0, 4.0, 0.0), 3.0, grey);
+    let camera = Camera::new(
+        16,
+        16,
+        1,
+        8,
+        90.0,
+        Point3D::new(0.0, 0.5, 1.0),
+        Point3D::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let r = Ray::new(Point3D::new(0.0, 0.5, 1.0), Vec3::new(0.0, -0.5, -1.0));
+    let stats = |world: &ObjectList| {
+        let samples = 20_000;
+        let values: Vec<f64> = (0..samples)
+            .map(|_| camera.ray_color(&r, camera.max_depth(), world).y())
+            .collect();
+        let mean = values.iter().sum::<f64>() / samples as f64;
+        let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / samples as f64;
+        (mean, variance)
+    };
+    let without = stats(&world);
+
+    Guide::train(&camera, &mut world, Guiding::default());
+    let guide = world.guide.clone().unwrap();
+    assert!(!guide.training);
+    // Training again learns exactly the same.
+    Guide::train(&camera, &mut world, Guiding::default());
+    assert_eq!(world.guide.as_ref().unwrap().cells, guide.cells);
+    assert!(guide.cell(Point3D::new(0.0, 0.0, 0.0)).is_some());
+    let with = stats(&world);
+    // The same image, with less noise.
+    assert!(
+        (with.0 - without.0).abs() < 0.05 * without.0,
+        "{:?} {:?}",
+        with,
+        without
+    );
+    assert!(with.1 < without.1, "{:?} {:?}", with, without);
+}
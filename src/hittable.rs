@@ -1,10 +1,31 @@
+use crate::aabb::Aabb;
+use crate::atmosphere::Atmosphere;
+use crate::backplate::Backplate;
+use crate::capsule::Capsule;
+use crate::clip::{clip, ClipPlane};
 use crate::color::Color;
+use crate::curves::Curves;
+use crate::custom::Custom;
+use crate::environment::Environment;
+use crate::guide::Guide;
+use crate::instance::Instance;
 use crate::interval::Interval;
+use crate::light_link::ObjectSet;
 use crate::material::{Lambertian, Material};
-use crate::ray::Ray;
+use crate::mesh::MeshShape;
+use crate::point_cloud::PointCloud;
+use crate::portal::Portal;
+use crate::quadric::Quadric;
+use crate::ray::{Ray, RayKind};
+use crate::sdf::Sdf;
+use crate::sky::GradientSky;
 use crate::sphere::Sphere;
+use crate::sun::Sun;
 use crate::vec3::{Point3D, Vec3};
+use crate::volume::Volume;
+use crate::water::Water;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 #[derive(Debug, Clone)]
 pub struct HitRecord {
@@ -13,6 +34,17 @@ pub struct HitRecord {
     pub mat: Material,
     pub t: f64,
     pub front_face: bool,
+    // Index of the hit object in its ObjectList.
+    pub object: usize,
+    // Texture coordinates, and how the point moves as they change. Zero on
+    // shapes without them.
+    pub u: f64,
+    pub v: f64,
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    // From the point to the nearest edge of the triangle hit, for the
+    // wireframe overlay. None on shapes without edges.
+    pub edge: Option<Vec3>,
 }
 
 impl HitRecord {
@@ -34,36 +66,427 @@ impl Default for HitRecord {
             mat: Material::Lambertian(Lambertian::new(Color::new(0.0, 0.0, 0.0))),
             t: 0.0,
             front_face: false,
+            object: 0,
+            u: 0.0,
+            v: 0.0,
+            dpdu: Vec3::default(),
+            dpdv: Vec3::default(),
+            edge: None,
         }
     }
 }
 
 pub trait Hittable {
     fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool;
+
+    // Whether anything is hit at all, for shadow rays. Shapes can skip
+    // finding the closest hit and filling in a record.
+    fn occluded(&self, r: &Ray, ray_t: &Interval) -> bool {
+        self.hit(r, ray_t, &mut HitRecord::default())
+    }
+
+    fn bounding_box(&self) -> Aabb;
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub enum Object {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Shape {
     Sphere(Sphere),
+    Quadric(Quadric),
+    Capsule(Capsule),
+    Sdf(Sdf),
+    Mesh(MeshShape),
+    Curves(Curves),
+    PointCloud(PointCloud),
+    Volume(Volume),
+    Water(Water),
+    Instance(Box<Instance>),
+    // Only scene files can't hold custom shapes without the "json" feature.
+    #[cfg_attr(not(feature = "json"), serde(skip))]
+    Custom(Custom),
 }
 
-impl Hittable for Object {
+impl Shape {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Shape::Sphere(_) => "sphere",
+            Shape::Quadric(_) => "quadric",
+            Shape::Capsule(_) => "capsule",
+            Shape::Sdf(_) => "sdf",
+            Shape::Mesh(_) => "mesh",
+            Shape::Curves(_) => "curves",
+            Shape::PointCloud(_) => "point_cloud",
+            Shape::Volume(_) => "volume",
+            Shape::Water(_) => "water",
+            Shape::Instance(_) => "instance",
+            Shape::Custom(_) => "custom",
+        }
+    }
+
+    // Surface area, where it is known exactly.
+    pub fn area(&self) -> Option<f64> {
+        match self {
+            Shape::Sphere(sphere) => {
+                Some(4.0 * std::f64::consts::PI * sphere.radius * sphere.radius)
+            }
+            Shape::Capsule(capsule) => Some(capsule.area()),
+            Shape::Mesh(mesh) => Some(mesh.area()),
+            Shape::Quadric(_)
+            | Shape::Sdf(_)
+            | Shape::Curves(_)
+            | Shape::PointCloud(_)
+            | Shape::Volume(_)
+            | Shape::Water(_) => None,
+            Shape::Instance(instance) => instance.area(),
+            Shape::Custom(_) => None,
+        }
+    }
+
+    pub fn material(&self) -> &Material {
+        match self {
+            Shape::Sphere(sphere) => &sphere.material,
+            Shape::Quadric(quadric) => &quadric.material,
+            Shape::Capsule(capsule) => &capsule.material,
+            Shape::Sdf(sdf) => &sdf.material,
+            Shape::Mesh(mesh) => &mesh.material,
+            Shape::Curves(curves) => &curves.material,
+            Shape::PointCloud(cloud) => &cloud.material,
+            Shape::Volume(volume) => &volume.material,
+            Shape::Water(water) => &water.material,
+            Shape::Instance(instance) => instance.shape.material(),
+            Shape::Custom(custom) => &custom.material,
+        }
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        match self {
+            Shape::Sphere(sphere) => &mut sphere.material,
+            Shape::Quadric(quadric) => &mut quadric.material,
+            Shape::Capsule(capsule) => &mut capsule.material,
+            Shape::Sdf(sdf) => &mut sdf.material,
+            Shape::Mesh(mesh) => &mut mesh.material,
+            Shape::Curves(curves) => &mut curves.material,
+            Shape::PointCloud(cloud) => &mut cloud.material,
+            Shape::Volume(volume) => &mut volume.material,
+            Shape::Water(water) => &mut water.material,
+            Shape::Instance(instance) => instance.shape.material_mut(),
+            Shape::Custom(custom) => &mut custom.material,
+        }
+    }
+
+    // Switches meshes to their level of detail for the distance from `eye`
+    // to the middle of the shape, after any instance's transform.
+    pub fn select_lods(&mut self, eye: Point3D) {
+        let distance = (self.bounding_box().centroid() - eye).length();
+        self.select_lod(distance);
+    }
+
+    fn select_lod(&mut self, distance: f64) {
+        match self {
+            Shape::Mesh(mesh) => mesh.select_lod(distance),
+            Shape::Instance(instance) => instance.shape.select_lod(distance),
+            _ => {}
+        }
+    }
+}
+
+impl Hittable for Shape {
     fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
         match self {
-            Object::Sphere(sphere) => sphere.hit(r, ray_t, rec),
+            Shape::Sphere(sphere) => sphere.hit(r, ray_t, rec),
+            Shape::Quadric(quadric) => quadric.hit(r, ray_t, rec),
+            Shape::Capsule(capsule) => capsule.hit(r, ray_t, rec),
+            Shape::Sdf(sdf) => sdf.hit(r, ray_t, rec),
+            Shape::Mesh(mesh) => mesh.hit(r, ray_t, rec),
+            Shape::Curves(curves) => curves.hit(r, ray_t, rec),
+            Shape::PointCloud(cloud) => cloud.hit(r, ray_t, rec),
+            Shape::Volume(volume) => volume.hit(r, ray_t, rec),
+            Shape::Water(water) => water.hit(r, ray_t, rec),
+            Shape::Instance(instance) => instance.hit(r, ray_t, rec),
+            Shape::Custom(custom) => custom.hit(r, ray_t, rec),
+        }
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: &Interval) -> bool {
+        match self {
+            Shape::Sphere(sphere) => sphere.occluded(r, ray_t),
+            Shape::Quadric(quadric) => quadric.occluded(r, ray_t),
+            Shape::Capsule(capsule) => capsule.occluded(r, ray_t),
+            Shape::Sdf(sdf) => sdf.occluded(r, ray_t),
+            Shape::Mesh(mesh) => mesh.occluded(r, ray_t),
+            Shape::Curves(curves) => curves.occluded(r, ray_t),
+            Shape::PointCloud(cloud) => cloud.occluded(r, ray_t),
+            Shape::Volume(volume) => volume.occluded(r, ray_t),
+            Shape::Water(water) => water.occluded(r, ray_t),
+            Shape::Instance(instance) => instance.occluded(r, ray_t),
+            Shape::Custom(custom) => custom.occluded(r, ray_t),
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        match self {
+            Shape::Sphere(sphere) => sphere.bounding_box(),
+            Shape::Quadric(quadric) => quadric.bounding_box(),
+            Shape::Capsule(capsule) => capsule.bounding_box(),
+            Shape::Sdf(sdf) => sdf.bounding_box(),
+            Shape::Mesh(mesh) => mesh.bounding_box(),
+            Shape::Curves(curves) => curves.bounding_box(),
+            Shape::PointCloud(cloud) => cloud.bounding_box(),
+            Shape::Volume(volume) => volume.bounding_box(),
+            Shape::Water(water) => water.bounding_box(),
+            Shape::Instance(instance) => instance.bounding_box(),
+            Shape::Custom(custom) => custom.bounding_box(),
+        }
+    }
+}
+
+fn is_true(value: &bool) -> bool {
+    *value
+}
+
+// Per-object settings that are independent of the shape. The visibility flags
+// decide which kinds of rays see the object at all: `casts_shadows` covers
+// the diffuse bounces that gather (and so shadow) indirect light.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ObjectAttributes {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "is_true")]
+    pub camera_visible: bool,
+    #[serde(skip_serializing_if = "is_true")]
+    pub casts_shadows: bool,
+    #[serde(skip_serializing_if = "is_true")]
+    pub visible_in_reflections: bool,
+    // Cuts away parts of this object, see src/clip.rs.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub clip_planes: Vec<ClipPlane>,
+    // Seen by the camera as transparent black, for compositing onto a
+    // photographed backplate: it hides what is behind it and leaves a hole
+    // in the alpha AOV, while it still blocks, shadows and reflects light.
+    // Shadows other objects cast onto it are caught, see
+    // `Camera::coverage`.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub holdout: bool,
+    // Outside the render layer being rendered, see `ObjectList::isolate`.
+    // Works like `holdout`.
+    #[serde(skip)]
+    pub matte: bool,
+}
+
+impl Default for ObjectAttributes {
+    fn default() -> Self {
+        ObjectAttributes {
+            name: None,
+            tags: Vec::new(),
+            camera_visible: true,
+            casts_shadows: true,
+            visible_in_reflections: true,
+            clip_planes: Vec::new(),
+            holdout: false,
+            matte: false,
+        }
+    }
+}
+
+impl ObjectAttributes {
+    pub fn visible_to(&self, kind: RayKind) -> bool {
+        match kind {
+            RayKind::Camera => self.camera_visible,
+            RayKind::Diffuse => self.casts_shadows,
+            RayKind::Specular => self.visible_in_reflections,
         }
     }
 }
 
+// Most cut out surfaces a ray passes through in one object, e.g. the
+// layers of leaves in a tree.
+const MAX_CUTOUTS: usize = 64;
+
+// Most glass surfaces a transparent shadow ray passes through before it
+// counts as blocked.
+const MAX_SHADOW_SURFACES: usize = 64;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Object {
+    #[serde(flatten)]
+    pub shape: Shape,
+    #[serde(flatten)]
+    pub attributes: ObjectAttributes,
+}
+
+impl Object {
+    pub fn new(shape: Shape) -> Object {
+        Object {
+            shape,
+            attributes: ObjectAttributes::default(),
+        }
+    }
+
+    pub fn named(mut self, name: &str) -> Object {
+        self.attributes.name = Some(name.to_string());
+        self
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.attributes.name.as_deref()
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.attributes.tags.iter().any(|t| t == tag)
+    }
+
+    pub fn kind(&self) -> &'static str {
+        self.shape.kind()
+    }
+
+    pub fn material(&self) -> &Material {
+        self.shape.material()
+    }
+
+    pub fn material_mut(&mut self) -> &mut Material {
+        self.shape.material_mut()
+    }
+}
+
+impl From<Sphere> for Object {
+    fn from(sphere: Sphere) -> Self {
+        Object::new(Shape::Sphere(sphere))
+    }
+}
+
+impl From<Capsule> for Object {
+    fn from(capsule: Capsule) -> Self {
+        Object::new(Shape::Capsule(capsule))
+    }
+}
+
+impl From<Sdf> for Object {
+    fn from(sdf: Sdf) -> Self {
+        Object::new(Shape::Sdf(sdf))
+    }
+}
+
+impl From<Quadric> for Object {
+    fn from(quadric: Quadric) -> Self {
+        Object::new(Shape::Quadric(quadric))
+    }
+}
+
+impl From<Volume> for Object {
+    fn from(volume: Volume) -> Self {
+        Object::new(Shape::Volume(volume))
+    }
+}
+
+impl From<Water> for Object {
+    fn from(water: Water) -> Self {
+        Object::new(Shape::Water(water))
+    }
+}
+
+impl From<Custom> for Object {
+    fn from(custom: Custom) -> Self {
+        Object::new(Shape::Custom(custom))
+    }
+}
+
+impl From<Instance> for Object {
+    fn from(instance: Instance) -> Self {
+        Object::new(Shape::Instance(Box::new(instance)))
+    }
+}
+
+impl Hittable for Object {
+    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+        if !self.attributes.visible_to(r.kind()) {
+            return false;
+        }
+        let Some(mut ray_t) = clip(&self.attributes.clip_planes, r, ray_t) else {
+            return false;
+        };
+        // Past cut out parts of the surface, to what is behind them.
+        for _ in 0..MAX_CUTOUTS {
+            rec.edge = None;
+            if !self.shape.hit(r, &ray_t, rec) {
+                return false;
+            }
+            if !rec.mat.is_cut_out(r, rec) {
+                return true;
+            }
+            ray_t.min = rec.t.next_up();
+        }
+        false
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: &Interval) -> bool {
+        if !self.attributes.visible_to(r.kind()) {
+            return false;
+        }
+        // Cutouts need to know where the surface was hit.
+        if self.material().has_opacity() {
+            return self.hit(r, ray_t, &mut HitRecord::default());
+        }
+        match clip(&self.attributes.clip_planes, r, ray_t) {
+            Some(ray_t) => self.shape.occluded(r, &ray_t),
+            None => false,
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.shape.bounding_box()
+    }
+}
+
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct ObjectList {
     pub objects: Vec<Object>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub portals: Vec<Portal>,
+    // Cut away parts of every object.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub clip_planes: Vec<ClipPlane>,
+    // Shadow rays pass through glass, tinted by its absorption, rather than
+    // stopping at it.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub transparent_shadows: bool,
+    // Seen by camera rays instead of the sky, see src/backplate.rs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backplate: Option<Backplate>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sun: Option<Sun>,
+    // The sky's colors, see src/sky.rs.
+    #[serde(default, skip_serializing_if = "GradientSky::is_default")]
+    pub sky: GradientSky,
+    // In place of the sky's gradient, lit by the sun.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub atmosphere: Option<Atmosphere>,
+    // Turns the sky and scales its light.
+    #[serde(default, skip_serializing_if = "Environment::is_default")]
+    pub environment: Environment,
+    // Learned by `Guide::train` for the camera's `guiding`.
+    #[serde(skip)]
+    pub guide: Option<Arc<Guide>>,
 }
 
 impl ObjectList {
     pub fn new() -> ObjectList {
+        ObjectList::default()
+    }
+
+    pub fn with_objects(objects: Vec<Object>) -> ObjectList {
         ObjectList {
-            objects: Vec::new(),
+            objects,
+            portals: Vec::new(),
+            clip_planes: Vec::new(),
+            transparent_shadows: false,
+            backplate: None,
+            sun: None,
+            sky: GradientSky::default(),
+            atmosphere: None,
+            environment: Environment::default(),
+            guide: None,
         }
     }
 
@@ -71,25 +494,370 @@ impl ObjectList {
         self.objects.push(object);
     }
 
+    pub fn select_lods(&mut self, eye: Point3D) {
+        for object in &mut self.objects {
+            object.shape.select_lods(eye);
+        }
+    }
+
+    // Turns the objects outside `layer` into mattes, for rendering a render
+    // layer on its own.
+    pub fn isolate(&mut self, layer: &ObjectSet) {
+        for object in &mut self.objects {
+            object.attributes.matte = !layer.contains(&object.attributes);
+        }
+    }
+
+    // Whether a camera ray that hit `rec` sees a holdout or a matte.
+    pub fn is_matte(&self, r: &Ray, rec: &HitRecord) -> bool {
+        let attributes = &self.objects[rec.object].attributes;
+        r.kind() == RayKind::Camera && (attributes.holdout || attributes.matte)
+    }
+
+    // Whether a camera ray that hit `rec` sees a holdout, which catches
+    // shadows unlike the mattes of render layers.
+    pub fn is_holdout(&self, r: &Ray, rec: &HitRecord) -> bool {
+        r.kind() == RayKind::Camera && self.objects[rec.object].attributes.holdout
+    }
+
+    // Whether a holdout blocks `r` in `ray_t`, i.e. whether the
+    // photographed set a holdout stands in for casts the shadow itself.
+    pub fn occluded_by_holdouts(&self, r: &Ray, ray_t: &Interval) -> bool {
+        let Some(ray_t) = clip(&self.clip_planes, r, ray_t) else {
+            return false;
+        };
+        self.objects
+            .iter()
+            .any(|object| object.attributes.holdout && object.occluded(r, &ray_t))
+    }
+
+    // Returns the list again, so several spheres can be added in a row.
+    pub fn add_sphere(&mut self, center: Point3D, radius: f64, material: Material) -> &mut Self {
+        self.add(Object::from(Sphere::new(center, radius, material)));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Object> {
+        self.objects.iter()
+    }
+
     pub fn clear(&mut self) {
         self.objects.clear();
     }
-}
 
-impl Hittable for ObjectList {
-    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+    // The closest hit among the objects in `objects`.
+    pub fn hit_among(
+        &self,
+        r: &Ray,
+        ray_t: &Interval,
+        rec: &mut HitRecord,
+        objects: &ObjectSet,
+    ) -> bool {
+        let Some(ray_t) = clip(&self.clip_planes, r, ray_t) else {
+            return false;
+        };
         let mut temp_rec = HitRecord::default();
         let mut hit_anything = false;
         let mut closest_so_far = ray_t.max;
 
-        for object in &self.objects {
+        for (index, object) in self.objects.iter().enumerate() {
+            if !objects.contains(&object.attributes) {
+                continue;
+            }
             if object.hit(r, &Interval::new(ray_t.min, closest_so_far), &mut temp_rec) {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
+                temp_rec.object = index;
                 *rec = temp_rec.clone();
             }
         }
 
         hit_anything
     }
+
+    pub fn occluded_by(&self, r: &Ray, ray_t: &Interval, objects: &ObjectSet) -> bool {
+        let Some(ray_t) = clip(&self.clip_planes, r, ray_t) else {
+            return false;
+        };
+        self.objects
+            .iter()
+            .any(|object| objects.contains(&object.attributes) && object.occluded(r, &ray_t))
+    }
+
+    // How much light gets through along `r` in `ray_t` past the objects in
+    // `blockers`: nothing when it is blocked, and with `transparent_shadows`
+    // what the glass on the way lets through. The ray goes straight through,
+    // without bending.
+    pub fn transmittance(&self, r: &Ray, ray_t: &Interval, blockers: &ObjectSet) -> Color {
+        if !self.transparent_shadows {
+            return if self.occluded_by(r, ray_t, blockers) {
+                Color::default()
+            } else {
+                Color::new(1.0, 1.0, 1.0)
+            };
+        }
+        let mut transmittance = Color::new(1.0, 1.0, 1.0);
+        let mut ray_t = *ray_t;
+        let mut rec = HitRecord::default();
+        for _ in 0..MAX_SHADOW_SURFACES {
+            if !self.hit_among(r, &ray_t, &mut rec, blockers) {
+                return transmittance;
+            }
+            let Material::Glass(glass) = rec.mat else {
+                return Color::default();
+            };
+            // Leaving the glass, the ray has been inside since the last
+            // surface, or since it started.
+            if !rec.front_face {
+                let distance = (rec.t - ray_t.min) * r.direction().length();
+                transmittance = transmittance * glass.transmittance(distance);
+            }
+            ray_t.min = rec.t.next_up();
+        }
+        Color::default()
+    }
+}
+
+impl FromIterator<Object> for ObjectList {
+    fn from_iter<I: IntoIterator<Item = Object>>(iter: I) -> Self {
+        ObjectList::with_objects(iter.into_iter().collect())
+    }
+}
+
+impl Extend<Object> for ObjectList {
+    fn extend<I: IntoIterator<Item = Object>>(&mut self, iter: I) {
+        self.objects.extend(iter);
+    }
+}
+
+impl IntoIterator for ObjectList {
+    type Item = Object;
+    type IntoIter = std::vec::IntoIter<Object>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.objects.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a ObjectList {
+    type Item = &'a Object;
+    type IntoIter = std::slice::Iter<'a, Object>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.objects.iter()
+    }
+}
+
+impl Hittable for ObjectList {
+    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+        self.hit_among(r, ray_t, rec, &ObjectSet::default())
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: &Interval) -> bool {
+        self.occluded_by(r, ray_t, &ObjectSet::default())
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.objects.iter().fold(Aabb::EMPTY, |aabb, object| {
+            Aabb::surrounding_box(&aabb, &object.bounding_box())
+        })
+    }
+}
+
+#[test]
+fn test_visibility_flags() {
+    let object: Object = serde_json::from_str(
+        r#"{"Sphere": {"center": {"x": 0.0, "y": 0.0, "z": -2.0}, "radius": 0.5,
+                       "material": {"Glass": {"refraction_index": 1.5}}},
+            "name": "ball", "tags": ["props"], "camera_visible": false}"#,
+    )
+    .unwrap();
+    assert_eq!(object.name(), Some("ball"));
+    assert!(object.has_tag("props"));
+
+    let ray = Ray::new(Point3D::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+    let interval = Interval::new(0.001, f64::INFINITY);
+    let mut rec = HitRecord::default();
+    assert!(!object.hit(&ray, &interval, &mut rec));
+    assert!(object.hit(&ray.with_kind(RayKind::Diffuse), &interval, &mut rec));
+
+    let json = serde_json::to_string(&object).unwrap();
+    assert!(json.contains("\"camera_visible\":false"));
+    assert!(!json.contains("casts_shadows"));
+}
+
+#[test]
+fn test_holdout() {
+    let world: ObjectList = serde_json::from_str(
+        r#"{"objects": [{"Sphere": {"center": {"x": 0.0, "y": 0.0, "z": -2.0}, "radius": 0.5,
+                                     "material": {"Glass": {"refraction_index": 1.5}}},
+                          "holdout": true}]}"#,
+    )
+    .unwrap();
+    assert!(world.objects[0].attributes.holdout);
+
+    // Only the camera sees it as a holdout.
+    let ray = Ray::new(Point3D::new(0.0, 0.0, 0.0), Vec3::new(0.0, 0.0, -1.0));
+    let mut rec = HitRecord::default();
+    assert!(world.hit(&ray, &Interval::new(0.001, f64::INFINITY), &mut rec));
+    assert!(world.is_matte(&ray, &rec));
+    assert!(!world.is_matte(&ray.with_kind(RayKind::Specular), &rec));
+
+    let json = serde_json::to_string(&world.objects[0]).unwrap();
+    assert!(json.contains("\"holdout\":true") && !json.contains("matte"));
+}
+
+#[test]
+fn test_object_list_construction() {
+    let sphere = |x: f64| {
+        Object::from(Sphere::new(
+            Point3D::new(x, 0.0, 0.0),
+            1.0,
+            Material::Named("x".to_string()),
+        ))
+    };
+    let mut list: ObjectList = (0..3).map(|i| sphere(i as f64)).collect();
+    list.extend([sphere(3.0)]);
+    list.add_sphere(
+        Point3D::new(4.0, 0.0, 0.0),
+        1.0,
+        Material::Named("x".to_string()),
+    )
+    .add_sphere(
+        Point3D::new(5.0, 0.0, 0.0),
+        1.0,
+        Material::Named("x".to_string()),
+    );
+    assert_eq!(list.len(), 6);
+    assert_eq!(
+        (&list).into_iter().filter(|o| o.kind() == "sphere").count(),
+        6
+    );
+
+    let names: Vec<Option<String>> = list.into_iter().map(|o| o.attributes.name).collect();
+    assert_eq!(names.len(), 6);
+    assert!(ObjectList::with_objects(Vec::new()).is_empty());
+}
+
+#[test]
+fn test_occluded() {
+    use crate::instance::Instance;
+    use crate::mesh::{Mesh, MeshShape, TriangleMesh};
+
+    // A sphere, and a unit quad turned and moved by an instance.
+    let material = Material::Named("x".to_string());
+    let mut quad = MeshShape::new("quad.obj", material.clone());
+    let mesh = Mesh::parse_obj("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap();
+    quad.triangles = Some(Arc::new(TriangleMesh::new(mesh)));
+    let instance: Instance = serde_json::from_value(serde_json::json!({
+        "shape": {"Mesh": quad},
+        "translate": [3.0, 0.0, 0.0],
+        "rotate": {"euler": {"x": 0.0, "y": 90.0, "z": 0.0}}
+    }))
+    .unwrap();
+    let world = ObjectList::with_objects(vec![
+        Object::from(Sphere::new(Point3D::new(0.0, 0.0, 0.0), 1.0, material)),
+        Object::from(instance),
+    ]);
+
+    // Shadow queries agree with the closest hits.
+    let mut rec = HitRecord::default();
+    for i in 0..200 {
+        let origin = Point3D::new(-5.0, 0.0, 0.0);
+        let target = Point3D::new(
+            5.0,
+            (i % 20) as f64 * 0.15 - 1.5,
+            (i / 20) as f64 * 0.3 - 1.5,
+        );
+        let r = Ray::new(origin, target - origin).with_kind(RayKind::Diffuse);
+        for ray_t in [
+            Interval::new(0.001, f64::INFINITY),
+            Interval::new(0.001, 0.45),
+        ] {
+            let hit = world.hit(&r, &ray_t, &mut rec);
+            assert_eq!(world.occluded(&r, &ray_t), hit, "{:?} {:?}", target, ray_t);
+        }
+    }
+}
+
+#[test]
+fn test_cutouts() {
+    use crate::film::Film;
+    use crate::texture::{ImageTexture, MipChain, TextureFilter};
+
+    // A sphere cut away on the half facing +z, around u = 0.25.
+    let mut mask = Film::new(2, 1);
+    mask.pixels[1] = Color::new(1.0, 1.0, 1.0);
+    let mut opacity = ImageTexture::new("mask.png", TextureFilter::Nearest);
+    opacity.mips = Some(Arc::new(MipChain::new(mask)));
+    let mut lambertian = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+    lambertian.opacity = Some(opacity);
+    let sphere = Object::from(Sphere::new(
+        Point3D::new(0.0, 0.0, 0.0),
+        1.0,
+        Material::Lambertian(lambertian),
+    ));
+
+    // Through the missing front to the inside of the back.
+    let ray_t = Interval::new(0.001, f64::INFINITY);
+    let r = Ray::new(Point3D::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    let mut rec = HitRecord::default();
+    assert!(sphere.hit(&r, &ray_t, &mut rec));
+    assert!((rec.t - 6.0).abs() < 1e-12 && !rec.front_face);
+    assert!(sphere.occluded(&r, &ray_t));
+    assert!(!sphere.occluded(&r, &Interval::new(0.001, 5.5)));
+    // The back is there from the other side too.
+    let r = Ray::new(Point3D::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+    assert!(sphere.hit(&r, &ray_t, &mut rec));
+    assert!((rec.t - 4.0).abs() < 1e-12 && rec.front_face);
+}
+
+#[test]
+fn test_transparent_shadows() {
+    use crate::material::Glass;
+
+    // Red glass two units thick, in front of a grey ball.
+    let mut red = Glass::new(1.5);
+    red.absorption = Some(Color::new(0.0, 1.0, 1.0));
+    let mut world = ObjectList::new();
+    world
+        .add_sphere(Point3D::new(0.0, 0.0, 0.0), 1.0, Material::Glass(red))
+        .add_sphere(
+            Point3D::new(0.0, 0.0, -5.0),
+            1.0,
+            Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        );
+
+    let ray_t = Interval::new(0.001, f64::INFINITY);
+    let everything = ObjectSet::default();
+    let r = Ray::new(Point3D::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -0.5));
+    let past = Ray::new(Point3D::new(0.0, 1.5, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    assert_eq!(
+        world.transmittance(&r, &ray_t, &everything),
+        Color::default()
+    );
+    world.transparent_shadows = true;
+    assert_eq!(
+        world.transmittance(&r, &ray_t, &everything),
+        Color::default()
+    );
+    assert_eq!(
+        world.transmittance(&past, &ray_t, &everything),
+        Color::new(1.0, 1.0, 1.0)
+    );
+    // Stopping short of the ball, or starting inside the glass.
+    let tint = world.transmittance(&r, &Interval::new(0.001, 15.0), &everything);
+    let expected = Color::new(1.0, (-2.0f64).exp(), (-2.0f64).exp());
+    assert!((tint - expected).length() < 1e-9, "{:?}", tint);
+    let inside = Ray::new(Point3D::new(0.0, 0.0, 0.5), Vec3::new(0.0, 0.0, 1.0));
+    let tint = world.transmittance(&inside, &Interval::new(0.0, f64::INFINITY), &everything);
+    assert!((tint.y() - (-0.5f64).exp()).abs() < 1e-9 && tint.x() == 1.0);
 }
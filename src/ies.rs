@@ -0,0 +1,248 @@
+// IES LM-63 photometric files, which describe how much light a real fixture
+// sends out in every direction. Only type C photometry is supported, the kind
+// used for nearly all architectural fixtures: vertical angles go from 0 along
+// the fixture's axis (usually straight down) to 180 opposite of it, horizontal
+// angles go around the axis.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::vec3::Vec3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct IesProfile {
+    // Both in degrees and ascending.
+    pub vertical: Vec<f64>,
+    pub horizontal: Vec<f64>,
+    // For each horizontal angle all vertical angles, in candela.
+    pub candela: Vec<f64>,
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+// Where `x` falls between two entries of `angles` and how far towards the
+// second one, or None outside of them.
+fn locate(angles: &[f64], x: f64) -> Option<(usize, f64)> {
+    if angles.len() == 1 {
+        return (x == angles[0]).then_some((0, 0.0));
+    }
+    if x < angles[0] || x > angles[angles.len() - 1] {
+        return None;
+    }
+    let i = angles[1..].iter().position(|&a| x <= a).unwrap_or(0);
+    let (a, b) = (angles[i], angles[i + 1]);
+    Some((i, if b > a { (x - a) / (b - a) } else { 0.0 }))
+}
+
+impl IesProfile {
+    pub fn load(path: &Path) -> io::Result<IesProfile> {
+        IesProfile::parse(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn parse(src: &str) -> io::Result<IesProfile> {
+        let mut lines = src.lines();
+        // Keywords like [MANUFAC] come before the TILT line.
+        let tilt = lines
+            .by_ref()
+            .map(str::trim)
+            .find(|line| line.starts_with("TILT="))
+            .ok_or_else(|| invalid_data("missing TILT line"))?;
+        let mut numbers = Vec::new();
+        for field in lines.flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ',')) {
+            if !field.is_empty() {
+                numbers.push(
+                    field
+                        .parse::<f64>()
+                        .map_err(|_| invalid_data(&format!("\"{}\" is not a number", field)))?,
+                );
+            }
+        }
+
+        let mut numbers = numbers.into_iter();
+        let mut next = |what: &str| {
+            numbers
+                .next()
+                .ok_or_else(|| invalid_data(&format!("missing {}", what)))
+        };
+        match tilt {
+            "TILT=NONE" => {}
+            // Lamp geometry, then angles and multipliers for tilted lamps,
+            // which don't matter for a fixed light.
+            "TILT=INCLUDE" => {
+                next("lamp geometry")?;
+                let pairs = next("tilt angle count")? as usize;
+                for _ in 0..2 * pairs {
+                    next("tilt angles")?;
+                }
+            }
+            _ => return Err(invalid_data("tilt files are not supported")),
+        }
+
+        let _lamps = next("number of lamps")?;
+        let _lumens_per_lamp = next("lumens per lamp")?;
+        let multiplier = next("candela multiplier")?;
+        let vertical_count = next("number of vertical angles")? as usize;
+        let horizontal_count = next("number of horizontal angles")? as usize;
+        if next("photometric type")? != 1.0 {
+            return Err(invalid_data("only type C photometry is supported"));
+        }
+        // Units, the luminous opening's size, ballast factors and input watts.
+        for _ in 0..7 {
+            next("fixture dimensions and factors")?;
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(invalid_data("no angles"));
+        }
+
+        let mut take = |count: usize, what: &str| -> io::Result<Vec<f64>> {
+            (0..count).map(|_| next(what)).collect()
+        };
+        let vertical = take(vertical_count, "vertical angles")?;
+        let horizontal = take(horizontal_count, "horizontal angles")?;
+        let candela = take(vertical_count * horizontal_count, "candela values")?
+            .into_iter()
+            .map(|c| c * multiplier)
+            .collect();
+        for angles in [&vertical, &horizontal] {
+            if angles.windows(2).any(|w| w[0] > w[1]) {
+                return Err(invalid_data("angles must be ascending"));
+            }
+        }
+        Ok(IesProfile {
+            vertical,
+            horizontal,
+            candela,
+        })
+    }
+
+    // Interpolated intensity towards the given angles in degrees. Horizontal
+    // angles are mirrored as the file's symmetry says: a single angle means
+    // the same all around, a last angle of 90 one quadrant mirrored to all
+    // four and 180 one half mirrored to the other.
+    pub fn candela(&self, vertical: f64, horizontal: f64) -> f64 {
+        let mut h = horizontal.rem_euclid(360.0);
+        match self.horizontal[self.horizontal.len() - 1] {
+            last if self.horizontal.len() == 1 => h = last,
+            90.0 => {
+                h = if h > 180.0 { 360.0 - h } else { h };
+                h = if h > 90.0 { 180.0 - h } else { h };
+            }
+            180.0 if h > 180.0 => h = 360.0 - h,
+            _ => {}
+        }
+
+        let (Some((v, tv)), Some((h, th))) = (
+            locate(&self.vertical, vertical),
+            locate(&self.horizontal, h),
+        ) else {
+            return 0.0;
+        };
+        let n = self.vertical.len();
+        let at =
+            |h: usize, v: usize| self.candela[h.min(self.horizontal.len() - 1) * n + v.min(n - 1)];
+        let row = |h: usize| at(h, v) * (1.0 - tv) + at(h, v + 1) * tv;
+        row(h) * (1.0 - th) + row(h + 1) * th
+    }
+
+    pub fn max_candela(&self) -> f64 {
+        self.candela.iter().copied().fold(0.0, f64::max)
+    }
+}
+
+// An IES profile on a light, relative to its brightest direction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Ies {
+    pub file: String,
+    // Where the profile's vertical angle 0 points. Horizontal angle 0 is
+    // towards +x, or +y for lights pointing along the x axis.
+    #[serde(default = "crate::ies::down")]
+    pub direction: Vec3,
+    #[serde(skip)]
+    pub profile: Option<Arc<IesProfile>>,
+}
+
+pub(crate) fn down() -> Vec3 {
+    Vec3::new(0.0, -1.0, 0.0)
+}
+
+impl Ies {
+    // Reads the profile and returns the path it was read from.
+    pub fn load(&mut self, base_dir: &Path) -> io::Result<PathBuf> {
+        let path = base_dir.join(&self.file);
+        self.profile = Some(Arc::new(IesProfile::load(&path)?));
+        Ok(path)
+    }
+
+    // Between 0 and 1 for light leaving towards `towards`; 1 until loaded.
+    pub fn factor(&self, towards: Vec3) -> f64 {
+        let Some(profile) = &self.profile else {
+            return 1.0;
+        };
+        let max = profile.max_candela();
+        if max <= 0.0 {
+            return 0.0;
+        }
+
+        let w = self.direction.unit_vector();
+        let (u, v) = w.perpendiculars();
+        let d = towards.unit_vector();
+        let vertical = d.dot(&w).clamp(-1.0, 1.0).acos().to_degrees();
+        let horizontal = d.dot(&v).atan2(d.dot(&u)).to_degrees();
+        profile.candela(vertical, horizontal) / max
+    }
+}
+
+#[cfg(test)]
+const DOWNLIGHT: &str = "IESNA:LM-63-2002
+[TEST] downlight
+[MANUFAC] nobody
+TILT=NONE
+1 1000 2.0 3 2 1 2 0.1 0.1 0.0
+1.0 1.0 12
+0 45 90
+0 180
+100 50 0
+100 60, 0
+";
+
+#[test]
+fn test_parse() {
+    let profile = IesProfile::parse(DOWNLIGHT).unwrap();
+    assert_eq!(profile.vertical, vec![0.0, 45.0, 90.0]);
+    assert_eq!(profile.horizontal, vec![0.0, 180.0]);
+    assert_eq!(profile.max_candela(), 200.0);
+
+    assert_eq!(profile.candela(0.0, 0.0), 200.0);
+    assert_eq!(profile.candela(22.5, 0.0), 150.0);
+    assert_eq!(profile.candela(45.0, 90.0), 110.0);
+    // Mirrored from 90.
+    assert_eq!(profile.candela(45.0, 270.0), 110.0);
+    // Nothing above the horizon.
+    assert_eq!(profile.candela(135.0, 0.0), 0.0);
+
+    assert!(IesProfile::parse("TILT=NONE\n1 1000 1 2 1 2").is_err());
+    assert!(IesProfile::parse(&DOWNLIGHT.replace("TILT=NONE", "TILT=lamp.tlt")).is_err());
+}
+
+#[test]
+fn test_factor() {
+    let mut ies = Ies {
+        file: String::new(),
+        direction: down(),
+        profile: None,
+    };
+    assert_eq!(ies.factor(Vec3::new(0.0, 1.0, 0.0)), 1.0);
+
+    ies.profile = Some(Arc::new(IesProfile::parse(DOWNLIGHT).unwrap()));
+    assert_eq!(ies.factor(Vec3::new(0.0, -2.0, 0.0)), 1.0);
+    assert!((ies.factor(Vec3::new(1.0, -1.0, 0.0)) - 0.5).abs() < 1e-12);
+    assert!((ies.factor(Vec3::new(0.0, -1.0, 1.0)) - 0.55).abs() < 1e-12);
+    assert_eq!(ies.factor(Vec3::new(0.0, 1.0, 0.0)), 0.0);
+}
@@ -0,0 +1,143 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::aabb::Aabb;
+use crate::config::Config;
+use crate::hittable::Hittable;
+use crate::material::Material;
+
+// Summary of a scene printed by `raytracer info`.
+pub struct SceneInfo {
+    pub objects: BTreeMap<&'static str, usize>,
+    pub materials: BTreeMap<&'static str, usize>,
+    pub lights: usize,
+    pub bounds: Option<Aabb>,
+    pub bvh_depth: usize,
+    pub camera: crate::camera::CameraParams,
+    pub pixels: usize,
+    pub samples: usize,
+}
+
+impl SceneInfo {
+    pub fn new(config: &Config) -> SceneInfo {
+        let mut objects = BTreeMap::new();
+        let mut materials = BTreeMap::new();
+        let mut lights = 0;
+
+        for object in &config.object_list.objects {
+            *objects.entry(object.kind()).or_insert(0) += 1;
+
+            let material = object.material();
+            *materials.entry(material.kind()).or_insert(0) += 1;
+            if matches!(material, Material::DiffuseLight(_)) {
+                lights += 1;
+            }
+        }
+
+        // A binary BVH with one object per leaf is about log2(n) levels deep.
+        let count = config.object_list.objects.len();
+        let bvh_depth = (count.max(1) as f64).log2().ceil() as usize;
+
+        let aabb = config.object_list.bounding_box();
+        let bounds = (!aabb.is_empty()).then_some(aabb);
+
+        let camera = config.camera;
+        SceneInfo {
+            objects,
+            materials,
+            lights,
+            bounds,
+            bvh_depth,
+            camera: camera.into(),
+            pixels: camera.width() * camera.height(),
+            samples: camera.width() * camera.height() * camera.samples_per_pixel(),
+        }
+    }
+}
+
+impl fmt::Display for SceneInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total: usize = self.objects.values().sum();
+        writeln!(f, "Objects: {}", total)?;
+        for (kind, count) in &self.objects {
+            writeln!(f, "  {:<14}{}", kind, count)?;
+        }
+        writeln!(f, "Materials:")?;
+        for (kind, count) in &self.materials {
+            writeln!(f, "  {:<14}{}", kind, count)?;
+        }
+        writeln!(f, "Lights: {}", self.lights)?;
+        match self.bounds {
+            Some(aabb) => writeln!(
+                f,
+                "Bounds: ({:.3}, {:.3}, {:.3}) to ({:.3}, {:.3}, {:.3})",
+                aabb.x.min, aabb.y.min, aabb.z.min, aabb.x.max, aabb.y.max, aabb.z.max
+            )?,
+            None => writeln!(f, "Bounds: empty scene")?,
+        }
+        writeln!(f, "Estimated BVH depth: {}", self.bvh_depth)?;
+
+        let c = &self.camera;
+        writeln!(f, "Camera:")?;
+        writeln!(
+            f,
+            "  resolution    {}x{} ({} pixels)",
+            c.width, c.height, self.pixels
+        )?;
+        writeln!(
+            f,
+            "  samples       {} per pixel, {} total",
+            c.samples_per_pixel, self.samples
+        )?;
+        writeln!(f, "  max depth     {}", c.max_depth)?;
+        writeln!(f, "  vfov          {}", c.vfov)?;
+        writeln!(
+            f,
+            "  lookfrom      ({}, {}, {})",
+            c.lookfrom.x(),
+            c.lookfrom.y(),
+            c.lookfrom.z()
+        )?;
+        writeln!(
+            f,
+            "  lookat        ({}, {}, {})",
+            c.lookat.x(),
+            c.lookat.y(),
+            c.lookat.z()
+        )?;
+        writeln!(
+            f,
+            "  vup           ({}, {}, {})",
+            c.vup.x(),
+            c.vup.y(),
+            c.vup.z()
+        )?;
+        writeln!(f, "  defocus angle {}", c.defocus_angle)?;
+        write!(f, "  focus dist    {}", c.focus_dist)
+    }
+}
+
+#[test]
+fn test_scene_info() {
+    let info = SceneInfo::new(&crate::scenes::random_spheres(1));
+    let total: usize = info.objects.values().sum();
+    assert_eq!(info.objects["sphere"], total);
+    assert_eq!(info.materials.values().sum::<usize>(), total);
+    assert_eq!(info.lights, 0);
+    let bounds = info.bounds.unwrap();
+    assert_eq!(bounds.y.min, -2000.0);
+    assert_eq!(bounds.y.max, 2.0);
+    assert_eq!(info.bvh_depth, (total as f64).log2().ceil() as usize);
+    assert!(info.to_string().contains("Estimated BVH depth"));
+}
+
+#[test]
+fn test_object_kinds() {
+    let object = crate::hittable::Object::from(crate::sphere::Sphere::new(
+        crate::vec3::Point3D::new(0.0, 0.0, 0.0),
+        1.0,
+        Material::Named("x".to_string()),
+    ));
+    assert_eq!(object.kind(), "sphere");
+    assert_eq!(object.material().kind(), "named");
+}
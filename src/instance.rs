@@ -0,0 +1,162 @@
+// A shape moved, rotated and scaled as a whole. Rays are transformed into the
+// shape's own space, so any shape can be stretched or turned without knowing
+// about transforms itself.
+
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable, Shape};
+use crate::interval::Interval;
+use crate::quat::{Quat, Rotation};
+use crate::ray::Ray;
+use crate::transform::Transform;
+use crate::vec3::{Point3D, Vec3};
+
+fn one() -> Vec3 {
+    Vec3::new(1.0, 1.0, 1.0)
+}
+
+fn is_zero(v: &Vec3) -> bool {
+    *v == Vec3::default()
+}
+
+fn is_one(v: &Vec3) -> bool {
+    *v == one()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "InstanceParams", into = "InstanceParams")]
+pub struct Instance {
+    pub shape: Shape,
+    pub translate: Vec3,
+    pub rotate: Option<Rotation>,
+    pub scale: Vec3,
+    transform: Transform,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceParams {
+    pub shape: Shape,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub translate: Vec3,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotate: Option<Rotation>,
+    #[serde(default = "one", skip_serializing_if = "is_one")]
+    pub scale: Vec3,
+}
+
+impl From<Instance> for InstanceParams {
+    fn from(i: Instance) -> Self {
+        InstanceParams {
+            shape: i.shape,
+            translate: i.translate,
+            rotate: i.rotate,
+            scale: i.scale,
+        }
+    }
+}
+
+impl From<InstanceParams> for Instance {
+    fn from(p: InstanceParams) -> Self {
+        Instance::new(p.shape, p.translate, p.rotate, p.scale)
+    }
+}
+
+impl Instance {
+    // Scales first, then rotates, then translates.
+    pub fn new(shape: Shape, translate: Vec3, rotate: Option<Rotation>, scale: Vec3) -> Instance {
+        let rotation = rotate.map(Quat::from).unwrap_or_default();
+        Instance {
+            shape,
+            translate,
+            rotate,
+            scale,
+            transform: Transform::from_trs(translate, rotation, scale),
+        }
+    }
+
+    // Only uniformly scaled shapes keep a known area.
+    pub fn area(&self) -> Option<f64> {
+        let s = self.scale.x().abs();
+        if self.scale.y().abs() != s || self.scale.z().abs() != s {
+            return None;
+        }
+        self.shape.area().map(|area| area * s * s)
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+        // The direction isn't normalized, so distances along the ray stay the
+        // same in both spaces.
+        let inverse = self.transform.inverse();
+        let local = Ray::new(inverse.point(*r.origin()), inverse.vector(*r.direction()))
+            .with_kind(r.kind());
+        if !self.shape.hit(&local, ray_t, rec) {
+            return false;
+        }
+        rec.p = self.transform.point(rec.p);
+        rec.normal = self.transform.normal(rec.normal).unit_vector();
+        rec.dpdu = self.transform.vector(rec.dpdu);
+        rec.dpdv = self.transform.vector(rec.dpdv);
+        rec.edge = rec.edge.map(|edge| self.transform.vector(edge));
+        true
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: &Interval) -> bool {
+        let inverse = self.transform.inverse();
+        let local = Ray::new(inverse.point(*r.origin()), inverse.vector(*r.direction()))
+            .with_kind(r.kind());
+        self.shape.occluded(&local, ray_t)
+    }
+
+    // Surrounds the transformed corners of the shape's box.
+    fn bounding_box(&self) -> Aabb {
+        let aabb = self.shape.bounding_box();
+        (0..8).fold(Aabb::EMPTY, |bounds, i| {
+            let corner = Point3D::new(
+                if i & 1 == 0 { aabb.x.min } else { aabb.x.max },
+                if i & 2 == 0 { aabb.y.min } else { aabb.y.max },
+                if i & 4 == 0 { aabb.z.min } else { aabb.z.max },
+            );
+            let p = self.transform.point(corner);
+            Aabb::surrounding_box(&bounds, &Aabb::from_points(p, p))
+        })
+    }
+}
+
+#[test]
+fn test_stretched_sphere() {
+    let sphere = crate::sphere::Sphere::new(
+        Point3D::new(0.0, 0.0, 0.0),
+        1.0,
+        crate::material::Material::Named("x".to_string()),
+    );
+    let instance: Instance = serde_json::from_value(serde_json::json!({
+        "shape": {"Sphere": sphere},
+        "translate": {"x": 0.0, "y": 0.0, "z": -10.0},
+        "rotate": {"axis_angle": {"axis": {"x": 0.0, "y": 1.0, "z": 0.0}, "angle": 90.0}},
+        "scale": {"x": 1.0, "y": 1.0, "z": 3.0},
+    }))
+    .unwrap();
+
+    // The long axis ends up along x.
+    let aabb = instance.bounding_box();
+    assert!((aabb.min() - Point3D::new(-3.0, -1.0, -11.0)).length() < 1e-9);
+    assert!((aabb.max() - Point3D::new(3.0, 1.0, -9.0)).length() < 1e-9);
+
+    let mut rec = HitRecord::default();
+    let ray = Ray::new(Point3D::new(-10.0, 0.0, -10.0), Vec3::new(1.0, 0.0, 0.0));
+    assert!(instance.hit(&ray, &Interval::new(0.001, f64::INFINITY), &mut rec));
+    assert!((rec.t - 7.0).abs() < 1e-9);
+    assert!((rec.p - Point3D::new(-3.0, 0.0, -10.0)).length() < 1e-9);
+    assert!((rec.normal - Vec3::new(-1.0, 0.0, 0.0)).length() < 1e-9);
+
+    let json = serde_json::to_value(&instance).unwrap();
+    assert!(json.get("translate").is_some());
+    assert!(json.get("rotate").is_some());
+}
@@ -1,8 +1,18 @@
+use std::ops::{Add, Sub};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Interval {
     pub min: f64,
     pub max: f64,
 }
 
+// Empty, so folding bounds with `union` can start from the default.
+impl Default for Interval {
+    fn default() -> Self {
+        Interval::EMPTY
+    }
+}
+
 impl Interval {
     pub const EMPTY: Interval = Interval {
         min: f64::INFINITY,
@@ -18,6 +28,10 @@ impl Interval {
         Interval { min, max }
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.min > self.max
+    }
+
     pub fn size(&self) -> f64 {
         self.max - self.min
     }
@@ -39,6 +53,50 @@ impl Interval {
             x
         }
     }
+
+    // Grows the interval by `delta` in total, half on each side.
+    pub fn expand(&self, delta: f64) -> Interval {
+        let padding = delta / 2.0;
+        Interval::new(self.min - padding, self.max + padding)
+    }
+
+    // The smallest interval containing both.
+    pub fn union(&self, other: &Interval) -> Interval {
+        Interval::new(self.min.min(other.min), self.max.max(other.max))
+    }
+
+    // Empty if the intervals don't overlap.
+    pub fn intersection(&self, other: &Interval) -> Interval {
+        Interval::new(self.min.max(other.min), self.max.min(other.max))
+    }
+
+    pub fn offset(&self, displacement: f64) -> Interval {
+        Interval::new(self.min + displacement, self.max + displacement)
+    }
+}
+
+impl Add<f64> for Interval {
+    type Output = Interval;
+
+    fn add(self, displacement: f64) -> Interval {
+        self.offset(displacement)
+    }
+}
+
+impl Add<Interval> for f64 {
+    type Output = Interval;
+
+    fn add(self, interval: Interval) -> Interval {
+        interval.offset(self)
+    }
+}
+
+impl Sub<f64> for Interval {
+    type Output = Interval;
+
+    fn sub(self, displacement: f64) -> Interval {
+        self.offset(-displacement)
+    }
 }
 
 #[test]
@@ -70,3 +128,23 @@ fn test_clamp() {
     assert_eq!(interval.clamp(3.0), 3.0);
     assert_eq!(interval.clamp(6.0), 5.0);
 }
+
+#[test]
+fn test_set_operations() {
+    let a = Interval::new(1.0, 5.0);
+    let b = Interval::new(4.0, 8.0);
+    assert_eq!(a.union(&b), Interval::new(1.0, 8.0));
+    assert_eq!(a.intersection(&b), Interval::new(4.0, 5.0));
+    assert!(a.intersection(&Interval::new(6.0, 7.0)).is_empty());
+    assert_eq!(Interval::default().union(&a), a);
+    assert!(Interval::default().is_empty());
+}
+
+#[test]
+fn test_expand_and_offset() {
+    let interval = Interval::new(1.0, 5.0);
+    assert_eq!(interval.expand(2.0), Interval::new(0.0, 6.0));
+    assert_eq!(interval + 1.0, Interval::new(2.0, 6.0));
+    assert_eq!(1.0 + interval, interval.offset(1.0));
+    assert_eq!(interval - 1.0, Interval::new(0.0, 4.0));
+}
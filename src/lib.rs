@@ -1,9 +1,78 @@
+pub mod aabb;
+pub mod aov;
+pub mod atmosphere;
+pub mod backplate;
+pub mod bake;
+#[cfg(feature = "json")]
+pub mod bench;
+pub mod blue_noise;
+pub mod bvh;
 pub mod camera;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod capsule;
+pub mod clip;
 pub mod color;
+pub mod compare;
+#[cfg(feature = "json")]
 pub mod config;
+pub mod contact_sheet;
+pub mod curves;
+pub mod custom;
+pub mod displacement;
+pub mod environment;
+pub mod exr;
+pub mod film;
+pub mod furnace;
+pub mod gobo;
+pub mod guide;
 pub mod hittable;
+pub mod ies;
+#[cfg(feature = "json")]
+pub mod info;
+pub mod instance;
 pub mod interval;
+pub mod light_link;
+pub mod lut;
 pub mod material;
+pub mod mesh;
+#[cfg(feature = "json")]
+pub mod migration;
+pub mod parallel;
+#[cfg(feature = "json")]
+pub mod pbrt;
+pub mod point_cloud;
+pub mod portal;
+pub mod post;
+pub mod profile;
+pub mod quadric;
+pub mod quat;
 pub mod ray;
+pub mod renderer;
+pub mod rng;
+pub mod scene;
+#[cfg(feature = "json")]
+pub mod scenes;
+#[cfg(feature = "scripting")]
+pub mod script;
+pub mod sdf;
+pub mod shake;
+pub mod sky;
 pub mod sphere;
+pub mod sppm;
+pub mod subdivision;
+pub mod sun;
+pub mod term;
+pub mod testing;
+pub mod texture;
+pub mod texture_cache;
+pub mod transform;
+#[cfg(feature = "usd")]
+pub mod usd;
 pub mod vec3;
+pub mod volume;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "watch")]
+pub mod watch;
+pub mod water;
@@ -0,0 +1,76 @@
+// Light linking: which objects a light falls on, and which of them block it.
+// Objects are picked by their names or tags. An emitting object's links only
+// say what it lights, since nothing aims shadow rays at it; a portal's links
+// say both, for the sky seen through it.
+
+use serde::{Deserialize, Serialize};
+
+use crate::hittable::ObjectAttributes;
+
+// Every object, or only those listed in `include`, less those in `exclude`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ObjectSet {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
+impl ObjectSet {
+    pub fn is_everything(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    pub fn contains(&self, object: &ObjectAttributes) -> bool {
+        let listed = |names: &[String]| {
+            names
+                .iter()
+                .any(|name| object.name.as_ref() == Some(name) || object.tags.contains(name))
+        };
+        (self.include.is_empty() || listed(&self.include)) && !listed(&self.exclude)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LightLinks {
+    #[serde(skip_serializing_if = "ObjectSet::is_everything")]
+    pub illuminates: ObjectSet,
+    #[serde(skip_serializing_if = "ObjectSet::is_everything")]
+    pub shadows: ObjectSet,
+}
+
+impl LightLinks {
+    pub fn is_default(&self) -> bool {
+        self.illuminates.is_everything() && self.shadows.is_everything()
+    }
+}
+
+#[test]
+fn test_object_set() {
+    let object = |name: Option<&str>, tags: &[&str]| ObjectAttributes {
+        name: name.map(str::to_string),
+        tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        ..ObjectAttributes::default()
+    };
+    let statue = object(Some("statue"), &["hero"]);
+    let floor = object(Some("floor"), &[]);
+    let unnamed = object(None, &[]);
+
+    let everything = ObjectSet::default();
+    assert!(everything.is_everything());
+    assert!(everything.contains(&statue) && everything.contains(&unnamed));
+
+    let links: LightLinks = serde_json::from_value(serde_json::json!({
+        "illuminates": {"include": ["hero", "floor"]},
+        "shadows": {"exclude": ["statue"]}
+    }))
+    .unwrap();
+    assert!(links.illuminates.contains(&statue) && links.illuminates.contains(&floor));
+    assert!(!links.illuminates.contains(&unnamed));
+    assert!(!links.shadows.contains(&statue) && links.shadows.contains(&unnamed));
+    assert!(!links.is_default() && LightLinks::default().is_default());
+    let json = serde_json::to_value(&links).unwrap();
+    assert!(json["shadows"].get("include").is_none());
+}
@@ -0,0 +1,182 @@
+// Color lookup tables in the Adobe/Resolve .cube format. Both 1D and 3D
+// tables are supported; 3D tables are interpolated trilinearly.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::color::Color;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CubeLut {
+    pub title: Option<String>,
+    // Entries per axis.
+    pub size: usize,
+    // A 1D table maps every channel on its own.
+    pub one_dimensional: bool,
+    pub domain_min: Color,
+    pub domain_max: Color,
+    // For 3D tables red changes fastest, then green, then blue.
+    pub table: Vec<Color>,
+}
+
+fn invalid_data(line: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line, msg),
+    )
+}
+
+fn parse_color(line: usize, fields: &[&str]) -> io::Result<Color> {
+    let values: Vec<f64> = fields
+        .iter()
+        .map(|f| {
+            f.parse()
+                .map_err(|_| invalid_data(line, "expected a number"))
+        })
+        .collect::<io::Result<_>>()?;
+    match values[..] {
+        [r, g, b] => Ok(Color::new(r, g, b)),
+        _ => Err(invalid_data(line, "expected three numbers")),
+    }
+}
+
+fn lerp(a: Color, b: Color, t: f64) -> Color {
+    (1.0 - t) * a + t * b
+}
+
+impl CubeLut {
+    pub fn load(path: &Path) -> io::Result<CubeLut> {
+        CubeLut::parse(&fs::read_to_string(path)?)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn parse(src: &str) -> io::Result<CubeLut> {
+        let mut lut = CubeLut {
+            title: None,
+            size: 0,
+            one_dimensional: false,
+            domain_min: Color::new(0.0, 0.0, 0.0),
+            domain_max: Color::new(1.0, 1.0, 1.0),
+            table: Vec::new(),
+        };
+
+        for (n, line) in src.lines().enumerate() {
+            let n = n + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields[0] {
+                "TITLE" => {
+                    lut.title = Some(line["TITLE".len()..].trim().trim_matches('"').to_string())
+                }
+                "LUT_3D_SIZE" | "LUT_1D_SIZE" => {
+                    lut.size = fields
+                        .get(1)
+                        .and_then(|s| s.parse().ok())
+                        .filter(|size| *size >= 2)
+                        .ok_or_else(|| invalid_data(n, "invalid table size"))?;
+                    lut.one_dimensional = fields[0] == "LUT_1D_SIZE";
+                }
+                "DOMAIN_MIN" => lut.domain_min = parse_color(n, &fields[1..])?,
+                "DOMAIN_MAX" => lut.domain_max = parse_color(n, &fields[1..])?,
+                keyword if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                    // Other keywords, e.g. LUT_1D_INPUT_RANGE, are ignored.
+                }
+                _ => lut.table.push(parse_color(n, &fields)?),
+            }
+        }
+
+        if lut.size == 0 {
+            return Err(invalid_data(0, "missing LUT_3D_SIZE or LUT_1D_SIZE"));
+        }
+        let expected = if lut.one_dimensional {
+            lut.size
+        } else {
+            lut.size.pow(3)
+        };
+        if lut.table.len() != expected {
+            return Err(invalid_data(
+                0,
+                &format!("expected {} entries, found {}", expected, lut.table.len()),
+            ));
+        }
+        Ok(lut)
+    }
+
+    // Position of `c` in table coordinates, 0 to size - 1 per channel.
+    fn coordinates(&self, c: Color) -> [f64; 3] {
+        let max = (self.size - 1) as f64;
+        let scale = |v: f64, min: f64, hi: f64| ((v - min) / (hi - min) * max).clamp(0.0, max);
+        [
+            scale(c.x(), self.domain_min.x(), self.domain_max.x()),
+            scale(c.y(), self.domain_min.y(), self.domain_max.y()),
+            scale(c.z(), self.domain_min.z(), self.domain_max.z()),
+        ]
+    }
+
+    pub fn apply(&self, c: Color) -> Color {
+        let p = self.coordinates(c);
+        let split = |v: f64| {
+            let i = (v.floor() as usize).min(self.size - 2);
+            (i, v - i as f64)
+        };
+
+        if self.one_dimensional {
+            let channel = |axis: usize| {
+                let (i, t) = split(p[axis]);
+                let (a, b) = (self.table[i], self.table[i + 1]);
+                let (a, b) = match axis {
+                    0 => (a.x(), b.x()),
+                    1 => (a.y(), b.y()),
+                    _ => (a.z(), b.z()),
+                };
+                a + (b - a) * t
+            };
+            return Color::new(channel(0), channel(1), channel(2));
+        }
+
+        let ((r, tr), (g, tg), (b, tb)) = (split(p[0]), split(p[1]), split(p[2]));
+        let at = |r: usize, g: usize, b: usize| self.table[(b * self.size + g) * self.size + r];
+        let plane = |b: usize| {
+            lerp(
+                lerp(at(r, g, b), at(r + 1, g, b), tr),
+                lerp(at(r, g + 1, b), at(r + 1, g + 1, b), tr),
+                tg,
+            )
+        };
+        lerp(plane(b), plane(b + 1), tb)
+    }
+}
+
+#[test]
+fn test_identity_cube() {
+    let mut src = String::from("TITLE \"identity\"\n# comment\nLUT_3D_SIZE 2\n");
+    for b in 0..2 {
+        for g in 0..2 {
+            for r in 0..2 {
+                src.push_str(&format!("{} {} {}\n", r, g, b));
+            }
+        }
+    }
+    let lut = CubeLut::parse(&src).unwrap();
+    assert_eq!(lut.title.as_deref(), Some("identity"));
+    let c = Color::new(0.25, 0.5, 0.75);
+    assert!((lut.apply(c) - c).length() < 1e-12);
+    assert_eq!(
+        lut.apply(Color::new(2.0, -1.0, 1.0)),
+        Color::new(1.0, 0.0, 1.0)
+    );
+}
+
+#[test]
+fn test_1d_cube() {
+    let lut = CubeLut::parse("LUT_1D_SIZE 2\n1 1 0\n0 0 1\n").unwrap();
+    assert_eq!(
+        lut.apply(Color::new(0.0, 0.25, 1.0)),
+        Color::new(1.0, 0.75, 1.0)
+    );
+    assert!(CubeLut::parse("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+}
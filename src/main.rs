@@ -1,19 +1,683 @@
 use std::env;
-use std::fs;
+use std::path::Path;
 
-use raytracer::config::Config;
+use raytracer::aov;
+use raytracer::bake::{self, BakeSettings};
+use raytracer::bench;
+use raytracer::camera::MaterialOverride;
+use raytracer::compare;
+use raytracer::config::{self, Config};
+use raytracer::contact_sheet;
+use raytracer::environment::Environment;
+use raytracer::film::{self, Film};
+use raytracer::furnace;
+use raytracer::info::SceneInfo;
+use raytracer::mesh::{Mesh, TriangleMesh};
+use raytracer::profile;
+use raytracer::renderer::Renderer;
+use raytracer::scene::Scene;
+use raytracer::scenes;
+use raytracer::term::TermGraphics;
+use tracing_subscriber::EnvFilter;
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        println!("Usage: {} <config_file> <output_file>", args[0]);
+fn usage(program: &str) {
+    println!(
+        "Usage: {} <config_file> <output_file> [--aov <name>]... [--preview-term[=ansi|sixel|kitty]] [--check-nan] [--override clay|uv_checker|normals] [--stream|--layers] [--camera <name>]... [--all-cameras] [--environment-rotation <yaw,pitch,roll>] [--environment-intensity <x>]",
+        program
+    );
+    println!("       {} <config_file> --dry-run", program);
+    println!("       {} export <generator> <output_file> [seed]", program);
+    println!("       {} info <config_file|image.png>", program);
+    println!("       {} watch <config_file> [output_file]", program);
+    println!("       {} migrate <config_file> [output_file]", program);
+    println!(
+        "       {} bake <config_file> <mesh.obj> <output_file> [--size <texels>] [--samples <n>] [--distance <d>] [--irradiance]",
+        program
+    );
+    println!(
+        "       {} compare <image_a> <image_b> [--heatmap <output_file>]",
+        program
+    );
+    println!("       {} furnace [config_file] [--samples <n>]", program);
+    println!("       {} bench [seconds_per_workload]", program);
+    println!("       {} profile <config_file>", program);
+    println!(
+        "       {} batch <output_dir> <config_file>... [--contact-sheet <output_file>] [--thumbnails]",
+        program
+    );
+    println!("Generators: {}", scenes::GENERATORS.join(", "));
+    println!("AOVs: {}", aov::AOVS.join(", "));
+}
+
+fn export(args: &[String]) {
+    let seed = match args.get(4) {
+        Some(seed) => seed.parse().expect("Seed must be a number"),
+        None => 0,
+    };
+    let Some(config) = scenes::generate(&args[2], seed) else {
+        println!("Unknown generator {}", args[2]);
         return;
+    };
+
+    config
+        .save(Path::new(&args[3]))
+        .expect("Unable to write scene file");
+    println!("Exported {} to {}", args[2], args[3]);
+}
+
+fn bench(args: &[String]) {
+    let seconds = match args.get(2) {
+        Some(seconds) => seconds.parse().expect("Seconds must be a number"),
+        None => 3.0,
+    };
+    for result in bench::run(std::time::Duration::from_secs_f64(seconds)) {
+        println!(
+            "{:<14} {:>8.2} Msamples/s",
+            result.name,
+            result.msamples_per_second()
+        );
+    }
+}
+
+fn profile(args: &[String]) {
+    let scene = Config::from_file(Path::new(&args[2])).expect("Unable to load scene file");
+    let renderer = Renderer::from(scene);
+    println!(
+        "{}",
+        profile::profile(&renderer.camera, &renderer.scene.objects)
+    );
+}
+
+fn info(args: &[String]) {
+    // Rendered images tell how they were made.
+    if Path::new(&args[2]).extension().is_some_and(|e| e == "png") {
+        let text = film::read_text(&args[2]).expect("Unable to read image");
+        for (keyword, value) in text {
+            println!("{}: {}", keyword, value);
+        }
+        return;
+    }
+    let scene = Config::from_file(Path::new(&args[2])).expect("Unable to load scene file");
+    println!("{}", SceneInfo::new(&scene));
+}
+
+fn migrate(args: &[String]) {
+    let input = Path::new(&args[2]);
+    let output = args.get(3).map_or(input, Path::new);
+    let version = config::migrate_file(input, output).expect("Unable to migrate scene file");
+    println!(
+        "Migrated {} from version {} to {}",
+        args[2],
+        version,
+        raytracer::migration::CURRENT_VERSION
+    );
+}
+
+#[cfg(feature = "watch")]
+fn watch(args: &[String]) {
+    let scene = Path::new(&args[2]);
+    let output = match args.get(3) {
+        Some(output) => Path::new(output).to_path_buf(),
+        None => scene.with_extension("preview.png"),
+    };
+    raytracer::watch::watch(scene, &output).expect("Unable to watch scene file");
+}
+
+#[cfg(not(feature = "watch"))]
+fn watch(_args: &[String]) {
+    println!("raytracer was built without the \"watch\" feature");
+}
+
+fn dry_run(args: &[String]) {
+    let scene = Config::from_file(Path::new(&args[1])).expect("Unable to load scene file");
+
+    let problems = scene.validate();
+    for problem in &problems {
+        println!("error: {}", problem);
+    }
+    if !problems.is_empty() {
+        std::process::exit(1);
+    }
+
+    let estimate = scene.camera.estimate(&scene.object_list, 2000);
+    println!(
+        "Scene is valid ({} objects)",
+        scene.object_list.objects.len()
+    );
+    println!(
+        "Rendered {} of {} pixels in {:.2?}",
+        estimate.sampled_pixels, estimate.total_pixels, estimate.elapsed
+    );
+    println!("Estimated render time: {:.1?}", estimate.estimated);
+    println!(
+        "Estimated memory: {:.1} MiB",
+        estimate.memory_bytes as f64 / (1024.0 * 1024.0)
+    );
+}
+
+// Removes every `<flag> <value>` pair from `args` and returns the values.
+fn take_option(args: &mut Vec<String>, flag: &str) -> Option<Vec<String>> {
+    let mut values = Vec::new();
+    while let Some(pos) = args.iter().position(|arg| arg == flag) {
+        if pos + 1 >= args.len() {
+            return None;
+        }
+        values.push(args.remove(pos + 1));
+        args.remove(pos);
+    }
+    Some(values)
+}
+
+fn bake(mut args: Vec<String>) {
+    let mut settings = BakeSettings::default();
+    let mut number = |flag: &str| match take_option(&mut args, flag).as_deref() {
+        Some([]) => None,
+        Some([.., value]) => Some(value.parse::<f64>().expect("Bake options must be numbers")),
+        None => panic!("{} needs a value", flag),
+    };
+    if let Some(size) = number("--size") {
+        settings.size = size as usize;
+    }
+    if let Some(samples) = number("--samples") {
+        settings.samples = samples as usize;
+    }
+    if let Some(distance) = number("--distance") {
+        settings.distance = distance;
+    }
+    settings.irradiance = args.iter().any(|arg| arg == "--irradiance");
+    args.retain(|arg| arg != "--irradiance");
+    if args.len() != 5 {
+        usage(&args[0]);
+        return;
+    }
+
+    let scene = Config::from_file(Path::new(&args[2])).expect("Unable to load scene file");
+    let mesh = TriangleMesh::new(Mesh::load_obj(Path::new(&args[3])).expect("Unable to load mesh"));
+    println!("Baking {} to {}", args[3], args[4]);
+    bake::bake_to_file(
+        &scene.camera,
+        &Scene::new(scene.object_list),
+        &mesh,
+        &settings,
+        &args[4],
+    )
+    .expect("Unable to bake mesh");
+}
+
+fn compare(mut args: Vec<String>) {
+    let heatmap = match take_option(&mut args, "--heatmap").as_deref() {
+        Some([]) => None,
+        Some([.., filename]) => Some(filename.clone()),
+        None => {
+            usage(&args[0]);
+            return;
+        }
+    };
+    if args.len() != 4 {
+        usage(&args[0]);
+        return;
+    }
+
+    let comparison = compare::compare_files(&args[2], &args[3], heatmap.as_deref())
+        .expect("Unable to compare images");
+    println!("PSNR: {:.2} dB", comparison.psnr);
+    println!("SSIM: {:.4}", comparison.ssim);
+    println!("FLIP: {:.4}", comparison.flip);
+    println!("Max difference: {}", comparison.max_difference);
+}
+
+// Renders every scene to output_dir/<scene name>.png, keeping a thumbnail of
+// each for the contact sheet.
+fn batch(mut args: Vec<String>) {
+    let sheet = match take_option(&mut args, "--contact-sheet").as_deref() {
+        Some([]) => None,
+        Some([.., filename]) => Some(filename.clone()),
+        None => {
+            usage(&args[0]);
+            return;
+        }
+    };
+    let write_thumbnails = args.iter().any(|arg| arg == "--thumbnails");
+    args.retain(|arg| arg != "--thumbnails");
+    if args.len() < 4 {
+        usage(&args[0]);
+        return;
+    }
+
+    let output_dir = Path::new(&args[2]);
+    std::fs::create_dir_all(output_dir).expect("Unable to create output directory");
+    let mut thumbnails = Vec::new();
+    for scene_file in &args[3..] {
+        let scene_file = Path::new(scene_file);
+        let name = scene_file.file_stem().expect("Scene files need a name");
+        let output = output_dir.join(name).with_extension("png");
+        println!("Rendering {}", output.display());
+
+        let scene = Config::from_file(scene_file).expect("Unable to load scene file");
+        let renderer = Renderer::from(scene);
+        let film = renderer
+            .render_and_save(&output.to_string_lossy())
+            .expect("Unable to write image")
+            .clipped(renderer.camera.clip);
+        let thumbnail = contact_sheet::thumbnail(&film, contact_sheet::THUMBNAIL_WIDTH);
+        if write_thumbnails {
+            thumbnail
+                .save(&output.with_extension("thumb.png").to_string_lossy())
+                .expect("Unable to write thumbnail");
+        }
+        thumbnails.push(thumbnail);
+    }
+
+    if let Some(sheet) = sheet {
+        let columns = contact_sheet::columns(thumbnails.len());
+        contact_sheet::contact_sheet(&thumbnails, columns)
+            .save(&sheet)
+            .expect("Unable to write contact sheet");
+        println!("Wrote contact sheet {}", sheet);
     }
+}
+
+// Checks the built-in materials, or those of a scene, in a white furnace and
+// exits with an error if any of them gains or loses energy.
+fn furnace(mut args: Vec<String>) {
+    let samples = match take_option(&mut args, "--samples").as_deref() {
+        Some([]) => 10000,
+        Some([.., samples]) => samples.parse().expect("Samples must be a number"),
+        None => {
+            usage(&args[0]);
+            return;
+        }
+    };
+    let materials = match args.len() {
+        2 => furnace::builtin_materials()
+            .into_iter()
+            .map(|(name, material)| (name.to_string(), material))
+            .collect(),
+        3 => {
+            let scene = Config::from_file(Path::new(&args[2])).expect("Unable to load scene file");
+            scene
+                .object_list
+                .objects
+                .iter()
+                .enumerate()
+                .map(|(index, object)| {
+                    let name = object.name().map_or(format!("#{}", index), str::to_string);
+                    (name, object.material().clone())
+                })
+                .collect::<Vec<_>>()
+        }
+        _ => {
+            usage(&args[0]);
+            return;
+        }
+    };
 
-    let json = fs::read(&args[1]).expect("Unable to read config file.");
-    let scene = serde_json::from_slice::<Config>(&json).expect("Unable to parse config json");
+    let mut failed = 0;
+    for (name, material) in &materials {
+        let Some(result) = furnace::furnace(material, samples) else {
+            println!("{:<16} {:<13} skipped", name, material.kind());
+            continue;
+        };
+        let (e, m) = (result.expected, result.measured);
+        println!(
+            "{:<16} {:<13} expected {:.3} {:.3} {:.3}  measured {:.3} {:.3} {:.3}  {}",
+            name,
+            material.kind(),
+            e.x(),
+            e.y(),
+            e.z(),
+            m.x(),
+            m.y(),
+            m.z(),
+            if result.passed() { "ok" } else { "FAILED" }
+        );
+        if !result.passed() {
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        println!("{} of {} materials failed", failed, materials.len());
+        std::process::exit(1);
+    }
+}
+
+// Removes `--preview-term` or `--preview-term=<mode>` from `args`. The outer
+// None means an unknown mode.
+fn take_preview_term(args: &mut Vec<String>) -> Option<Option<TermGraphics>> {
+    let Some(pos) = args
+        .iter()
+        .position(|arg| arg.starts_with("--preview-term"))
+    else {
+        return Some(None);
+    };
+    let arg = args.remove(pos);
+    match arg.strip_prefix("--preview-term") {
+        Some("") => Some(Some(TermGraphics::detect())),
+        Some(mode) => TermGraphics::from_name(mode.strip_prefix('=')?).map(Some),
+        None => unreachable!(),
+    }
+}
+
+// Renders with the scene's own camera, or with each of `cameras` to an output
+// file named after it, e.g. out.closeup.png.
+#[allow(clippy::too_many_arguments)]
+fn render(
+    args: &[String],
+    aovs: &[String],
+    preview: Option<TermGraphics>,
+    check_nan: bool,
+    material_override: Option<MaterialOverride>,
+    environment: EnvironmentOverride,
+    mode: Output,
+    cameras: Cameras,
+) {
+    let path = Path::new(&args[1]);
+    let scene = Config::from_file(path).expect("Unable to load scene file");
+    let names = match cameras {
+        Cameras::Default => {
+            render_view(
+                scene,
+                &args[2],
+                aovs,
+                preview,
+                check_nan,
+                material_override,
+                environment,
+                mode,
+            );
+            return;
+        }
+        Cameras::Named(names) if names.len() == 1 => {
+            let mut scene = scene;
+            scene
+                .use_camera(&names[0])
+                .expect("Unable to select camera");
+            render_view(
+                scene,
+                &args[2],
+                aovs,
+                preview,
+                check_nan,
+                material_override,
+                environment,
+                mode,
+            );
+            return;
+        }
+        Cameras::Named(names) => names,
+        Cameras::All => scene.cameras.keys().cloned().collect(),
+    };
+    if names.is_empty() {
+        println!("{} has no named cameras", args[1]);
+        std::process::exit(1);
+    }
+
+    let output = Path::new(&args[2]);
+    let extension = output
+        .extension()
+        .map_or("png".into(), |e| e.to_string_lossy());
+    let mut scene = Some(scene);
+    for name in &names {
+        // Rendering takes the scene, so every camera after the first loads
+        // it again.
+        let mut scene = scene
+            .take()
+            .unwrap_or_else(|| Config::from_file(path).expect("Unable to load scene file"));
+        scene.use_camera(name).expect("Unable to select camera");
+        let filename = output.with_extension(format!("{}.{}", name, extension));
+        render_view(
+            scene,
+            &filename.to_string_lossy(),
+            aovs,
+            preview,
+            check_nan,
+            material_override,
+            environment,
+            mode,
+        );
+    }
+}
 
-    let filename = &args[2];
+// How the image is written: whole, streamed a few rows at a time, or as one
+// image for each render layer.
+#[derive(Clone, Copy)]
+enum Output {
+    Image,
+    Stream,
+    Layers,
+}
+
+// Which cameras of the scene to render with.
+enum Cameras {
+    Default,
+    Named(Vec<String>),
+    All,
+}
+
+// Environment settings from the command line, over the scene's.
+#[derive(Clone, Copy, Default)]
+struct EnvironmentOverride {
+    rotation: Option<(f64, f64, f64)>,
+    intensity: Option<f64>,
+}
+
+impl EnvironmentOverride {
+    // None when a value can't be read.
+    fn take(args: &mut Vec<String>) -> Option<EnvironmentOverride> {
+        let rotation = match take_option(args, "--environment-rotation")?.last() {
+            Some(angles) => Some(Environment::parse_rotation(angles)?),
+            None => None,
+        };
+        let intensity = match take_option(args, "--environment-intensity")?.last() {
+            Some(intensity) => Some(intensity.parse().ok()?),
+            None => None,
+        };
+        Some(EnvironmentOverride {
+            rotation,
+            intensity,
+        })
+    }
+
+    fn apply(&self, environment: &mut Environment) {
+        if let Some((yaw, pitch, roll)) = self.rotation {
+            (environment.yaw, environment.pitch, environment.roll) = (yaw, pitch, roll);
+        }
+        if let Some(intensity) = self.intensity {
+            environment.intensity = intensity;
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_view(
+    mut scene: Config,
+    filename: &str,
+    aovs: &[String],
+    preview: Option<TermGraphics>,
+    check_nan: bool,
+    material_override: Option<MaterialOverride>,
+    environment: EnvironmentOverride,
+    mode: Output,
+) {
     println!("\nRendering {}", filename);
-    scene.camera.render(filename, &scene.object_list).unwrap()
+    environment.apply(&mut scene.object_list.environment);
+    let mut renderer = Renderer::from(scene);
+    renderer.terminal_preview = preview;
+    renderer.camera.check_nan |= check_nan;
+    if material_override.is_some() {
+        renderer.camera.material_override = material_override;
+    }
+    // The passes come with the image where it is kept whole, and from a
+    // render of their own otherwise.
+    let with_passes = aovs.iter().any(|name| aov::is_pass(name));
+    let features = aovs.iter().any(|name| name == "denoise");
+    let mut passes = None;
+    match mode {
+        Output::Image if with_passes => {
+            passes = Some(renderer.render_with_passes(filename, features).unwrap())
+        }
+        Output::Image => renderer.render(filename).unwrap(),
+        Output::Stream => renderer.render_streaming(filename).unwrap(),
+        Output::Layers if renderer.layers.is_empty() => {
+            println!("The scene has no render layers");
+            std::process::exit(1);
+        }
+        Output::Layers => renderer.render_layers(filename).unwrap(),
+    }
+
+    for name in aovs {
+        println!("Writing {} AOV", name);
+        let (camera, world) = (&renderer.camera, &renderer.scene.objects);
+        let output = Path::new(filename);
+        if !aov::is_pass(name) {
+            match name.as_str() {
+                "object_id" => aov::render_object_ids(camera, world, output)
+                    .expect("Unable to write object ID AOV"),
+                _ => aov::write_alpha(camera, world, output).expect("Unable to write alpha AOV"),
+            }
+            continue;
+        }
+        let passes = passes.get_or_insert_with(|| {
+            println!("Rendering AOV passes");
+            renderer.render_passes(features).unwrap()
+        });
+        let save = |film: Film| {
+            film.save(&aov::aov_path(output, name, "png").to_string_lossy())
+                .expect("Unable to write light AOV")
+        };
+        match name.as_str() {
+            "samples" => {
+                aov::write_samples(camera, passes, output).expect("Unable to write sampling AOV")
+            }
+            "variance" => {
+                aov::write_variance(camera, passes, output).expect("Unable to write sampling AOV")
+            }
+            "direct" | "indirect" => {
+                let (direct, indirect) = aov::light_split(camera, passes);
+                save(if name == "direct" { direct } else { indirect });
+            }
+            "emission" | "diffuse" | "specular" => {
+                let [emission, diffuse, specular] = aov::path_types(camera, passes);
+                save(match name.as_str() {
+                    "emission" => emission,
+                    "diffuse" => diffuse,
+                    _ => specular,
+                });
+            }
+            "denoise" => aov::features(camera, passes)
+                .save(output)
+                .expect("Unable to write denoising features"),
+            _ => unreachable!(),
+        }
+    }
+}
+
+// Log to stderr, at the info level unless RUST_LOG says otherwise.
+fn init_logging() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+fn main() {
+    init_logging();
+    let mut args: Vec<String> = env::args().collect();
+    let aovs = match take_option(&mut args, "--aov") {
+        Some(aovs) if aovs.iter().all(|name| aov::AOVS.contains(&name.as_str())) => aovs,
+        _ => {
+            usage(&args[0]);
+            return;
+        }
+    };
+    let Some(preview) = take_preview_term(&mut args) else {
+        usage(&args[0]);
+        return;
+    };
+    let all_cameras = args.iter().any(|arg| arg == "--all-cameras");
+    args.retain(|arg| arg != "--all-cameras");
+    let cameras = match take_option(&mut args, "--camera") {
+        Some(names) if names.is_empty() && all_cameras => Cameras::All,
+        Some(names) if names.is_empty() => Cameras::Default,
+        Some(names) if !all_cameras => Cameras::Named(names),
+        _ => {
+            usage(&args[0]);
+            return;
+        }
+    };
+    // The outer None is a missing or unknown name.
+    let material_override = match take_option(&mut args, "--override").as_deref() {
+        Some([]) => Some(None),
+        Some([.., name]) => MaterialOverride::from_name(name).map(Some),
+        None => None,
+    };
+    let Some(material_override) = material_override else {
+        usage(&args[0]);
+        return;
+    };
+    let Some(environment) = EnvironmentOverride::take(&mut args) else {
+        usage(&args[0]);
+        return;
+    };
+    let check_nan = args.iter().any(|arg| arg == "--check-nan");
+    let mode = match (
+        args.iter().any(|arg| arg == "--stream"),
+        args.iter().any(|arg| arg == "--layers"),
+    ) {
+        (false, false) => Output::Image,
+        (true, false) => Output::Stream,
+        (false, true) => Output::Layers,
+        (true, true) => {
+            usage(&args[0]);
+            return;
+        }
+    };
+    args.retain(|arg| arg != "--check-nan" && arg != "--stream" && arg != "--layers");
+    if args.iter().any(|arg| arg == "--dry-run") {
+        let args: Vec<String> = args.into_iter().filter(|arg| arg != "--dry-run").collect();
+        if args.len() == 2 || args.len() == 3 {
+            dry_run(&args);
+        } else {
+            usage(&args[0]);
+        }
+        return;
+    }
+
+    if args.get(1).map(String::as_str) == Some("bake") {
+        bake(args);
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("compare") {
+        compare(args);
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("batch") {
+        batch(args);
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("furnace") {
+        furnace(args);
+        return;
+    }
+
+    match (args.get(1).map(String::as_str), args.len()) {
+        (Some("export"), 4 | 5) => export(&args),
+        (Some("migrate"), 3 | 4) => migrate(&args),
+        (Some("info"), 3) => info(&args),
+        (Some("bench"), 2 | 3) => bench(&args),
+        (Some("profile"), 3) => profile(&args),
+        (Some("watch"), 3 | 4) => watch(&args),
+        (Some(_), 3) => render(
+            &args,
+            &aovs,
+            preview,
+            check_nan,
+            material_override,
+            environment,
+            mode,
+            cameras,
+        ),
+        _ => usage(&args[0]),
+    }
 }
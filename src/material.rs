@@ -1,20 +1,73 @@
-use crate::color::Color;
+use crate::color::{blackbody, gamma_to_linear, luminance, Color};
+use crate::gobo::Gobo;
 use crate::hittable::HitRecord;
-use crate::ray::Ray;
+use crate::ies::Ies;
+use crate::light_link::LightLinks;
+use crate::ray::{Ray, RayKind};
+use crate::texture::ImageTexture;
 use crate::vec3::Vec3;
 use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+// Colors in scenes: linear [r, g, b], as large as a light needs, or as
+// picked in an image editor, a "#ff8800" or "#f80" hex string or
+// {"rgb255": [255, 136, 0]}, which are gamma encoded like the image written.
+// {"color": ..., "intensity": 4} multiplies any of them.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorInput {
+    Linear([f64; 3]),
+    Hex(String),
+    Rgb255 {
+        rgb255: [u8; 3],
+    },
+    Scaled {
+        color: Box<ColorInput>,
+        intensity: f64,
+    },
+}
+
+fn from_encoded(rgb: [u8; 3]) -> Color {
+    let channel = |c: u8| gamma_to_linear(c as f64 / 255.0);
+    Color::new(channel(rgb[0]), channel(rgb[1]), channel(rgb[2]))
+}
+
+impl ColorInput {
+    fn color(self) -> Result<Color, String> {
+        match self {
+            ColorInput::Linear([r, g, b]) => Ok(Color::new(r, g, b)),
+            ColorInput::Hex(text) => {
+                let invalid = || format!("color \"{}\" is not a hex color like \"#ff8800\"", text);
+                let digits = text.strip_prefix('#').ok_or_else(invalid)?;
+                // "#f80" is "#ff8800".
+                // from_str_radix alone would take "+f" as a digit pair.
+                if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(invalid());
+                }
+                let width = match digits.len() {
+                    3 => 1,
+                    6 => 2,
+                    _ => return Err(invalid()),
+                };
+                let mut rgb = [0; 3];
+                for (i, channel) in rgb.iter_mut().enumerate() {
+                    let value = u8::from_str_radix(&digits[i * width..(i + 1) * width], 16)
+                        .map_err(|_| invalid())?;
+                    *channel = if width == 1 { value * 17 } else { value };
+                }
+                Ok(from_encoded(rgb))
+            }
+            ColorInput::Rgb255 { rgb255 } => Ok(from_encoded(rgb255)),
+            ColorInput::Scaled { color, intensity } => Ok(color.color()? * intensity),
+        }
+    }
+}
 
 serde_with::serde_conv!(
-    ColorAsArray,
+    pub(crate) ColorAsArray,
     Color,
-    |color: &Color| [color.x() as f32, color.y() as f32, color.z() as f32],
-    |value: [f32; 3]| -> Result<_, std::convert::Infallible> {
-        Ok(Color::new(
-            value[0] as f64,
-            value[1] as f64,
-            value[2] as f64,
-        ))
-    }
+    |color: &Color| [color.x(), color.y(), color.z()],
+    |value: ColorInput| value.color()
 );
 
 pub trait Scatterable {
@@ -25,6 +78,11 @@ pub trait Scatterable {
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool;
+
+    // Light leaving the surface back along `r_in`, the ray that hit it.
+    fn emitted(&self, _r_in: &Ray) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -32,6 +90,136 @@ pub enum Material {
     Lambertian(Lambertian),
     Metal(Metal),
     Glass(Glass),
+    DiffuseLight(DiffuseLight),
+    Hair(Hair),
+    Medium(Medium),
+    // Reference to an entry of the scene's material palette, replaced by the
+    // material itself when the scene is loaded.
+    Named(String),
+}
+
+impl Material {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Material::Lambertian(_) => "lambertian",
+            Material::Metal(_) => "metal",
+            Material::Glass(_) => "glass",
+            Material::DiffuseLight(_) => "diffuse_light",
+            Material::Hair(_) => "hair",
+            Material::Medium(_) => "medium",
+            Material::Named(_) => "named",
+        }
+    }
+
+    // The images the material reads, to be loaded with the scene.
+    pub fn textures_mut(&mut self) -> impl Iterator<Item = &mut ImageTexture> {
+        let (texture, opacity) = match self {
+            Material::Lambertian(lambertian) => {
+                (lambertian.texture.as_mut(), lambertian.opacity.as_mut())
+            }
+            _ => (None, None),
+        };
+        texture.into_iter().chain(opacity)
+    }
+
+    // Whether parts of the surface may be cut away, see `is_cut_out`.
+    pub fn has_opacity(&self) -> bool {
+        matches!(
+            self,
+            Material::Lambertian(Lambertian {
+                opacity: Some(_),
+                ..
+            }) | Material::DiffuseLight(DiffuseLight {
+                sides: Sides::FrontInvisibleBack,
+                ..
+            })
+        )
+    }
+
+    // Whether the surface is cut away where `r` hit it: by an opacity mask,
+    // or on the back of a light whose back is invisible.
+    pub fn is_cut_out(&self, r: &Ray, rec: &HitRecord) -> bool {
+        match self {
+            Material::Lambertian(Lambertian {
+                opacity: Some(opacity),
+                ..
+            }) => {
+                let grey = opacity.color(r, rec);
+                (grey.x() + grey.y() + grey.z()) / 3.0 < 0.5
+            }
+            Material::DiffuseLight(light) => {
+                light.sides == Sides::FrontInvisibleBack && !rec.front_face
+            }
+            _ => false,
+        }
+    }
+
+    // `emitted` where `r_in` hit the surface at `rec`, which is nothing on
+    // the back of one-sided lights.
+    pub fn emitted_at(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        match self {
+            Material::DiffuseLight(light) if light.sides != Sides::Both && !rec.front_face => {
+                Color::default()
+            }
+            _ => self.emitted(r_in),
+        }
+    }
+
+    // Makes metal, glass and hair at least `roughness` rough, for path
+    // regularization.
+    pub fn roughen(&mut self, roughness: f64) {
+        match self {
+            Material::Metal(metal) => metal.fuzz = metal.fuzz.max(roughness),
+            Material::Glass(glass) => glass.roughness = glass.roughness.max(roughness),
+            Material::Hair(hair) => hair.roughness = hair.roughness.max(roughness),
+            _ => {}
+        }
+    }
+
+    // Scatters around the mirror or refracted direction rather than over the
+    // whole hemisphere, even when fuzzy.
+    pub fn is_specular(&self) -> bool {
+        matches!(self, Material::Metal(_) | Material::Glass(_))
+    }
+
+    // The light scattered at `rec` towards `wo` per unit of light arriving
+    // from `wi` per solid angle: the BSDF times the cosine at `wi`. Both
+    // point away from the surface. Over `pdf` it is the attenuation `scatter`
+    // gives for `wi`. Smooth metal and glass, whose lobes are infinitely
+    // thin, hair and lights give nothing; they can only be sampled. Textures
+    // are read at their sharpest.
+    pub fn eval(&self, wi: Vec3, wo: Vec3, rec: &HitRecord) -> Color {
+        let wo = wo.unit_vector();
+        self.eval_along(&Ray::new(rec.p + wo, -wo), wi, rec)
+    }
+
+    // `eval` for light leaving back along `r_in`, the ray that hit.
+    pub(crate) fn eval_along(&self, r_in: &Ray, wi: Vec3, rec: &HitRecord) -> Color {
+        let wi = wi.unit_vector();
+        let wo = -r_in.direction().unit_vector();
+        match self {
+            Material::Lambertian(lambertian) => {
+                lambertian.albedo(r_in, rec) * self.pdf(wi, wo, rec)
+            }
+            Material::Metal(metal) if wi.dot(&rec.normal) > 0.0 => {
+                metal.albedo * self.pdf(wi, wo, rec)
+            }
+            Material::Medium(medium) => medium.albedo * self.pdf(wi, wo, rec),
+            _ => Color::default(),
+        }
+    }
+
+    // The density per solid angle with which `scatter` sends light arriving
+    // from `wo` towards `wi`; 0 where `eval` gives nothing.
+    pub fn pdf(&self, wi: Vec3, wo: Vec3, rec: &HitRecord) -> f64 {
+        let (wi, wo) = (wi.unit_vector(), wo.unit_vector());
+        match self {
+            Material::Lambertian(_) => rec.normal.dot(&wi).max(0.0) / std::f64::consts::PI,
+            Material::Metal(metal) => metal.pdf(wi, Vec3::reflect(&-wo, &rec.normal)),
+            Material::Medium(medium) => medium.phase(wi.dot(&-wo)),
+            _ => 0.0,
+        }
+    }
 }
 
 impl Scatterable for Material {
@@ -42,45 +230,83 @@ impl Scatterable for Material {
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
-        match self {
+        let scatters = match self {
             Material::Lambertian(l) => l.scatter(r_in, rec, attenuation, scattered),
             Material::Metal(m) => m.scatter(r_in, rec, attenuation, scattered),
             Material::Glass(g) => g.scatter(r_in, rec, attenuation, scattered),
+            Material::DiffuseLight(d) => d.scatter(r_in, rec, attenuation, scattered),
+            Material::Hair(h) => h.scatter(r_in, rec, attenuation, scattered),
+            Material::Medium(i) => i.scatter(r_in, rec, attenuation, scattered),
+            Material::Named(_) => false,
+        };
+        // The scattered ray's cone carries on from where this one's ended.
+        let cone = (r_in.cone_width(rec.t), r_in.spread());
+        *scattered = std::mem::take(scattered).with_cone(cone.0, cone.1);
+        scatters
+    }
+
+    fn emitted(&self, r_in: &Ray) -> Color {
+        match self {
+            Material::DiffuseLight(d) => d.emitted(r_in),
+            Material::Medium(i) => i.emitted(r_in),
+            _ => Color::new(0.0, 0.0, 0.0),
         }
     }
 }
 
 #[serde_with::serde_as]
-#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Lambertian {
     #[serde_as(as = "ColorAsArray")]
     pub albedo: Color,
+    // Multiplies `albedo`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub texture: Option<ImageTexture>,
+    // A cutout mask: where its grey level is below one half, the surface
+    // isn't there, for camera and shadow rays alike.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub opacity: Option<ImageTexture>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Self {
-        Self { albedo }
+        Self {
+            albedo,
+            texture: None,
+            opacity: None,
+        }
+    }
+
+    pub fn with_texture(albedo: Color, texture: ImageTexture) -> Self {
+        Self {
+            albedo,
+            texture: Some(texture),
+            opacity: None,
+        }
+    }
+
+    // The albedo where `r_in` hit.
+    pub fn albedo(&self, r_in: &Ray, rec: &HitRecord) -> Color {
+        match &self.texture {
+            Some(texture) => self.albedo * texture.color(r_in, rec),
+            None => self.albedo,
+        }
     }
 }
 
 impl Scatterable for Lambertian {
     fn scatter(
         &self,
-        _r_in: &Ray,
+        r_in: &Ray,
         rec: &HitRecord,
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
-        let scatter_direction = rec.normal + Vec3::random_unit_vector();
+        let scatter_direction =
+            (rec.normal + Vec3::random_unit_vector()).or_if_near_zero(rec.normal);
 
-        let scatter_direction = if scatter_direction.near_zero() {
-            rec.normal
-        } else {
-            scatter_direction
-        };
-
-        *scattered = Ray::new(rec.p, scatter_direction);
-        *attenuation = self.albedo;
+        *scattered = Ray::new(rec.p, scatter_direction).with_kind(RayKind::Diffuse);
+        *attenuation = self.albedo(r_in, rec);
         true
     }
 }
@@ -102,6 +328,30 @@ impl Metal {
     }
 }
 
+impl Metal {
+    // The density of the fuzzed direction `wi` around the unit vector
+    // `reflected`. Fuzz moves the tip of `reflected` to a random point of a
+    // sphere of radius `fuzz` around it, so it is the density of that point
+    // over the sphere, turned into one per solid angle at each point the
+    // direction passes through.
+    fn pdf(&self, wi: Vec3, reflected: Vec3) -> f64 {
+        if self.fuzz <= 0.0 {
+            return 0.0;
+        }
+        let along = wi.dot(&reflected);
+        let discriminant = along * along - 1.0 + self.fuzz * self.fuzz;
+        if discriminant <= 0.0 {
+            return 0.0;
+        }
+        let root = discriminant.sqrt();
+        [along - root, along + root]
+            .into_iter()
+            .filter(|&t| t > 0.0)
+            .map(|t| t * t / (4.0 * std::f64::consts::PI * self.fuzz * root))
+            .sum()
+    }
+}
+
 impl Scatterable for Metal {
     fn scatter(
         &self,
@@ -111,8 +361,9 @@ impl Scatterable for Metal {
         scattered: &mut Ray,
     ) -> bool {
         let reflected = Vec3::reflect(&r_in.direction().unit_vector(), &rec.normal);
-        let scattered_direction = reflected + self.fuzz * Vec3::random_unit_vector();
-        *scattered = Ray::new(rec.p, scattered_direction);
+        let scattered_direction =
+            (reflected + self.fuzz * Vec3::random_unit_vector()).or_if_near_zero(reflected);
+        *scattered = Ray::new(rec.p, scattered_direction).with_kind(RayKind::Specular);
         *attenuation = self.albedo;
         scattered.direction().dot(&rec.normal) > 0.0
     }
@@ -122,11 +373,36 @@ impl Scatterable for Metal {
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
 pub struct Glass {
     pub refraction_index: f64,
+    // How much of each color is absorbed per unit of distance inside, as in
+    // the Beer-Lambert law: colored glass.
+    #[serde_as(as = "Option<ColorAsArray>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub absorption: Option<Color>,
+    // Spreads the reflected and refracted rays like a metal's fuzz. Only
+    // path regularization sets it.
+    #[serde(skip)]
+    pub roughness: f64,
 }
 
 impl Glass {
     pub fn new(refraction_index: f64) -> Self {
-        Self { refraction_index }
+        Self {
+            refraction_index,
+            absorption: None,
+            roughness: 0.0,
+        }
+    }
+
+    // What is left of the light after `distance` inside.
+    pub fn transmittance(&self, distance: f64) -> Color {
+        match self.absorption {
+            Some(a) => Color::new(
+                (-a.x() * distance).exp(),
+                (-a.y() * distance).exp(),
+                (-a.z() * distance).exp(),
+            ),
+            None => Color::new(1.0, 1.0, 1.0),
+        }
     }
 
     fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
@@ -144,7 +420,12 @@ impl Scatterable for Glass {
         attenuation: &mut Color,
         scattered: &mut Ray,
     ) -> bool {
-        *attenuation = Color::new(1.0, 1.0, 1.0);
+        // Leaving the glass, the ray has been inside since it started.
+        *attenuation = if rec.front_face {
+            Color::new(1.0, 1.0, 1.0)
+        } else {
+            self.transmittance(rec.t * r_in.direction().length())
+        };
         let refraction_ratio = if rec.front_face {
             1.0 / self.refraction_index
         } else {
@@ -156,15 +437,686 @@ impl Scatterable for Glass {
         let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
+        let reflected = Vec3::reflect(&unit_direction, &rec.normal);
         let direction = if cannot_refract
-            || Glass::reflectance(cos_theta, refraction_ratio) > rand::random::<f64>()
+            || Glass::reflectance(cos_theta, refraction_ratio) > crate::rng::random()
         {
-            Vec3::reflect(&unit_direction, &rec.normal)
+            reflected
+        } else {
+            Vec3::refract(&unit_direction, &rec.normal, refraction_ratio).or_if_near_zero(reflected)
+        };
+        // Fuzzed, as long as that doesn't take it to the other side.
+        let direction = if self.roughness > 0.0 {
+            let fuzzed = (direction + self.roughness * Vec3::random_unit_vector())
+                .or_if_near_zero(direction);
+            if fuzzed.dot(&rec.normal) * direction.dot(&rec.normal) > 0.0 {
+                fuzzed
+            } else {
+                direction
+            }
         } else {
-            Vec3::refract(&unit_direction, &rec.normal, refraction_ratio)
+            direction
         };
 
-        *scattered = Ray::new(rec.p, direction);
+        *scattered = Ray::new(rec.p, direction).with_kind(RayKind::Specular);
         true
     }
 }
+
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DiffuseLight {
+    #[serde_as(as = "ColorAsArray")]
+    pub emit: Color,
+    // Color temperature in Kelvin. The black body color is multiplied with
+    // `emit`, which then only sets the brightness and a tint. Scene files may
+    // name a light source instead, see `NAMED_TEMPERATURES`.
+    #[serde(
+        default,
+        deserialize_with = "crate::color::deserialize_optional_kelvin",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub temperature: Option<f64>,
+    // Total power of the light. Only the color of `emit` is kept, scaled so
+    // that the object sends out this much light whatever its size. Watts are
+    // of light, not electricity, at 683 lm/W.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub watts: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lumens: Option<f64>,
+    // Surface area of the emitting object, filled in when the scene is
+    // loaded. Without it `watts` and `lumens` are ignored.
+    #[serde(skip)]
+    pub area: Option<f64>,
+    // Makes the brightness depend on the direction like a real fixture's,
+    // with `emit` in the brightest direction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ies: Option<Ies>,
+    // Projects an image, like a spot light with a gobo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gobo: Option<Gobo>,
+    // The objects it lights, see src/light_link.rs.
+    #[serde(default, skip_serializing_if = "LightLinks::is_default")]
+    pub links: LightLinks,
+    #[serde(default, skip_serializing_if = "Sides::is_both")]
+    pub sides: Sides,
+    // The black body color of `temperature`, worked out on the first hit
+    // instead of on every one.
+    #[serde(skip)]
+    black_body: OnceLock<(f64, Color)>,
+}
+
+// Which sides of the surface a light shines from. The front is the side its
+// normal points to: the outside of spheres and closed meshes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sides {
+    #[default]
+    Both,
+    // The back is black, like the housing of a light panel.
+    Front,
+    // The back isn't there: rays pass through it, so a panel seen from
+    // behind shows what is beyond it.
+    FrontInvisibleBack,
+}
+
+impl Sides {
+    pub fn is_both(&self) -> bool {
+        *self == Sides::Both
+    }
+}
+
+// The luminous efficacy of green light at 555 nm, by definition of the lumen.
+pub const LUMENS_PER_WATT: f64 = 683.0;
+
+impl DiffuseLight {
+    pub fn new(emit: Color) -> Self {
+        Self {
+            emit,
+            temperature: None,
+            watts: None,
+            lumens: None,
+            area: None,
+            ies: None,
+            gobo: None,
+            links: LightLinks::default(),
+            sides: Sides::Both,
+            black_body: OnceLock::new(),
+        }
+    }
+
+    // `emit` in the color of `temperature`.
+    pub fn color(&self) -> Color {
+        let Some(kelvin) = self.temperature else {
+            return self.emit;
+        };
+        let &(cached, color) = self.black_body.get_or_init(|| (kelvin, blackbody(kelvin)));
+        // The temperature changed since.
+        if cached != kelvin {
+            return self.emit * blackbody(kelvin);
+        }
+        self.emit * color
+    }
+
+    pub fn with_temperature(emit: Color, kelvin: f64) -> Self {
+        Self {
+            temperature: Some(kelvin),
+            ..Self::new(emit)
+        }
+    }
+
+    pub fn with_lumens(emit: Color, lumens: f64) -> Self {
+        Self {
+            lumens: Some(lumens),
+            ..Self::new(emit)
+        }
+    }
+
+    pub fn power_in_lumens(&self) -> Option<f64> {
+        self.lumens
+            .or(self.watts.map(|watts| watts * LUMENS_PER_WATT))
+    }
+}
+
+impl Scatterable for DiffuseLight {
+    fn scatter(
+        &self,
+        _r_in: &Ray,
+        _rec: &HitRecord,
+        _attenuation: &mut Color,
+        _scattered: &mut Ray,
+    ) -> bool {
+        false
+    }
+
+    fn emitted(&self, r_in: &Ray) -> Color {
+        let color = self.color();
+        // A diffuse emitter of area A sending out Φ has a luminance of
+        // Φ / (π A).
+        let color = match (self.power_in_lumens(), self.area) {
+            (Some(lumens), Some(area)) if area > 0.0 && luminance(color) > 0.0 => {
+                color / luminance(color) * lumens / (std::f64::consts::PI * area)
+            }
+            _ => color,
+        };
+        let towards = -*r_in.direction();
+        let color = match &self.ies {
+            Some(ies) => color * ies.factor(towards),
+            None => color,
+        };
+        match &self.gobo {
+            Some(gobo) => color * gobo.color(towards),
+            None => color,
+        }
+    }
+}
+
+fn default_hair_roughness() -> f64 {
+    0.3
+}
+
+// Keratin's.
+const HAIR_REFRACTION_INDEX: f64 = 1.55;
+
+// Hair and fur, made for curves, whose direction it takes from `dpdu`. Light
+// is reflected off a fiber (R), passes through it (TT), or is reflected once
+// inside it (TRT), picked by how much each lets out. All of them leave at the
+// angle to the fiber they came in at, spread by the roughness, which makes
+// the cones of Marschner's model; around the fiber they go where a cylinder
+// sends light entering at a random offset from its middle.
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct Hair {
+    // What is left of the light after passing once through the middle of a
+    // fiber: the pigment's color.
+    #[serde_as(as = "ColorAsArray")]
+    pub color: Color,
+    // How far the lobes spread, 0 to 1.
+    #[serde(default = "default_hair_roughness")]
+    pub roughness: f64,
+}
+
+impl Hair {
+    pub fn new(color: Color, roughness: f64) -> Self {
+        Self { color, roughness }
+    }
+}
+
+impl Scatterable for Hair {
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        use crate::rng::random;
+        use std::f64::consts::{FRAC_PI_2, PI};
+
+        let d = r_in.direction().unit_vector();
+        // On shapes other than curves, any direction along the surface.
+        let tangent = if rec.dpdu.near_zero() {
+            rec.normal.perpendiculars().0
+        } else {
+            rec.dpdu.unit_vector()
+        };
+        let sin_theta = d.dot(&tangent).clamp(-1.0, 1.0);
+        let cos_theta = (1.0 - sin_theta * sin_theta).sqrt();
+        let across = (d - sin_theta * tangent)
+            .or_if_near_zero(-rec.normal)
+            .unit_vector();
+        let around = tangent.cross(&across);
+
+        // Where across the fiber the light enters, and the angles to the
+        // surface outside and, refracted, inside, seen along the fiber.
+        let h = 2.0 * random() - 1.0;
+        let gamma_o = h.asin();
+        let eta = (HAIR_REFRACTION_INDEX * HAIR_REFRACTION_INDEX - sin_theta * sin_theta).sqrt()
+            / cos_theta.max(1e-6);
+        let gamma_t = (h / eta).asin();
+        let f = Glass::reflectance(cos_theta * gamma_o.cos(), HAIR_REFRACTION_INDEX);
+        // Longer paths through a fiber, off its middle or along it, absorb
+        // more.
+        let sin_theta_t = sin_theta / HAIR_REFRACTION_INDEX;
+        let passes = gamma_t.cos() / (1.0 - sin_theta_t * sin_theta_t).sqrt();
+        let through = Color::new(
+            self.color.x().powf(passes),
+            self.color.y().powf(passes),
+            self.color.z().powf(passes),
+        );
+        let lobes = [
+            Color::new(f, f, f),
+            (1.0 - f) * (1.0 - f) * through,
+            (1.0 - f) * (1.0 - f) * f * through * through,
+        ];
+        let weights = lobes.map(luminance);
+        let total: f64 = weights.iter().sum();
+        if total <= 0.0 {
+            return false;
+        }
+        let mut pick = random() * total;
+        let p = (0..2)
+            .find(|&p| {
+                pick -= weights[p];
+                pick < 0.0
+            })
+            .unwrap_or(2);
+        *attenuation = lobes[p] * (total / weights[p]);
+
+        // Turned around the fiber from where the light was going, and spread
+        // more the more often it was refracted.
+        let spread = [1.0, 0.5, 2.0][p] * self.roughness;
+        let phi = 2.0 * p as f64 * gamma_t - 2.0 * gamma_o
+            + (p as f64 - 1.0) * PI
+            + (random() - random()) * spread * PI;
+        let theta = (sin_theta.asin() + (random() - random()) * spread * FRAC_PI_2)
+            .clamp(-FRAC_PI_2, FRAC_PI_2);
+        let direction =
+            theta.sin() * tangent + theta.cos() * (phi.cos() * across + phi.sin() * around);
+        *scattered = Ray::new(rec.p, direction).with_kind(RayKind::Specular);
+        true
+    }
+}
+
+// The inside of a volume, see src/volume.rs. Light scattered in it leaves
+// in a direction drawn from the Henyey-Greenstein phase function: evenly in
+// all directions at an anisotropy of 0, mostly onwards towards 1, like in
+// clouds, whose droplets let most light through with a small turn, and
+// mostly back towards -1. Volumes hand it the light they give off where a
+// ray stopped in them.
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+pub struct Medium {
+    // The part of the light stopped in the volume that is scattered rather
+    // than absorbed.
+    #[serde_as(as = "ColorAsArray")]
+    pub albedo: Color,
+    // The average cosine of the turn scattered light takes, -1 to 1.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub anisotropy: f64,
+    #[serde(skip)]
+    pub emission: Color,
+}
+
+fn is_zero(value: &f64) -> bool {
+    *value == 0.0
+}
+
+impl Medium {
+    pub fn new(albedo: Color) -> Self {
+        Self {
+            albedo,
+            anisotropy: 0.0,
+            emission: Color::default(),
+        }
+    }
+
+    // The density of light turned by an angle with cosine `cos_theta`, per
+    // solid angle.
+    pub fn phase(&self, cos_theta: f64) -> f64 {
+        let g = self.anisotropy.clamp(-0.99, 0.99);
+        let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+        (1.0 - g * g) / (4.0 * std::f64::consts::PI * denominator * denominator.sqrt())
+    }
+
+    // The cosine of a turn drawn from `phase`.
+    fn sample_cos_theta(&self) -> f64 {
+        let g = self.anisotropy.clamp(-0.99, 0.99);
+        let u = crate::rng::random();
+        if g.abs() < 1e-3 {
+            return 1.0 - 2.0 * u;
+        }
+        let s = (1.0 - g * g) / (1.0 - g + 2.0 * g * u);
+        ((1.0 + g * g - s * s) / (2.0 * g)).clamp(-1.0, 1.0)
+    }
+}
+
+impl Scatterable for Medium {
+    // Draws the direction from the phase function itself, so the albedo is
+    // all there is to weigh it by.
+    fn scatter(
+        &self,
+        r_in: &Ray,
+        rec: &HitRecord,
+        attenuation: &mut Color,
+        scattered: &mut Ray,
+    ) -> bool {
+        let forward = r_in.direction().unit_vector();
+        let cos_theta = self.sample_cos_theta();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * crate::rng::random();
+        let (u, v) = forward.perpendiculars();
+        let direction = sin_theta * (phi.cos() * u + phi.sin() * v) + cos_theta * forward;
+        *scattered = Ray::new(rec.p, direction).with_kind(RayKind::Diffuse);
+        *attenuation = self.albedo;
+        self.albedo != Color::default()
+    }
+
+    fn emitted(&self, _r_in: &Ray) -> Color {
+        self.emission
+    }
+}
+
+#[cfg(test)]
+fn assert_scatters(material: &Material, r_in: &Ray, rec: &HitRecord) {
+    for _ in 0..1000 {
+        let mut attenuation = Color::default();
+        let mut scattered = Ray::default();
+        if material.scatter(r_in, rec, &mut attenuation, &mut scattered) {
+            assert!(!scattered.direction().near_zero());
+            assert!(scattered.direction().is_finite());
+        }
+    }
+}
+
+#[test]
+fn test_no_zero_length_scatter() {
+    let rec = HitRecord {
+        normal: Vec3::new(0.0, 1.0, 0.0),
+        front_face: true,
+        ..HitRecord::default()
+    };
+    // Straight down onto the surface, and grazing it.
+    for direction in [Vec3::new(0.0, -1.0, 0.0), Vec3::new(1.0, -1e-12, 0.0)] {
+        let r_in = Ray::new(Vec3::new(0.0, 1.0, 0.0), direction);
+        assert_scatters(
+            &Material::Lambertian(Lambertian::new(Color::default())),
+            &r_in,
+            &rec,
+        );
+        assert_scatters(
+            &Material::Metal(Metal::new(Color::default(), 1.0)),
+            &r_in,
+            &rec,
+        );
+        assert_scatters(&Material::Glass(Glass::new(1.5)), &r_in, &rec);
+        assert_scatters(
+            &Material::Hair(Hair::new(Color::new(0.5, 0.3, 0.1), 0.3)),
+            &r_in,
+            &rec,
+        );
+    }
+}
+
+#[test]
+fn test_hair() {
+    // A fiber along x, lit from above and to the side.
+    let rec = HitRecord {
+        normal: Vec3::new(0.0, 1.0, 0.0),
+        front_face: true,
+        dpdu: Vec3::new(2.0, 0.0, 0.0),
+        ..HitRecord::default()
+    };
+    let r_in = Ray::new(Vec3::new(-1.0, 1.0, 0.0), Vec3::new(1.0, -1.0, 0.0));
+    let scatter = |hair: &Hair| {
+        let mut attenuation = Color::default();
+        let mut scattered = Ray::default();
+        assert!(hair.scatter(&r_in, &rec, &mut attenuation, &mut scattered));
+        (attenuation, scattered.direction().unit_vector())
+    };
+
+    // Smooth, all of it leaves on the cone around the fiber.
+    let smooth = Hair::new(Color::new(0.8, 0.5, 0.2), 0.0);
+    for _ in 0..100 {
+        let (attenuation, direction) = scatter(&smooth);
+        assert!((direction.x() - 0.5f64.sqrt()).abs() < 1e-9);
+        assert!(attenuation.x() >= attenuation.z());
+    }
+    // Black hair only reflects off its surface, uncolored and mostly back up.
+    let black = Hair::new(Color::default(), 0.0);
+    let mut up = 0;
+    for _ in 0..100 {
+        let (attenuation, direction) = scatter(&black);
+        assert!(attenuation.x() == attenuation.y() && attenuation.y() == attenuation.z());
+        up += (direction.y() > 0.0) as usize;
+    }
+    assert!(up > 50);
+}
+
+#[test]
+fn test_roughen() {
+    let mut metal = Material::Metal(Metal::new(Color::default(), 0.2));
+    metal.roughen(0.5);
+    assert!(matches!(metal, Material::Metal(Metal { fuzz: 0.5, .. })));
+    metal.roughen(0.1);
+    assert!(matches!(metal, Material::Metal(Metal { fuzz: 0.5, .. })));
+
+    // Rough glass still sends rays through it, not back out.
+    let mut glass = Material::Glass(Glass::new(1.0));
+    glass.roughen(1.0);
+    let rec = HitRecord {
+        normal: Vec3::new(0.0, 1.0, 0.0),
+        front_face: true,
+        ..HitRecord::default()
+    };
+    let r_in = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+    let mut spread = 0.0f64;
+    for _ in 0..100 {
+        let mut attenuation = Color::default();
+        let mut scattered = Ray::default();
+        assert!(glass.scatter(&r_in, &rec, &mut attenuation, &mut scattered));
+        assert!(scattered.direction().y() < 0.0);
+        spread = spread.max(scattered.direction().unit_vector().x().abs());
+    }
+    assert!(spread > 0.1);
+}
+
+#[test]
+fn test_medium() {
+    let r_in = Ray::new(Vec3::default(), Vec3::new(0.0, 0.0, -2.0));
+    let rec = HitRecord::default();
+    for g in [-0.5, 0.0, 0.8] {
+        let medium = Medium {
+            anisotropy: g,
+            ..Medium::new(Color::new(0.9, 0.9, 0.9))
+        };
+        // The phase function is a density over the sphere, and its average
+        // cosine is the anisotropy; the scattered rays follow it.
+        let n = 2000;
+        let integral: f64 = (0..n)
+            .map(|i| {
+                let cos_theta = -1.0 + (i as f64 + 0.5) * 2.0 / n as f64;
+                2.0 * std::f64::consts::PI * medium.phase(cos_theta) * 2.0 / n as f64
+            })
+            .sum();
+        assert!((integral - 1.0).abs() < 1e-3, "{}", integral);
+
+        let samples = 20000;
+        let mut mean = 0.0;
+        for _ in 0..samples {
+            let mut attenuation = Color::default();
+            let mut scattered = Ray::default();
+            assert!(medium.scatter(&r_in, &rec, &mut attenuation, &mut scattered));
+            assert_eq!(attenuation, medium.albedo);
+            mean += -scattered.direction().unit_vector().z() / samples as f64;
+        }
+        assert!((mean - g).abs() < 0.02, "{} {}", g, mean);
+
+        crate::rng::seed_sample(0, 0, 0, 0);
+        let forward = r_in.direction().unit_vector();
+        let phase = |wi: Vec3| medium.phase(wi.dot(&forward));
+        let result =
+            crate::testing::chi_squared_scatter(&medium, phase, &r_in, &rec, forward, 50_000);
+        assert!(result.passed(), "{} {:?}", g, result);
+    }
+}
+
+#[test]
+fn test_eval() {
+    use std::f64::consts::PI;
+
+    let rec = HitRecord {
+        normal: Vec3::new(0.0, 1.0, 0.0),
+        front_face: true,
+        ..HitRecord::default()
+    };
+    let wo = Vec3::new(1.0, 1.0, 0.0).unit_vector();
+    let r_in = Ray::new(wo, -wo);
+    let materials = [
+        Material::Lambertian(Lambertian::new(Color::new(0.8, 0.5, 0.2))),
+        Material::Metal(Metal::new(Color::new(0.9, 0.9, 0.9), 0.3)),
+        Material::Metal(Metal::new(Color::new(0.9, 0.9, 0.9), 1.0)),
+        Material::Medium(Medium {
+            anisotropy: 0.6,
+            ..Medium::new(Color::new(0.5, 0.5, 0.5))
+        }),
+    ];
+    for material in &materials {
+        // Each density only depends on the angle to an axis, so it is
+        // summed over rings around it. It covers the sphere once, and
+        // scattered rays fall into a cone around the axis as often as it
+        // says.
+        let axis = match material {
+            Material::Metal(_) => Vec3::reflect(&-wo, &rec.normal),
+            Material::Medium(_) => -wo,
+            _ => rec.normal,
+        };
+        let across = axis.perpendiculars().0;
+        let cos_cone = 0.9;
+        let n = 100_000;
+        let (mut total, mut inside) = (0.0, 0.0);
+        for i in 0..n {
+            let cos_theta = -1.0 + (i as f64 + 0.5) * 2.0 / n as f64;
+            let wi = cos_theta * axis + (1.0 - cos_theta * cos_theta).sqrt() * across;
+            let ring = material.pdf(wi, wo, &rec) * 2.0 * PI * 2.0 / n as f64;
+            total += ring;
+            if cos_theta > cos_cone {
+                inside += ring;
+            }
+        }
+        assert!((total - 1.0).abs() < 0.01, "{} {}", material.kind(), total);
+
+        // They carry what `eval` over `pdf` does.
+        let samples = 20_000;
+        let mut hits = 0;
+        for _ in 0..samples {
+            let mut attenuation = Color::default();
+            let mut scattered = Ray::default();
+            let scatters = material.scatter(&r_in, &rec, &mut attenuation, &mut scattered);
+            let wi = scattered.direction().unit_vector();
+            hits += (wi.dot(&axis) > cos_cone) as usize;
+            let (eval, pdf) = (material.eval(wi, wo, &rec), material.pdf(wi, wo, &rec));
+            if !scatters {
+                assert_eq!(eval, Color::default());
+            } else if pdf < 100.0 {
+                // Right at the rim of the fuzz the density has a spike too
+                // sharp to check.
+                assert!((eval / pdf - attenuation).length() < 1e-6);
+            }
+        }
+        let fraction = hits as f64 / samples as f64;
+        assert!(
+            (fraction - inside).abs() < 0.02,
+            "{} {} {}",
+            material.kind(),
+            fraction,
+            inside
+        );
+    }
+
+    // Mirrors can only be sampled.
+    let mirror = Material::Metal(Metal::new(Color::new(1.0, 1.0, 1.0), 0.0));
+    let reflected = Vec3::reflect(&-wo, &rec.normal);
+    assert_eq!(mirror.pdf(reflected, wo, &rec), 0.0);
+    assert_eq!(mirror.eval(reflected, wo, &rec), Color::default());
+}
+
+#[test]
+fn test_sides() {
+    let front = HitRecord {
+        normal: Vec3::new(0.0, 1.0, 0.0),
+        front_face: true,
+        ..HitRecord::default()
+    };
+    let back = HitRecord {
+        front_face: false,
+        ..front.clone()
+    };
+    let r = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+    let light = |sides| {
+        Material::DiffuseLight(DiffuseLight {
+            sides,
+            ..DiffuseLight::new(Color::new(4.0, 4.0, 4.0))
+        })
+    };
+
+    let both = light(Sides::Both);
+    assert_eq!(both.emitted_at(&r, &back), Color::new(4.0, 4.0, 4.0));
+    assert!(!both.has_opacity() && !both.is_cut_out(&r, &back));
+
+    let dark = light(Sides::Front);
+    assert_eq!(dark.emitted_at(&r, &front), Color::new(4.0, 4.0, 4.0));
+    assert_eq!(dark.emitted_at(&r, &back), Color::default());
+    assert!(!dark.is_cut_out(&r, &back));
+
+    let invisible = light(Sides::FrontInvisibleBack);
+    assert!(invisible.has_opacity());
+    assert!(!invisible.is_cut_out(&r, &front) && invisible.is_cut_out(&r, &back));
+
+    let parsed: DiffuseLight =
+        serde_json::from_str(r#"{"emit": [1, 1, 1], "sides": "front_invisible_back"}"#).unwrap();
+    assert_eq!(parsed.sides, Sides::FrontInvisibleBack);
+}
+
+#[test]
+fn test_color_input() {
+    let albedo = |json: &str| {
+        let json = format!(r#"{{"Lambertian": {{"albedo": {}}}}}"#, json);
+        serde_json::from_str::<Material>(&json).map(|m| match m {
+            Material::Lambertian(l) => l.albedo,
+            _ => unreachable!(),
+        })
+    };
+    assert_eq!(
+        albedo("[0.5, 0.25, 2]").unwrap(),
+        Color::new(0.5, 0.25, 2.0)
+    );
+    let orange = Color::new(1.0, gamma_to_linear(136.0 / 255.0), 0.0);
+    assert_eq!(albedo(r##""#ff8800""##).unwrap(), orange);
+    assert_eq!(albedo(r##""#F80""##).unwrap(), orange);
+    assert_eq!(albedo(r#"{"rgb255": [255, 136, 0]}"#).unwrap(), orange);
+    assert_eq!(
+        albedo(r##"{"color": "#ff8800", "intensity": 4}"##).unwrap(),
+        orange * 4.0
+    );
+    assert_eq!(
+        albedo(r#"{"color": [1, 1, 0.5], "intensity": 2}"#).unwrap(),
+        Color::new(2.0, 2.0, 1.0)
+    );
+    for wrong in [
+        r#""ff8800""#,
+        r##""#ff88""##,
+        r##""#gg8800""##,
+        r##""#ffé88""##,
+        r##""#+f+8+0""##,
+        r#"{"rgb255": [256, 0, 0]}"#,
+    ] {
+        assert!(albedo(wrong).is_err(), "{}", wrong);
+    }
+
+    // Written back as plain linear values.
+    let light = Material::DiffuseLight(DiffuseLight::new(orange * 4.0));
+    let json = serde_json::to_string(&light).unwrap();
+    assert!(json.contains(r#""emit":[4.0,"#), "{}", json);
+    // At full precision, so encoded colors load back unchanged.
+    match serde_json::from_str::<Material>(&json).unwrap() {
+        Material::DiffuseLight(read) => assert_eq!(read.emit, orange * 4.0),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_temperature() {
+    let r = Ray::new(Vec3::new(0.0, 1.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+    let mut light = DiffuseLight::with_temperature(Color::new(2.0, 2.0, 2.0), 3200.0);
+    let warm = light.emitted(&r);
+    assert_eq!(warm, Color::new(2.0, 2.0, 2.0) * blackbody(3200.0));
+    assert!(warm.x() > warm.z());
+    assert_eq!(light.emitted(&r), warm);
+    // Changed after the first hit.
+    light.temperature = Some(6500.0);
+    assert_eq!(
+        light.emitted(&r),
+        Color::new(2.0, 2.0, 2.0) * blackbody(6500.0)
+    );
+}
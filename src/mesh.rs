@@ -0,0 +1,557 @@
+// Triangle meshes loaded from Wavefront OBJ files. Only positions, texture
+// coordinates, normals and faces are read; polygons are split into fans.
+// In a scene a mesh is a `Mesh` shape, its triangles kept in a BVH.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::displacement::Displacement;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::subdivision::catmull_clark;
+use crate::vec3::{Point3D, Vec3};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vertex {
+    pub position: usize,
+    pub uv: Option<usize>,
+    pub normal: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Mesh {
+    pub positions: Vec<Point3D>,
+    pub uvs: Vec<(f64, f64)>,
+    pub normals: Vec<Vec3>,
+    pub triangles: Vec<[Vertex; 3]>,
+    // The polygons the triangles were split from, as written in the file;
+    // subdivision surfaces need them whole.
+    pub faces: Vec<Vec<Vertex>>,
+}
+
+fn invalid_data(line: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line, msg),
+    )
+}
+
+fn numbers<const N: usize>(line: usize, fields: &[&str]) -> io::Result<[f64; N]> {
+    let mut values = [0.0; N];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = fields
+            .get(i)
+            .and_then(|f| f.parse().ok())
+            .ok_or_else(|| invalid_data(line, "expected a number"))?;
+    }
+    Ok(values)
+}
+
+// OBJ indices start at 1; negative ones count back from the last element.
+fn index(line: usize, field: &str, len: usize) -> io::Result<usize> {
+    let i: i64 = field
+        .parse()
+        .map_err(|_| invalid_data(line, "invalid index"))?;
+    let i = if i < 0 { len as i64 + i } else { i - 1 };
+    if i < 0 || i >= len as i64 {
+        return Err(invalid_data(line, "index out of range"));
+    }
+    Ok(i as usize)
+}
+
+impl Mesh {
+    pub fn load_obj(path: &Path) -> io::Result<Mesh> {
+        Mesh::parse_obj(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse_obj(src: &str) -> io::Result<Mesh> {
+        let mut mesh = Mesh::default();
+
+        for (n, line) in src.lines().enumerate() {
+            let n = n + 1;
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match fields.first() {
+                Some(&"v") => {
+                    let [x, y, z] = numbers(n, &fields[1..])?;
+                    mesh.positions.push(Point3D::new(x, y, z));
+                }
+                Some(&"vt") => {
+                    let [u, v] = numbers(n, &fields[1..])?;
+                    mesh.uvs.push((u, v));
+                }
+                Some(&"vn") => {
+                    let [x, y, z] = numbers(n, &fields[1..])?;
+                    mesh.normals.push(Vec3::new(x, y, z).unit_vector());
+                }
+                Some(&"f") => {
+                    let mut vertices = Vec::new();
+                    for field in &fields[1..] {
+                        let mut parts = field.split('/');
+                        let position = index(n, parts.next().unwrap(), mesh.positions.len())?;
+                        let uv = match parts.next() {
+                            Some(s) if !s.is_empty() => Some(index(n, s, mesh.uvs.len())?),
+                            _ => None,
+                        };
+                        let normal = match parts.next() {
+                            Some(s) if !s.is_empty() => Some(index(n, s, mesh.normals.len())?),
+                            _ => None,
+                        };
+                        vertices.push(Vertex {
+                            position,
+                            uv,
+                            normal,
+                        });
+                    }
+                    if vertices.len() < 3 {
+                        return Err(invalid_data(n, "a face needs at least 3 vertices"));
+                    }
+                    mesh.add_face(vertices);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(mesh)
+    }
+
+    // Keeps the polygon and adds it to the triangles as a fan.
+    pub fn add_face(&mut self, vertices: Vec<Vertex>) {
+        for i in 1..vertices.len() - 1 {
+            self.triangles
+                .push([vertices[0], vertices[i], vertices[i + 1]]);
+        }
+        self.faces.push(vertices);
+    }
+
+    // Replaces the normals with ones averaged over the triangles around each
+    // position, weighted by their areas.
+    pub fn smooth_normals(&mut self) {
+        let mut normals = vec![Vec3::default(); self.positions.len()];
+        for (i, triangle) in self.triangles.iter().enumerate() {
+            let [a, b, c] = self.corners(i);
+            let area_normal = (b - a).cross(&(c - a));
+            for vertex in triangle {
+                normals[vertex.position] += area_normal;
+            }
+        }
+        self.normals = normals
+            .into_iter()
+            .map(|n| n.or_if_near_zero(Vec3::new(0.0, 0.0, 1.0)).unit_vector())
+            .collect();
+        for vertex in self.triangles.iter_mut().flatten() {
+            vertex.normal = Some(vertex.position);
+        }
+        for vertex in self.faces.iter_mut().flatten() {
+            vertex.normal = Some(vertex.position);
+        }
+    }
+
+    pub fn position(&self, triangle: usize, barycentric: [f64; 3]) -> Point3D {
+        let [a, b, c] = self.triangles[triangle];
+        barycentric[0] * self.positions[a.position]
+            + barycentric[1] * self.positions[b.position]
+            + barycentric[2] * self.positions[c.position]
+    }
+
+    // The interpolated vertex normal, or the face normal when the triangle has
+    // no normals.
+    pub fn normal(&self, triangle: usize, barycentric: [f64; 3]) -> Vec3 {
+        let [a, b, c] = self.triangles[triangle];
+        match (a.normal, b.normal, c.normal) {
+            (Some(na), Some(nb), Some(nc)) => (barycentric[0] * self.normals[na]
+                + barycentric[1] * self.normals[nb]
+                + barycentric[2] * self.normals[nc])
+                .unit_vector(),
+            _ => {
+                let (pa, pb, pc) = (
+                    self.positions[a.position],
+                    self.positions[b.position],
+                    self.positions[c.position],
+                );
+                (pb - pa).cross(&(pc - pa)).unit_vector()
+            }
+        }
+    }
+
+    pub fn uv(&self, triangle: usize) -> Option<[(f64, f64); 3]> {
+        let [a, b, c] = self.triangles[triangle];
+        Some([self.uvs[a.uv?], self.uvs[b.uv?], self.uvs[c.uv?]])
+    }
+
+    pub fn corners(&self, triangle: usize) -> [Point3D; 3] {
+        self.triangles[triangle].map(|vertex| self.positions[vertex.position])
+    }
+
+    // From `p` to the closest point on the triangle's sides.
+    fn to_edge(&self, triangle: usize, p: Point3D) -> Vec3 {
+        let corners = self.corners(triangle);
+        (0..3)
+            .map(|i| {
+                let (a, b) = (corners[i], corners[(i + 1) % 3]);
+                let side = b - a;
+                let s = ((p - a).dot(&side) / side.length_squared()).clamp(0.0, 1.0);
+                a + s * side - p
+            })
+            .filter(|to_edge| to_edge.x().is_finite())
+            .min_by(|x, y| x.length_squared().total_cmp(&y.length_squared()))
+            .unwrap_or_default()
+    }
+
+    pub fn area(&self) -> f64 {
+        (0..self.triangles.len())
+            .map(|i| {
+                let [a, b, c] = self.corners(i);
+                0.5 * (b - a).cross(&(c - a)).length()
+            })
+            .sum()
+    }
+
+    // Padded, so that triangles lying in an axis plane can still be hit.
+    pub fn bounding_box(&self, triangle: usize) -> Aabb {
+        let [a, b, c] = self.corners(triangle);
+        Aabb::surrounding_box(&Aabb::from_points(a, b), &Aabb::from_points(c, c)).pad(1e-4)
+    }
+
+    // Möller-Trumbore intersection of `r` with one triangle: the distance and
+    // barycentric coordinates of the hit.
+    pub fn hit_triangle(
+        &self,
+        triangle: usize,
+        r: &Ray,
+        ray_t: &Interval,
+    ) -> Option<(f64, [f64; 3])> {
+        let [p0, p1, p2] = self.corners(triangle);
+        let edge1 = p1 - p0;
+        let edge2 = p2 - p0;
+
+        let pvec = r.direction().cross(&edge2);
+        let det = edge1.dot(&pvec);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let tvec = *r.origin() - p0;
+        let u = tvec.dot(&pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let qvec = tvec.cross(&edge1);
+        let v = r.direction().dot(&qvec) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(&qvec) * inv_det;
+        ray_t.surrounds(t).then_some((t, [1.0 - u - v, u, v]))
+    }
+
+    // Fills in where on `triangle` a ray hit, except for the material.
+    fn set_hit(
+        &self,
+        triangle: usize,
+        r: &Ray,
+        t: f64,
+        barycentric: [f64; 3],
+        rec: &mut HitRecord,
+    ) {
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.set_face_normal(r, self.normal(triangle, barycentric));
+        rec.edge = Some(self.to_edge(triangle, rec.p));
+        let Some(uvs) = self.uv(triangle) else {
+            (rec.u, rec.v, rec.dpdu, rec.dpdv) = (0.0, 0.0, Vec3::default(), Vec3::default());
+            return;
+        };
+        rec.u = (0..3).map(|i| barycentric[i] * uvs[i].0).sum();
+        rec.v = (0..3).map(|i| barycentric[i] * uvs[i].1).sum();
+        // Solves p = p2 + (u - u2) dpdu + (v - v2) dpdv at the other corners.
+        let [p0, p1, p2] = self.corners(triangle);
+        let (du02, dv02) = (uvs[0].0 - uvs[2].0, uvs[0].1 - uvs[2].1);
+        let (du12, dv12) = (uvs[1].0 - uvs[2].0, uvs[1].1 - uvs[2].1);
+        let (dp02, dp12) = (p0 - p2, p1 - p2);
+        let det = du02 * dv12 - dv02 * du12;
+        if det.abs() < 1e-12 {
+            (rec.dpdu, rec.dpdv) = (Vec3::default(), Vec3::default());
+            return;
+        }
+        rec.dpdu = (dv12 * dp02 - dv02 * dp12) / det;
+        rec.dpdv = (du02 * dp12 - du12 * dp02) / det;
+    }
+}
+
+// A mesh ready to be hit.
+#[derive(Debug)]
+pub struct TriangleMesh {
+    pub mesh: Mesh,
+    bvh: Bvh,
+}
+
+impl TriangleMesh {
+    pub fn new(mesh: Mesh) -> TriangleMesh {
+        let boxes: Vec<Aabb> = (0..mesh.triangles.len())
+            .map(|i| mesh.bounding_box(i))
+            .collect();
+        TriangleMesh {
+            bvh: Bvh::new(&boxes),
+            mesh,
+        }
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+        let mut closest = None;
+        self.bvh.hit(r, ray_t, |i, interval| {
+            let (t, barycentric) = self.mesh.hit_triangle(i, r, interval)?;
+            closest = Some((i, t, barycentric));
+            Some(t)
+        });
+        let Some((i, t, barycentric)) = closest else {
+            return false;
+        };
+        self.mesh.set_hit(i, r, t, barycentric, rec);
+        true
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: &Interval) -> bool {
+        self.bvh
+            .any(r, ray_t, |i| self.mesh.hit_triangle(i, r, ray_t).is_some())
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bvh.bounds()
+    }
+}
+
+fn is_zero(levels: &usize) -> bool {
+    *levels == 0
+}
+
+// A coarser version of a mesh, used from `distance` to the camera on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Lod {
+    pub file: String,
+    pub distance: f64,
+}
+
+// A mesh in a scene, read from an OBJ file relative to the scene file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MeshShape {
+    pub file: String,
+    pub material: Material,
+    // Times the mesh is smoothed as a Catmull-Clark subdivision surface,
+    // each making four times as many faces.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub subdivision_levels: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub displacement: Option<Displacement>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub lods: Vec<Lod>,
+    // The level of detail picked for the camera, the full mesh until then.
+    #[serde(skip)]
+    pub triangles: Option<Arc<TriangleMesh>>,
+    // The full mesh and then `lods`, in their order.
+    #[serde(skip)]
+    levels: Vec<Arc<TriangleMesh>>,
+}
+
+impl MeshShape {
+    pub fn new(file: &str, material: Material) -> MeshShape {
+        MeshShape {
+            file: file.to_string(),
+            material,
+            subdivision_levels: 0,
+            displacement: None,
+            lods: Vec::new(),
+            triangles: None,
+            levels: Vec::new(),
+        }
+    }
+
+    // A mesh built in memory, by an importer, instead of read from a file.
+    pub fn from_mesh(mesh: Mesh, material: Material) -> MeshShape {
+        let triangles = Arc::new(TriangleMesh::new(mesh));
+        MeshShape {
+            triangles: Some(triangles.clone()),
+            levels: vec![triangles],
+            ..MeshShape::new("", material)
+        }
+    }
+
+    // Reads the mesh, subdivides and displaces it as asked, reads its levels
+    // of detail as they are, and returns the paths of the files read.
+    pub fn load(&mut self, base_dir: &Path) -> io::Result<Vec<PathBuf>> {
+        if self.file.is_empty() && self.triangles.is_some() {
+            return Ok(Vec::new());
+        }
+        let path = base_dir.join(&self.file);
+        let with_path = |path: &Path, e: io::Error| {
+            io::Error::new(e.kind(), format!("{}: {}", path.display(), e))
+        };
+        let mut mesh = Mesh::load_obj(&path).map_err(|e| with_path(&path, e))?;
+        if self.subdivision_levels > 0 {
+            mesh = catmull_clark(&mesh, self.subdivision_levels);
+        }
+        let mut files = vec![path];
+        if let Some(displacement) = &self.displacement {
+            let map = base_dir.join(&displacement.file);
+            mesh = displacement
+                .apply(&mesh, &map)
+                .map_err(|e| with_path(&map, e))?;
+            files.push(map);
+        }
+        self.levels = vec![Arc::new(TriangleMesh::new(mesh))];
+        for lod in &self.lods {
+            let path = base_dir.join(&lod.file);
+            let mesh = Mesh::load_obj(&path).map_err(|e| with_path(&path, e))?;
+            self.levels.push(Arc::new(TriangleMesh::new(mesh)));
+            files.push(path);
+        }
+        self.triangles = Some(self.levels[0].clone());
+        Ok(files)
+    }
+
+    // Uses the coarsest level whose distance has been reached.
+    pub fn select_lod(&mut self, distance: f64) {
+        let level = self
+            .lods
+            .iter()
+            .enumerate()
+            .filter(|(_, lod)| distance >= lod.distance)
+            .max_by(|(_, a), (_, b)| a.distance.total_cmp(&b.distance))
+            .map_or(0, |(i, _)| i + 1);
+        if let Some(triangles) = self.levels.get(level) {
+            self.triangles = Some(triangles.clone());
+        }
+    }
+
+    // Zero until loaded.
+    pub fn area(&self) -> f64 {
+        self.triangles
+            .as_ref()
+            .map_or(0.0, |triangles| triangles.mesh.area())
+    }
+}
+
+impl Hittable for MeshShape {
+    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+        let Some(triangles) = &self.triangles else {
+            return false;
+        };
+        if !triangles.hit(r, ray_t, rec) {
+            return false;
+        }
+        rec.mat = self.material.clone();
+        true
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: &Interval) -> bool {
+        self.triangles
+            .as_ref()
+            .is_some_and(|triangles| triangles.occluded(r, ray_t))
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.triangles
+            .as_ref()
+            .map_or(Aabb::EMPTY, |triangles| triangles.bounding_box())
+    }
+}
+
+#[test]
+fn test_parse_obj() {
+    let mesh = Mesh::parse_obj(
+        "# quad\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 2/2 3/3 -1/-1\n",
+    )
+    .unwrap();
+    assert_eq!(mesh.positions.len(), 4);
+    assert_eq!(mesh.triangles.len(), 2);
+    assert_eq!(mesh.triangles[1][2].position, 3);
+    assert_eq!(mesh.uv(1).unwrap()[2], (0.0, 1.0));
+    assert_eq!(mesh.normal(0, [1.0, 0.0, 0.0]).z(), 1.0);
+
+    let down = Ray::new(Point3D::new(0.5, 0.5, 1.0), Vec3::new(0.0, 0.0, -1.0));
+    let mesh = TriangleMesh::new(mesh);
+    assert!(mesh.occluded(&down, &Interval::new(0.001, f64::INFINITY)));
+    assert!(!mesh.occluded(&down, &Interval::new(0.001, 0.5)));
+
+    assert!(Mesh::parse_obj("v 0 0 0\nf 1 2 3\n").is_err());
+}
+
+#[test]
+fn test_triangle_mesh() {
+    // A grid of quads two units wide, textured once across.
+    let mut src = String::new();
+    for y in 0..=4 {
+        for x in 0..=4 {
+            src += &format!(
+                "v {} {} 0\nvt {} {}\n",
+                x as f64 / 2.0,
+                y as f64 / 2.0,
+                x as f64 / 4.0,
+                y as f64 / 4.0
+            );
+        }
+    }
+    for y in 0..4 {
+        for x in 0..4 {
+            let i = y * 5 + x + 1;
+            src += &format!(
+                "f {0}/{0} {1}/{1} {2}/{2} {3}/{3}\n",
+                i,
+                i + 1,
+                i + 6,
+                i + 5
+            );
+        }
+    }
+    let mesh = TriangleMesh::new(Mesh::parse_obj(&src).unwrap());
+    assert_eq!(mesh.mesh.triangles.len(), 32);
+    assert!((mesh.mesh.area() - 4.0).abs() < 1e-12);
+
+    let r = Ray::new(Point3D::new(0.6, 1.4, 3.0), Vec3::new(0.0, 0.0, -1.0));
+    let mut rec = HitRecord::default();
+    assert!(mesh.hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec));
+    assert!((rec.t - 3.0).abs() < 1e-12);
+    assert!((rec.u - 0.3).abs() < 1e-12 && (rec.v - 0.7).abs() < 1e-12);
+    assert!((rec.dpdu - Vec3::new(2.0, 0.0, 0.0)).length() < 1e-12);
+    assert!((rec.dpdv - Vec3::new(0.0, 2.0, 0.0)).length() < 1e-12);
+    assert!(rec.front_face && rec.normal.z() == 1.0);
+    assert!(!mesh.hit(&r, &Interval::new(0.001, 2.0), &mut rec));
+    let beside = Ray::new(Point3D::new(2.5, 1.5, 3.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(!mesh.hit(&beside, &Interval::new(0.001, f64::INFINITY), &mut rec));
+}
+
+#[test]
+fn test_select_lod() {
+    let quad = |size: f64| {
+        let src = format!(
+            "v 0 0 0\nv {0} 0 0\nv {0} {0} 0\nv 0 {0} 0\nf 1 2 3 4\n",
+            size
+        );
+        Arc::new(TriangleMesh::new(Mesh::parse_obj(&src).unwrap()))
+    };
+    let mut shape = MeshShape::new("full.obj", Material::Named("x".to_string()));
+    shape.lods = vec![
+        Lod {
+            file: "far.obj".to_string(),
+            distance: 100.0,
+        },
+        Lod {
+            file: "middle.obj".to_string(),
+            distance: 10.0,
+        },
+    ];
+    shape.levels = vec![quad(1.0), quad(3.0), quad(2.0)];
+    for (distance, area) in [(5.0, 1.0), (10.0, 4.0), (50.0, 4.0), (200.0, 9.0)] {
+        shape.select_lod(distance);
+        assert_eq!(shape.area(), area, "at {}", distance);
+    }
+}
@@ -0,0 +1,127 @@
+// Scene files carry a schema `version`. Older files are upgraded step by step
+// while they are still untyped JSON values, so the typed structures only ever
+// have to understand the current schema.
+
+use std::io;
+
+use serde_json::{Map, Value};
+
+pub const CURRENT_VERSION: u32 = 2;
+
+type Migration = fn(&mut Map<String, Value>);
+
+// MIGRATIONS[i] upgrades a version i + 1 scene to version i + 2.
+const MIGRATIONS: &[Migration] = &[v1_to_v2];
+
+// Version 1 is the original format without a `version` field. Version 2 added
+// the optional `materials`, `include`, `generators` and `script` keys, so old
+// scenes are already valid and only get stamped.
+fn v1_to_v2(_scene: &mut Map<String, Value>) {}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+// Upgrades `scene` in place to the current version and returns the version it
+// was written in.
+pub fn migrate(scene: &mut Value) -> io::Result<u32> {
+    let scene = scene
+        .as_object_mut()
+        .ok_or_else(|| invalid_data("a scene file must contain an object".to_string()))?;
+
+    let version = match scene.get("version") {
+        None => 1,
+        Some(v) => v
+            .as_u64()
+            .and_then(|v| u32::try_from(v).ok())
+            .filter(|v| *v >= 1)
+            .ok_or_else(|| invalid_data(format!("invalid scene version {}", v)))?,
+    };
+    if version > CURRENT_VERSION {
+        return Err(invalid_data(format!(
+            "scene version {} is newer than the newest supported version {}, please update raytracer",
+            version, CURRENT_VERSION
+        )));
+    }
+
+    for migration in &MIGRATIONS[version as usize - 1..] {
+        migration(scene);
+    }
+    scene.insert("version".to_string(), Value::from(CURRENT_VERSION));
+
+    Ok(version)
+}
+
+// YAML enum tags (`!Sphere`) become single-key maps, which is how the same
+// enums look in JSON.
+#[cfg(feature = "yaml")]
+pub fn yaml_to_json(value: serde_yaml::Value) -> io::Result<Value> {
+    Ok(match value {
+        serde_yaml::Value::Null => Value::Null,
+        serde_yaml::Value::Bool(b) => Value::Bool(b),
+        serde_yaml::Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Value::from(i)
+            } else if let Some(u) = n.as_u64() {
+                Value::from(u)
+            } else {
+                Value::from(n.as_f64().unwrap_or(f64::NAN))
+            }
+        }
+        serde_yaml::Value::String(s) => Value::String(s),
+        serde_yaml::Value::Sequence(seq) => Value::Array(
+            seq.into_iter()
+                .map(yaml_to_json)
+                .collect::<io::Result<_>>()?,
+        ),
+        serde_yaml::Value::Mapping(mapping) => {
+            let mut map = Map::new();
+            for (key, value) in mapping {
+                let key = match key {
+                    serde_yaml::Value::String(s) => s,
+                    other => return Err(invalid_data(format!("unsupported key {:?}", other))),
+                };
+                map.insert(key, yaml_to_json(value)?);
+            }
+            Value::Object(map)
+        }
+        serde_yaml::Value::Tagged(tagged) => {
+            let mut map = Map::new();
+            let tag = tagged.tag.to_string();
+            map.insert(
+                tag.trim_start_matches('!').to_string(),
+                yaml_to_json(tagged.value)?,
+            );
+            Value::Object(map)
+        }
+    })
+}
+
+#[test]
+fn test_migrate_unversioned() {
+    let mut scene = serde_json::json!({"camera": {}, "object_list": {"objects": []}});
+    assert_eq!(migrate(&mut scene).unwrap(), 1);
+    assert_eq!(scene["version"], CURRENT_VERSION);
+}
+
+#[test]
+fn test_reject_future_version() {
+    let mut scene = serde_json::json!({"version": CURRENT_VERSION + 1});
+    let err = migrate(&mut scene).unwrap_err();
+    assert!(err.to_string().contains("newer"));
+    assert!(migrate(&mut serde_json::json!({"version": 0})).is_err());
+    // Not version 1 with the high bits cut off.
+    let err = migrate(&mut serde_json::json!({"version": (1u64 << 32) + 1})).unwrap_err();
+    assert!(err.to_string().contains("invalid scene version 4294967297"));
+}
+
+#[cfg(feature = "yaml")]
+#[test]
+fn test_yaml_to_json() {
+    let yaml: serde_yaml::Value =
+        serde_yaml::from_str("material: !Glass\n  refraction_index: 1.5\nn: 3\n").unwrap();
+    assert_eq!(
+        yaml_to_json(yaml).unwrap(),
+        serde_json::json!({"material": {"Glass": {"refraction_index": 1.5}}, "n": 3})
+    );
+}
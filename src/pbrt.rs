@@ -0,0 +1,802 @@
+// Importer for a subset of the PBRT v3/v4 scene format. Supported are the
+// camera, film, sampler and integrator settings, transforms, spheres, the
+// common materials, diffuse area lights, included files and object
+// instances. Everything else is skipped with a warning, so benchmark scenes
+// still load with whatever we can render.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::config::Config;
+use crate::hittable::{Object, ObjectList};
+use crate::instance::Instance;
+use crate::material::{DiffuseLight, Glass, Lambertian, Material, Metal};
+use crate::quat::Rotation;
+use crate::sphere::Sphere;
+use crate::transform::Mat4;
+use crate::vec3::{Point3D, Vec3};
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+// PBRT's LookAt produces the camera-from-world matrix of a left-handed frame.
+fn look_at(eye: Point3D, look: Point3D, up: Vec3) -> Mat4 {
+    let dir = (look - eye).unit_vector();
+    let right = up.unit_vector().cross(&dir).unit_vector();
+    let new_up = dir.cross(&right);
+    let world_from_camera = Mat4([
+        [right.x(), new_up.x(), dir.x(), eye.x()],
+        [right.y(), new_up.y(), dir.y(), eye.y()],
+        [right.z(), new_up.z(), dir.z(), eye.z()],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+    world_from_camera.inverse().unwrap_or(Mat4::IDENTITY)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Open,
+    Close,
+}
+
+fn tokenize(src: &str) -> io::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = src.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '#' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '[' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ']' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => s.push(c),
+                        None => return Err(invalid("unterminated string")),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(c) =
+                    chars.next_if(|&c| !c.is_whitespace() && !matches!(c, '[' | ']' | '"' | '#'))
+                {
+                    word.push(c);
+                }
+                match word.parse::<f64>() {
+                    Ok(n) => tokens.push(Token::Num(n)),
+                    Err(_) => tokens.push(Token::Ident(word)),
+                }
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Num(f64),
+    Str(String),
+}
+
+#[derive(Debug, Clone)]
+struct Param {
+    ty: String,
+    name: String,
+    values: Vec<Value>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ParamList(Vec<Param>);
+
+impl ParamList {
+    fn find(&self, name: &str) -> Option<&Param> {
+        self.0.iter().find(|p| p.name == name)
+    }
+
+    fn nums(&self, name: &str) -> Option<Vec<f64>> {
+        self.find(name).map(|p| {
+            p.values
+                .iter()
+                .filter_map(|v| match v {
+                    Value::Num(n) => Some(*n),
+                    _ => None,
+                })
+                .collect()
+        })
+    }
+
+    fn float(&self, name: &str) -> Option<f64> {
+        self.nums(name).and_then(|v| v.first().copied())
+    }
+
+    fn string(&self, name: &str) -> Option<String> {
+        self.find(name).and_then(|p| match p.values.first() {
+            Some(Value::Str(s)) => Some(s.clone()),
+            _ => None,
+        })
+    }
+
+    // Only constant RGB and grayscale spectra are understood; textures and
+    // named spectra fall back to the caller's default.
+    fn color(&self, names: &[&str]) -> Option<Color> {
+        names.iter().find_map(|name| {
+            let p = self.find(name)?;
+            let v = self.nums(name)?;
+            match (p.ty.as_str(), v.len()) {
+                ("rgb" | "color", 3) => Some(Color::new(v[0], v[1], v[2])),
+                ("float" | "spectrum", 1) => Some(Color::new(v[0], v[0], v[0])),
+                _ => None,
+            }
+        })
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn num(&mut self) -> io::Result<f64> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(n),
+            other => Err(invalid(format!("expected number, found {:?}", other))),
+        }
+    }
+
+    fn nums<const N: usize>(&mut self) -> io::Result<[f64; N]> {
+        let mut out = [0.0; N];
+        let bracketed = self.peek() == Some(&Token::Open);
+        if bracketed {
+            self.next();
+        }
+        for value in out.iter_mut() {
+            *value = self.num()?;
+        }
+        if bracketed && self.next() != Some(Token::Close) {
+            return Err(invalid("expected ']'"));
+        }
+        Ok(out)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        match self.next() {
+            Some(Token::Str(s)) => Ok(s),
+            other => Err(invalid(format!("expected string, found {:?}", other))),
+        }
+    }
+
+    fn params(&mut self) -> io::Result<ParamList> {
+        let mut params = Vec::new();
+        while let Some(Token::Str(decl)) = self.peek().cloned() {
+            self.next();
+            let mut parts = decl.split_whitespace();
+            let (ty, name) = match (parts.next(), parts.next()) {
+                (Some(ty), Some(name)) => (ty.to_string(), name.to_string()),
+                _ => return Err(invalid(format!("bad parameter declaration \"{}\"", decl))),
+            };
+
+            let mut values = Vec::new();
+            let mut push = |token: Token| match token {
+                Token::Num(n) => values.push(Value::Num(n)),
+                Token::Str(s) | Token::Ident(s) => values.push(Value::Str(s)),
+                _ => {}
+            };
+            match self.next() {
+                Some(Token::Open) => loop {
+                    match self.next() {
+                        Some(Token::Close) => break,
+                        Some(token) => push(token),
+                        None => return Err(invalid("unterminated parameter list")),
+                    }
+                },
+                Some(token) => push(token),
+                None => return Err(invalid("missing parameter value")),
+            }
+            params.push(Param { ty, name, values });
+        }
+        Ok(ParamList(params))
+    }
+}
+
+#[derive(Clone)]
+struct GraphicsState {
+    ctm: Mat4,
+    material: Material,
+    area_light: Option<Color>,
+}
+
+struct SceneBuilder {
+    base_dir: PathBuf,
+    state: GraphicsState,
+    stack: Vec<GraphicsState>,
+    named_materials: HashMap<String, Material>,
+    named_coordinate_systems: HashMap<String, Mat4>,
+    camera_from_world: Option<Mat4>,
+    camera_params: ParamList,
+    width: usize,
+    height: usize,
+    samples_per_pixel: usize,
+    max_depth: usize,
+    objects: ObjectList,
+    // The object between ObjectBegin and ObjectEnd, and the ones finished.
+    defining: Option<(String, Vec<Object>)>,
+    prototypes: HashMap<String, Vec<Object>>,
+    // The files being read, innermost last, to catch includes in a cycle.
+    including: Vec<PathBuf>,
+    skipped: HashMap<String, usize>,
+}
+
+fn default_material() -> Material {
+    Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+}
+
+impl SceneBuilder {
+    fn new(base_dir: PathBuf) -> Self {
+        SceneBuilder {
+            base_dir,
+            state: GraphicsState {
+                ctm: Mat4::IDENTITY,
+                material: default_material(),
+                area_light: None,
+            },
+            stack: Vec::new(),
+            named_materials: HashMap::new(),
+            named_coordinate_systems: HashMap::new(),
+            camera_from_world: None,
+            camera_params: ParamList::default(),
+            width: 640,
+            height: 480,
+            samples_per_pixel: 16,
+            max_depth: 5,
+            objects: ObjectList::new(),
+            defining: None,
+            prototypes: HashMap::new(),
+            including: Vec::new(),
+            skipped: HashMap::new(),
+        }
+    }
+
+    fn skip(&mut self, what: String) {
+        *self.skipped.entry(what).or_insert(0) += 1;
+    }
+
+    fn apply(&mut self, m: Mat4) {
+        self.state.ctm = self.state.ctm * m;
+    }
+
+    fn parse(&mut self, src: &str) -> io::Result<()> {
+        let mut p = Parser {
+            tokens: tokenize(src)?,
+            pos: 0,
+        };
+
+        while let Some(token) = p.next() {
+            let directive = match token {
+                Token::Ident(name) => name,
+                other => return Err(invalid(format!("expected directive, found {:?}", other))),
+            };
+
+            match directive.as_str() {
+                "Identity" => self.state.ctm = Mat4::IDENTITY,
+                "Translate" => {
+                    let [x, y, z] = p.nums()?;
+                    self.apply(Mat4::translate(Vec3::new(x, y, z)));
+                }
+                "Scale" => {
+                    let [x, y, z] = p.nums()?;
+                    self.apply(Mat4::scale(Vec3::new(x, y, z)));
+                }
+                "Rotate" => {
+                    let [angle, x, y, z] = p.nums()?;
+                    self.apply(Mat4::rotate(angle, Vec3::new(x, y, z)));
+                }
+                "LookAt" => {
+                    let [ex, ey, ez, lx, ly, lz, ux, uy, uz] = p.nums()?;
+                    self.apply(look_at(
+                        Point3D::new(ex, ey, ez),
+                        Point3D::new(lx, ly, lz),
+                        Vec3::new(ux, uy, uz),
+                    ));
+                }
+                "Transform" | "ConcatTransform" => {
+                    // PBRT matrices are given in column-major order.
+                    let v: [f64; 16] = p.nums()?;
+                    let mut m = Mat4::IDENTITY;
+                    for (i, value) in v.iter().enumerate() {
+                        m.0[i % 4][i / 4] = *value;
+                    }
+                    if directive == "Transform" {
+                        self.state.ctm = m;
+                    } else {
+                        self.apply(m);
+                    }
+                }
+                "CoordinateSystem" => {
+                    let name = p.string()?;
+                    self.named_coordinate_systems.insert(name, self.state.ctm);
+                }
+                "CoordSysTransform" => {
+                    let name = p.string()?;
+                    match self.named_coordinate_systems.get(&name) {
+                        Some(m) => self.state.ctm = *m,
+                        None => tracing::warn!(name, "unknown coordinate system"),
+                    }
+                }
+                "Camera" => {
+                    let ty = p.string()?;
+                    self.camera_params = p.params()?;
+                    if ty != "perspective" {
+                        self.skip(format!("camera \"{}\"", ty));
+                    }
+                    self.camera_from_world = Some(self.state.ctm);
+                    self.named_coordinate_systems.insert(
+                        "camera".to_string(),
+                        self.state.ctm.inverse().unwrap_or(Mat4::IDENTITY),
+                    );
+                }
+                "Film" => {
+                    p.string()?;
+                    let params = p.params()?;
+                    if let Some(x) = params.float("xresolution") {
+                        self.width = x as usize;
+                    }
+                    if let Some(y) = params.float("yresolution") {
+                        self.height = y as usize;
+                    }
+                }
+                "Sampler" => {
+                    p.string()?;
+                    if let Some(n) = p.params()?.float("pixelsamples") {
+                        self.samples_per_pixel = n as usize;
+                    }
+                }
+                "Integrator" => {
+                    p.string()?;
+                    if let Some(n) = p.params()?.float("maxdepth") {
+                        self.max_depth = n as usize;
+                    }
+                }
+                "WorldBegin" => {
+                    self.state.ctm = Mat4::IDENTITY;
+                    self.named_coordinate_systems
+                        .insert("world".to_string(), Mat4::IDENTITY);
+                }
+                "WorldEnd" => {}
+                "AttributeBegin" | "TransformBegin" => self.stack.push(self.state.clone()),
+                "AttributeEnd" => {
+                    self.state = self
+                        .stack
+                        .pop()
+                        .ok_or_else(|| invalid("unmatched AttributeEnd"))?;
+                }
+                "TransformEnd" => {
+                    let saved = self
+                        .stack
+                        .pop()
+                        .ok_or_else(|| invalid("unmatched TransformEnd"))?;
+                    self.state.ctm = saved.ctm;
+                }
+                "Material" => {
+                    let ty = p.string()?;
+                    let params = p.params()?;
+                    self.state.material = self.material(&ty, &params);
+                }
+                "MakeNamedMaterial" => {
+                    let name = p.string()?;
+                    let params = p.params()?;
+                    let ty = params.string("type").unwrap_or_default();
+                    let material = self.material(&ty, &params);
+                    self.named_materials.insert(name, material);
+                }
+                "NamedMaterial" => {
+                    let name = p.string()?;
+                    match self.named_materials.get(&name) {
+                        Some(m) => self.state.material = m.clone(),
+                        None => tracing::warn!(name, "unknown named material"),
+                    }
+                }
+                "AreaLightSource" => {
+                    let ty = p.string()?;
+                    let params = p.params()?;
+                    let scale = params.float("scale").unwrap_or(1.0);
+                    match params.color(&["L"]) {
+                        Some(l) if ty == "diffuse" => self.state.area_light = Some(l * scale),
+                        _ => self.skip(format!("area light \"{}\"", ty)),
+                    }
+                }
+                "LightSource" => {
+                    let ty = p.string()?;
+                    p.params()?;
+                    self.skip(format!("light \"{}\"", ty));
+                }
+                "Shape" => {
+                    let ty = p.string()?;
+                    let params = p.params()?;
+                    self.shape(&ty, &params);
+                }
+                "Include" | "Import" => {
+                    let file = p.string()?;
+                    let path = fs::canonicalize(self.base_dir.join(file))?;
+                    if self.including.contains(&path) {
+                        return Err(invalid(format!("{} includes itself", path.display())));
+                    }
+                    let src = fs::read_to_string(&path)?;
+                    self.including.push(path);
+                    self.parse(&src)?;
+                    self.including.pop();
+                }
+                // Like AttributeBegin, and the shapes up to ObjectEnd make
+                // up the object instead of going into the scene.
+                "ObjectBegin" => {
+                    let name = p.string()?;
+                    if let Some((outer, _)) = &self.defining {
+                        return Err(invalid(format!(
+                            "object \"{}\" begins inside \"{}\"",
+                            name, outer
+                        )));
+                    }
+                    self.stack.push(self.state.clone());
+                    self.defining = Some((name, Vec::new()));
+                }
+                "ObjectEnd" => {
+                    let (name, objects) = self
+                        .defining
+                        .take()
+                        .ok_or_else(|| invalid("unmatched ObjectEnd"))?;
+                    self.prototypes.insert(name, objects);
+                    self.state = self
+                        .stack
+                        .pop()
+                        .ok_or_else(|| invalid("unmatched ObjectEnd"))?;
+                }
+                "ObjectInstance" => {
+                    let name = p.string()?;
+                    self.instance(&name)?;
+                }
+                "ReverseOrientation" => {}
+                "Option" | "ColorSpace" | "PixelFilter" | "Accelerator" | "Texture"
+                | "MakeNamedMedium" | "MediumInterface" | "Attribute" | "CameraMedium" => {
+                    while !matches!(p.peek(), Some(Token::Ident(_)) | None) {
+                        p.next();
+                    }
+                    self.skip(format!("directive {}", directive));
+                }
+                other => return Err(invalid(format!("unknown directive {}", other))),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn material(&mut self, ty: &str, params: &ParamList) -> Material {
+        match ty {
+            "matte" | "diffuse" => {
+                let albedo = params
+                    .color(&["Kd", "reflectance"])
+                    .unwrap_or(Color::new(0.5, 0.5, 0.5));
+                Material::Lambertian(Lambertian::new(albedo))
+            }
+            "metal" | "conductor" | "mirror" => {
+                let albedo = params
+                    .color(&["reflectance", "Kr"])
+                    .unwrap_or(Color::new(0.9, 0.9, 0.9));
+                let fuzz = params.float("roughness").unwrap_or(0.0);
+                Material::Metal(Metal::new(albedo, fuzz))
+            }
+            "glass" | "dielectric" | "thindielectric" => {
+                let eta = params.float("eta").or(params.float("index")).unwrap_or(1.5);
+                Material::Glass(Glass::new(eta))
+            }
+            "plastic" | "uber" | "substrate" | "coateddiffuse" => {
+                self.skip(format!("material \"{}\" (approximated as diffuse)", ty));
+                let albedo = params
+                    .color(&["Kd", "reflectance"])
+                    .unwrap_or(Color::new(0.25, 0.25, 0.25));
+                Material::Lambertian(Lambertian::new(albedo))
+            }
+            _ => {
+                self.skip(format!("material \"{}\"", ty));
+                default_material()
+            }
+        }
+    }
+
+    fn shape(&mut self, ty: &str, params: &ParamList) {
+        if ty != "sphere" {
+            self.skip(format!("shape \"{}\"", ty));
+            return;
+        }
+
+        let radius = params.float("radius").unwrap_or(1.0);
+        let center = self.state.ctm.transform_point(Point3D::default());
+        let radius = radius
+            * self
+                .state
+                .ctm
+                .transform_vector(Vec3::new(1.0, 0.0, 0.0))
+                .length();
+        let material = match self.state.area_light {
+            Some(emit) => Material::DiffuseLight(DiffuseLight::new(emit)),
+            None => self.state.material.clone(),
+        };
+
+        let object = Object::from(Sphere::new(mirror(center), radius, material));
+        match &mut self.defining {
+            Some((_, objects)) => objects.push(object),
+            None => self.objects.add(object),
+        }
+    }
+
+    // The shapes of object `name`, placed by the current transform on top
+    // of the ones they were defined with.
+    fn instance(&mut self, name: &str) -> io::Result<()> {
+        let prototype = self
+            .prototypes
+            .get(name)
+            .ok_or_else(|| invalid(format!("unknown object \"{}\"", name)))?;
+        if self.defining.is_some() {
+            return Err(invalid(format!(
+                "object \"{}\" instanced inside an object",
+                name
+            )));
+        }
+        // The same transform in the mirrored scene.
+        let mirrored = Mat4::scale(Vec3::new(-1.0, 1.0, 1.0));
+        let (translate, rotate, scale) = (mirrored * self.state.ctm * mirrored)
+            .to_trs()
+            .ok_or_else(|| invalid(format!("object \"{}\" instanced with a shear", name)))?;
+        for object in prototype {
+            let instance = Instance::new(
+                object.shape.clone(),
+                translate,
+                Some(Rotation::Quat(rotate)),
+                scale,
+            );
+            self.objects.add(Object::from(instance));
+        }
+        Ok(())
+    }
+
+    fn camera(&self) -> Camera {
+        let camera_from_world = self.camera_from_world.unwrap_or(Mat4::IDENTITY);
+        let world_from_camera = camera_from_world.inverse().unwrap_or(Mat4::IDENTITY);
+        let lookfrom = world_from_camera.transform_point(Point3D::default());
+        let lookat = world_from_camera.transform_point(Point3D::new(0.0, 0.0, 1.0));
+        let vup = world_from_camera.transform_vector(Vec3::new(0.0, 1.0, 0.0));
+
+        // PBRT's fov spans the shorter image axis.
+        let fov = self.camera_params.float("fov").unwrap_or(90.0);
+        let vfov = if self.width >= self.height {
+            fov
+        } else {
+            let half = (fov.to_radians() / 2.0).tan() * self.height as f64 / self.width as f64;
+            2.0 * half.atan().to_degrees()
+        };
+
+        let lens_radius = self.camera_params.float("lensradius").unwrap_or(0.0);
+        let focus_dist = self
+            .camera_params
+            .float("focaldistance")
+            .unwrap_or((lookat - lookfrom).length());
+        let defocus_angle = 2.0 * (lens_radius / focus_dist).atan().to_degrees();
+
+        Camera::new(
+            self.height,
+            self.width,
+            self.samples_per_pixel,
+            self.max_depth,
+            vfov,
+            mirror(lookfrom),
+            mirror(lookat),
+            mirror(vup),
+            defocus_angle,
+            focus_dist,
+        )
+    }
+}
+
+// PBRT uses a left-handed coordinate system. Mirroring the whole scene along
+// the x axis keeps the rendered image identical to PBRT's.
+fn mirror(v: Vec3) -> Vec3 {
+    Vec3::new(-v.x(), v.y(), v.z())
+}
+
+pub fn parse(src: &str, base_dir: &Path) -> io::Result<Config> {
+    parse_file(src, base_dir, None)
+}
+
+fn parse_file(src: &str, base_dir: &Path, path: Option<&Path>) -> io::Result<Config> {
+    let mut builder = SceneBuilder::new(base_dir.to_path_buf());
+    if let Some(path) = path {
+        builder.including.push(fs::canonicalize(path)?);
+    }
+    builder.parse(src)?;
+    if let Some((name, _)) = &builder.defining {
+        return Err(invalid(format!("object \"{}\" has no ObjectEnd", name)));
+    }
+
+    let mut skipped: Vec<_> = builder.skipped.iter().collect();
+    skipped.sort();
+    for (what, count) in skipped {
+        tracing::warn!(what, count, "skipped unsupported pbrt feature");
+    }
+
+    Ok(Config {
+        version: crate::migration::CURRENT_VERSION,
+        camera: builder.camera(),
+        cameras: Default::default(),
+        object_list: builder.objects,
+        materials: Default::default(),
+        generators: Vec::new(),
+        post: Vec::new(),
+        texture_cache: None,
+        layers: Default::default(),
+    })
+}
+
+pub fn load(path: &Path) -> io::Result<Config> {
+    let src = fs::read_to_string(path)?;
+    parse_file(&src, path.parent().unwrap_or(Path::new(".")), Some(path))
+}
+
+#[test]
+fn test_tokenize() {
+    let tokens = tokenize("Shape \"sphere\" \"float radius\" [ 2.5 ] # comment\n").unwrap();
+    assert_eq!(
+        tokens,
+        vec![
+            Token::Ident("Shape".to_string()),
+            Token::Str("sphere".to_string()),
+            Token::Str("float radius".to_string()),
+            Token::Open,
+            Token::Num(2.5),
+            Token::Close,
+        ]
+    );
+}
+
+#[test]
+fn test_parse_scene() {
+    let src = r#"
+        LookAt 0 0 -10  0 0 0  0 1 0
+        Camera "perspective" "float fov" [ 30 ]
+        Film "rgb" "integer xresolution" [ 200 ] "integer yresolution" [ 100 ]
+        Sampler "halton" "integer pixelsamples" 8
+        WorldBegin
+        LightSource "point" "rgb I" [ 1 1 1 ]
+        AttributeBegin
+          Material "dielectric" "float eta" 1.33
+          Translate 1 2 3
+          Scale 2 2 2
+          Shape "sphere" "float radius" 0.5
+        AttributeEnd
+        AttributeBegin
+          AreaLightSource "diffuse" "rgb L" [ 4 4 4 ]
+          Shape "sphere"
+        AttributeEnd
+        Shape "trianglemesh" "point3 P" [ 0 0 0 1 0 0 0 1 0 ] "integer indices" [ 0 1 2 ]
+    "#;
+    let config = parse(src, Path::new(".")).unwrap();
+
+    assert_eq!(config.camera.width(), 200);
+    assert_eq!(config.camera.height(), 100);
+    assert_eq!(config.camera.samples_per_pixel(), 8);
+    assert_eq!(config.camera.vfov(), 30.0);
+    assert!((config.camera.lookfrom() - Point3D::new(0.0, 0.0, -10.0)).length() < 1e-9);
+
+    assert_eq!(config.object_list.objects.len(), 2);
+    let crate::hittable::Shape::Sphere(glass) = &config.object_list.objects[0].shape else {
+        panic!("expected a sphere");
+    };
+    assert!((glass.center - Point3D::new(-1.0, 2.0, 3.0)).length() < 1e-9);
+    assert!((glass.radius - 1.0).abs() < 1e-9);
+    assert!(matches!(glass.material, Material::Glass(g) if g.refraction_index == 1.33));
+    let crate::hittable::Shape::Sphere(light) = &config.object_list.objects[1].shape else {
+        panic!("expected a sphere");
+    };
+    assert!(matches!(light.material, Material::DiffuseLight(_)));
+}
+
+#[test]
+fn test_instances() {
+    use crate::hittable::{HitRecord, Hittable, Shape};
+    use crate::interval::Interval;
+    use crate::ray::Ray;
+
+    let src = r#"
+        WorldBegin
+        ObjectBegin "ball"
+          Translate 0 1 0
+          Shape "sphere" "float radius" 0.5
+        ObjectEnd
+        AttributeBegin
+          Translate 3 0 0
+          ObjectInstance "ball"
+        AttributeEnd
+        AttributeBegin
+          Translate -3 0 0
+          Scale 2 2 2
+          ObjectInstance "ball"
+        AttributeEnd
+    "#;
+    let config = parse(src, Path::new(".")).unwrap();
+    // Only the instances are in the scene, not the definition.
+    let objects = &config.object_list.objects;
+    assert_eq!(objects.len(), 2);
+    assert!(objects
+        .iter()
+        .all(|o| matches!(o.shape, Shape::Instance(_))));
+    // Mirrored along x like the rest of the scene.
+    let down = |x: f64| Ray::new(Point3D::new(x, 5.0, 0.0), Vec3::new(0.0, -1.0, 0.0));
+    let mut rec = HitRecord::default();
+    let interval = Interval::new(0.001, f64::INFINITY);
+    assert!(objects[0].shape.hit(&down(-3.0), &interval, &mut rec));
+    assert!((rec.p.y() - 1.5).abs() < 1e-9);
+    assert!(objects[1].shape.hit(&down(3.0), &interval, &mut rec));
+    assert!((rec.p.y() - 3.0).abs() < 1e-9);
+
+    assert!(parse("ObjectInstance \"none\"", Path::new(".")).is_err());
+    assert!(parse("ObjectBegin \"open\"", Path::new(".")).is_err());
+    assert!(parse("ObjectEnd", Path::new(".")).is_err());
+    let sheared = "ObjectBegin \"a\" ObjectEnd Transform [1 0 0 0 0.5 1 0 0 0 0 1 0 0 0 0 1] ObjectInstance \"a\"";
+    assert!(parse(sheared, Path::new(".")).is_err());
+}
+
+#[test]
+fn test_include_cycle() {
+    let dir = std::env::temp_dir().join(format!("raytracer-pbrt-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.pbrt"), "Include \"b.pbrt\"\n").unwrap();
+    fs::write(dir.join("b.pbrt"), "Include \"a.pbrt\"\n").unwrap();
+    fs::write(
+        dir.join("c.pbrt"),
+        "Include \"d.pbrt\"\nInclude \"d.pbrt\"\n",
+    )
+    .unwrap();
+    fs::write(dir.join("d.pbrt"), "Shape \"sphere\"\n").unwrap();
+    let Err(error) = load(&dir.join("a.pbrt")) else {
+        panic!("a cycle of includes loaded");
+    };
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("includes itself"), "{}", error);
+    // Including the same file twice is no cycle.
+    assert_eq!(
+        load(&dir.join("c.pbrt")).unwrap().object_list.objects.len(),
+        2
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,494 @@
+// Point clouds, such as laser scans, read from PLY files relative to the scene
+// file. Every point is drawn as a small disk turned to face the ray, or as a
+// sphere, and the points are kept in a BVH of their own. Only the vertex
+// element is read: its x, y and z, a radius if there is one, and red, green
+// and blue, which tint a Lambertian material. Bytes are 8 bit sRGB and decoded
+// like images are; floats are taken as linear.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+use crate::color::{gamma_to_linear, Color};
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::{Point3D, Vec3};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Splat {
+    #[default]
+    Disk,
+    Sphere,
+}
+
+#[derive(Debug, Default)]
+pub struct Points {
+    pub positions: Vec<Point3D>,
+    pub radii: Vec<f64>,
+    // Empty when the file has none.
+    pub colors: Vec<Color>,
+    bvh: Bvh,
+}
+
+impl Points {
+    fn new(positions: Vec<Point3D>, radii: Vec<f64>, colors: Vec<Color>) -> Points {
+        let boxes: Vec<Aabb> = positions
+            .iter()
+            .zip(&radii)
+            .map(|(&p, &r)| {
+                let r = Vec3::new(r, r, r);
+                Aabb::from_points(p - r, p + r)
+            })
+            .collect();
+        Points {
+            bvh: Bvh::new(&boxes),
+            positions,
+            radii,
+            colors,
+        }
+    }
+
+    fn hit_point(&self, i: usize, r: &Ray, ray_t: &Interval, splat: Splat) -> Option<f64> {
+        let (center, radius) = (self.positions[i], self.radii[i]);
+        let oc = center - *r.origin();
+        let a = r.direction().length_squared();
+        let along = oc.dot(r.direction());
+        let t = match splat {
+            // Where the ray comes closest to the center.
+            Splat::Disk => {
+                let t = along / a;
+                if (r.at(t) - center).length_squared() > radius * radius {
+                    return None;
+                }
+                t
+            }
+            Splat::Sphere => {
+                let discriminant = along * along - a * (oc.length_squared() - radius * radius);
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let near = (along - discriminant.sqrt()) / a;
+                if ray_t.surrounds(near) {
+                    near
+                } else {
+                    (along + discriminant.sqrt()) / a
+                }
+            }
+        };
+        ray_t.surrounds(t).then_some(t)
+    }
+}
+
+fn default_radius() -> f64 {
+    0.01
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PointCloud {
+    pub file: String,
+    #[serde(default)]
+    pub splat: Splat,
+    // The radius of points the file gives none.
+    #[serde(default = "default_radius")]
+    pub radius: f64,
+    pub material: Material,
+    #[serde(skip)]
+    pub points: Option<Arc<Points>>,
+}
+
+impl PointCloud {
+    pub fn new(file: &str, material: Material) -> PointCloud {
+        PointCloud {
+            file: file.to_string(),
+            splat: Splat::default(),
+            radius: default_radius(),
+            material,
+            points: None,
+        }
+    }
+
+    // Reads the points and returns the path they were read from.
+    pub fn load(&mut self, base_dir: &Path) -> io::Result<PathBuf> {
+        let path = base_dir.join(&self.file);
+        let ply = fs::read(&path)
+            .and_then(|data| parse_ply(&data))
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        let radii = ply
+            .radii
+            .unwrap_or_else(|| vec![self.radius; ply.positions.len()]);
+        self.points = Some(Arc::new(Points::new(
+            ply.positions,
+            radii,
+            ply.colors.unwrap_or_default(),
+        )));
+        Ok(path)
+    }
+}
+
+impl Hittable for PointCloud {
+    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+        let Some(points) = &self.points else {
+            return false;
+        };
+        let mut closest = None;
+        points.bvh.hit(r, ray_t, |i, interval| {
+            let t = points.hit_point(i, r, interval, self.splat)?;
+            closest = Some((i, t));
+            Some(t)
+        });
+        let Some((i, t)) = closest else {
+            return false;
+        };
+        rec.t = t;
+        rec.p = r.at(t);
+        let normal = match self.splat {
+            Splat::Disk => -r.direction().unit_vector(),
+            Splat::Sphere => (rec.p - points.positions[i]) / points.radii[i],
+        };
+        rec.set_face_normal(r, normal);
+        (rec.u, rec.v, rec.dpdu, rec.dpdv) = (0.0, 0.0, Vec3::default(), Vec3::default());
+        rec.mat = match (&self.material, points.colors.get(i)) {
+            (Material::Lambertian(lambertian), Some(&color)) => {
+                let mut tinted = lambertian.clone();
+                tinted.albedo = tinted.albedo * color;
+                Material::Lambertian(tinted)
+            }
+            (material, _) => material.clone(),
+        };
+        true
+    }
+
+    fn occluded(&self, r: &Ray, ray_t: &Interval) -> bool {
+        let Some(points) = &self.points else {
+            return false;
+        };
+        points.bvh.any(r, ray_t, |i| {
+            points.hit_point(i, r, ray_t, self.splat).is_some()
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.points
+            .as_ref()
+            .map_or(Aabb::EMPTY, |points| points.bvh.bounds())
+    }
+}
+
+#[derive(Debug, Default)]
+struct Ply {
+    positions: Vec<Point3D>,
+    radii: Option<Vec<f64>>,
+    colors: Option<Vec<Color>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        Some(match name {
+            "char" | "int8" => Scalar::I8,
+            "uchar" | "uint8" => Scalar::U8,
+            "short" | "int16" => Scalar::I16,
+            "ushort" | "uint16" => Scalar::U16,
+            "int" | "int32" => Scalar::I32,
+            "uint" | "uint32" => Scalar::U32,
+            "float" | "float32" => Scalar::F32,
+            "double" | "float64" => Scalar::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    // The type of a list's length, if it is one.
+    list: Option<Scalar>,
+    scalar: Scalar,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+// The data after the header, read one value at a time.
+struct Body<'a> {
+    data: &'a [u8],
+    offset: usize,
+    format: Format,
+}
+
+impl Body<'_> {
+    fn next(&mut self, scalar: Scalar) -> io::Result<f64> {
+        let short = || io::Error::new(io::ErrorKind::InvalidData, "the file is cut short");
+        if self.format == Format::Ascii {
+            let rest = &self.data[self.offset..];
+            let start = rest
+                .iter()
+                .position(|b| !b.is_ascii_whitespace())
+                .ok_or_else(short)?;
+            let end = rest[start..]
+                .iter()
+                .position(|b| b.is_ascii_whitespace())
+                .map_or(rest.len(), |end| start + end);
+            self.offset += end;
+            let token = String::from_utf8_lossy(&rest[start..end]);
+            return token.parse().map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("\"{}\" is not a number", token),
+                )
+            });
+        }
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + scalar.size())
+            .ok_or_else(short)?;
+        self.offset += scalar.size();
+        let big = self.format == Format::BinaryBigEndian;
+        macro_rules! read {
+            ($t:ty) => {{
+                let bytes = bytes.try_into().unwrap();
+                (if big {
+                    <$t>::from_be_bytes(bytes)
+                } else {
+                    <$t>::from_le_bytes(bytes)
+                }) as f64
+            }};
+        }
+        Ok(match scalar {
+            Scalar::I8 => read!(i8),
+            Scalar::U8 => read!(u8),
+            Scalar::I16 => read!(i16),
+            Scalar::U16 => read!(u16),
+            Scalar::I32 => read!(i32),
+            Scalar::U32 => read!(u32),
+            Scalar::F32 => read!(f32),
+            Scalar::F64 => read!(f64),
+        })
+    }
+}
+
+fn parse_ply(data: &[u8]) -> io::Result<Ply> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut offset = 0;
+    let mut lines = Vec::new();
+    loop {
+        let end = data[offset..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| invalid("the header has no end".to_string()))?;
+        let line = String::from_utf8_lossy(&data[offset..offset + end])
+            .trim()
+            .to_string();
+        offset += end + 1;
+        if line == "end_header" {
+            break;
+        }
+        lines.push(line);
+    }
+    if lines.first().map(String::as_str) != Some("ply") {
+        return Err(invalid("not a PLY file".to_string()));
+    }
+
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    for line in &lines[1..] {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["format", name, _] => {
+                format = Some(match *name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => return Err(invalid(format!("unknown format {}", name))),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| invalid(format!("bad count in \"{}\"", line)))?,
+                properties: Vec::new(),
+            }),
+            ["property", rest @ ..] => {
+                let element = elements
+                    .last_mut()
+                    .ok_or_else(|| invalid("a property before any element".to_string()))?;
+                let scalar = |name: &str| {
+                    Scalar::parse(name).ok_or_else(|| invalid(format!("unknown type {}", name)))
+                };
+                element.properties.push(match rest {
+                    ["list", length, item, name] => Property {
+                        name: name.to_string(),
+                        list: Some(scalar(length)?),
+                        scalar: scalar(item)?,
+                    },
+                    [item, name] => Property {
+                        name: name.to_string(),
+                        list: None,
+                        scalar: scalar(item)?,
+                    },
+                    _ => return Err(invalid(format!("can't read \"{}\"", line))),
+                });
+            }
+            _ => {}
+        }
+    }
+    let format = format.ok_or_else(|| invalid("the header has no format".to_string()))?;
+
+    let mut body = Body {
+        data,
+        offset,
+        format,
+    };
+    for element in &elements {
+        let index = |name: &str| element.properties.iter().position(|p| p.name == name);
+        let mut values = vec![0.0; element.properties.len()];
+        let mut ply = Ply::default();
+        let (x, y, z) = (index("x"), index("y"), index("z"));
+        let radius = index("radius");
+        let color = match (index("red"), index("green"), index("blue")) {
+            (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+            _ => None,
+        };
+        for _ in 0..element.count {
+            for (value, property) in values.iter_mut().zip(&element.properties) {
+                match property.list {
+                    // Lists, like a face's corners, are skipped.
+                    Some(length) => {
+                        for _ in 0..body.next(length)? as usize {
+                            body.next(property.scalar)?;
+                        }
+                    }
+                    None => *value = body.next(property.scalar)?,
+                }
+            }
+            if element.name != "vertex" {
+                continue;
+            }
+            let (Some(x), Some(y), Some(z)) = (x, y, z) else {
+                return Err(invalid("the vertices have no positions".to_string()));
+            };
+            ply.positions
+                .push(Point3D::new(values[x], values[y], values[z]));
+            if let Some(radius) = radius {
+                ply.radii.get_or_insert_with(Vec::new).push(values[radius]);
+            }
+            if let Some(channels) = color {
+                let [r, g, b] = channels.map(|c| match element.properties[c].scalar {
+                    Scalar::U8 => gamma_to_linear(values[c] / 255.0),
+                    _ => values[c],
+                });
+                ply.colors
+                    .get_or_insert_with(Vec::new)
+                    .push(Color::new(r, g, b));
+            }
+        }
+        if element.name == "vertex" {
+            return Ok(ply);
+        }
+    }
+    Err(invalid("the file has no vertices".to_string()))
+}
+
+#[test]
+fn test_parse_ply() {
+    let ascii = "ply\nformat ascii 1.0\ncomment a scan\nelement vertex 2\nproperty float x\nproperty float y\nproperty float z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\nelement face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0 255 0 0\n1 2 3 0 255 0\n3 0 1 1\n";
+    let ply = parse_ply(ascii.as_bytes()).unwrap();
+    assert_eq!(ply.positions[1], Point3D::new(1.0, 2.0, 3.0));
+    assert_eq!(ply.colors.unwrap()[1], Color::new(0.0, 1.0, 0.0));
+    assert!(ply.radii.is_none());
+
+    // The same positions, binary, after a list it has to skip.
+    let mut binary = b"ply\r\nformat binary_big_endian 1.0\r\nelement note 1\r\nproperty list uchar short values\r\nelement vertex 2\r\nproperty double x\r\nproperty double y\r\nproperty double z\r\nproperty float radius\r\nend_header\r\n".to_vec();
+    binary.extend([2, 0, 7, 0, 8]);
+    for (p, radius) in [([0.0, 0.0, 0.0], 0.5f32), ([1.0, 2.0, 3.0], 0.25)] {
+        for c in p {
+            binary.extend(f64::to_be_bytes(c));
+        }
+        binary.extend(radius.to_be_bytes());
+    }
+    let ply = parse_ply(&binary).unwrap();
+    assert_eq!(ply.positions[1], Point3D::new(1.0, 2.0, 3.0));
+    assert_eq!(ply.radii.unwrap(), vec![0.5, 0.25]);
+    assert!(ply.colors.is_none());
+
+    binary.pop();
+    assert!(parse_ply(&binary).is_err());
+    assert!(parse_ply(b"solid cube\nend_header\n").is_err());
+}
+
+#[test]
+fn test_point_cloud() {
+    use crate::material::Lambertian;
+
+    let points = Points::new(
+        vec![Point3D::new(0.0, 0.0, 0.0), Point3D::new(0.0, 0.0, -1.0)],
+        vec![0.5, 0.5],
+        vec![Color::new(1.0, 0.0, 0.0), Color::new(0.0, 1.0, 0.0)],
+    );
+    let mut cloud = PointCloud::new(
+        "scan.ply",
+        Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    );
+    cloud.points = Some(Arc::new(points));
+    let everywhere = Interval::new(0.001, f64::INFINITY);
+    let r = Ray::new(Point3D::new(0.3, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+
+    // The near disk, facing the ray, tinting the material red.
+    let mut rec = HitRecord::default();
+    assert!(cloud.hit(&r, &everywhere, &mut rec));
+    assert!((rec.t - 5.0).abs() < 1e-12);
+    assert!(rec.front_face && rec.normal == Vec3::new(0.0, 0.0, 1.0));
+    let Material::Lambertian(tinted) = &rec.mat else {
+        panic!("not lambertian");
+    };
+    assert_eq!(tinted.albedo, Color::new(0.5, 0.0, 0.0));
+    // Past it, the far one.
+    assert!(cloud.hit(&r, &Interval::new(5.1, f64::INFINITY), &mut rec));
+    assert!((rec.t - 6.0).abs() < 1e-12);
+
+    // As spheres they are hit on their surfaces.
+    cloud.splat = Splat::Sphere;
+    assert!(cloud.hit(&r, &everywhere, &mut rec));
+    assert!((rec.t - 4.6).abs() < 1e-12);
+    assert!((rec.normal - Vec3::new(0.6, 0.0, 0.8)).length() < 1e-12);
+    let beside = Ray::new(Point3D::new(0.6, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0));
+    assert!(!cloud.hit(&beside, &everywhere, &mut rec));
+}
@@ -0,0 +1,142 @@
+// Portals: parallelograms over the openings of an interior scene, such as
+// windows, through which the sky lights it. Nothing hits a portal; it only
+// tells the camera where the sky can be seen from inside. At every diffuse
+// surface one extra ray is aimed through a random point of a random portal
+// and combined with the ray the material scattered by multiple importance
+// sampling, so a small window no longer has to be found by chance.
+//
+// Portals should cover every opening. Sky seen through an opening without a
+// portal is still found by scattered rays, just as noisily as without them.
+
+use crate::light_link::LightLinks;
+use crate::vec3::{Point3D, Vec3};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Portal {
+    // One corner and the two edges leaving it.
+    pub corner: Point3D,
+    pub u: Vec3,
+    pub v: Vec3,
+    // What the sky seen through the portal lights and what shadows it.
+    #[serde(default, skip_serializing_if = "LightLinks::is_default")]
+    pub links: LightLinks,
+}
+
+impl Portal {
+    pub fn new(corner: Point3D, u: Vec3, v: Vec3) -> Portal {
+        Portal {
+            corner,
+            u,
+            v,
+            links: LightLinks::default(),
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        self.u.cross(&self.v).length()
+    }
+
+    pub fn sample_point(&self) -> Point3D {
+        self.corner + crate::rng::random() * self.u + crate::rng::random() * self.v
+    }
+
+    // The probability density, per solid angle at `origin`, of aiming at a
+    // uniformly chosen point of the portal in `direction`. Zero if the
+    // direction misses it.
+    pub fn pdf(&self, origin: Point3D, direction: Vec3) -> f64 {
+        let n = self.u.cross(&self.v);
+        let denominator = n.dot(&direction);
+        if denominator.abs() < 1e-12 {
+            return 0.0;
+        }
+        let t = n.dot(&(self.corner - origin)) / denominator;
+        if t <= 0.0 {
+            return 0.0;
+        }
+
+        // Coordinates of the hit point along the edges, as in the
+        // "Ray Tracing: The Next Week" quads.
+        let w = n / n.dot(&n);
+        let offset = origin + t * direction - self.corner;
+        let a = w.dot(&offset.cross(&self.v));
+        let b = w.dot(&self.u.cross(&offset));
+        if !(0.0..=1.0).contains(&a) || !(0.0..=1.0).contains(&b) {
+            return 0.0;
+        }
+        let distance_squared = t * t * direction.length_squared();
+        let cosine = (denominator / (n.length() * direction.length())).abs();
+        distance_squared / (cosine * self.area())
+    }
+}
+
+// The portals a ray from `origin` in `direction` passes through.
+pub fn crossed(
+    portals: &[Portal],
+    origin: Point3D,
+    direction: Vec3,
+) -> impl Iterator<Item = &Portal> {
+    portals
+        .iter()
+        .filter(move |portal| portal.pdf(origin, direction) > 0.0)
+}
+
+// Aims at one of `portals`, each as likely, from `origin`.
+pub fn sample_direction(portals: &[Portal], origin: Point3D) -> Vec3 {
+    let index = ((crate::rng::random() * portals.len() as f64) as usize).min(portals.len() - 1);
+    (portals[index].sample_point() - origin).unit_vector()
+}
+
+// The density of `sample_direction` in `direction`, which may pass through
+// several portals. Zero without portals.
+pub fn pdf(portals: &[Portal], origin: Point3D, direction: Vec3) -> f64 {
+    if portals.is_empty() {
+        return 0.0;
+    }
+    portals
+        .iter()
+        .map(|portal| portal.pdf(origin, direction))
+        .sum::<f64>()
+        / portals.len() as f64
+}
+
+// How much of a sample taken with density `pdf` counts when another strategy
+// could have taken it with density `other`.
+pub fn power_heuristic(pdf: f64, other: f64) -> f64 {
+    if pdf == 0.0 {
+        return 0.0;
+    }
+    pdf * pdf / (pdf * pdf + other * other)
+}
+
+#[test]
+fn test_pdf() {
+    // A 2x2 window, 1 above the origin, looked at straight on.
+    let portal = Portal::new(
+        Point3D::new(-1.0, 1.0, -1.0),
+        Vec3::new(2.0, 0.0, 0.0),
+        Vec3::new(0.0, 0.0, 2.0),
+    );
+    let origin = Point3D::new(0.0, 0.0, 0.0);
+    assert_eq!(portal.area(), 4.0);
+    assert!((portal.pdf(origin, Vec3::new(0.0, 1.0, 0.0)) - 0.25).abs() < 1e-12);
+    assert_eq!(portal.pdf(origin, Vec3::new(0.0, -1.0, 0.0)), 0.0);
+    assert_eq!(portal.pdf(origin, Vec3::new(2.0, 1.0, 0.0)), 0.0);
+
+    // Integrating the density over the sphere of directions gives 1.
+    let mut sum = 0.0;
+    let samples = 200_000;
+    for _ in 0..samples {
+        sum += portal.pdf(origin, Vec3::random_unit_vector());
+    }
+    let integral = sum / samples as f64 * 4.0 * std::f64::consts::PI;
+    assert!((integral - 1.0).abs() < 0.05, "{}", integral);
+
+    for _ in 0..100 {
+        let direction = sample_direction(std::slice::from_ref(&portal), origin);
+        assert!(pdf(std::slice::from_ref(&portal), origin, direction) > 0.0);
+    }
+    assert_eq!(pdf(&[], origin, Vec3::new(0.0, 1.0, 0.0)), 0.0);
+    assert_eq!(power_heuristic(1.0, 1.0), 0.5);
+    assert_eq!(power_heuristic(0.0, 1.0), 0.0);
+}
@@ -0,0 +1,598 @@
+// Effects applied to the linear film after rendering and before it is
+// quantized, in the order they are listed in the scene's `post` array. Sizes
+// are fractions of the image width, so previews look like the final frame.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::color::{blackbody, gamma_to_linear, linear_to_gamma, luminance, Color};
+use crate::film::Film;
+use crate::lut::CubeLut;
+use crate::parallel::*;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PostEffect {
+    Bloom(Bloom),
+    Vignette(Vignette),
+    ChromaticAberration(ChromaticAberration),
+    SoftClip(SoftClip),
+    Lut(Lut),
+    Dither(Dither),
+    FalseColor(FalseColor),
+}
+
+impl PostEffect {
+    pub fn apply(&self, film: &mut Film) {
+        match self {
+            PostEffect::Bloom(e) => e.apply(film),
+            PostEffect::Vignette(e) => e.apply(film),
+            PostEffect::ChromaticAberration(e) => e.apply(film),
+            PostEffect::SoftClip(e) => e.apply(film),
+            PostEffect::Lut(e) => e.apply(film),
+            PostEffect::Dither(e) => e.apply(film),
+            PostEffect::FalseColor(e) => e.apply(film),
+        }
+    }
+}
+
+pub fn apply(effects: &[PostEffect], film: &mut Film) {
+    for effect in effects {
+        effect.apply(film);
+    }
+}
+
+// Light brighter than `threshold` is blurred and added back on top, so
+// highlights glow.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bloom {
+    pub threshold: f64,
+    pub intensity: f64,
+    pub radius: f64,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Bloom {
+            threshold: 1.0,
+            intensity: 0.5,
+            radius: 0.02,
+        }
+    }
+}
+
+// Separable Gaussian blur with a kernel reaching three standard deviations.
+fn blur(film: &Film, radius: usize) -> Film {
+    let sigma = radius.max(1) as f64 / 3.0;
+    let kernel: Vec<f64> = (0..=radius)
+        .map(|i| (-((i * i) as f64) / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let norm = kernel[0] + 2.0 * kernel[1..].iter().sum::<f64>();
+
+    let pass = |src: &Film, dx: isize, dy: isize| -> Film {
+        let mut dst = Film::new(src.width, src.height);
+        dst.pixels
+            .par_chunks_mut(src.width)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (x, pixel) in row.iter_mut().enumerate() {
+                    let mut sum = Color::default();
+                    for (i, weight) in kernel.iter().enumerate() {
+                        for sign in [-1, 1] {
+                            if i == 0 && sign == 1 {
+                                continue;
+                            }
+                            let offset = sign * i as isize;
+                            let sx = (x as isize + dx * offset).clamp(0, src.width as isize - 1);
+                            let sy = (y as isize + dy * offset).clamp(0, src.height as isize - 1);
+                            sum += *weight * src.get(sx as usize, sy as usize);
+                        }
+                    }
+                    *pixel = sum / norm;
+                }
+            });
+        dst
+    };
+
+    pass(&pass(film, 1, 0), 0, 1)
+}
+
+impl Bloom {
+    pub fn apply(&self, film: &mut Film) {
+        let mut bright = film.clone();
+        for pixel in &mut bright.pixels {
+            let lum = luminance(*pixel);
+            *pixel = if lum > self.threshold {
+                *pixel * ((lum - self.threshold) / lum)
+            } else {
+                Color::default()
+            };
+        }
+
+        let radius = (self.radius * film.width as f64).round() as usize;
+        let glow = blur(&bright, radius);
+        for (pixel, glow) in film.pixels.iter_mut().zip(glow.pixels) {
+            *pixel += self.intensity * glow;
+        }
+    }
+}
+
+// Darkens the image towards the corners. `strength` 1 makes the corners black.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Vignette {
+    pub strength: f64,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Vignette { strength: 0.3 }
+    }
+}
+
+impl Vignette {
+    pub fn apply(&self, film: &mut Film) {
+        let (cx, cy) = (film.width as f64 / 2.0, film.height as f64 / 2.0);
+        let corner = cx * cx + cy * cy;
+        let width = film.width;
+        for (i, pixel) in film.pixels.iter_mut().enumerate() {
+            let dx = (i % width) as f64 + 0.5 - cx;
+            let dy = (i / width) as f64 + 0.5 - cy;
+            let r2 = (dx * dx + dy * dy) / corner;
+            *pixel *= (1.0 - self.strength * r2).max(0.0);
+        }
+    }
+}
+
+// Lateral color fringing of a simple lens: red is magnified and blue shrunk
+// around the image center by `strength` times the distance to the center.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChromaticAberration {
+    pub strength: f64,
+}
+
+impl Default for ChromaticAberration {
+    fn default() -> Self {
+        ChromaticAberration { strength: 0.005 }
+    }
+}
+
+impl ChromaticAberration {
+    pub fn apply(&self, film: &mut Film) {
+        let src = film.clone();
+        let (cx, cy) = (film.width as f64 / 2.0, film.height as f64 / 2.0);
+        let width = film.width;
+        for (i, pixel) in film.pixels.iter_mut().enumerate() {
+            let x = (i % width) as f64 + 0.5;
+            let y = (i / width) as f64 + 0.5;
+            let at = |scale: f64| src.sample(cx + (x - cx) * scale, cy + (y - cy) * scale);
+            let red = at(1.0 - self.strength).x();
+            let blue = at(1.0 + self.strength).z();
+            *pixel = Color::new(red, pixel.y(), blue);
+        }
+    }
+}
+
+// Makes light of the given color temperature look white. `tint` moves the
+// result towards magenta (positive) or green (negative); at 1 or -1 green
+// is changed by half.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WhiteBalance {
+    #[serde(deserialize_with = "crate::color::deserialize_kelvin")]
+    pub temperature: f64,
+    pub tint: f64,
+}
+
+impl Default for WhiteBalance {
+    fn default() -> Self {
+        WhiteBalance {
+            temperature: 6500.0,
+            tint: 0.0,
+        }
+    }
+}
+
+impl WhiteBalance {
+    // The temperatures scenes may balance for. Below about 1500 K a black
+    // body has no blue at all.
+    pub const TEMPERATURES: std::ops::RangeInclusive<f64> = 1000.0..=40000.0;
+
+    // Per channel factors, scaled to keep the luminance of gray.
+    pub fn gains(&self) -> Color {
+        let light = blackbody(self.temperature);
+        let light = Color::new(
+            light.x().max(1e-4),
+            light.y().max(1e-4),
+            light.z().max(1e-4),
+        );
+        let gains = blackbody(6500.0) / light;
+        let gains = Color::new(gains.x(), gains.y() * (1.0 - 0.5 * self.tint), gains.z());
+        gains / luminance(gains)
+    }
+
+    pub fn apply(&self, film: &mut Film) {
+        let gains = self.gains();
+        for pixel in &mut film.pixels {
+            *pixel = *pixel * gains;
+        }
+    }
+}
+
+// Rolls highlights off towards white instead of cutting them at it. Colors
+// whose brightest channel is above `knee` are scaled down together, so that
+// channel approaches 1 without reaching it: an overexposed orange lamp stays
+// orange instead of turning yellow or white where single channels would
+// clip. Below `knee` nothing changes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SoftClip {
+    pub knee: f64,
+}
+
+impl Default for SoftClip {
+    fn default() -> Self {
+        SoftClip { knee: 0.8 }
+    }
+}
+
+impl SoftClip {
+    // The brightest channel's new value: continuous at the knee, with the
+    // same slope there, and never above 1 however bright it gets.
+    pub fn curve(&self, value: f64) -> f64 {
+        let knee = self.knee();
+        if value <= knee {
+            return value;
+        }
+        let room = 1.0 - knee;
+        knee + room * (1.0 - (-(value - knee) / room).exp())
+    }
+
+    // Below 1, so the curve has room to approach it.
+    fn knee(&self) -> f64 {
+        self.knee.clamp(0.0, 0.999)
+    }
+
+    pub fn pixel(&self, color: Color) -> Color {
+        let brightest = color.x().max(color.y()).max(color.z());
+        if brightest > self.knee() {
+            color * (self.curve(brightest) / brightest)
+        } else {
+            color
+        }
+    }
+
+    pub fn apply(&self, film: &mut Film) {
+        film.pixels
+            .par_iter_mut()
+            .for_each(|pixel| *pixel = self.pixel(*pixel));
+    }
+}
+
+// Grades the image with a .cube file, whose path is relative to the scene
+// file. The table sees the clamped, gamma encoded colors that would otherwise
+// be written, like a LUT applied to a finished frame.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Lut {
+    pub file: String,
+    #[serde(skip)]
+    pub table: Option<Arc<CubeLut>>,
+}
+
+impl Lut {
+    // Reads the table and returns the path it was read from.
+    pub fn load(&mut self, base_dir: &Path) -> io::Result<PathBuf> {
+        let path = base_dir.join(&self.file);
+        self.table = Some(Arc::new(CubeLut::load(&path)?));
+        Ok(path)
+    }
+
+    pub fn apply(&self, film: &mut Film) {
+        let Some(table) = &self.table else {
+            return;
+        };
+        film.pixels.par_iter_mut().for_each(|pixel| {
+            let encoded = Color::new(
+                linear_to_gamma(pixel.x()).min(1.0),
+                linear_to_gamma(pixel.y()).min(1.0),
+                linear_to_gamma(pixel.z()).min(1.0),
+            );
+            let graded = table.apply(encoded);
+            *pixel = Color::new(
+                gamma_to_linear(graded.x()),
+                gamma_to_linear(graded.y()),
+                gamma_to_linear(graded.z()),
+            );
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DitherNoise {
+    // The same threshold from a blue noise tile for all channels: the finest
+    // grain, which the eye barely sees.
+    #[default]
+    BlueNoise,
+    // Independent triangular noise of two steps per channel, which hides
+    // the steps completely but is coarser.
+    Triangular,
+}
+
+// Adds noise of about one 8-bit step to the gamma encoded colors, so smooth
+// dark gradients like a vignette or the sky turn into fine grain instead of
+// bands when they are quantized. Pure black and clipped white stay as they
+// are. It belongs at the end of the list.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Dither {
+    pub noise: DitherNoise,
+}
+
+impl Dither {
+    pub fn apply(&self, film: &mut Film) {
+        use rand::Rng;
+
+        let width = film.width;
+        film.pixels
+            .par_iter_mut()
+            .enumerate()
+            .for_each(|(index, pixel)| {
+                let (x, y) = (index % width, index / width);
+                // Quantizing rounds down, so offsets averaging half a step
+                // keep the average color.
+                let offsets = match self.noise {
+                    DitherNoise::BlueNoise => [crate::blue_noise::threshold(x, y); 3],
+                    DitherNoise::Triangular => {
+                        let mut rng = crate::rng::SplitMix64::new(index as u64);
+                        std::array::from_fn(|_| rng.gen::<f64>() + rng.gen::<f64>() - 0.5)
+                    }
+                };
+                let dither = |linear: f64, offset: f64| {
+                    let encoded = linear_to_gamma(linear);
+                    if encoded <= 0.0 || encoded >= 1.0 {
+                        return linear;
+                    }
+                    gamma_to_linear((encoded + offset / 256.0).max(0.0))
+                };
+                *pixel = Color::new(
+                    dither(pixel.x(), offsets[0]),
+                    dither(pixel.y(), offsets[1]),
+                    dither(pixel.z(), offsets[2]),
+                );
+            });
+    }
+}
+
+// The luminance false color scales are measured from, in exposure stops.
+const MIDDLE_GREY: f64 = 0.18;
+
+// A band of a false color scale: luminances from `ev` stops around middle
+// grey up to the next stop's. Without a color they are shown in grey.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FalseColorStop {
+    pub ev: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<Color>,
+}
+
+// Replaces the image with its luminance, painted in the color of the band
+// it falls in, to judge exposure like the false color view of a camera
+// monitor. The stops are in increasing order; luminances below the first are
+// in the first band. It belongs at the end of the list.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FalseColor {
+    pub stops: Vec<FalseColorStop>,
+}
+
+impl Default for FalseColor {
+    // Crushed blacks purple, deep shadows blue, middle grey green, a stop
+    // above it (skin in a typical exposure) pink, near clipping yellow and
+    // clipped red.
+    fn default() -> Self {
+        let stop = |ev: f64, color: Option<(f64, f64, f64)>| FalseColorStop {
+            ev,
+            color: color.map(|(r, g, b)| Color::new(r, g, b)),
+        };
+        let clipped = (1.0 / MIDDLE_GREY).log2();
+        FalseColor {
+            stops: vec![
+                stop(-6.5, Some((0.5, 0.0, 0.5))),
+                stop(-6.0, Some((0.0, 0.2, 1.0))),
+                stop(-5.0, None),
+                stop(-0.25, Some((0.0, 0.8, 0.0))),
+                stop(0.25, None),
+                stop(0.75, Some((1.0, 0.5, 0.7))),
+                stop(1.25, None),
+                stop(clipped - 0.5, Some((1.0, 1.0, 0.0))),
+                stop(clipped, Some((1.0, 0.0, 0.0))),
+            ],
+        }
+    }
+}
+
+impl FalseColor {
+    pub fn color(&self, pixel: Color) -> Color {
+        let lum = luminance(pixel);
+        let ev = (lum / MIDDLE_GREY).log2();
+        let band = self.stops.iter().rev().find(|stop| ev >= stop.ev);
+        match band.or(self.stops.first()).and_then(|stop| stop.color) {
+            Some(color) => color,
+            None => Color::new(lum, lum, lum),
+        }
+    }
+
+    pub fn apply(&self, film: &mut Film) {
+        film.pixels
+            .par_iter_mut()
+            .for_each(|pixel| *pixel = self.color(*pixel));
+    }
+}
+
+#[test]
+fn test_bloom_spreads_highlights() {
+    let mut film = Film::new(21, 21);
+    film.pixels[10 * 21 + 10] = Color::new(100.0, 100.0, 100.0);
+    let bloom = Bloom {
+        radius: 0.2,
+        ..Bloom::default()
+    };
+    bloom.apply(&mut film);
+    assert!(film.get(12, 10).x() > 0.0);
+    assert!(film.get(10, 12).x() > 0.0);
+    assert_eq!(film.get(0, 0).x(), 0.0);
+
+    // Nothing below the threshold changes.
+    let mut dim = Film::new(5, 5);
+    dim.pixels[12] = Color::new(0.9, 0.9, 0.9);
+    let before = dim.clone();
+    Bloom::default().apply(&mut dim);
+    assert_eq!(dim, before);
+}
+
+#[test]
+fn test_vignette_and_aberration() {
+    let mut film = Film::new(10, 10);
+    film.pixels.fill(Color::new(1.0, 1.0, 1.0));
+    apply(
+        &[
+            PostEffect::ChromaticAberration(ChromaticAberration::default()),
+            PostEffect::Vignette(Vignette { strength: 0.5 }),
+        ],
+        &mut film,
+    );
+    assert!(film.get(0, 0).x() < film.get(5, 5).x());
+    assert!(film.get(5, 5).x() > 0.99);
+    assert!(film.get(0, 0).x() >= 0.5);
+}
+
+#[test]
+fn test_lut() {
+    let mut film = Film::new(2, 1);
+    film.pixels[0] = Color::new(0.25, 4.0, 0.0);
+    let lut = Lut {
+        file: "invert.cube".to_string(),
+        table: Some(Arc::new(
+            CubeLut::parse("LUT_1D_SIZE 2\n1 1 1\n0 0 0\n").unwrap(),
+        )),
+    };
+    lut.apply(&mut film);
+    assert_eq!(film.get(0, 0), Color::new(0.25, 0.0, 1.0));
+    assert_eq!(film.get(1, 0), Color::new(1.0, 1.0, 1.0));
+}
+
+#[test]
+fn test_white_balance() {
+    let tungsten = blackbody(3200.0);
+    let mut film = Film::new(1, 1);
+    film.pixels[0] = tungsten;
+    let balance = WhiteBalance {
+        temperature: 3200.0,
+        tint: 0.0,
+    };
+    balance.apply(&mut film);
+    let c = film.get(0, 0);
+    assert!((c.x() - c.z()).abs() < 0.1 && (c.x() - c.y()).abs() < 0.1);
+
+    let neutral = WhiteBalance::default().gains();
+    assert!((neutral - Color::new(1.0, 1.0, 1.0)).length() < 1e-9);
+    let magenta = WhiteBalance {
+        tint: 0.5,
+        ..WhiteBalance::default()
+    };
+    assert!(magenta.gains().y() < magenta.gains().x());
+
+    // A candle's flame has no blue to balance, which mustn't turn the
+    // image into NaNs.
+    let dim = WhiteBalance {
+        temperature: 1000.0,
+        ..WhiteBalance::default()
+    };
+    assert!(dim.gains().is_finite());
+}
+
+#[test]
+fn test_dither() {
+    // A flat dark gray between two 8-bit steps comes out as only the lower
+    // one without dithering, and as a mix of both averaging to it with.
+    let level: f64 = 10.3;
+    let mut film = Film::new(64, 64);
+    film.pixels
+        .fill(Color::new(1.0, 1.0, 1.0) * gamma_to_linear(level / 256.0));
+    assert!(film.to_rgb8().iter().all(|&v| v == 10));
+
+    for noise in [DitherNoise::BlueNoise, DitherNoise::Triangular] {
+        let mut dithered = film.clone();
+        Dither { noise }.apply(&mut dithered);
+        let bytes = dithered.to_rgb8();
+        let mean = bytes.iter().map(|&v| v as f64).sum::<f64>() / bytes.len() as f64;
+        assert!((mean - level).abs() < 0.05, "{:?} {}", noise, mean);
+        assert!(bytes.iter().all(|&v| (9..=12).contains(&v)));
+    }
+
+    // Black and clipped white are left alone.
+    let mut edges = Film::new(2, 1);
+    edges.pixels[1] = Color::new(2.0, 2.0, 2.0);
+    let before = edges.clone();
+    Dither::default().apply(&mut edges);
+    assert_eq!(edges, before);
+}
+
+#[test]
+fn test_false_color() {
+    let false_color = FalseColor::default();
+    let grey = |lum: f64| Color::new(lum, lum, lum);
+    assert_eq!(false_color.color(grey(0.0)), Color::new(0.5, 0.0, 0.5));
+    assert_eq!(false_color.color(grey(0.18)), Color::new(0.0, 0.8, 0.0));
+    assert_eq!(false_color.color(grey(0.36)), Color::new(1.0, 0.5, 0.7));
+    assert_eq!(false_color.color(grey(1.0)), Color::new(1.0, 0.0, 0.0));
+    // Between the marked bands the luminance shows as it is.
+    assert_eq!(false_color.color(grey(0.05)), grey(0.05));
+    let tinted = Color::new(0.05, 0.1, 0.02);
+    assert_eq!(false_color.color(tinted), grey(luminance(tinted)));
+
+    // Stops given in the scene replace the default scale.
+    let effect: PostEffect = serde_json::from_value(serde_json::json!({
+        "false_color": {"stops": [
+            {"ev": -1.0},
+            {"ev": 1.0, "color": {"x": 1.0, "y": 0.0, "z": 0.0}}
+        ]}
+    }))
+    .unwrap();
+    let mut film = Film::new(2, 1);
+    film.pixels = vec![grey(0.01), grey(0.5)];
+    effect.apply(&mut film);
+    assert_eq!(film.pixels, vec![grey(0.01), Color::new(1.0, 0.0, 0.0)]);
+}
+
+#[test]
+fn test_soft_clip() {
+    let soft_clip = SoftClip::default();
+    assert_eq!(soft_clip.curve(0.5), 0.5);
+    assert!((soft_clip.curve(0.8 + 1e-6) - (0.8 + 1e-6)).abs() < 1e-9);
+    assert!(soft_clip.curve(1.0) < 1.0 && soft_clip.curve(100.0) <= 1.0);
+    assert!(soft_clip.curve(2.0) > soft_clip.curve(1.5));
+
+    // A bright orange stays orange where clamping would make it yellow.
+    let mut film = Film::new(2, 1);
+    film.pixels[0] = Color::new(4.0, 2.0, 0.5);
+    film.pixels[1] = Color::new(0.5, 0.25, 0.1);
+    soft_clip.apply(&mut film);
+    let orange = film.pixels[0];
+    assert!(orange.x() < 1.0);
+    assert!((orange.y() / orange.x() - 0.5).abs() < 1e-9);
+    assert!((orange.z() / orange.x() - 0.125).abs() < 1e-9);
+    assert_eq!(film.pixels[1], Color::new(0.5, 0.25, 0.1));
+    // A knee above white is taken as just below it.
+    let late = SoftClip { knee: 1.5 };
+    assert!(late.pixel(Color::new(1.2, 0.6, 0.0)).x() <= 1.0);
+
+    let post: Vec<PostEffect> = serde_json::from_str(r#"[{"soft_clip": {"knee": 0.6}}]"#).unwrap();
+    assert!(matches!(post[0], PostEffect::SoftClip(SoftClip { knee }) if knee == 0.6));
+}
@@ -0,0 +1,212 @@
+// Where the time of a render goes. `profile` renders through the camera's
+// own integrator and times every bounce: finding the closest hit and
+// shading there, shadow rays included, is credited to the object the ray
+// hit, rays leaving the scene to the background. Objects that keep rays bouncing around, like glass, collect
+// both more rays and more time.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::AddAssign;
+use std::time::{Duration, Instant};
+
+use crate::camera::Camera;
+use crate::hittable::ObjectList;
+use crate::parallel::*;
+
+// How many objects the report lists.
+const TOP: usize = 10;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Cost {
+    pub rays: u64,
+    // Summed over all threads, so it can exceed the wall clock time.
+    pub time: Duration,
+}
+
+impl AddAssign for Cost {
+    fn add_assign(&mut self, other: Cost) {
+        self.rays += other.rays;
+        self.time += other.time;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectCost {
+    // The object's name, or "#index" for unnamed objects.
+    pub name: String,
+    pub material: &'static str,
+    pub cost: Cost,
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderStats {
+    // Indexed like the objects of the scene.
+    pub objects: Vec<ObjectCost>,
+    pub background: Cost,
+    pub elapsed: Duration,
+}
+
+impl RenderStats {
+    pub fn total(&self) -> Cost {
+        let mut total = self.background;
+        for object in &self.objects {
+            total += object.cost;
+        }
+        total
+    }
+
+    // Indices of the `n` objects that took the most time, slowest first.
+    pub fn top_objects(&self, n: usize) -> Vec<usize> {
+        let mut indices: Vec<usize> = (0..self.objects.len()).collect();
+        indices.sort_by_key(|&i| std::cmp::Reverse(self.objects[i].cost.time));
+        indices.truncate(n);
+        indices
+    }
+
+    // The cost of all objects of each kind of material, slowest first.
+    pub fn materials(&self) -> Vec<(&'static str, Cost)> {
+        let mut materials = BTreeMap::new();
+        for object in &self.objects {
+            *materials.entry(object.material).or_insert(Cost::default()) += object.cost;
+        }
+        let mut materials: Vec<_> = materials.into_iter().collect();
+        materials.sort_by_key(|(_, cost)| std::cmp::Reverse(cost.time));
+        materials
+    }
+}
+
+impl fmt::Display for RenderStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total();
+        let line = |f: &mut fmt::Formatter, label: &str, cost: Cost| {
+            let share = cost.time.as_secs_f64() / total.time.as_secs_f64().max(1e-12);
+            let per_ray = cost.time.as_secs_f64() * 1e9 / cost.rays.max(1) as f64;
+            writeln!(
+                f,
+                "  {:<28}{:>6.1}% {:>12} rays {:>8.0} ns/ray",
+                label,
+                100.0 * share,
+                cost.rays,
+                per_ray
+            )
+        };
+
+        writeln!(
+            f,
+            "Rendered in {:.3} s, {} rays",
+            self.elapsed.as_secs_f64(),
+            total.rays
+        )?;
+        writeln!(f, "Slowest objects:")?;
+        for i in self.top_objects(TOP) {
+            let object = &self.objects[i];
+            let label = format!("{} ({})", object.name, object.material);
+            line(f, &label, object.cost)?;
+        }
+        writeln!(f, "Materials:")?;
+        for (material, cost) in self.materials() {
+            line(f, material, cost)?;
+        }
+        line(f, "background", self.background)
+    }
+}
+
+// Renders every sample of the image without keeping the colors and reports
+// what each object cost.
+pub fn profile(camera: &Camera, world: &ObjectList) -> RenderStats {
+    let _span = tracing::info_span!("profile").entered();
+    let start = Instant::now();
+    let rows: Vec<Vec<Cost>> = (0..camera.height())
+        .into_par_iter()
+        .map(|j| {
+            let mut costs = vec![Cost::default(); world.objects.len() + 1];
+            for i in 0..camera.width() {
+                for sample in 0..camera.samples_per_pixel() {
+                    let r = camera.get_ray(i, j, sample);
+                    camera.ray_color_costed(&r, world, &mut costs);
+                }
+            }
+            costs
+        })
+        .collect();
+
+    let mut costs = vec![Cost::default(); world.objects.len() + 1];
+    for row in rows {
+        for (sum, cost) in costs.iter_mut().zip(row) {
+            *sum += cost;
+        }
+    }
+    let background = costs.pop().unwrap_or_default();
+    let objects = world
+        .objects
+        .iter()
+        .zip(costs)
+        .enumerate()
+        .map(|(index, (object, cost))| ObjectCost {
+            name: object
+                .name()
+                .map_or_else(|| format!("#{}", index), str::to_string),
+            material: object.material().kind(),
+            cost,
+        })
+        .collect();
+    RenderStats {
+        objects,
+        background,
+        elapsed: start.elapsed(),
+    }
+}
+
+#[test]
+fn test_profile() {
+    use crate::color::Color;
+    use crate::material::{Glass, Lambertian, Material};
+    use crate::vec3::{Point3D, Vec3};
+
+    let camera = Camera::new(
+        8,
+        8,
+        4,
+        10,
+        20.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let mut world = ObjectList::new();
+    // The glass ball fills the view, the grey one behind the camera is never
+    // hit by a camera ray.
+    world.add_sphere(
+        Point3D::new(0.0, 0.0, -3.0),
+        1.5,
+        Material::Glass(Glass::new(1.5)),
+    );
+    world.add_sphere(
+        Point3D::new(0.0, 0.0, 100.0),
+        1.0,
+        Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    );
+
+    let stats = profile(&camera, &world);
+    let camera_rays = 8 * 8 * 4;
+    // Every camera ray hits the glass and most of them go through it.
+    assert!(stats.objects[0].cost.rays > camera_rays);
+    assert_eq!(stats.objects[1].name, "#1");
+    assert!(stats.background.rays <= camera_rays);
+    assert_eq!(
+        stats.total().rays,
+        stats.objects[0].cost.rays + stats.objects[1].cost.rays + stats.background.rays
+    );
+    assert_eq!(stats.top_objects(1), vec![0]);
+    assert_eq!(stats.materials()[0].0, "glass");
+    assert!(stats.to_string().contains("#0 (glass)"));
+
+    // The camera's bounce limits hold: without going into the glass, only
+    // its reflections bounce on.
+    let mut camera = camera;
+    camera.bounces.transmission = Some(0);
+    let limited = profile(&camera, &world);
+    assert!(limited.objects[0].cost.rays < stats.objects[0].cost.rays);
+}
@@ -0,0 +1,293 @@
+// Quadric surfaces: the points p where (p, 1)ᵀ Q (p, 1) = 0 for a 4x4
+// coefficient matrix Q. Ellipsoids, paraboloids, hyperboloids, cones and
+// cylinders are all quadrics. Many of them go on forever, so every quadric
+// is clipped to a box, `min` to `max`, which is also its bounding box. The
+// outside is where the product is positive.
+
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::transform::Mat4;
+use crate::vec3::{Point3D, Vec3};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Quadric {
+    // Only the symmetric part counts: Q and its transpose give the same
+    // surface.
+    pub coefficients: [[f64; 4]; 4],
+    pub min: Point3D,
+    pub max: Point3D,
+    pub material: Material,
+}
+
+impl Quadric {
+    pub fn new(
+        coefficients: [[f64; 4]; 4],
+        min: Point3D,
+        max: Point3D,
+        material: Material,
+    ) -> Quadric {
+        Quadric {
+            coefficients,
+            min,
+            max,
+            material,
+        }
+    }
+
+    // A unit quadric, diagonal in `diagonal` and with `linear` times y added,
+    // scaled by `scale` and moved to `center`.
+    fn canonical(
+        diagonal: [f64; 4],
+        linear: f64,
+        center: Point3D,
+        scale: Vec3,
+        min: Point3D,
+        max: Point3D,
+        material: Material,
+    ) -> Quadric {
+        let mut q = [[0.0; 4]; 4];
+        for (i, d) in diagonal.into_iter().enumerate() {
+            q[i][i] = d;
+        }
+        q[1][3] = 0.5 * linear;
+        q[3][1] = 0.5 * linear;
+        // Points p on the moved surface are m·u for u on the unit one, so
+        // the moved surface's matrix is m⁻ᵀ Q m⁻¹.
+        let m = Mat4::translate(center) * Mat4::scale(scale);
+        let inverse = m.inverse().unwrap_or(Mat4::IDENTITY);
+        let coefficients = (inverse.transpose() * Mat4(q) * inverse).0;
+        // Negative scales flip the box.
+        let clip = Aabb::from_points(center + scale * min, center + scale * max);
+        Quadric::new(coefficients, clip.min(), clip.max(), material)
+    }
+
+    pub fn ellipsoid(center: Point3D, radii: Vec3, material: Material) -> Quadric {
+        Quadric::canonical(
+            [1.0, 1.0, 1.0, -1.0],
+            0.0,
+            center,
+            radii,
+            Point3D::new(-1.0, -1.0, -1.0),
+            Point3D::new(1.0, 1.0, 1.0),
+            material,
+        )
+    }
+
+    // A bowl opening upwards from `base`, `radius` wide at `height`.
+    pub fn paraboloid(base: Point3D, radius: f64, height: f64, material: Material) -> Quadric {
+        Quadric::canonical(
+            [1.0, 0.0, 1.0, 0.0],
+            -1.0,
+            base,
+            Vec3::new(radius, height, radius),
+            Point3D::new(-1.0, 0.0, -1.0),
+            Point3D::new(1.0, 1.0, 1.0),
+            material,
+        )
+    }
+
+    // A hyperboloid of one sheet around the y axis, `radius` wide at its
+    // waist and widening to twice that `height` above and below it.
+    pub fn hyperboloid(center: Point3D, radius: f64, height: f64, material: Material) -> Quadric {
+        Quadric::canonical(
+            [1.0, -3.0, 1.0, -1.0],
+            0.0,
+            center,
+            Vec3::new(radius, height, radius),
+            Point3D::new(-2.0, -1.0, -2.0),
+            Point3D::new(2.0, 1.0, 2.0),
+            material,
+        )
+    }
+
+    // With `min` above `max` on some axis nothing is left.
+    pub fn is_clipped_away(&self) -> bool {
+        self.min.x() > self.max.x() || self.min.y() > self.max.y() || self.min.z() > self.max.z()
+    }
+
+    // vᵀ Q w, for points (w = 1) and directions (w = 0).
+    fn product(&self, v: [f64; 4], w: [f64; 4]) -> f64 {
+        let q = &self.coefficients;
+        (0..4)
+            .map(|i| (0..4).map(|j| v[i] * q[i][j] * w[j]).sum::<f64>())
+            .sum()
+    }
+
+    // Grows towards the outside.
+    fn gradient(&self, p: Point3D) -> Vec3 {
+        let q = &self.coefficients;
+        let p = [p.x(), p.y(), p.z(), 1.0];
+        let row = |i: usize| (0..4).map(|j| (q[i][j] + q[j][i]) * p[j]).sum::<f64>();
+        Vec3::new(row(0), row(1), row(2))
+    }
+
+    fn inside_box(&self, p: Point3D) -> bool {
+        let slack = Vec3::new(1.0, 1.0, 1.0) * 1e-9 * (self.max - self.min).length();
+        let (min, max) = (self.min - slack, self.max + slack);
+        (min.x()..=max.x()).contains(&p.x())
+            && (min.y()..=max.y()).contains(&p.y())
+            && (min.z()..=max.z()).contains(&p.z())
+    }
+}
+
+impl Hittable for Quadric {
+    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+        let (o, d) = (*r.origin(), *r.direction());
+        let o = [o.x(), o.y(), o.z(), 1.0];
+        let d = [d.x(), d.y(), d.z(), 0.0];
+        let a = self.product(d, d);
+        let b = self.product(d, o) + self.product(o, d);
+        let c = self.product(o, o);
+
+        // Planes and rays along a paraboloid's axis leave a linear equation.
+        let roots = if a.abs() < 1e-12 * (b.abs() + c.abs()) {
+            if b == 0.0 {
+                return false;
+            }
+            [-c / b, f64::INFINITY]
+        } else {
+            let discriminant = b * b - 4.0 * a * c;
+            if discriminant < 0.0 {
+                return false;
+            }
+            // Without cancelling when b is much larger than a·c.
+            let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+            let (t0, t1) = (q / a, if q != 0.0 { c / q } else { -q / a });
+            [t0.min(t1), t0.max(t1)]
+        };
+
+        for t in roots {
+            if !ray_t.contains(t) {
+                continue;
+            }
+            let p = r.at(t);
+            if !self.inside_box(p) {
+                continue;
+            }
+            rec.t = t;
+            rec.p = p;
+            rec.set_face_normal(r, self.gradient(p).unit_vector());
+            // No texture coordinates.
+            rec.u = 0.0;
+            rec.v = 0.0;
+            rec.dpdu = Vec3::default();
+            rec.dpdv = Vec3::default();
+            rec.mat = self.material.clone();
+            return true;
+        }
+        false
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::from_points(self.min, self.max)
+    }
+}
+
+#[test]
+fn test_quadrics() {
+    let hit = |quadric: &Quadric, origin: Point3D, direction: Vec3| {
+        let mut rec = HitRecord::default();
+        let r = Ray::new(origin, direction);
+        quadric
+            .hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec)
+            .then_some(rec)
+    };
+    let material = Material::Named("x".to_string());
+
+    // An ellipsoid three wide in x, hit on its long side and its short one.
+    let ellipsoid = Quadric::ellipsoid(
+        Point3D::new(1.0, 0.0, 0.0),
+        Vec3::new(3.0, 1.0, 1.0),
+        material.clone(),
+    );
+    let rec = hit(
+        &ellipsoid,
+        Point3D::new(10.0, 0.0, 0.0),
+        Vec3::new(-1.0, 0.0, 0.0),
+    )
+    .unwrap();
+    assert!((rec.t - 6.0).abs() < 1e-9);
+    assert!((rec.normal - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-9);
+    let rec = hit(
+        &ellipsoid,
+        Point3D::new(1.0, 5.0, 0.0),
+        Vec3::new(0.0, -2.0, 0.0),
+    )
+    .unwrap();
+    assert!((rec.t - 2.0).abs() < 1e-9 && rec.front_face);
+    // From the inside the far side is hit, facing away.
+    let rec = hit(
+        &ellipsoid,
+        Point3D::new(1.0, 0.0, 0.0),
+        Vec3::new(1.0, 0.0, 0.0),
+    )
+    .unwrap();
+    assert!((rec.t - 3.0).abs() < 1e-9 && !rec.front_face);
+    assert_eq!(ellipsoid.bounding_box().max(), Point3D::new(4.0, 1.0, 1.0));
+
+    // A bowl two deep and one wide at the top: straight down its axis the
+    // linear case hits the bottom, and through the open top the inside.
+    let bowl = Quadric::paraboloid(Point3D::new(0.0, 0.0, 0.0), 1.0, 2.0, material.clone());
+    let rec = hit(
+        &bowl,
+        Point3D::new(0.0, 5.0, 0.0),
+        Vec3::new(0.0, -1.0, 0.0),
+    )
+    .unwrap();
+    assert!((rec.t - 5.0).abs() < 1e-9);
+    assert!((rec.normal - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9);
+    // y = 2 x², so at x = 0.5 the inside is at height 0.5.
+    let rec = hit(
+        &bowl,
+        Point3D::new(0.5, 5.0, 0.0),
+        Vec3::new(0.0, -1.0, 0.0),
+    )
+    .unwrap();
+    assert!((rec.t - 4.5).abs() < 1e-9);
+    // Clipped at the rim: passing above it misses.
+    assert!(hit(
+        &bowl,
+        Point3D::new(-5.0, 2.5, 0.0),
+        Vec3::new(1.0, 0.0, 0.0)
+    )
+    .is_none());
+    // Passing below it goes through both walls, at y = 2 x² = 0.5.
+    let rec = hit(
+        &bowl,
+        Point3D::new(-5.0, 0.5, 0.0),
+        Vec3::new(1.0, 0.0, 0.0),
+    )
+    .unwrap();
+    assert!((rec.t - 4.5).abs() < 1e-9);
+
+    // A hyperboloid narrowest at its waist, twice as wide at its ends.
+    let hyperboloid = Quadric::hyperboloid(Point3D::new(0.0, 0.0, 0.0), 1.0, 1.0, material);
+    let rec = hit(
+        &hyperboloid,
+        Point3D::new(5.0, 0.0, 0.0),
+        Vec3::new(-1.0, 0.0, 0.0),
+    )
+    .unwrap();
+    assert!((rec.t - 4.0).abs() < 1e-9);
+    let rec = hit(
+        &hyperboloid,
+        Point3D::new(5.0, 1.0, 0.0),
+        Vec3::new(-1.0, 0.0, 0.0),
+    )
+    .unwrap();
+    assert!((rec.t - 3.0).abs() < 1e-9);
+    // The walls lean outwards going up, so the normal tilts down.
+    assert!(rec.normal.y() < 0.0 && rec.normal.x() > 0.0);
+    // It is open along its axis.
+    assert!(hit(
+        &hyperboloid,
+        Point3D::new(0.0, 5.0, 0.0),
+        Vec3::new(0.0, -1.0, 0.0)
+    )
+    .is_none());
+}
@@ -0,0 +1,250 @@
+// Unit quaternions for orientations: they interpolate smoothly with `slerp`
+// and don't need an up vector the way a look-at basis does. Angles are in
+// degrees, like the camera's field of view.
+
+use std::ops::Mul;
+
+use serde::{Deserialize, Serialize};
+
+use crate::vec3::Vec3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Quat {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+// How rotations are written in scene files.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rotation {
+    Quat(Quat),
+    AxisAngle { axis: Vec3, angle: f64 },
+    // Degrees around x, then y, then z.
+    Euler(Vec3),
+    // Turns -z towards `forward` and y towards `up`, like a camera.
+    LookRotation { forward: Vec3, up: Vec3 },
+}
+
+impl From<Rotation> for Quat {
+    fn from(rotation: Rotation) -> Self {
+        match rotation {
+            Rotation::Quat(q) => q.normalize(),
+            Rotation::AxisAngle { axis, angle } => Quat::from_axis_angle(axis, angle),
+            Rotation::Euler(angles) => Quat::from_euler(angles.x(), angles.y(), angles.z()),
+            Rotation::LookRotation { forward, up } => Quat::look_rotation(forward, up),
+        }
+    }
+}
+
+impl Default for Quat {
+    fn default() -> Self {
+        Quat::IDENTITY
+    }
+}
+
+impl Quat {
+    pub const IDENTITY: Quat = Quat {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    pub fn new(w: f64, x: f64, y: f64, z: f64) -> Quat {
+        Quat { w, x, y, z }
+    }
+
+    pub fn from_axis_angle(axis: Vec3, degrees: f64) -> Quat {
+        let half = degrees.to_radians() / 2.0;
+        let axis = axis.unit_vector() * half.sin();
+        Quat::new(half.cos(), axis.x(), axis.y(), axis.z())
+    }
+
+    pub fn from_euler(x: f64, y: f64, z: f64) -> Quat {
+        Quat::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), z)
+            * Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), y)
+            * Quat::from_axis_angle(Vec3::new(1.0, 0.0, 0.0), x)
+    }
+
+    // The rotation from the rotated x, y and z axes, which must be
+    // orthonormal.
+    pub fn from_axes(x: Vec3, y: Vec3, z: Vec3) -> Quat {
+        let trace = x.x() + y.y() + z.z();
+        let q = if trace > 0.0 {
+            let s = 2.0 * (trace + 1.0).sqrt();
+            Quat::new(
+                s / 4.0,
+                (y.z() - z.y()) / s,
+                (z.x() - x.z()) / s,
+                (x.y() - y.x()) / s,
+            )
+        } else if x.x() > y.y() && x.x() > z.z() {
+            let s = 2.0 * (1.0 + x.x() - y.y() - z.z()).sqrt();
+            Quat::new(
+                (y.z() - z.y()) / s,
+                s / 4.0,
+                (y.x() + x.y()) / s,
+                (z.x() + x.z()) / s,
+            )
+        } else if y.y() > z.z() {
+            let s = 2.0 * (1.0 + y.y() - x.x() - z.z()).sqrt();
+            Quat::new(
+                (z.x() - x.z()) / s,
+                (y.x() + x.y()) / s,
+                s / 4.0,
+                (z.y() + y.z()) / s,
+            )
+        } else {
+            let s = 2.0 * (1.0 + z.z() - x.x() - y.y()).sqrt();
+            Quat::new(
+                (x.y() - y.x()) / s,
+                (z.x() + x.z()) / s,
+                (z.y() + y.z()) / s,
+                s / 4.0,
+            )
+        };
+        q.normalize()
+    }
+
+    // `up` only needs to be roughly perpendicular to `forward`.
+    pub fn look_rotation(forward: Vec3, up: Vec3) -> Quat {
+        let z = -forward.unit_vector();
+        let x = up.cross(&z).unit_vector();
+        let y = z.cross(&x);
+        Quat::from_axes(x, y, z)
+    }
+
+    pub fn dot(&self, other: &Quat) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    pub fn normalize(&self) -> Quat {
+        let length = self.dot(self).sqrt();
+        Quat::new(
+            self.w / length,
+            self.x / length,
+            self.y / length,
+            self.z / length,
+        )
+    }
+
+    // The inverse rotation.
+    pub fn conjugate(&self) -> Quat {
+        Quat::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    pub fn rotate(&self, v: Vec3) -> Vec3 {
+        let u = Vec3::new(self.x, self.y, self.z);
+        let t = 2.0 * u.cross(&v);
+        v + self.w * t + u.cross(&t)
+    }
+
+    // The rotated x, y and z axes.
+    pub fn axes(&self) -> (Vec3, Vec3, Vec3) {
+        (
+            self.rotate(Vec3::new(1.0, 0.0, 0.0)),
+            self.rotate(Vec3::new(0.0, 1.0, 0.0)),
+            self.rotate(Vec3::new(0.0, 0.0, 1.0)),
+        )
+    }
+
+    // Interpolates along the shorter arc at constant angular speed.
+    pub fn slerp(&self, other: &Quat, t: f64) -> Quat {
+        let mut cos_theta = self.dot(other);
+        let mut other = *other;
+        if cos_theta < 0.0 {
+            other = Quat::new(-other.w, -other.x, -other.y, -other.z);
+            cos_theta = -cos_theta;
+        }
+
+        // Nearly identical rotations, where sin(theta) would divide by zero.
+        let (a, b) = if cos_theta > 0.9995 {
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            let sin_theta = theta.sin();
+            (
+                ((1.0 - t) * theta).sin() / sin_theta,
+                (t * theta).sin() / sin_theta,
+            )
+        };
+        Quat::new(
+            a * self.w + b * other.w,
+            a * self.x + b * other.x,
+            a * self.y + b * other.y,
+            a * self.z + b * other.z,
+        )
+        .normalize()
+    }
+}
+
+// `a * b` rotates by `b` first, then by `a`.
+impl Mul for Quat {
+    type Output = Quat;
+
+    fn mul(self, o: Quat) -> Quat {
+        Quat::new(
+            self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z,
+            self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y,
+            self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x,
+            self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w,
+        )
+    }
+}
+
+#[cfg(test)]
+fn assert_close(a: Vec3, b: Vec3) {
+    assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+}
+
+#[test]
+fn test_rotate() {
+    let q = Quat::from_axis_angle(Vec3::new(0.0, 0.0, 2.0), 90.0);
+    assert_close(q.rotate(Vec3::new(1.0, 0.0, 0.0)), Vec3::new(0.0, 1.0, 0.0));
+    assert_close(
+        q.conjugate().rotate(Vec3::new(0.0, 1.0, 0.0)),
+        Vec3::new(1.0, 0.0, 0.0),
+    );
+
+    let euler = Quat::from_euler(90.0, 90.0, 0.0);
+    // x turns y into z, then y turns z into x.
+    assert_close(
+        euler.rotate(Vec3::new(0.0, 1.0, 0.0)),
+        Vec3::new(1.0, 0.0, 0.0),
+    );
+}
+
+#[test]
+fn test_look_rotation() {
+    let forward = Vec3::new(1.0, -1.0, 2.0);
+    let q = Quat::look_rotation(forward, Vec3::new(0.0, 1.0, 0.0));
+    let (x, y, z) = q.axes();
+    assert_close(-z, forward.unit_vector());
+    assert!(x.y().abs() < 1e-9);
+    assert!(y.y() > 0.0);
+
+    // Every branch of from_axes.
+    for (axis, angle) in [
+        ((1.0, 0.0, 0.0), 170.0),
+        ((0.0, 1.0, 0.0), 170.0),
+        ((0.0, 0.0, 1.0), 170.0),
+    ] {
+        let q = Quat::from_axis_angle(Vec3::new(axis.0, axis.1, axis.2), angle);
+        let (x, y, z) = q.axes();
+        assert!(Quat::from_axes(x, y, z).dot(&q).abs() > 1.0 - 1e-9);
+    }
+}
+
+#[test]
+fn test_slerp() {
+    let a = Quat::IDENTITY;
+    let b = Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 90.0);
+    let half = a.slerp(&b, 0.5);
+    let expected = Quat::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 45.0);
+    assert!(half.dot(&expected) > 1.0 - 1e-9);
+    assert_eq!(a.slerp(&b, 0.0), a);
+    assert!(a.slerp(&b, 1.0).dot(&b) > 1.0 - 1e-9);
+}
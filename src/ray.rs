@@ -1,9 +1,24 @@
 use crate::vec3::{Point3D, Vec3};
 
+// What a ray is used for, so objects can be hidden from some kinds of rays.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RayKind {
+    #[default]
+    Camera,
+    Diffuse,
+    Specular,
+}
+
 #[derive(Default)]
 pub struct Ray {
     orig: Point3D,
     dir: Vec3,
+    kind: RayKind,
+    // The ray stands for a cone this wide at its origin, widening by
+    // `spread` per unit of distance, for filtering textures. Zero for rays
+    // that aren't from the camera or a path starting there.
+    width: f64,
+    spread: f64,
 }
 
 impl Ray {
@@ -11,9 +26,36 @@ impl Ray {
         Ray {
             orig: origin,
             dir: direction,
+            kind: RayKind::Camera,
+            width: 0.0,
+            spread: 0.0,
         }
     }
 
+    pub fn with_cone(mut self, width: f64, spread: f64) -> Ray {
+        self.width = width;
+        self.spread = spread;
+        self
+    }
+
+    // Width of the ray's cone at `t`.
+    pub fn cone_width(&self, t: f64) -> f64 {
+        self.width + self.spread * t * self.dir.length()
+    }
+
+    pub fn spread(&self) -> f64 {
+        self.spread
+    }
+
+    pub fn with_kind(mut self, kind: RayKind) -> Ray {
+        self.kind = kind;
+        self
+    }
+
+    pub fn kind(&self) -> RayKind {
+        self.kind
+    }
+
     pub fn origin(&self) -> &Point3D {
         &self.orig
     }
@@ -43,6 +85,14 @@ fn test_direction() {
     assert_eq!(*ray.direction(), direction);
 }
 
+#[test]
+fn test_cone() {
+    let ray = Ray::new(Point3D::default(), Vec3::new(0.0, 0.0, -2.0)).with_cone(0.5, 0.1);
+    assert_eq!(ray.cone_width(0.0), 0.5);
+    assert!((ray.cone_width(5.0) - 1.5).abs() < 1e-12);
+    assert_eq!(Ray::default().cone_width(100.0), 0.0);
+}
+
 #[test]
 fn test_at() {
     let origin = Point3D::new(1.0, 2.0, 3.0);
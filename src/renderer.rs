@@ -0,0 +1,313 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::aov::{self, object_id};
+use crate::camera::{Camera, PixelPasses, Progress};
+use crate::film::Film;
+use crate::guide::Guide;
+use crate::hittable::ObjectList;
+use crate::light_link::ObjectSet;
+use crate::post::PostEffect;
+use crate::scene::Scene;
+use crate::term::{TermGraphics, TerminalPreview};
+use crate::vec3::{Point3D, Vec3};
+
+// A camera together with the scene it looks at.
+pub struct Renderer {
+    pub camera: Camera,
+    pub scene: Scene,
+    pub post: Vec<PostEffect>,
+    // Shows the image in the terminal while it renders.
+    pub terminal_preview: Option<TermGraphics>,
+    // Identifies the scene file in the metadata of written images.
+    pub scene_hash: Option<u64>,
+    // Render layers by name, see `render_layers`.
+    pub layers: BTreeMap<String, ObjectSet>,
+}
+
+// How an image was produced, written into it as PNG text chunks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderMetadata {
+    pub scene_hash: Option<u64>,
+    pub seed: u64,
+    pub samples_per_pixel: usize,
+    pub width: usize,
+    pub height: usize,
+    // Unknown for streamed images, whose text comes before the pixels.
+    pub render_time: Option<Duration>,
+}
+
+impl RenderMetadata {
+    pub fn text(&self) -> Vec<(&'static str, String)> {
+        let mut text = vec![(
+            "Software",
+            format!("raytracer {}", env!("CARGO_PKG_VERSION")),
+        )];
+        if let Some(hash) = self.scene_hash {
+            text.push(("Scene hash", format!("{:016x}", hash)));
+        }
+        text.push(("Seed", self.seed.to_string()));
+        text.push(("Samples per pixel", self.samples_per_pixel.to_string()));
+        text.push(("Resolution", format!("{}x{}", self.width, self.height)));
+        if let Some(time) = self.render_time {
+            text.push(("Render time", format!("{:.3} s", time.as_secs_f64())));
+        }
+        text
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PickResult {
+    // Index into `scene.objects.objects`.
+    pub object: usize,
+    // The same ID the object_id AOV uses.
+    pub id: u32,
+    pub name: Option<String>,
+    pub point: Point3D,
+    // Faces the camera.
+    pub normal: Vec3,
+    // From the camera to `point`.
+    pub distance: f64,
+}
+
+#[cfg(feature = "json")]
+impl From<crate::config::Config> for Renderer {
+    fn from(config: crate::config::Config) -> Self {
+        let scene_hash = config.hash().ok();
+        let mut renderer = Renderer::new(config.camera, config.object_list);
+        renderer.post = config.post;
+        renderer.scene_hash = scene_hash;
+        renderer.layers = config.layers;
+        renderer
+    }
+}
+
+impl Renderer {
+    // Picks the meshes' levels of detail for the camera, and runs the
+    // training renders first if it uses path guiding.
+    pub fn new(camera: Camera, mut world: ObjectList) -> Renderer {
+        world.select_lods(camera.lookfrom());
+        if let Some(guiding) = camera.guiding {
+            Guide::train(&camera, &mut world, guiding);
+        }
+        Renderer {
+            camera,
+            scene: Scene::new(world),
+            post: Vec::new(),
+            terminal_preview: None,
+            scene_hash: None,
+            layers: BTreeMap::new(),
+        }
+    }
+
+    pub fn metadata(&self, render_time: Option<Duration>) -> RenderMetadata {
+        let (width, height) = self.camera.output_size();
+        RenderMetadata {
+            scene_hash: self.scene_hash,
+            seed: self.camera.seed,
+            samples_per_pixel: self.camera.samples_per_pixel(),
+            width,
+            height,
+            render_time,
+        }
+    }
+
+    // The finished image, without writing it anywhere.
+    pub fn render_film(&self) -> Film {
+        self.camera.render_film(&self.scene.objects, &self.post)
+    }
+
+    pub fn render_film_with_progress(&self, progress: &mut dyn FnMut(&Film, f64)) -> Film {
+        self.camera
+            .render_film_with_progress(&self.scene.objects, &self.post, progress)
+    }
+
+    pub fn render(&self, filename: &str) -> io::Result<()> {
+        self.render_and_save(filename).map(drop)
+    }
+
+    // Like `render`, also returning the film, e.g. to make a thumbnail of it.
+    pub fn render_and_save(&self, filename: &str) -> io::Result<Film> {
+        Ok(self.render_and_save_with(filename, None)?.0)
+    }
+
+    // Like `render`, also returning the passes the AOVs take from the same
+    // samples, with the denoising features if `features` is set, see
+    // `Camera::render_film_with_passes`.
+    pub fn render_with_passes(
+        &self,
+        filename: &str,
+        features: bool,
+    ) -> io::Result<Vec<PixelPasses>> {
+        Ok(self.render_and_save_with(filename, Some(features))?.1)
+    }
+
+    // The passes alone, for images that aren't kept whole, e.g. streamed
+    // ones. This renders the image again.
+    pub fn render_passes(&self, features: bool) -> io::Result<Vec<PixelPasses>> {
+        let world = &self.scene.objects;
+        let (_, passes) = self
+            .camera
+            .render_film_with_passes(world, &[], features, None)?;
+        Ok(passes)
+    }
+
+    // With passes, and their denoising features, if `passes` is given.
+    fn render_and_save_with(
+        &self,
+        filename: &str,
+        passes: Option<bool>,
+    ) -> io::Result<(Film, Vec<PixelPasses>)> {
+        let start = Instant::now();
+        let render = |progress: Option<&mut Progress>| {
+            let world = &self.scene.objects;
+            match (passes, progress) {
+                (Some(features), progress) => self
+                    .camera
+                    .render_film_with_passes(world, &self.post, features, progress),
+                (None, Some(progress)) => {
+                    Ok((self.render_film_with_progress(progress), Vec::new()))
+                }
+                (None, None) => Ok((self.render_film(), Vec::new())),
+            }
+        };
+        let (film, passes) = match self.terminal_preview {
+            None => render(None)?,
+            Some(graphics) => {
+                let mut preview = TerminalPreview::new(graphics);
+                let rendered = render(Some(&mut |film, _| preview.update(film)))?;
+                preview.draw(&rendered.0)?;
+                rendered
+            }
+        };
+        let text = self.metadata(Some(start.elapsed())).text();
+        film.save_with(filename, &text, self.camera.clip)?;
+        Ok((film, passes))
+    }
+
+    // Writes the image while it renders instead of keeping all of it in
+    // memory, without the terminal preview. See `Camera::render_streaming`.
+    pub fn render_streaming(&self, filename: &str) -> io::Result<()> {
+        self.camera.render_streaming(
+            filename,
+            &self.scene.objects,
+            &self.post,
+            &self.metadata(None).text(),
+        )
+    }
+
+    // Renders every layer on its own to a file named after it, e.g.
+    // out.characters.png, with the objects outside it as mattes: the camera
+    // sees them black, but they still cast shadows and show in reflections.
+    // Each layer also gets a mask of the pixels its objects cover, e.g.
+    // out.characters.alpha.png, to cut it out with when compositing.
+    pub fn render_layers(&mut self, filename: &str) -> io::Result<()> {
+        let output = Path::new(filename);
+        let extension = output
+            .extension()
+            .map_or("png".into(), |e| e.to_string_lossy());
+        for (name, layer) in &self.layers {
+            let _span = tracing::info_span!("layer", name).entered();
+            self.scene.objects.isolate(layer);
+            let layer_output = output.with_extension(format!("{}.{}", name, extension));
+            self.render(&layer_output.to_string_lossy())?;
+            aov::write_alpha(&self.camera, &self.scene.objects, &layer_output)?;
+        }
+        self.scene.objects.isolate(&ObjectSet::default());
+        Ok(())
+    }
+
+    // Traces a single ray through the center of pixel (x, y) and reports the
+    // closest object it hits, e.g. for click selection in an editor.
+    pub fn pick(&self, x: usize, y: usize) -> Option<PickResult> {
+        if x >= self.camera.width() || y >= self.camera.height() {
+            return None;
+        }
+
+        let r = self.camera.pixel_ray(x, y);
+        let rec = self.scene.intersect(&r)?;
+
+        let object = &self.scene.objects.objects[rec.object];
+        Some(PickResult {
+            object: rec.object,
+            id: object_id(object, rec.object),
+            name: object.name().map(str::to_string),
+            point: rec.p,
+            normal: rec.normal,
+            distance: rec.t * r.direction().length(),
+        })
+    }
+}
+
+#[test]
+fn test_pick() {
+    let mut world = ObjectList::new();
+    world.add(
+        crate::hittable::Object::from(crate::sphere::Sphere::new(
+            Point3D::new(0.0, 0.0, -5.0),
+            1.0,
+            crate::material::Material::Named("x".to_string()),
+        ))
+        .named("ball"),
+    );
+    let camera = Camera::new(
+        21,
+        21,
+        1,
+        2,
+        90.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let renderer = Renderer::new(camera, world);
+
+    let pick = renderer.pick(10, 10).unwrap();
+    assert_eq!(pick.object, 0);
+    assert_eq!(pick.name.as_deref(), Some("ball"));
+    assert!((pick.distance - 4.0).abs() < 1e-9);
+    assert!((pick.normal.z() - 1.0).abs() < 1e-9);
+    assert_eq!(pick.id, object_id(&renderer.scene.objects.objects[0], 0));
+
+    assert!(renderer.pick(0, 0).is_none());
+    assert!(renderer.pick(21, 10).is_none());
+}
+
+#[cfg(all(feature = "json", feature = "image"))]
+#[test]
+fn test_metadata() {
+    let json = r#"{
+        "camera": {
+            "height": 4, "width": 6, "samples_per_pixel": 3, "max_depth": 2, "vfov": 40.0,
+            "lookfrom": [0.0, 0.0, 0.0], "lookat": [0.0, 0.0, -1.0], "vup": [0.0, 1.0, 0.0],
+            "defocus_angle": 0.0, "focus_dist": 1.0, "seed": 7
+        },
+        "object_list": {"objects": []}
+    }"#;
+    let config = crate::config::Config::from_json(json).unwrap();
+    let hash = config.hash().unwrap();
+    let renderer = Renderer::from(config);
+    assert_eq!(renderer.scene_hash, Some(hash));
+
+    let path = std::env::temp_dir().join(format!("raytracer-metadata-{}.png", std::process::id()));
+    let filename = path.to_str().unwrap();
+    renderer.render(filename).unwrap();
+    let text = crate::film::read_text(filename).unwrap();
+    std::fs::remove_file(filename).unwrap();
+
+    let get = |keyword: &str| {
+        text.iter()
+            .find(|(k, _)| k == keyword)
+            .map(|(_, v)| v.as_str())
+    };
+    assert_eq!(get("Scene hash"), Some(format!("{:016x}", hash).as_str()));
+    assert_eq!(get("Seed"), Some("7"));
+    assert_eq!(get("Samples per pixel"), Some("3"));
+    assert_eq!(get("Resolution"), Some("6x4"));
+    assert!(get("Render time").is_some_and(|t| t.ends_with(" s")));
+    assert!(get("Software").is_some_and(|s| s.starts_with("raytracer ")));
+}
@@ -0,0 +1,95 @@
+// The random numbers used while rendering. Every thread has its own
+// generator, which the camera reseeds from (seed, x, y, sample) before each
+// camera sample, so an image comes out bit-identical however its pixels are
+// spread over threads. Outside of that the generator starts from entropy,
+// like `rand::thread_rng`.
+//
+// SplitMix64 is used for its single word of state, which makes reseeding for
+// every sample cheap, and because it gives the same numbers on every platform.
+
+use std::cell::RefCell;
+
+use rand::{Rng, RngCore};
+
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> SplitMix64 {
+        SplitMix64 { state: seed }
+    }
+}
+
+// The SplitMix64 output function, also used to hash the seed inputs.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+impl RngCore for SplitMix64 {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        mix(self.state)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+thread_local! {
+    static RNG: RefCell<SplitMix64> = RefCell::new(SplitMix64::new(rand::random()));
+}
+
+// Restarts this thread's generator for one camera sample of pixel (x, y).
+pub fn seed_sample(seed: u64, x: usize, y: usize, sample: usize) {
+    let state = [x, y, sample]
+        .iter()
+        .fold(mix(seed), |h, &v| mix(h ^ v as u64));
+    RNG.with(|rng| rng.borrow_mut().state = state);
+}
+
+pub fn with_rng<T>(f: impl FnOnce(&mut SplitMix64) -> T) -> T {
+    RNG.with(|rng| f(&mut rng.borrow_mut()))
+}
+
+// Runs `f` and then puts this thread's generator back where it was, so
+// whatever `f` draws doesn't change the numbers after it.
+pub fn preserving<T>(f: impl FnOnce() -> T) -> T {
+    let state = with_rng(|rng| rng.state);
+    let result = f();
+    with_rng(|rng| rng.state = state);
+    result
+}
+
+// Uniform in [0, 1).
+pub fn random() -> f64 {
+    with_rng(|rng| rng.gen())
+}
+
+#[test]
+fn test_seed_sample() {
+    let numbers = |seed, x, y, sample| {
+        seed_sample(seed, x, y, sample);
+        [random(), random(), random()]
+    };
+    assert_eq!(numbers(1, 2, 3, 4), numbers(1, 2, 3, 4));
+    assert_ne!(numbers(1, 2, 3, 4), numbers(2, 2, 3, 4));
+    assert_ne!(numbers(1, 2, 3, 4), numbers(1, 3, 2, 4));
+    assert_ne!(numbers(1, 2, 3, 4), numbers(1, 2, 3, 5));
+    assert!(numbers(0, 0, 0, 0).iter().all(|v| (0.0..1.0).contains(v)));
+}
@@ -0,0 +1,99 @@
+use crate::hittable::{HitRecord, Hittable, ObjectList};
+use crate::interval::Interval;
+use crate::ray::{Ray, RayKind};
+use crate::vec3::Point3D;
+
+// Rays start this far from their origin so surfaces don't hit themselves.
+const EPSILON: f64 = 0.001;
+
+// Ray queries against the objects of a scene, usable without rendering, e.g.
+// for visibility checks, baking or collision tests. Like a render, a query
+// tests every top-level object in turn; only meshes inside it have a BVH.
+pub struct Scene {
+    pub objects: ObjectList,
+}
+
+impl From<ObjectList> for Scene {
+    fn from(objects: ObjectList) -> Self {
+        Scene::new(objects)
+    }
+}
+
+impl Scene {
+    pub fn new(objects: ObjectList) -> Scene {
+        Scene { objects }
+    }
+
+    // The closest hit along `r`, honoring the objects' visibility flags for the
+    // ray's kind.
+    pub fn intersect(&self, r: &Ray) -> Option<HitRecord> {
+        let mut rec = HitRecord::default();
+        self.objects
+            .hit(r, &Interval::new(EPSILON, f64::INFINITY), &mut rec)
+            .then_some(rec)
+    }
+
+    // Whether anything lies between `a` and `b`, leaving out `EPSILON` at
+    // either end for the surfaces they lie on. The query is a diffuse ray, so
+    // objects with `casts_shadows: false` never block it.
+    pub fn occluded(&self, a: Point3D, b: Point3D) -> bool {
+        let distance = (b - a).length();
+        if distance <= 2.0 * EPSILON {
+            return false;
+        }
+        let r = Ray::new(a, (b - a) / distance).with_kind(RayKind::Diffuse);
+        self.objects
+            .occluded(&r, &Interval::new(EPSILON, distance - EPSILON))
+    }
+}
+
+#[test]
+fn test_queries() {
+    let mut objects = ObjectList::new();
+    objects.add_sphere(
+        Point3D::new(0.0, 0.0, 0.0),
+        1.0,
+        crate::material::Material::Named("x".to_string()),
+    );
+    let mut scene = Scene::new(objects);
+
+    let r = Ray::new(
+        Point3D::new(0.0, 0.0, 5.0),
+        crate::vec3::Vec3::new(0.0, 0.0, -1.0),
+    );
+    let rec = scene.intersect(&r).unwrap();
+    assert!((rec.t - 4.0).abs() < 1e-9);
+    assert!(scene
+        .intersect(&Ray::new(
+            Point3D::new(0.0, 3.0, 5.0),
+            crate::vec3::Vec3::new(0.0, 0.0, -1.0)
+        ))
+        .is_none());
+
+    let (a, b) = (Point3D::new(0.0, 0.0, 5.0), Point3D::new(0.0, 0.0, -5.0));
+    assert!(scene.occluded(a, b));
+    assert!(!scene.occluded(a, Point3D::new(0.0, 0.0, 2.0)));
+    assert!(!scene.occluded(Point3D::new(0.0, 3.0, 5.0), Point3D::new(0.0, 3.0, -5.0)));
+
+    // The end points' surfaces don't block a short segment leaving them.
+    assert!(!scene.occluded(Point3D::new(0.0, 0.0, 1.0), Point3D::new(0.0, 0.0, 1.01)));
+
+    scene.objects.objects[0].attributes.casts_shadows = false;
+    assert!(!scene.occluded(a, b));
+}
+
+#[test]
+fn test_occluded_long_segment() {
+    // The offset at the ends is a distance, so a long segment is still
+    // blocked right next to its start.
+    let mut objects = ObjectList::new();
+    objects.add_sphere(
+        Point3D::new(0.0, 0.0, -0.5),
+        0.1,
+        crate::material::Material::Named("x".to_string()),
+    );
+    let scene = Scene::new(objects);
+    let a = Point3D::new(0.0, 0.0, 0.0);
+    assert!(scene.occluded(a, Point3D::new(0.0, 0.0, -1000.0)));
+    assert!(scene.occluded(Point3D::new(0.0, 0.0, -1000.0), a));
+}
@@ -0,0 +1,256 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::config::Config;
+use crate::hittable::{Object, ObjectList};
+use crate::material::{Glass, Lambertian, Material, Metal};
+use crate::sphere::Sphere;
+use crate::vec3::{Point3D, Vec3};
+
+pub const GENERATORS: &[&str] = &["random_spheres"];
+
+pub fn generate(name: &str, seed: u64) -> Option<Config> {
+    match name {
+        "random_spheres" => Some(random_spheres(seed)),
+        _ => None,
+    }
+}
+
+// Procedural content that can be listed in a scene file and is expanded into
+// regular objects when the scene is loaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Generator {
+    RandomSpheres(RandomSpheres),
+}
+
+impl Generator {
+    pub fn generate(&self) -> Vec<Object> {
+        match self {
+            Generator::RandomSpheres(g) => g.generate(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MaterialWeights {
+    pub diffuse: f64,
+    pub metal: f64,
+    pub glass: f64,
+}
+
+impl Default for MaterialWeights {
+    fn default() -> Self {
+        MaterialWeights {
+            diffuse: 0.8,
+            metal: 0.15,
+            glass: 0.05,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RandomSpheres {
+    pub count: usize,
+    pub seed: u64,
+    // Small spheres are scattered over the square [-area, area] on the ground.
+    pub area: f64,
+    pub radius: f64,
+    pub material_weights: MaterialWeights,
+    // Adds the ground and the three large spheres of the book cover.
+    pub feature_spheres: bool,
+}
+
+impl Default for RandomSpheres {
+    fn default() -> Self {
+        RandomSpheres {
+            count: 484,
+            seed: 0,
+            area: 11.0,
+            radius: 0.2,
+            material_weights: MaterialWeights::default(),
+            feature_spheres: true,
+        }
+    }
+}
+
+fn random_color(rng: &mut StdRng, min: f64, max: f64) -> Color {
+    Color::new(
+        rng.gen_range(min..max),
+        rng.gen_range(min..max),
+        rng.gen_range(min..max),
+    )
+}
+
+impl RandomSpheres {
+    fn random_material(&self, rng: &mut StdRng) -> Material {
+        let weights = self.material_weights;
+        let total = weights.diffuse + weights.metal + weights.glass;
+        let choose_mat = rng.gen::<f64>() * total;
+
+        if choose_mat < weights.diffuse {
+            let albedo = random_color(rng, 0.0, 1.0) * random_color(rng, 0.0, 1.0);
+            Material::Lambertian(Lambertian::new(albedo))
+        } else if choose_mat < weights.diffuse + weights.metal {
+            let albedo = random_color(rng, 0.5, 1.0);
+            let fuzz = rng.gen_range(0.0..0.5);
+            Material::Metal(Metal::new(albedo, fuzz))
+        } else {
+            Material::Glass(Glass::new(1.5))
+        }
+    }
+
+    pub fn generate(&self) -> Vec<Object> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut objects = Vec::new();
+
+        if self.feature_spheres {
+            objects.push(Object::from(Sphere::new(
+                Point3D::new(0.0, -1000.0, 0.0),
+                1000.0,
+                Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+            )));
+        }
+
+        // The spheres are jittered inside the cells of a square grid, keeping
+        // a radius away from the cell's sides, so they don't overlap each
+        // other as long as a cell is at least a diameter wide.
+        let cells = (self.count as f64).sqrt().ceil() as usize;
+        let cell_size = 2.0 * self.area / cells.max(1) as f64;
+        let margin = self.radius.min(cell_size / 2.0);
+        let jitter = |cell: usize, rng: &mut StdRng| {
+            -self.area
+                + cell as f64 * cell_size
+                + margin
+                + (cell_size - 2.0 * margin) * rng.gen::<f64>()
+        };
+        for cell in 0..self.count {
+            let (a, b) = (cell / cells, cell % cells);
+            let center = Point3D::new(jitter(a, &mut rng), self.radius, jitter(b, &mut rng));
+
+            if self.feature_spheres
+                && [-4.0, 0.0, 4.0]
+                    .iter()
+                    .any(|x| (center - Point3D::new(*x, self.radius, 0.0)).length() <= 0.9)
+            {
+                continue;
+            }
+
+            let material = self.random_material(&mut rng);
+            objects.push(Object::from(Sphere::new(center, self.radius, material)));
+        }
+
+        if self.feature_spheres {
+            objects.push(Object::from(Sphere::new(
+                Point3D::new(0.0, 1.0, 0.0),
+                1.0,
+                Material::Glass(Glass::new(1.5)),
+            )));
+            objects.push(Object::from(Sphere::new(
+                Point3D::new(-4.0, 1.0, 0.0),
+                1.0,
+                Material::Lambertian(Lambertian::new(Color::new(0.4, 0.2, 0.1))),
+            )));
+            objects.push(Object::from(Sphere::new(
+                Point3D::new(4.0, 1.0, 0.0),
+                1.0,
+                Material::Metal(Metal::new(Color::new(0.7, 0.6, 0.5), 0.0)),
+            )));
+        }
+
+        objects
+    }
+}
+
+// The cover scene of "Ray Tracing in One Weekend".
+pub fn random_spheres(seed: u64) -> Config {
+    let generator = RandomSpheres {
+        seed,
+        ..RandomSpheres::default()
+    };
+    let world = ObjectList::with_objects(generator.generate());
+
+    let camera = Camera::new(
+        675,
+        1200,
+        64,
+        50,
+        20.0,
+        Point3D::new(13.0, 2.0, 3.0),
+        Point3D::new(0.0, 0.0, 0.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.6,
+        10.0,
+    );
+
+    Config {
+        version: crate::migration::CURRENT_VERSION,
+        camera,
+        cameras: Default::default(),
+        object_list: world,
+        materials: Default::default(),
+        generators: Vec::new(),
+        post: Vec::new(),
+        texture_cache: None,
+        layers: Default::default(),
+    }
+}
+
+#[test]
+fn test_random_spheres_is_deterministic() {
+    let generator = RandomSpheres {
+        seed: 42,
+        ..RandomSpheres::default()
+    };
+    let a = serde_json::to_string(&generator.generate()).unwrap();
+    let b = serde_json::to_string(&generator.generate()).unwrap();
+    assert_eq!(a, b);
+}
+
+#[test]
+fn test_random_spheres_count() {
+    let generator = RandomSpheres {
+        count: 10,
+        feature_spheres: false,
+        ..RandomSpheres::default()
+    };
+    let objects = generator.generate();
+    assert_eq!(objects.len(), 10);
+
+    // Neighbouring cells never put their spheres into each other.
+    let centers: Vec<Point3D> = objects
+        .iter()
+        .map(|object| match &object.shape {
+            crate::hittable::Shape::Sphere(sphere) => sphere.center,
+            other => panic!("expected a sphere, found {:?}", other),
+        })
+        .collect();
+    for (i, a) in centers.iter().enumerate() {
+        for b in &centers[i + 1..] {
+            assert!((*a - *b).length() >= 2.0 * generator.radius);
+        }
+    }
+}
+
+#[test]
+fn test_material_weights() {
+    let generator = RandomSpheres {
+        count: 50,
+        feature_spheres: false,
+        material_weights: MaterialWeights {
+            diffuse: 0.0,
+            metal: 0.0,
+            glass: 1.0,
+        },
+        ..RandomSpheres::default()
+    };
+    assert!(generator
+        .generate()
+        .iter()
+        .all(|object| matches!(object.material(), Material::Glass(_))));
+}
@@ -0,0 +1,143 @@
+// Scene scripts written in Rhai. A script builds objects programmatically and
+// runs once while the scene is loaded. Available functions:
+//
+//   lambertian(r, g, b)      metal(r, g, b, fuzz)      glass(refraction_index)
+//   light(r, g, b)           named("palette entry")
+//   sphere(x, y, z, radius, material)
+//   seed(n)  random()  random(min, max)
+//
+// Numbers may be written as integers or floats. Scripts are cut off after
+// MAX_OPERATIONS steps, so a runaway loop fails the load instead of hanging it.
+
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rhai::{Dynamic, Engine, EvalAltResult};
+
+use crate::color::Color;
+use crate::hittable::Object;
+use crate::material::{DiffuseLight, Glass, Lambertian, Material, Metal};
+use crate::sphere::Sphere;
+use crate::vec3::Point3D;
+
+const MAX_OPERATIONS: u64 = 10_000_000;
+const MAX_CALL_LEVELS: usize = 64;
+const MAX_ARRAY_SIZE: usize = 1_000_000;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+fn num(value: Dynamic) -> ScriptResult<f64> {
+    if let Ok(f) = value.as_float() {
+        Ok(f)
+    } else if let Ok(i) = value.as_int() {
+        Ok(i as f64)
+    } else {
+        Err(format!("expected a number, found {}", value.type_name()).into())
+    }
+}
+
+fn color(r: Dynamic, g: Dynamic, b: Dynamic) -> ScriptResult<Color> {
+    Ok(Color::new(num(r)?, num(g)?, num(b)?))
+}
+
+pub fn run(source: &str) -> io::Result<Vec<Object>> {
+    let objects = Rc::new(RefCell::new(Vec::new()));
+    let rng = Rc::new(RefCell::new(StdRng::seed_from_u64(0)));
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(MAX_CALL_LEVELS)
+        .set_max_array_size(MAX_ARRAY_SIZE);
+
+    engine
+        .register_type_with_name::<Material>("Material")
+        .register_fn("lambertian", |r: Dynamic, g: Dynamic, b: Dynamic| {
+            Ok(Material::Lambertian(Lambertian::new(color(r, g, b)?))) as ScriptResult<Material>
+        })
+        .register_fn(
+            "metal",
+            |r: Dynamic, g: Dynamic, b: Dynamic, fuzz: Dynamic| {
+                Ok(Material::Metal(Metal::new(color(r, g, b)?, num(fuzz)?)))
+                    as ScriptResult<Material>
+            },
+        )
+        .register_fn("glass", |refraction_index: Dynamic| {
+            Ok(Material::Glass(Glass::new(num(refraction_index)?))) as ScriptResult<Material>
+        })
+        .register_fn("light", |r: Dynamic, g: Dynamic, b: Dynamic| {
+            Ok(Material::DiffuseLight(DiffuseLight::new(color(r, g, b)?))) as ScriptResult<Material>
+        })
+        .register_fn("named", |name: &str| Material::Named(name.to_string()));
+
+    let sink = objects.clone();
+    engine.register_fn(
+        "sphere",
+        move |x: Dynamic, y: Dynamic, z: Dynamic, radius: Dynamic, material: Material| {
+            let center = Point3D::new(num(x)?, num(y)?, num(z)?);
+            sink.borrow_mut()
+                .push(Object::from(Sphere::new(center, num(radius)?, material)));
+            Ok(()) as ScriptResult<()>
+        },
+    );
+
+    let r = rng.clone();
+    engine.register_fn("seed", move |seed: i64| {
+        *r.borrow_mut() = StdRng::seed_from_u64(seed as u64);
+    });
+    let r = rng.clone();
+    engine.register_fn("random", move || r.borrow_mut().gen::<f64>());
+    let r = rng.clone();
+    engine.register_fn("random", move |min: Dynamic, max: Dynamic| {
+        let (min, max) = (num(min)?, num(max)?);
+        Ok(min + (max - min) * r.borrow_mut().gen::<f64>()) as ScriptResult<f64>
+    });
+
+    engine
+        .run(source)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("script error: {}", e)))?;
+
+    Ok(objects.take())
+}
+
+#[test]
+fn test_run() {
+    let objects = run(r#"
+        seed(1);
+        for i in 0..5 {
+            sphere(i, 0.5, random(-1, 1), 0.5, lambertian(random(), 0.5, 0.5));
+        }
+        sphere(0, -100.5, 0, 100, metal(0.8, 0.8, 0.8, 0.1));
+        sphere(0, 3, 0, 1, named("lamp"));
+    "#)
+    .unwrap();
+
+    assert_eq!(objects.len(), 7);
+    match &objects[3].shape {
+        crate::hittable::Shape::Sphere(sphere) => {
+            assert_eq!(sphere.center.x(), 3.0);
+            assert!(matches!(sphere.material, Material::Lambertian(_)));
+        }
+        other => panic!("expected a sphere, found {:?}", other),
+    }
+    match &objects[6].shape {
+        crate::hittable::Shape::Sphere(sphere) => {
+            assert!(matches!(&sphere.material, Material::Named(n) if n == "lamp"))
+        }
+        other => panic!("expected a sphere, found {:?}", other),
+    }
+}
+
+#[test]
+fn test_run_error() {
+    assert!(run("sphere(0, 0, 0, \"big\", glass(1.5));").is_err());
+}
+
+#[test]
+fn test_run_limits() {
+    let err = run("loop {}").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(run("fn f(n) { f(n + 1) } f(0);").is_err());
+}
@@ -0,0 +1,494 @@
+// Shapes given by a signed distance function: how far a point is from the
+// surface, negative inside. Rays are sphere traced: from where the ray enters
+// the shape's bounding box, they step forward by the distance to the surface,
+// which can't step past it, until they are close enough to count as a hit or
+// leave the box. Where the function only estimates the distance, steps are
+// shortened, and a step that ends up on the other side of the surface is
+// bisected back to it. Normals are the function's gradient, by central
+// differences.
+
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::vec3::{Point3D, Vec3};
+
+const MAX_STEPS: usize = 512;
+
+fn default_power() -> f64 {
+    8.0
+}
+
+fn default_iterations() -> usize {
+    12
+}
+
+fn default_bailout() -> f64 {
+    2.0
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SdfShape {
+    // A box `half_size` out from the center in each direction, with its
+    // edges and corners rounded off by `radius`.
+    RoundedBox {
+        half_size: Vec3,
+        radius: f64,
+    },
+    // Barr's superellipsoid, `radii` out along the axes. The `exponents`
+    // shape its profile from pole to pole along y and its cross-section
+    // around y: 1 is round, towards 0 square and 2 pinched to an edge.
+    Superellipsoid {
+        radii: Vec3,
+        exponents: (f64, f64),
+    },
+    // The Mandelbulb, `scale` times its usual size of about 1.2 across:
+    // points p where z ↦ z^power + p, raising to a power in spherical
+    // coordinates, doesn't get past `bailout` in `iterations` steps. More
+    // iterations show more detail.
+    Mandelbulb {
+        #[serde(default = "default_power")]
+        power: f64,
+        #[serde(default = "default_iterations")]
+        iterations: usize,
+        #[serde(default = "default_bailout")]
+        bailout: f64,
+        #[serde(default = "default_scale")]
+        scale: f64,
+    },
+    // A quaternion Julia set, z ↦ z² + c with z = (x, y, z, slice), cut
+    // through 4D space at `slice`. The first of `c` is the real part.
+    Julia {
+        c: [f64; 4],
+        #[serde(default)]
+        slice: f64,
+        #[serde(default = "default_iterations")]
+        iterations: usize,
+        #[serde(default = "default_bailout")]
+        bailout: f64,
+        #[serde(default = "default_scale")]
+        scale: f64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sdf {
+    pub center: Point3D,
+    pub shape: SdfShape,
+    pub material: Material,
+}
+
+fn abs(v: Vec3) -> Vec3 {
+    Vec3::new(v.x().abs(), v.y().abs(), v.z().abs())
+}
+
+fn max_component(v: Vec3) -> f64 {
+    v.x().max(v.y()).max(v.z())
+}
+
+fn min_component(v: Vec3) -> f64 {
+    v.x().min(v.y()).min(v.z())
+}
+
+impl SdfShape {
+    // Relative to the center.
+    pub fn distance(&self, p: Point3D) -> f64 {
+        match self {
+            SdfShape::RoundedBox { half_size, radius } => {
+                let q = abs(p) - (*half_size - Vec3::new(*radius, *radius, *radius));
+                let outside = Vec3::new(q.x().max(0.0), q.y().max(0.0), q.z().max(0.0));
+                outside.length() + max_component(q).min(0.0) - radius
+            }
+            SdfShape::Superellipsoid {
+                radii,
+                exponents: (north_south, east_west),
+            } => {
+                // In the unit shape, `g` grows like the distance from the
+                // center, and is 1 on the surface: the point is `g` times as
+                // far out as the surface in its direction. That distance
+                // along the radius only estimates the true one.
+                let u = Vec3::new(p.x() / radii.x(), p.y() / radii.y(), p.z() / radii.z());
+                let power = |x: f64, e: f64| x.abs().powf(2.0 / e);
+                let around = power(u.x(), *east_west) + power(u.z(), *east_west);
+                let f = around.powf(east_west / north_south) + power(u.y(), *north_south);
+                let g = f.powf(north_south / 2.0);
+                if g == 0.0 {
+                    return -min_component(*radii);
+                }
+                u.length() * (1.0 - 1.0 / g) * min_component(*radii)
+            }
+            // Both fractals estimate the distance from how fast the
+            // iteration's derivative grows, 0.5 |z| ln |z| / |dz|.
+            SdfShape::Mandelbulb {
+                power,
+                iterations,
+                bailout,
+                scale,
+            } => {
+                let c = p / *scale;
+                let mut z = c;
+                let (mut r, mut dr) = (z.length(), 1.0);
+                for _ in 0..*iterations {
+                    if r > *bailout {
+                        break;
+                    }
+                    dr = r.powf(power - 1.0) * power * dr + 1.0;
+                    let theta = (z.z() / r).clamp(-1.0, 1.0).acos() * power;
+                    let phi = z.y().atan2(z.x()) * power;
+                    z = r.powf(*power)
+                        * Vec3::new(
+                            theta.sin() * phi.cos(),
+                            theta.sin() * phi.sin(),
+                            theta.cos(),
+                        )
+                        + c;
+                    r = z.length();
+                }
+                0.5 * r.ln() * r / dr * scale
+            }
+            SdfShape::Julia {
+                c,
+                slice,
+                iterations,
+                bailout,
+                scale,
+            } => {
+                let mut z = [p.x() / scale, p.y() / scale, p.z() / scale, *slice];
+                let length = |z: &[f64; 4]| z.iter().map(|x| x * x).sum::<f64>().sqrt();
+                let (mut r, mut dr) = (length(&z), 1.0);
+                for _ in 0..*iterations {
+                    if r > *bailout {
+                        break;
+                    }
+                    // |2 z dz| = 2 |z| |dz| for quaternions.
+                    dr *= 2.0 * r;
+                    let [a, b, cz, d] = z;
+                    z = [
+                        a * a - b * b - cz * cz - d * d + c[0],
+                        2.0 * a * b + c[1],
+                        2.0 * a * cz + c[2],
+                        2.0 * a * d + c[3],
+                    ];
+                    r = length(&z);
+                }
+                0.5 * r.ln() * r / dr * scale
+            }
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let positive = |v: &Vec3| min_component(*v) > 0.0;
+        match self {
+            SdfShape::RoundedBox { half_size, radius } => {
+                positive(half_size) && (0.0..=min_component(*half_size)).contains(radius)
+            }
+            SdfShape::Superellipsoid { radii, exponents } => {
+                positive(radii) && exponents.0 > 0.0 && exponents.1 > 0.0
+            }
+            SdfShape::Mandelbulb {
+                power,
+                iterations,
+                bailout,
+                scale,
+            } => *power > 1.0 && *iterations > 0 && *bailout > 0.0 && *scale > 0.0,
+            SdfShape::Julia {
+                iterations,
+                bailout,
+                scale,
+                ..
+            } => *iterations > 0 && *bailout > 0.0 && *scale > 0.0,
+        }
+    }
+
+    // How much of the distance is safe to step.
+    fn step_scale(&self) -> f64 {
+        match self {
+            SdfShape::RoundedBox { .. } => 1.0,
+            SdfShape::Superellipsoid { .. } => 0.5,
+            SdfShape::Mandelbulb { .. } | SdfShape::Julia { .. } => 0.9,
+        }
+    }
+
+    // Points this close to the surface are on it, relative to the size.
+    // Fractals have detail at every scale; a coarser limit keeps rays from
+    // creeping along them forever.
+    fn precision(&self) -> f64 {
+        match self {
+            SdfShape::RoundedBox { .. } | SdfShape::Superellipsoid { .. } => 1e-6,
+            SdfShape::Mandelbulb { .. } | SdfShape::Julia { .. } => 1e-4,
+        }
+    }
+
+    // Relative to the center.
+    fn bounds(&self) -> Vec3 {
+        match self {
+            SdfShape::RoundedBox { half_size, .. } => *half_size,
+            SdfShape::Superellipsoid { radii, .. } => *radii,
+            // Everything further out escapes, the Mandelbulb at powers of 8
+            // and up from about 1.2.
+            SdfShape::Mandelbulb { power, scale, .. } => {
+                let radius = if *power >= 8.0 { 1.25 } else { 2.0 };
+                Vec3::new(1.0, 1.0, 1.0) * radius * *scale
+            }
+            SdfShape::Julia { c, scale, .. } => {
+                let c = c.iter().map(|x| x * x).sum::<f64>().sqrt();
+                let radius = 0.5 * (1.0 + (1.0 + 4.0 * c).sqrt());
+                Vec3::new(1.0, 1.0, 1.0) * radius * *scale
+            }
+        }
+    }
+}
+
+impl Sdf {
+    pub fn new(center: Point3D, shape: SdfShape, material: Material) -> Sdf {
+        Sdf {
+            center,
+            shape,
+            material,
+        }
+    }
+
+    pub fn distance(&self, p: Point3D) -> f64 {
+        self.shape.distance(p - self.center)
+    }
+
+    fn epsilon(&self) -> f64 {
+        self.shape.precision() * self.shape.bounds().length()
+    }
+
+    fn normal(&self, p: Point3D) -> Vec3 {
+        let h = 10.0 * self.epsilon();
+        let gradient = |axis: Vec3| self.distance(p + h * axis) - self.distance(p - h * axis);
+        Vec3::new(
+            gradient(Vec3::new(1.0, 0.0, 0.0)),
+            gradient(Vec3::new(0.0, 1.0, 0.0)),
+            gradient(Vec3::new(0.0, 0.0, 1.0)),
+        )
+        .unit_vector()
+    }
+
+    // The first point along the ray within `ray_t` where the distance
+    // crosses zero.
+    fn trace(&self, r: &Ray, ray_t: &Interval) -> Option<f64> {
+        let ray_t = self.bounding_box().pad(self.epsilon()).clip(r, ray_t)?;
+        let speed = r.direction().length();
+        let epsilon = self.epsilon();
+        let scale = self.shape.step_scale();
+
+        let mut t = ray_t.min;
+        let mut distance = self.distance(r.at(t));
+        // Rays starting inside step on the negated distance.
+        let side = distance.signum();
+        for _ in 0..MAX_STEPS {
+            let step = (side * distance * scale).max(epsilon) / speed;
+            let next = t + step;
+            if next > ray_t.max {
+                return None;
+            }
+            let next_distance = self.distance(r.at(next));
+            if side * next_distance < 0.0 {
+                // Stepped through the surface: bisect back to it.
+                let (mut inside, mut outside) = (next, t);
+                for _ in 0..64 {
+                    let middle = 0.5 * (inside + outside);
+                    if side * self.distance(r.at(middle)) < 0.0 {
+                        inside = middle;
+                    } else {
+                        outside = middle;
+                    }
+                    if (inside - outside) * speed < epsilon {
+                        break;
+                    }
+                }
+                return Some(outside);
+            }
+            (t, distance) = (next, next_distance);
+            if distance.abs() < epsilon {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+impl Hittable for Sdf {
+    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+        let Some(t) = self.trace(r, ray_t) else {
+            return false;
+        };
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.set_face_normal(r, self.normal(rec.p));
+        // No texture coordinates.
+        rec.u = 0.0;
+        rec.v = 0.0;
+        rec.dpdu = Vec3::default();
+        rec.dpdv = Vec3::default();
+        rec.mat = self.material.clone();
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let bounds = self.shape.bounds();
+        Aabb::from_points(self.center - bounds, self.center + bounds)
+    }
+}
+
+#[cfg(test)]
+fn hit_at(sdf: &Sdf, origin: Point3D, direction: Vec3) -> Option<HitRecord> {
+    let mut rec = HitRecord::default();
+    sdf.hit(
+        &Ray::new(origin, direction),
+        &Interval::new(0.001, f64::INFINITY),
+        &mut rec,
+    )
+    .then_some(rec)
+}
+
+#[test]
+fn test_rounded_box() {
+    let sdf = Sdf::new(
+        Point3D::new(0.0, 1.0, 0.0),
+        SdfShape::RoundedBox {
+            half_size: Vec3::new(2.0, 1.0, 1.0),
+            radius: 0.25,
+        },
+        Material::Named("x".to_string()),
+    );
+    assert!((sdf.distance(Point3D::new(3.0, 1.0, 0.0)) - 1.0).abs() < 1e-12);
+    assert!((sdf.distance(Point3D::new(0.0, 1.0, 0.0)) + 1.0).abs() < 1e-12);
+
+    // Flat faces are where the box's are, with a ray that isn't unit length.
+    let rec = hit_at(&sdf, Point3D::new(5.0, 1.0, 0.0), Vec3::new(-2.0, 0.0, 0.0)).unwrap();
+    assert!((rec.t - 1.5).abs() < 1e-5);
+    assert!((rec.normal - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-4);
+    // Straight at a corner, the rounding is hit sqrt(3) r - r short of it.
+    let corner = Point3D::new(2.0, 2.0, 1.0);
+    let direction = Vec3::new(-1.0, -1.0, -1.0).unit_vector();
+    let rec = hit_at(&sdf, corner - 5.0 * direction, direction).unwrap();
+    let expected = 5.0 + (3.0_f64.sqrt() - 1.0) * 0.25;
+    assert!((rec.t - expected).abs() < 1e-5, "{}", rec.t);
+    assert!((rec.normal + direction).length() < 1e-4);
+    // Just past the rounded-off corner, where a sharp box would be hit.
+    let miss = Point3D::new(1.99, 1.99, 5.0);
+    assert!(hit_at(&sdf, miss, Vec3::new(0.0, 0.0, -1.0)).is_none());
+    // From the inside, the far side.
+    let rec = hit_at(&sdf, Point3D::new(0.0, 1.0, 0.0), Vec3::new(0.0, 0.0, 1.0)).unwrap();
+    assert!((rec.t - 1.0).abs() < 1e-5 && !rec.front_face);
+}
+
+#[test]
+fn test_superellipsoid() {
+    let shape = |exponents| {
+        Sdf::new(
+            Point3D::default(),
+            SdfShape::Superellipsoid {
+                radii: Vec3::new(2.0, 1.0, 1.0),
+                exponents,
+            },
+            Material::Named("x".to_string()),
+        )
+    };
+    // With exponents of 1 it is an ellipsoid.
+    let ellipsoid = shape((1.0, 1.0));
+    let rec = hit_at(
+        &ellipsoid,
+        Point3D::new(5.0, 0.0, 0.0),
+        Vec3::new(-1.0, 0.0, 0.0),
+    )
+    .unwrap();
+    assert!((rec.t - 3.0).abs() < 1e-5);
+    let rec = hit_at(
+        &ellipsoid,
+        Point3D::new(0.0, 5.0, 0.0),
+        Vec3::new(0.0, -1.0, 0.0),
+    )
+    .unwrap();
+    assert!((rec.t - 4.0).abs() < 1e-5);
+    assert!((rec.normal - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-4);
+    // Where (x/2)² + y² = 1 at y = 0.6.
+    let rec = hit_at(
+        &ellipsoid,
+        Point3D::new(5.0, 0.6, 0.0),
+        Vec3::new(-1.0, 0.0, 0.0),
+    )
+    .unwrap();
+    assert!((rec.t - 3.4).abs() < 1e-5);
+
+    // Small exponents square it off: near the corner it is still there.
+    let boxy = shape((0.1, 0.1));
+    assert!(hit_at(
+        &boxy,
+        Point3D::new(1.9, 0.9, 5.0),
+        Vec3::new(0.0, 0.0, -1.0)
+    )
+    .is_some());
+    assert!(hit_at(
+        &ellipsoid,
+        Point3D::new(1.9, 0.9, 5.0),
+        Vec3::new(0.0, 0.0, -1.0)
+    )
+    .is_none());
+    // And 2 makes a diamond: |x|/2 + |y| = 1 at y = 0.5.
+    let diamond = shape((2.0, 2.0));
+    let rec = hit_at(
+        &diamond,
+        Point3D::new(5.0, 0.5, 0.0),
+        Vec3::new(-1.0, 0.0, 0.0),
+    )
+    .unwrap();
+    assert!((rec.t - 4.0).abs() < 1e-5);
+}
+
+#[test]
+fn test_fractals() {
+    let material = Material::Named("x".to_string());
+    let from_x =
+        |sdf: &Sdf, y: f64| hit_at(sdf, Point3D::new(5.0, y, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+
+    // With c = 0 the Julia set is the unit sphere, twice as large here.
+    let sphere = Sdf::new(
+        Point3D::default(),
+        SdfShape::Julia {
+            c: [0.0; 4],
+            slice: 0.0,
+            iterations: 12,
+            bailout: 2.0,
+            scale: 2.0,
+        },
+        material.clone(),
+    );
+    let rec = from_x(&sphere, 0.0).unwrap();
+    assert!((rec.t - 3.0).abs() < 1e-3, "{}", rec.t);
+    assert!((rec.normal - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-2);
+    assert!(from_x(&sphere, 2.1).is_none());
+
+    // Along x the Mandelbulb reaches about 0.88 out, between its bulbs.
+    let bulb: SdfShape = serde_json::from_str(r#"{"mandelbulb": {}}"#).unwrap();
+    assert_eq!(
+        bulb,
+        SdfShape::Mandelbulb {
+            power: 8.0,
+            iterations: 12,
+            bailout: 2.0,
+            scale: 1.0
+        }
+    );
+    assert!(bulb.is_valid());
+    let bulb = Sdf::new(Point3D::default(), bulb, material);
+    let rec = from_x(&bulb, 0.0).unwrap();
+    assert!((rec.p.x() - 0.88).abs() < 0.01, "{:?}", rec.p);
+    assert!(rec.front_face && rec.normal.x() > 0.5);
+    assert!(from_x(&bulb, 1.3).is_none());
+    // The distance estimate never claims more room than there is.
+    let far = Point3D::new(3.0, 0.0, 0.0);
+    assert!(bulb.distance(far) > 0.0 && bulb.distance(far) < 3.0 - rec.p.x());
+}
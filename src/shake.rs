@@ -0,0 +1,176 @@
+// Handheld camera shake: the camera wanders around where it is placed and
+// turns a little off where it looks, following smooth Perlin noise over time.
+// There are no animated cameras in a scene file, so every frame of an
+// animation is its own scene (see `raytracer batch`); giving each frame's
+// shake the frame's `time` makes the shake continue smoothly from frame to
+// frame on top of however the frames move the camera themselves.
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::rng::SplitMix64;
+use crate::vec3::Vec3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraShake {
+    // The furthest the camera moves along each axis, in scene units.
+    #[serde(default)]
+    pub position: f64,
+    // The furthest the camera turns around each of its axes, in degrees.
+    #[serde(default)]
+    pub rotation: f64,
+    // About how many times per second the shake changes direction.
+    #[serde(default = "default_frequency")]
+    pub frequency: f64,
+    // Of the frame, in seconds.
+    #[serde(default)]
+    pub time: f64,
+    // Different seeds shake differently.
+    #[serde(default)]
+    pub seed: u64,
+}
+
+fn default_frequency() -> f64 {
+    1.0
+}
+
+impl Default for CameraShake {
+    fn default() -> Self {
+        CameraShake {
+            position: 0.0,
+            rotation: 0.0,
+            frequency: default_frequency(),
+            time: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+impl CameraShake {
+    // How far the camera is moved at `time`, and how far it is turned around
+    // its own x (pitch), y (yaw) and z (roll) axes in degrees.
+    pub fn offset(&self) -> (Vec3, Vec3) {
+        let t = self.time * self.frequency;
+        let channel = |i: u64| fractal_noise(self.seed.wrapping_add(i << 32), t);
+        (
+            self.position * Vec3::new(channel(0), channel(1), channel(2)),
+            self.rotation * Vec3::new(channel(3), channel(4), channel(5)),
+        )
+    }
+}
+
+// The gradient at lattice point `i`, between -1 and 1.
+fn gradient(seed: u64, i: i64) -> f64 {
+    let bits = SplitMix64::new(seed ^ (i as u64).wrapping_mul(0x9e3779b97f4a7c15)).next_u64();
+    (bits >> 11) as f64 / (1u64 << 52) as f64 - 1.0
+}
+
+// One dimensional Perlin noise: zero at whole numbers, with random slopes
+// there, blended by the quintic fade of "Improving Noise". Between -1 and 1.
+pub fn perlin(seed: u64, t: f64) -> f64 {
+    let i = t.floor();
+    let f = t - i;
+    let fade = f * f * f * (f * (f * 6.0 - 15.0) + 10.0);
+    let a = gradient(seed, i as i64) * f;
+    let b = gradient(seed, i as i64 + 1) * (f - 1.0);
+    // Each ramp reaches at most 1/2 before the other takes over.
+    2.0 * (a + fade * (b - a))
+}
+
+// Two octaves, for the small jitter on top of the slow drift of a hand.
+fn fractal_noise(seed: u64, t: f64) -> f64 {
+    (perlin(seed, t) + 0.5 * perlin(seed ^ 1, 2.0 * t)) / 1.5
+}
+
+#[test]
+fn test_perlin() {
+    for i in -3..3 {
+        assert_eq!(perlin(7, i as f64), 0.0);
+    }
+    let mut previous = perlin(7, 0.0);
+    let mut largest: f64 = 0.0;
+    for step in 1..10_000 {
+        let value = perlin(7, step as f64 * 0.001);
+        // Smooth: no jumps between close times.
+        assert!((value - previous).abs() < 0.01);
+        largest = largest.max(value.abs());
+        previous = value;
+    }
+    assert!(largest <= 1.0 && largest > 0.1, "{}", largest);
+    assert_ne!(perlin(7, 0.5), perlin(8, 0.5));
+}
+
+#[test]
+fn test_offset() {
+    let shake = CameraShake {
+        position: 0.1,
+        rotation: 2.0,
+        frequency: 3.0,
+        time: 1.3,
+        seed: 5,
+    };
+    let (position, rotation) = shake.offset();
+    for v in [position.x(), position.y(), position.z()] {
+        assert!(v.abs() <= 0.1);
+    }
+    for v in [rotation.x(), rotation.y(), rotation.z()] {
+        assert!(v.abs() <= 2.0);
+    }
+    assert_ne!(position.x(), position.y());
+
+    // The next frame at 24 frames per second is close by.
+    let next = CameraShake {
+        time: 1.3 + 1.0 / 24.0,
+        ..shake
+    };
+    assert!((next.offset().0 - position).length() < 0.05);
+    assert_eq!(
+        CameraShake::default().offset(),
+        (Vec3::default(), Vec3::default())
+    );
+}
+
+#[test]
+fn test_camera() {
+    use crate::camera::Camera;
+    use crate::vec3::Point3D;
+
+    let mut camera = Camera::new(
+        10,
+        10,
+        1,
+        1,
+        40.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let still = camera.pixel_ray(5, 5);
+    camera.set_shake(Some(CameraShake::default()));
+    let ray = camera.pixel_ray(5, 5);
+    assert!((ray.direction().unit_vector() - still.direction().unit_vector()).length() < 1e-9);
+
+    let shake = CameraShake {
+        position: 0.5,
+        rotation: 5.0,
+        time: 0.7,
+        ..Default::default()
+    };
+    camera.set_shake(Some(shake));
+    let ray = camera.pixel_ray(5, 5);
+    assert_eq!(
+        *ray.origin(),
+        Point3D::new(0.0, 0.0, 0.0) + shake.offset().0
+    );
+    let angle = ray
+        .direction()
+        .unit_vector()
+        .dot(&still.direction().unit_vector())
+        .acos()
+        .to_degrees();
+    assert!(angle > 0.0 && angle < 5.0 * 3.0_f64.sqrt(), "{}", angle);
+    // Where the camera was placed is what gets saved.
+    assert_eq!(camera.lookfrom(), Point3D::new(0.0, 0.0, 0.0));
+}
@@ -0,0 +1,96 @@
+// The sky's gradient, from `bottom` straight down to `top` straight up,
+// for setting a scene's mood: a warm evening, a grey overcast or a studio's
+// plain white. `exponent` above 1 keeps the bottom's color higher up the
+// sky, below 1 brings the top's down towards the horizon. The sun dims it
+// at night and an atmosphere replaces it.
+
+use serde::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::material::ColorAsArray;
+use crate::vec3::Vec3;
+
+fn default_top() -> Color {
+    Color::new(0.5, 0.7, 1.0)
+}
+
+fn default_bottom() -> Color {
+    Color::new(1.0, 1.0, 1.0)
+}
+
+fn default_exponent() -> f64 {
+    1.0
+}
+
+#[serde_with::serde_as]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct GradientSky {
+    #[serde_as(as = "ColorAsArray")]
+    #[serde(default = "default_top")]
+    pub top: Color,
+    #[serde_as(as = "ColorAsArray")]
+    #[serde(default = "default_bottom")]
+    pub bottom: Color,
+    #[serde(default = "default_exponent")]
+    pub exponent: f64,
+}
+
+// White below, blue above.
+impl Default for GradientSky {
+    fn default() -> Self {
+        GradientSky {
+            top: default_top(),
+            bottom: default_bottom(),
+            exponent: default_exponent(),
+        }
+    }
+}
+
+impl GradientSky {
+    pub fn is_default(&self) -> bool {
+        *self == GradientSky::default()
+    }
+
+    pub fn color(&self, direction: Vec3) -> Color {
+        let t = 0.5 * (direction.unit_vector().y() + 1.0);
+        let t = if self.exponent == 1.0 {
+            t
+        } else {
+            t.powf(self.exponent)
+        };
+        (1.0 - t) * self.bottom + t * self.top
+    }
+
+    // Over all directions, whose heights are spread evenly.
+    pub fn average(&self) -> Color {
+        let t = 1.0 / (self.exponent + 1.0);
+        (1.0 - t) * self.bottom + t * self.top
+    }
+}
+
+#[test]
+fn test_gradient_sky() {
+    let sky = GradientSky::default();
+    assert_eq!(sky.color(Vec3::new(0.0, 2.0, 0.0)), default_top());
+    assert_eq!(sky.color(Vec3::new(0.0, -1.0, 0.0)), default_bottom());
+    assert_eq!(
+        sky.color(Vec3::new(1.0, 0.0, 0.0)),
+        Color::new(0.75, 0.85, 1.0)
+    );
+
+    // Squared, the bottom's color reaches past the horizon.
+    let sky: GradientSky =
+        serde_json::from_str(r##"{"top": "#000", "bottom": [2, 2, 2], "exponent": 2}"##).unwrap();
+    assert_eq!(sky.top, Color::default());
+    assert_eq!(
+        sky.color(Vec3::new(1.0, 0.0, 0.0)),
+        Color::new(1.5, 1.5, 1.5)
+    );
+    // The average is what many directions add up to.
+    let n = 1000;
+    let sum = (0..n).fold(Color::default(), |sum, i| {
+        let y = -1.0 + (i as f64 + 0.5) * 2.0 / n as f64;
+        sum + sky.color(Vec3::new((1.0 - y * y).sqrt(), y, 0.0))
+    });
+    assert!((sum / n as f64 - sky.average()).length() < 1e-5);
+}
@@ -1,8 +1,10 @@
+use crate::aabb::Aabb;
 use crate::hittable::{HitRecord, Hittable};
 use crate::interval::Interval;
 use crate::material::Material;
 use crate::ray::Ray;
-use crate::vec3::Point3D;
+use crate::vec3::{Point3D, Vec3};
+use std::f64::consts::PI;
 
 use serde::{Deserialize, Serialize};
 
@@ -23,8 +25,30 @@ impl Sphere {
     }
 }
 
-impl Hittable for Sphere {
-    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+impl Sphere {
+    // Longitude as u, starting at -x and going around through +z, and
+    // latitude from the south pole as v, as in "Ray Tracing: The Next Week".
+    fn set_uv(&self, n: Vec3, rec: &mut HitRecord) {
+        let theta = (-n.y()).clamp(-1.0, 1.0).acos();
+        let phi = (-n.z()).atan2(n.x()) + PI;
+        rec.u = phi / (2.0 * PI);
+        rec.v = theta / PI;
+        let r = self.radius;
+        rec.dpdu = 2.0 * PI * r * Vec3::new(phi.sin() * theta.sin(), 0.0, phi.cos() * theta.sin());
+        rec.dpdv = PI
+            * r
+            * Vec3::new(
+                -phi.cos() * theta.cos(),
+                theta.sin(),
+                phi.sin() * theta.cos(),
+            );
+    }
+}
+
+impl Sphere {
+    // The closest distance along `r` within `ray_t` where it meets the
+    // sphere.
+    fn root(&self, r: &Ray, ray_t: &Interval) -> Option<f64> {
         let oc = *r.origin() - self.center;
         let a = r.direction().length_squared();
         let half_b = oc.dot(r.direction());
@@ -32,7 +56,7 @@ impl Hittable for Sphere {
 
         let discriminant = half_b * half_b - a * c;
         if discriminant < 0.0 {
-            return false;
+            return None;
         }
         let sqrtd = discriminant.sqrt();
 
@@ -40,16 +64,64 @@ impl Hittable for Sphere {
         if root < ray_t.min || root > ray_t.max {
             root = (-half_b + sqrtd) / a;
             if root < ray_t.min || root > ray_t.max {
-                return false;
+                return None;
             }
         }
+        Some(root)
+    }
+}
 
+impl Hittable for Sphere {
+    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+        let Some(root) = self.root(r, ray_t) else {
+            return false;
+        };
         rec.t = root;
         rec.p = r.at(rec.t);
         let outward_normal = (rec.p - self.center) / self.radius;
         rec.set_face_normal(r, outward_normal);
+        self.set_uv(outward_normal, rec);
         rec.mat = self.material.clone();
 
         true
     }
+
+    fn occluded(&self, r: &Ray, ray_t: &Interval) -> bool {
+        self.root(r, ray_t).is_some()
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let r = Point3D::new(self.radius, self.radius, self.radius);
+        Aabb::from_points(self.center - r, self.center + r)
+    }
+}
+
+#[test]
+fn test_uv() {
+    let sphere = Sphere::new(
+        Point3D::new(0.0, 0.0, 0.0),
+        2.0,
+        Material::Named("x".to_string()),
+    );
+    let mut rec = HitRecord::default();
+    let r = Ray::new(Point3D::new(5.0, 0.0, 0.0), Vec3::new(-1.0, 0.0, 0.0));
+    assert!(sphere.hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec));
+    assert!((rec.u - 0.5).abs() < 1e-12 && (rec.v - 0.5).abs() < 1e-12);
+
+    // The derivatives agree with moving the point a little.
+    let n = Vec3::new(0.3, -0.4, 0.5).unit_vector();
+    sphere.set_uv(n, &mut rec);
+    let (u, v, dpdu, dpdv) = (rec.u, rec.v, rec.dpdu, rec.dpdv);
+    let at = |u: f64, v: f64| {
+        let (theta, phi) = (v * PI, u * 2.0 * PI - PI);
+        2.0 * Vec3::new(
+            phi.cos() * theta.sin(),
+            -theta.cos(),
+            -phi.sin() * theta.sin(),
+        )
+    };
+    assert!((at(u, v) - 2.0 * n).length() < 1e-9);
+    let h = 1e-6;
+    assert!(((at(u + h, v) - at(u, v)) / h - dpdu).length() < 1e-4);
+    assert!(((at(u, v + h) - at(u, v)) / h - dpdv).length() < 1e-4);
 }
@@ -0,0 +1,523 @@
+// Stochastic progressive photon mapping (Hachisuka and Jensen 2009), for
+// caustics that a path tracer practically never finds: light focused by a
+// glass ball onto a table can only be reached by a camera path that bounces
+// off the table into the glass and exactly onto the tiny light behind it.
+//
+// Every iteration traces one camera path per pixel through mirrors and glass
+// to the first diffuse surface, its visible point, then shoots photons from
+// the lights and the sky. Photons landing within a pixel's radius of its
+// visible point add to the pixel's flux; the radius shrinks as photons
+// arrive, so the image converges to the right answer.
+//
+// Only spheres, the sky and the sun send out photons; other emitters are
+// still seen directly. Sky and sun photons are aimed at the part of the scene
+// the camera sees, so light they cast on objects far outside of it and then
+// bounces into view is lost.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::camera::{sky, sunlight, Camera};
+use crate::color::{luminance, Color};
+use crate::film::Film;
+use crate::hittable::{HitRecord, Hittable, ObjectList, Shape};
+use crate::interval::Interval;
+use crate::material::{Material, Scatterable};
+use crate::parallel::*;
+use crate::ray::Ray;
+use crate::rng;
+use crate::sun::Sun;
+use crate::vec3::{Point3D, Vec3};
+
+// How much of the photons found in an iteration are kept when shrinking the
+// radius; 2/3 as in the paper.
+const ALPHA: f64 = 2.0 / 3.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Sppm {
+    // Photons per iteration, by default one per pixel. There are as many
+    // iterations as samples per pixel.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub photons: Option<usize>,
+    // The starting radius around visible points in scene units, by default a
+    // hundredth of the size of what the camera sees.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub radius: Option<f64>,
+}
+
+// The first diffuse surface seen through a pixel.
+#[derive(Debug, Clone, Copy)]
+struct VisiblePoint {
+    p: Point3D,
+    normal: Vec3,
+    // The path's throughput times the surface's BRDF.
+    weight: Color,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PixelState {
+    // Sum of the light seen directly and through mirrors.
+    direct: Color,
+    visible: Option<VisiblePoint>,
+    radius: f64,
+    // Photons gathered so far, scaled to the current radius.
+    photons: f64,
+    flux: Color,
+    // This iteration's photons.
+    new_photons: usize,
+    new_flux: Color,
+}
+
+// Where photons come from, with how much power each one sends out.
+enum Source<'a> {
+    Sky {
+        world: &'a ObjectList,
+        center: Point3D,
+        radius: f64,
+    },
+    Sphere(&'a crate::sphere::Sphere),
+    Sun {
+        sun: &'a Sun,
+        world: &'a ObjectList,
+        center: Point3D,
+        radius: f64,
+    },
+}
+
+impl Source<'_> {
+    fn power(&self) -> f64 {
+        match self {
+            // The gradient's average over all directions, which is near
+            // enough for other skies.
+            Source::Sky { world, radius, .. } => {
+                let average = luminance(world.sky.average()) * world.environment.intensity;
+                average * 4.0 * PI * PI * radius * radius
+            }
+            Source::Sphere(sphere) => {
+                let outwards = Ray::new(sphere.center, Vec3::new(0.0, -1.0, 0.0));
+                let area = 4.0 * PI * sphere.radius * sphere.radius;
+                luminance(sphere.material.emitted(&outwards)) * PI * area
+            }
+            Source::Sun {
+                sun,
+                world,
+                center,
+                radius,
+            } => {
+                let light = sunlight(&Ray::new(*center, sun.towards), sun, world);
+                luminance(light) * sun.irradiance * PI * radius * radius
+            }
+        }
+    }
+
+    // A photon's ray and its power, before dividing by the number of photons.
+    fn emit(&self) -> (Ray, Color) {
+        match self {
+            Source::Sky {
+                world,
+                center,
+                radius,
+            } => {
+                // Coming from a random direction, through a disk that covers
+                // the scene as seen from there.
+                let towards = Vec3::random_unit_vector();
+                let (u, v) = towards.perpendiculars();
+                let disk = Vec3::random_in_unit_disk() * *radius;
+                let origin = *center + towards * *radius + u * disk.x() + v * disk.y();
+                let sky = sky(&Ray::new(*center, towards), world);
+                let area = PI * radius * radius;
+                (Ray::new(origin, -towards), sky * (4.0 * PI * area))
+            }
+            Source::Sphere(sphere) => {
+                let normal = Vec3::random_unit_vector();
+                let p = sphere.center + normal * sphere.radius;
+                let direction = (normal + Vec3::random_unit_vector()).or_if_near_zero(normal);
+                let area = 4.0 * PI * sphere.radius * sphere.radius;
+                let emitted = sphere
+                    .material
+                    .emitted(&Ray::new(p + direction, -direction));
+                (Ray::new(p, direction), emitted * (PI * area))
+            }
+            Source::Sun {
+                sun,
+                world,
+                center,
+                radius,
+            } => {
+                // Parallel rays from a point of the disk, through a disk that
+                // covers the scene.
+                let towards = sun.sample_direction();
+                let (u, v) = towards.perpendiculars();
+                let disk = Vec3::random_in_unit_disk() * *radius;
+                let origin = *center + towards * *radius + u * disk.x() + v * disk.y();
+                let light = sunlight(&Ray::new(*center, towards), sun, world);
+                let area = PI * radius * radius;
+                (Ray::new(origin, -towards), light * (sun.irradiance * area))
+            }
+        }
+    }
+}
+
+fn sources<'a>(world: &'a ObjectList, center: Point3D, radius: f64) -> Vec<Source<'a>> {
+    let mut sources = vec![Source::Sky {
+        world,
+        center,
+        radius,
+    }];
+    if let Some(sun) = &world.sun {
+        sources.push(Source::Sun {
+            sun,
+            world,
+            center,
+            radius,
+        });
+    }
+    for object in world.iter() {
+        if let Shape::Sphere(sphere) = &object.shape {
+            if matches!(sphere.material, Material::DiffuseLight(_)) {
+                sources.push(Source::Sphere(sphere));
+            }
+        }
+    }
+    sources
+}
+
+// Follows the camera ray through mirrors and glass to the first diffuse
+// surface, adding up emitters and sky it sees on the way.
+fn visible_point(camera: &Camera, world: &ObjectList, mut r: Ray) -> (Color, Option<VisiblePoint>) {
+    let mut direct = Color::default();
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    for _ in 0..camera.max_depth() {
+        let mut rec = HitRecord::default();
+        if !world.hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec) {
+            direct += throughput * sky(&r, world);
+            break;
+        }
+        direct += throughput * rec.mat.emitted_at(&r, &rec);
+        if let Material::Lambertian(lambertian) = &rec.mat {
+            let visible = VisiblePoint {
+                p: rec.p,
+                normal: rec.normal,
+                weight: throughput * lambertian.albedo(&r, &rec) / PI,
+            };
+            return (direct, Some(visible));
+        }
+        let mut scattered = Ray::default();
+        let mut attenuation = Color::default();
+        if !rec.mat.scatter(&r, &rec, &mut attenuation, &mut scattered) {
+            break;
+        }
+        throughput = throughput * attenuation;
+        r = scattered;
+    }
+    (direct, None)
+}
+
+// Visible points by the grid cells their radius overlaps.
+struct Grid {
+    cell: f64,
+    cells: HashMap<(i64, i64, i64), Vec<usize>>,
+}
+
+impl Grid {
+    fn new(pixels: &[PixelState]) -> Grid {
+        let cell = pixels.iter().map(|p| p.radius).fold(0.0, f64::max) * 2.0;
+        let mut grid = Grid {
+            cell,
+            cells: HashMap::new(),
+        };
+        for (index, pixel) in pixels.iter().enumerate() {
+            let Some(visible) = pixel.visible else {
+                continue;
+            };
+            let offset = Vec3::new(pixel.radius, pixel.radius, pixel.radius);
+            let (min, max) = (grid.key(visible.p - offset), grid.key(visible.p + offset));
+            for x in min.0..=max.0 {
+                for y in min.1..=max.1 {
+                    for z in min.2..=max.2 {
+                        grid.cells.entry((x, y, z)).or_default().push(index);
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    fn key(&self, p: Point3D) -> (i64, i64, i64) {
+        let cell = |v: f64| (v / self.cell).floor() as i64;
+        (cell(p.x()), cell(p.y()), cell(p.z()))
+    }
+
+    fn near(&self, p: Point3D) -> &[usize] {
+        self.cells.get(&self.key(p)).map_or(&[], Vec::as_slice)
+    }
+}
+
+// Traces one photon and returns the pixels it adds flux to.
+fn trace_photon(
+    camera: &Camera,
+    world: &ObjectList,
+    pixels: &[PixelState],
+    grid: &Grid,
+    source: &Source,
+    source_probability: f64,
+) -> Vec<(usize, Color)> {
+    let mut found = Vec::new();
+    let (mut r, power) = source.emit();
+    let mut power = power / source_probability;
+    for _ in 0..camera.max_depth() {
+        let mut rec = HitRecord::default();
+        if !world.hit(&r, &Interval::new(0.001, f64::INFINITY), &mut rec) {
+            break;
+        }
+        if matches!(rec.mat, Material::Lambertian(_)) {
+            for &index in grid.near(rec.p) {
+                let pixel = &pixels[index];
+                let Some(visible) = pixel.visible else {
+                    continue;
+                };
+                let close = (visible.p - rec.p).length_squared() <= pixel.radius * pixel.radius;
+                if close && visible.normal.dot(r.direction()) < 0.0 {
+                    found.push((index, power * visible.weight));
+                }
+            }
+        }
+
+        let mut scattered = Ray::default();
+        let mut attenuation = Color::default();
+        if !rec.mat.scatter(&r, &rec, &mut attenuation, &mut scattered) {
+            break;
+        }
+        // Russian roulette keeps the photons' power from spreading thin.
+        let survival = attenuation
+            .x()
+            .max(attenuation.y())
+            .max(attenuation.z())
+            .min(1.0);
+        if rng::random() >= survival {
+            break;
+        }
+        power = power * attenuation / survival;
+        r = scattered;
+    }
+    found
+}
+
+// The size of what the camera sees.
+fn visible_bounds(pixels: &[PixelState]) -> Aabb {
+    pixels
+        .iter()
+        .filter_map(|p| p.visible)
+        .fold(Aabb::EMPTY, |bounds, v| {
+            Aabb::surrounding_box(&bounds, &Aabb::from_points(v.p, v.p))
+        })
+}
+
+pub fn render(
+    camera: &Camera,
+    world: &ObjectList,
+    sppm: Sppm,
+    progress: &mut dyn FnMut(&Film, f64),
+) -> Film {
+    let (width, height) = (camera.width(), camera.height());
+    let iterations = camera.samples_per_pixel();
+    let photons = sppm.photons.unwrap_or(width * height).max(1);
+    let mut pixels = vec![
+        PixelState {
+            direct: Color::default(),
+            visible: None,
+            radius: 0.0,
+            photons: 0.0,
+            flux: Color::default(),
+            new_photons: 0,
+            new_flux: Color::default(),
+        };
+        width * height
+    ];
+
+    let mut film = Film::new(width, height);
+    for iteration in 0..iterations {
+        let visible: Vec<(Color, Option<VisiblePoint>)> = (0..width * height)
+            .into_par_iter()
+            .map(|index| {
+                let r = camera.get_ray(index % width, index / width, iteration);
+                visible_point(camera, world, r)
+            })
+            .collect();
+        for (pixel, (direct, visible)) in pixels.iter_mut().zip(visible) {
+            pixel.direct += direct;
+            pixel.visible = visible;
+        }
+
+        let bounds = visible_bounds(&pixels);
+        if bounds.is_empty() {
+            continue;
+        }
+        let size = (bounds.max() - bounds.min()).length().max(1e-3);
+        if iteration == 0 {
+            let radius = sppm.radius.unwrap_or(size / 100.0);
+            pixels.iter_mut().for_each(|p| p.radius = radius);
+        }
+
+        let sources = sources(world, bounds.centroid(), size);
+        let powers: Vec<f64> = sources.iter().map(Source::power).collect();
+        let total: f64 = powers.iter().sum();
+        let grid = Grid::new(&pixels);
+        let found: Vec<Vec<(usize, Color)>> = (0..photons)
+            .into_par_iter()
+            .map(|photon| {
+                rng::seed_sample(!camera.seed, photon, iteration, 0);
+                let mut pick = rng::random() * total;
+                let mut chosen = sources.len() - 1;
+                for (index, power) in powers.iter().enumerate() {
+                    if pick < *power {
+                        chosen = index;
+                        break;
+                    }
+                    pick -= power;
+                }
+                let probability = powers[chosen] / total;
+                trace_photon(camera, world, &pixels, &grid, &sources[chosen], probability)
+            })
+            .collect();
+        for (index, flux) in found.into_iter().flatten() {
+            pixels[index].new_photons += 1;
+            pixels[index].new_flux += flux;
+        }
+
+        // Keep a share ALPHA of the new photons and shrink the radius so the
+        // photon density stays the same.
+        for pixel in pixels.iter_mut().filter(|p| p.new_photons > 0) {
+            let total_photons = pixel.photons + ALPHA * pixel.new_photons as f64;
+            let radius =
+                pixel.radius * (total_photons / (pixel.photons + pixel.new_photons as f64)).sqrt();
+            let shrink = (radius / pixel.radius).powi(2);
+            pixel.flux = (pixel.flux + pixel.new_flux) * shrink;
+            pixel.photons = total_photons;
+            pixel.radius = radius;
+            pixel.new_photons = 0;
+            pixel.new_flux = Color::default();
+        }
+
+        let done = (iteration + 1) as f64;
+        let emitted = done * photons as f64;
+        let exposure = camera.exposure().map_or(1.0, |e| e.scale());
+        for (pixel_color, pixel) in film.pixels.iter_mut().zip(&pixels) {
+            let gathered = if pixel.radius > 0.0 {
+                pixel.flux / (emitted * PI * pixel.radius * pixel.radius)
+            } else {
+                Color::default()
+            };
+            *pixel_color = (pixel.direct / done + gathered) * exposure;
+        }
+        progress(&film, done / iterations as f64);
+    }
+    film
+}
+
+#[test]
+fn test_matches_path_tracing() {
+    use crate::material::{DiffuseLight, Lambertian};
+
+    let mut world = ObjectList::new();
+    world
+        .add_sphere(
+            Point3D::new(0.0, -100.5, -1.0),
+            100.0,
+            Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+        )
+        .add_sphere(
+            Point3D::new(0.0, 0.0, -1.0),
+            0.5,
+            Material::Lambertian(Lambertian::new(Color::new(0.8, 0.3, 0.3))),
+        )
+        .add_sphere(
+            Point3D::new(1.0, 0.5, -0.5),
+            0.25,
+            Material::DiffuseLight(DiffuseLight::new(Color::new(4.0, 4.0, 4.0))),
+        );
+    let mut camera = Camera::new(
+        12,
+        16,
+        64,
+        8,
+        90.0,
+        Point3D::new(0.0, 0.0, 0.0),
+        Point3D::new(0.0, 0.0, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let average = |film: &Film| {
+        film.pixels.iter().fold(Color::default(), |sum, &c| sum + c) / film.pixels.len() as f64
+    };
+    let path_traced = average(&camera.render_film(&world, &[]));
+
+    camera.set_samples_per_pixel(16);
+    let photons = render(
+        &camera,
+        &world,
+        Sppm {
+            photons: Some(20_000),
+            radius: None,
+        },
+        &mut |_, _| {},
+    );
+    let photon_mapped = average(&photons);
+    assert!(
+        (photon_mapped - path_traced).length() < 0.05 * path_traced.length(),
+        "{:?} {:?}",
+        photon_mapped,
+        path_traced
+    );
+}
+
+#[test]
+fn test_sun_photons() {
+    use crate::material::Lambertian;
+
+    let mut world = ObjectList::new();
+    world.add_sphere(
+        Point3D::new(0.0, -100.5, -1.0),
+        100.0,
+        Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+    );
+    world.sun = Some(Sun::new(Vec3::new(1.0, 1.0, 0.5)));
+    let mut camera = Camera::new(
+        8,
+        8,
+        64,
+        8,
+        60.0,
+        Point3D::new(0.0, 0.5, 0.0),
+        Point3D::new(0.0, -0.5, -1.0),
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        1.0,
+    );
+    let average = |film: &Film| {
+        film.pixels.iter().fold(Color::default(), |sum, &c| sum + c) / film.pixels.len() as f64
+    };
+    let path_traced = average(&camera.render_film(&world, &[]));
+
+    camera.set_samples_per_pixel(16);
+    let photons = render(
+        &camera,
+        &world,
+        Sppm {
+            photons: Some(20_000),
+            radius: None,
+        },
+        &mut |_, _| {},
+    );
+    let photon_mapped = average(&photons);
+    assert!(
+        (photon_mapped - path_traced).length() < 0.05 * path_traced.length(),
+        "{:?} {:?}",
+        photon_mapped,
+        path_traced
+    );
+}
@@ -0,0 +1,219 @@
+// Catmull-Clark subdivision surfaces. A mesh's polygons are the control cage
+// of a smooth surface; every level of subdivision splits each n-sided face
+// into n quads and moves the points towards that surface: a new point in the
+// middle of each face, one on each edge, and the old points pulled towards
+// their neighbors. Edges with only one face are boundaries, which stay sharp
+// and follow a cubic curve through the cage's boundary points instead.
+//
+// Texture coordinates are only split, not smoothed, so that they stay put
+// at UV seams.
+
+use std::collections::HashMap;
+
+use crate::mesh::{Mesh, Vertex};
+use crate::vec3::{Point3D, Vec3};
+
+// The cage subdivided `levels` times, with smooth normals.
+pub fn catmull_clark(cage: &Mesh, levels: usize) -> Mesh {
+    let mut mesh = cage.clone();
+    for _ in 0..levels {
+        mesh = subdivide(&mesh);
+    }
+    mesh.smooth_normals();
+    mesh
+}
+
+fn edge(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+fn average(points: impl Iterator<Item = Point3D>) -> Point3D {
+    let (sum, count) = points.fold((Vec3::default(), 0), |(sum, count), p| (sum + p, count + 1));
+    sum / count.max(1) as f64
+}
+
+fn subdivide(mesh: &Mesh) -> Mesh {
+    let positions = &mesh.positions;
+    let face_points: Vec<Point3D> = mesh
+        .faces
+        .iter()
+        .map(|face| average(face.iter().map(|v| positions[v.position])))
+        .collect();
+
+    // The faces on either side of every edge.
+    let mut edge_faces: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for (f, face) in mesh.faces.iter().enumerate() {
+        for i in 0..face.len() {
+            let (a, b) = (face[i].position, face[(i + 1) % face.len()].position);
+            edge_faces.entry(edge(a, b)).or_default().push(f);
+        }
+    }
+    let mut edges: Vec<(usize, usize)> = edge_faces.keys().copied().collect();
+    edges.sort_unstable();
+    let edge_index: HashMap<(usize, usize), usize> =
+        edges.iter().enumerate().map(|(i, &e)| (e, i)).collect();
+
+    let edge_points: Vec<Point3D> = edges
+        .iter()
+        .map(|&(a, b)| {
+            let faces = &edge_faces[&(a, b)];
+            let middle = 0.5 * (positions[a] + positions[b]);
+            if faces.len() == 2 {
+                0.5 * (middle + 0.5 * (face_points[faces[0]] + face_points[faces[1]]))
+            } else {
+                middle
+            }
+        })
+        .collect();
+
+    // What touches each old point.
+    let mut point_faces = vec![Vec::new(); positions.len()];
+    for (f, face) in mesh.faces.iter().enumerate() {
+        for v in face {
+            point_faces[v.position].push(f);
+        }
+    }
+    let mut point_edges = vec![Vec::new(); positions.len()];
+    for &(a, b) in &edges {
+        point_edges[a].push((a, b));
+        point_edges[b].push((a, b));
+    }
+
+    let vertex_points: Vec<Point3D> = (0..positions.len())
+        .map(|p| {
+            let old = positions[p];
+            let other = |&(a, b): &(usize, usize)| positions[if a == p { b } else { a }];
+            let boundary: Vec<_> = point_edges[p]
+                .iter()
+                .filter(|e| edge_faces[e].len() == 1)
+                .collect();
+            match boundary.len() {
+                0 => {
+                    let n = point_faces[p].len() as f64;
+                    if n == 0.0 {
+                        return old;
+                    }
+                    let f = average(point_faces[p].iter().map(|&f| face_points[f]));
+                    let r = average(point_edges[p].iter().map(|e| 0.5 * (old + other(e))));
+                    (f + 2.0 * r + (n - 3.0) * old) / n
+                }
+                2 if point_faces[p].len() > 1 => {
+                    (other(boundary[0]) + 6.0 * old + other(boundary[1])) / 8.0
+                }
+                // Corners of a single face, and where more than two
+                // boundaries meet, stay put.
+                _ => old,
+            }
+        })
+        .collect();
+
+    let edge_start = vertex_points.len();
+    let face_start = edge_start + edge_points.len();
+    let mut subdivided = Mesh {
+        positions: [vertex_points, edge_points, face_points].concat(),
+        ..Mesh::default()
+    };
+
+    for (f, face) in mesh.faces.iter().enumerate() {
+        let n = face.len();
+        let has_uvs = face.iter().all(|v| v.uv.is_some());
+        let uv = |v: &Vertex| v.uv.filter(|_| has_uvs).map(|uv| mesh.uvs[uv]);
+        let middle = |a: &Vertex, b: &Vertex| {
+            let (a, b) = (uv(a)?, uv(b)?);
+            Some((0.5 * (a.0 + b.0), 0.5 * (a.1 + b.1)))
+        };
+        let center = face.iter().try_fold((0.0, 0.0), |sum, v| {
+            let uv = uv(v)?;
+            Some((sum.0 + uv.0 / n as f64, sum.1 + uv.1 / n as f64))
+        });
+        let edge_point = |a: &Vertex, b: &Vertex| {
+            (
+                edge_start + edge_index[&edge(a.position, b.position)],
+                middle(a, b),
+            )
+        };
+
+        for i in 0..n {
+            let (previous, this, next) = (&face[(i + n - 1) % n], &face[i], &face[(i + 1) % n]);
+            let quad = [
+                (this.position, uv(this)),
+                edge_point(this, next),
+                (face_start + f, center),
+                edge_point(previous, this),
+            ];
+            let vertices = quad
+                .into_iter()
+                .map(|(position, uv)| Vertex {
+                    position,
+                    uv: uv.map(|uv| {
+                        subdivided.uvs.push(uv);
+                        subdivided.uvs.len() - 1
+                    }),
+                    normal: None,
+                })
+                .collect();
+            subdivided.add_face(vertices);
+        }
+    }
+    subdivided
+}
+
+#[test]
+fn test_cube() {
+    // A unit cube around the origin, made of quads with outward normals.
+    let cube = Mesh::parse_obj(
+        "v -1 -1 -1\nv 1 -1 -1\nv 1 1 -1\nv -1 1 -1\nv -1 -1 1\nv 1 -1 1\nv 1 1 1\nv -1 1 1\n\
+         f 1 4 3 2\nf 5 6 7 8\nf 1 2 6 5\nf 2 3 7 6\nf 3 4 8 7\nf 4 1 5 8\n",
+    )
+    .unwrap();
+    let once = catmull_clark(&cube, 1);
+    // 6 faces, 12 edges and 8 corners become 24 quads around 26 points.
+    assert_eq!(once.faces.len(), 24);
+    assert_eq!(once.triangles.len(), 48);
+    assert_eq!(once.positions.len(), 26);
+    // The corners move in to (5/9) of the way, face points stay.
+    assert!(once
+        .positions
+        .iter()
+        .any(|p| (*p - Vec3::new(5.0, 5.0, 5.0) / 9.0).length() < 1e-12));
+    assert!(once.positions.contains(&Point3D::new(0.0, 0.0, 1.0)));
+
+    // It rounds off towards a sphere: the points' distances from the center
+    // spread less and less.
+    let spread = |mesh: &Mesh| {
+        let lengths: Vec<f64> = mesh.positions.iter().map(|p| p.length()).collect();
+        let max = lengths.iter().cloned().fold(0.0, f64::max);
+        let min = lengths.iter().cloned().fold(f64::INFINITY, f64::min);
+        max / min
+    };
+    let twice = catmull_clark(&cube, 2);
+    assert_eq!(twice.faces.len(), 96);
+    assert!(spread(&twice) < spread(&once));
+    // The normals point outwards.
+    for (i, _) in twice.triangles.iter().enumerate() {
+        let p = twice.position(i, [1.0 / 3.0; 3]);
+        assert!(twice.normal(i, [1.0 / 3.0; 3]).dot(&p) > 0.0);
+    }
+}
+
+#[test]
+fn test_boundary() {
+    // A single quad with texture coordinates: all its edges are boundaries,
+    // so it stays flat and its corners stay put.
+    let quad = Mesh::parse_obj(
+        "v 0 0 0\nv 2 0 0\nv 2 2 0\nv 0 2 0\nvt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\nf 1/1 2/2 3/3 4/4\n",
+    )
+    .unwrap();
+    let mesh = catmull_clark(&quad, 2);
+    assert_eq!(mesh.faces.len(), 16);
+    assert!(mesh.positions.iter().all(|p| p.z() == 0.0));
+    assert!(mesh.positions.contains(&Point3D::new(2.0, 2.0, 0.0)));
+    // Texture coordinates still follow the positions.
+    for face in &mesh.faces {
+        for v in face {
+            let p = mesh.positions[v.position];
+            let (u, v) = mesh.uvs[v.uv.unwrap()];
+            assert!((u - p.x() / 2.0).abs() < 1e-12 && (v - p.y() / 2.0).abs() < 1e-12);
+        }
+    }
+}
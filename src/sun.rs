@@ -0,0 +1,289 @@
+// The sun: a small, very bright disk in the sky, placed directly or where it
+// stands at a place on earth at a given local time, e.g. for shadow studies
+// of a building. Diffuse surfaces aim one extra ray at it, so its sharp
+// shadows don't depend on scattered rays finding the disk by chance. Its
+// light is reddened by the air it passes through when it is low, and the sky
+// darkens and glows around it as it sets.
+//
+// The position follows NOAA's approximate solar equations, good to about a
+// tenth of a degree between 1900 and 2100.
+
+use std::f64::consts::PI;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::rng;
+use crate::vec3::Vec3;
+
+fn default_irradiance() -> f64 {
+    10.0
+}
+
+fn default_angular_diameter() -> f64 {
+    0.53
+}
+
+fn north() -> Vec3 {
+    Vec3::new(0.0, 0.0, -1.0)
+}
+
+// Where on earth, and when. Latitudes are positive to the north, longitudes
+// to the east, both in degrees.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SunLocation {
+    pub latitude: f64,
+    pub longitude: f64,
+    // "YYYY-MM-DD".
+    pub date: String,
+    // Local clock time, "HH:MM".
+    pub time: String,
+    // Hours the local clock is ahead of UTC, e.g. 2 for summer in Berlin.
+    #[serde(default)]
+    pub utc_offset: f64,
+    // The scene direction that points north; y is up.
+    #[serde(default = "north")]
+    pub north: Vec3,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sun {
+    // Towards the sun, unless `location` places it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub direction: Option<Vec3>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<SunLocation>,
+    // The light falling on a surface facing the sun when it is overhead.
+    // The default sky puts about 2.7 on a surface facing up.
+    #[serde(default = "default_irradiance")]
+    pub irradiance: f64,
+    // In degrees.
+    #[serde(default = "default_angular_diameter")]
+    pub angular_diameter: f64,
+    // Unit vector towards the sun, set by `resolve`.
+    #[serde(skip)]
+    pub towards: Vec3,
+}
+
+fn invalid_input(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+// Splits "a<separator>b<separator>..." into numbers.
+fn numbers(text: &str, separator: char, what: &str) -> io::Result<Vec<u32>> {
+    text.split(separator)
+        .map(|field| field.trim().parse())
+        .collect::<Result<_, _>>()
+        .map_err(|_| invalid_input(format!("sun {} \"{}\" is not a number", what, text)))
+}
+
+const MONTH_DAYS: [u32; 12] = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+
+// 1 for January 1st.
+fn day_of_year(year: u32, month: u32, day: u32) -> Option<u32> {
+    let leap = year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+    let month_days = MONTH_DAYS.get(month.checked_sub(1)? as usize)? + (leap && month == 2) as u32;
+    if day == 0 || day > month_days {
+        return None;
+    }
+    Some(MONTH_DAYS[..month as usize - 1].iter().sum::<u32>() + (leap && month > 2) as u32 + day)
+}
+
+impl SunLocation {
+    // Unit vector towards the sun, which is below the horizon at night.
+    pub fn direction(&self) -> io::Result<Vec3> {
+        let date = numbers(&self.date, '-', "date")?;
+        let time = numbers(&self.time, ':', "time")?;
+        let (&[year, month, day], &[hour, minute]) = (&date[..], &time[..]) else {
+            return Err(invalid_input(format!(
+                "sun date \"{}\" or time \"{}\" is not YYYY-MM-DD and HH:MM",
+                self.date, self.time
+            )));
+        };
+        let day = day_of_year(year, month, day)
+            .ok_or_else(|| invalid_input(format!("sun date \"{}\" doesn't exist", self.date)))?;
+        let minutes = (hour * 60 + minute) as f64;
+
+        // The fractional year, and from it the equation of time in minutes
+        // and the declination.
+        let utc_hour = minutes / 60.0 - self.utc_offset;
+        let g = 2.0 * PI / 365.0 * (day as f64 - 1.0 + (utc_hour - 12.0) / 24.0);
+        let equation_of_time = 229.18
+            * (0.000075 + 0.001868 * g.cos()
+                - 0.032077 * g.sin()
+                - 0.014615 * (2.0 * g).cos()
+                - 0.040849 * (2.0 * g).sin());
+        let declination = 0.006918 - 0.399912 * g.cos() + 0.070257 * g.sin()
+            - 0.006758 * (2.0 * g).cos()
+            + 0.000907 * (2.0 * g).sin()
+            - 0.002697 * (3.0 * g).cos()
+            + 0.00148 * (3.0 * g).sin();
+        let solar_minutes =
+            minutes + equation_of_time + 4.0 * self.longitude - 60.0 * self.utc_offset;
+        let hour_angle = (solar_minutes / 4.0 - 180.0).to_radians();
+
+        let latitude = self.latitude.to_radians();
+        let east = -hour_angle.sin() * declination.cos();
+        let north = latitude.cos() * declination.sin()
+            - latitude.sin() * declination.cos() * hour_angle.cos();
+        let up = latitude.sin() * declination.sin()
+            + latitude.cos() * declination.cos() * hour_angle.cos();
+
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        let n = (self.north - self.north.dot(&y) * y).unit_vector();
+        Ok((east * n.cross(&y) + north * n + up * y).unit_vector())
+    }
+}
+
+impl Sun {
+    pub fn new(direction: Vec3) -> Sun {
+        let mut sun = Sun {
+            direction: Some(direction),
+            location: None,
+            irradiance: default_irradiance(),
+            angular_diameter: default_angular_diameter(),
+            towards: Vec3::default(),
+        };
+        sun.resolve().expect("a direction needs no date");
+        sun
+    }
+
+    // Works out where the sun is. Straight up without a direction or
+    // location.
+    pub fn resolve(&mut self) -> io::Result<()> {
+        self.towards = match (&self.location, self.direction) {
+            (Some(location), _) => location.direction()?,
+            (None, Some(direction)) => direction.unit_vector(),
+            (None, None) => Vec3::new(0.0, 1.0, 0.0),
+        };
+        Ok(())
+    }
+
+    // Sine of the height above the horizon.
+    fn elevation(&self) -> f64 {
+        self.towards.y()
+    }
+
+    fn cos_radius(&self) -> f64 {
+        (self.angular_diameter / 2.0).to_radians().cos()
+    }
+
+    fn solid_angle(&self) -> f64 {
+        2.0 * PI * (1.0 - self.cos_radius())
+    }
+
+    // The sunlight left after the air it crossed, white with the sun
+    // overhead. Blue is scattered away most, so a low sun turns orange and
+    // red. Black below the horizon.
+    pub fn color(&self) -> Color {
+        let elevation = self.elevation();
+        if elevation <= 0.0 {
+            return Color::default();
+        }
+        // Kasten and Young's air mass, 1 overhead and about 38 at the horizon.
+        let degrees = elevation.asin().to_degrees();
+        let air_mass = 1.0 / (elevation + 0.50572 * (degrees + 6.07995).powf(-1.6364));
+        let optical_depth = Color::new(0.05, 0.1, 0.25) * (air_mass - 1.0).max(0.0);
+        Color::new(
+            (-optical_depth.x()).exp(),
+            (-optical_depth.y()).exp(),
+            (-optical_depth.z()).exp(),
+        )
+    }
+
+    // What a ray in `direction` sees of the disk, before the air on the
+    // way takes its share, see `color`.
+    pub fn disk(&self, direction: Vec3) -> f64 {
+        if direction.unit_vector().dot(&self.towards) < self.cos_radius() {
+            return 0.0;
+        }
+        self.irradiance / self.solid_angle()
+    }
+
+    // A direction towards a uniformly chosen point of the disk, whose
+    // density per solid angle is one over its solid angle.
+    pub fn sample_direction(&self) -> Vec3 {
+        let cos_theta = 1.0 - rng::random() * (1.0 - self.cos_radius());
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng::random();
+        let (u, v) = self.towards.perpendiculars();
+        sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * self.towards
+    }
+
+    // The sky as the sun leaves it: `sky` at full strength by day, fading
+    // to a little starlight while the sun sets, with a glow around it near
+    // the horizon at dusk and dawn.
+    pub fn sky(&self, sky: Color, direction: Vec3) -> Color {
+        let elevation = self.elevation();
+        let daylight = ((elevation + 0.1) / 0.3).clamp(0.0, 1.0);
+        let daylight = daylight * daylight * (3.0 - 2.0 * daylight);
+        let d = direction.unit_vector();
+        let twilight = (1.0 - elevation.abs() / 0.3).max(0.0);
+        let horizontal = Vec3::new(self.towards.x(), 0.0, self.towards.z()).unit_vector();
+        let facing = d.dot(&horizontal).max(0.0);
+        let near_horizon = (1.0 - d.y().abs()).powi(4);
+        let glow = Color::new(1.0, 0.45, 0.15) * (0.5 * twilight * facing.powi(4) * near_horizon);
+        sky * (0.01 + 0.99 * daylight) + glow
+    }
+}
+
+#[test]
+fn test_location() {
+    let location = |latitude: f64, date: &str, time: &str| SunLocation {
+        latitude,
+        longitude: 0.0,
+        date: date.to_string(),
+        time: time.to_string(),
+        utc_offset: 0.0,
+        north: north(),
+    };
+    let elevation = |d: Vec3| d.y().asin().to_degrees();
+
+    // Around noon at an equinox the sun is overhead at the equator, and in
+    // the south at 45 degrees north.
+    let equator = location(0.0, "2024-03-20", "12:07").direction().unwrap();
+    assert!(elevation(equator) > 88.0, "{:?}", equator);
+    let north_45 = location(45.0, "2024-03-20", "12:07").direction().unwrap();
+    assert!((elevation(north_45) - 45.0).abs() < 1.0);
+    assert!(north_45.z() > 0.0 && north_45.x().abs() < 0.05);
+    // It rises in the east and sets in the west, and is gone at midnight.
+    let morning = location(45.0, "2024-06-21", "08:00").direction().unwrap();
+    let evening = location(45.0, "2024-06-21", "16:00").direction().unwrap();
+    assert!(morning.x() > 0.0 && evening.x() < 0.0);
+    let midnight = location(45.0, "2024-06-21", "00:00").direction().unwrap();
+    assert!(midnight.y() < 0.0);
+    // New York at local noon on the summer solstice: about 72 degrees up.
+    let new_york = SunLocation {
+        longitude: -74.0,
+        utc_offset: -4.0,
+        ..location(40.7, "2024-06-21", "13:00")
+    };
+    assert!((elevation(new_york.direction().unwrap()) - 72.7).abs() < 1.0);
+
+    assert!(location(45.0, "2023-02-29", "12:00").direction().is_err());
+    assert!(location(45.0, "2024-06-21", "noon").direction().is_err());
+}
+
+#[test]
+fn test_sun() {
+    let high = Sun::new(Vec3::new(0.0, 1.0, 0.0));
+    assert!((high.color() - Color::new(1.0, 1.0, 1.0)).length() < 1e-9);
+    let low = Sun::new(Vec3::new(1.0, 0.05, 0.0));
+    assert!(low.color().x() > low.color().y() && low.color().y() > low.color().z());
+    let set = Sun::new(Vec3::new(1.0, -0.2, 0.0));
+    assert_eq!(set.color(), Color::default());
+
+    // The disk's radiance times its solid angle is the irradiance.
+    let radiance = high.disk(Vec3::new(0.0, 1.0, 0.0));
+    assert!((radiance * high.solid_angle() - high.irradiance).abs() < 1e-9);
+    assert_eq!(high.disk(Vec3::new(0.0, 1.0, 0.1)), 0.0);
+    for _ in 0..100 {
+        let d = high.sample_direction();
+        assert!(d.dot(&high.towards) >= high.cos_radius() - 1e-12);
+    }
+
+    let sky = Color::new(0.5, 0.7, 1.0);
+    assert_eq!(high.sky(sky, Vec3::new(0.0, 0.0, 1.0)), sky);
+    assert!(set.sky(sky, Vec3::new(0.0, 1.0, 0.0)).y() < 0.1);
+}
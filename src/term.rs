@@ -0,0 +1,283 @@
+// Shows the film in a terminal while it renders, so renders over SSH give
+// visual feedback. Plain terminals get 24-bit ANSI colored half blocks (two
+// pixels per character cell); Kitty and Sixel graphics show real pixels.
+
+use std::env;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crate::film::Film;
+
+// Graphics protocols are capped at this width in pixels.
+const MAX_GRAPHICS_WIDTH: usize = 480;
+const MIN_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TermGraphics {
+    Ansi,
+    Sixel,
+    Kitty,
+}
+
+impl TermGraphics {
+    pub fn from_name(name: &str) -> Option<TermGraphics> {
+        match name {
+            "ansi" => Some(TermGraphics::Ansi),
+            "sixel" => Some(TermGraphics::Sixel),
+            "kitty" => Some(TermGraphics::Kitty),
+            _ => None,
+        }
+    }
+
+    // Guesses from the environment. Sixel support can't be detected without
+    // querying the terminal, so it has to be asked for by name.
+    pub fn detect() -> TermGraphics {
+        let term = env::var("TERM").unwrap_or_default();
+        if env::var_os("KITTY_WINDOW_ID").is_some() || term == "xterm-kitty" {
+            TermGraphics::Kitty
+        } else {
+            TermGraphics::Ansi
+        }
+    }
+}
+
+fn terminal_columns() -> usize {
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .filter(|c| *c > 0)
+        .unwrap_or(80)
+}
+
+// The film scaled down to at most `max_width` pixels, keeping square pixels.
+fn fit(film: &Film, max_width: usize) -> Film {
+    let width = film.width.min(max_width).max(1);
+    let height = ((film.height * width) as f64 / film.width as f64).round() as usize;
+    film.downsample(width, height.max(1))
+}
+
+// Each character shows two pixels: the upper one as the foreground of '▀'
+// and the lower one as the background.
+pub fn ansi(film: &Film, columns: usize) -> String {
+    let small = fit(film, columns);
+    let rgb = small.to_rgb8();
+    let pixel = |x: usize, y: usize| {
+        let i = (y * small.width + x) * 3;
+        (rgb[i], rgb[i + 1], rgb[i + 2])
+    };
+
+    let mut out = String::new();
+    for y in (0..small.height).step_by(2) {
+        for x in 0..small.width {
+            let (r, g, b) = pixel(x, y);
+            if y + 1 < small.height {
+                let (br, bg, bb) = pixel(x, y + 1);
+                let _ = write!(
+                    out,
+                    "\x1b[38;2;{};{};{};48;2;{};{};{}m▀",
+                    r, g, b, br, bg, bb
+                );
+            } else {
+                let _ = write!(out, "\x1b[38;2;{};{};{};49m▀", r, g, b);
+            }
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// The Kitty graphics protocol with raw RGB data, sent in 4096 byte chunks.
+// Reusing the image id replaces the previous preview.
+pub fn kitty(film: &Film) -> String {
+    let small = fit(film, MAX_GRAPHICS_WIDTH);
+    let data = base64(&small.to_rgb8());
+    let chunks: Vec<&str> = data
+        .as_bytes()
+        .chunks(4096)
+        .map(|c| std::str::from_utf8(c).unwrap())
+        .collect();
+
+    let mut out = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = (i + 1 < chunks.len()) as u8;
+        if i == 0 {
+            let _ = write!(
+                out,
+                "\x1b_Ga=T,i=1,f=24,s={},v={},m={};{}\x1b\\",
+                small.width, small.height, more, chunk
+            );
+        } else {
+            let _ = write!(out, "\x1b_Gm={};{}\x1b\\", more, chunk);
+        }
+    }
+    out.push('\n');
+    out
+}
+
+// Sixel graphics with a fixed 6x6x6 color cube palette.
+pub fn sixel(film: &Film) -> String {
+    let small = fit(film, MAX_GRAPHICS_WIDTH);
+    let rgb = small.to_rgb8();
+    let level = |v: u8| (v as usize * 5 + 127) / 255;
+    let indices: Vec<usize> = rgb
+        .chunks(3)
+        .map(|p| level(p[0]) * 36 + level(p[1]) * 6 + level(p[2]))
+        .collect();
+
+    let mut out = format!("\x1bPq\"1;1;{};{}", small.width, small.height);
+    for i in 0..216 {
+        let percent = |v: usize| v * 100 / 5;
+        let _ = write!(
+            out,
+            "#{};2;{};{};{}",
+            i,
+            percent(i / 36),
+            percent(i / 6 % 6),
+            percent(i % 6)
+        );
+    }
+
+    for band in (0..small.height).step_by(6) {
+        let rows = (small.height - band).min(6);
+        let mut used: Vec<usize> = (band..band + rows)
+            .flat_map(|y| {
+                indices[y * small.width..(y + 1) * small.width]
+                    .iter()
+                    .copied()
+            })
+            .collect();
+        used.sort_unstable();
+        used.dedup();
+
+        for (n, color) in used.iter().enumerate() {
+            if n > 0 {
+                out.push('$');
+            }
+            let _ = write!(out, "#{}", color);
+            let sixels: Vec<u8> = (0..small.width)
+                .map(|x| {
+                    (0..rows).fold(0, |bits, dy| {
+                        if indices[(band + dy) * small.width + x] == *color {
+                            bits | 1 << dy
+                        } else {
+                            bits
+                        }
+                    })
+                })
+                .collect();
+            // Run length encode repeated columns.
+            let mut x = 0;
+            while x < sixels.len() {
+                let run = sixels[x..].iter().take_while(|s| **s == sixels[x]).count();
+                let c = (63 + sixels[x]) as char;
+                if run > 3 {
+                    let _ = write!(out, "!{}{}", run, c);
+                } else {
+                    out.extend(std::iter::repeat_n(c, run));
+                }
+                x += run;
+            }
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\\n");
+    out
+}
+
+// Redraws the preview in place, at most twice per second.
+pub struct TerminalPreview {
+    graphics: TermGraphics,
+    last_draw: Option<Instant>,
+    started: bool,
+}
+
+impl TerminalPreview {
+    pub fn new(graphics: TermGraphics) -> TerminalPreview {
+        TerminalPreview {
+            graphics,
+            last_draw: None,
+            started: false,
+        }
+    }
+
+    pub fn update(&mut self, film: &Film) {
+        if self.last_draw.is_some_and(|t| t.elapsed() < MIN_INTERVAL) {
+            return;
+        }
+        // Errors only cost the preview, never the render.
+        let _ = self.draw(film);
+        self.last_draw = Some(Instant::now());
+    }
+
+    pub fn draw(&mut self, film: &Film) -> io::Result<()> {
+        let image = match self.graphics {
+            TermGraphics::Ansi => ansi(film, terminal_columns()),
+            TermGraphics::Sixel => sixel(film),
+            TermGraphics::Kitty => kitty(film),
+        };
+
+        let mut stdout = io::stdout().lock();
+        // Go back to where the first preview started.
+        if self.started {
+            write!(stdout, "\x1b[u")?;
+        } else {
+            write!(stdout, "\r\x1b[s")?;
+            self.started = true;
+        }
+        stdout.write_all(image.as_bytes())?;
+        stdout.flush()
+    }
+}
+
+#[test]
+fn test_ansi() {
+    let mut film = Film::new(4, 3);
+    film.pixels[0] = crate::color::Color::new(1.0, 0.0, 0.0);
+    let out = ansi(&film, 80);
+    assert_eq!(out.lines().count(), 2);
+    assert!(out.starts_with("\x1b[38;2;255;0;0;48;2;0;0;0m▀"));
+    assert!(out.contains("49m▀"));
+    assert_eq!(ansi(&Film::new(400, 200), 80).lines().count(), 20);
+}
+
+#[test]
+fn test_base64() {
+    assert_eq!(base64(b""), "");
+    assert_eq!(base64(b"f"), "Zg==");
+    assert_eq!(base64(b"fo"), "Zm8=");
+    assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+}
+
+#[test]
+fn test_graphics_protocols() {
+    let film = Film::new(8, 7);
+    let kitty = kitty(&film);
+    assert!(kitty.starts_with("\x1b_Ga=T,i=1,f=24,s=8,v=7,m=0;"));
+    let sixel = sixel(&film);
+    assert!(sixel.starts_with("\x1bPq\"1;1;8;7"));
+    // Two bands of six rows, all black: color 0, eight empty-bit columns.
+    assert_eq!(sixel.matches("#0!8~").count(), 1);
+    assert!(sixel.ends_with("-\x1b\\\n"));
+}
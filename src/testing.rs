@@ -0,0 +1,334 @@
+// Statistical checks for materials, for this crate's tests and for programs
+// writing their own `Scatterable`s.
+//
+// `chi_squared` checks that the directions a material scatters into follow
+// the density it claims: it sorts many sampled directions into bins over the
+// sphere and compares how many land in each with what the density says,
+// like Mitsuba's tests. A low p-value means the sampling and the density
+// disagree. `white_furnace` checks that a material doesn't create light: lit
+// by a white sky, one bounce can't reflect more than all of it.
+
+use std::f64::consts::PI;
+
+use crate::color::Color;
+use crate::hittable::HitRecord;
+use crate::material::Scatterable;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+// How unlikely a result has to be to fail a test.
+pub const SIGNIFICANCE: f64 = 0.01;
+
+// Bins along the angle from the axis, with equal steps of its cosine, and
+// around it.
+const THETA_BINS: usize = 20;
+const PHI_BINS: usize = 40;
+
+// Points each bin's density is integrated over, along each side.
+const BIN_SAMPLES: usize = 8;
+
+// Bins expecting fewer samples are pooled into one, or the statistic
+// doesn't hold.
+const MIN_EXPECTED: f64 = 5.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChiSquared {
+    pub statistic: f64,
+    pub degrees_of_freedom: usize,
+    // The chance of a statistic at least this large if the samples follow
+    // the density.
+    pub p_value: f64,
+}
+
+impl ChiSquared {
+    pub fn passed(&self) -> bool {
+        self.p_value >= SIGNIFICANCE
+    }
+}
+
+// The bin of a unit vector in the frame around `axis`.
+fn bin(d: Vec3, frame: (Vec3, Vec3, Vec3)) -> usize {
+    let (u, v, w) = frame;
+    let cos_theta = d.dot(&w).clamp(-1.0, 1.0);
+    let phi = d.dot(&v).atan2(d.dot(&u)).rem_euclid(2.0 * PI);
+    let theta_bin = (((1.0 - cos_theta) / 2.0 * THETA_BINS as f64) as usize).min(THETA_BINS - 1);
+    let phi_bin = ((phi / (2.0 * PI) * PHI_BINS as f64) as usize).min(PHI_BINS - 1);
+    theta_bin * PHI_BINS + phi_bin
+}
+
+// Draws `samples` directions from `sample` and tests them against `pdf`, a
+// density per solid angle. The bins are laid out around `axis`, which should
+// be where the density changes most, e.g. the normal or the mirror
+// direction. Densities with spikes or edges inside a bin can fail even when
+// right, since each bin's is only summed at a few points.
+pub fn chi_squared(
+    mut sample: impl FnMut() -> Vec3,
+    pdf: impl Fn(Vec3) -> f64,
+    axis: Vec3,
+    samples: usize,
+) -> ChiSquared {
+    let w = axis.unit_vector();
+    let (u, v) = w.perpendiculars();
+    let frame = (u, v, w);
+
+    let mut observed = vec![0.0; THETA_BINS * PHI_BINS];
+    for _ in 0..samples {
+        let d = sample();
+        if d.is_finite() && !d.near_zero() {
+            observed[bin(d.unit_vector(), frame)] += 1.0;
+        }
+    }
+
+    let (cos_step, phi_step) = (2.0 / THETA_BINS as f64, 2.0 * PI / PHI_BINS as f64);
+    let expected: Vec<f64> = (0..THETA_BINS * PHI_BINS)
+        .map(|i| {
+            let (theta_bin, phi_bin) = (i / PHI_BINS, i % PHI_BINS);
+            let mut sum = 0.0;
+            for j in 0..BIN_SAMPLES {
+                for k in 0..BIN_SAMPLES {
+                    let cos_theta =
+                        1.0 - (theta_bin as f64 + (j as f64 + 0.5) / BIN_SAMPLES as f64) * cos_step;
+                    let phi = (phi_bin as f64 + (k as f64 + 0.5) / BIN_SAMPLES as f64) * phi_step;
+                    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                    let d = sin_theta * (phi.cos() * u + phi.sin() * v) + cos_theta * w;
+                    sum += pdf(d);
+                }
+            }
+            let solid_angle = cos_step * phi_step;
+            sum / (BIN_SAMPLES * BIN_SAMPLES) as f64 * solid_angle * samples as f64
+        })
+        .collect();
+
+    let (mut statistic, mut bins) = (0.0, 0usize);
+    let (mut pooled_observed, mut pooled_expected) = (0.0, 0.0);
+    for (&observed, &expected) in observed.iter().zip(&expected) {
+        if expected <= 0.0 && observed > 0.0 {
+            // Samples where the density says there can't be any.
+            return ChiSquared {
+                statistic: f64::INFINITY,
+                degrees_of_freedom: 0,
+                p_value: 0.0,
+            };
+        }
+        if expected < MIN_EXPECTED {
+            pooled_observed += observed;
+            pooled_expected += expected;
+        } else {
+            statistic += (observed - expected) * (observed - expected) / expected;
+            bins += 1;
+        }
+    }
+    if pooled_expected > 0.0 {
+        statistic += (pooled_observed - pooled_expected) * (pooled_observed - pooled_expected)
+            / pooled_expected;
+        bins += 1;
+    }
+    let degrees_of_freedom = bins.saturating_sub(1).max(1);
+    ChiSquared {
+        statistic,
+        degrees_of_freedom,
+        p_value: gamma_q(degrees_of_freedom as f64 / 2.0, statistic / 2.0),
+    }
+}
+
+// `chi_squared` for the directions `material` scatters `r_in` into at `rec`,
+// whether it absorbs the light or not.
+pub fn chi_squared_scatter(
+    material: &impl Scatterable,
+    pdf: impl Fn(Vec3) -> f64,
+    r_in: &Ray,
+    rec: &HitRecord,
+    axis: Vec3,
+    samples: usize,
+) -> ChiSquared {
+    let sample = || {
+        let mut attenuation = Color::default();
+        let mut scattered = Ray::default();
+        material.scatter(r_in, rec, &mut attenuation, &mut scattered);
+        *scattered.direction()
+    };
+    chi_squared(sample, pdf, axis, samples)
+}
+
+// The average light `material` reflects back along `r_in` after one bounce
+// at `rec` under a white sky: its albedo for that direction, which must not
+// be above 1 in any channel. `furnace::furnace` averages it over a sphere.
+pub fn white_furnace(
+    material: &impl Scatterable,
+    r_in: &Ray,
+    rec: &HitRecord,
+    samples: usize,
+) -> Color {
+    let mut sum = Color::default();
+    for _ in 0..samples {
+        let mut attenuation = Color::default();
+        let mut scattered = Ray::default();
+        if material.scatter(r_in, rec, &mut attenuation, &mut scattered) {
+            sum += attenuation;
+        }
+    }
+    sum / samples as f64
+}
+
+// Whether `white_furnace` stays at or below 1, up to `tolerance` for noise.
+pub fn conserves_energy(
+    material: &impl Scatterable,
+    r_in: &Ray,
+    rec: &HitRecord,
+    samples: usize,
+    tolerance: f64,
+) -> bool {
+    let albedo = white_furnace(material, r_in, rec, samples);
+    [albedo.x(), albedo.y(), albedo.z()]
+        .into_iter()
+        .all(|channel| channel <= 1.0 + tolerance)
+}
+
+// Lanczos's approximation of ln Γ(x), for x > 0.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        0.1208650973866179e-2,
+        -0.5395239384953e-5,
+    ];
+    let tmp = x + 5.5;
+    let tmp = tmp - (x + 0.5) * tmp.ln();
+    let series = COEFFICIENTS
+        .iter()
+        .enumerate()
+        .fold(1.000000000190015, |sum, (i, c)| {
+            sum + c / (x + 1.0 + i as f64)
+        });
+    -tmp + (2.5066282746310005 * series / x).ln()
+}
+
+// The regularized upper incomplete gamma function Q(a, x), from its series
+// below a + 1 and its continued fraction above, as in Numerical Recipes. The
+// chi-squared distribution with k degrees of freedom is above s with
+// probability Q(k / 2, s / 2).
+fn gamma_q(a: f64, x: f64) -> f64 {
+    const ITERATIONS: usize = 1000;
+    const EPSILON: f64 = 1e-14;
+    const TINY: f64 = 1e-300;
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let log_prefactor = -x + a * x.ln() - ln_gamma(a);
+    if x < a + 1.0 {
+        let (mut term, mut sum) = (1.0 / a, 1.0 / a);
+        for n in 1..ITERATIONS {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * EPSILON {
+                break;
+            }
+        }
+        (1.0 - sum * log_prefactor.exp()).max(0.0)
+    } else {
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / TINY;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..ITERATIONS {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = b + an / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < EPSILON {
+                break;
+            }
+        }
+        log_prefactor.exp() * h
+    }
+}
+
+#[test]
+fn test_gamma_q() {
+    // With two degrees of freedom the chance is e^(-s/2).
+    for s in [0.5, 2.0, 10.0] {
+        assert!((gamma_q(1.0, s / 2.0) - (-s / 2.0).exp()).abs() < 1e-10);
+    }
+    // The 1% critical value of 10 degrees of freedom is 23.209.
+    assert!((gamma_q(5.0, 23.209 / 2.0) - 0.01).abs() < 1e-4);
+    assert!((ln_gamma(5.0) - 24.0f64.ln()).abs() < 1e-9);
+}
+
+#[test]
+fn test_chi_squared() {
+    use crate::material::{Lambertian, Material, Medium, Metal};
+
+    let rec = HitRecord {
+        normal: Vec3::new(0.0, 1.0, 0.0),
+        front_face: true,
+        ..HitRecord::default()
+    };
+    let wo = Vec3::new(1.0, 1.0, 0.0).unit_vector();
+    let r_in = Ray::new(wo, -wo);
+    let mirror = Vec3::reflect(&-wo, &rec.normal);
+    let materials = [
+        (
+            Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5))),
+            rec.normal,
+        ),
+        (
+            Material::Metal(Metal::new(Color::new(0.9, 0.9, 0.9), 1.0)),
+            mirror,
+        ),
+        (
+            Material::Medium(Medium {
+                anisotropy: 0.7,
+                ..Medium::new(Color::new(0.5, 0.5, 0.5))
+            }),
+            -wo,
+        ),
+    ];
+    for (i, (material, axis)) in materials.iter().enumerate() {
+        // Seeded, so that the one time in a hundred a right density fails
+        // doesn't fail the tests.
+        crate::rng::seed_sample(0, 0, 0, i);
+        let pdf = |wi| material.pdf(wi, wo, &rec);
+        let result = chi_squared_scatter(material, pdf, &r_in, &rec, *axis, 100_000);
+        assert!(result.passed(), "{} {:?}", material.kind(), result);
+
+        // A density off by a tenth is found out.
+        let wrong = |wi| 1.1 * pdf(wi);
+        let result = chi_squared_scatter(material, wrong, &r_in, &rec, *axis, 100_000);
+        assert!(!result.passed(), "{} {:?}", material.kind(), result);
+    }
+
+    // So are samples where the density has none.
+    let upper = |wi: Vec3| if wi.y() > 0.0 { 1.0 / (2.0 * PI) } else { 0.0 };
+    let result = chi_squared(Vec3::random_unit_vector, upper, rec.normal, 10_000);
+    assert_eq!(result.p_value, 0.0);
+}
+
+#[test]
+fn test_white_furnace() {
+    use crate::material::{Glass, Lambertian};
+
+    let rec = HitRecord {
+        normal: Vec3::new(0.0, 1.0, 0.0),
+        front_face: true,
+        ..HitRecord::default()
+    };
+    let r_in = Ray::new(Vec3::new(0.0, 1.0, 1.0), Vec3::new(0.0, -1.0, -1.0));
+    let grey = Lambertian::new(Color::new(0.5, 0.5, 0.5));
+    assert_eq!(white_furnace(&grey, &r_in, &rec, 100), grey.albedo);
+    assert!(conserves_energy(&grey, &r_in, &rec, 100, 0.0));
+    assert!(conserves_energy(&Glass::new(1.5), &r_in, &rec, 1000, 0.0));
+
+    let bright = Lambertian::new(Color::new(1.2, 0.5, 0.5));
+    assert!(!conserves_energy(&bright, &r_in, &rec, 100, 0.01));
+}
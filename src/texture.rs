@@ -0,0 +1,708 @@
+// Image textures, filtered by how much of the texture a ray's footprint
+// covers, so that far away or steeply tilted textures don't sparkle. Every
+// texture gets a mip chain, each level half the size of the one before, and
+// the filter picks the level whose texels are about as large as the
+// footprint. The footprint comes from the ray's cone: its width where it hits,
+// stretched by the angle it hits at, carried over to texture space with the
+// surface's derivatives of position along u and v. With a texture cache the
+// mip chain lives on disk instead, read back a tile at a time (see
+// texture_cache.rs).
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::film::{read_linear_image, Film};
+use crate::hittable::HitRecord;
+use crate::ray::Ray;
+use crate::texture_cache::{CachedImage, TextureCache};
+use crate::vec3::Vec3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TextureFilter {
+    // The single closest texel, for pixel art.
+    Nearest,
+    // The four closest texels of the full size image, ignoring the footprint.
+    Bilinear,
+    // Between the two levels closest to the footprint's longer axis, which
+    // blurs textures seen at an angle.
+    #[default]
+    Trilinear,
+    // Several trilinear lookups along the footprint's longer axis, each at
+    // the level of its shorter one.
+    Anisotropic,
+}
+
+fn default_max_anisotropy() -> usize {
+    8
+}
+
+// What lies beyond the edges of the image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Wrap {
+    // The image again.
+    #[default]
+    Repeat,
+    // The edge texels, stretched.
+    Clamp,
+    // The image again, flipped every other time so the edges meet.
+    Mirror,
+}
+
+impl Wrap {
+    // Texel `x` of a row or column of `size`.
+    fn apply(self, x: i64, size: usize) -> usize {
+        let size = size as i64;
+        let x = match self {
+            Wrap::Repeat => x.rem_euclid(size),
+            Wrap::Clamp => x.clamp(0, size - 1),
+            Wrap::Mirror => {
+                let x = x.rem_euclid(2 * size);
+                if x < size {
+                    x
+                } else {
+                    2 * size - 1 - x
+                }
+            }
+        };
+        x as usize
+    }
+}
+
+fn default_scale() -> [f64; 2] {
+    [1.0, 1.0]
+}
+
+// Moves the image on the surface: texture coordinates are scaled, then
+// turned around (0, 0), then offset.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct UvTransform {
+    // How many times the image repeats along u and v.
+    #[serde(default = "default_scale")]
+    pub scale: [f64; 2],
+    // Counterclockwise, in degrees.
+    #[serde(default)]
+    pub rotation: f64,
+    // In repeats of the image.
+    #[serde(default)]
+    pub offset: [f64; 2],
+}
+
+impl Default for UvTransform {
+    fn default() -> Self {
+        UvTransform {
+            scale: default_scale(),
+            rotation: 0.0,
+            offset: [0.0, 0.0],
+        }
+    }
+}
+
+impl UvTransform {
+    fn is_identity(&self) -> bool {
+        *self == UvTransform::default()
+    }
+
+    // The derivatives follow the coordinates: moving along the new u is
+    // moving along the old u and v by the inverse transform.
+    fn apply(&self, mapping: Mapping) -> Mapping {
+        let (sin, cos) = self.rotation.to_radians().sin_cos();
+        let [su, sv] = self.scale;
+        let (u, v) = (mapping.u * su, mapping.v * sv);
+        let inverse_u = (cos / su, -sin / sv);
+        let inverse_v = (sin / su, cos / sv);
+        Mapping {
+            u: cos * u - sin * v + self.offset[0],
+            v: sin * u + cos * v + self.offset[1],
+            dpdu: inverse_u.0 * mapping.dpdu + inverse_u.1 * mapping.dpdv,
+            dpdv: inverse_v.0 * mapping.dpdu + inverse_v.1 * mapping.dpdv,
+        }
+    }
+}
+
+fn default_size() -> f64 {
+    1.0
+}
+
+fn default_sharpness() -> f64 {
+    4.0
+}
+
+// Where on the image a point of a surface is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Projection {
+    // The surface's own texture coordinates.
+    #[default]
+    Uv,
+    // The image repeated along the three planes of the world axes, each seen
+    // by the parts of the surface that face its axis, for surfaces without
+    // texture coordinates.
+    Triplanar {
+        // Scene units one repeat of the image covers.
+        #[serde(default = "default_size")]
+        size: f64,
+        // How quickly the planes fade into each other where the surface
+        // turns; higher is a narrower seam.
+        #[serde(default = "default_sharpness")]
+        sharpness: f64,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageTexture {
+    pub file: String,
+    #[serde(default)]
+    pub filter: TextureFilter,
+    // Most lookups an anisotropic filter takes.
+    #[serde(default = "default_max_anisotropy")]
+    pub max_anisotropy: usize,
+    #[serde(default, skip_serializing_if = "is_uv")]
+    pub projection: Projection,
+    #[serde(default, skip_serializing_if = "UvTransform::is_identity")]
+    pub transform: UvTransform,
+    #[serde(default)]
+    pub wrap: Wrap,
+    // `file` relative to the scene file it is used in.
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+    #[serde(skip)]
+    pub mips: Option<Arc<MipChain>>,
+}
+
+impl ImageTexture {
+    pub fn new(file: &str, filter: TextureFilter) -> ImageTexture {
+        ImageTexture {
+            file: file.to_string(),
+            filter,
+            max_anisotropy: default_max_anisotropy(),
+            projection: Projection::Uv,
+            transform: UvTransform::default(),
+            wrap: Wrap::Repeat,
+            path: None,
+            mips: None,
+        }
+    }
+
+    // Finds the image next to the scene file in `base_dir`, to be loaded
+    // once the whole scene is, and returns its path.
+    pub fn resolve(&mut self, base_dir: &Path) -> PathBuf {
+        let path = base_dir.join(&self.file);
+        self.path = Some(path.clone());
+        path
+    }
+
+    pub fn load(&mut self, cache: Option<&Arc<TextureCache>>) -> io::Result<()> {
+        let path = self
+            .path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(&self.file));
+        self.mips = Some(Arc::new(MipChain::load(&path, cache)?));
+        Ok(())
+    }
+
+    // The color where `r` hit, white until loaded.
+    pub fn color(&self, r: &Ray, rec: &HitRecord) -> Color {
+        let Some(mips) = &self.mips else {
+            return Color::new(1.0, 1.0, 1.0);
+        };
+        let lookup = |mapping: &Mapping| {
+            let mapping = self.transform.apply(*mapping);
+            let (width, height) = mips.sizes()[0];
+            let (major, minor) = footprint(r, rec, &mapping, width, height);
+            mips.lookup(
+                self.filter,
+                self.max_anisotropy,
+                self.wrap,
+                (mapping.u, mapping.v),
+                major,
+                minor,
+            )
+        };
+        match self.projection {
+            Projection::Uv => lookup(&Mapping::from(rec)),
+            Projection::Triplanar { size, sharpness } => {
+                triplanar(rec.p, rec.normal, size, sharpness)
+                    .into_iter()
+                    .fold(Color::default(), |sum, (weight, mapping)| {
+                        sum + weight * lookup(&mapping)
+                    })
+            }
+        }
+    }
+}
+
+// Checks across each unit of texture space, for the UV checker override.
+const UV_CHECKS: f64 = 8.0;
+
+// A checkerboard for looking at texture coordinates: dark grey checks
+// between colored ones that get redder along u and greener along v, so the
+// layout's orientation, seams and stretching show at a glance.
+pub fn uv_checker(u: f64, v: f64) -> Color {
+    let (i, j) = (
+        (u.rem_euclid(1.0) * UV_CHECKS).floor(),
+        (v.rem_euclid(1.0) * UV_CHECKS).floor(),
+    );
+    if (i + j) % 2.0 == 1.0 {
+        return Color::new(0.1, 0.1, 0.1);
+    }
+    let ramp = |check: f64| 0.2 + 0.7 * (check + 0.5) / UV_CHECKS;
+    Color::new(ramp(i), ramp(j), 0.5)
+}
+
+fn is_uv(projection: &Projection) -> bool {
+    *projection == Projection::Uv
+}
+
+// Texture coordinates at a point and how the point moves as they change.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Mapping {
+    u: f64,
+    v: f64,
+    dpdu: Vec3,
+    dpdv: Vec3,
+}
+
+impl From<&HitRecord> for Mapping {
+    fn from(rec: &HitRecord) -> Self {
+        Mapping {
+            u: rec.u,
+            v: rec.v,
+            dpdu: rec.dpdu,
+            dpdv: rec.dpdv,
+        }
+    }
+}
+
+// The planes that the normal `n` at `p` faces, with how much each counts.
+// Each plane is seen from outside, so that images on the faces pointing
+// away from an axis aren't mirrored.
+fn triplanar(p: Vec3, n: Vec3, size: f64, sharpness: f64) -> Vec<(f64, Mapping)> {
+    let weights = [n.x(), n.y(), n.z()].map(|c| c.abs().powf(sharpness));
+    let total: f64 = weights.iter().sum();
+    let plane = |u: Vec3, v: Vec3| Mapping {
+        u: p.dot(&u) / size,
+        v: p.dot(&v) / size,
+        dpdu: size * u,
+        dpdv: size * v,
+    };
+    let up = Vec3::new(0.0, 1.0, 0.0);
+    let planes = [
+        plane(Vec3::new(0.0, 0.0, -n.x().signum()), up),
+        plane(
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -n.y().signum()),
+        ),
+        plane(Vec3::new(n.z().signum(), 0.0, 0.0), up),
+    ];
+    let kept: Vec<_> = weights
+        .into_iter()
+        .zip(planes)
+        .filter(|(weight, _)| *weight > 1e-3 * total)
+        .collect();
+    let sum: f64 = kept.iter().map(|(weight, _)| weight).sum();
+    kept.into_iter()
+        .map(|(weight, mapping)| (weight / sum, mapping))
+        .collect()
+}
+
+// The axes of a ray's footprint on the surface in texels, the longer first.
+fn footprint(
+    r: &Ray,
+    rec: &HitRecord,
+    mapping: &Mapping,
+    width: usize,
+    height: usize,
+) -> ((f64, f64), (f64, f64)) {
+    let width_at_hit = r.cone_width(rec.t);
+    let to_texels = |v: Vec3| {
+        let along = |dp: Vec3| {
+            let length_squared = dp.length_squared();
+            if length_squared > 0.0 {
+                v.dot(&dp) / length_squared
+            } else {
+                0.0
+            }
+        };
+        (
+            along(mapping.dpdu) * width as f64,
+            along(mapping.dpdv) * height as f64,
+        )
+    };
+    let d = r.direction().unit_vector();
+    let n = rec.normal;
+    let cosine = d.dot(&n).abs().max(0.01);
+    // Along the tilt the footprint stretches by 1 / cos, across it it doesn't.
+    let along_tilt = (d - d.dot(&n) * n)
+        .or_if_near_zero(mapping.dpdu)
+        .unit_vector();
+    let across = n.cross(&along_tilt);
+    (
+        to_texels(width_at_hit / cosine * along_tilt),
+        to_texels(width_at_hit * across),
+    )
+}
+
+// The width and height of every level of a mip chain, each half the one
+// before rounding up, down to a single texel.
+pub fn mip_sizes(width: usize, height: usize) -> Vec<(usize, usize)> {
+    let mut sizes = vec![(width, height)];
+    while sizes.last() != Some(&(1, 1)) {
+        let (width, height) = sizes.last().unwrap();
+        sizes.push((width.div_ceil(2), height.div_ceil(2)));
+    }
+    sizes
+}
+
+// An image and its smaller copies, down to a single texel.
+#[derive(Debug)]
+pub struct MipChain {
+    sizes: Vec<(usize, usize)>,
+    texels: Texels,
+}
+
+#[derive(Debug)]
+enum Texels {
+    Resident(Vec<Film>),
+    Cached(CachedImage),
+}
+
+impl MipChain {
+    pub fn new(image: Film) -> MipChain {
+        let sizes = mip_sizes(image.width, image.height);
+        let mut levels = vec![image];
+        for &(width, height) in &sizes[1..] {
+            let smaller = levels.last().unwrap().downsample(width, height);
+            levels.push(smaller);
+        }
+        MipChain {
+            sizes,
+            texels: Texels::Resident(levels),
+        }
+    }
+
+    pub fn cached(image: CachedImage) -> MipChain {
+        MipChain {
+            sizes: image.sizes().to_vec(),
+            texels: Texels::Cached(image),
+        }
+    }
+
+    // Decodes the image at `path` now, or only reads its size if there is a
+    // cache to load it through when it is first looked up.
+    pub fn load(path: &Path, cache: Option<&Arc<TextureCache>>) -> io::Result<MipChain> {
+        let filename = path.to_string_lossy();
+        let mips = match cache {
+            Some(cache) => CachedImage::open(path, cache).map(MipChain::cached),
+            None => read_linear_image(&filename).map(MipChain::new),
+        };
+        mips.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn sizes(&self) -> &[(usize, usize)] {
+        &self.sizes
+    }
+
+    // The texel at (x, y) of `level`, which may be outside the image.
+    pub fn texel(&self, level: usize, x: i64, y: i64, wrap: Wrap) -> Color {
+        let (width, height) = self.sizes[level];
+        let x = wrap.apply(x, width);
+        let y = wrap.apply(y, height);
+        match &self.texels {
+            Texels::Resident(levels) => levels[level].get(x, y),
+            Texels::Cached(image) => image.texel(level, x, y),
+        }
+    }
+
+    // Texture coordinates have v going up, images rows going down.
+    fn bilinear(&self, level: usize, wrap: Wrap, u: f64, v: f64) -> Color {
+        let (width, height) = self.sizes[level];
+        let x = u * width as f64 - 0.5;
+        let y = (1.0 - v) * height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let texel = |x, y| self.texel(level, x, y, wrap);
+        let top = (1.0 - fx) * texel(x0, y0) + fx * texel(x0 + 1, y0);
+        let bottom = (1.0 - fx) * texel(x0, y0 + 1) + fx * texel(x0 + 1, y0 + 1);
+        (1.0 - fy) * top + fy * bottom
+    }
+
+    // Between the levels around `size` texels of the full image.
+    fn trilinear(&self, size: f64, wrap: Wrap, u: f64, v: f64) -> Color {
+        let lod = size.max(1.0).log2().min((self.sizes.len() - 1) as f64);
+        let level = lod.floor() as usize;
+        let t = lod - level as f64;
+        if t == 0.0 {
+            return self.bilinear(level, wrap, u, v);
+        }
+        (1.0 - t) * self.bilinear(level, wrap, u, v) + t * self.bilinear(level + 1, wrap, u, v)
+    }
+
+    // The filtered color at (u, v) for a footprint with axes `major` and
+    // `minor` in texels.
+    pub fn lookup(
+        &self,
+        filter: TextureFilter,
+        max_anisotropy: usize,
+        wrap: Wrap,
+        (u, v): (f64, f64),
+        major: (f64, f64),
+        minor: (f64, f64),
+    ) -> Color {
+        let length = |(x, y): (f64, f64)| x.hypot(y);
+        let (major, minor) = if length(major) >= length(minor) {
+            (major, minor)
+        } else {
+            (minor, major)
+        };
+        match filter {
+            TextureFilter::Nearest => {
+                let (width, height) = self.sizes[0];
+                let x = (u * width as f64).floor() as i64;
+                let y = ((1.0 - v) * height as f64).floor() as i64;
+                self.texel(0, x, y, wrap)
+            }
+            TextureFilter::Bilinear => self.bilinear(0, wrap, u, v),
+            TextureFilter::Trilinear => self.trilinear(length(major), wrap, u, v),
+            TextureFilter::Anisotropic => {
+                let max = max_anisotropy.max(1) as f64;
+                let count = (length(major) / length(minor).max(1e-9))
+                    .ceil()
+                    .clamp(1.0, max);
+                let size = length(minor).max(length(major) / max);
+                // Spread evenly over the longer axis, in texture coordinates.
+                let (width, height) = self.sizes[0];
+                let (du, dv) = (major.0 / width as f64, major.1 / height as f64);
+                let n = count as usize;
+                let sum = (0..n).fold(Color::default(), |sum, i| {
+                    let s = (i as f64 + 0.5) / count - 0.5;
+                    sum + self.trilinear(size, wrap, u + s * du, v + s * dv)
+                });
+                sum / count
+            }
+        }
+    }
+}
+
+#[test]
+fn test_mip_chain() {
+    // A 4x2 checkerboard averages to grey at the smaller levels.
+    let mut image = Film::new(4, 2);
+    for (i, pixel) in image.pixels.iter_mut().enumerate() {
+        if (i + i / 4) % 2 == 0 {
+            *pixel = Color::new(1.0, 1.0, 1.0);
+        }
+    }
+    let mips = MipChain::new(image);
+    assert_eq!(mips.sizes(), [(4, 2), (2, 1), (1, 1)]);
+    assert_eq!(mips.texel(2, 0, 0, Wrap::Repeat), Color::new(0.5, 0.5, 0.5));
+
+    // Texel centers, and wrapping around.
+    let (u, v) = (0.125, 0.75);
+    let none = (0.0, 0.0);
+    let white = Color::new(1.0, 1.0, 1.0);
+    assert_eq!(
+        mips.lookup(TextureFilter::Nearest, 8, Wrap::Repeat, (u, v), none, none),
+        white
+    );
+    assert_eq!(
+        mips.lookup(
+            TextureFilter::Bilinear,
+            8,
+            Wrap::Repeat,
+            (u + 1.0, v),
+            none,
+            none
+        ),
+        white
+    );
+    assert_eq!(
+        mips.lookup(
+            TextureFilter::Trilinear,
+            8,
+            Wrap::Repeat,
+            (u, v),
+            none,
+            none
+        ),
+        white
+    );
+    // A footprint of a few texels is averaged out.
+    let grey = mips.lookup(
+        TextureFilter::Trilinear,
+        8,
+        Wrap::Repeat,
+        (u, v),
+        (4.0, 0.0),
+        (4.0, 0.0),
+    );
+    assert_eq!(grey, Color::new(0.5, 0.5, 0.5));
+}
+
+#[test]
+fn test_anisotropic() {
+    // Vertical stripes, one texel wide.
+    let mut image = Film::new(64, 64);
+    for (i, pixel) in image.pixels.iter_mut().enumerate() {
+        if i % 2 == 0 {
+            *pixel = Color::new(1.0, 1.0, 1.0);
+        }
+    }
+    let mips = MipChain::new(image);
+    let (u, v) = (0.5 / 64.0, 0.5);
+
+    // A footprint long along the stripes and thin across them: the
+    // anisotropic filter still tells them apart, trilinear blurs them away.
+    let (major, minor) = ((0.0, 8.0), (0.5, 0.0));
+    let trilinear = mips.lookup(
+        TextureFilter::Trilinear,
+        8,
+        Wrap::Repeat,
+        (u, v),
+        major,
+        minor,
+    );
+    let anisotropic = mips.lookup(
+        TextureFilter::Anisotropic,
+        8,
+        Wrap::Repeat,
+        (u, v),
+        major,
+        minor,
+    );
+    assert!((trilinear.x() - 0.5).abs() < 1e-9, "{:?}", trilinear);
+    assert!(anisotropic.x() > 0.99, "{:?}", anisotropic);
+    // Across the stripes both average them.
+    let anisotropic = mips.lookup(
+        TextureFilter::Anisotropic,
+        8,
+        Wrap::Repeat,
+        (u, v),
+        (8.0, 0.0),
+        (0.0, 0.5),
+    );
+    assert!((anisotropic.x() - 0.5).abs() < 0.1, "{:?}", anisotropic);
+}
+
+#[test]
+fn test_footprint() {
+    // A plane facing +z whose texture repeats every 2 units.
+    let rec = HitRecord {
+        t: 10.0,
+        normal: Vec3::new(0.0, 0.0, 1.0),
+        dpdu: Vec3::new(2.0, 0.0, 0.0),
+        dpdv: Vec3::new(0.0, 2.0, 0.0),
+        ..HitRecord::default()
+    };
+    let straight =
+        Ray::new(Vec3::new(0.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0)).with_cone(0.0, 0.01);
+    // 0.1 units wide, a twentieth of the texture's size.
+    let (major, minor) = footprint(&straight, &rec, &Mapping::from(&rec), 200, 100);
+    assert!(
+        (major.0.abs() - 10.0).abs() < 1e-9 && major.1 == 0.0,
+        "{:?}",
+        major
+    );
+    assert!((minor.1.abs() - 5.0).abs() < 1e-9, "{:?}", minor);
+
+    // At 60 degrees the footprint is twice as long along the tilt.
+    let tilted =
+        Ray::new(Vec3::default(), Vec3::new(0.0, 3f64.sqrt() / 2.0, -0.5)).with_cone(0.0, 0.01);
+    let (major, minor) = footprint(&tilted, &rec, &Mapping::from(&rec), 100, 100);
+    assert!((major.1.abs() - 10.0).abs() < 1e-6, "{:?}", major);
+    assert!((minor.0.abs() - 5.0).abs() < 1e-6, "{:?}", minor);
+}
+
+#[test]
+fn test_triplanar() {
+    let p = Vec3::new(0.5, 0.25, 3.0);
+    // Facing +z: only the xy plane, with u along x.
+    let planes = triplanar(p, Vec3::new(0.0, 0.0, 1.0), 2.0, 4.0);
+    assert_eq!(planes.len(), 1);
+    let (weight, mapping) = planes[0];
+    assert_eq!((weight, mapping.u, mapping.v), (1.0, 0.25, 0.125));
+    assert_eq!(mapping.dpdu, Vec3::new(2.0, 0.0, 0.0));
+    // Seen from -z, x goes the other way.
+    let (_, mapping) = triplanar(p, Vec3::new(0.0, 0.0, -1.0), 2.0, 4.0)[0];
+    assert_eq!(mapping.u, -0.25);
+
+    // Halfway between two planes both count as much.
+    let n = Vec3::new(1.0, 1.0, 0.0).unit_vector();
+    let planes = triplanar(p, n, 1.0, 4.0);
+    assert_eq!(planes.len(), 2);
+    assert!((planes[0].0 - 0.5).abs() < 1e-12 && (planes[1].0 - 0.5).abs() < 1e-12);
+
+    // A shape without texture coordinates still shows the image.
+    let mut image = Film::new(2, 1);
+    image.pixels = vec![Color::new(1.0, 0.0, 0.0), Color::new(0.0, 0.0, 1.0)];
+    let mut texture = ImageTexture::new("map.png", TextureFilter::Nearest);
+    texture.mips = Some(Arc::new(MipChain::new(image)));
+    texture.projection = Projection::Triplanar {
+        size: 2.0,
+        sharpness: 4.0,
+    };
+    let r = Ray::new(Vec3::new(0.0, 0.0, 10.0), Vec3::new(0.0, 0.0, -1.0));
+    let color = |x: f64| {
+        let rec = HitRecord {
+            p: Vec3::new(x, 0.0, 0.0),
+            normal: Vec3::new(0.0, 0.0, 1.0),
+            ..HitRecord::default()
+        };
+        texture.color(&r, &rec)
+    };
+    assert_eq!(color(0.5), Color::new(1.0, 0.0, 0.0));
+    assert_eq!(color(1.5), Color::new(0.0, 0.0, 1.0));
+}
+
+#[test]
+fn test_uv_transform() {
+    let transform = UvTransform {
+        scale: [2.0, 4.0],
+        rotation: 90.0,
+        offset: [0.5, 0.0],
+    };
+    // A plane where p = (u, v, 0).
+    let at = |u: f64, v: f64| Mapping {
+        u,
+        v,
+        dpdu: Vec3::new(1.0, 0.0, 0.0),
+        dpdv: Vec3::new(0.0, 1.0, 0.0),
+    };
+    let mapping = transform.apply(at(0.25, 0.125));
+    assert!((mapping.u - 0.0).abs() < 1e-12 && (mapping.v - 0.5).abs() < 1e-12);
+    // Moving along the derivatives moves the new coordinates by as much.
+    let p = Vec3::new(0.25, 0.125, 0.0) + 0.3 * mapping.dpdu + 0.7 * mapping.dpdv;
+    let moved = transform.apply(at(p.x(), p.y()));
+    assert!((moved.u - (mapping.u + 0.3)).abs() < 1e-12);
+    assert!((moved.v - (mapping.v + 0.7)).abs() < 1e-12);
+    assert_eq!(UvTransform::default().apply(at(0.3, 0.6)), at(0.3, 0.6));
+}
+
+#[test]
+fn test_uv_checker() {
+    let (dark, first) = (Color::new(0.1, 0.1, 0.1), uv_checker(0.01, 0.01));
+    assert_eq!(uv_checker(0.99 / UV_CHECKS, 0.5 / UV_CHECKS), first);
+    assert_eq!(uv_checker(1.5 / UV_CHECKS, 0.5 / UV_CHECKS), dark);
+    assert_eq!(uv_checker(0.5 / UV_CHECKS, 1.5 / UV_CHECKS), dark);
+    // Redder along u, greener along v.
+    let along_u = uv_checker(2.5 / UV_CHECKS, 0.5 / UV_CHECKS);
+    let along_v = uv_checker(0.5 / UV_CHECKS, 2.5 / UV_CHECKS);
+    assert!(along_u.x() > first.x() && along_u.y() == first.y());
+    assert!(along_v.y() > first.y() && along_v.x() == first.x());
+    // It repeats outside the unit square.
+    assert_eq!(uv_checker(1.01, -0.99), first);
+}
+
+#[test]
+fn test_wrap() {
+    let wrapped = |wrap: Wrap| [-2, -1, 0, 3, 4, 5].map(|x| wrap.apply(x, 4));
+    assert_eq!(wrapped(Wrap::Repeat), [2, 3, 0, 3, 0, 1]);
+    assert_eq!(wrapped(Wrap::Clamp), [0, 0, 0, 3, 3, 3]);
+    assert_eq!(wrapped(Wrap::Mirror), [1, 0, 0, 3, 3, 2]);
+}
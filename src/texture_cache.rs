@@ -0,0 +1,493 @@
+// A memory budget for scenes with more, or larger, image textures than fit in
+// memory at once. A texture behind the cache isn't decoded at scene load, only
+// its size is read. The first lookup decodes it, builds its mip chain and
+// writes every level cut into square tiles to a file in the temporary
+// directory; from then on lookups read single tiles back from there. The
+// cache keeps the tiles used most recently in memory and drops the least
+// recently used ones once the tiles of all its textures go over the budget.
+//
+// The budget is for tiles. Tiling a texture on first use needs the whole
+// decoded image, 24 bytes a texel, and the next mip level next to it, on top
+// of the budget. Only one texture is tiled at a time, so that peak is set
+// by the largest texture alone.
+//
+// Lookups come from every render thread at once. Large caches are split into
+// shards by tile, each with its own lock and its share of the budget and
+// dropping its own least recently used tiles, and every thread remembers the
+// tile it used last, which neighboring lookups of a filtered texel usually
+// hit again without locking at all. Tiles are read from their file at their
+// offset, without a lock.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::color::Color;
+use crate::film::{read_image_size, read_linear_image, Film};
+use crate::texture::mip_sizes;
+
+// Width and height of the tiles, in texels.
+pub const TILE: usize = 32;
+// Texels are stored as three 32-bit floats.
+const TILE_BYTES: usize = TILE * TILE * 3 * 4;
+
+// Caches are only split where each shard still holds this many tiles, so
+// small budgets keep a single least recently used order.
+const SHARD_TILES: usize = 64;
+const MAX_SHARDS: usize = 16;
+
+// One texture is tiled at a time.
+static TILING: Mutex<()> = Mutex::new(());
+
+fn default_memory_mb() -> usize {
+    256
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TextureCacheSettings {
+    // How much memory the tiles of all textures may take together.
+    #[serde(default = "default_memory_mb")]
+    pub memory_mb: usize,
+}
+
+impl Default for TextureCacheSettings {
+    fn default() -> Self {
+        TextureCacheSettings {
+            memory_mb: default_memory_mb(),
+        }
+    }
+}
+
+// Image, mip level, column and row.
+type TileKey = (usize, usize, usize, usize);
+type Tile = Arc<[[f32; 3]]>;
+
+#[derive(Debug, Default)]
+struct Tiles {
+    // Every tile in memory with when it was last used.
+    tiles: HashMap<TileKey, (Tile, u64)>,
+    // The same tiles by when they were last used, the least recent first.
+    by_use: BTreeMap<u64, TileKey>,
+    clock: u64,
+    // Tiles read from disk, counting those read again after being dropped.
+    reads: usize,
+}
+
+impl Tiles {
+    fn touch(&mut self, key: TileKey) -> Option<Tile> {
+        let (tile, used) = self.tiles.get_mut(&key)?;
+        self.clock += 1;
+        self.by_use.remove(used);
+        self.by_use.insert(self.clock, key);
+        *used = self.clock;
+        Some(tile.clone())
+    }
+
+    fn insert(&mut self, key: TileKey, tile: Tile) {
+        self.clock += 1;
+        self.tiles.insert(key, (tile, self.clock));
+        self.by_use.insert(self.clock, key);
+    }
+}
+
+thread_local! {
+    // The tile this thread looked up last, by cache.
+    static LAST_TILE: RefCell<Option<(usize, TileKey, Tile)>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug)]
+pub struct TextureCache {
+    id: usize,
+    // Of each shard.
+    budget: usize,
+    shards: Vec<Mutex<Tiles>>,
+}
+
+impl TextureCache {
+    pub fn new(settings: TextureCacheSettings) -> TextureCache {
+        TextureCache::with_budget(settings.memory_mb << 20)
+    }
+
+    pub fn with_budget(bytes: usize) -> TextureCache {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let shards = (bytes / TILE_BYTES / SHARD_TILES).clamp(1, MAX_SHARDS);
+        TextureCache {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            budget: bytes / shards,
+            shards: (0..shards).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    fn shard(&self, key: TileKey) -> &Mutex<Tiles> {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        key.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % self.shards.len()]
+    }
+
+    // Bytes taken by the tiles in memory.
+    pub fn memory(&self) -> usize {
+        let tiles: usize = self
+            .shards
+            .iter()
+            .map(|s| s.lock().unwrap().tiles.len())
+            .sum();
+        tiles * TILE_BYTES
+    }
+
+    // How many tiles were read from disk so far.
+    pub fn reads(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().reads).sum()
+    }
+
+    fn tile(&self, key: TileKey, read: impl FnOnce() -> Tile) -> Tile {
+        let last = LAST_TILE.with_borrow(|last| match last {
+            Some((cache, last_key, tile)) if *cache == self.id && *last_key == key => {
+                Some(tile.clone())
+            }
+            _ => None,
+        });
+        if let Some(tile) = last {
+            return tile;
+        }
+        let tile = self.shared_tile(key, read);
+        LAST_TILE.set(Some((self.id, key, tile.clone())));
+        tile
+    }
+
+    fn shared_tile(&self, key: TileKey, read: impl FnOnce() -> Tile) -> Tile {
+        let shard = self.shard(key);
+        if let Some(tile) = shard.lock().unwrap().touch(key) {
+            return tile;
+        }
+        // Other threads go on looking up tiles while this one reads, and may
+        // even read the same tile.
+        let tile = read();
+        let mut tiles = shard.lock().unwrap();
+        tiles.reads += 1;
+        if let Some(tile) = tiles.touch(key) {
+            return tile;
+        }
+        tiles.insert(key, tile.clone());
+        // The tile just read stays, however small the budget.
+        while tiles.tiles.len() > 1 && tiles.tiles.len() * TILE_BYTES > self.budget {
+            let (_, oldest) = tiles.by_use.pop_first().unwrap();
+            tiles.tiles.remove(&oldest);
+        }
+        tile
+    }
+
+    fn forget(&self, image: usize) {
+        for shard in &self.shards {
+            let mut tiles = shard.lock().unwrap();
+            let Tiles { tiles, by_use, .. } = &mut *tiles;
+            tiles.retain(|key, (_, used)| {
+                if key.0 == image {
+                    by_use.remove(used);
+                }
+                key.0 != image
+            });
+        }
+        LAST_TILE.with_borrow_mut(|last| {
+            if matches!(last, Some((cache, key, _)) if *cache == self.id && key.0 == image) {
+                *last = None;
+            }
+        });
+    }
+}
+
+// Reads `bytes.len()` bytes at `offset`, with other threads reading the same
+// file at other offsets.
+#[cfg(unix)]
+fn read_at(file: &File, bytes: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, bytes, offset)
+}
+
+#[cfg(windows)]
+fn read_at(file: &File, mut bytes: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !bytes.is_empty() {
+        match file.seek_read(bytes, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            n => {
+                bytes = &mut bytes[n..];
+                offset += n as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Elsewhere the file position is shared, so reads take turns.
+#[cfg(not(any(unix, windows)))]
+fn read_at(file: &File, bytes: &mut [u8], offset: u64) -> io::Result<()> {
+    use std::io::{Read, Seek, SeekFrom};
+    static READING: Mutex<()> = Mutex::new(());
+    let _reading = READING.lock().unwrap();
+    let mut file = file;
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(bytes)
+}
+
+// An image file whose mip levels are read through a cache a tile at a time.
+#[derive(Debug)]
+pub struct CachedImage {
+    id: usize,
+    source: PathBuf,
+    sizes: Vec<(usize, usize)>,
+    cache: Arc<TextureCache>,
+    tiles_path: PathBuf,
+    // Written on first use; None if the image couldn't be decoded.
+    tiles: OnceLock<Option<File>>,
+}
+
+impl CachedImage {
+    // Reads only the size of the image.
+    pub fn open(path: &Path, cache: &Arc<TextureCache>) -> io::Result<CachedImage> {
+        let (width, height) = read_image_size(&path.to_string_lossy())?;
+        if width == 0 || height == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the image is empty",
+            ));
+        }
+        // Unique in the process, even between caches.
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        Ok(CachedImage {
+            id,
+            source: path.to_path_buf(),
+            sizes: mip_sizes(width, height),
+            cache: cache.clone(),
+            tiles_path: std::env::temp_dir().join(format!(
+                "raytracer-{}-texture-{}.tiles",
+                std::process::id(),
+                id
+            )),
+            tiles: OnceLock::new(),
+        })
+    }
+
+    // Width and height of every mip level, the full image first.
+    pub fn sizes(&self) -> &[(usize, usize)] {
+        &self.sizes
+    }
+
+    // (x, y) must be inside `level`.
+    pub fn texel(&self, level: usize, x: usize, y: usize) -> Color {
+        let (column, row) = (x / TILE, y / TILE);
+        let tile = self.cache.tile((self.id, level, column, row), || {
+            self.read_tile(level, column, row)
+        });
+        let [r, g, b] = tile[(y % TILE) * TILE + x % TILE];
+        Color::new(r as f64, g as f64, b as f64)
+    }
+
+    // Magenta where the image can't be read, which the error log explains.
+    fn read_tile(&self, level: usize, column: usize, row: usize) -> Tile {
+        let file = self.tiles.get_or_init(|| match self.write_tiles() {
+            Ok(file) => Some(file),
+            Err(e) => {
+                tracing::error!(path = %self.source.display(), "can't read texture: {}", e);
+                None
+            }
+        });
+        let mut bytes = vec![0; TILE_BYTES];
+        let read = file
+            .as_ref()
+            .map(|file| read_at(file, &mut bytes, self.offset(level, column, row)));
+        match read {
+            Some(Ok(())) => bytes
+                .chunks(12)
+                .map(|texel| {
+                    let value = |i: usize| f32::from_le_bytes(texel[i..i + 4].try_into().unwrap());
+                    [value(0), value(4), value(8)]
+                })
+                .collect(),
+            Some(Err(e)) => {
+                tracing::error!(path = %self.source.display(), "can't read texture tile: {}", e);
+                vec![[1.0, 0.0, 1.0]; TILE * TILE].into()
+            }
+            None => vec![[1.0, 0.0, 1.0]; TILE * TILE].into(),
+        }
+    }
+
+    // Where a tile starts in the tiles file. Levels follow each other, each
+    // with its tiles row by row, and the tiles at the right and bottom edges
+    // are padded to full size.
+    fn offset(&self, level: usize, column: usize, row: usize) -> u64 {
+        let tiles = |(width, height): (usize, usize)| width.div_ceil(TILE) * height.div_ceil(TILE);
+        let before: usize = self.sizes[..level].iter().map(|&size| tiles(size)).sum();
+        let columns = self.sizes[level].0.div_ceil(TILE);
+        ((before + row * columns + column) * TILE_BYTES) as u64
+    }
+
+    fn write_tiles(&self) -> io::Result<File> {
+        let _tiling = TILING.lock().unwrap_or_else(|e| e.into_inner());
+        let _span = tracing::info_span!("tile texture", path = %self.source.display()).entered();
+        let mut level = read_linear_image(&self.source.to_string_lossy())?;
+        if (level.width, level.height) != self.sizes[0] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "the image changed size since the scene was loaded",
+            ));
+        }
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.tiles_path)?;
+        let mut output = BufWriter::new(&file);
+        // One level at a time, each made from the one before.
+        for (i, &(width, height)) in self.sizes.iter().enumerate() {
+            if i > 0 {
+                level = level.downsample(width, height);
+            }
+            write_level(&mut output, &level)?;
+        }
+        output.flush()?;
+        drop(output);
+        Ok(file)
+    }
+}
+
+impl Drop for CachedImage {
+    fn drop(&mut self) {
+        self.cache.forget(self.id);
+        if self.tiles.get().is_some() {
+            let _ = fs::remove_file(&self.tiles_path);
+        }
+    }
+}
+
+fn write_level(output: &mut impl Write, level: &Film) -> io::Result<()> {
+    for row in 0..level.height.div_ceil(TILE) {
+        for column in 0..level.width.div_ceil(TILE) {
+            for y in row * TILE..(row + 1) * TILE {
+                for x in column * TILE..(column + 1) * TILE {
+                    let texel = if x < level.width && y < level.height {
+                        level.get(x, y)
+                    } else {
+                        Color::default()
+                    };
+                    for value in [texel.x(), texel.y(), texel.z()] {
+                        output.write_all(&(value as f32).to_le_bytes())?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_least_recently_used() {
+    let cache = TextureCache::with_budget(2 * TILE_BYTES);
+    let tile = |value: f32| -> Tile { vec![[value; 3]; TILE * TILE].into() };
+    let get = |i: usize| cache.tile((0, 0, i, 0), || tile(i as f32))[0][0];
+
+    assert_eq!(get(0), 0.0);
+    assert_eq!(get(1), 1.0);
+    // Tile 0 is used again, so tile 1 is the one dropped for tile 2.
+    assert_eq!(get(0), 0.0);
+    assert_eq!(get(2), 2.0);
+    assert_eq!(cache.reads(), 3);
+    assert_eq!(cache.memory(), 2 * TILE_BYTES);
+    get(0);
+    assert_eq!(cache.reads(), 3);
+    get(1);
+    assert_eq!(cache.reads(), 4);
+
+    cache.forget(0);
+    assert_eq!(cache.memory(), 0);
+    assert!(cache.shards[0].lock().unwrap().by_use.is_empty());
+    // The tile this thread used last is forgotten too.
+    assert_eq!(get(1), 1.0);
+    assert_eq!(cache.reads(), 5);
+}
+
+#[test]
+fn test_shards() {
+    // Small budgets keep one order of use, large ones are split.
+    assert_eq!(TextureCache::with_budget(2 * TILE_BYTES).shards.len(), 1);
+    let cache = TextureCache::with_budget(4 * SHARD_TILES * TILE_BYTES);
+    assert_eq!(cache.shards.len(), 4);
+    let tile = |value: f32| -> Tile { vec![[value; 3]; TILE * TILE].into() };
+
+    // Looked up from many threads, each tile is read about once and the
+    // cache stays within its budget.
+    let tiles = 3 * SHARD_TILES;
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                for i in 0..tiles {
+                    let value = cache.tile((0, 0, i, 0), || tile(i as f32))[0][0];
+                    assert_eq!(value, i as f32);
+                }
+            });
+        }
+    });
+    assert!(cache.reads() >= tiles && cache.reads() <= 4 * tiles);
+    assert!(cache.memory() <= 4 * SHARD_TILES * TILE_BYTES);
+    // The same tile again, from the thread's last tile.
+    let reads = cache.reads();
+    cache.tile((0, 0, 7, 0), || tile(7.0));
+    cache.tile((0, 0, 7, 0), || unreachable!());
+    assert!(cache.reads() <= reads + 1);
+}
+
+#[cfg(feature = "image")]
+#[test]
+fn test_cached_image() {
+    use crate::texture::{MipChain, Wrap};
+
+    // Larger than a tile in both directions, with partial tiles at the edges.
+    let (width, height) = (TILE * 2 + 5, TILE + 3);
+    let mut pixels = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            pixels.extend([(x * 3) as u8, (y * 5) as u8, ((x + y) % 2 * 255) as u8]);
+        }
+    }
+    let path = std::env::temp_dir().join(format!("raytracer-cached-{}.png", std::process::id()));
+    crate::film::write_image(&path.to_string_lossy(), &pixels, (width, height)).unwrap();
+
+    let cache = Arc::new(TextureCache::with_budget(3 * TILE_BYTES));
+    let image = CachedImage::open(&path, &cache).unwrap();
+    // Nothing is decoded until the first lookup.
+    assert!(image.tiles.get().is_none());
+    let resident = MipChain::new(read_linear_image(&path.to_string_lossy()).unwrap());
+    assert_eq!(image.sizes(), resident.sizes());
+
+    for (level, &(width, height)) in image.sizes().iter().enumerate() {
+        for y in 0..height {
+            for x in 0..width {
+                let cached = image.texel(level, x, y);
+                let expected = resident.texel(level, x as i64, y as i64, Wrap::Repeat);
+                assert!((cached - expected).length() < 1e-6, "{} {} {}", level, x, y);
+            }
+        }
+    }
+    // A row of tiles fits in the budget, so every tile was read once.
+    assert!(cache.memory() <= 3 * TILE_BYTES);
+    let tiles: usize = image
+        .sizes()
+        .iter()
+        .map(|(width, height)| width.div_ceil(TILE) * height.div_ceil(TILE))
+        .sum();
+    assert_eq!(cache.reads(), tiles);
+
+    let tiles_path = image.tiles_path.clone();
+    assert!(tiles_path.exists());
+    drop(image);
+    assert!(!tiles_path.exists());
+    assert_eq!(cache.memory(), 0);
+    fs::remove_file(&path).unwrap();
+}
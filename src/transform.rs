@@ -0,0 +1,256 @@
+// Affine transforms as 4x4 matrices acting on column vectors, so `a * b`
+// applies `b` first. A `Transform` keeps the inverse next to the matrix
+// because rays are moved into object space and normals back out on every hit.
+
+use std::ops::Mul;
+
+use crate::quat::Quat;
+use crate::vec3::{Point3D, Vec3};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4(pub [[f64; 4]; 4]);
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Mat4::IDENTITY
+    }
+}
+
+impl Mat4 {
+    pub const IDENTITY: Mat4 = Mat4([
+        [1.0, 0.0, 0.0, 0.0],
+        [0.0, 1.0, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0],
+    ]);
+
+    pub fn translate(d: Vec3) -> Mat4 {
+        let mut m = Mat4::IDENTITY;
+        m.0[0][3] = d.x();
+        m.0[1][3] = d.y();
+        m.0[2][3] = d.z();
+        m
+    }
+
+    pub fn scale(s: Vec3) -> Mat4 {
+        let mut m = Mat4::IDENTITY;
+        m.0[0][0] = s.x();
+        m.0[1][1] = s.y();
+        m.0[2][2] = s.z();
+        m
+    }
+
+    // `angle` in degrees around `axis`.
+    pub fn rotate(angle: f64, axis: Vec3) -> Mat4 {
+        Mat4::from_quat(Quat::from_axis_angle(axis, angle))
+    }
+
+    pub fn from_quat(q: Quat) -> Mat4 {
+        let (x, y, z) = q.axes();
+        Mat4([
+            [x.x(), y.x(), z.x(), 0.0],
+            [x.y(), y.y(), z.y(), 0.0],
+            [x.z(), y.z(), z.z(), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    // Splits the matrix into a translation, a rotation and a scale along
+    // the rotated axes, as `Transform::from_trs` takes them. A mirroring
+    // matrix gets a negative x scale. None for shears and projections.
+    pub fn to_trs(&self) -> Option<(Vec3, Quat, Vec3)> {
+        let m = &self.0;
+        if m[3] != [0.0, 0.0, 0.0, 1.0] {
+            return None;
+        }
+        let column = |j: usize| Vec3::new(m[0][j], m[1][j], m[2][j]);
+        let (mut x, y, z) = (column(0), column(1), column(2));
+        let mut scale = Vec3::new(x.length(), y.length(), z.length());
+        if scale.x() == 0.0 || scale.y() == 0.0 || scale.z() == 0.0 {
+            return None;
+        }
+        if x.cross(&y).dot(&z) < 0.0 {
+            x = -x;
+            scale = Vec3::new(-scale.x(), scale.y(), scale.z());
+        }
+        let (x, y, z) = (x.unit_vector(), y.unit_vector(), z.unit_vector());
+        if x.dot(&y).abs() > 1e-9 || y.dot(&z).abs() > 1e-9 || z.dot(&x).abs() > 1e-9 {
+            return None;
+        }
+        Some((column(3), Quat::from_axes(x, y, z), scale))
+    }
+
+    pub fn transpose(&self) -> Mat4 {
+        let mut t = Mat4::IDENTITY;
+        for (i, row) in t.0.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.0[j][i];
+            }
+        }
+        t
+    }
+
+    // Gauss-Jordan elimination with partial pivoting. None for singular
+    // matrices.
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.0;
+        let mut inv = Mat4::IDENTITY.0;
+        for col in 0..4 {
+            let pivot = (col..4).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+            if a[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let p = a[col][col];
+            for k in 0..4 {
+                a[col][k] /= p;
+                inv[col][k] /= p;
+            }
+            for row in 0..4 {
+                if row != col {
+                    let f = a[row][col];
+                    for k in 0..4 {
+                        a[row][k] -= f * a[col][k];
+                        inv[row][k] -= f * inv[col][k];
+                    }
+                }
+            }
+        }
+        Some(Mat4(inv))
+    }
+
+    pub fn transform_point(&self, p: Point3D) -> Point3D {
+        let m = &self.0;
+        let v = [p.x(), p.y(), p.z(), 1.0];
+        let r: Vec<f64> = (0..4)
+            .map(|i| (0..4).map(|k| m[i][k] * v[k]).sum())
+            .collect();
+        Point3D::new(r[0] / r[3], r[1] / r[3], r[2] / r[3])
+    }
+
+    // Ignores the translation.
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.0;
+        Vec3::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.0[i][k] * other.0[k][j]).sum();
+            }
+        }
+        Mat4(m)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Transform {
+    pub matrix: Mat4,
+    pub inverse: Mat4,
+}
+
+impl Transform {
+    // None if `matrix` can't be inverted, e.g. because it scales by zero.
+    pub fn new(matrix: Mat4) -> Option<Transform> {
+        Some(Transform {
+            matrix,
+            inverse: matrix.inverse()?,
+        })
+    }
+
+    // Scales first, then rotates, then translates. The inverse is built from
+    // the parts, so a zero scale gives infinities instead of failing.
+    pub fn from_trs(translate: Vec3, rotate: Quat, scale: Vec3) -> Transform {
+        let inverse_scale = Vec3::new(1.0 / scale.x(), 1.0 / scale.y(), 1.0 / scale.z());
+        Transform {
+            matrix: Mat4::translate(translate) * Mat4::from_quat(rotate) * Mat4::scale(scale),
+            inverse: Mat4::scale(inverse_scale)
+                * Mat4::from_quat(rotate.conjugate())
+                * Mat4::translate(-translate),
+        }
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn point(&self, p: Point3D) -> Point3D {
+        self.matrix.transform_point(p)
+    }
+
+    pub fn vector(&self, v: Vec3) -> Vec3 {
+        self.matrix.transform_vector(v)
+    }
+
+    // Normals stay perpendicular to the surface under the inverse transpose.
+    // The result is not normalized.
+    pub fn normal(&self, n: Vec3) -> Vec3 {
+        self.inverse.transpose().transform_vector(n)
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, other: Transform) -> Transform {
+        Transform {
+            matrix: self.matrix * other.matrix,
+            inverse: other.inverse * self.inverse,
+        }
+    }
+}
+
+#[test]
+fn test_inverse() {
+    let m =
+        Mat4::translate(Vec3::new(1.0, 2.0, 3.0)) * Mat4::rotate(30.0, Vec3::new(0.0, 1.0, 0.0));
+    let p = Point3D::new(4.0, 5.0, 6.0);
+    let q = m.inverse().unwrap().transform_point(m.transform_point(p));
+    assert!((q - p).length() < 1e-9);
+    assert!(Mat4::scale(Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+}
+
+#[test]
+fn test_transform() {
+    let t = Transform::from_trs(
+        Vec3::new(1.0, 0.0, 0.0),
+        Quat::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), 90.0),
+        Vec3::new(2.0, 1.0, 1.0),
+    );
+    // Scaled to (2, 0, 0), turned to (0, 2, 0), moved to (1, 2, 0).
+    let p = t.point(Point3D::new(1.0, 0.0, 0.0));
+    assert!((p - Point3D::new(1.0, 2.0, 0.0)).length() < 1e-9);
+    assert!((t.inverse().point(p) - Point3D::new(1.0, 0.0, 0.0)).length() < 1e-9);
+    let general = Transform::new(t.matrix).unwrap();
+    assert!((general.inverse.transform_point(p) - Point3D::new(1.0, 0.0, 0.0)).length() < 1e-9);
+
+    let (translate, rotate, scale) = t.matrix.to_trs().unwrap();
+    let q = Transform::from_trs(translate, rotate, scale).point(p);
+    assert!((q - t.point(p)).length() < 1e-9);
+    let mirrored = Mat4::scale(Vec3::new(-1.0, 1.0, 1.0)) * t.matrix;
+    let (translate, rotate, scale) = mirrored.to_trs().unwrap();
+    let q = Transform::from_trs(translate, rotate, scale).point(p);
+    assert!((q - mirrored.transform_point(p)).length() < 1e-9);
+    let mut sheared = Mat4::IDENTITY;
+    sheared.0[0][1] = 0.5;
+    assert!(sheared.to_trs().is_none());
+
+    // A normal on a surface stretched along x leans towards y.
+    let n = t.normal(Vec3::new(1.0, 1.0, 0.0));
+    let tangent = t.vector(Vec3::new(1.0, -1.0, 0.0));
+    assert!(n.dot(&tangent).abs() < 1e-9);
+}
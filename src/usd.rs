@@ -0,0 +1,1084 @@
+// Importer for USD stages in their text form, .usda. Supported are Xform and
+// Scope prims with their transform ops, polygon meshes with texture
+// coordinates and normals, spheres, cubic basis curves and cameras, with
+// materials from display colors or bound UsdPreviewSurface shaders. Only the
+// first time sample of animated attributes is read. Binary crate files
+// (.usdc, .usdz) and Alembic archives need the C++ libraries to read; convert
+// them first, e.g. with `usdcat -o scene.usda scene.usdc`. Everything else,
+// including references to other layers, is skipped with a warning, as in the
+// PBRT importer.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::aabb::Aabb;
+use crate::camera::Camera;
+use crate::color::Color;
+use crate::config::Config;
+use crate::curves::{Basis, Curves, Strand};
+use crate::hittable::{Hittable, Object, ObjectList, Shape};
+use crate::material::{DiffuseLight, Glass, Lambertian, Material, Metal};
+use crate::mesh::{Mesh, MeshShape, Vertex};
+use crate::quat::Quat;
+use crate::sphere::Sphere;
+use crate::transform::Mat4;
+use crate::vec3::{Point3D, Vec3};
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Asset(String),
+    Path(String),
+    Num(f64),
+    Punct(char),
+}
+
+fn tokenize(src: &str) -> io::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = src.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '#' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '"' | '\'' => {
+                chars.next();
+                // Triple quotes allow line breaks and quotes inside.
+                let triple = chars.next_if_eq(&c).is_some();
+                if triple && chars.next_if_eq(&c).is_none() {
+                    tokens.push(Token::Str(String::new()));
+                    continue;
+                }
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        None => return Err(invalid("unterminated string")),
+                        Some('\\') => s.extend(chars.next()),
+                        Some(q) if q == c && !triple => break,
+                        Some(q) if q == c && chars.peek() == Some(&c) => {
+                            chars.next();
+                            if chars.next_if_eq(&c).is_some() {
+                                break;
+                            }
+                            s.push(q);
+                            s.push(q);
+                        }
+                        Some(other) => s.push(other),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            '@' | '<' => {
+                chars.next();
+                let end = if c == '@' { '@' } else { '>' };
+                let s: String = chars.by_ref().take_while(|&c| c != end).collect();
+                tokens.push(if c == '@' {
+                    Token::Asset(s)
+                } else {
+                    Token::Path(s)
+                });
+            }
+            '0'..='9' | '-' | '+' | '.' => {
+                let mut s = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || "+-.eE".contains(*c)) {
+                    s.push(c);
+                }
+                match s.parse() {
+                    Ok(n) => tokens.push(Token::Num(n)),
+                    // -inf and the like.
+                    Err(_) if s == "-" || s == "+" => {
+                        let mut word = String::new();
+                        while let Some(c) = chars.next_if(|c| c.is_alphabetic()) {
+                            word.push(c);
+                        }
+                        let text = format!("{}{}", s, word);
+                        tokens
+                            .push(Token::Num(text.parse::<f64>().map_err(|_| {
+                                invalid(format!("invalid number \"{}\"", text))
+                            })?));
+                    }
+                    Err(_) => return Err(invalid(format!("invalid number \"{}\"", s))),
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut s = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || "_:.".contains(*c)) {
+                    s.push(c);
+                }
+                // Array types, like `point3f[]`.
+                let mut ahead = chars.clone();
+                if ahead.next() == Some('[') && ahead.next() == Some(']') {
+                    chars.next();
+                    chars.next();
+                    s.push_str("[]");
+                }
+                tokens.push(Token::Ident(s));
+            }
+            '(' | ')' | '[' | ']' | '{' | '}' | '=' | ',' | ';' | ':' => {
+                chars.next();
+                tokens.push(Token::Punct(c));
+            }
+            other => return Err(invalid(format!("unexpected character '{}'", other))),
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Num(f64),
+    Str(String),
+    Path(String),
+    Ident(String),
+    Tuple(Vec<Value>),
+    List(Vec<Value>),
+    // Time samples, in order.
+    Samples(Vec<(f64, Value)>),
+    // Dictionaries and anything else there is no use for.
+    Other,
+}
+
+impl Value {
+    // The first time sample of animated values.
+    fn current(&self) -> &Value {
+        match self {
+            Value::Samples(samples) => samples.first().map_or(&Value::Other, |(_, v)| v),
+            value => value,
+        }
+    }
+
+    fn num(&self) -> Option<f64> {
+        match self.current() {
+            Value::Num(n) => Some(*n),
+            Value::Ident(s) if s == "true" => Some(1.0),
+            Value::Ident(s) if s == "false" => Some(0.0),
+            _ => None,
+        }
+    }
+
+    fn str(&self) -> Option<&str> {
+        match self.current() {
+            Value::Str(s) | Value::Ident(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    // All numbers inside, in order.
+    fn nums(&self) -> Vec<f64> {
+        match self.current() {
+            Value::Num(n) => vec![*n],
+            Value::Tuple(items) | Value::List(items) => {
+                items.iter().flat_map(Value::nums).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn vec3(&self) -> Option<Vec3> {
+        match self.nums()[..] {
+            [x, y, z] => Some(Vec3::new(x, y, z)),
+            _ => None,
+        }
+    }
+
+    fn items(&self) -> &[Value] {
+        match self.current() {
+            Value::List(items) | Value::Tuple(items) => items,
+            _ => &[],
+        }
+    }
+
+    fn vec3s(&self) -> Vec<Vec3> {
+        self.items().iter().filter_map(Value::vec3).collect()
+    }
+
+    fn indices(&self) -> Vec<usize> {
+        self.nums()
+            .into_iter()
+            .map(|n| n.max(0.0) as usize)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Attribute {
+    value: Value,
+    metadata: HashMap<String, Value>,
+}
+
+#[derive(Debug, Default)]
+struct Prim {
+    specifier: String,
+    type_name: String,
+    name: String,
+    metadata: HashMap<String, Value>,
+    attributes: HashMap<String, Attribute>,
+    children: Vec<Prim>,
+}
+
+impl Prim {
+    fn value(&self, name: &str) -> Option<&Value> {
+        self.attributes.get(name).map(|a| &a.value)
+    }
+
+    fn num(&self, name: &str, default: f64) -> f64 {
+        self.value(name).and_then(Value::num).unwrap_or(default)
+    }
+
+    fn str(&self, name: &str) -> Option<&str> {
+        self.value(name).and_then(Value::str)
+    }
+
+    // Where values change within a primvar: per prim, per face, per point or
+    // per face corner.
+    fn interpolation(&self, name: &str) -> &str {
+        self.attributes
+            .get(name)
+            .and_then(|a| a.metadata.get("interpolation"))
+            .and_then(Value::str)
+            .unwrap_or("constant")
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn at(&self, c: char) -> bool {
+        self.peek() == Some(&Token::Punct(c))
+    }
+
+    fn expect(&mut self, c: char) -> io::Result<()> {
+        match self.next() {
+            Some(Token::Punct(p)) if p == c => Ok(()),
+            other => Err(invalid(format!("expected '{}', found {:?}", c, other))),
+        }
+    }
+
+    // Past the bracket matching the one just read.
+    fn skip_nested(&mut self, open: char, close: char) -> io::Result<()> {
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next() {
+                Some(Token::Punct(c)) if c == open => depth += 1,
+                Some(Token::Punct(c)) if c == close => depth -= 1,
+                Some(_) => {}
+                None => return Err(invalid(format!("missing '{}'", close))),
+            }
+        }
+        Ok(())
+    }
+
+    fn list(&mut self, close: char) -> io::Result<Vec<Value>> {
+        let mut items = Vec::new();
+        while !self.at(close) {
+            items.push(self.value()?);
+            if !self.at(close) {
+                self.expect(',')?;
+            }
+        }
+        self.next();
+        Ok(items)
+    }
+
+    fn value(&mut self) -> io::Result<Value> {
+        Ok(match self.next() {
+            Some(Token::Num(n)) => Value::Num(n),
+            Some(Token::Str(s)) => Value::Str(s),
+            Some(Token::Path(p)) => Value::Path(p),
+            Some(Token::Ident(s)) => Value::Ident(s),
+            Some(Token::Asset(a)) => {
+                // A reference may name a prim in the layer after it.
+                if let Some(Token::Path(_)) = self.peek() {
+                    self.next();
+                }
+                Value::Str(a)
+            }
+            Some(Token::Punct('(')) => Value::Tuple(self.list(')')?),
+            Some(Token::Punct('[')) => Value::List(self.list(']')?),
+            Some(Token::Punct('{')) if matches!(self.peek(), Some(Token::Num(_))) => {
+                let mut samples = Vec::new();
+                while !self.at('}') {
+                    let Some(Token::Num(time)) = self.next() else {
+                        return Err(invalid("expected a time"));
+                    };
+                    self.expect(':')?;
+                    samples.push((time, self.value()?));
+                    if !self.at('}') {
+                        self.expect(',')?;
+                    }
+                }
+                self.next();
+                samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+                Value::Samples(samples)
+            }
+            Some(Token::Punct('{')) => {
+                self.skip_nested('{', '}')?;
+                Value::Other
+            }
+            other => return Err(invalid(format!("expected a value, found {:?}", other))),
+        })
+    }
+
+    // The words before an `=`, like `uniform token[] name`, and the value
+    // after it, if there is one.
+    fn statement(&mut self) -> io::Result<(Vec<String>, Option<Value>)> {
+        let mut words = Vec::new();
+        while let Some(Token::Ident(word)) = self.peek() {
+            words.push(word.clone());
+            self.next();
+        }
+        if words.is_empty() {
+            return Err(invalid(format!("unexpected {:?}", self.peek())));
+        }
+        let value = if self.at('=') {
+            self.next();
+            Some(self.value()?)
+        } else {
+            None
+        };
+        Ok((words, value))
+    }
+
+    // After the opening parenthesis.
+    fn metadata(&mut self) -> io::Result<HashMap<String, Value>> {
+        let mut metadata = HashMap::new();
+        loop {
+            match self.peek() {
+                Some(Token::Punct(')')) => {
+                    self.next();
+                    return Ok(metadata);
+                }
+                Some(Token::Punct(';')) | Some(Token::Str(_)) => {
+                    // Doc strings.
+                    self.next();
+                }
+                _ => {
+                    let (mut words, value) = self.statement()?;
+                    metadata.insert(words.pop().unwrap(), value.unwrap_or(Value::Other));
+                }
+            }
+        }
+    }
+
+    fn prim(&mut self) -> io::Result<Prim> {
+        let mut prim = Prim::default();
+        if let Some(Token::Ident(specifier)) = self.next() {
+            prim.specifier = specifier;
+        }
+        if let Some(Token::Ident(type_name)) = self.peek() {
+            prim.type_name = type_name.clone();
+            self.next();
+        }
+        match self.next() {
+            Some(Token::Str(name)) => prim.name = name,
+            other => return Err(invalid(format!("expected a prim name, found {:?}", other))),
+        }
+        if self.at('(') {
+            self.next();
+            prim.metadata = self.metadata()?;
+        }
+        self.expect('{')?;
+        while !self.at('}') {
+            match self.peek() {
+                None => return Err(invalid(format!("prim \"{}\" is missing '}}'", prim.name))),
+                Some(Token::Punct(';')) => {
+                    self.next();
+                }
+                Some(Token::Ident(word)) if ["def", "over", "class"].contains(&word.as_str()) => {
+                    prim.children.push(self.prim()?);
+                }
+                Some(Token::Ident(word)) if word == "variantSet" => {
+                    self.statement()?;
+                }
+                _ => {
+                    let (words, value) = self.statement()?;
+                    let metadata = if self.at('(') {
+                        self.next();
+                        self.metadata()?
+                    } else {
+                        HashMap::new()
+                    };
+                    let name = words.last().unwrap();
+                    let name = name.strip_suffix(".timeSamples").unwrap_or(name);
+                    prim.attributes.insert(
+                        name.to_string(),
+                        Attribute {
+                            value: value.unwrap_or(Value::Other),
+                            metadata,
+                        },
+                    );
+                }
+            }
+        }
+        self.next();
+        Ok(prim)
+    }
+}
+
+struct Stage {
+    metadata: HashMap<String, Value>,
+    prims: Vec<Prim>,
+}
+
+fn parse_stage(src: &str) -> io::Result<Stage> {
+    if !src.starts_with("#usda") {
+        return Err(invalid(
+            "only text USD files (.usda) can be read; convert others with usdcat",
+        ));
+    }
+    let mut p = Parser {
+        tokens: tokenize(src)?,
+        pos: 0,
+    };
+    let mut stage = Stage {
+        metadata: HashMap::new(),
+        prims: Vec::new(),
+    };
+    if p.at('(') {
+        p.next();
+        stage.metadata = p.metadata()?;
+    }
+    while p.peek().is_some() {
+        stage.prims.push(p.prim()?);
+    }
+    Ok(stage)
+}
+
+// The matrix of one transform op, like `xformOp:rotateXYZ`, with its value.
+fn op_matrix(op: &str, value: &Value) -> Option<Mat4> {
+    let kind = op.split(':').nth(1)?;
+    let axis = |c: char| match c {
+        'X' => Some(Vec3::new(1.0, 0.0, 0.0)),
+        'Y' => Some(Vec3::new(0.0, 1.0, 0.0)),
+        'Z' => Some(Vec3::new(0.0, 0.0, 1.0)),
+        _ => None,
+    };
+    Some(match kind {
+        "translate" => Mat4::translate(value.vec3()?),
+        "scale" => match value.nums()[..] {
+            [s] => Mat4::scale(Vec3::new(s, s, s)),
+            _ => Mat4::scale(value.vec3()?),
+        },
+        "orient" => match value.nums()[..] {
+            [w, x, y, z] => Mat4::from_quat(Quat::new(w, x, y, z).normalize()),
+            _ => return None,
+        },
+        // Rows, acting on row vectors.
+        "transform" => {
+            let n = value.nums();
+            if n.len() != 16 {
+                return None;
+            }
+            let mut m = Mat4::IDENTITY;
+            for (i, row) in m.0.iter_mut().enumerate() {
+                for (j, x) in row.iter_mut().enumerate() {
+                    *x = n[j * 4 + i];
+                }
+            }
+            m
+        }
+        // Rotations about the axes in the order named, in degrees.
+        rotate => {
+            let axes = rotate.strip_prefix("rotate")?;
+            let angles = value.nums();
+            if axes.len() != angles.len() || axes.is_empty() {
+                return None;
+            }
+            axes.chars()
+                .zip(angles)
+                .try_fold(Mat4::IDENTITY, |m, (c, angle)| {
+                    Some(Mat4::rotate(angle, axis(c)?) * m)
+                })?
+        }
+    })
+}
+
+// The prim's transform relative to its parent, and whether it ignores the
+// parent's.
+fn local_transform(prim: &Prim, skipped: &mut impl FnMut(String)) -> (Mat4, bool) {
+    let mut m = Mat4::IDENTITY;
+    let mut reset = false;
+    let Some(order) = prim.value("xformOpOrder") else {
+        return (m, reset);
+    };
+    for op in order.items().iter().filter_map(Value::str) {
+        if op == "!resetXformStack!" {
+            (m, reset) = (Mat4::IDENTITY, true);
+            continue;
+        }
+        let (inverse, name) = match op.strip_prefix("!invert!") {
+            Some(name) => (true, name),
+            None => (false, op),
+        };
+        let matrix = prim.value(name).and_then(|value| op_matrix(name, value));
+        let Some(matrix) = matrix else {
+            skipped(format!("transform op \"{}\"", name));
+            continue;
+        };
+        let matrix = if inverse {
+            matrix.inverse().unwrap_or(Mat4::IDENTITY)
+        } else {
+            matrix
+        };
+        m = m * matrix;
+    }
+    (m, reset)
+}
+
+fn default_material() -> Material {
+    Material::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)))
+}
+
+struct Importer<'a> {
+    prims: HashMap<String, &'a Prim>,
+    objects: ObjectList,
+    cameras: Vec<(String, Camera)>,
+    skipped: HashMap<String, usize>,
+}
+
+impl<'a> Importer<'a> {
+    fn index(&mut self, prim: &'a Prim, parent: &str) {
+        let path = format!("{}/{}", parent, prim.name);
+        for child in &prim.children {
+            self.index(child, &path);
+        }
+        self.prims.insert(path, prim);
+    }
+
+    fn skip(&mut self, what: String) {
+        *self.skipped.entry(what).or_insert(0) += 1;
+    }
+
+    // `binding` is the closest material bound above, which applies to all
+    // prims below it.
+    fn walk(&mut self, prim: &Prim, parent: Mat4, binding: Option<&str>) -> io::Result<()> {
+        if prim.specifier == "class"
+            || prim.str("visibility") == Some("invisible")
+            || matches!(prim.str("purpose"), Some("guide" | "proxy"))
+        {
+            return Ok(());
+        }
+        for what in ["references", "payload", "inherits", "specializes"] {
+            if prim.metadata.contains_key(what) {
+                self.skip(what.to_string());
+            }
+        }
+        let (local, reset) = local_transform(prim, &mut |what| self.skip(what));
+        let world = if reset { local } else { parent * local };
+        let binding = match prim.value("material:binding") {
+            Some(Value::Path(path)) => Some(path.as_str()),
+            _ => binding,
+        };
+
+        match prim.type_name.as_str() {
+            "" | "Xform" | "Scope" | "Material" | "Shader" | "GeomSubset" => {}
+            "Mesh" => self.mesh(prim, world, binding)?,
+            "Sphere" => {
+                let center = world.transform_point(Point3D::default());
+                let radius = prim.num("radius", 1.0)
+                    * world.transform_vector(Vec3::new(1.0, 0.0, 0.0)).length();
+                let material = self.material(prim, binding);
+                self.objects
+                    .add(Object::from(Sphere::new(center, radius, material)));
+            }
+            "BasisCurves" => self.curves(prim, world, binding),
+            "Camera" => {
+                let camera = camera(prim, world);
+                self.cameras.push((prim.name.clone(), camera));
+            }
+            other => self.skip(format!("prim type \"{}\"", other)),
+        }
+        if prim.type_name != "Material" {
+            for child in &prim.children {
+                self.walk(child, world, binding)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn mesh(&mut self, prim: &Prim, world: Mat4, binding: Option<&str>) -> io::Result<()> {
+        let error = |msg: &str| invalid(format!("mesh \"{}\": {}", prim.name, msg));
+        let points = prim.value("points").map(Value::vec3s).unwrap_or_default();
+        let counts = prim
+            .value("faceVertexCounts")
+            .map(Value::indices)
+            .unwrap_or_default();
+        let indices = prim
+            .value("faceVertexIndices")
+            .map(Value::indices)
+            .unwrap_or_default();
+        if counts.iter().sum::<usize>() != indices.len() {
+            return Err(error("faceVertexCounts don't add up to faceVertexIndices"));
+        }
+        if indices.iter().any(|&i| i >= points.len()) {
+            return Err(error("a face vertex index is out of range"));
+        }
+        let holes = prim
+            .value("holeIndices")
+            .map(Value::indices)
+            .unwrap_or_default();
+        let left_handed = prim.str("orientation") == Some("leftHanded");
+
+        let mut mesh = Mesh {
+            positions: points.iter().map(|&p| world.transform_point(p)).collect(),
+            ..Mesh::default()
+        };
+        // Which value of a primvar a face corner uses, by the primvar's
+        // interpolation and its indices, if it has them.
+        let lookup = |name: &str| {
+            let interpolation = prim.interpolation(name).to_string();
+            let indices = prim.value(&format!("{}:indices", name)).map(Value::indices);
+            move |face: usize, corner: usize, point: usize| {
+                let i = match interpolation.as_str() {
+                    "constant" => 0,
+                    "uniform" => face,
+                    "vertex" | "varying" => point,
+                    _ => corner,
+                };
+                match &indices {
+                    Some(indices) => indices.get(i).copied(),
+                    None => Some(i),
+                }
+            }
+        };
+        let uv_name = ["primvars:st", "primvars:uv", "primvars:UVMap"]
+            .into_iter()
+            .find(|name| prim.value(name).is_some());
+        if let Some(name) = uv_name {
+            mesh.uvs = prim
+                .value(name)
+                .unwrap()
+                .items()
+                .iter()
+                .filter_map(|uv| match uv.nums()[..] {
+                    [u, v] => Some((u, v)),
+                    _ => None,
+                })
+                .collect();
+        }
+        let uv = uv_name.map(lookup);
+        let normal_name = ["primvars:normals", "normals"]
+            .into_iter()
+            .find(|name| prim.value(name).is_some());
+        if let Some(name) = normal_name {
+            let to_world = world.inverse().unwrap_or(Mat4::IDENTITY).transpose();
+            mesh.normals = prim
+                .value(name)
+                .unwrap()
+                .vec3s()
+                .iter()
+                .map(|&n| to_world.transform_vector(n).unit_vector())
+                .collect();
+        }
+        let normal = normal_name.map(lookup);
+
+        let mut corner = 0;
+        for (face, &count) in counts.iter().enumerate() {
+            let corners = corner..corner + count;
+            corner += count;
+            if count < 3 || holes.contains(&face) {
+                continue;
+            }
+            let mut vertices: Vec<Vertex> = corners
+                .map(|c| {
+                    let point = indices[c];
+                    Vertex {
+                        position: point,
+                        uv: uv
+                            .as_ref()
+                            .and_then(|uv| uv(face, c, point))
+                            .filter(|&i| i < mesh.uvs.len()),
+                        normal: normal
+                            .as_ref()
+                            .and_then(|normal| normal(face, c, point))
+                            .filter(|&i| i < mesh.normals.len()),
+                    }
+                })
+                .collect();
+            if left_handed {
+                vertices.reverse();
+            }
+            mesh.add_face(vertices);
+        }
+        let material = self.material(prim, binding);
+        self.objects
+            .add(Object::new(Shape::Mesh(MeshShape::from_mesh(
+                mesh, material,
+            ))));
+        Ok(())
+    }
+
+    fn curves(&mut self, prim: &Prim, world: Mat4, binding: Option<&str>) {
+        let basis = match (prim.str("type").unwrap_or("cubic"), prim.str("basis")) {
+            ("cubic", None | Some("bezier")) => Basis::Bezier,
+            ("cubic", Some("bspline")) => Basis::Bspline,
+            (kind, basis) => {
+                self.skip(format!("{} {} curves", basis.unwrap_or(""), kind));
+                return;
+            }
+        };
+        // Widths are scaled with the curves, assuming the same scale in all
+        // directions.
+        let scale = world.transform_vector(Vec3::new(1.0, 0.0, 0.0)).length();
+        let points = prim.value("points").map(Value::vec3s).unwrap_or_default();
+        let widths: Vec<f64> = prim
+            .value("widths")
+            .map(|w| w.nums().iter().map(|w| w * scale).collect())
+            .unwrap_or_default();
+        let per_point = prim.interpolation("widths") != "constant";
+
+        let mut start = 0;
+        let mut strands = Vec::new();
+        for count in prim
+            .value("curveVertexCounts")
+            .map(Value::indices)
+            .unwrap_or_default()
+        {
+            let range = start..(start + count).min(points.len());
+            start += count;
+            strands.push(Strand {
+                points: points[range.clone()]
+                    .iter()
+                    .map(|&p| world.transform_point(p))
+                    .collect(),
+                widths: if per_point {
+                    widths.get(range).map(<[f64]>::to_vec).unwrap_or_default()
+                } else {
+                    Vec::new()
+                },
+            });
+        }
+        let mut curves = Curves::new(strands, basis, self.material(prim, binding));
+        if let (false, Some(&width)) = (per_point, widths.first()) {
+            curves.width = width;
+        }
+        self.objects.add(Object::new(Shape::Curves(curves)));
+    }
+
+    // The bound preview surface, or else the display color.
+    fn material(&mut self, prim: &Prim, binding: Option<&str>) -> Material {
+        if let Some(material) = binding.and_then(|path| self.preview_surface(path)) {
+            return material;
+        }
+        if let Some(path) = binding {
+            self.skip(format!("material {}", path));
+        }
+        prim.value("primvars:displayColor")
+            .and_then(|colors| match colors.items().first() {
+                Some(first) => first.vec3(),
+                None => colors.vec3(),
+            })
+            .map_or_else(default_material, |color| {
+                Material::Lambertian(Lambertian::new(color))
+            })
+    }
+
+    fn preview_surface(&self, path: &str) -> Option<Material> {
+        let material = self.prims.get(path)?;
+        // The shader connected to the material's surface output.
+        let shader = match material.value("outputs:surface.connect") {
+            Some(Value::Path(target)) => {
+                let shader = target.split('.').next().unwrap_or(target);
+                self.prims.get(shader).copied()
+            }
+            _ => material
+                .children
+                .iter()
+                .find(|child| child.str("info:id") == Some("UsdPreviewSurface")),
+        }?;
+        if shader.str("info:id") != Some("UsdPreviewSurface") {
+            return None;
+        }
+        let color = |name: &str, default: f64| {
+            shader
+                .value(name)
+                .and_then(Value::vec3)
+                .unwrap_or(Vec3::new(default, default, default))
+        };
+        let diffuse = color("inputs:diffuseColor", 0.18);
+        let emissive = color("inputs:emissiveColor", 0.0);
+        Some(if emissive != Vec3::default() {
+            Material::DiffuseLight(DiffuseLight::new(emissive))
+        } else if shader.num("inputs:opacity", 1.0) < 1.0 {
+            Material::Glass(Glass::new(shader.num("inputs:ior", 1.5)))
+        } else if shader.num("inputs:metallic", 0.0) >= 0.5 {
+            Material::Metal(Metal::new(diffuse, shader.num("inputs:roughness", 0.5)))
+        } else {
+            Material::Lambertian(Lambertian::new(diffuse))
+        })
+    }
+}
+
+// USD cameras look down their -z axis with y up. Focal length and apertures
+// are in tenths of a scene unit, which only matters for depth of field.
+fn camera(prim: &Prim, world: Mat4) -> Camera {
+    let focal_length = prim.num("focalLength", 50.0);
+    let horizontal = prim.num("horizontalAperture", 20.955);
+    let vertical = prim.num("verticalAperture", 15.2908);
+    let vfov = 2.0 * (vertical / (2.0 * focal_length)).atan().to_degrees();
+    let width = 640;
+    let height = ((width as f64 * vertical / horizontal).round() as usize).max(1);
+
+    let lookfrom = world.transform_point(Point3D::default());
+    let lookat = world.transform_point(Point3D::new(0.0, 0.0, -1.0));
+    let vup = world.transform_vector(Vec3::new(0.0, 1.0, 0.0));
+    let focus_dist = match prim.num("focusDistance", 0.0) {
+        d if d > 0.0 => d,
+        _ => (lookat - lookfrom).length(),
+    };
+    let defocus_angle = match prim.num("fStop", 0.0) {
+        f if f > 0.0 => {
+            let lens_radius = focal_length / (2.0 * f) / 10.0;
+            2.0 * (lens_radius / focus_dist).atan().to_degrees()
+        }
+        _ => 0.0,
+    };
+    Camera::new(
+        height,
+        width,
+        16,
+        5,
+        vfov,
+        lookfrom,
+        lookat,
+        vup,
+        defocus_angle,
+        focus_dist,
+    )
+}
+
+// For stages without a camera: one looking down -z at everything.
+fn framing_camera(objects: &ObjectList) -> Camera {
+    let bounds = objects.objects.iter().fold(Aabb::EMPTY, |bounds, object| {
+        Aabb::surrounding_box(&bounds, &object.bounding_box())
+    });
+    let (center, radius) = if bounds.is_empty() {
+        (Point3D::default(), 1.0)
+    } else {
+        (
+            bounds.centroid(),
+            0.5 * (bounds.max() - bounds.min()).length(),
+        )
+    };
+    let vfov: f64 = 40.0;
+    let distance = radius / (vfov / 2.0).to_radians().sin();
+    Camera::new(
+        480,
+        640,
+        16,
+        5,
+        vfov,
+        center + Vec3::new(0.0, 0.0, distance),
+        center,
+        Vec3::new(0.0, 1.0, 0.0),
+        0.0,
+        distance,
+    )
+}
+
+pub fn parse(src: &str) -> io::Result<Config> {
+    let stage = parse_stage(src)?;
+    let mut importer = Importer {
+        prims: HashMap::new(),
+        objects: ObjectList::new(),
+        cameras: Vec::new(),
+        skipped: HashMap::new(),
+    };
+    for prim in &stage.prims {
+        importer.index(prim, "");
+    }
+    // Stages with z up are turned to have y up.
+    let root = match stage.metadata.get("upAxis").and_then(Value::str) {
+        Some("Z") => Mat4::rotate(-90.0, Vec3::new(1.0, 0.0, 0.0)),
+        _ => Mat4::IDENTITY,
+    };
+    for prim in &stage.prims {
+        importer.walk(prim, root, None)?;
+    }
+
+    let mut skipped: Vec<_> = importer.skipped.iter().collect();
+    skipped.sort();
+    for (what, count) in skipped {
+        tracing::warn!(what, count, "skipped unsupported usd feature");
+    }
+
+    let camera = match importer.cameras.first() {
+        Some((_, camera)) => *camera,
+        None => framing_camera(&importer.objects),
+    };
+    let cameras: BTreeMap<String, Camera> = importer.cameras.into_iter().collect();
+    Ok(Config {
+        version: crate::migration::CURRENT_VERSION,
+        camera,
+        cameras,
+        object_list: importer.objects,
+        materials: Default::default(),
+        generators: Vec::new(),
+        post: Vec::new(),
+        texture_cache: None,
+        layers: BTreeMap::new(),
+    })
+}
+
+pub fn load(path: &Path) -> io::Result<Config> {
+    parse(&fs::read_to_string(path)?)
+}
+
+#[test]
+fn test_tokenize() {
+    let tokens = tokenize(
+        "#usda 1.0\ndef Xform \"a\" (doc = \"\"\"two\n\"lines\"\"\") { float3[] p:q = [(1, -2.5e1, -inf)] rel r = </a/b> }",
+    )
+    .unwrap();
+    assert_eq!(tokens[0], Token::Ident("def".to_string()));
+    assert_eq!(tokens[6], Token::Str("two\n\"lines".to_string()));
+    assert_eq!(tokens[9], Token::Ident("float3[]".to_string()));
+    assert_eq!(tokens[10], Token::Ident("p:q".to_string()));
+    assert_eq!(tokens[16], Token::Num(-25.0));
+    assert_eq!(tokens[18], Token::Num(f64::NEG_INFINITY));
+    assert_eq!(tokens[19], Token::Punct(')'));
+    assert_eq!(tokens[24], Token::Path("/a/b".to_string()));
+    assert!(tokenize("def Xform \"a").is_err());
+    let error = tokenize("(1, -infinite)").unwrap_err();
+    assert!(error.to_string().contains("\"-infinite\""), "{}", error);
+}
+
+#[test]
+fn test_parse_stage() {
+    use crate::hittable::HitRecord;
+    use crate::interval::Interval;
+    use crate::ray::Ray;
+
+    let src = r#"#usda 1.0
+(
+    defaultPrim = "World"
+    upAxis = "Z"
+)
+
+def Xform "World"
+{
+    def Xform "Moved" (
+        kind = "component"
+    )
+    {
+        double3 xformOp:translate = (0, 0, -1)
+        float xformOp:rotateZ.timeSamples = {
+            10: 0,
+            1: 90,
+        }
+        uniform token[] xformOpOrder = ["xformOp:translate", "xformOp:rotateZ"]
+
+        def Mesh "Quad" (
+            prepend apiSchemas = ["MaterialBindingAPI"]
+        )
+        {
+            int[] faceVertexCounts = [4, 2]
+            int[] faceVertexIndices = [0, 1, 2, 3, 0, 1]
+            point3f[] points = [(0, 0, 0), (2, 0, 0), (2, 2, 0), (0, 2, 0)]
+            texCoord2f[] primvars:st = [(0, 0), (1, 0), (1, 1), (0, 1)] (
+                interpolation = "vertex"
+            )
+            rel material:binding = </World/Looks/Red>
+        }
+    }
+
+    def Sphere "Hidden"
+    {
+        token visibility = "invisible"
+    }
+
+    def Sphere "Ball"
+    {
+        double radius = 0.5
+        color3f[] primvars:displayColor = [(0, 0, 1)]
+        double3 xformOp:translate = (5, 0, 0)
+        double3 xformOp:scale = (2, 2, 2)
+        uniform token[] xformOpOrder = ["xformOp:translate", "xformOp:scale"]
+    }
+
+    def Scope "Looks"
+    {
+        def Material "Red"
+        {
+            token outputs:surface.connect = </World/Looks/Red/Surface.outputs:surface>
+
+            def Shader "Surface"
+            {
+                uniform token info:id = "UsdPreviewSurface"
+                color3f inputs:diffuseColor = (1, 0, 0)
+                token outputs:surface
+            }
+        }
+    }
+
+    def Camera "Main"
+    {
+        float focalLength = 25
+        float horizontalAperture = 20
+        float verticalAperture = 10
+        matrix4d xformOp:transform = ( (1, 0, 0, 0), (0, 1, 0, 0), (0, 0, 1, 0), (1, 2, 10, 1) )
+        uniform token[] xformOpOrder = ["xformOp:transform"]
+    }
+    def Cube "Box"
+    {
+    }
+}
+"#;
+    let config = parse(src).unwrap();
+    assert_eq!(config.object_list.objects.len(), 2);
+
+    // Z up is turned to y up: the quad is moved down to y = -1 and turned a
+    // quarter around what is now the y axis, and colored by its material.
+    let Shape::Mesh(mesh) = &config.object_list.objects[0].shape else {
+        panic!("not a mesh");
+    };
+    let triangles = mesh.triangles.as_ref().unwrap();
+    assert_eq!(triangles.mesh.triangles.len(), 2);
+    let up = Ray::new(Point3D::new(-1.0, -5.0, -1.0), Vec3::new(0.0, 1.0, 0.0));
+    let mut rec = HitRecord::default();
+    assert!(config
+        .object_list
+        .hit(&up, &Interval::new(0.001, f64::INFINITY), &mut rec));
+    assert!((rec.t - 4.0).abs() < 1e-9);
+    assert!((rec.u - 0.5).abs() < 1e-9 && (rec.v - 0.5).abs() < 1e-9);
+    assert!(matches!(&rec.mat, Material::Lambertian(l) if l.albedo == Color::new(1.0, 0.0, 0.0)));
+
+    // The ball is scaled by two, blue from its display color.
+    let Shape::Sphere(ball) = &config.object_list.objects[1].shape else {
+        panic!("not a sphere");
+    };
+    assert!((ball.center - Point3D::new(5.0, 0.0, 0.0)).length() < 1e-9);
+    assert!((ball.radius - 1.0).abs() < 1e-9);
+    assert!(
+        matches!(&ball.material, Material::Lambertian(l) if l.albedo == Color::new(0.0, 0.0, 1.0))
+    );
+
+    // The camera sits at (1, 10, -2) after turning z up, looking down.
+    assert_eq!(config.cameras.len(), 1);
+    assert!((config.camera.lookfrom() - Point3D::new(1.0, 10.0, -2.0)).length() < 1e-9);
+    assert_eq!(config.camera.output_size(), (640, 320));
+
+    assert!(parse("PXR-USDC").is_err());
+}
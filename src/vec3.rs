@@ -1,9 +1,14 @@
+use crate::rng::with_rng;
 use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
 pub type Point3D = Vec3;
 
+// Vectors with all components below this are treated as zero length, e.g.
+// scatter directions that would make degenerate rays.
+pub const NEAR_ZERO: f64 = 1e-8;
+
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq)]
 pub struct Vec3 {
     x: f64,
@@ -17,12 +22,13 @@ impl Vec3 {
     }
 
     pub fn random(min: f64, max: f64) -> Vec3 {
-        let mut rng = rand::thread_rng();
-        Vec3::new(
-            rng.gen_range(min..max),
-            rng.gen_range(min..max),
-            rng.gen_range(min..max),
-        )
+        with_rng(|rng| {
+            Vec3::new(
+                rng.gen_range(min..max),
+                rng.gen_range(min..max),
+                rng.gen_range(min..max),
+            )
+        })
     }
 
     pub fn random_in_unit_sphere() -> Vec3 {
@@ -65,6 +71,19 @@ impl Vec3 {
         *self / self.length()
     }
 
+    // Two unit vectors completing a right-handed basis with this direction:
+    // the first towards +x, or towards +y when this points along x.
+    pub fn perpendiculars(&self) -> (Vec3, Vec3) {
+        let w = self.unit_vector();
+        let reference = if w.x().abs() > 0.9 {
+            Vec3::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let u = (reference - reference.dot(&w) * w).unit_vector();
+        (u, w.cross(&u))
+    }
+
     pub fn dot(&self, other: &Vec3) -> f64 {
         self.x * other.x + self.y * other.y + self.z * other.z
     }
@@ -78,17 +97,30 @@ impl Vec3 {
     }
 
     pub fn near_zero(&self) -> bool {
-        self.x.abs() < f64::EPSILON && self.y.abs() < f64::EPSILON && self.z.abs() < f64::EPSILON
+        self.x.abs() < NEAR_ZERO && self.y.abs() < NEAR_ZERO && self.z.abs() < NEAR_ZERO
+    }
+
+    // `fallback` instead of a vector too short to be a direction.
+    pub fn or_if_near_zero(self, fallback: Vec3) -> Vec3 {
+        if self.near_zero() {
+            fallback
+        } else {
+            self
+        }
+    }
+
+    // Neither NaN nor infinite in any component.
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
     }
 
     pub fn random_in_unit_disk() -> Vec3 {
-        let mut rng = rand::thread_rng();
-        loop {
+        with_rng(|rng| loop {
             let p = Vec3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), 0.0);
             if p.length_squared() < 1.0 {
                 return p;
             }
-        }
+        })
     }
 
     pub fn random_unit_vector() -> Vec3 {
@@ -310,3 +342,14 @@ fn test_refract() {
     let refracted = Vec3::refract(&uv, &n, etai_over_etat);
     assert!((refracted.length() - 1.0).abs() < f64::EPSILON);
 }
+
+#[test]
+fn test_near_zero() {
+    assert!(Vec3::new(1e-9, -1e-9, 0.0).near_zero());
+    assert!(!Vec3::new(1e-9, 1e-7, 0.0).near_zero());
+    // Almost cancelling vectors, like a scatter direction opposite the normal.
+    let n = Vec3::new(0.0, 1.0, 0.0);
+    let d = n + Vec3::new(1e-10, -1.0 + 1e-12, 0.0);
+    assert_eq!(d.or_if_near_zero(n), n);
+    assert_eq!(n.or_if_near_zero(-n), n);
+}
@@ -0,0 +1,401 @@
+// Volumes: smoke, clouds and fire filling a box. Rays go into them and stop
+// at random points, more often where they are denser, where the medium
+// material scatters the light or absorbs it. Densities and temperatures are
+// either constant or 3D grids of voxels stretched over the box, read
+// trilinearly; grids come inline or from raw files of little-endian 32 bit
+// floats relative to the scene file, e.g. exported from a fire simulation.
+// Hot volumes glow in the color of a black body at their temperature.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::color::{blackbody_with_luminance, Color};
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::rng;
+use crate::vec3::{Point3D, Vec3};
+
+// The temperature whose glow `Emission::intensity` gives.
+const REFERENCE_TEMPERATURE: f64 = 1500.0;
+
+// Colder parts give off no visible light.
+const MIN_GLOWING_TEMPERATURE: f64 = 500.0;
+
+// Entries of `Emission`'s table of glows, from the coldest glowing
+// temperature to the hottest in the volume.
+const GLOW_TABLE_SIZE: usize = 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Grid {
+    // Voxels along x, y and z.
+    pub resolution: [usize; 3],
+    // x changes fastest, then y, then z.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<f32>,
+    // Holds the values instead, as raw little-endian 32 bit floats in the
+    // same order.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(skip)]
+    loaded: Option<Arc<Vec<f32>>>,
+    #[serde(skip)]
+    max: OnceLock<f64>,
+}
+
+impl Grid {
+    pub fn new(resolution: [usize; 3], values: Vec<f32>) -> Grid {
+        Grid {
+            resolution,
+            values,
+            file: None,
+            loaded: None,
+            max: OnceLock::new(),
+        }
+    }
+
+    // Reads the file, if there is one, and returns its path.
+    pub fn load(&mut self, base_dir: &Path) -> io::Result<Option<PathBuf>> {
+        let Some(file) = &self.file else {
+            return Ok(None);
+        };
+        let path = base_dir.join(file);
+        let data = fs::read(&path)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))?;
+        let values = data
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .collect();
+        self.loaded = Some(Arc::new(values));
+        self.max = OnceLock::new();
+        Ok(Some(path))
+    }
+
+    fn values(&self) -> &[f32] {
+        self.loaded.as_deref().unwrap_or(&self.values)
+    }
+
+    pub fn check(&self) -> io::Result<()> {
+        let [x, y, z] = self.resolution;
+        if self.values().len() != x * y * z || x * y * z == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "volume grid has {} values for a resolution of {}x{}x{}",
+                    self.values().len(),
+                    x,
+                    y,
+                    z
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn max(&self) -> f64 {
+        *self
+            .max
+            .get_or_init(|| self.values().iter().fold(0.0, |max, &v| max.max(v as f64)))
+    }
+
+    // The value at `local`, from (0, 0, 0) at the grid's first corner to
+    // (1, 1, 1) at the opposite one.
+    pub fn value(&self, local: Vec3) -> f64 {
+        let values = self.values();
+        let [nx, ny, nz] = self.resolution;
+        // The voxel below along one axis, the one above and how far between
+        // their centers.
+        let axis = |x: f64, n: usize| {
+            let x = (x * n as f64 - 0.5).clamp(0.0, (n - 1) as f64);
+            let i = x.floor() as usize;
+            (i, (i + 1).min(n - 1), x - i as f64)
+        };
+        let (x0, x1, fx) = axis(local.x(), nx);
+        let (y0, y1, fy) = axis(local.y(), ny);
+        let (z0, z1, fz) = axis(local.z(), nz);
+        let at = |x: usize, y: usize, z: usize| values[x + nx * (y + ny * z)] as f64;
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let along_x = |y, z| lerp(at(x0, y, z), at(x1, y, z), fx);
+        let along_y = |z| lerp(along_x(y0, z), along_x(y1, z), fy);
+        lerp(along_y(z0), along_y(z1), fz)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Field {
+    Constant(f64),
+    Grid(Grid),
+}
+
+impl Field {
+    fn grid_mut(&mut self) -> Option<&mut Grid> {
+        match self {
+            Field::Constant(_) => None,
+            Field::Grid(grid) => Some(grid),
+        }
+    }
+
+    fn max(&self) -> f64 {
+        match self {
+            Field::Constant(value) => *value,
+            Field::Grid(grid) => grid.max(),
+        }
+    }
+
+    fn value(&self, local: Vec3) -> f64 {
+        match self {
+            Field::Constant(value) => *value,
+            Field::Grid(grid) => grid.value(local),
+        }
+    }
+}
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+// Light given off by hot parts of a volume, like a black body: red at 1000 K,
+// orange and yellow above, and much brighter the hotter they are.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Emission {
+    // In kelvin.
+    pub temperature: Field,
+    // The brightness of parts at 1500 K, seen through enough of them to
+    // hide what is behind.
+    #[serde(default = "default_intensity")]
+    pub intensity: f64,
+    #[serde(skip)]
+    glows: OnceLock<GlowTable>,
+}
+
+// The glow of a black body at evenly spaced temperatures, relative to one
+// at `REFERENCE_TEMPERATURE`, looked up between them instead of integrated
+// at every collision.
+#[derive(Debug, Clone)]
+struct GlowTable {
+    step: f64,
+    glows: Vec<Color>,
+}
+
+impl GlowTable {
+    fn new(hottest: f64) -> GlowTable {
+        let (_, reference) = blackbody_with_luminance(REFERENCE_TEMPERATURE);
+        let range = (hottest - MIN_GLOWING_TEMPERATURE).max(0.0);
+        let step = range / (GLOW_TABLE_SIZE - 1) as f64;
+        let glows = (0..GLOW_TABLE_SIZE)
+            .map(|i| {
+                let (color, luminance) =
+                    blackbody_with_luminance(MIN_GLOWING_TEMPERATURE + i as f64 * step);
+                color * (luminance / reference)
+            })
+            .collect();
+        GlowTable { step, glows }
+    }
+
+    fn glow(&self, kelvin: f64) -> Color {
+        if self.step == 0.0 {
+            return self.glows[0];
+        }
+        let x = ((kelvin - MIN_GLOWING_TEMPERATURE) / self.step)
+            .clamp(0.0, (GLOW_TABLE_SIZE - 1) as f64);
+        let i = (x as usize).min(GLOW_TABLE_SIZE - 2);
+        let t = x - i as f64;
+        (1.0 - t) * self.glows[i] + t * self.glows[i + 1]
+    }
+}
+
+impl Emission {
+    pub fn new(temperature: Field, intensity: f64) -> Emission {
+        Emission {
+            temperature,
+            intensity,
+            glows: OnceLock::new(),
+        }
+    }
+
+    fn glows(&self) -> &GlowTable {
+        self.glows
+            .get_or_init(|| GlowTable::new(self.temperature.max()))
+    }
+
+    pub fn radiance(&self, local: Vec3) -> Color {
+        let kelvin = self.temperature.value(local);
+        if kelvin < MIN_GLOWING_TEMPERATURE {
+            return Color::default();
+        }
+        self.intensity * self.glows().glow(kelvin)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Volume {
+    // Opposite corners of the box.
+    pub min: Point3D,
+    pub max: Point3D,
+    // How likely light is to be stopped, per unit of length.
+    pub density: Field,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emission: Option<Emission>,
+    // Usually `Medium`, whose albedo splits the stopped light into
+    // scattered and absorbed.
+    pub material: Material,
+}
+
+impl Volume {
+    pub fn new(min: Point3D, max: Point3D, density: f64, material: Material) -> Volume {
+        Volume {
+            min,
+            max,
+            density: Field::Constant(density),
+            emission: None,
+            material,
+        }
+    }
+
+    pub fn grids_mut(&mut self) -> impl Iterator<Item = &mut Grid> {
+        let temperature = self.emission.as_mut().map(|e| &mut e.temperature);
+        self.density
+            .grid_mut()
+            .into_iter()
+            .chain(temperature.and_then(Field::grid_mut))
+    }
+
+    // Fails on grids whose size doesn't match their resolution. Otherwise
+    // also works out the glows of the temperatures in the volume, as loading
+    // the scene is the time for it.
+    pub fn check(&mut self) -> io::Result<()> {
+        self.grids_mut().try_for_each(|grid| grid.check())?;
+        if let Some(emission) = &mut self.emission {
+            emission.glows = OnceLock::new();
+            emission.glows();
+        }
+        Ok(())
+    }
+
+    fn local(&self, p: Point3D) -> Vec3 {
+        let (p, size) = (p - self.min, self.max - self.min);
+        Vec3::new(p.x() / size.x(), p.y() / size.y(), p.z() / size.z())
+    }
+}
+
+impl Hittable for Volume {
+    // Stops where a collision is sampled by delta tracking: tentative ones
+    // come as if the whole box were as dense as its densest voxel, and each
+    // is real as often as the density there is of that.
+    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+        let Some(inside) = self.bounding_box().clip(r, ray_t) else {
+            return false;
+        };
+        let majorant = self.density.max();
+        if majorant <= 0.0 {
+            return false;
+        }
+        let length = r.direction().length();
+        let mut t = inside.min;
+        loop {
+            t -= (1.0 - rng::random()).ln() / (majorant * length);
+            if t >= inside.max {
+                return false;
+            }
+            let local = self.local(r.at(t));
+            let density = self.density.value(local);
+            if rng::random() * majorant >= density {
+                continue;
+            }
+            rec.t = t;
+            rec.p = r.at(t);
+            rec.set_face_normal(r, -r.direction().unit_vector());
+            rec.u = 0.0;
+            rec.v = 0.0;
+            rec.dpdu = Vec3::default();
+            rec.dpdv = Vec3::default();
+            rec.mat = self.material.clone();
+            if let (Some(emission), Material::Medium(medium)) = (&self.emission, &mut rec.mat) {
+                let absorbed = Color::new(1.0, 1.0, 1.0) - medium.albedo;
+                medium.emission = absorbed * emission.radiance(local);
+            }
+            return true;
+        }
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::from_points(self.min, self.max)
+    }
+}
+
+#[test]
+fn test_grid() {
+    let grid = Grid::new([2, 1, 1], vec![1.0, 3.0]);
+    assert!(grid.check().is_ok());
+    assert_eq!(grid.max(), 3.0);
+    assert_eq!(grid.value(Vec3::new(0.0, 0.5, 0.5)), 1.0);
+    assert_eq!(grid.value(Vec3::new(0.5, 0.5, 0.5)), 2.0);
+    assert_eq!(grid.value(Vec3::new(0.75, 0.0, 1.0)), 3.0);
+    assert!(Grid::new([2, 2, 1], vec![1.0, 3.0]).check().is_err());
+}
+
+#[test]
+fn test_volume() {
+    use crate::material::{Medium, Scatterable};
+
+    let grey = Material::Medium(Medium::new(Color::new(0.5, 0.5, 0.5)));
+    let mut volume = Volume::new(
+        Point3D::new(-1.0, -1.0, -1.0),
+        Point3D::new(1.0, 1.0, 1.0),
+        1.0,
+        grey,
+    );
+    // As much light gets through two units of density one as e^-2.
+    let r = Ray::new(Point3D::new(0.0, 0.0, -5.0), Vec3::new(0.0, 0.0, 1.0));
+    let mut rec = HitRecord::default();
+    let n = 20000;
+    let passed = (0..n)
+        .filter(|_| !volume.hit(&r, &Interval::new(0.0, f64::INFINITY), &mut rec))
+        .count();
+    assert!((passed as f64 / n as f64 - (-2.0f64).exp()).abs() < 0.01);
+
+    // Half of the light stopped in fire at 1500 K is absorbed, and given off
+    // again as its glow.
+    volume.density = Field::Constant(100.0);
+    volume.emission = Some(Emission::new(
+        Field::Grid(Grid::new([1, 1, 1], vec![1500.0])),
+        2.0,
+    ));
+    assert!(volume.check().is_ok());
+    assert!(volume.hit(&r, &Interval::new(0.0, f64::INFINITY), &mut rec));
+    let glow = rec.mat.emitted(&r);
+    assert!((crate::color::luminance(glow) - 1.0).abs() < 0.01);
+    assert!(glow.x() > glow.y() && glow.y() > glow.z());
+
+    // Cold smoke doesn't glow.
+    volume.emission = Some(Emission::new(Field::Constant(300.0), 2.0));
+    assert!(volume.hit(&r, &Interval::new(0.0, f64::INFINITY), &mut rec));
+    assert_eq!(rec.mat.emitted(&r), Color::default());
+}
+
+#[test]
+fn test_glow_table() {
+    use crate::color::blackbody_luminance;
+
+    let emission = Emission::new(Field::Grid(Grid::new([2, 1, 1], vec![800.0, 2500.0])), 1.0);
+    // Between the entries as well as on them, close to the integrated glow.
+    for kelvin in [800.0, 1000.0, 1234.5, 1500.0, 2500.0] {
+        let exact =
+            blackbody_luminance(kelvin, REFERENCE_TEMPERATURE) * blackbody_with_luminance(kelvin).0;
+        let glow = emission.glows().glow(kelvin);
+        assert!(
+            (glow - exact).length() < 1e-3 * exact.length(),
+            "{} K",
+            kelvin
+        );
+    }
+    let reference = emission.glows().glow(REFERENCE_TEMPERATURE);
+    assert!((crate::color::luminance(reference) - 1.0).abs() < 0.01);
+}
@@ -0,0 +1,28 @@
+// Entry points for JavaScript, built into pkg/ with `cargo xtask wasm`.
+// Without the "parallel" feature everything runs on the calling thread; a web
+// page that wants threads can enable it and start Rayon's thread pool with
+// wasm-bindgen-rayon.
+
+use wasm_bindgen::prelude::*;
+
+use crate::config::Config;
+use crate::renderer::Renderer;
+
+fn load(scene_json: &str) -> Result<Config, JsError> {
+    Config::from_json(scene_json).map_err(|e| JsError::new(&e.to_string()))
+}
+
+// Width and height of the image `render_to_rgba` returns.
+#[wasm_bindgen]
+pub fn image_size(scene_json: &str) -> Result<Vec<u32>, JsError> {
+    let (width, height) = load(scene_json)?.camera.output_size();
+    Ok(vec![width as u32, height as u32])
+}
+
+// Renders a scene given as JSON into RGBA bytes, row by row from the top
+// left, ready for `new ImageData(...)`.
+#[wasm_bindgen]
+pub fn render_to_rgba(scene_json: &str) -> Result<Vec<u8>, JsError> {
+    let renderer = Renderer::from(load(scene_json)?);
+    Ok(renderer.render_film().to_rgba8_with(renderer.camera.clip))
+}
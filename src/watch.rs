@@ -0,0 +1,111 @@
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::config::Config;
+
+const PREVIEW_WIDTH: usize = 400;
+const PREVIEW_SAMPLES: usize = 8;
+// Editors often write a file in several steps, wait for them to settle.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+// The files the scene was loaded from, or None if it couldn't be loaded.
+fn render_preview(scene: &Path, output: &Path) -> Option<Vec<PathBuf>> {
+    match Config::from_file_with_dependencies(scene) {
+        Ok((config, files)) => {
+            let camera = config.camera.preview(PREVIEW_WIDTH, PREVIEW_SAMPLES);
+            let start = Instant::now();
+            let film = camera.render_film(&config.object_list, &config.post);
+            match film.save_with(&output.to_string_lossy(), &[], camera.clip) {
+                Ok(()) => {
+                    tracing::info!(output = %output.display(), elapsed = ?start.elapsed(), "preview rendered")
+                }
+                Err(e) => tracing::error!(error = %e, "unable to render preview"),
+            }
+            Some(files)
+        }
+        Err(e) => {
+            tracing::error!(scene = %scene.display(), error = %e, "unable to load scene");
+            None
+        }
+    }
+}
+
+// An absolute path without symbolic links for comparing paths, also for
+// files that have just been removed, as long as their directory is there.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    path.canonicalize().ok().or_else(|| {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        Some(dir.canonicalize().ok()?.join(path.file_name()?))
+    })
+}
+
+// Re-renders a small preview of `scene` into `output` every time the scene or
+// one of the files it includes changes. Runs until interrupted.
+pub fn watch(scene: &Path, output: &Path) -> io::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(tx).map_err(io::Error::other)?;
+
+    let mut watched_dirs: HashSet<PathBuf> = HashSet::new();
+    let mut files: HashSet<PathBuf> = HashSet::new();
+
+    loop {
+        // A scene that doesn't load keeps the files of the last one that did,
+        // as the mistake may well be in one of them.
+        match render_preview(scene, output) {
+            Some(loaded) => files = loaded.iter().filter_map(|f| normalize(f)).collect(),
+            None => files.extend(normalize(scene)),
+        }
+
+        // Directories are watched instead of the files, so files replaced by
+        // a rename (as many editors save) keep being noticed.
+        for dir in files.iter().filter_map(|f| f.parent()) {
+            if watched_dirs.insert(dir.to_path_buf()) {
+                watcher
+                    .watch(dir, RecursiveMode::NonRecursive)
+                    .map_err(io::Error::other)?;
+            }
+        }
+        tracing::info!(files = files.len(), "watching for changes");
+
+        loop {
+            let event = rx
+                .recv()
+                .map_err(io::Error::other)?
+                .map_err(io::Error::other)?;
+            let relevant = matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            ) && event
+                .paths
+                .iter()
+                .any(|p| normalize(p).is_some_and(|p| files.contains(&p)));
+            if relevant {
+                break;
+            }
+        }
+        while rx.recv_timeout(DEBOUNCE).is_ok() {}
+    }
+}
+
+#[test]
+fn test_normalize() {
+    let dir = std::env::temp_dir().join("raytracer_test_watch");
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("scene.json");
+    std::fs::write(&file, "{}").unwrap();
+    let existing = normalize(&file).unwrap();
+
+    // A removed file still compares equal to what it was.
+    std::fs::remove_file(&file).unwrap();
+    assert_eq!(normalize(&file), Some(existing));
+    assert_eq!(normalize(&dir.join("missing").join("scene.json")), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
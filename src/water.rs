@@ -0,0 +1,271 @@
+// Water surfaces: a rectangle of sea or pool moved by waves, for pools and
+// underwater shots. The height is a sum of sines whose lengths shrink from
+// `wavelength` and whose directions spread around `direction`, each as steep
+// as the next, and which travel at the speed of waves on deep water, so
+// rendering at increasing `time` animates them. It is found by stepping
+// along the ray no further than the waves could rise to meet it.
+//
+// Its material is water by default, glass that absorbs red most; the
+// caustics it throws on the floor of a pool are best rendered with photon
+// mapping.
+
+use std::f64::consts::PI;
+use std::sync::OnceLock;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::aabb::Aabb;
+use crate::color::Color;
+use crate::hittable::{HitRecord, Hittable};
+use crate::interval::Interval;
+use crate::material::{Glass, Material};
+use crate::ray::Ray;
+use crate::vec3::{Point3D, Vec3};
+
+const MAX_STEPS: usize = 512;
+
+const EPSILON: f64 = 1e-7;
+
+// In meters per second squared, for scenes in meters.
+const GRAVITY: f64 = 9.81;
+
+// How much each wave is shorter than the one before.
+const WAVELENGTH_FALLOFF: f64 = 0.7;
+
+fn default_amplitude() -> f64 {
+    0.02
+}
+
+fn default_wavelength() -> f64 {
+    1.0
+}
+
+fn default_waves() -> usize {
+    8
+}
+
+fn default_water() -> Material {
+    Material::Glass(Glass::water())
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Wave {
+    amplitude: f64,
+    // The wavenumber along x and z.
+    k: (f64, f64),
+    frequency: f64,
+    phase: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Water {
+    // The middle of the surface at rest.
+    pub center: Point3D,
+    // Along x and z.
+    pub size: [f64; 2],
+    // Of the longest wave, from its middle to its crest.
+    #[serde(default = "default_amplitude")]
+    pub amplitude: f64,
+    #[serde(default = "default_wavelength")]
+    pub wavelength: f64,
+    // Where the waves go, in degrees from x towards z.
+    #[serde(default)]
+    pub direction: f64,
+    #[serde(default = "default_waves")]
+    pub waves: usize,
+    #[serde(default)]
+    pub seed: u64,
+    // In seconds.
+    #[serde(default)]
+    pub time: f64,
+    #[serde(default = "default_water")]
+    pub material: Material,
+    #[serde(skip)]
+    components: OnceLock<Vec<Wave>>,
+}
+
+impl Water {
+    pub fn new(center: Point3D, size: [f64; 2]) -> Water {
+        Water {
+            center,
+            size,
+            amplitude: default_amplitude(),
+            wavelength: default_wavelength(),
+            direction: 0.0,
+            waves: default_waves(),
+            seed: 0,
+            time: 0.0,
+            material: default_water(),
+            components: OnceLock::new(),
+        }
+    }
+
+    fn components(&self) -> &[Wave] {
+        self.components.get_or_init(|| {
+            let mut rng = StdRng::seed_from_u64(self.seed);
+            let mut wavelength = self.wavelength;
+            (0..self.waves)
+                .map(|_| {
+                    let angle = (self.direction + rng.gen_range(-45.0..45.0)).to_radians();
+                    let k = 2.0 * PI / wavelength;
+                    let wave = Wave {
+                        amplitude: self.amplitude * wavelength / self.wavelength,
+                        k: (k * angle.cos(), k * angle.sin()),
+                        frequency: (GRAVITY * k).sqrt(),
+                        phase: rng.gen_range(0.0..2.0 * PI),
+                    };
+                    wavelength *= WAVELENGTH_FALLOFF;
+                    wave
+                })
+                .collect()
+        })
+    }
+
+    // How far above the surface at rest the water is at (x, z), and how
+    // steeply it rises along x and along z.
+    pub fn height(&self, x: f64, z: f64) -> (f64, f64, f64) {
+        let (x, z) = (x - self.center.x(), z - self.center.z());
+        self.components()
+            .iter()
+            .fold((0.0, 0.0, 0.0), |(h, dx, dz), wave| {
+                let angle = wave.k.0 * x + wave.k.1 * z - wave.frequency * self.time + wave.phase;
+                let slope = wave.amplitude * angle.cos();
+                (
+                    h + wave.amplitude * angle.sin(),
+                    dx + slope * wave.k.0,
+                    dz + slope * wave.k.1,
+                )
+            })
+    }
+
+    // The highest the waves can rise together, and the steepest they can
+    // get.
+    fn bounds(&self) -> (f64, f64) {
+        self.components()
+            .iter()
+            .fold((0.0, 0.0), |(height, slope), wave| {
+                let k = (wave.k.0 * wave.k.0 + wave.k.1 * wave.k.1).sqrt();
+                (height + wave.amplitude, slope + wave.amplitude * k)
+            })
+    }
+
+    // Above the water when positive.
+    fn above(&self, p: Point3D) -> f64 {
+        p.y() - self.center.y() - self.height(p.x(), p.z()).0
+    }
+
+    fn trace(&self, r: &Ray, ray_t: &Interval) -> Option<f64> {
+        let ray_t = self.bounding_box().pad(EPSILON).clip(r, ray_t)?;
+        let (_, slope) = self.bounds();
+        let d = r.direction();
+        // The water's height changes along the ray at most this fast.
+        let speed = d.y().abs() + slope * (d.x() * d.x() + d.z() * d.z()).sqrt();
+        if speed <= 0.0 {
+            return None;
+        }
+
+        let mut t = ray_t.min;
+        let mut above = self.above(r.at(t));
+        let side = above.signum();
+        for _ in 0..MAX_STEPS {
+            let next = t + (side * above).max(EPSILON) / speed;
+            if next > ray_t.max {
+                return None;
+            }
+            let next_above = self.above(r.at(next));
+            if side * next_above < 0.0 {
+                let (mut through, mut before) = (next, t);
+                for _ in 0..64 {
+                    let middle = 0.5 * (through + before);
+                    if side * self.above(r.at(middle)) < 0.0 {
+                        through = middle;
+                    } else {
+                        before = middle;
+                    }
+                    if (through - before) * speed < EPSILON {
+                        break;
+                    }
+                }
+                return Some(before);
+            }
+            (t, above) = (next, next_above);
+            if above.abs() < EPSILON {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+impl Hittable for Water {
+    fn hit(&self, r: &Ray, ray_t: &Interval, rec: &mut HitRecord) -> bool {
+        let Some(t) = self.trace(r, ray_t) else {
+            return false;
+        };
+        let p = r.at(t);
+        let (_, dx, dz) = self.height(p.x(), p.z());
+        rec.t = t;
+        rec.p = p;
+        rec.set_face_normal(r, Vec3::new(-dx, 1.0, -dz).unit_vector());
+        let [width, depth] = self.size;
+        rec.u = (p.x() - self.center.x()) / width + 0.5;
+        rec.v = (p.z() - self.center.z()) / depth + 0.5;
+        rec.dpdu = Vec3::new(1.0, dx, 0.0) * width;
+        rec.dpdv = Vec3::new(0.0, dz, 1.0) * depth;
+        rec.mat = self.material.clone();
+        true
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        let (height, _) = self.bounds();
+        let half = Vec3::new(0.5 * self.size[0], height, 0.5 * self.size[1]);
+        Aabb::from_points(self.center - half, self.center + half)
+    }
+}
+
+impl Glass {
+    // Clear water, which absorbs red light over a few meters and blue much
+    // more slowly, per meter.
+    pub fn water() -> Glass {
+        Glass {
+            absorption: Some(Color::new(0.45, 0.06, 0.02)),
+            ..Glass::new(1.33)
+        }
+    }
+}
+
+#[test]
+fn test_water() {
+    let mut water = Water::new(Point3D::new(0.0, 1.0, 0.0), [10.0, 10.0]);
+    let down = |x: f64| Ray::new(Point3D::new(x, 3.0, 0.5), Vec3::new(0.0, -2.0, 0.0));
+    let mut rec = HitRecord::default();
+    for x in [-2.0, 0.3, 4.0] {
+        assert!(water.hit(&down(x), &Interval::new(0.001, f64::INFINITY), &mut rec));
+        let (height, dx, dz) = water.height(x, 0.5);
+        assert!((rec.p.y() - 1.0 - height).abs() < 1e-5);
+        assert!(height.abs() > 0.0 && height.abs() <= water.bounds().0);
+        let normal = Vec3::new(-dx, 1.0, -dz).unit_vector();
+        assert!((rec.normal - normal).length() < 1e-9 && rec.front_face);
+    }
+    // From below, at a slant.
+    let up = Ray::new(Point3D::new(0.0, -1.0, 0.0), Vec3::new(0.3, 1.0, 0.2));
+    assert!(water.hit(&up, &Interval::new(0.001, f64::INFINITY), &mut rec));
+    assert!(!rec.front_face && water.above(rec.p).abs() < 1e-5);
+    // Past the edges there is none.
+    assert!(!water.hit(&down(6.0), &Interval::new(0.001, f64::INFINITY), &mut rec));
+
+    // The waves move on over time.
+    let before = water.height(0.3, 0.5).0;
+    water.time = 0.5;
+    assert!((water.height(0.3, 0.5).0 - before).abs() > 1e-4);
+
+    let water: Water =
+        serde_json::from_str(r#"{"center": [0, 0, 0], "size": [2, 2], "amplitude": 0}"#).unwrap();
+    assert!(
+        matches!(water.material, Material::Glass(Glass { refraction_index, .. }) if refraction_index == 1.33)
+    );
+    assert!(water.hit(&down(0.0), &Interval::new(0.001, f64::INFINITY), &mut rec));
+    assert!((rec.t - 1.5).abs() < 1e-9);
+}
@@ -0,0 +1,132 @@
+// Renders the tiny scenes in tests/golden and compares them to the committed
+// reference images, so changes to the integrator or intersection code can't
+// silently change what comes out.
+//
+// Renders are deterministic for a given seed, but floating point results
+// differ slightly between platforms and any change to how rays are sampled
+// moves the noise around, so single pixels aren't compared. Both images are
+// averaged over blocks of pixels first and the blocks have to agree within a
+// tolerance. Failed renders are kept in target/tmp/golden next
+// to a heatmap of where they differ (see `raytracer compare`).
+//
+// After an intentional change, write new references with
+//
+//     GOLDEN_UPDATE=1 cargo test --test golden
+//
+// and check the new PNGs before committing them.
+
+#![cfg(all(feature = "json", feature = "image"))]
+
+use std::path::{Path, PathBuf};
+
+use raytracer::compare;
+use raytracer::config::Config;
+use raytracer::renderer::Renderer;
+
+const BLOCK: usize = 4;
+// Root mean square difference of the block averages, in 8-bit steps.
+const TOLERANCE: f64 = 1.5;
+
+fn golden_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden")
+}
+
+// Averages each BLOCK x BLOCK square of an RGB image.
+fn blocks(rgb: &[u8], width: usize, height: usize) -> Vec<f64> {
+    let mut averages = Vec::new();
+    for by in 0..height / BLOCK {
+        for bx in 0..width / BLOCK {
+            for channel in 0..3 {
+                let mut sum = 0.0;
+                for y in by * BLOCK..(by + 1) * BLOCK {
+                    for x in bx * BLOCK..(bx + 1) * BLOCK {
+                        sum += rgb[(y * width + x) * 3 + channel] as f64;
+                    }
+                }
+                averages.push(sum / (BLOCK * BLOCK) as f64);
+            }
+        }
+    }
+    averages
+}
+
+fn check(name: &str) {
+    let dir = golden_dir();
+    let config = Config::from_file(&dir.join(format!("{}.json", name))).unwrap();
+    let film = Renderer::from(config).render_film();
+    let reference = dir.join(format!("{}.png", name));
+
+    if std::env::var_os("GOLDEN_UPDATE").is_some() {
+        film.save(reference.to_str().unwrap()).unwrap();
+        return;
+    }
+
+    let expected = image::open(&reference)
+        .unwrap_or_else(|e| {
+            panic!(
+                "can't read {}: {} (run with GOLDEN_UPDATE=1 to create it)",
+                reference.display(),
+                e
+            )
+        })
+        .to_rgb8();
+    assert_eq!(
+        (expected.width() as usize, expected.height() as usize),
+        (film.width, film.height),
+        "{}: size changed",
+        name
+    );
+
+    let rendered = film.to_rgb8();
+    let a = blocks(&rendered, film.width, film.height);
+    let b = blocks(expected.as_raw(), film.width, film.height);
+    let rmse =
+        (a.iter().zip(&b).map(|(a, b)| (a - b).powi(2)).sum::<f64>() / a.len() as f64).sqrt();
+    if rmse <= TOLERANCE {
+        return;
+    }
+
+    // Keep the failed render and where it differs for a look.
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&out).unwrap();
+    let failed = out.join(format!("{}.png", name));
+    film.save(failed.to_str().unwrap()).unwrap();
+    let heatmap = out.join(format!("{}.diff.png", name));
+    let comparison = compare::compare_files(
+        failed.to_str().unwrap(),
+        reference.to_str().unwrap(),
+        heatmap.to_str(),
+    )
+    .unwrap();
+    panic!(
+        "{}: differs from {} by {:.2} (tolerance {}), PSNR {:.2} dB, SSIM {:.4}, FLIP {:.4}; see {}",
+        name,
+        reference.display(),
+        rmse,
+        TOLERANCE,
+        comparison.psnr,
+        comparison.ssim,
+        comparison.flip,
+        out.display()
+    );
+}
+
+#[test]
+fn golden_diffuse() {
+    check("diffuse");
+}
+
+#[test]
+fn golden_metal() {
+    check("metal");
+}
+
+#[test]
+fn golden_glass() {
+    check("glass");
+}
+
+#[test]
+fn golden_depth_of_field() {
+    check("dof");
+}
@@ -0,0 +1,115 @@
+// Builds that take more than `cargo build`, run as `cargo xtask <task>`:
+//
+//   capi  the C library in target/release and its header in include/
+//   wasm  the WebAssembly package in pkg/, with wasm-bindgen's CLI
+//
+// The library is only linked as a cdylib here, so ordinary builds and the
+// crates depending on it don't get a shared library they don't use.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+
+fn usage() -> ! {
+    eprintln!("Usage: cargo xtask capi|wasm");
+    process::exit(2);
+}
+
+fn root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .parent()
+        .expect("xtask lives in the workspace")
+        .to_path_buf()
+}
+
+fn cargo() -> Command {
+    let mut command = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()));
+    command.current_dir(root());
+    command
+}
+
+fn run(command: &mut Command) {
+    let status = command.status().unwrap_or_else(|error| {
+        eprintln!("Unable to run {:?}: {}", command, error);
+        process::exit(1);
+    });
+    if !status.success() {
+        process::exit(status.code().unwrap_or(1));
+    }
+}
+
+// The OUT_DIR of the raytracer package's build script, from the messages of
+// a `--message-format=json` build.
+fn out_dir(messages: &str) -> Option<PathBuf> {
+    messages
+        .lines()
+        .filter(|line| line.contains(r#""reason":"build-script-executed""#))
+        .filter(|line| line.contains("raytracer"))
+        .find_map(|line| {
+            let start = line.find(r#""out_dir":""#)? + r#""out_dir":""#.len();
+            let end = start + line[start..].find('"')?;
+            Some(PathBuf::from(line[start..end].replace(r"\\", r"\")))
+        })
+}
+
+fn capi() {
+    let output = cargo()
+        .args(["rustc", "--release", "--lib", "--package", "raytracer"])
+        .args(["--features", "capi", "--crate-type", "cdylib"])
+        .arg("--message-format=json-render-diagnostics")
+        .stderr(Stdio::inherit())
+        .output()
+        .expect("Unable to run cargo");
+    if !output.status.success() {
+        process::exit(output.status.code().unwrap_or(1));
+    }
+    let Some(out_dir) = out_dir(&String::from_utf8_lossy(&output.stdout)) else {
+        eprintln!("The build didn't run raytracer's build script");
+        process::exit(1);
+    };
+    let header = root().join("include/raytracer.h");
+    fs::create_dir_all(root().join("include")).expect("Unable to create include/");
+    fs::copy(out_dir.join("raytracer.h"), &header).expect("Unable to copy the header");
+    println!("Wrote {}", header.display());
+}
+
+fn wasm() {
+    let target = "wasm32-unknown-unknown";
+    run(cargo()
+        .args(["rustc", "--release", "--lib", "--package", "raytracer"])
+        .args([
+            "--target",
+            target,
+            "--no-default-features",
+            "--features",
+            "wasm",
+        ])
+        .args(["--crate-type", "cdylib"]));
+    let module = root().join(format!("target/{}/release/raytracer.wasm", target));
+    run(Command::new("wasm-bindgen")
+        .args(["--target", "web", "--out-dir"])
+        .arg(root().join("pkg"))
+        .arg(module));
+}
+
+fn main() {
+    match env::args().nth(1).as_deref() {
+        Some("capi") => capi(),
+        Some("wasm") => wasm(),
+        _ => usage(),
+    }
+}
+
+#[test]
+fn test_out_dir() {
+    let messages = concat!(
+        r#"{"reason":"compiler-artifact","package_id":"path+file:///w#raytracer@0.1.0"}"#,
+        "\n",
+        r#"{"reason":"build-script-executed","package_id":"registry+https://x#serde@1.0.0","out_dir":"/t/serde/out"}"#,
+        "\n",
+        r#"{"reason":"build-script-executed","package_id":"path+file:///w#raytracer@0.1.0","linked_libs":[],"out_dir":"/t/raytracer-1/out"}"#,
+    );
+    assert_eq!(out_dir(messages), Some(PathBuf::from("/t/raytracer-1/out")));
+    assert_eq!(out_dir(""), None);
+}